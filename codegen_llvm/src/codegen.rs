@@ -0,0 +1,2310 @@
+//! Translation of MIR bodies to LLVM functions.
+//!
+//! MIR maps closely onto LLVM IR: blocks, phis and temporaries all carry over. The one wrinkle
+//! is the runtime checks, which branch to a block that traps. They split MIR blocks in two, so
+//! the LLVM block a MIR block ends in can differ from the one it starts at.
+//!
+//! Builtins are implemented with the C library, which the program is linked against.
+
+use crate::diagnostics::LlvmError;
+use hir::{BinOp, Builtin, IntTy, Literal, Overflow, SystemNative, Ty, UnOp};
+use inkwell::{
+    basic_block::BasicBlock,
+    builder::Builder,
+    context::Context,
+    module::{Linkage, Module},
+    types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, FunctionType, IntType, PointerType},
+    values::{
+        BasicMetadataValueEnum, BasicValueEnum, FloatValue, FunctionValue, IntValue, PointerValue,
+    },
+    AddressSpace, FloatPredicate, IntPredicate,
+};
+use mir::{BlockId, Body, Inst, InstKind, Operand, Temp, Terminator};
+use std::{cmp::Ordering, collections::HashMap};
+
+/// The decimal digits, for finding them with `strspn`.
+const DIGITS: &str = "0123456789";
+
+/// The globals the C `main` function saves the program's arguments in.
+const ARG_COUNT: &str = "matrix.arg_count";
+const ARGS: &str = "matrix.args";
+
+/// The global holding the state of the `rand` module's SplitMix64 generator.
+const RNG_STATE: &str = "matrix.rng_state";
+
+/// The LLVM type of a value, or `None` for `void`.
+fn basic_type(context: &Context, ty: Ty) -> Option<BasicTypeEnum<'_>> {
+    match ty {
+        Ty::Int(ty) => Some(int_type(context, ty).into()),
+        Ty::Float => Some(context.f64_type().into()),
+        Ty::Bool => Some(context.bool_type().into()),
+        Ty::Char => Some(context.i32_type().into()),
+        Ty::Str => Some(context.i8_type().ptr_type(AddressSpace::default()).into()),
+        Ty::Void | Ty::Error => None,
+        Ty::Array(_) | Ty::Map(..) => unreachable!("`translate` rejects arrays and maps"),
+    }
+}
+
+/// The LLVM type of an int type: an integer of its width, which LLVM doesn't give a sign.
+fn int_type(context: &Context, ty: IntTy) -> IntType<'_> {
+    context.custom_width_int_type(ty.bits())
+}
+
+fn function_type<'ctx>(context: &'ctx Context, body: &Body) -> FunctionType<'ctx> {
+    let params = body
+        .params
+        .iter()
+        .map(|&param| {
+            basic_type(context, body.temp_ty(param))
+                .expect("parameters aren't void")
+                .into()
+        })
+        .collect::<Vec<BasicMetadataTypeEnum<'_>>>();
+
+    match basic_type(context, body.ret_ty) {
+        Some(ty) => ty.fn_type(&params, false),
+        None => context.void_type().fn_type(&params, false),
+    }
+}
+
+fn operand_ty(body: &Body, operand: &Operand) -> Ty {
+    match operand {
+        Operand::Temp(temp) => body.temp_ty(*temp),
+        Operand::Const(literal) => literal.ty(),
+    }
+}
+
+/// The number of edges from a terminator to a block. A switch has one for each int that jumps
+/// there, and a phi needs its incoming value once for every edge.
+fn edges(terminator: &Terminator, to: BlockId) -> usize {
+    match terminator {
+        Terminator::Jump(target) => usize::from(*target == to),
+        Terminator::Branch {
+            then, otherwise, ..
+        } => usize::from(*then == to) + usize::from(*otherwise == to),
+        Terminator::Switch {
+            targets, otherwise, ..
+        } => targets
+            .iter()
+            .chain([otherwise])
+            .filter(|&&target| target == to)
+            .count(),
+        Terminator::Ret(_) | Terminator::Unreachable => 0,
+    }
+}
+
+/// The predicate of a comparison operator.
+fn int_predicate(op: BinOp, signed: bool) -> IntPredicate {
+    match (op, signed) {
+        (BinOp::Eq, _) => IntPredicate::EQ,
+        (BinOp::Ne, _) => IntPredicate::NE,
+        (BinOp::Lt, true) => IntPredicate::SLT,
+        (BinOp::Le, true) => IntPredicate::SLE,
+        (BinOp::Gt, true) => IntPredicate::SGT,
+        (BinOp::Ge, true) => IntPredicate::SGE,
+        (BinOp::Lt, false) => IntPredicate::ULT,
+        (BinOp::Le, false) => IntPredicate::ULE,
+        (BinOp::Gt, false) => IntPredicate::UGT,
+        (BinOp::Ge, false) => IntPredicate::UGE,
+        _ => unreachable!("`{op:?}` isn't a comparison"),
+    }
+}
+
+/// The state of the body being translated.
+struct Frame<'ctx> {
+    function: FunctionValue<'ctx>,
+    values: HashMap<Temp, BasicValueEnum<'ctx>>,
+
+    /// The LLVM block each MIR block starts at. Indexed by [`BlockId`].
+    starts: Vec<BasicBlock<'ctx>>,
+
+    /// The LLVM block each MIR block ends in, which phis name as the predecessor.
+    ends: Vec<BasicBlock<'ctx>>,
+
+    /// The block runtime checks branch to when they fail, created on first use.
+    trap: Option<BasicBlock<'ctx>>,
+}
+
+impl<'ctx> Frame<'ctx> {
+    fn start(&self, id: BlockId) -> BasicBlock<'ctx> {
+        self.starts[id.0 as usize]
+    }
+}
+
+struct Codegen<'ctx, 'p> {
+    context: &'ctx Context,
+    module: Module<'ctx>,
+    builder: Builder<'ctx>,
+    program: &'p mir::Program,
+
+    /// Indexed by [`hir::ProcId`].
+    functions: Vec<FunctionValue<'ctx>>,
+
+    /// Globals holding the string constants, which are shared between uses.
+    strings: HashMap<String, PointerValue<'ctx>>,
+}
+
+impl<'ctx, 'p> Codegen<'ctx, 'p> {
+    /// Get a function by name, declaring it first if the module doesn't have it yet.
+    fn declaration(&self, name: &str, ty: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, ty, None))
+    }
+
+    fn string(&mut self, value: &str) -> PointerValue<'ctx> {
+        if let Some(&pointer) = self.strings.get(value) {
+            return pointer;
+        }
+
+        let initializer = self.context.const_string(value.as_bytes(), true);
+        let global = self.module.add_global(initializer.get_type(), None, "str");
+        global.set_initializer(&initializer);
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        global.set_unnamed_addr(true);
+
+        let pointer = global.as_pointer_value();
+        self.strings.insert(value.to_owned(), pointer);
+        pointer
+    }
+
+    fn literal(&mut self, literal: &Literal) -> BasicValueEnum<'ctx> {
+        match literal {
+            Literal::Int(value, ty) => int_type(self.context, *ty)
+                .const_int(*value as u64, ty.is_signed())
+                .into(),
+            Literal::Float(value) => self.context.f64_type().const_float(*value).into(),
+            Literal::Bool(value) => self
+                .context
+                .bool_type()
+                .const_int(u64::from(*value), false)
+                .into(),
+            Literal::Char(value) => self
+                .context
+                .i32_type()
+                .const_int(u64::from(u32::from(*value)), false)
+                .into(),
+            Literal::Str(value) => self.string(value.as_str()).into(),
+        }
+    }
+
+    fn operand(&mut self, frame: &Frame<'ctx>, operand: &Operand) -> BasicValueEnum<'ctx> {
+        match operand {
+            Operand::Temp(temp) => frame.values[temp],
+            Operand::Const(literal) => self.literal(literal),
+        }
+    }
+
+    fn trap_block(&self, frame: &mut Frame<'ctx>) -> BasicBlock<'ctx> {
+        if let Some(trap) = frame.trap {
+            return trap;
+        }
+
+        let current = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        let trap = self.context.append_basic_block(frame.function, "trap");
+        self.builder.position_at_end(trap);
+        let abort = self.declaration("llvm.trap", self.context.void_type().fn_type(&[], false));
+        self.builder.build_call(abort, &[], "");
+        self.builder.build_unreachable();
+        self.builder.position_at_end(current);
+
+        frame.trap = Some(trap);
+        trap
+    }
+
+    /// Abort the program if `failed` is true, and continue in a new block otherwise.
+    fn trap_if(&self, frame: &mut Frame<'ctx>, failed: IntValue<'ctx>) {
+        let trap = self.trap_block(frame);
+        let next = self.context.append_basic_block(frame.function, "");
+        self.builder.build_conditional_branch(failed, trap, next);
+        self.builder.position_at_end(next);
+    }
+
+    /// Apply an `llvm.*.with.overflow` intrinsic, aborting the program if it overflows.
+    fn checked(
+        &self,
+        frame: &mut Frame<'ctx>,
+        op: &str,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let int = lhs.get_type();
+        let result = self
+            .context
+            .struct_type(&[int.into(), self.context.bool_type().into()], false);
+        let intrinsic = self.declaration(
+            &format!("llvm.{op}.with.overflow.i{}", int.get_bit_width()),
+            result.fn_type(&[int.into(), int.into()], false),
+        );
+
+        let result = self
+            .builder
+            .build_call(intrinsic, &[lhs.into(), rhs.into()], "")
+            .try_as_basic_value()
+            .left()
+            .expect("overflow intrinsics return a value")
+            .into_struct_value();
+        let overflowed = self
+            .builder
+            .build_extract_value(result, 1, "")
+            .expect("the result has an overflow flag")
+            .into_int_value();
+        self.trap_if(frame, overflowed);
+
+        self.builder
+            .build_extract_value(result, 0, "")
+            .expect("the result has a value")
+            .into_int_value()
+    }
+
+    /// Convert an int to another int type, aborting the program if it doesn't fit.
+    fn cast(
+        &self,
+        frame: &mut Frame<'ctx>,
+        value: IntValue<'ctx>,
+        from: IntTy,
+        to: IntTy,
+    ) -> IntValue<'ctx> {
+        let resize = |value, from: IntTy, to: IntTy| match from.bits().cmp(&to.bits()) {
+            Ordering::Greater => {
+                self.builder
+                    .build_int_truncate(value, int_type(self.context, to), "")
+            }
+            Ordering::Less if from.is_signed() => {
+                self.builder
+                    .build_int_s_extend(value, int_type(self.context, to), "")
+            }
+            Ordering::Less => {
+                self.builder
+                    .build_int_z_extend(value, int_type(self.context, to), "")
+            }
+            Ordering::Equal => value,
+        };
+        let result = resize(value, from, to);
+
+        // The value fits if converting it back gives it again, and changing the sign doesn't
+        // make it negative or lose a negative value.
+        let back = resize(result, to, from);
+        let mut overflows = self
+            .builder
+            .build_int_compare(IntPredicate::NE, back, value, "");
+        let negative = match (from.is_signed(), to.is_signed()) {
+            (true, false) => Some(value),
+            (false, true) => Some(result),
+            _ => None,
+        };
+        if let Some(negative) = negative {
+            let zero = negative.get_type().const_zero();
+            let is_negative = self
+                .builder
+                .build_int_compare(IntPredicate::SLT, negative, zero, "");
+            overflows = self.builder.build_or(overflows, is_negative, "");
+        }
+        self.trap_if(frame, overflows);
+
+        result
+    }
+
+    fn unary(
+        &self,
+        frame: &mut Frame<'ctx>,
+        op: UnOp,
+        ty: Ty,
+        operand: BasicValueEnum<'ctx>,
+    ) -> BasicValueEnum<'ctx> {
+        match (op, operand) {
+            (UnOp::Cast(to), BasicValueEnum::IntValue(value)) => {
+                let Ty::Int(from) = ty else {
+                    unreachable!("casts are type checked, found {ty}");
+                };
+                self.cast(frame, value, from, to).into()
+            }
+            (UnOp::Neg, BasicValueEnum::IntValue(value)) => {
+                let zero = value.get_type().const_zero();
+                self.checked(frame, "ssub", zero, value).into()
+            }
+            (UnOp::Neg, BasicValueEnum::FloatValue(value)) => {
+                self.builder.build_float_neg(value, "").into()
+            }
+            (UnOp::Not | UnOp::BitNot, BasicValueEnum::IntValue(value)) => {
+                self.builder.build_not(value, "").into()
+            }
+            _ => unreachable!("`{op:?}` is type checked, found {operand:?}"),
+        }
+    }
+
+    fn int_binary(
+        &self,
+        frame: &mut Frame<'ctx>,
+        ty: IntTy,
+        op: BinOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let int = lhs.get_type();
+        let sign = if ty.is_signed() { "s" } else { "u" };
+        match op {
+            BinOp::Add => self.checked(frame, &format!("{sign}add"), lhs, rhs),
+            BinOp::Sub => self.checked(frame, &format!("{sign}sub"), lhs, rhs),
+            BinOp::Mul => self.checked(frame, &format!("{sign}mul"), lhs, rhs),
+            BinOp::Div | BinOp::Rem if ty.is_signed() => {
+                let zero =
+                    self.builder
+                        .build_int_compare(IntPredicate::EQ, rhs, int.const_zero(), "");
+                self.trap_if(frame, zero);
+
+                // The only quotient that doesn't fit is the minimum divided by -1.
+                let min = int.const_int(ty.min() as u64, true);
+                let is_min = self
+                    .builder
+                    .build_int_compare(IntPredicate::EQ, lhs, min, "");
+                let is_minus_one =
+                    self.builder
+                        .build_int_compare(IntPredicate::EQ, rhs, int.const_all_ones(), "");
+                let overflows = self.builder.build_and(is_min, is_minus_one, "");
+                self.trap_if(frame, overflows);
+
+                if op == BinOp::Div {
+                    self.builder.build_int_signed_div(lhs, rhs, "")
+                } else {
+                    self.builder.build_int_signed_rem(lhs, rhs, "")
+                }
+            }
+            BinOp::Div | BinOp::Rem => {
+                let zero =
+                    self.builder
+                        .build_int_compare(IntPredicate::EQ, rhs, int.const_zero(), "");
+                self.trap_if(frame, zero);
+
+                if op == BinOp::Div {
+                    self.builder.build_int_unsigned_div(lhs, rhs, "")
+                } else {
+                    self.builder.build_int_unsigned_rem(lhs, rhs, "")
+                }
+            }
+            BinOp::BitAnd => self.builder.build_and(lhs, rhs, ""),
+            BinOp::BitOr => self.builder.build_or(lhs, rhs, ""),
+            BinOp::Shl | BinOp::Shr => {
+                // Negative amounts are huge when unsigned, so this rejects them too.
+                let invalid = self.builder.build_int_compare(
+                    IntPredicate::UGE,
+                    rhs,
+                    int.const_int(ty.bits().into(), false),
+                    "",
+                );
+                self.trap_if(frame, invalid);
+
+                if op == BinOp::Shl {
+                    self.builder.build_left_shift(lhs, rhs, "")
+                } else {
+                    self.builder.build_right_shift(lhs, rhs, ty.is_signed(), "")
+                }
+            }
+            _ => self
+                .builder
+                .build_int_compare(int_predicate(op, ty.is_signed()), lhs, rhs, ""),
+        }
+    }
+
+    fn float_binary(
+        &self,
+        op: BinOp,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+    ) -> BasicValueEnum<'ctx> {
+        let predicate = match op {
+            BinOp::Add => return self.builder.build_float_add(lhs, rhs, "").into(),
+            BinOp::Sub => return self.builder.build_float_sub(lhs, rhs, "").into(),
+            BinOp::Mul => return self.builder.build_float_mul(lhs, rhs, "").into(),
+            BinOp::Div => return self.builder.build_float_div(lhs, rhs, "").into(),
+            BinOp::Rem => return self.builder.build_float_rem(lhs, rhs, "").into(),
+            BinOp::Eq => FloatPredicate::OEQ,
+            // `NaN != NaN`, so this is the one unordered predicate.
+            BinOp::Ne => FloatPredicate::UNE,
+            BinOp::Lt => FloatPredicate::OLT,
+            BinOp::Le => FloatPredicate::OLE,
+            BinOp::Gt => FloatPredicate::OGT,
+            BinOp::Ge => FloatPredicate::OGE,
+            BinOp::BitAnd | BinOp::BitOr | BinOp::Shl | BinOp::Shr => {
+                unreachable!("bitwise operators only apply to ints")
+            }
+        };
+
+        self.builder
+            .build_float_compare(predicate, lhs, rhs, "")
+            .into()
+    }
+
+    /// Compare strings by their contents with the C library's `strcmp`.
+    fn str_compare(
+        &self,
+        op: BinOp,
+        lhs: PointerValue<'ctx>,
+        rhs: PointerValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let c_int = self.context.i32_type();
+        let pointer = self.context.i8_type().ptr_type(AddressSpace::default());
+        let strcmp = self.declaration(
+            "strcmp",
+            c_int.fn_type(&[pointer.into(), pointer.into()], false),
+        );
+
+        let ordering = self
+            .builder
+            .build_call(strcmp, &[lhs.into(), rhs.into()], "")
+            .try_as_basic_value()
+            .left()
+            .expect("`strcmp` returns an int")
+            .into_int_value();
+        self.builder
+            .build_int_compare(int_predicate(op, true), ordering, c_int.const_zero(), "")
+    }
+
+    /// Call a C library function, declaring it first if needed.
+    fn call_c(
+        &self,
+        name: &str,
+        ty: FunctionType<'ctx>,
+        args: &[BasicMetadataValueEnum<'ctx>],
+    ) -> Option<BasicValueEnum<'ctx>> {
+        self.builder
+            .build_call(self.declaration(name, ty), args, "")
+            .try_as_basic_value()
+            .left()
+    }
+
+    fn pointer_type(&self) -> PointerType<'ctx> {
+        self.context.i8_type().ptr_type(AddressSpace::default())
+    }
+
+    /// Allocate `size` bytes on the heap. Strings built at runtime are never freed.
+    fn malloc(&self, size: IntValue<'ctx>) -> PointerValue<'ctx> {
+        let size_t = self.context.i64_type();
+        self.call_c(
+            "malloc",
+            self.pointer_type().fn_type(&[size_t.into()], false),
+            &[size.into()],
+        )
+        .expect("`malloc` returns a pointer")
+        .into_pointer_value()
+    }
+
+    fn strlen(&self, s: PointerValue<'ctx>) -> IntValue<'ctx> {
+        self.call_c(
+            "strlen",
+            self.context
+                .i64_type()
+                .fn_type(&[self.pointer_type().into()], false),
+            &[s.into()],
+        )
+        .expect("`strlen` returns a length")
+        .into_int_value()
+    }
+
+    /// Copy `len` bytes from `source` into a new string.
+    fn copy_str(&self, source: PointerValue<'ctx>, len: IntValue<'ctx>) -> PointerValue<'ctx> {
+        let size_t = self.context.i64_type();
+        let size = self
+            .builder
+            .build_int_add(len, size_t.const_int(1, false), "");
+        let buffer = self.malloc(size);
+        self.memcpy(buffer, source, len);
+        self.builder.build_store(
+            self.byte_at(buffer, len),
+            self.context.i8_type().const_zero(),
+        );
+        buffer
+    }
+
+    fn memcpy(&self, dest: PointerValue<'ctx>, source: PointerValue<'ctx>, len: IntValue<'ctx>) {
+        let pointer = self.pointer_type();
+        self.call_c(
+            "memcpy",
+            pointer.fn_type(
+                &[
+                    pointer.into(),
+                    pointer.into(),
+                    self.context.i64_type().into(),
+                ],
+                false,
+            ),
+            &[dest.into(), source.into(), len.into()],
+        );
+    }
+
+    /// Join two strings into a new one.
+    fn str_concat(&self, lhs: PointerValue<'ctx>, rhs: PointerValue<'ctx>) -> PointerValue<'ctx> {
+        let (lhs_len, rhs_len) = (self.strlen(lhs), self.strlen(rhs));
+        // Copying the right string includes its terminator.
+        let rhs_size =
+            self.builder
+                .build_int_add(rhs_len, self.context.i64_type().const_int(1, false), "");
+        let size = self.builder.build_int_add(lhs_len, rhs_size, "");
+
+        let buffer = self.malloc(size);
+        self.memcpy(buffer, lhs, lhs_len);
+        self.memcpy(self.byte_at(buffer, lhs_len), rhs, rhs_size);
+        buffer
+    }
+
+    /// Whether the byte at `index` continues a char, rather than starting one.
+    fn is_continuation(&self, s: PointerValue<'ctx>, index: IntValue<'ctx>) -> IntValue<'ctx> {
+        let i8 = self.context.i8_type();
+        let byte = self
+            .builder
+            .build_load(i8, self.byte_at(s, index), "")
+            .into_int_value();
+        let high_bits = self.builder.build_and(byte, i8.const_int(0xc0, false), "");
+        self.builder
+            .build_int_compare(IntPredicate::EQ, high_bits, i8.const_int(0x80, false), "")
+    }
+
+    /// Copy the bytes of a string from `start` up to `end` into a new one, aborting the program
+    /// if either is out of bounds or inside a char.
+    fn str_slice(
+        &self,
+        frame: &mut Frame<'ctx>,
+        s: PointerValue<'ctx>,
+        start: IntValue<'ctx>,
+        end: IntValue<'ctx>,
+    ) -> PointerValue<'ctx> {
+        // Negative indices are out of bounds as unsigned values too.
+        let len = self.strlen(s);
+        let start_out = self
+            .builder
+            .build_int_compare(IntPredicate::UGT, start, len, "");
+        let end_out = self
+            .builder
+            .build_int_compare(IntPredicate::UGT, end, len, "");
+        self.trap_if(frame, self.builder.build_or(start_out, end_out, ""));
+
+        let backwards = self
+            .builder
+            .build_int_compare(IntPredicate::SLT, end, start, "");
+        let end = self
+            .builder
+            .build_select(backwards, start, end, "")
+            .into_int_value();
+        let inside_char = self.builder.build_or(
+            self.is_continuation(s, start),
+            self.is_continuation(s, end),
+            "",
+        );
+        self.trap_if(frame, inside_char);
+
+        let len = self.builder.build_int_sub(end, start, "");
+        self.copy_str(self.byte_at(s, start), len)
+    }
+
+    /// A pointer to the byte at `index` in a string.
+    fn byte_at(&self, string: PointerValue<'ctx>, index: IntValue<'ctx>) -> PointerValue<'ctx> {
+        // SAFETY: callers only index within the string's allocation.
+        unsafe {
+            self.builder
+                .build_in_bounds_gep(self.context.i8_type(), string, &[index], "")
+        }
+    }
+
+    /// Format a value with `snprintf` into a new string.
+    fn format(&mut self, format: &str, value: BasicValueEnum<'ctx>) -> PointerValue<'ctx> {
+        // Enough for any int.
+        const SIZE: u64 = 32;
+
+        let pointer = self.pointer_type();
+        let size_t = self.context.i64_type();
+        let format = self.string(format);
+        let buffer = self.malloc(size_t.const_int(SIZE, false));
+        self.call_c(
+            "snprintf",
+            self.context
+                .i32_type()
+                .fn_type(&[pointer.into(), size_t.into(), pointer.into()], true),
+            &[
+                buffer.into(),
+                size_t.const_int(SIZE, false).into(),
+                format.into(),
+                value.into(),
+            ],
+        );
+        buffer
+    }
+
+    /// Format a float like [`hir::float::format`], with a function defined on first use.
+    fn float_to_str(&mut self, value: FloatValue<'ctx>) -> PointerValue<'ctx> {
+        const NAME: &str = "matrix.float_to_str";
+
+        let function = match self.module.get_function(NAME) {
+            Some(function) => function,
+            None => self.define_float_to_str(NAME),
+        };
+        self.builder
+            .build_call(function, &[value.into()], "")
+            .try_as_basic_value()
+            .left()
+            .expect("formatting returns a string")
+            .into_pointer_value()
+    }
+
+    /// Define the function formatting floats. It finds the shortest digits that round-trip by
+    /// printing with `%.*e` at increasing precisions until `strtod` reads back the same float,
+    /// then prints them in decimal or scientific notation depending on the exponent.
+    fn define_float_to_str(&mut self, name: &str) -> FunctionValue<'ctx> {
+        // Enough for 17 significant digits, a sign and up to 5 leading zeros.
+        const SIZE: u64 = 32;
+
+        let context = self.context;
+        let (pointer, float) = (self.pointer_type(), context.f64_type());
+        let (c_int, size_t) = (context.i32_type(), context.i64_type());
+        let function = self.module.add_function(
+            name,
+            pointer.fn_type(&[float.into()], false),
+            Some(Linkage::Private),
+        );
+        let value = function
+            .get_nth_param(0)
+            .expect("the function takes a float")
+            .into_float_value();
+
+        let current = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        let entry = context.append_basic_block(function, "entry");
+        let special = context.append_basic_block(function, "special");
+        let finite = context.append_basic_block(function, "finite");
+        let search = context.append_basic_block(function, "search");
+        let found = context.append_basic_block(function, "found");
+        let scientific = context.append_basic_block(function, "scientific");
+        let decimal = context.append_basic_block(function, "decimal");
+
+        // Infinities and `nan` are spelled out, without the sign C gives negative `nan`s.
+        self.builder.position_at_end(entry);
+        let fabs = self.declaration("llvm.fabs.f64", float.fn_type(&[float.into()], false));
+        let magnitude = self
+            .builder
+            .build_call(fabs, &[value.into()], "")
+            .try_as_basic_value()
+            .left()
+            .expect("`fabs` returns a float")
+            .into_float_value();
+        let is_finite = self.builder.build_float_compare(
+            FloatPredicate::OLT,
+            magnitude,
+            float.const_float(f64::INFINITY),
+            "",
+        );
+        self.builder
+            .build_conditional_branch(is_finite, finite, special);
+
+        self.builder.position_at_end(special);
+        let (nan, inf, neg_inf) = (self.string("nan"), self.string("inf"), self.string("-inf"));
+        let is_nan = self
+            .builder
+            .build_float_compare(FloatPredicate::UNO, value, value, "");
+        let is_negative =
+            self.builder
+                .build_float_compare(FloatPredicate::OLT, value, float.const_zero(), "");
+        let infinity = self
+            .builder
+            .build_select(is_negative, neg_inf, inf, "")
+            .into_pointer_value();
+        let spelled_out = self.builder.build_select(is_nan, nan, infinity, "");
+        self.builder.build_return(Some(&spelled_out));
+
+        self.builder.position_at_end(finite);
+        let size = size_t.const_int(SIZE, false);
+        let buffer = self.malloc(size);
+        self.builder.build_unconditional_branch(search);
+
+        let (scientific_format, decimal_format) = (self.string("%.*e"), self.string("%.*f"));
+        let snprintf = context
+            .i32_type()
+            .fn_type(&[pointer.into(), size_t.into(), pointer.into()], true);
+
+        // `precision` digits after the first, from 0 up to at most 16.
+        self.builder.position_at_end(search);
+        let precision = self.builder.build_phi(c_int, "precision");
+        let precision_value = precision.as_basic_value().into_int_value();
+        self.call_c(
+            "snprintf",
+            snprintf,
+            &[
+                buffer.into(),
+                size.into(),
+                scientific_format.into(),
+                precision_value.into(),
+                value.into(),
+            ],
+        );
+        let parsed = self
+            .call_c(
+                "strtod",
+                float.fn_type(&[pointer.into(), pointer.into()], false),
+                &[buffer.into(), pointer.const_null().into()],
+            )
+            .expect("`strtod` returns a float")
+            .into_float_value();
+        let round_trips = self
+            .builder
+            .build_float_compare(FloatPredicate::OEQ, parsed, value, "");
+        let next = self
+            .builder
+            .build_int_add(precision_value, c_int.const_int(1, false), "");
+        self.builder
+            .build_conditional_branch(round_trips, found, search);
+        precision.add_incoming(&[(&c_int.const_zero(), finite), (&next, search)]);
+
+        self.builder.position_at_end(found);
+        let exponent_text = self
+            .call_c(
+                "strchr",
+                pointer.fn_type(&[pointer.into(), c_int.into()], false),
+                &[
+                    buffer.into(),
+                    c_int.const_int(u64::from(b'e'), false).into(),
+                ],
+            )
+            .expect("`strchr` returns a pointer")
+            .into_pointer_value();
+        let exponent = self
+            .call_c(
+                "strtol",
+                size_t.fn_type(&[pointer.into(), pointer.into(), c_int.into()], false),
+                &[
+                    self.byte_at(exponent_text, size_t.const_int(1, false))
+                        .into(),
+                    pointer.const_null().into(),
+                    c_int.const_int(10, false).into(),
+                ],
+            )
+            .expect("`strtol` returns an int")
+            .into_int_value();
+        let is_zero =
+            self.builder
+                .build_float_compare(FloatPredicate::OEQ, value, float.const_zero(), "");
+        let (min, max) = (
+            hir::float::DECIMAL_EXPONENTS.start,
+            hir::float::DECIMAL_EXPONENTS.end,
+        );
+        let above_min = self.builder.build_int_compare(
+            IntPredicate::SGE,
+            exponent,
+            size_t.const_int(i64::from(min) as u64, true),
+            "",
+        );
+        let below_max = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            exponent,
+            size_t.const_int(i64::from(max) as u64, true),
+            "",
+        );
+        let in_range = self.builder.build_and(above_min, below_max, "");
+        let is_decimal = self.builder.build_or(is_zero, in_range, "");
+        self.builder
+            .build_conditional_branch(is_decimal, decimal, scientific);
+
+        // C pads the exponent and gives it a sign, so it's printed again over C's.
+        self.builder.position_at_end(scientific);
+        let exponent_format = self.string("e%lld");
+        self.call_c(
+            "snprintf",
+            snprintf,
+            &[
+                exponent_text.into(),
+                size_t.const_int(8, false).into(),
+                exponent_format.into(),
+                exponent.into(),
+            ],
+        );
+        self.builder.build_return(Some(&buffer));
+
+        // The digits after the first become fractional digits as the exponent shifts them, and
+        // there's always at least one.
+        self.builder.position_at_end(decimal);
+        let digits = self.builder.build_int_s_extend(precision_value, size_t, "");
+        let fraction_digits = self.builder.build_int_sub(digits, exponent, "");
+        let one = size_t.const_int(1, false);
+        let too_few = self
+            .builder
+            .build_int_compare(IntPredicate::SLT, fraction_digits, one, "");
+        let fraction_digits = self
+            .builder
+            .build_select(too_few, one, fraction_digits, "")
+            .into_int_value();
+        let fraction_digits = self.builder.build_int_truncate(fraction_digits, c_int, "");
+        self.call_c(
+            "snprintf",
+            snprintf,
+            &[
+                buffer.into(),
+                size.into(),
+                decimal_format.into(),
+                fraction_digits.into(),
+                value.into(),
+            ],
+        );
+        self.builder.build_return(Some(&buffer));
+
+        self.builder.position_at_end(current);
+        function
+    }
+
+    /// Encode a char as a UTF-8 string. The encoding's length is computed up front, so every
+    /// byte can be selected without branching.
+    fn char_to_str(&self, value: IntValue<'ctx>) -> PointerValue<'ctx> {
+        let (i8, i32) = (self.context.i8_type(), self.context.i32_type());
+        let constant = |value: u64| i32.const_int(value, false);
+
+        let mut len = constant(1);
+        for start in [0x80, 0x800, 0x1_0000] {
+            let longer =
+                self.builder
+                    .build_int_compare(IntPredicate::UGE, value, constant(start), "");
+            let longer = self.builder.build_int_z_extend(longer, i32, "");
+            len = self.builder.build_int_add(len, longer, "");
+        }
+
+        // The first byte is marked with as many leading ones as the encoding has bytes, unless
+        // it's a single byte.
+        let single = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, len, constant(1), "");
+        let marker = self
+            .builder
+            .build_right_shift(constant(0xff00), len, false, "");
+        let marker = self.builder.build_and(marker, constant(0xff), "");
+        let marker = self
+            .builder
+            .build_select(single, constant(0), marker, "")
+            .into_int_value();
+
+        let buffer = self.malloc(self.context.i64_type().const_int(5, false));
+        for i in 0..4 {
+            // Each byte holds 6 bits, with the first holding the highest.
+            let remaining = self.builder.build_int_sub(len, constant(i + 1), "");
+            let shift = self.builder.build_int_mul(remaining, constant(6), "");
+            let bits = self.builder.build_right_shift(value, shift, false, "");
+
+            let byte = if i == 0 {
+                self.builder.build_or(marker, bits, "")
+            } else {
+                // Bytes past the end terminate the string. Their shift is negative, but the
+                // select doesn't use them.
+                let continuation = self.builder.build_and(bits, constant(0x3f), "");
+                let continuation = self.builder.build_or(continuation, constant(0x80), "");
+                let within =
+                    self.builder
+                        .build_int_compare(IntPredicate::ULT, constant(i), len, "");
+                self.builder
+                    .build_select(within, continuation, constant(0), "")
+                    .into_int_value()
+            };
+
+            let byte = self.builder.build_int_truncate(byte, i8, "");
+            self.builder
+                .build_store(self.byte_at(buffer, i32.const_int(i, false)), byte);
+        }
+        self.builder
+            .build_store(self.byte_at(buffer, constant(4)), i8.const_zero());
+
+        buffer
+    }
+
+    /// The char with a code point, aborting the program if it's negative, past `0x10ffff` or a
+    /// surrogate.
+    fn int_to_char(&self, frame: &mut Frame<'ctx>, code: IntValue<'ctx>) -> IntValue<'ctx> {
+        let int = self.context.i64_type();
+        let constant = |value: u64| int.const_int(value, false);
+
+        // Negative codes are past `0x10ffff` as unsigned values too.
+        let too_large =
+            self.builder
+                .build_int_compare(IntPredicate::UGT, code, constant(0x10_ffff), "");
+        let surrogate_offset = self.builder.build_int_sub(code, constant(0xd800), "");
+        let surrogate = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            surrogate_offset,
+            constant(0x800),
+            "",
+        );
+        self.trap_if(frame, self.builder.build_or(too_large, surrogate, ""));
+        self.builder
+            .build_int_truncate(code, self.context.i32_type(), "")
+    }
+
+    /// A pointer to the first occurrence of `pattern` in a string, or null if it doesn't occur.
+    fn strstr(&self, s: PointerValue<'ctx>, pattern: PointerValue<'ctx>) -> PointerValue<'ctx> {
+        let pointer = self.pointer_type();
+        self.call_c(
+            "strstr",
+            pointer.fn_type(&[pointer.into(), pointer.into()], false),
+            &[s.into(), pattern.into()],
+        )
+        .expect("`strstr` returns a pointer")
+        .into_pointer_value()
+    }
+
+    /// The number of bytes at the start of a string that are in `set`.
+    fn strspn(&mut self, s: PointerValue<'ctx>, set: &str) -> IntValue<'ctx> {
+        let (pointer, size_t) = (self.pointer_type(), self.context.i64_type());
+        let set = self.string(set);
+        self.call_c(
+            "strspn",
+            size_t.fn_type(&[pointer.into(), pointer.into()], false),
+            &[s.into(), set.into()],
+        )
+        .expect("`strspn` returns a length")
+        .into_int_value()
+    }
+
+    /// Whether the byte a pointer points to is `byte`.
+    fn is_byte(&self, s: PointerValue<'ctx>, byte: u8) -> IntValue<'ctx> {
+        let i8 = self.context.i8_type();
+        let loaded = self.builder.build_load(i8, s, "").into_int_value();
+        self.builder.build_int_compare(
+            IntPredicate::EQ,
+            loaded,
+            i8.const_int(u64::from(byte), false),
+            "",
+        )
+    }
+
+    /// Decode the char starting at the byte at `index`, aborting the program if it's out of
+    /// bounds or inside a char. Like [`Codegen::char_to_str`], the char's length is computed up
+    /// front so every byte can be selected without branching. Bytes past the end of the string
+    /// are read as its terminator.
+    fn char_at(
+        &self,
+        frame: &mut Frame<'ctx>,
+        s: PointerValue<'ctx>,
+        index: IntValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let (i8, i32, size_t) = (
+            self.context.i8_type(),
+            self.context.i32_type(),
+            self.context.i64_type(),
+        );
+        let constant = |value: u64| i32.const_int(value, false);
+
+        // Negative indices are out of bounds as unsigned values too.
+        let len = self.strlen(s);
+        let out_of_bounds = self
+            .builder
+            .build_int_compare(IntPredicate::UGE, index, len, "");
+        self.trap_if(frame, out_of_bounds);
+        self.trap_if(frame, self.is_continuation(s, index));
+
+        let byte = |offset: u64| {
+            let at = self
+                .builder
+                .build_int_add(index, size_t.const_int(offset, false), "");
+            let past_end = self
+                .builder
+                .build_int_compare(IntPredicate::UGT, at, len, "");
+            let at = self
+                .builder
+                .build_select(past_end, len, at, "")
+                .into_int_value();
+            let byte = self
+                .builder
+                .build_load(i8, self.byte_at(s, at), "")
+                .into_int_value();
+            self.builder.build_int_z_extend(byte, i32, "")
+        };
+
+        let first = byte(0);
+        let mut char_len = constant(1);
+        for start in [0xc0, 0xe0, 0xf0] {
+            let longer =
+                self.builder
+                    .build_int_compare(IntPredicate::UGE, first, constant(start), "");
+            let longer = self.builder.build_int_z_extend(longer, i32, "");
+            char_len = self.builder.build_int_add(char_len, longer, "");
+        }
+
+        // The first byte's bits after its length marker are the highest, and each following
+        // byte holds 6 more.
+        let single = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, char_len, constant(1), "");
+        let mask = self
+            .builder
+            .build_right_shift(constant(0x7f), char_len, false, "");
+        let mask = self
+            .builder
+            .build_select(single, constant(0x7f), mask, "")
+            .into_int_value();
+        let mut value = self.builder.build_and(first, mask, "");
+        for i in 1..4 {
+            let bits = self.builder.build_and(byte(i), constant(0x3f), "");
+            let shifted = self.builder.build_left_shift(value, constant(6), "");
+            let shifted = self.builder.build_or(shifted, bits, "");
+            let within =
+                self.builder
+                    .build_int_compare(IntPredicate::ULT, constant(i), char_len, "");
+            value = self
+                .builder
+                .build_select(within, shifted, value, "")
+                .into_int_value();
+        }
+
+        value
+    }
+
+    /// Count the parts splitting a string at each separator gives, finding the separators
+    /// with `strstr`.
+    fn split_count(
+        &self,
+        frame: &Frame<'ctx>,
+        s: PointerValue<'ctx>,
+        separator: PointerValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let (pointer, size_t) = (self.pointer_type(), self.context.i64_type());
+        let one = size_t.const_int(1, false);
+
+        // An empty separator doesn't split the string.
+        let separator_len = self.strlen(separator);
+        let empty = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            separator_len,
+            size_t.const_zero(),
+            "",
+        );
+        let current = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        let search = self.context.append_basic_block(frame.function, "");
+        let next = self.context.append_basic_block(frame.function, "");
+        let done = self.context.append_basic_block(frame.function, "");
+        self.builder.build_conditional_branch(empty, done, search);
+
+        self.builder.position_at_end(search);
+        let count = self.builder.build_phi(size_t, "count");
+        let rest = self.builder.build_phi(pointer, "rest");
+        let count_value = count.as_basic_value().into_int_value();
+        let found = self.strstr(rest.as_basic_value().into_pointer_value(), separator);
+        self.builder
+            .build_conditional_branch(self.builder.build_is_null(found, ""), done, next);
+
+        self.builder.position_at_end(next);
+        let next_count = self.builder.build_int_add(count_value, one, "");
+        let next_rest = self.byte_at(found, separator_len);
+        self.builder.build_unconditional_branch(search);
+        count.add_incoming(&[(&one, current), (&next_count, next)]);
+        rest.add_incoming(&[(&s, current), (&next_rest, next)]);
+
+        self.builder.position_at_end(done);
+        let result = self.builder.build_phi(size_t, "");
+        result.add_incoming(&[(&one, current), (&count_value, search)]);
+        result.as_basic_value().into_int_value()
+    }
+
+    /// Copy the part of a string at `index` when it's split at each separator into a new
+    /// string, aborting the program if there's no such part.
+    fn split(
+        &self,
+        frame: &mut Frame<'ctx>,
+        s: PointerValue<'ctx>,
+        separator: PointerValue<'ctx>,
+        index: IntValue<'ctx>,
+    ) -> PointerValue<'ctx> {
+        let (pointer, size_t) = (self.pointer_type(), self.context.i64_type());
+
+        // Negative indices are out of bounds as unsigned values too.
+        let count = self.split_count(frame, s, separator);
+        let out_of_bounds = self
+            .builder
+            .build_int_compare(IntPredicate::UGE, index, count, "");
+        self.trap_if(frame, out_of_bounds);
+
+        // Skip a part for each separator before the one at `index`.
+        let separator_len = self.strlen(separator);
+        let current = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        let skip = self.context.append_basic_block(frame.function, "");
+        let next = self.context.append_basic_block(frame.function, "");
+        let found = self.context.append_basic_block(frame.function, "");
+        self.builder.build_unconditional_branch(skip);
+
+        self.builder.position_at_end(skip);
+        let i = self.builder.build_phi(size_t, "i");
+        let part = self.builder.build_phi(pointer, "part");
+        let (i_value, part_value) = (
+            i.as_basic_value().into_int_value(),
+            part.as_basic_value().into_pointer_value(),
+        );
+        let reached = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, i_value, index, "");
+        self.builder.build_conditional_branch(reached, found, next);
+
+        self.builder.position_at_end(next);
+        let next_i = self
+            .builder
+            .build_int_add(i_value, size_t.const_int(1, false), "");
+        let next_part = self.byte_at(self.strstr(part_value, separator), separator_len);
+        self.builder.build_unconditional_branch(skip);
+        i.add_incoming(&[(&size_t.const_zero(), current), (&next_i, next)]);
+        part.add_incoming(&[(&s, current), (&next_part, next)]);
+
+        // The part ends at the next separator, or the end of the string if it's the last part
+        // or the separator is empty.
+        self.builder.position_at_end(found);
+        let part_len = self.strlen(part_value);
+        let end = self.strstr(part_value, separator);
+        let empty = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            separator_len,
+            size_t.const_zero(),
+            "",
+        );
+        let whole = self
+            .builder
+            .build_or(self.builder.build_is_null(end, ""), empty, "");
+        let end = self
+            .builder
+            .build_select(whole, self.byte_at(part_value, part_len), end, "")
+            .into_pointer_value();
+        let len = self.builder.build_int_sub(part_len, self.strlen(end), "");
+        self.copy_str(part_value, len)
+    }
+
+    /// Parse an int with `strtoll`, aborting the program unless the string is an optional sign
+    /// followed by decimal digits that fit in an int. `strtoll` clamps ints that don't fit, so
+    /// they're found by comparing their digits to the limits'.
+    fn str_to_int(&mut self, frame: &mut Frame<'ctx>, s: PointerValue<'ctx>) -> IntValue<'ctx> {
+        let (pointer, c_int, size_t) = (
+            self.pointer_type(),
+            self.context.i32_type(),
+            self.context.i64_type(),
+        );
+        let zero = size_t.const_zero();
+
+        let negative = self.is_byte(s, b'-');
+        let signed = self.builder.build_or(negative, self.is_byte(s, b'+'), "");
+        let digits = self.byte_at(s, self.builder.build_int_z_extend(signed, size_t, ""));
+        let len = self.strlen(digits);
+        let non_empty = self
+            .builder
+            .build_int_compare(IntPredicate::NE, len, zero, "");
+        let digit_count = self.strspn(digits, DIGITS);
+        let all_digits = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, digit_count, len, "");
+
+        // Leading zeros don't count towards the int's size.
+        let zeros = self.strspn(digits, "0");
+        let significant = self.byte_at(digits, zeros);
+        let significant_len = self.builder.build_int_sub(len, zeros, "");
+        let (max, min) = (
+            self.string(&i64::MAX.to_string()),
+            self.string(&i64::MIN.unsigned_abs().to_string()),
+        );
+        let limit = self
+            .builder
+            .build_select(negative, min, max, "")
+            .into_pointer_value();
+        let limit_len = size_t.const_int(i64::MAX.to_string().len() as u64, false);
+        let shorter =
+            self.builder
+                .build_int_compare(IntPredicate::ULT, significant_len, limit_len, "");
+        let as_long =
+            self.builder
+                .build_int_compare(IntPredicate::EQ, significant_len, limit_len, "");
+        let within =
+            self.builder
+                .build_and(as_long, self.str_compare(BinOp::Le, significant, limit), "");
+        let fits = self.builder.build_or(shorter, within, "");
+
+        let valid = self.builder.build_and(non_empty, all_digits, "");
+        let valid = self.builder.build_and(valid, fits, "");
+        self.trap_if(frame, self.builder.build_not(valid, ""));
+
+        self.call_c(
+            "strtoll",
+            size_t.fn_type(&[pointer.into(), pointer.into(), c_int.into()], false),
+            &[
+                s.into(),
+                pointer.const_null().into(),
+                c_int.const_int(10, false).into(),
+            ],
+        )
+        .expect("`strtoll` returns an int")
+        .into_int_value()
+    }
+
+    /// Parse a float like [`hir::float::from_str`] with `strtod`, aborting the program if the
+    /// string isn't a float. `strtod` accepts more than matrix does, so the syntax is checked
+    /// first: the digits of each part of a literal are skipped, with every pointer selected
+    /// rather than branched to.
+    fn str_to_float(&mut self, frame: &mut Frame<'ctx>, s: PointerValue<'ctx>) -> FloatValue<'ctx> {
+        let (pointer, size_t, float) = (
+            self.pointer_type(),
+            self.context.i64_type(),
+            self.context.f64_type(),
+        );
+        let (zero, one) = (size_t.const_zero(), size_t.const_int(1, false));
+        let nan = self.string("nan");
+        let inf = self.string("inf");
+
+        let is_nan = self.str_compare(BinOp::Eq, s, nan);
+        let negative = self.is_byte(s, b'-');
+        let unsigned = self.byte_at(s, self.builder.build_int_z_extend(negative, size_t, ""));
+        let is_inf = self.str_compare(BinOp::Eq, unsigned, inf);
+
+        let whole = self.strspn(unsigned, DIGITS);
+        let has_whole = self
+            .builder
+            .build_int_compare(IntPredicate::NE, whole, zero, "");
+        let rest = self.byte_at(unsigned, whole);
+
+        let has_point = self.is_byte(rest, b'.');
+        let fraction_start = self
+            .builder
+            .build_select(has_point, self.byte_at(rest, one), rest, "")
+            .into_pointer_value();
+        let fraction = self.strspn(fraction_start, DIGITS);
+        let no_fraction = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, fraction, zero, "");
+        let fraction_invalid = self.builder.build_and(has_point, no_fraction, "");
+        let rest = self
+            .builder
+            .build_select(has_point, self.byte_at(fraction_start, fraction), rest, "")
+            .into_pointer_value();
+
+        let has_exponent =
+            self.builder
+                .build_or(self.is_byte(rest, b'e'), self.is_byte(rest, b'E'), "");
+        let sign = self
+            .builder
+            .build_select(has_exponent, self.byte_at(rest, one), rest, "")
+            .into_pointer_value();
+        let has_sign =
+            self.builder
+                .build_or(self.is_byte(sign, b'+'), self.is_byte(sign, b'-'), "");
+        let has_sign = self.builder.build_and(has_exponent, has_sign, "");
+        let exponent_start = self
+            .builder
+            .build_select(has_sign, self.byte_at(sign, one), sign, "")
+            .into_pointer_value();
+        let exponent = self.strspn(exponent_start, DIGITS);
+        let no_exponent = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, exponent, zero, "");
+        let exponent_invalid = self.builder.build_and(has_exponent, no_exponent, "");
+        let end = self
+            .builder
+            .build_select(
+                has_exponent,
+                self.byte_at(exponent_start, exponent),
+                rest,
+                "",
+            )
+            .into_pointer_value();
+
+        let value = self
+            .call_c(
+                "strtod",
+                float.fn_type(&[pointer.into(), pointer.into()], false),
+                &[s.into(), pointer.const_null().into()],
+            )
+            .expect("`strtod` returns a float")
+            .into_float_value();
+        // `strtod` gives an infinity for literals too large to be finite.
+        let magnitude = self
+            .float_intrinsic(Builtin::Abs, &[value.into()])
+            .into_float_value();
+        let finite = self.builder.build_float_compare(
+            FloatPredicate::OLT,
+            magnitude,
+            float.const_float(f64::INFINITY),
+            "",
+        );
+
+        let invalid = self
+            .builder
+            .build_or(fraction_invalid, exponent_invalid, "");
+        let literal = self.builder.build_and(has_whole, self.is_byte(end, 0), "");
+        let literal = self
+            .builder
+            .build_and(literal, self.builder.build_not(invalid, ""), "");
+        let literal = self.builder.build_and(literal, finite, "");
+        let valid = self.builder.build_or(is_nan, is_inf, "");
+        let valid = self.builder.build_or(valid, literal, "");
+        self.trap_if(frame, self.builder.build_not(valid, ""));
+        value
+    }
+
+    /// Allocate a stack slot in the entry block, so one used in a loop doesn't grow the stack.
+    fn entry_alloca(
+        &self,
+        frame: &Frame<'ctx>,
+        ty: impl BasicType<'ctx>,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        let entry = frame
+            .function
+            .get_first_basic_block()
+            .expect("functions have an entry block");
+        let allocas = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(first) => allocas.position_before(&first),
+            None => allocas.position_at_end(entry),
+        }
+        allocas.build_alloca(ty, name)
+    }
+
+    /// A C `timespec` on the stack, with pointers to its seconds and nanoseconds.
+    fn timespec(
+        &self,
+        frame: &Frame<'ctx>,
+    ) -> (PointerValue<'ctx>, PointerValue<'ctx>, PointerValue<'ctx>) {
+        let long = self.context.i64_type();
+        let timespec = self.entry_alloca(frame, long.array_type(2), "timespec");
+        // SAFETY: both indices are within the two fields.
+        let [secs, nanos] = [0, 1].map(|field| unsafe {
+            self.builder.build_in_bounds_gep(
+                long.array_type(2),
+                timespec,
+                &[long.const_zero(), long.const_int(field, false)],
+                "",
+            )
+        });
+        (timespec, secs, nanos)
+    }
+
+    /// The milliseconds of `CLOCK_MONOTONIC`, from `clock_gettime`.
+    fn clock_ms(&self, frame: &Frame<'ctx>) -> IntValue<'ctx> {
+        let (long, c_int) = (self.context.i64_type(), self.context.i32_type());
+        let (timespec, secs, nanos) = self.timespec(frame);
+        self.call_c(
+            "clock_gettime",
+            c_int.fn_type(&[c_int.into(), self.pointer_type().into()], false),
+            &[c_int.const_int(1, false).into(), timespec.into()],
+        );
+
+        let secs = self.builder.build_load(long, secs, "").into_int_value();
+        let nanos = self.builder.build_load(long, nanos, "").into_int_value();
+        let secs = self
+            .builder
+            .build_int_mul(secs, long.const_int(1000, false), "");
+        let millis = self
+            .builder
+            .build_int_signed_div(nanos, long.const_int(1_000_000, false), "");
+        self.builder.build_int_add(secs, millis, "")
+    }
+
+    /// Sleep with `nanosleep`, which doesn't sleep for a negative `timespec`.
+    fn sleep_ms(&self, frame: &Frame<'ctx>, ms: IntValue<'ctx>) {
+        let (long, pointer) = (self.context.i64_type(), self.pointer_type());
+        let (timespec, secs, nanos) = self.timespec(frame);
+        let thousand = long.const_int(1000, false);
+        let whole = self.builder.build_int_signed_div(ms, thousand, "");
+        let rest = self.builder.build_int_signed_rem(ms, thousand, "");
+        let rest = self
+            .builder
+            .build_int_mul(rest, long.const_int(1_000_000, false), "");
+        self.builder.build_store(secs, whole);
+        self.builder.build_store(nanos, rest);
+        self.call_c(
+            "nanosleep",
+            self.context
+                .i32_type()
+                .fn_type(&[pointer.into(), pointer.into()], false),
+            &[timespec.into(), pointer.const_null().into()],
+        );
+    }
+
+    /// Read a line from standard input with `getline`, without its line ending, or an empty
+    /// string at the end of the input.
+    fn read_line(&mut self, frame: &Frame<'ctx>) -> PointerValue<'ctx> {
+        let pointer = self.pointer_type();
+        let size_t = self.context.i64_type();
+        let empty = self.string("");
+        let line_endings = self.string("\r\n");
+
+        // The buffer and its capacity are reset on every call, so each line gets a new buffer.
+        let line = self.entry_alloca(frame, pointer, "line");
+        let capacity = self.entry_alloca(frame, size_t, "capacity");
+        self.builder.build_store(line, pointer.const_null());
+        self.builder.build_store(capacity, size_t.const_zero());
+
+        let stdin = self
+            .module
+            .get_global("stdin")
+            .unwrap_or_else(|| self.module.add_global(pointer, None, "stdin"));
+        let stream = self
+            .builder
+            .build_load(pointer, stdin.as_pointer_value(), "");
+        let read = self
+            .call_c(
+                "getline",
+                size_t.fn_type(&[pointer.into(), pointer.into(), pointer.into()], false),
+                &[line.into(), capacity.into(), stream.into()],
+            )
+            .expect("`getline` returns a length")
+            .into_int_value();
+        let at_end =
+            self.builder
+                .build_int_compare(IntPredicate::SLT, read, size_t.const_zero(), "");
+
+        let current = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned in a block");
+        let strip = self.context.append_basic_block(frame.function, "");
+        let done = self.context.append_basic_block(frame.function, "");
+        self.builder.build_conditional_branch(at_end, done, strip);
+
+        self.builder.position_at_end(strip);
+        let text = self
+            .builder
+            .build_load(pointer, line, "")
+            .into_pointer_value();
+        let len = self
+            .call_c(
+                "strcspn",
+                size_t.fn_type(&[pointer.into(), pointer.into()], false),
+                &[text.into(), line_endings.into()],
+            )
+            .expect("`strcspn` returns a length")
+            .into_int_value();
+        self.builder
+            .build_store(self.byte_at(text, len), self.context.i8_type().const_zero());
+        self.builder.build_unconditional_branch(done);
+
+        self.builder.position_at_end(done);
+        let result = self.builder.build_phi(pointer, "");
+        result.add_incoming(&[(&empty, current), (&text, strip)]);
+        result.as_basic_value().into_pointer_value()
+    }
+
+    /// Open a file with `fopen`, aborting the program if it can't be.
+    fn fopen(
+        &mut self,
+        frame: &mut Frame<'ctx>,
+        path: PointerValue<'ctx>,
+        mode: &str,
+    ) -> PointerValue<'ctx> {
+        let pointer = self.pointer_type();
+        let mode = self.string(mode);
+        let file = self
+            .call_c(
+                "fopen",
+                pointer.fn_type(&[pointer.into(), pointer.into()], false),
+                &[path.into(), mode.into()],
+            )
+            .expect("`fopen` returns a file")
+            .into_pointer_value();
+        self.trap_if(frame, self.builder.build_is_null(file, ""));
+        file
+    }
+
+    /// Close a file, aborting the program if its writes couldn't be finished.
+    fn fclose(&self, frame: &mut Frame<'ctx>, file: PointerValue<'ctx>) {
+        let c_int = self.context.i32_type();
+        let status = self
+            .call_c(
+                "fclose",
+                c_int.fn_type(&[self.pointer_type().into()], false),
+                &[file.into()],
+            )
+            .expect("`fclose` returns a status")
+            .into_int_value();
+        let failed =
+            self.builder
+                .build_int_compare(IntPredicate::NE, status, c_int.const_zero(), "");
+        self.trap_if(frame, failed);
+    }
+
+    /// Read the whole of a file into a new string, aborting the program if it can't be read.
+    /// Unlike the interpreter, this doesn't check the contents are UTF-8.
+    fn read_file(
+        &mut self,
+        frame: &mut Frame<'ctx>,
+        path: PointerValue<'ctx>,
+    ) -> PointerValue<'ctx> {
+        const SEEK_SET: u64 = 0;
+        const SEEK_END: u64 = 2;
+
+        let (pointer, c_int, size_t) = (
+            self.pointer_type(),
+            self.context.i32_type(),
+            self.context.i64_type(),
+        );
+        let file = self.fopen(frame, path, "rb");
+
+        // The file's length is where seeking to its end leaves it.
+        let fseek = c_int.fn_type(&[pointer.into(), size_t.into(), c_int.into()], false);
+        self.call_c(
+            "fseek",
+            fseek,
+            &[
+                file.into(),
+                size_t.const_zero().into(),
+                c_int.const_int(SEEK_END, false).into(),
+            ],
+        );
+        let len = self
+            .call_c(
+                "ftell",
+                size_t.fn_type(&[pointer.into()], false),
+                &[file.into()],
+            )
+            .expect("`ftell` returns a position")
+            .into_int_value();
+        let failed =
+            self.builder
+                .build_int_compare(IntPredicate::SLT, len, size_t.const_zero(), "");
+        self.trap_if(frame, failed);
+        self.call_c(
+            "fseek",
+            fseek,
+            &[
+                file.into(),
+                size_t.const_zero().into(),
+                c_int.const_int(SEEK_SET, false).into(),
+            ],
+        );
+
+        let size = self
+            .builder
+            .build_int_add(len, size_t.const_int(1, false), "");
+        let buffer = self.malloc(size);
+        let read = self
+            .call_c(
+                "fread",
+                size_t.fn_type(
+                    &[pointer.into(), size_t.into(), size_t.into(), pointer.into()],
+                    false,
+                ),
+                &[
+                    buffer.into(),
+                    size_t.const_int(1, false).into(),
+                    len.into(),
+                    file.into(),
+                ],
+            )
+            .expect("`fread` returns a length")
+            .into_int_value();
+        self.fclose(frame, file);
+        let short = self
+            .builder
+            .build_int_compare(IntPredicate::NE, read, len, "");
+        self.trap_if(frame, short);
+
+        self.builder.build_store(
+            self.byte_at(buffer, len),
+            self.context.i8_type().const_zero(),
+        );
+        buffer
+    }
+
+    /// Write a string to a file opened with an `fopen` mode, aborting the program if it can't
+    /// be written.
+    fn write_file(
+        &mut self,
+        frame: &mut Frame<'ctx>,
+        path: PointerValue<'ctx>,
+        contents: PointerValue<'ctx>,
+        mode: &str,
+    ) {
+        let (pointer, c_int) = (self.pointer_type(), self.context.i32_type());
+        let file = self.fopen(frame, path, mode);
+        let status = self
+            .call_c(
+                "fputs",
+                c_int.fn_type(&[pointer.into(), pointer.into()], false),
+                &[contents.into(), file.into()],
+            )
+            .expect("`fputs` returns a status")
+            .into_int_value();
+        // `fputs` returns `EOF`, which is negative, when it fails.
+        let failed =
+            self.builder
+                .build_int_compare(IntPredicate::SLT, status, c_int.const_zero(), "");
+        self.trap_if(frame, failed);
+        self.fclose(frame, file);
+    }
+
+    fn builtin(
+        &mut self,
+        frame: &mut Frame<'ctx>,
+        builtin: Builtin,
+        args: &[(Ty, BasicValueEnum<'ctx>)],
+    ) -> Option<BasicValueEnum<'ctx>> {
+        let pointer = self.pointer_type();
+        let c_int = self.context.i32_type();
+
+        match (builtin, args) {
+            (Builtin::Print, &[(_, s)]) => {
+                let format = self.string("%s");
+                self.call_c(
+                    "printf",
+                    c_int.fn_type(&[pointer.into()], true),
+                    &[format.into(), s.into()],
+                );
+                None
+            }
+            (Builtin::Println, &[(_, s)]) => {
+                self.call_c("puts", c_int.fn_type(&[pointer.into()], false), &[s.into()]);
+                None
+            }
+            (Builtin::ReadLine, []) => Some(self.read_line(frame).into()),
+            (Builtin::Seed, &[(_, seed)]) => {
+                self.builder.build_store(self.rng_global(), seed);
+                None
+            }
+            (Builtin::RandInt, &[(_, lo), (_, hi)]) => Some(
+                self.rand_int(frame, lo.into_int_value(), hi.into_int_value())
+                    .into(),
+            ),
+            (Builtin::RandFloat, []) => {
+                let f64_type = self.context.f64_type();
+                let bits = self.builder.build_right_shift(
+                    self.rng_next(),
+                    self.context.i64_type().const_int(11, false),
+                    false,
+                    "",
+                );
+                let x = self.builder.build_unsigned_int_to_float(bits, f64_type, "");
+                let scale = f64_type.const_float(1.0 / (1u64 << 53) as f64);
+                Some(self.builder.build_float_mul(x, scale, "").into())
+            }
+            // Executables abort like they do for every other error, without the message.
+            (Builtin::Assert, &[(_, condition), _]) => {
+                let failed = self.builder.build_not(condition.into_int_value(), "");
+                self.trap_if(frame, failed);
+                None
+            }
+            (Builtin::Panic, [_]) => {
+                let trap = self.trap_block(frame);
+                self.builder.build_unconditional_branch(trap);
+                // MIR ends the block after a `panic`, so its terminator needs a block of its own.
+                let next = self.context.append_basic_block(frame.function, "");
+                self.builder.position_at_end(next);
+                None
+            }
+            (Builtin::ToStr, &[(ty, value)]) => {
+                let s = match ty {
+                    Ty::Int(int) => {
+                        // `printf` takes 64-bit ints, so narrower ones are extended first.
+                        let (value, long) = (value.into_int_value(), self.context.i64_type());
+                        let value = match int.bits() {
+                            64 => value,
+                            _ if int.is_signed() => {
+                                self.builder.build_int_s_extend(value, long, "")
+                            }
+                            _ => self.builder.build_int_z_extend(value, long, ""),
+                        };
+                        let format = if int.is_signed() { "%lld" } else { "%llu" };
+                        self.format(format, value.into())
+                    }
+                    Ty::Float => self.float_to_str(value.into_float_value()),
+                    Ty::Bool => {
+                        let (yes, no) = (self.string("true"), self.string("false"));
+                        self.builder
+                            .build_select(value.into_int_value(), yes, no, "")
+                            .into_pointer_value()
+                    }
+                    Ty::Char => self.char_to_str(value.into_int_value()),
+                    Ty::Str => value.into_pointer_value(),
+                    Ty::Void | Ty::Error | Ty::Array(_) | Ty::Map(..) => {
+                        unreachable!("`to_str` is type checked, found {ty}")
+                    }
+                };
+                Some(s.into())
+            }
+            (Builtin::Len, &[(_, s)]) => Some(self.strlen(s.into_pointer_value()).into()),
+            (Builtin::Slice, &[(_, s), (_, start), (_, end)]) => Some(
+                self.str_slice(
+                    frame,
+                    s.into_pointer_value(),
+                    start.into_int_value(),
+                    end.into_int_value(),
+                )
+                .into(),
+            ),
+            (Builtin::ArgCount, []) => {
+                let (count, _) = self.arg_globals();
+                Some(self.builder.build_load(self.context.i64_type(), count, ""))
+            }
+            (Builtin::Arg, &[(_, index)]) => {
+                let index = index.into_int_value();
+                let (count, args) = self.arg_globals();
+                let count = self
+                    .builder
+                    .build_load(self.context.i64_type(), count, "")
+                    .into_int_value();
+                // Negative indices are out of bounds as unsigned values too.
+                let out_of_bounds =
+                    self.builder
+                        .build_int_compare(IntPredicate::UGE, index, count, "");
+                self.trap_if(frame, out_of_bounds);
+
+                let args = self
+                    .builder
+                    .build_load(pointer, args, "")
+                    .into_pointer_value();
+                // SAFETY: the index was checked to be within `argv`.
+                let arg = unsafe {
+                    self.builder
+                        .build_in_bounds_gep(pointer, args, &[index], "")
+                };
+                Some(self.builder.build_load(pointer, arg, ""))
+            }
+            (Builtin::ReadFile, &[(_, path)]) => {
+                Some(self.read_file(frame, path.into_pointer_value()).into())
+            }
+            (Builtin::WriteFile | Builtin::AppendFile, &[(_, path), (_, contents)]) => {
+                let mode = if builtin == Builtin::WriteFile {
+                    "wb"
+                } else {
+                    "ab"
+                };
+                self.write_file(
+                    frame,
+                    path.into_pointer_value(),
+                    contents.into_pointer_value(),
+                    mode,
+                );
+                None
+            }
+            (Builtin::Substr, &[(_, s), (_, start), (_, count)]) => {
+                let start = start.into_int_value();
+                let end = self
+                    .builder
+                    .build_int_add(start, count.into_int_value(), "");
+                Some(
+                    self.str_slice(frame, s.into_pointer_value(), start, end)
+                        .into(),
+                )
+            }
+            (Builtin::CharAt, &[(_, s), (_, index)]) => Some(
+                self.char_at(frame, s.into_pointer_value(), index.into_int_value())
+                    .into(),
+            ),
+            (Builtin::Contains, &[(_, s), (_, pattern)]) => {
+                let found = self.strstr(s.into_pointer_value(), pattern.into_pointer_value());
+                let missing = self.builder.build_is_null(found, "");
+                Some(self.builder.build_not(missing, "").into())
+            }
+            (Builtin::SplitCount, &[(_, s), (_, separator)]) => Some(
+                self.split_count(
+                    frame,
+                    s.into_pointer_value(),
+                    separator.into_pointer_value(),
+                )
+                .into(),
+            ),
+            (Builtin::Split, &[(_, s), (_, separator), (_, index)]) => Some(
+                self.split(
+                    frame,
+                    s.into_pointer_value(),
+                    separator.into_pointer_value(),
+                    index.into_int_value(),
+                )
+                .into(),
+            ),
+            (Builtin::ToInt, &[(Ty::Char, c)]) => Some(
+                self.builder
+                    .build_int_z_extend(c.into_int_value(), self.context.i64_type(), "")
+                    .into(),
+            ),
+            (Builtin::ToInt, &[(_, s)]) => {
+                Some(self.str_to_int(frame, s.into_pointer_value()).into())
+            }
+            (Builtin::FromInt, &[(_, code)]) => {
+                Some(self.int_to_char(frame, code.into_int_value()).into())
+            }
+            (Builtin::ToFloat, &[(_, s)]) => {
+                Some(self.str_to_float(frame, s.into_pointer_value()).into())
+            }
+            (Builtin::Sqrt | Builtin::Abs | Builtin::Floor | Builtin::Ceil, &[(_, x)]) => {
+                Some(self.float_intrinsic(builtin, &[x]))
+            }
+            (Builtin::Pow | Builtin::Min | Builtin::Max, &[(_, x), (_, y)]) => {
+                Some(self.float_intrinsic(builtin, &[x, y]))
+            }
+            _ => unreachable!("arguments to `{}` are type checked", builtin.name()),
+        }
+    }
+
+    fn system_native(
+        &mut self,
+        frame: &mut Frame<'ctx>,
+        native: SystemNative,
+        args: &[BasicValueEnum<'ctx>],
+    ) -> Option<BasicValueEnum<'ctx>> {
+        match (native, args) {
+            (SystemNative::Env, &[name]) => {
+                let pointer = self.pointer_type();
+                let value = self
+                    .call_c(
+                        "getenv",
+                        pointer.fn_type(&[pointer.into()], false),
+                        &[name.into()],
+                    )
+                    .expect("`getenv` returns a pointer")
+                    .into_pointer_value();
+                // `getenv` returns null for variables that aren't set.
+                let unset = self.builder.build_is_null(value, "");
+                let empty = self.string("");
+                Some(self.builder.build_select(unset, empty, value, ""))
+            }
+            (SystemNative::ClockMs, []) => Some(self.clock_ms(frame).into()),
+            (SystemNative::SleepMs, &[ms]) => {
+                self.sleep_ms(frame, ms.into_int_value());
+                None
+            }
+            _ => unreachable!("arguments to `{}` are type checked", native.name()),
+        }
+    }
+
+    /// Call the LLVM intrinsic implementing a `math` builtin.
+    fn float_intrinsic(
+        &self,
+        builtin: Builtin,
+        args: &[BasicValueEnum<'ctx>],
+    ) -> BasicValueEnum<'ctx> {
+        let name = match builtin {
+            Builtin::Sqrt => "llvm.sqrt.f64",
+            Builtin::Abs => "llvm.fabs.f64",
+            Builtin::Pow => "llvm.pow.f64",
+            Builtin::Floor => "llvm.floor.f64",
+            Builtin::Ceil => "llvm.ceil.f64",
+            // `minnum` and `maxnum` ignore `nan`s, like the interpreter.
+            Builtin::Min => "llvm.minnum.f64",
+            Builtin::Max => "llvm.maxnum.f64",
+            _ => unreachable!("`{}` isn't a `math` builtin", builtin.name()),
+        };
+
+        let f64_type = self.context.f64_type();
+        let params = vec![f64_type.into(); args.len()];
+        let args: Vec<_> = args.iter().map(|&arg| arg.into()).collect();
+        self.call_c(name, f64_type.fn_type(&params, false), &args)
+            .expect("float intrinsics return a float")
+    }
+
+    fn binary(
+        &self,
+        frame: &mut Frame<'ctx>,
+        ty: Ty,
+        op: BinOp,
+        lhs: BasicValueEnum<'ctx>,
+        rhs: BasicValueEnum<'ctx>,
+    ) -> BasicValueEnum<'ctx> {
+        match ty {
+            Ty::Int(int) => self
+                .int_binary(frame, int, op, lhs.into_int_value(), rhs.into_int_value())
+                .into(),
+            Ty::Float => self.float_binary(op, lhs.into_float_value(), rhs.into_float_value()),
+            // Chars and bools compare as their unsigned integer values.
+            Ty::Bool | Ty::Char => self
+                .builder
+                .build_int_compare(
+                    int_predicate(op, false),
+                    lhs.into_int_value(),
+                    rhs.into_int_value(),
+                    "",
+                )
+                .into(),
+            Ty::Str if op == BinOp::Add => self
+                .str_concat(lhs.into_pointer_value(), rhs.into_pointer_value())
+                .into(),
+            Ty::Str => self
+                .str_compare(op, lhs.into_pointer_value(), rhs.into_pointer_value())
+                .into(),
+            Ty::Void | Ty::Error | Ty::Array(_) | Ty::Map(..) => {
+                unreachable!("`{op:?}` is type checked, found {ty}")
+            }
+        }
+    }
+
+    fn inst(&mut self, frame: &mut Frame<'ctx>, body: &Body, inst: &Inst) {
+        let value = match &inst.kind {
+            InstKind::Unary { op, operand } => {
+                let ty = operand_ty(body, operand);
+                let operand = self.operand(frame, operand);
+                Some(self.unary(frame, *op, ty, operand))
+            }
+            InstKind::Binary { lhs, op, rhs } => {
+                let ty = operand_ty(body, lhs);
+                let lhs = self.operand(frame, lhs);
+                let rhs = self.operand(frame, rhs);
+                Some(self.binary(frame, ty, *op, lhs, rhs))
+            }
+            InstKind::Call { callee, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.operand(frame, arg).into())
+                    .collect::<Vec<BasicMetadataValueEnum<'_>>>();
+                self.builder
+                    .build_call(self.functions[callee.0 as usize], &args, "")
+                    .try_as_basic_value()
+                    .left()
+            }
+            InstKind::Builtin { builtin, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| (operand_ty(body, arg), self.operand(frame, arg)))
+                    .collect::<Vec<_>>();
+                self.builtin(frame, *builtin, &args)
+            }
+            InstKind::Array { .. }
+            | InstKind::Map { .. }
+            | InstKind::Index { .. }
+            | InstKind::Store { .. } => unreachable!("`translate` rejects arrays and maps"),
+            InstKind::Native { native, args } => {
+                let native = SystemNative::from_id(*native)
+                    .expect("`translate` rejects calls to natives other than the system's");
+                let args = args
+                    .iter()
+                    .map(|arg| self.operand(frame, arg))
+                    .collect::<Vec<_>>();
+                self.system_native(frame, native, &args)
+            }
+        };
+
+        if let Some(dest) = inst.dest {
+            let value = value.expect("instructions with a destination produce a value");
+            frame.values.insert(dest, value);
+        }
+    }
+
+    fn terminator(&mut self, frame: &Frame<'ctx>, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => {
+                self.builder
+                    .build_unconditional_branch(frame.start(*target));
+            }
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let cond = self.operand(frame, cond).into_int_value();
+                self.builder.build_conditional_branch(
+                    cond,
+                    frame.start(*then),
+                    frame.start(*otherwise),
+                );
+            }
+            Terminator::Switch {
+                value,
+                low,
+                targets,
+                otherwise,
+            } => {
+                let value = self.operand(frame, value).into_int_value();
+                let int = value.get_type();
+                let cases = targets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, target)| {
+                        let case = int.const_int((low + i as i64) as u64, true);
+                        (case, frame.start(*target))
+                    })
+                    .collect::<Vec<_>>();
+                self.builder
+                    .build_switch(value, frame.start(*otherwise), &cases);
+            }
+            Terminator::Ret(Some(value)) => {
+                let value = self.operand(frame, value);
+                self.builder.build_return(Some(&value));
+            }
+            Terminator::Ret(None) => {
+                self.builder.build_return(None);
+            }
+            Terminator::Unreachable => {
+                self.builder.build_unreachable();
+            }
+        }
+    }
+
+    fn body(&mut self, body: &Body) {
+        let function = self.functions[body.proc.0 as usize];
+        let starts = (0..body.blocks.len())
+            .map(|i| self.context.append_basic_block(function, &format!("bb{i}")))
+            .collect::<Vec<_>>();
+        let mut frame = Frame {
+            function,
+            values: HashMap::new(),
+            ends: starts.clone(),
+            starts,
+            trap: None,
+        };
+
+        for (i, &param) in body.params.iter().enumerate() {
+            let value = function
+                .get_nth_param(i as u32)
+                .expect("functions are declared with every parameter");
+            frame.values.insert(param, value);
+        }
+
+        // Phis are created before any instruction, since their incoming values can be defined
+        // later on, in blocks that loop back.
+        let mut phis = Vec::new();
+        for (i, (block, &start)) in body.blocks.iter().zip(&frame.starts).enumerate() {
+            self.builder.position_at_end(start);
+            for phi in &block.phis {
+                let ty =
+                    basic_type(self.context, body.temp_ty(phi.dest)).expect("phis aren't void");
+                let value = self.builder.build_phi(ty, "");
+                frame.values.insert(phi.dest, value.as_basic_value());
+                phis.push((value, phi, BlockId(i as u32)));
+            }
+        }
+
+        for (i, block) in body.blocks.iter().enumerate() {
+            self.builder.position_at_end(frame.starts[i]);
+            for inst in &block.insts {
+                self.inst(&mut frame, body, inst);
+            }
+            self.terminator(&frame, &block.terminator);
+            frame.ends[i] = self
+                .builder
+                .get_insert_block()
+                .expect("the builder is positioned in a block");
+        }
+
+        for (value, phi, block) in phis {
+            for (pred, operand) in &phi.incoming {
+                let incoming = self.operand(&frame, operand);
+                for _ in 0..edges(&body.block(*pred).terminator, block) {
+                    value.add_incoming(&[(&incoming, frame.ends[pred.0 as usize])]);
+                }
+            }
+        }
+    }
+
+    /// The globals holding the number of arguments the program was run with and a pointer to
+    /// the first of them, which the C `main` function sets.
+    fn arg_globals(&self) -> (PointerValue<'ctx>, PointerValue<'ctx>) {
+        let (int, pointer) = (self.context.i64_type(), self.pointer_type());
+        let count = self.module.get_global(ARG_COUNT).unwrap_or_else(|| {
+            let global = self.module.add_global(int, None, ARG_COUNT);
+            global.set_initializer(&int.const_zero());
+            global.set_linkage(Linkage::Private);
+            global
+        });
+        let args = self.module.get_global(ARGS).unwrap_or_else(|| {
+            let global = self.module.add_global(pointer, None, ARGS);
+            global.set_initializer(&pointer.const_null());
+            global.set_linkage(Linkage::Private);
+            global
+        });
+        (count.as_pointer_value(), args.as_pointer_value())
+    }
+
+    fn rng_global(&self) -> PointerValue<'ctx> {
+        let int = self.context.i64_type();
+        let state = self.module.get_global(RNG_STATE).unwrap_or_else(|| {
+            let global = self.module.add_global(int, None, RNG_STATE);
+            global.set_initializer(&int.const_zero());
+            global.set_linkage(Linkage::Private);
+            global
+        });
+        state.as_pointer_value()
+    }
+
+    /// Advance the `rand` generator, like the interpreter's `Rng::next_u64`.
+    fn rng_next(&self) -> IntValue<'ctx> {
+        let int = self.context.i64_type();
+        let global = self.rng_global();
+        let state = self.builder.build_load(int, global, "").into_int_value();
+        let state =
+            self.builder
+                .build_int_add(state, int.const_int(0x9e37_79b9_7f4a_7c15, false), "");
+        self.builder.build_store(global, state);
+
+        let mix = |z: IntValue<'ctx>, shift: u64, factor: Option<u64>| {
+            let shifted = self
+                .builder
+                .build_right_shift(z, int.const_int(shift, false), false, "");
+            let z = self.builder.build_xor(z, shifted, "");
+            match factor {
+                Some(factor) => self
+                    .builder
+                    .build_int_mul(z, int.const_int(factor, false), ""),
+                None => z,
+            }
+        };
+        let z = mix(state, 30, Some(0xbf58_476d_1ce4_e5b9));
+        let z = mix(z, 27, Some(0x94d0_49bb_1331_11eb));
+        mix(z, 31, None)
+    }
+
+    /// A pseudo-random int from `lo` up to and including `hi`, aborting the program if the
+    /// range is empty. The generator's next number is scaled by the size of the range, keeping
+    /// the high bits, and a size of 0 is the whole range.
+    fn rand_int(
+        &self,
+        frame: &mut Frame<'ctx>,
+        lo: IntValue<'ctx>,
+        hi: IntValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        let (int, wide) = (self.context.i64_type(), self.context.i128_type());
+        let empty = self
+            .builder
+            .build_int_compare(IntPredicate::SLT, hi, lo, "");
+        self.trap_if(frame, empty);
+
+        let next = self.rng_next();
+        let size = self.builder.build_int_sub(hi, lo, "");
+        let size = self
+            .builder
+            .build_int_add(size, int.const_int(1, false), "");
+        let product = self.builder.build_int_mul(
+            self.builder.build_int_z_extend(next, wide, ""),
+            self.builder.build_int_z_extend(size, wide, ""),
+            "",
+        );
+        let high = self
+            .builder
+            .build_right_shift(product, wide.const_int(64, false), false, "");
+        let high = self.builder.build_int_truncate(high, int, "");
+        let whole = self
+            .builder
+            .build_int_compare(IntPredicate::EQ, size, int.const_zero(), "");
+        let offset = self
+            .builder
+            .build_select(whole, next, high, "")
+            .into_int_value();
+        self.builder.build_int_add(lo, offset, "")
+    }
+
+    /// Define the C `main` function, which saves the arguments, calls the program's `main` and
+    /// exits with its result, if it returns an int.
+    fn entry_point(&mut self) -> Result<(), LlvmError> {
+        let main = self
+            .program
+            .bodies
+            .iter()
+            .find(|body| body.name == "main")
+            .ok_or(LlvmError::NoMain)?;
+        if !main.params.is_empty() {
+            return Err(LlvmError::MainHasParameters);
+        }
+
+        let (c_int, pointer) = (self.context.i32_type(), self.pointer_type());
+        let function = self.module.add_function(
+            "main",
+            c_int.fn_type(&[c_int.into(), pointer.into()], false),
+            None,
+        );
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+
+        // `argv` starts with the program's name, which isn't one of its arguments.
+        let (argc, argv) = (
+            function.get_nth_param(0).expect("`main` takes `argc`"),
+            function.get_nth_param(1).expect("`main` takes `argv`"),
+        );
+        let int = self.context.i64_type();
+        let argc = self
+            .builder
+            .build_int_s_extend(argc.into_int_value(), int, "");
+        let count = self
+            .builder
+            .build_int_sub(argc, int.const_int(1, false), "");
+        let (count_global, args_global) = self.arg_globals();
+        self.builder.build_store(count_global, count);
+        // SAFETY: `argv` always holds at least the program's name.
+        let args = unsafe {
+            self.builder.build_in_bounds_gep(
+                pointer,
+                argv.into_pointer_value(),
+                &[int.const_int(1, false)],
+                "",
+            )
+        };
+        self.builder.build_store(args_global, args);
+
+        // The `rand` generator is seeded from the time stamp counter, like the x86 backend.
+        let cycles = self
+            .call_c("llvm.readcyclecounter", int.fn_type(&[], false), &[])
+            .expect("`llvm.readcyclecounter` returns a count");
+        self.builder.build_store(self.rng_global(), cycles);
+
+        let result = self
+            .builder
+            .build_call(self.functions[main.proc.0 as usize], &[], "")
+            .try_as_basic_value()
+            .left();
+        let status = match result {
+            Some(BasicValueEnum::IntValue(value)) if main.ret_ty == Ty::INT => {
+                self.builder.build_int_truncate(value, c_int, "")
+            }
+            _ => c_int.const_zero(),
+        };
+        self.builder.build_return(Some(&status));
+
+        Ok(())
+    }
+}
+
+/// Translate a program to an LLVM module.
+pub fn translate<'ctx>(
+    context: &'ctx Context,
+    program: &mir::Program,
+) -> Result<Module<'ctx>, LlvmError> {
+    if let Some(body) = program
+        .bodies
+        .iter()
+        .find(|body| body.overflow != Overflow::Checked)
+    {
+        return Err(LlvmError::UnsupportedOverflow(body.overflow));
+    }
+    // Arrays and maps are only ever held in temporaries, so a body without temporaries of
+    // either never touches one.
+    if let Some(body) = program.bodies.iter().find(|body| {
+        body.temps
+            .iter()
+            .chain([&body.ret_ty])
+            .any(|ty| matches!(ty, Ty::Array(_) | Ty::Map(..)))
+    }) {
+        return Err(LlvmError::UnsupportedArrays(body.name.clone()));
+    }
+    if let Some((body, native)) = program.bodies.iter().find_map(|body| {
+        let native = body
+            .natives()
+            .find(|&native| SystemNative::from_id(native).is_none())?;
+        Some((body, native))
+    }) {
+        return Err(LlvmError::NativeCall {
+            proc: body.name.clone(),
+            native: program.native(native).name.clone(),
+        });
+    }
+
+    let module = context.create_module("program");
+    let functions = program
+        .bodies
+        .iter()
+        .map(|body| module.add_function(&body.symbol, function_type(context, body), None))
+        .collect();
+
+    let mut codegen = Codegen {
+        context,
+        module,
+        builder: context.create_builder(),
+        program,
+        functions,
+        strings: HashMap::new(),
+    };
+    for body in &program.bodies {
+        codegen.body(body);
+    }
+    codegen.entry_point()?;
+
+    Ok(codegen.module)
+}
@@ -0,0 +1,56 @@
+use hir::Overflow;
+use miette::Diagnostic;
+use thiserror::Error;
+
+/// Errors reported by LLVM while compiling a program.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum LlvmError {
+    #[diagnostic(code(codegen_llvm::target))]
+    #[error("Cannot target the host: {0}")]
+    Target(String),
+
+    #[diagnostic(
+        code(codegen_llvm::no_main),
+        help("add a `proc main() void` or `proc main() int` at the top level")
+    )]
+    #[error("The program has no `main` procedure")]
+    NoMain,
+
+    #[diagnostic(code(codegen_llvm::main_has_parameters))]
+    #[error("`main` cannot take parameters")]
+    MainHasParameters,
+
+    #[diagnostic(
+        code(codegen_llvm::unsupported_overflow),
+        help("run the program with the interpreter or the VM, or use checked overflow")
+    )]
+    #[error("The LLVM backend doesn't support {} overflow", .0.name())]
+    UnsupportedOverflow(Overflow),
+
+    #[diagnostic(
+        code(codegen_llvm::unsupported_arrays),
+        help("run the program with the interpreter or the VM")
+    )]
+    #[error("The LLVM backend doesn't support arrays or maps, which `{0}` uses")]
+    UnsupportedArrays(String),
+
+    #[diagnostic(
+        code(codegen_llvm::native_call),
+        help("native procedures need the interpreter or the VM to run the program")
+    )]
+    #[error("`{proc}` calls the native procedure `{native}`, which native code can't call")]
+    NativeCall { proc: String, native: String },
+
+    /// The generated module is malformed, which is a bug in the backend.
+    #[diagnostic(code(codegen_llvm::verify))]
+    #[error("LLVM rejected the generated module: {0}")]
+    Verify(String),
+
+    #[diagnostic(code(codegen_llvm::optimize))]
+    #[error("Optimization failed: {0}")]
+    Optimize(String),
+
+    #[diagnostic(code(codegen_llvm::emit))]
+    #[error("Cannot write the object file: {0}")]
+    Emit(String),
+}
@@ -0,0 +1,66 @@
+//! Extended explanations of the LLVM backend's errors, which `mtxc explain` prints.
+
+/// Every error's code and explanation. Short codes number them in this order, so new ones go
+/// at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "codegen_llvm::target",
+        r#"LLVM can't generate code for the machine mtxc runs on.
+
+The LLVM that mtxc was built with doesn't include a backend for this machine's architecture.
+Rebuild mtxc against an LLVM that targets it."#,
+    ),
+    (
+        "codegen_llvm::no_main",
+        r#"The program is compiled to an object file, but has no `main` procedure to start from.
+
+Add a `proc main() void` or `proc main() int` at the top level, not in a module."#,
+    ),
+    (
+        "codegen_llvm::main_has_parameters",
+        r#"The program's `main` procedure takes parameters.
+
+Nothing calls `main` with arguments, so it can't have parameters. Remove them from `main`."#,
+    ),
+    (
+        "codegen_llvm::unsupported_overflow",
+        r#"The program is compiled with an overflow mode the LLVM backend doesn't support.
+
+The backend only compiles checked arithmetic, which is the default. Compile without
+`--overflow`, or run the program with the interpreter or the VM."#,
+    ),
+    (
+        "codegen_llvm::unsupported_arrays",
+        r#"A procedure uses arrays or maps, which the LLVM backend can't compile yet.
+
+Run the program with the interpreter or the VM instead."#,
+    ),
+    (
+        "codegen_llvm::verify",
+        r#"LLVM rejected the module the backend generated.
+
+The generated IR is malformed, which is a bug in the LLVM backend rather than in the program.
+The error includes LLVM's explanation."#,
+    ),
+    (
+        "codegen_llvm::optimize",
+        r#"LLVM failed to run the optimization pipeline on the program.
+
+The error includes LLVM's explanation. Compiling with a lower `-O` level may work around it."#,
+    ),
+    (
+        "codegen_llvm::emit",
+        r#"LLVM couldn't write the object file.
+
+The error includes LLVM's explanation. Check that the output's directory exists, and that it
+can be written to."#,
+    ),
+    (
+        "codegen_llvm::native_call",
+        r#"A procedure calls a native procedure, which the LLVM backend can't compile.
+
+Native procedures are Rust functions that the program embedding matrix registers, and only
+exist while the interpreter or the VM runs the program. Native code has no host to call them
+in. Run the program with the interpreter or the VM instead."#,
+    ),
+];
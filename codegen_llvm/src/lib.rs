@@ -0,0 +1,247 @@
+//! A backend translating MIR to LLVM IR, for optimized native code.
+//!
+//! Everything here is behind the `llvm` feature, since building it needs LLVM 16 installed.
+
+#![cfg(feature = "llvm")]
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod codegen;
+mod diagnostics;
+mod explanations;
+
+pub use diagnostics::LlvmError;
+pub use explanations::EXPLANATIONS;
+
+use inkwell::{
+    context::Context,
+    module::Module,
+    passes::PassBuilderOptions,
+    targets::{CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine},
+    OptimizationLevel,
+};
+use std::path::Path;
+
+/// How hard LLVM optimizes, as selected with `-O`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    O3,
+}
+
+impl OptLevel {
+    /// The optimization level for a `-O` value, which must be between 0 and 3.
+    pub fn from_level(level: u8) -> Option<Self> {
+        match level {
+            0 => Some(Self::O0),
+            1 => Some(Self::O1),
+            2 => Some(Self::O2),
+            3 => Some(Self::O3),
+            _ => None,
+        }
+    }
+
+    /// The new pass manager's pipeline for this level.
+    fn pipeline(self) -> &'static str {
+        match self {
+            Self::O0 => "default<O0>",
+            Self::O1 => "default<O1>",
+            Self::O2 => "default<O2>",
+            Self::O3 => "default<O3>",
+        }
+    }
+
+    fn codegen_level(self) -> OptimizationLevel {
+        match self {
+            Self::O0 => OptimizationLevel::None,
+            Self::O1 => OptimizationLevel::Less,
+            Self::O2 => OptimizationLevel::Default,
+            Self::O3 => OptimizationLevel::Aggressive,
+        }
+    }
+}
+
+/// Create a target machine for the host.
+fn host_machine(opt_level: OptLevel) -> Result<TargetMachine, LlvmError> {
+    Target::initialize_native(&InitializationConfig::default()).map_err(LlvmError::Target)?;
+
+    let triple = TargetMachine::get_default_triple();
+    let target = Target::from_triple(&triple).map_err(|e| LlvmError::Target(e.to_string()))?;
+    target
+        .create_target_machine(
+            &triple,
+            &TargetMachine::get_host_cpu_name().to_string(),
+            &TargetMachine::get_host_cpu_features().to_string(),
+            opt_level.codegen_level(),
+            RelocMode::PIC,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| LlvmError::Target(format!("cannot target `{triple}`")))
+}
+
+/// Translate a program to an optimized LLVM module for the host, and pass it to `f`.
+fn with_module<T>(
+    program: &mir::Program,
+    opt_level: OptLevel,
+    f: impl FnOnce(&Module<'_>, &TargetMachine) -> Result<T, LlvmError>,
+) -> Result<T, LlvmError> {
+    let machine = host_machine(opt_level)?;
+    let context = Context::create();
+    let module = codegen::translate(&context, program)?;
+    module.set_triple(&machine.get_triple());
+    module.set_data_layout(&machine.get_target_data().get_data_layout());
+
+    module
+        .verify()
+        .map_err(|e| LlvmError::Verify(e.to_string()))?;
+    module
+        .run_passes(opt_level.pipeline(), &machine, PassBuilderOptions::create())
+        .map_err(|e| LlvmError::Optimize(e.to_string()))?;
+
+    f(&module, &machine)
+}
+
+/// Compile a program to textual LLVM IR.
+pub fn emit_llvm_ir(program: &mir::Program, opt_level: OptLevel) -> Result<String, LlvmError> {
+    with_module(program, opt_level, |module, _| {
+        Ok(module.print_to_string().to_string())
+    })
+}
+
+/// Compile a program to an object file for the host. The program's `main` becomes the C
+/// `main` function, so linking the object with the system's C compiler produces an executable.
+pub fn emit_object(
+    program: &mir::Program,
+    opt_level: OptLevel,
+    path: &Path,
+) -> Result<(), LlvmError> {
+    with_module(program, opt_level, |module, machine| {
+        machine
+            .write_to_file(module, FileType::Object, path)
+            .map_err(|e| LlvmError::Emit(e.to_string()))
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{fs, process::Command};
+
+    /// Switches on a dense `match`, joins and prints strings, and calls procedures, recursively
+    /// too.
+    const PROGRAM: &str = r#"proc greet(name: str) str { ret "hello, " + name; }
+        proc day(n: int) str {
+            ret match n { 0 | 6 => "weekend", 1 => "monday", 2..=5 => "weekday", _ => "never" };
+        }
+        proc fib(n: int) int {
+            if n < 2 { ret n; }
+            ret fib(n - 1) + fib(n - 2);
+        }
+        proc main() int {
+            println(greet("world"));
+            for let i = -1; i < 8; i += 1 { println(to_str(i) + " " + day(i)); }
+            print(to_str(fib(10)) + "\n");
+            ret fib(10) % 7;
+        }"#;
+
+    const LEVELS: [OptLevel; 4] = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3];
+
+    fn lower_source(source: &str) -> anyhow::Result<hir::Program> {
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        Ok(hir::lower(&ast)?.program)
+    }
+
+    /// Lower a program to MIR, optimized at the level `mtxc -O` would.
+    fn optimized_mir(program: &hir::Program, opt_level: u8) -> mir::Program {
+        let mut program = mir::lower(program);
+        mir::opt::PassManager::preset(opt_level).run(&mut program, |_, _| {});
+        program
+    }
+
+    #[test]
+    fn test_ir_is_verified_at_every_level() -> anyhow::Result<()> {
+        let program = lower_source(PROGRAM)?;
+        for (level, opt_level) in LEVELS.into_iter().enumerate() {
+            // `emit_llvm_ir` fails if LLVM doesn't verify the module.
+            let ir = emit_llvm_ir(&optimized_mir(&program, level as u8), opt_level)?;
+            assert!(ir.contains("define i32 @main("), "-O{level}:\n{ir}");
+            assert!(ir.contains("@puts("), "-O{level}:\n{ir}");
+            if opt_level == OptLevel::O0 {
+                assert!(ir.contains("switch i64"), "{ir}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_executables_match_the_interpreter() -> anyhow::Result<()> {
+        let program = lower_source(PROGRAM)?;
+        let (mut input, mut expected) = (std::io::empty(), Vec::new());
+        let io = interp::Io::new(&mut input, &mut expected);
+        let code = match interp::run_with_io(&program, io, Default::default())? {
+            interp::Value::Int(code, _) => code as i32,
+            value => anyhow::bail!("`main` returned a {}", value.type_name()),
+        };
+
+        let dir = std::env::temp_dir().join(format!("matrix-llvm-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        for (level, opt_level) in LEVELS.into_iter().enumerate() {
+            let object_path = dir.join(format!("program-O{level}.o"));
+            let exe_path = dir.join(format!("program-O{level}"));
+            emit_object(
+                &optimized_mir(&program, level as u8),
+                opt_level,
+                &object_path,
+            )?;
+            let status = Command::new("cc")
+                .arg(&object_path)
+                .arg("-o")
+                .arg(&exe_path)
+                .status()?;
+            anyhow::ensure!(
+                status.success(),
+                "cc failed to link the program at -O{level}"
+            );
+
+            let output = Command::new(&exe_path).output()?;
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&expected),
+                "-O{level}"
+            );
+            assert_eq!(output.status.code(), Some(code), "-O{level}");
+        }
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_programs_are_rejected() -> anyhow::Result<()> {
+        let program = mir::lower(&lower_source(
+            "proc main() int { let xs = [1, 2]; ret xs[0]; }",
+        )?);
+        assert!(matches!(
+            emit_llvm_ir(&program, OptLevel::O0),
+            Err(LlvmError::UnsupportedArrays(proc)) if proc == "main"
+        ));
+
+        let source = "proc main() int { ret 1; }";
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let options = hir::LowerOptions {
+            overflow: hir::Overflow::Wrapping,
+            ..Default::default()
+        };
+        let program = mir::lower(&hir::lower_with(&ast, &options)?.program);
+        assert!(matches!(
+            emit_llvm_ir(&program, OptLevel::O2),
+            Err(LlvmError::UnsupportedOverflow(hir::Overflow::Wrapping))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use hir::{Overflow, Ty};
+use miette::Diagnostic;
+use thiserror::Error;
+
+/// Errors that stop a program from being compiled to assembly.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum AsmError {
+    #[diagnostic(
+        code(codegen_x86::no_main),
+        help("add a `proc main() void` or `proc main() int` at the top level")
+    )]
+    #[error("The program has no `main` procedure")]
+    NoMain,
+
+    #[diagnostic(code(codegen_x86::main_has_parameters))]
+    #[error("`main` cannot take parameters")]
+    MainHasParameters,
+
+    #[diagnostic(
+        code(codegen_x86::unsupported),
+        help("the x86-64 backend only supports ints and `bool` values so far")
+    )]
+    #[error("`{proc}` uses `{ty}` values, which the x86-64 backend doesn't support")]
+    Unsupported { proc: String, ty: Ty },
+
+    #[diagnostic(
+        code(codegen_x86::unsupported_overflow),
+        help("run the program with the interpreter or the VM, or use another overflow mode")
+    )]
+    #[error("The x86-64 backend doesn't support {} overflow", .0.name())]
+    UnsupportedOverflow(Overflow),
+
+    #[diagnostic(
+        code(codegen_x86::native_call),
+        help("native procedures need the interpreter or the VM to run the program")
+    )]
+    #[error("`{proc}` calls the native procedure `{native}`, which executables can't call")]
+    NativeCall { proc: String, native: String },
+}
+
+/// Errors that stop a program from being built into an executable.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum BuildError {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Asm(#[from] AsmError),
+
+    #[diagnostic(code(codegen_x86::io))]
+    #[error("Cannot write `{0}`: {1}")]
+    Io(String, String),
+
+    #[diagnostic(
+        code(codegen_x86::no_cc),
+        help("install a C toolchain, like gcc or clang, which provides `cc`")
+    )]
+    #[error("Cannot run `cc`: {0}")]
+    NoCc(String),
+
+    #[diagnostic(code(codegen_x86::cc_failed))]
+    #[error("`cc` failed to {0} the program:\n{1}")]
+    CcFailed(&'static str, String),
+}
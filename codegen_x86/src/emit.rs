@@ -0,0 +1,575 @@
+//! Translation of MIR bodies to x86-64 assembly.
+//!
+//! Every temporary lives in its own 8 byte stack slot below the frame pointer, and instructions
+//! work in `%rax` and `%rcx`, loading their operands and storing their result. It's slow, but
+//! each MIR instruction maps onto a few lines of assembly that are easy to follow.
+//!
+//! Phis become copies on the edges into their block. The copies go through the stack, so phis
+//! reading each other's old values still see them.
+//!
+//! Ints of every width are kept as [`IntTy::wrap`] stores them: sign extended to 64 bits if
+//! their type is signed and zero extended if it isn't. Arithmetic on a narrower type works on
+//! all 64 bits, then truncates the result and extends it again, trapping if that changed it and
+//! overflow is checked.
+
+use crate::{diagnostics::AsmError, runtime};
+use hir::{BinOp, Builtin, IntTy, Literal, Overflow, SystemNative, Ty, UnOp};
+use mir::{BlockId, Body, Inst, InstKind, Operand, Temp, Terminator};
+use std::fmt::{Display, Write};
+
+/// The registers the System V ABI passes the first integer arguments in.
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+/// The runtime function every failed runtime check jumps to, which aborts the program.
+const TRAP: &str = "matrix_trap";
+
+fn is_supported(ty: Ty) -> bool {
+    matches!(ty, Ty::Int(_) | Ty::Bool | Ty::Void)
+}
+
+fn operand_ty(body: &Body, operand: &Operand) -> Ty {
+    match operand {
+        Operand::Temp(temp) => body.temp_ty(*temp),
+        Operand::Const(literal) => literal.ty(),
+    }
+}
+
+fn int_ty(ty: Ty) -> IntTy {
+    match ty {
+        Ty::Int(ty) => ty,
+        ty => unreachable!("arithmetic is type checked, found a `{ty}`"),
+    }
+}
+
+/// Check that a body only uses values the backend supports.
+fn check_types(body: &Body) -> Result<(), AsmError> {
+    let operands = body
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .flat_map(|inst| match &inst.kind {
+            InstKind::Unary { operand, .. } => vec![operand],
+            InstKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            // Executables trap without printing messages, so they're never used.
+            InstKind::Builtin {
+                builtin: Builtin::Assert,
+                args,
+            } => vec![&args[0]],
+            InstKind::Builtin {
+                builtin: Builtin::Panic,
+                ..
+            } => vec![],
+            InstKind::Call { args, .. }
+            | InstKind::Builtin { args, .. }
+            | InstKind::Native { args, .. }
+            | InstKind::Array { elements: args }
+            | InstKind::Map { entries: args } => args.iter().collect(),
+            InstKind::Index { array, index } => vec![array, index],
+            InstKind::Store {
+                array,
+                index,
+                value,
+            } => vec![array, index, value],
+        });
+    let literal_tys = operands.filter_map(|operand| match operand {
+        Operand::Const(literal) => Some(literal.ty()),
+        Operand::Temp(_) => None,
+    });
+
+    let mut tys = body
+        .temps
+        .iter()
+        .copied()
+        .chain([body.ret_ty])
+        .chain(literal_tys);
+    tys.find(|&ty| !is_supported(ty)).map_or(Ok(()), |ty| {
+        Err(AsmError::Unsupported {
+            proc: body.name.clone(),
+            ty,
+        })
+    })
+}
+
+/// The condition code `setcc` uses for a comparison.
+fn condition(op: BinOp, signed: bool) -> &'static str {
+    match (op, signed) {
+        (BinOp::Eq, _) => "e",
+        (BinOp::Ne, _) => "ne",
+        (BinOp::Lt, true) => "l",
+        (BinOp::Le, true) => "le",
+        (BinOp::Gt, true) => "g",
+        (BinOp::Ge, true) => "ge",
+        (BinOp::Lt, false) => "b",
+        (BinOp::Le, false) => "be",
+        (BinOp::Gt, false) => "a",
+        (BinOp::Ge, false) => "ae",
+        _ => unreachable!("`{op:?}` isn't a comparison"),
+    }
+}
+
+fn slot(temp: Temp) -> String {
+    format!("-{}(%rbp)", 8 * (temp.0 + 1))
+}
+
+struct Emitter<'p> {
+    program: &'p mir::Program,
+    asm: String,
+
+    /// Indexes [`mir::Program::bodies`], naming the function's labels.
+    function: usize,
+}
+
+impl<'p> Emitter<'p> {
+    fn line(&mut self, line: impl Display) {
+        writeln!(self.asm, "    {line}").unwrap();
+    }
+
+    fn label(&mut self, label: impl Display) {
+        writeln!(self.asm, "{label}:").unwrap();
+    }
+
+    fn block_label(&self, block: BlockId) -> String {
+        format!(".LF{}B{}", self.function, block.0)
+    }
+
+    fn load(&mut self, operand: &Operand, register: &str) {
+        match operand {
+            Operand::Temp(temp) => self.line(format_args!("movq {}, {register}", slot(*temp))),
+            Operand::Const(Literal::Int(value, _)) => {
+                self.line(format_args!("movabsq ${value}, {register}"));
+            }
+            Operand::Const(Literal::Bool(value)) => {
+                self.line(format_args!("movq ${}, {register}", u8::from(*value)));
+            }
+            Operand::Const(literal) => {
+                unreachable!("unsupported literals are rejected: {literal:?}")
+            }
+        }
+    }
+
+    fn store(&mut self, register: &str, temp: Temp) {
+        self.line(format_args!("movq {register}, {}", slot(temp)));
+    }
+
+    /// Sign or zero extend the low bits of `%rax` that an int type narrower than 64 bits uses
+    /// to the whole register, truncating it to the type.
+    fn extend(&mut self, ty: IntTy) {
+        let line = match ty {
+            IntTy::I8 => "movsbq %al, %rax",
+            IntTy::I16 => "movswq %ax, %rax",
+            IntTy::I32 => "movslq %eax, %rax",
+            IntTy::U8 => "movzbq %al, %rax",
+            IntTy::U16 => "movzwq %ax, %rax",
+            // Writing a 32 bit register clears the upper half of its 64 bit one.
+            IntTy::U32 => "movl %eax, %eax",
+            IntTy::I64 | IntTy::U64 => return,
+        };
+        self.line(line);
+    }
+
+    /// Truncate `%rax` to an int type, trapping if that changes its value and overflow is
+    /// checked. Ints of 64 bits check the flags of the instruction that made them instead.
+    fn fit(&mut self, ty: IntTy, overflow: Overflow) {
+        if ty.bits() == 64 {
+            return;
+        }
+        if overflow == Overflow::Checked {
+            self.line("movq %rax, %r11");
+            self.extend(ty);
+            self.line("cmpq %rax, %r11");
+            self.line(format_args!("jne {TRAP}"));
+        } else {
+            self.extend(ty);
+        }
+    }
+
+    /// Apply a unary operator to `%rax`, an operand of type `ty`.
+    fn unary(&mut self, op: UnOp, ty: Ty, overflow: Overflow) {
+        match op {
+            UnOp::Neg => {
+                let ty = int_ty(ty);
+                self.line("negq %rax");
+                if ty == IntTy::I64 && overflow == Overflow::Checked {
+                    self.line(format_args!("jo {TRAP}"));
+                }
+                self.fit(ty, overflow);
+            }
+            // Bools are 0 or 1, so only the lowest bit flips.
+            UnOp::Not => self.line("xorq $1, %rax"),
+            UnOp::BitNot => {
+                self.line("notq %rax");
+                self.extend(int_ty(ty));
+            }
+            UnOp::Cast(to) => {
+                // The 64 bit types are the only ones whose ints can have the top bit set for
+                // one and not fit in the other.
+                let from = int_ty(ty);
+                let reinterprets = (from == IntTy::U64 && to.is_signed())
+                    || (to == IntTy::U64 && from.is_signed());
+                if reinterprets && overflow == Overflow::Checked {
+                    self.line("testq %rax, %rax");
+                    self.line(format_args!("js {TRAP}"));
+                }
+                self.fit(to, overflow);
+            }
+        }
+    }
+
+    /// Apply a binary operator to `%rax` and `%rcx`, operands of type `ty`, leaving the result
+    /// in `%rax`.
+    fn binary(&mut self, op: BinOp, ty: Ty, overflow: Overflow) {
+        match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                let ty = int_ty(ty);
+                match op {
+                    BinOp::Add => self.line("addq %rcx, %rax"),
+                    BinOp::Sub => self.line("subq %rcx, %rax"),
+                    _ if ty == IntTy::U64 => self.line("mulq %rcx"),
+                    _ => self.line("imulq %rcx, %rax"),
+                }
+                // Narrower types can't overflow 64 bits, even `u32`s multiplied, whose product
+                // is right in the low 64 bits if not as a signed int.
+                match ty {
+                    _ if overflow != Overflow::Checked => {}
+                    IntTy::I64 => self.line(format_args!("jo {TRAP}")),
+                    IntTy::U64 => self.line(format_args!("jc {TRAP}")),
+                    _ => {}
+                }
+                self.fit(ty, overflow);
+            }
+            BinOp::Div | BinOp::Rem => {
+                let ty = int_ty(ty);
+                self.line("testq %rcx, %rcx");
+                self.line(format_args!("jz {TRAP}"));
+                // `int::MIN / -1` makes `idivq` fault, which aborts the program if overflow is
+                // checked. Otherwise the quotient wraps around to `int::MIN`, and the
+                // remainder is 0.
+                let wraps = ty == IntTy::I64 && overflow != Overflow::Checked;
+                if wraps {
+                    self.line("cmpq $-1, %rcx");
+                    self.line("jne 1f");
+                    if op == BinOp::Div {
+                        self.line("negq %rax");
+                    } else {
+                        self.line("xorl %eax, %eax");
+                    }
+                    self.line("jmp 2f");
+                    self.label("1");
+                }
+                if ty.is_signed() {
+                    self.line("cqto");
+                    self.line("idivq %rcx");
+                    // Only the minimum of a narrower type divided by -1 doesn't fit, and its
+                    // remainder overflows too where overflow is checked.
+                    self.fit(ty, overflow);
+                } else {
+                    self.line("xorl %edx, %edx");
+                    self.line("divq %rcx");
+                }
+                if op == BinOp::Rem {
+                    self.line("movq %rdx, %rax");
+                }
+                if wraps {
+                    self.label("2");
+                }
+            }
+            BinOp::BitAnd => self.line("andq %rcx, %rax"),
+            BinOp::BitOr => self.line("orq %rcx, %rax"),
+            BinOp::Shl | BinOp::Shr => {
+                let ty = int_ty(ty);
+                // Negative amounts are huge when unsigned, so this rejects them too.
+                self.line(format_args!("cmpq ${}, %rcx", ty.bits() - 1));
+                self.line(format_args!("ja {TRAP}"));
+                match op {
+                    BinOp::Shl => self.line("salq %cl, %rax"),
+                    _ if ty.is_signed() => self.line("sarq %cl, %rax"),
+                    _ => self.line("shrq %cl, %rax"),
+                }
+                // Left shifts wrap around whatever the overflow mode.
+                self.extend(ty);
+            }
+            _ => {
+                let signed = matches!(ty, Ty::Int(ty) if ty.is_signed());
+                self.line("cmpq %rcx, %rax");
+                self.line(format_args!("set{} %al", condition(op, signed)));
+                self.line("movzbq %al, %rax");
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &Body, args: &[Operand]) {
+        let stack_args = args.len().saturating_sub(ARG_REGISTERS.len());
+        // The stack must stay 16 byte aligned at calls.
+        let padding = stack_args % 2;
+        if padding == 1 {
+            self.line("subq $8, %rsp");
+        }
+        for arg in args[ARG_REGISTERS.len().min(args.len())..].iter().rev() {
+            self.load(arg, "%rax");
+            self.line("pushq %rax");
+        }
+        for (arg, register) in args.iter().zip(ARG_REGISTERS) {
+            self.load(arg, register);
+        }
+
+        self.line(format_args!("call {}", callee.symbol));
+        if stack_args + padding > 0 {
+            self.line(format_args!("addq ${}, %rsp", 8 * (stack_args + padding)));
+        }
+    }
+
+    fn inst(&mut self, body: &Body, inst: &Inst) {
+        match &inst.kind {
+            InstKind::Unary { op, operand } => {
+                self.load(operand, "%rax");
+                self.unary(*op, operand_ty(body, operand), body.overflow);
+            }
+            InstKind::Binary { lhs, op, rhs } => {
+                self.load(lhs, "%rax");
+                self.load(rhs, "%rcx");
+                self.binary(*op, operand_ty(body, lhs), body.overflow);
+            }
+            InstKind::Call { callee, args } => {
+                let callee = self.program.body(*callee);
+                self.call(callee, args);
+            }
+            InstKind::Builtin {
+                builtin: Builtin::ArgCount,
+                ..
+            } => self.line("call matrix_arg_count"),
+            InstKind::Builtin {
+                builtin: Builtin::Seed,
+                args,
+            } => {
+                self.load(&args[0], "%rdi");
+                self.line("call matrix_rand_seed");
+            }
+            InstKind::Builtin {
+                builtin: Builtin::RandInt,
+                args,
+            } => {
+                self.load(&args[0], "%rdi");
+                self.load(&args[1], "%rsi");
+                self.line("call matrix_rand_int");
+            }
+            InstKind::Builtin {
+                builtin: Builtin::Assert,
+                args,
+            } => {
+                self.load(&args[0], "%rax");
+                self.line("testq %rax, %rax");
+                self.line(format_args!("jz {TRAP}"));
+            }
+            InstKind::Builtin {
+                builtin: Builtin::Panic,
+                ..
+            } => self.line(format_args!("jmp {TRAP}")),
+            // Every other builtin takes or returns a char, a string, a float, an array or a map.
+            InstKind::Builtin { .. } => {
+                unreachable!("`check_types` rejects strings, floats, arrays and maps")
+            }
+            InstKind::Native { native, args } => match SystemNative::from_id(*native) {
+                Some(SystemNative::ClockMs) => self.line("call matrix_clock_ms"),
+                Some(SystemNative::SleepMs) => {
+                    self.load(&args[0], "%rdi");
+                    self.line("call matrix_sleep_ms");
+                }
+                Some(SystemNative::Env) => unreachable!("`check_types` rejects strings"),
+                None => unreachable!("`emit_program` rejects calls to natives"),
+            },
+            // Arrays and maps are only ever held in temporaries, which `check_types` rejects.
+            InstKind::Array { .. }
+            | InstKind::Map { .. }
+            | InstKind::Index { .. }
+            | InstKind::Store { .. } => {
+                unreachable!("`check_types` rejects arrays and maps")
+            }
+        }
+
+        if let Some(dest) = inst.dest {
+            self.store("%rax", dest);
+        }
+    }
+
+    /// Copy the incoming values of `target`'s phis for the edge from `from`, then jump there.
+    fn jump(&mut self, body: &Body, from: BlockId, target: BlockId) {
+        let phis = &body.block(target).phis;
+        for phi in phis {
+            let (_, value) = phi
+                .incoming
+                .iter()
+                .find(|(pred, _)| *pred == from)
+                .expect("phis have a value for every predecessor");
+            self.load(value, "%rax");
+            self.line("pushq %rax");
+        }
+        for phi in phis.iter().rev() {
+            self.line("popq %rax");
+            self.store("%rax", phi.dest);
+        }
+
+        let label = self.block_label(target);
+        self.line(format_args!("jmp {label}"));
+    }
+
+    fn terminator(&mut self, body: &Body, from: BlockId, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => self.jump(body, from, *target),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let otherwise_edge = format!("{}_else", self.block_label(from));
+                self.load(cond, "%rax");
+                self.line("testq %rax, %rax");
+                self.line(format_args!("jz {otherwise_edge}"));
+                self.jump(body, from, *then);
+                self.label(otherwise_edge);
+                self.jump(body, from, *otherwise);
+            }
+            Terminator::Switch {
+                value,
+                low,
+                targets,
+                otherwise,
+            } => {
+                // The table holds the offset of each int's edge from the table itself, so it
+                // doesn't need relocating. Ints below `low` wrap around to huge offsets, so one
+                // unsigned comparison checks both ends.
+                let from_label = self.block_label(from);
+                let edge = |target: &BlockId| format!("{from_label}_to{}", target.0);
+                let table = format!("{from_label}_table");
+                self.load(value, "%rax");
+                self.line(format_args!("movabsq ${low}, %rcx"));
+                self.line("subq %rcx, %rax");
+                self.line(format_args!("cmpq ${}, %rax", targets.len()));
+                self.line(format_args!("jae {}", edge(otherwise)));
+                self.line(format_args!("leaq {table}(%rip), %rcx"));
+                self.line("movslq (%rcx,%rax,4), %rax");
+                self.line("addq %rcx, %rax");
+                self.line("jmp *%rax");
+
+                self.line(".p2align 2");
+                self.label(&table);
+                for target in targets {
+                    self.line(format_args!(".long {} - {table}", edge(target)));
+                }
+                for target in terminator.successors() {
+                    self.label(edge(&target));
+                    self.jump(body, from, target);
+                }
+            }
+            Terminator::Ret(value) => {
+                if let Some(value) = value {
+                    self.load(value, "%rax");
+                }
+                self.line("leave");
+                self.line("ret");
+            }
+            Terminator::Unreachable => self.line("ud2"),
+        }
+    }
+
+    fn body(&mut self, body: &Body) {
+        // The frame holds a slot for every temporary, rounded up to keep the stack aligned.
+        let frame_size = (8 * body.temps.len()).div_ceil(16) * 16;
+
+        writeln!(self.asm, "\n    .globl {}", body.symbol).unwrap();
+        self.label(&body.symbol);
+        self.line("pushq %rbp");
+        self.line("movq %rsp, %rbp");
+        if frame_size > 0 {
+            self.line(format_args!("subq ${frame_size}, %rsp"));
+        }
+
+        for (i, &param) in body.params.iter().enumerate() {
+            match ARG_REGISTERS.get(i) {
+                Some(register) => self.store(register, param),
+                None => {
+                    // Above the saved frame pointer and the return address.
+                    let offset = 16 + 8 * (i - ARG_REGISTERS.len());
+                    self.line(format_args!("movq {offset}(%rbp), %rax"));
+                    self.store("%rax", param);
+                }
+            }
+        }
+
+        for (i, block) in body.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            let label = self.block_label(id);
+            self.label(label);
+            for inst in &block.insts {
+                self.inst(body, inst);
+            }
+            self.terminator(body, id, &block.terminator);
+        }
+    }
+
+    /// Define `matrix_main`, which the runtime's C `main` function calls. It calls the
+    /// program's `main` and returns the exit code, its result if it returns an int.
+    fn entry_point(&mut self, main: &Body) {
+        self.asm.push_str("\n    .globl matrix_main\n");
+        self.label("matrix_main");
+        self.line("pushq %rbp");
+        self.line("movq %rsp, %rbp");
+        self.line(format_args!("call {}", main.symbol));
+        if !matches!(main.ret_ty, Ty::Int(_)) {
+            self.line("xorl %eax, %eax");
+        }
+        self.line("popq %rbp");
+        self.line("ret");
+    }
+}
+
+/// Compile a program to x86-64 assembly in AT&T syntax for the System V ABI, followed by the
+/// runtime it calls, so it can be assembled and linked on its own.
+pub fn emit(program: &mir::Program) -> Result<String, AsmError> {
+    Ok(emit_program(program)? + runtime::RUNTIME)
+}
+
+/// Compile a program to assembly like [`emit`], without the runtime, which is linked in from
+/// its own object file.
+pub fn emit_program(program: &mir::Program) -> Result<String, AsmError> {
+    let main = program
+        .bodies
+        .iter()
+        .find(|body| body.name == "main")
+        .ok_or(AsmError::NoMain)?;
+    if !main.params.is_empty() {
+        return Err(AsmError::MainHasParameters);
+    }
+    for body in &program.bodies {
+        if body.overflow == Overflow::Saturating {
+            return Err(AsmError::UnsupportedOverflow(body.overflow));
+        }
+        if let Some(native) = body
+            .natives()
+            .find(|&native| SystemNative::from_id(native).is_none())
+        {
+            return Err(AsmError::NativeCall {
+                proc: body.name.clone(),
+                native: program.native(native).name.clone(),
+            });
+        }
+        check_types(body)?;
+    }
+
+    let mut emitter = Emitter {
+        program,
+        asm: String::from("    .text\n"),
+        function: 0,
+    };
+    for (i, body) in program.bodies.iter().enumerate() {
+        emitter.function = i;
+        emitter.body(body);
+    }
+    emitter.entry_point(main);
+
+    // Mark the stack as non-executable, which linkers warn about otherwise.
+    emitter
+        .asm
+        .push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+
+    Ok(emitter.asm)
+}
@@ -0,0 +1,89 @@
+//! Extended explanations of the x86-64 backend's errors, which `mtxc explain` prints.
+
+/// Every error's code and explanation. Short codes number them in this order, so new ones go
+/// at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "codegen_x86::no_main",
+        r#"The program is built into an executable, but has no `main` procedure to start from.
+
+Add a `proc main() void` or `proc main() int` at the top level, not in a module."#,
+    ),
+    (
+        "codegen_x86::main_has_parameters",
+        r#"The program's `main` procedure takes parameters.
+
+Nothing calls `main` with arguments, so it can't have parameters. Remove them from `main`."#,
+    ),
+    (
+        "codegen_x86::unsupported",
+        r#"A procedure uses values of a type the x86-64 backend can't compile yet.
+
+The backend only supports ints of every width and `bool` values so far, so a program using
+strings, floats or arrays can't be built into an executable:
+
+    proc main() void {
+        println("hello");
+    }
+
+Run the program with the interpreter or the VM instead, like `mtxc run` or `mtxc build`
+without `--native`."#,
+    ),
+    (
+        "codegen_x86::unsupported_overflow",
+        r#"The program is compiled with an overflow mode the x86-64 backend doesn't support.
+
+The backend compiles checked arithmetic, which is the default, and wrapping arithmetic, but
+not saturating arithmetic. Build with another `--overflow`, or run the program with the
+interpreter or the VM."#,
+    ),
+    (
+        "codegen_x86::io",
+        r#"A file couldn't be written while building an executable, like the assembly or the
+executable itself.
+
+The error includes the reason from the operating system. Check that the output's directory
+exists, and that it can be written to."#,
+    ),
+    (
+        "codegen_x86::no_cc",
+        r#"`cc` couldn't be run to assemble and link the executable.
+
+Building an executable needs a C toolchain, like gcc or clang, which provides `cc`. Install
+one, and make sure `cc` is on the `PATH`."#,
+    ),
+    (
+        "codegen_x86::cc_failed",
+        r#"`cc` ran but failed to assemble or link the program.
+
+Its output is included in the error. This is usually a problem with the toolchain, like a
+missing linker, but can be a bug in the backend if the assembly is invalid."#,
+    ),
+    (
+        "codegen_x86::native_call",
+        r#"A procedure calls a native procedure, which the x86-64 backend can't compile.
+
+Native procedures are Rust functions that the program embedding matrix registers, and only
+exist while the interpreter or the VM runs the program. An executable has no host to call
+them in. Run the program with the interpreter or the VM instead."#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_error_is_explained() {
+        let mut codes: Vec<_> = include_str!("diagnostics.rs")
+            .split("code(")
+            .skip(1)
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .filter(|code| !code.is_empty())
+            .collect();
+        let mut explained: Vec<_> = EXPLANATIONS.iter().map(|(code, _)| *code).collect();
+        codes.sort_unstable();
+        explained.sort_unstable();
+        assert_eq!(codes, explained);
+    }
+}
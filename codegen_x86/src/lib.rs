@@ -0,0 +1,333 @@
+//! A backend compiling MIR straight to x86-64 assembly, which `cc` assembles and links into an
+//! executable. It needs no dependencies, and the output is meant to be read: see [`emit`].
+//! [`build_executable`] drives `cc` to build an executable, linked with a small runtime.
+
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod diagnostics;
+mod emit;
+mod explanations;
+mod link;
+mod runtime;
+
+pub use diagnostics::{AsmError, BuildError};
+pub use emit::emit;
+pub use explanations::EXPLANATIONS;
+pub use link::{build_executable, build_executable_with, BuildOptions};
+pub use runtime::RUNTIME;
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{fs, process::Command};
+
+    fn lower_source(source: &str) -> anyhow::Result<mir::Program> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        Ok(mir::lower(&hir::lower(&ast)?.program))
+    }
+
+    /// Assemble and link a program with `cc`, run it with arguments and return its exit code,
+    /// or `None` if it was killed by a signal.
+    fn run_native(name: &str, source: &str, args: &[&str]) -> anyhow::Result<Option<i32>> {
+        run_program(name, &lower_source(source)?, args)
+    }
+
+    fn run_program(
+        name: &str,
+        program: &mir::Program,
+        args: &[&str],
+    ) -> anyhow::Result<Option<i32>> {
+        let dir = std::env::temp_dir().join(format!("matrix-x86-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let asm_path = dir.join("program.s");
+        let exe_path = dir.join("program");
+        fs::write(&asm_path, emit(program)?)?;
+
+        let status = Command::new("cc")
+            .arg(&asm_path)
+            .arg("-o")
+            .arg(&exe_path)
+            .status()?;
+        anyhow::ensure!(status.success(), "cc failed to assemble the program");
+
+        let code = Command::new(&exe_path).args(args).status()?.code();
+        fs::remove_dir_all(&dir)?;
+        Ok(code)
+    }
+
+    /// Check that a program exits with the low byte of the int its `main` returns in the
+    /// interpreter, or is killed where the interpreter fails, at `-O0` and `-O2`.
+    fn assert_matches_interpreter(
+        name: &str,
+        source: &str,
+        overflow: hir::Overflow,
+    ) -> anyhow::Result<()> {
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let options = hir::LowerOptions {
+            overflow,
+            ..Default::default()
+        };
+        let program = hir::lower_with(&ast, &options)?.program;
+        let (mut input, mut output) = (std::io::empty(), Vec::new());
+        let io = interp::Io::new(&mut input, &mut output);
+        let expected = match interp::run_with_io(&program, io, Default::default()) {
+            Ok(interp::Value::Int(value, _)) => Some(i32::from(value as u8)),
+            Ok(value) => anyhow::bail!("`{name}` returned a {}", value.type_name()),
+            Err(_) => None,
+        };
+
+        for opt_level in [0, 2] {
+            let mut mir = mir::lower(&program);
+            mir::opt::PassManager::preset(opt_level).run(&mut mir, |_, _| {});
+            let name = format!("{name}-{}-O{opt_level}", overflow.name());
+            assert_eq!(run_program(&name, &mir, &[])?, expected, "{name}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sized_ints_match_the_interpreter() -> anyhow::Result<()> {
+        // Each operation is in its own procedure, so optimizing `main` can't fold it.
+        let programs = [
+            (
+                "i8-add",
+                "proc add(a: i8, b: i8) i8 { ret a + b; }
+                proc main() int { ret add(100, 27) as int + add(100, 28) as int; }",
+            ),
+            (
+                "u8-sub",
+                "proc sub(a: u8, b: u8) u8 { ret a - b; }
+                proc main() int { ret sub(3, 5) as int; }",
+            ),
+            (
+                "i16-mul",
+                "proc mul(a: i16, b: i16) i16 { ret a * b; }
+                proc main() int { ret mul(-3, 7) as int + mul(300, 300) as int; }",
+            ),
+            (
+                "u32-mul",
+                "proc mul(a: u32, b: u32) u32 { ret a * b; }
+                proc main() int { ret mul(65537, 65537) as int; }",
+            ),
+            (
+                "u64",
+                "proc add(a: u64, b: u64) u64 { ret a + b; }
+                proc mul(a: u64, b: u64) u64 { ret a * b; }
+                proc div(a: u64, b: u64) u64 { ret a / b; }
+                proc main() int {
+                    let big: u64 = 18446744073709551615;
+                    if big < 2 { ret 1; }
+                    let quotient = (div(big, 16) % 256) as int;
+                    ret quotient + (mul(4294967296, 3) >> 32) as int + add(big, 2) as int;
+                }",
+            ),
+            (
+                "i32-neg",
+                "proc neg(a: i32) i32 { ret -a; }
+                proc main() int { ret neg(-2147483648) as int / 16777216 + 200; }",
+            ),
+            (
+                "i8-div",
+                "proc div(a: i8, b: i8) i8 { ret a / b; }
+                proc rem(a: i8, b: i8) i8 { ret a % b; }
+                proc main() int {
+                    ret div(-7, 2) as int + rem(-7, 2) as int + div(-128, -1) as int;
+                }",
+            ),
+            (
+                "i8-rem",
+                "proc rem(a: i8, b: i8) i8 { ret a % b; }
+                proc main() int { ret rem(-128, -1) as int + 1; }",
+            ),
+            (
+                "int-div",
+                "proc div(a: int, b: int) int { ret a / b; }
+                proc rem(a: int, b: int) int { ret a % b; }
+                proc main() int {
+                    let min = -9223372036854775808;
+                    if div(min, -1) == min && rem(min, -1) == 0 { ret 3; }
+                    ret 4;
+                }",
+            ),
+            (
+                "narrow-cast",
+                "proc narrow(a: int) u8 { ret a as u8; }
+                proc main() int { ret narrow(255) as int + narrow(300) as int; }",
+            ),
+            (
+                "signed-cast",
+                "proc signed(a: u64) i64 { ret a as i64; }
+                proc unsigned(a: i8) u64 { ret a as u64; }
+                proc main() int {
+                    ret signed(18446744073709551615) + 2 + (unsigned(-1) % 1000) as int;
+                }",
+            ),
+            (
+                "bits",
+                "proc shl(a: u8, n: u8) u8 { ret a << n; }
+                proc shr(a: i16, n: i16) i16 { ret a >> n; }
+                proc shru(a: u16, n: u16) u16 { ret a >> n; }
+                proc flip(a: u8) u8 { ret ~a; }
+                proc below(a: u32, b: u32) bool { ret a < b; }
+                proc main() int {
+                    if !below(4000000000, 4000000001) { ret 1; }
+                    ret shl(200, 1) as int + shr(-64, 3) as int + shru(65535, 12) as int
+                        + flip(5) as int;
+                }",
+            ),
+            (
+                "shift",
+                "proc shl(a: u8, n: u8) u8 { ret a << n; }
+                proc main() int { ret shl(1, 8) as int; }",
+            ),
+            (
+                "folded",
+                "proc main() int {
+                    let byte: u8 = 250;
+                    ret (byte / 3) as int + (byte + 10) as int;
+                }",
+            ),
+        ];
+        for (name, source) in programs {
+            for overflow in [hir::Overflow::Checked, hir::Overflow::Wrapping] {
+                assert_matches_interpreter(name, source, overflow)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_native_programs_run() -> anyhow::Result<()> {
+        let source = "proc fib(n: int) int {
+                if n < 2 { ret n; }
+                ret fib(n - 1) + fib(n - 2);
+            }
+            proc weigh(a: int, b: int, c: int, d: int, e: int, f: int, g: int, h: int) int {
+                ret a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f + 7 * g + 8 * h;
+            }
+            proc is_even(n: int) bool { ret n % 2 == 0; }
+            proc main() int {
+                let total = 0;
+                for let i = 0; i < 10; i += 1 {
+                    if is_even(i) { total += fib(i); } elif !(i != 9) { total -= 1; }
+                }
+                let kind = match total { 0..=9 => 1, 10..=99 => 2, _ => 3 };
+                ret total + kind + weigh(1, 1, 1, 1, 1, 1, 1, 1) - 4 / 2 * 2;
+            }";
+
+        // fib(0) + fib(2) + fib(4) + fib(6) + fib(8) - 1 = 32, plus 2, 36 and -4.
+        assert_eq!(run_native("fib", source, &[])?, Some(66));
+
+        // Dense matches jump through a table, which the ends of `int` mustn't wrap into.
+        let source = "proc day(n: int) int {
+                ret match n { 0 | 6 => 0, 1 => 10, 2 => 20, 3 => 30, 4 | 5 => 40, _ => 1 };
+            }
+            proc main() int {
+                let total = 0;
+                for let i = -2; i < 9; i += 1 { total += day(i); }
+                ret total + day(-9223372036854775807 - 1) + day(9223372036854775807);
+            }";
+        assert_eq!(run_native("switch", source, &[])?, Some(146));
+
+        let source = "proc main() int { let zero = 0; ret 1 / zero; }";
+        assert_eq!(run_native("trap", source, &[])?, None);
+
+        let source = "proc main() int { ret arg_count(); }";
+        assert_eq!(run_native("args", source, &["a", "b", "c"])?, Some(3));
+
+        let source = r#"proc main() int {
+                let start = clock_ms();
+                sleep_ms(20);
+                sleep_ms(-1);
+                if clock_ms() - start > 19 { ret 7; }
+                ret 0;
+            }"#;
+        assert_eq!(run_native("clock", source, &[])?, Some(7));
+
+        // A seed gives the numbers it does in the interpreter.
+        let source = "import rand; proc main() int { rand::seed(3); ret rand::rand_int(1, 100); }";
+        assert_eq!(run_native("rand", source, &[])?, Some(12));
+
+        let source = r#"proc main() int { assert(true, "true"); panic("stop"); }"#;
+        assert_eq!(run_native("panic", source, &[])?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_executable() -> anyhow::Result<()> {
+        let source = "proc square(n: int) int { ret n * n; }
+            proc main() int { ret square(arg_count() + 4); }";
+        let dir = std::env::temp_dir().join(format!("matrix-x86-{}-build", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let exe_path = dir.join("program");
+        build_executable(&lower_source(source)?, &exe_path)?;
+
+        let code = Command::new(&exe_path).args(["a", "b"]).status()?.code();
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(code, Some(36));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_builds_are_identical() -> anyhow::Result<()> {
+        let program = lower_source("proc main() int { ret arg_count(); }")?;
+        let dir = std::env::temp_dir().join(format!("matrix-x86-{}-twice", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let options = BuildOptions {
+            deterministic: true,
+        };
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        build_executable_with(&program, &first, options)?;
+        build_executable_with(&program, &second, options)?;
+
+        let (first, second) = (fs::read(first)?, fs::read(second)?);
+        fs::remove_dir_all(&dir)?;
+        assert!(first == second, "the executables differ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_types_are_rejected() -> anyhow::Result<()> {
+        let program = lower_source(
+            "proc half(x: float) float { ret x / 2.0; } proc main() void { half(1.0); }",
+        )?;
+        assert!(matches!(
+            emit(&program),
+            Err(AsmError::Unsupported {
+                ty: hir::Ty::Float,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_native_calls_are_rejected() -> anyhow::Result<()> {
+        let source = "proc main() int { ret twice(21); }";
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let natives = [hir::NativeSignature {
+            name: "twice".to_string(),
+            params: vec![hir::Ty::INT],
+            ret_ty: hir::Ty::INT,
+        }];
+        let options = hir::LowerOptions {
+            natives: &natives,
+            ..Default::default()
+        };
+        let program = mir::lower(&hir::lower_with(&ast, &options)?.program);
+        assert!(matches!(
+            emit(&program),
+            Err(AsmError::NativeCall { proc, native }) if proc == "main" && native == "twice"
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Building executables. The program's assembly and the runtime are assembled into object files
+//! with `cc`, which then links them with the C library using the system linker.
+//!
+//! `cc` runs in the directory the files are built in, with paths relative to it, so the
+//! directory's name, which changes from build to build, can't end up in what it writes.
+
+use crate::{diagnostics::BuildError, emit::emit_program, runtime::RUNTIME};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Settings for building an executable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildOptions {
+    /// Ask the toolchain not to write timestamps either, so building a program twice makes
+    /// the same bytes.
+    pub deterministic: bool,
+}
+
+/// Run `cc` in a directory with some arguments, reporting what it was doing if it fails.
+fn cc(
+    dir: &Path,
+    args: &[&Path],
+    doing: &'static str,
+    options: BuildOptions,
+) -> Result<(), BuildError> {
+    let mut command = Command::new("cc");
+    command.current_dir(dir).args(args);
+    if options.deterministic {
+        // Read by GNU and LLVM tools in place of the time, and by Apple's to zero it.
+        command
+            .env("SOURCE_DATE_EPOCH", "0")
+            .env("ZERO_AR_DATE", "1");
+    }
+    let output = command
+        .output()
+        .map_err(|error| BuildError::NoCc(error.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(BuildError::CcFailed(doing, stderr.trim_end().to_owned()));
+    }
+    Ok(())
+}
+
+/// Assemble a file into an object file next to it in a directory, returning the object file's
+/// path relative to the directory.
+fn assemble(
+    dir: &Path,
+    name: &str,
+    asm: &str,
+    options: BuildOptions,
+) -> Result<PathBuf, BuildError> {
+    let asm_path = PathBuf::from(format!("{name}.s"));
+    let object_path = PathBuf::from(format!("{name}.o"));
+    let full_path = dir.join(&asm_path);
+    fs::write(&full_path, asm)
+        .map_err(|error| BuildError::Io(full_path.display().to_string(), error.to_string()))?;
+    cc(
+        dir,
+        &[Path::new("-c"), &asm_path, Path::new("-o"), &object_path],
+        "assemble",
+        options,
+    )?;
+    Ok(object_path)
+}
+
+/// Compile a program to an executable at `output`, linked with the runtime.
+pub fn build_executable(program: &mir::Program, output: &Path) -> Result<(), BuildError> {
+    build_executable_with(program, output, BuildOptions::default())
+}
+
+/// Build an executable like [`build_executable`], with options other than the defaults.
+pub fn build_executable_with(
+    program: &mir::Program,
+    output: &Path,
+    options: BuildOptions,
+) -> Result<(), BuildError> {
+    let asm = emit_program(program)?;
+    let current = env::current_dir()
+        .map_err(|error| BuildError::Io(output.display().to_string(), error.to_string()))?;
+    let output = current.join(output);
+
+    // The assembly and object files are only needed until the executable is linked, and each
+    // build gets its own directory so concurrent builds don't overwrite each other's files.
+    let dir = tempfile::Builder::new()
+        .prefix("mtxc-build-")
+        .tempdir()
+        .map_err(|error| {
+            BuildError::Io(env::temp_dir().display().to_string(), error.to_string())
+        })?;
+    let linked = assemble(dir.path(), "program", &asm, options).and_then(|program| {
+        let runtime = assemble(dir.path(), "runtime", RUNTIME, options)?;
+        cc(
+            dir.path(),
+            &[&program, &runtime, Path::new("-o"), &output],
+            "link",
+            options,
+        )
+    });
+    // Failing to clean up doesn't stop the build.
+    let _ = dir.close();
+    linked
+}
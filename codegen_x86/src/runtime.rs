@@ -0,0 +1,5 @@
+//! The runtime compiled programs are linked with: the C `main` function, which calls the
+//! program's `matrix_main`, and the builtins the backend supports.
+
+/// The runtime's assembly, in the same syntax as the backend's output.
+pub const RUNTIME: &str = include_str!("runtime.s");
@@ -0,0 +1,119 @@
+# The runtime every compiled program is linked with. It defines the C `main` function, which
+# the C library's startup code calls, and the builtins compiled code calls.
+
+    .text
+
+# Save the number of arguments and seed the `rand` generator from the time stamp counter, then
+# run the program's `main` through `matrix_main`, returning its exit code.
+    .globl main
+main:
+    pushq %rbp
+    movq %rsp, %rbp
+    # `argc` counts the program's name, which isn't one of its arguments.
+    movslq %edi, %rdi
+    decq %rdi
+    movq %rdi, .Larg_count(%rip)
+    rdtsc
+    shlq $32, %rdx
+    orq %rdx, %rax
+    movq %rax, .Lrng_state(%rip)
+    call matrix_main
+    popq %rbp
+    ret
+
+# `arg_count() int`.
+    .globl matrix_arg_count
+matrix_arg_count:
+    movq .Larg_count(%rip), %rax
+    ret
+
+# `clock_ms() int`, from `CLOCK_MONOTONIC`, whose `timespec` is kept in the stack space that
+# aligns the call to `clock_gettime`.
+    .globl matrix_clock_ms
+matrix_clock_ms:
+    subq $24, %rsp
+    movl $1, %edi
+    movq %rsp, %rsi
+    call clock_gettime
+    imulq $1000, (%rsp), %rcx
+    movq 8(%rsp), %rax
+    cqto
+    movq $1000000, %r8
+    idivq %r8
+    addq %rcx, %rax
+    addq $24, %rsp
+    ret
+
+# `sleep_ms(ms: int) void` with `nanosleep`, which doesn't sleep for a negative `timespec`.
+    .globl matrix_sleep_ms
+matrix_sleep_ms:
+    subq $24, %rsp
+    movq %rdi, %rax
+    cqto
+    movq $1000, %r8
+    idivq %r8
+    movq %rax, (%rsp)
+    imulq $1000000, %rdx, %rdx
+    movq %rdx, 8(%rsp)
+    movq %rsp, %rdi
+    xorl %esi, %esi
+    call nanosleep
+    addq $24, %rsp
+    ret
+
+# `rand::seed(n: int) void`.
+    .globl matrix_rand_seed
+matrix_rand_seed:
+    movq %rdi, .Lrng_state(%rip)
+    ret
+
+# `rand::rand_int(lo: int, hi: int) int`, scaling the generator's next number by the size of
+# the range and keeping the high bits, like the interpreter. A size of 0 is the whole range.
+    .globl matrix_rand_int
+matrix_rand_int:
+    cmpq %rdi, %rsi
+    jl matrix_trap
+    call .Lrng_next
+    subq %rdi, %rsi
+    incq %rsi
+    jz 1f
+    mulq %rsi
+    movq %rdx, %rax
+1:
+    addq %rdi, %rax
+    ret
+
+# The next number of the SplitMix64 generator in `%rax`, clobbering `%rcx`.
+.Lrng_next:
+    movabsq $0x9e3779b97f4a7c15, %rax
+    addq .Lrng_state(%rip), %rax
+    movq %rax, .Lrng_state(%rip)
+    movq %rax, %rcx
+    shrq $30, %rcx
+    xorq %rcx, %rax
+    movabsq $0xbf58476d1ce4e5b9, %rcx
+    imulq %rcx, %rax
+    movq %rax, %rcx
+    shrq $27, %rcx
+    xorq %rcx, %rax
+    movabsq $0x94d049bb133111eb, %rcx
+    imulq %rcx, %rax
+    movq %rax, %rcx
+    shrq $31, %rcx
+    xorq %rcx, %rax
+    ret
+
+# Every failed runtime check jumps here, which aborts the program.
+    .globl matrix_trap
+matrix_trap:
+    ud2
+
+    .bss
+    .p2align 3
+.Larg_count:
+    .zero 8
+.Lrng_state:
+    .zero 8
+
+# Mark the stack as non-executable, which linkers warn about otherwise.
+    .section .note.GNU-stack,"",@progbits
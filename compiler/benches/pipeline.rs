@@ -0,0 +1,146 @@
+//! Benchmarks of lexing, parsing and checking whole programs through the compiler, so a
+//! regression in any stage, or in how they're put together, shows up. Run them with
+//! `cargo bench -p matrix-compiler`.
+//!
+//! The programs are generated in a few shapes, each at a few sizes: many small procedures,
+//! deeply nested blocks and expressions, and long literals.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use matrix_compiler::{Lexed, Session, Sources};
+use std::fmt::Write;
+
+/// A program of `n` small procedures, each calling the one before it.
+fn many_procs(n: usize) -> String {
+    let mut source = String::from("proc f0(x: int) int { ret x; }\n");
+    for i in 1..n {
+        writeln!(
+            source,
+            "proc f{i}(x: int) int {{ let y = x * 2 + {i}; if y > 100 {{ ret f{}(y / 3); }} ret y; }}",
+            i - 1
+        )
+        .unwrap();
+    }
+    writeln!(source, "proc main() int {{ ret f{}(1); }}", n - 1).unwrap();
+    source
+}
+
+/// A program whose `main` nests `depth` blocks, each also nesting its condition in parentheses.
+fn deep_nesting(depth: usize) -> String {
+    let mut source = String::from("proc main() int {\n    let x = 0;\n");
+    for i in 0..depth {
+        writeln!(
+            source,
+            "if {}x < {i}{} {{ x += 1;",
+            "(".repeat(i % 8),
+            ")".repeat(i % 8)
+        )
+        .unwrap();
+    }
+    source.push_str(&"}".repeat(depth));
+    source.push_str("\n    ret x;\n}\n");
+    source
+}
+
+/// A program with a string literal and an array literal of `len` characters and elements.
+fn long_literals(len: usize) -> String {
+    let elements: Vec<_> = (0..len).map(|i| i.to_string()).collect();
+    format!(
+        "const TEXT: str = \"{}\";\nproc main() int {{\n    let xs = [{}];\n    ret len(xs) + len(TEXT);\n}}\n",
+        "abcdefghij".repeat(len / 10),
+        elements.join(", ")
+    )
+}
+
+/// A shape of program: its name, how to generate it at a size, and the sizes benchmarked.
+type Shape = (&'static str, fn(usize) -> String, [usize; 3]);
+
+const SHAPES: [Shape; 3] = [
+    ("many_procs", many_procs, [10, 100, 1000]),
+    // Kept under the parser's nesting limit, which each block's condition nests a few levels
+    // past.
+    ("deep_nesting", deep_nesting, [8, 24, 48]),
+    ("long_literals", long_literals, [100, 1000, 10000]),
+];
+
+/// Every program benchmarked, by its shape and size.
+fn programs() -> Vec<(&'static str, usize, Sources)> {
+    SHAPES
+        .into_iter()
+        .flat_map(|(shape, generate, sizes)| {
+            sizes.into_iter().map(move |size| {
+                let sources = Sources::new([(format!("{shape}.mtx"), generate(size))]);
+                (shape, size, sources)
+            })
+        })
+        .collect()
+}
+
+fn pipeline(c: &mut Criterion) {
+    let programs = programs();
+    for (shape, size, sources) in &programs {
+        // The programs must be valid, or the benchmarks would measure failing early.
+        let session = Session::new(sources.clone());
+        let parsed = session
+            .lex()
+            .and_then(Lexed::parse)
+            .unwrap_or_else(|error| panic!("{shape} {size} doesn't parse: {error}"));
+        if let Err(error) = parsed.check() {
+            panic!("{shape} {size} doesn't check: {error}");
+        }
+    }
+
+    let mut group = c.benchmark_group("lex");
+    for (shape, size, sources) in &programs {
+        group.throughput(Throughput::Bytes(sources.text().len() as u64));
+        group.bench_with_input(BenchmarkId::new(*shape, size), sources, |b, sources| {
+            let session = Session::new(sources.clone());
+            b.iter(|| session.lex().unwrap());
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse");
+    for (shape, size, sources) in &programs {
+        group.throughput(Throughput::Bytes(sources.text().len() as u64));
+        group.bench_with_input(BenchmarkId::new(*shape, size), sources, |b, sources| {
+            let session = Session::new(sources.clone());
+            b.iter_batched(
+                || session.lex().unwrap(),
+                |lexed| lexed.parse().unwrap(),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("check");
+    for (shape, size, sources) in &programs {
+        let session = Session::new(sources.clone());
+        let parsed = session.lex().and_then(Lexed::parse).unwrap();
+        group.throughput(Throughput::Bytes(sources.text().len() as u64));
+        group.bench_function(BenchmarkId::new(*shape, size), |b| {
+            b.iter(|| parsed.check().unwrap());
+        });
+    }
+    group.finish();
+
+    // Every stage at once, like `mtxc check`.
+    let mut group = c.benchmark_group("pipeline");
+    for (shape, size, sources) in &programs {
+        group.throughput(Throughput::Bytes(sources.text().len() as u64));
+        group.bench_with_input(BenchmarkId::new(*shape, size), sources, |b, sources| {
+            b.iter(|| {
+                let session = Session::new(sources.clone());
+                session
+                    .lex()
+                    .and_then(Lexed::parse)
+                    .and_then(|parsed| parsed.check())
+                    .unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
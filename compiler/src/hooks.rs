@@ -0,0 +1,102 @@
+//! Hooks that see what each stage of compiling makes, for tools built on the compiler like lint
+//! runners and metrics collectors. A hook can also veto what it sees, which stops compiling
+//! with [`Failure::Vetoed`](crate::Failure::Vetoed) as if the stage had failed.
+
+use crate::sources::SourceFile;
+use lexer::token::Token;
+use miette::{Diagnostic, LabeledSpan};
+use parser::ast;
+use span::Span;
+use std::fmt;
+
+/// Why a hook stopped compiling, reported like the stage's own diagnostics.
+#[derive(Debug, Clone)]
+pub struct Veto {
+    message: String,
+    span: Option<Span>,
+    help: Option<String>,
+}
+
+impl Veto {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+            help: None,
+        }
+    }
+
+    /// Point the veto at a part of the source. For a file's tokens, the span is from the start
+    /// of the file, like theirs, and otherwise from the start of the program's text.
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Veto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Veto {}
+
+impl Diagnostic for Veto {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new("compiler::vetoed"))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let span = self.span?;
+        Some(Box::new(std::iter::once(LabeledSpan::underline(span))))
+    }
+}
+
+type Hook<'a, T> = Box<dyn Fn(&T) -> Result<(), Veto> + Send + Sync + 'a>;
+type TokensHook<'a> = Box<dyn Fn(&SourceFile<'_>, &[Token]) -> Result<(), Veto> + Send + Sync + 'a>;
+type DiagnosticHook<'a> = Box<dyn Fn(&dyn Diagnostic) -> Result<(), Veto> + Send + Sync + 'a>;
+
+/// The hooks given to a [`Session`](crate::Session), each run once its stage has made what
+/// it sees. The tokens hook sees each file as it's lexed, so it can be run on several threads
+/// at once.
+#[derive(Default)]
+pub(crate) struct Hooks<'a> {
+    pub tokens: Option<TokensHook<'a>>,
+    pub ast: Option<Hook<'a, ast::Program>>,
+    pub hir: Option<Hook<'a, hir::Program>>,
+    pub diagnostic: Option<DiagnosticHook<'a>>,
+}
+
+impl Hooks<'_> {
+    pub fn tokens(&self, file: &SourceFile<'_>, tokens: &[Token]) -> Result<(), Veto> {
+        self.tokens
+            .as_ref()
+            .map_or(Ok(()), |hook| hook(file, tokens))
+    }
+
+    pub fn ast(&self, ast: &ast::Program) -> Result<(), Veto> {
+        self.ast.as_ref().map_or(Ok(()), |hook| hook(ast))
+    }
+
+    pub fn hir(&self, program: &hir::Program) -> Result<(), Veto> {
+        self.hir.as_ref().map_or(Ok(()), |hook| hook(program))
+    }
+
+    pub fn diagnostic(&self, diagnostic: &dyn Diagnostic) -> Result<(), Veto> {
+        self.diagnostic
+            .as_ref()
+            .map_or(Ok(()), |hook| hook(diagnostic))
+    }
+}
@@ -0,0 +1,567 @@
+//! Compiling programs from their source files a stage at a time, for the CLI, the language
+//! server and programs embedding the compiler. Each compile has a [`Session`], which every stage
+//! borrows:
+//!
+//! ```ignore
+//! let session = Session::new(sources);
+//! let checked = session.lex()?.parse()?.check()?;
+//! let bytecode = checked.build();
+//! ```
+//!
+//! Each stage returns what it made, or an [`Error`] with the diagnostics it failed with.
+//! Nothing is printed: the session keeps every diagnostic its stages found, warnings included,
+//! for the caller to report.
+//!
+//! A compile can be stopped from another thread with a [`Cancel`] token given to
+//! [`Session::cancel_on`], such as when the file being compiled changes again. The stage
+//! running then fails with [`Failure::Cancelled`] rather than finishing.
+//!
+//! Tools can see the tokens, syntax tree, HIR and diagnostics of a compile as they're made by
+//! giving hooks to [`Session::on_tokens`] and the like, and stop it with a [`Veto`]:
+//!
+//! ```ignore
+//! let session = Session::new(sources).on_hir(|program| match program.procs.len() {
+//!     0..=100 => Ok(()),
+//!     _ => Err(Veto::new("The program has too many procedures")),
+//! });
+//! let checked = session.lex()?.parse()?.check()?;
+//! ```
+
+#![warn(rust_2018_idioms)]
+
+pub mod hooks;
+pub mod session;
+pub mod sources;
+
+pub use hooks::Veto;
+pub use session::{Diagnostics, Session};
+pub use sources::Sources;
+pub use span::{Cancel, Cancelled, FileId, FullSpan};
+
+use lexer::token::Token;
+use miette::{Diagnostic, MietteError, Report, SourceCode, SourceSpan, SpanContents};
+use parser::ast;
+use rayon::prelude::*;
+use sources::FileSource;
+use std::fmt;
+use thiserror::Error;
+
+/// The diagnostics a stage of compilation failed with.
+#[derive(Debug, Error, Diagnostic)]
+pub enum Failure {
+    /// A file didn't lex. The diagnostics' spans are offsets into the file rather than into
+    /// the program's text.
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Lex(lexer::DiagnosticSink),
+
+    /// A file didn't parse.
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Parse(parser::DiagnosticSink),
+
+    /// The program has errors in its names or types.
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Check(hir::DiagnosticSink),
+
+    /// The compile's token was cancelled before the stage finished.
+    #[error("Compilation was cancelled")]
+    Cancelled,
+
+    /// A hook vetoed what the stage made.
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Vetoed(Veto),
+}
+
+/// The source an error's diagnostics point into.
+#[derive(Debug)]
+enum ErrorSource {
+    /// The file that didn't lex.
+    File(FileSource),
+    Program(Sources),
+}
+
+impl SourceCode for ErrorSource {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        match self {
+            Self::File(file) => file.read_span(span, context_lines_before, context_lines_after),
+            Self::Program(sources) => {
+                sources.read_span(span, context_lines_before, context_lines_after)
+            }
+        }
+    }
+}
+
+/// Why compiling stopped: the failure of a stage, with the source it points into.
+#[derive(Debug)]
+pub struct Error {
+    pub failure: Failure,
+    source: ErrorSource,
+}
+
+impl Error {
+    fn cancelled(sources: &Sources) -> Self {
+        Self {
+            failure: Failure::Cancelled,
+            source: ErrorSource::Program(sources.clone()),
+        }
+    }
+
+    /// The file a span of one of the failure's diagnostics is in, with the span from the file's
+    /// start. The spans of a lex failure are already from the start of the file that didn't lex.
+    pub fn locate(&self, span: span::Span) -> FullSpan {
+        match &self.source {
+            ErrorSource::File(file) => FullSpan {
+                file: file.id,
+                span,
+            },
+            ErrorSource::Program(sources) => sources.locate(span),
+        }
+    }
+
+    /// Whether compiling stopped because it was cancelled rather than because of the program.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.failure, Failure::Cancelled)
+    }
+
+    /// A report of the failure that shows its diagnostics with the source around them, and the
+    /// fixes they suggest.
+    pub fn into_report(self) -> Report {
+        match self.failure {
+            Failure::Lex(failure) => failure.into_report(self.source),
+            Failure::Parse(failure) => failure.into_report(self.source),
+            Failure::Check(failure) => failure.into_report(self.source),
+            failure => Report::new(failure).with_source_code(self.source),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.failure, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.failure)
+    }
+}
+
+impl Session<'_> {
+    /// Lex each of the program's files. They're lexed in parallel, but the first file in order
+    /// that doesn't lex is the one reported. The session keeps the diagnostics of every file
+    /// that doesn't.
+    pub fn lex(&self) -> Result<Lexed<'_>, Error> {
+        let lexed: Vec<_> = self
+            .sources
+            .files()
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|file| {
+                let _span =
+                    tracing::info_span!("lex", file = file.name, bytes = file.text.len()).entered();
+                let source = || ErrorSource::File(self.sources.file_source(file.id));
+                match lexer::lex_cancellable(file.text, &self.cancel) {
+                    Ok(Ok(tokens)) => match self.hooks.tokens(file, &tokens) {
+                        Ok(()) => Ok(tokens),
+                        Err(veto) => Err(Error {
+                            failure: Failure::Vetoed(veto),
+                            source: source(),
+                        }),
+                    },
+                    Ok(Err(failure)) => Err(Error {
+                        failure: Failure::Lex(failure),
+                        source: source(),
+                    }),
+                    Err(Cancelled) => Err(Error::cancelled(&self.sources)),
+                }
+            })
+            .collect();
+        // A file may have failed before the others were cancelled, but the failure is of text
+        // that's out of date.
+        if self.cancel.is_cancelled() {
+            return Err(Error::cancelled(&self.sources));
+        }
+        self.diagnostics().lex = lexed
+            .iter()
+            .filter_map(|lexed| match lexed {
+                Err(Error {
+                    failure: Failure::Lex(failure),
+                    source: ErrorSource::File(file),
+                }) => Some((file.id, failure.clone())),
+                _ => None,
+            })
+            .collect();
+        let tokens = match lexed.into_iter().collect::<Result<_, _>>() {
+            Ok(tokens) => tokens,
+            Err(error) => return Err(self.failed(error)),
+        };
+        Ok(Lexed {
+            session: self,
+            tokens,
+        })
+    }
+}
+
+/// A program whose files lexed.
+pub struct Lexed<'s> {
+    session: &'s Session<'s>,
+    tokens: Vec<Vec<Token>>,
+}
+
+impl<'s> Lexed<'s> {
+    pub fn session(&self) -> &'s Session<'s> {
+        self.session
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.session.sources
+    }
+
+    /// The tokens of each file, in the order of the files, with spans from the start of their
+    /// file.
+    pub fn tokens(&self) -> &[Vec<Token>] {
+        &self.tokens
+    }
+
+    /// Parse each of the program's files, putting their items together into one syntax tree.
+    /// Like lexing, they're parsed in parallel, and the first file that doesn't parse is
+    /// reported, while the session keeps the diagnostics of every file that doesn't.
+    pub fn parse(self) -> Result<Parsed<'s>, Error> {
+        let session = self.session;
+        let sources = &session.sources;
+        let files: Vec<_> = sources.files().zip(self.tokens).collect();
+        let parsed: Vec<_> = files
+            .into_par_iter()
+            .map(|(file, mut tokens)| {
+                let _span =
+                    tracing::info_span!("parse", file = file.name, tokens = tokens.len()).entered();
+
+                // Move the tokens to where the file is in the program's text, which the parser
+                // reads them from.
+                for token in &mut tokens {
+                    token.span.start += file.start;
+                    token.span.end += file.start;
+                }
+                match parser::parse_cancellable(sources.text(), tokens, &session.cancel) {
+                    Ok(parsed) => parsed.map_err(|failure| Error {
+                        failure: Failure::Parse(failure),
+                        source: ErrorSource::Program(sources.clone()),
+                    }),
+                    Err(Cancelled) => Err(Error::cancelled(sources)),
+                }
+            })
+            .collect();
+        if session.cancel.is_cancelled() {
+            return Err(Error::cancelled(sources));
+        }
+
+        let mut found = parser::DiagnosticSink::new();
+        for error in parsed.iter().filter_map(|parsed| parsed.as_ref().err()) {
+            if let Failure::Parse(failure) = &error.failure {
+                found.append(failure.clone());
+            }
+        }
+        session.diagnostics().parse = found;
+
+        let mut items = Vec::new();
+        for ast in parsed {
+            match ast {
+                Ok(ast) => items.extend(ast.items),
+                Err(error) => return Err(session.failed(error)),
+            }
+        }
+        let ast = ast::Program { items };
+        session
+            .hooks
+            .ast(&ast)
+            .map_err(|veto| session.vetoed(veto))?;
+        Ok(Parsed { session, ast })
+    }
+}
+
+/// A program that parsed.
+pub struct Parsed<'s> {
+    session: &'s Session<'s>,
+    ast: ast::Program,
+}
+
+impl<'s> Parsed<'s> {
+    pub fn session(&self) -> &'s Session<'s> {
+        self.session
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.session.sources
+    }
+
+    pub fn ast(&self) -> &ast::Program {
+        &self.ast
+    }
+
+    pub fn into_ast(self) -> ast::Program {
+        self.ast
+    }
+
+    /// Resolve the program's names, type check it and lower it to HIR. The syntax tree is
+    /// kept, so it's still there if the program has errors. The session keeps the errors, or
+    /// the warnings of a program without any.
+    pub fn check(&self) -> Result<Checked, Error> {
+        let session = self.session;
+        let Session {
+            sources,
+            options,
+            cancel,
+            hooks,
+            ..
+        } = session;
+        let lowered = match hir::lower_cancellable(&self.ast, options, cancel) {
+            Ok(Ok(lowered)) => lowered,
+            Ok(Err(failure)) => {
+                session.diagnostics().check = failure.clone();
+                return Err(session.failed(Error {
+                    failure: Failure::Check(failure),
+                    source: ErrorSource::Program(sources.clone()),
+                }));
+            }
+            Err(Cancelled) => return Err(Error::cancelled(sources)),
+        };
+
+        let mut warnings = hir::DiagnosticSink::with_levels(options.levels.clone());
+        for warning in &lowered.warnings {
+            warnings.push_diagnostic(warning.clone());
+        }
+        session.diagnostics().check = warnings;
+        hooks
+            .hir(&lowered.program)
+            .map_err(|veto| session.vetoed(veto))?;
+        for warning in &lowered.warnings {
+            hooks
+                .diagnostic(warning)
+                .map_err(|veto| session.vetoed(veto))?;
+        }
+        Ok(Checked {
+            program: lowered.program,
+        })
+    }
+}
+
+/// A program without errors. Its warnings are kept by the session that checked it.
+pub struct Checked {
+    pub program: hir::Program,
+}
+
+impl Checked {
+    /// Compile the program to VM bytecode.
+    pub fn build(&self) -> vm::Bytecode {
+        let _span = tracing::info_span!(
+            "codegen",
+            backend = "bytecode",
+            procs = self.program.procs.len()
+        )
+        .entered();
+        vm::compile(&self.program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use span::Span;
+
+    fn sources(files: &[(&str, &str)]) -> Sources {
+        Sources::new(
+            files
+                .iter()
+                .map(|&(name, text)| (name.to_owned(), text.to_owned())),
+        )
+    }
+
+    #[test]
+    fn test_compiles_programs_of_several_files() -> anyhow::Result<()> {
+        let sources = sources(&[
+            ("main.mtx", "proc main() int { ret double(21); }"),
+            ("double.mtx", "proc double(n: int) int { ret n * 2; }"),
+        ]);
+        let session = Session::new(sources);
+        let lexed = session.lex()?;
+        assert_eq!(lexed.tokens().len(), 2);
+        let parsed = lexed.parse()?;
+        assert_eq!(parsed.ast().items.len(), 2);
+        let checked = parsed.check()?;
+        assert!(session.diagnostics().is_empty());
+
+        let bytecode = checked.build();
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = interp::Io::new(&mut input, &mut output);
+        let value = vm::run_with_io(&bytecode, io, interp::Options::default())?;
+        assert_eq!(value, interp::Value::int(42));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reports_the_stage_that_failed() {
+        let session = Session::new(sources(&[("a.mtx", "proc main() {}"), ("b.mtx", "\"")]));
+        let lex = session.lex().err().unwrap();
+        assert!(matches!(lex.failure, Failure::Lex(_)));
+
+        let session = Session::new(sources(&[("a.mtx", "proc main( {}")]));
+        let parse = session.lex().and_then(Lexed::parse).err().unwrap();
+        assert!(matches!(parse.failure, Failure::Parse(_)));
+
+        let session = Session::new(sources(&[("a.mtx", "proc main() int { ret x; }")]));
+        let parsed = session.lex().and_then(Lexed::parse).unwrap();
+        let check = parsed.check().err().unwrap();
+        assert!(matches!(check.failure, Failure::Check(_)));
+        assert_eq!(parsed.ast().items.len(), 1);
+
+        // Reports carry the source their diagnostics point into.
+        assert!(parse.into_report().source_code().is_some());
+    }
+
+    #[test]
+    fn test_session_keeps_what_every_file_found() {
+        let session = Session::new(sources(&[
+            ("a.mtx", "\""),
+            ("b.mtx", "proc main() {}"),
+            ("c.mtx", "'"),
+        ]));
+        session.lex().err().unwrap();
+        let files: Vec<_> = session.sources().files().map(|file| file.id).collect();
+        let lexed: Vec<_> = session
+            .diagnostics()
+            .lex
+            .iter()
+            .map(|(file, _)| *file)
+            .collect();
+        assert_eq!(lexed, [files[0], files[2]]);
+
+        let session = Session::new(sources(&[("a.mtx", "proc a( {}"), ("b.mtx", "proc b( {}")]));
+        let error = session.lex().and_then(Lexed::parse).err().unwrap();
+        let Failure::Parse(failure) = &error.failure else {
+            unreachable!()
+        };
+        let diagnostics = session.diagnostics();
+        assert!(diagnostics.parse.diagnostics().len() > failure.diagnostics().len());
+        assert!(diagnostics.lex.is_empty());
+    }
+
+    #[test]
+    fn test_locates_diagnostics_in_their_files() {
+        let program = sources(&[("a.mtx", "proc main() {}"), ("b.mtx", "\"")]);
+        let b = program.files().nth(1).unwrap().id;
+        let lex = Session::new(program.clone()).lex().err().unwrap();
+        assert_eq!(lex.locate(Span::from(0..1)).file, b);
+
+        let program = sources(&[
+            ("a.mtx", "proc main() int { ret 0; }"),
+            ("b.mtx", "proc f() int { ret x; }"),
+        ]);
+        let session = Session::new(program.clone());
+        let check = session
+            .lex()
+            .and_then(Lexed::parse)
+            .and_then(|parsed| parsed.check())
+            .err()
+            .unwrap();
+        let label = check.failure.related().unwrap().next().unwrap();
+        let label = label.labels().unwrap().next().unwrap();
+        let span = Span::from(label.offset()..label.offset() + label.len());
+        let x = "proc f() int { ret ".len();
+        assert_eq!(
+            check.locate(span),
+            FullSpan {
+                file: program.files().nth(1).unwrap().id,
+                span: Span::from(x..x + 1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_stops_once_cancelled() {
+        let cancel = Cancel::new();
+        let session =
+            Session::new(sources(&[("a.mtx", "proc main() void {}")])).cancel_on(cancel.clone());
+        let parsed = session.lex().and_then(Lexed::parse).unwrap();
+
+        cancel.cancel();
+        let error = parsed.check().err().unwrap();
+        assert!(error.is_cancelled());
+
+        // Even a file that doesn't lex is cancelled rather than reported.
+        let session = Session::new(sources(&[("a.mtx", "\"")])).cancel_on(cancel);
+        let error = session.lex().err().unwrap();
+        assert!(matches!(error.failure, Failure::Cancelled));
+        assert!(session.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_hooks_see_and_veto_each_stage() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let program = || {
+            sources(&[
+                ("main.mtx", "proc main() int { 1 + 1; ret 0; }"),
+                ("other.mtx", "proc other() void {}"),
+            ])
+        };
+        let (files, items, procs, warnings) = (
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        );
+        let session = Session::new(program())
+            .on_tokens(|_, tokens| {
+                assert!(!tokens.is_empty());
+                files.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .on_ast(|ast| {
+                items.store(ast.items.len(), Ordering::Relaxed);
+                Ok(())
+            })
+            .on_hir(|program| {
+                procs.store(program.procs.len(), Ordering::Relaxed);
+                Ok(())
+            })
+            .on_diagnostic(|_| {
+                warnings.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            });
+        let checked = session
+            .lex()
+            .and_then(Lexed::parse)
+            .and_then(|parsed| parsed.check())
+            .unwrap();
+        assert_eq!(files.load(Ordering::Relaxed), 2);
+        assert_eq!(items.load(Ordering::Relaxed), 2);
+        assert_eq!(procs.load(Ordering::Relaxed), checked.program.procs.len());
+        assert_eq!(warnings.load(Ordering::Relaxed), 1);
+        assert_eq!(session.diagnostics().check.diagnostics().len(), 1);
+
+        let session = Session::new(program()).on_ast(|_| Err(Veto::new("no")));
+        let error = session.lex().and_then(Lexed::parse).err().unwrap();
+        assert!(matches!(error.failure, Failure::Vetoed(_)));
+
+        // A vetoed warning fails the compile like an error.
+        let session =
+            Session::new(program()).on_diagnostic(|_| Err(Veto::new("warnings are denied")));
+        let error = session
+            .lex()
+            .and_then(Lexed::parse)
+            .and_then(|parsed| parsed.check())
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "warnings are denied");
+    }
+}
@@ -0,0 +1,143 @@
+//! The state one compile shares between its stages.
+//!
+//! A [`Session`] owns a program's sources, the options it's compiled with, its cancel token, its
+//! hooks, and the diagnostics its stages find. Each stage borrows the session rather than taking
+//! it, so it's still there once compiling stops, however far it got: the language server keeps
+//! a document's session to answer questions about it, and the CLI reports what the session found
+//! without putting together the results of each stage itself.
+//!
+//! Names aren't interned per session: the interner is shared by the whole process, so symbols
+//! mean the same in every session, and a later session finds names already interned.
+
+use crate::{
+    hooks::{Hooks, Veto},
+    sources::{self, Sources},
+    Error, ErrorSource, Failure,
+};
+use lexer::token::Token;
+use miette::Diagnostic;
+use parser::ast;
+use span::{Cancel, FileId};
+use std::sync::{Mutex, MutexGuard};
+
+/// Every diagnostic a session's stages found, by stage. Running a stage again replaces what it
+/// found before.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// The diagnostics of each file that didn't lex, in the order of the files, with spans from
+    /// the start of the file.
+    pub lex: Vec<(FileId, lexer::DiagnosticSink)>,
+
+    /// The diagnostics of every file that didn't parse.
+    pub parse: parser::DiagnosticSink,
+
+    /// The errors of a program that didn't check, or the warnings of one that did.
+    pub check: hir::DiagnosticSink,
+}
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.lex.is_empty() && !self.parse.has_diagnostics() && !self.check.has_diagnostics()
+    }
+}
+
+/// A program being compiled, with the options it's compiled with and what its stages found.
+pub struct Session<'a> {
+    pub(crate) sources: Sources,
+    pub(crate) options: hir::LowerOptions<'a>,
+    pub(crate) cancel: Cancel,
+    pub(crate) hooks: Hooks<'a>,
+    diagnostics: Mutex<Diagnostics>,
+}
+
+impl<'a> Session<'a> {
+    /// Compile a program with the default options.
+    pub fn new(sources: Sources) -> Self {
+        Self {
+            sources,
+            options: hir::LowerOptions::default(),
+            cancel: Cancel::new(),
+            hooks: Hooks::default(),
+            diagnostics: Mutex::default(),
+        }
+    }
+
+    /// Compile the program with other options, like another overflow mode.
+    pub fn options(mut self, options: hir::LowerOptions<'a>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Stop compiling, between tokens, items or procedures, once a token is cancelled.
+    pub fn cancel_on(mut self, cancel: Cancel) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// See the tokens of each file once it's lexed, with spans from the start of the file.
+    /// Files are lexed in parallel, so the hook can be run on several threads at once.
+    pub fn on_tokens(
+        mut self,
+        hook: impl Fn(&sources::SourceFile<'_>, &[Token]) -> Result<(), Veto> + Send + Sync + 'a,
+    ) -> Self {
+        self.hooks.tokens = Some(Box::new(hook));
+        self
+    }
+
+    /// See the syntax tree of the whole program once it's parsed.
+    pub fn on_ast(
+        mut self,
+        hook: impl Fn(&ast::Program) -> Result<(), Veto> + Send + Sync + 'a,
+    ) -> Self {
+        self.hooks.ast = Some(Box::new(hook));
+        self
+    }
+
+    /// See the HIR of the program once it's checked without errors.
+    pub fn on_hir(
+        mut self,
+        hook: impl Fn(&hir::Program) -> Result<(), Veto> + Send + Sync + 'a,
+    ) -> Self {
+        self.hooks.hir = Some(Box::new(hook));
+        self
+    }
+
+    /// See each diagnostic the compile finds: the errors of the stage that failed, or the
+    /// warnings of the checked program. Vetoing a warning fails the compile, while a vetoed
+    /// error is reported as it would have been.
+    pub fn on_diagnostic(
+        mut self,
+        hook: impl Fn(&dyn Diagnostic) -> Result<(), Veto> + Send + Sync + 'a,
+    ) -> Self {
+        self.hooks.diagnostic = Some(Box::new(hook));
+        self
+    }
+
+    pub fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    /// What the stages run so far found.
+    pub fn diagnostics(&self) -> MutexGuard<'_, Diagnostics> {
+        self.diagnostics
+            .lock()
+            .expect("the session's diagnostics aren't poisoned")
+    }
+
+    /// Show the diagnostic hook the errors of a stage that failed.
+    pub(crate) fn failed(&self, error: Error) -> Error {
+        for diagnostic in error.failure.related().into_iter().flatten() {
+            // The compile fails anyway.
+            let _ = self.hooks.diagnostic(diagnostic);
+        }
+        error
+    }
+
+    /// An error for a hook's veto, pointing into the whole program.
+    pub(crate) fn vetoed(&self, veto: Veto) -> Error {
+        Error {
+            failure: Failure::Vetoed(veto),
+            source: ErrorSource::Program(self.sources.clone()),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Programs made of several source files.
+//!
+//! Each file is lexed and parsed on its own, then their items are lowered together as one
+//! program. The files are laid out one after another in a [`SourceMap`], so every span points
+//! into exactly one file, which [`Sources::locate`] finds, and diagnostics show the file each
+//! of their labels is in.
+//!
+//! The text is read once and shared: cloning [`Sources`] to report a stage's diagnostics, or
+//! to hand it to another thread, copies no text. Each file keeps where its lines start, so
+//! finding the line of an offset, and reading the lines around a label, doesn't scan the file
+//! from its start.
+
+use miette::{MietteError, SourceCode, SourceSpan, SpanContents};
+use span::{FileId, FullSpan, SourceMap, Span};
+use std::sync::Arc;
+
+/// A source file of a program.
+pub struct SourceFile<'a> {
+    pub id: FileId,
+    pub name: &'a str,
+    pub text: &'a str,
+
+    /// Where the file starts in the program's text.
+    pub start: usize,
+
+    map: &'a SourceMap,
+}
+
+impl SourceFile<'_> {
+    /// The line and column of an offset from the start of the file, from 1, with columns
+    /// counted in characters.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        self.map.line_column(self.id, offset)
+    }
+}
+
+/// The source files of a program, shared by its clones.
+#[derive(Debug, Clone)]
+pub struct Sources {
+    map: Arc<SourceMap>,
+}
+
+impl Sources {
+    /// Lay out files, given by name and text, in order.
+    pub fn new(files: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut map = SourceMap::new();
+        for (name, text) in files {
+            map.add(name, &text);
+        }
+        map.into()
+    }
+
+    pub fn map(&self) -> &SourceMap {
+        &self.map
+    }
+
+    /// The text of every file, in order.
+    pub fn text(&self) -> &str {
+        self.map.text()
+    }
+
+    /// The name of the program's file, or of every file joined with commas.
+    pub fn name(&self) -> String {
+        let names: Vec<_> = self.map.files().map(|file| self.map.name(file)).collect();
+        names.join(", ")
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = SourceFile<'_>> {
+        self.map.files().map(|id| self.file(id))
+    }
+
+    pub fn file(&self, id: FileId) -> SourceFile<'_> {
+        SourceFile {
+            id,
+            name: self.map.name(id),
+            text: self.map.source(id),
+            start: self.map.start(id),
+            map: &self.map,
+        }
+    }
+
+    /// The file a span of the program's text is in, with the span from the file's start.
+    pub fn locate(&self, span: Span) -> FullSpan {
+        self.map.locate(span)
+    }
+
+    /// One of the files, for reading spans from its start rather than the program's, like
+    /// those of the diagnostics of lexing it.
+    pub(crate) fn file_source(&self, id: FileId) -> FileSource {
+        FileSource {
+            sources: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Share a map already laid out, such as one whose files were added from their bytes.
+impl From<SourceMap> for Sources {
+    fn from(map: SourceMap) -> Self {
+        Self { map: Arc::new(map) }
+    }
+}
+
+/// Reads spans from the file they're in, named after it, with lines counted from its start.
+impl SourceCode for Sources {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        self.map
+            .read_span(span, context_lines_before, context_lines_after)
+    }
+}
+
+/// A file of a program's sources, whose spans are from the start of the file.
+#[derive(Debug)]
+pub(crate) struct FileSource {
+    sources: Sources,
+    pub id: FileId,
+}
+
+impl SourceCode for FileSource {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self.sources.map.read_file_span(
+            self.id,
+            span,
+            context_lines_before,
+            context_lines_after,
+        )?;
+        Ok(Box::new(contents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_share_the_map() {
+        let sources = Sources::new([
+            ("a.mtx".to_owned(), "one\ntwo".to_owned()),
+            ("b.mtx".to_owned(), "three\nfour".to_owned()),
+        ]);
+        let b = sources.files().nth(1).unwrap();
+        assert_eq!((b.name, b.text, b.start), ("b.mtx", "three\nfour", 8));
+        assert_eq!(b.line_column(6), (2, 1));
+
+        let four = sources.text().find("four").unwrap();
+        let full = sources.locate(Span::from(four..four + 4));
+        assert_eq!((full.file, full.span), (b.id, Span::from(6..10)));
+        assert_eq!(
+            sources.clone().map().text().as_ptr(),
+            sources.text().as_ptr()
+        );
+
+        let file = sources.file_source(b.id);
+        let contents = file.read_span(&(6, 4).into(), 0, 0).unwrap();
+        assert_eq!(contents.name(), Some("b.mtx"));
+        assert_eq!(contents.line(), 1);
+    }
+}
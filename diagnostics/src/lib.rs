@@ -0,0 +1,611 @@
+//! Collecting the diagnostics a stage of compiling finds, the same way for every stage. Each
+//! stage has its own diagnostics, with their own codes, and a [`DiagnosticSink`] of them which
+//! it fails with. The sink is itself a diagnostic, with the stage's failure code, whose related
+//! diagnostics are the ones it holds.
+//!
+//! A diagnostic can suggest a fix: text to replace part of the source with. Suggestions are
+//! data rather than prose, so `fix` can apply them and the language server can offer them, and
+//! a stage's report shows each one after its diagnostic's help.
+//!
+//! A diagnostic can also have notes, like where something it's about is defined. A report shows
+//! them as related diagnostics after it, in their order.
+//!
+//! A sink can be given [`Levels`] saying what to do with warnings, by their code: leave them
+//! out, keep them, or make them errors, which fail the stage like any other.
+
+use miette::{LabeledSpan, Report, SourceCode};
+use span::Span;
+use std::{collections::BTreeMap, fmt};
+
+pub use miette::{Diagnostic, Severity};
+
+/// The diagnostics of a stage of compiling.
+pub trait Stage: Diagnostic + Send + Sync + 'static {
+    /// What the stage does, like `lexing`, for the message the stage fails with.
+    const NAME: &'static str;
+
+    /// The code of the diagnostic the stage fails with, like `lexer::failure`.
+    const FAILURE: &'static str;
+
+    /// The fix the diagnostic suggests, if it has one.
+    fn suggestion(&self) -> Option<Suggestion> {
+        None
+    }
+
+    /// The notes shown after the diagnostic.
+    fn notes(&self) -> Vec<Note> {
+        Vec::new()
+    }
+}
+
+/// A note about a diagnostic, like `` `f` is defined here ``, pointing at a part of the source
+/// if it's about one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Note {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Note {}
+
+/// Notes have no code, and are only advice.
+impl Diagnostic for Note {
+    fn severity(&self) -> Option<Severity> {
+        Some(Severity::Advice)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let span = self.span?;
+        Some(Box::new(std::iter::once(LabeledSpan::underline(span))))
+    }
+}
+
+/// How sure a suggestion is to be what was meant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    /// The suggestion is what was meant, so it can be applied without judgement, like by `fix`.
+    MachineApplicable,
+
+    /// The suggestion may not be what was meant, so it's only offered.
+    MaybeIncorrect,
+}
+
+/// A fix for a diagnostic: text to replace a part of the source code with. An empty span
+/// inserts the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
+
+impl Suggestion {
+    /// A suggestion that can be applied without judgement.
+    pub fn new(span: Span, replacement: impl Into<String>) -> Self {
+        Self {
+            span,
+            replacement: replacement.into(),
+            applicability: Applicability::MachineApplicable,
+        }
+    }
+
+    /// Insert text at an offset.
+    pub fn insert(offset: usize, text: impl Into<String>) -> Self {
+        Self::new(Span::from(offset..offset), text)
+    }
+
+    pub fn with_applicability(mut self, applicability: Applicability) -> Self {
+        self.applicability = applicability;
+        self
+    }
+
+    pub fn is_machine_applicable(&self) -> bool {
+        self.applicability == Applicability::MachineApplicable
+    }
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.start == self.span.end {
+            write!(f, "insert `{}`", self.replacement)
+        } else if self.replacement.is_empty() {
+            f.write_str("remove this")
+        } else {
+            write!(f, "replace with `{}`", self.replacement)
+        }
+    }
+}
+
+/// What to do with a warning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Level {
+    /// Leave it out.
+    Allow,
+
+    /// Report it.
+    #[default]
+    Warn,
+
+    /// Report it as an error.
+    Deny,
+}
+
+/// What to do with warnings: with all of them, and with those of some codes, which overrides
+/// it. Errors are always errors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Levels {
+    warnings: Level,
+    codes: BTreeMap<String, Level>,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the level of every warning whose code has no level of its own.
+    pub fn set_warnings(&mut self, level: Level) {
+        self.warnings = level;
+    }
+
+    /// Set the level of the warnings with a code, like `hir::no_effect`.
+    pub fn set(&mut self, code: impl Into<String>, level: Level) {
+        self.codes.insert(code.into(), level);
+    }
+
+    /// The level of a diagnostic, which is [`Level::Deny`] for errors.
+    pub fn level(&self, diagnostic: &dyn Diagnostic) -> Level {
+        if is_error(diagnostic) {
+            return Level::Deny;
+        }
+        code(diagnostic)
+            .and_then(|code| self.codes.get(&code).copied())
+            .unwrap_or(self.warnings)
+    }
+
+    /// How severe a diagnostic is once denied warnings are made errors.
+    pub fn severity(&self, diagnostic: &dyn Diagnostic) -> Severity {
+        match self.level(diagnostic) {
+            Level::Deny => Severity::Error,
+            Level::Allow | Level::Warn => severity(diagnostic),
+        }
+    }
+
+    /// Whether a diagnostic is a warning made an error.
+    pub fn is_denied(&self, diagnostic: &dyn Diagnostic) -> bool {
+        !is_error(diagnostic) && self.level(diagnostic) == Level::Deny
+    }
+}
+
+/// How severe a diagnostic is. Diagnostics are errors unless they say otherwise.
+pub fn severity(diagnostic: &dyn Diagnostic) -> Severity {
+    diagnostic.severity().unwrap_or(Severity::Error)
+}
+
+/// Whether a diagnostic is an error rather than a warning or advice.
+pub fn is_error(diagnostic: &dyn Diagnostic) -> bool {
+    severity(diagnostic) == Severity::Error
+}
+
+/// A diagnostic's code, like `hir::type_mismatch`.
+pub fn code(diagnostic: &dyn Diagnostic) -> Option<String> {
+    diagnostic.code().map(|code| code.to_string())
+}
+
+/// Whether a diagnostic is a note about the one it's related to, rather than one it gathers
+/// like a stage's failure does.
+pub fn is_note(diagnostic: &dyn Diagnostic) -> bool {
+    diagnostic.code().is_none() && diagnostic.severity() == Some(Severity::Advice)
+}
+
+/// A report of a diagnostic with the source it points into, its notes and the fix it suggests.
+pub fn report<D: Stage>(diagnostic: D, source_code: impl SourceCode + 'static) -> Report {
+    Report::new(Annotated::new(diagnostic, false)).with_source_code(source_code)
+}
+
+/// Where a diagnostic points first: the start of its first label, if it has one.
+pub fn offset(diagnostic: &dyn Diagnostic) -> Option<usize> {
+    diagnostic.labels()?.next().map(|label| label.offset())
+}
+
+/// The diagnostics a stage has found so far, in the order it found them, besides allowed
+/// warnings.
+#[derive(Debug, Clone)]
+pub struct DiagnosticSink<D> {
+    diagnostics: Vec<D>,
+    levels: Levels,
+}
+
+impl<D> Default for DiagnosticSink<D> {
+    fn default() -> Self {
+        Self {
+            diagnostics: Vec::new(),
+            levels: Levels::default(),
+        }
+    }
+}
+
+impl<D> DiagnosticSink<D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A sink doing what the levels say with warnings.
+    pub fn with_levels(levels: Levels) -> Self {
+        Self {
+            diagnostics: Vec::new(),
+            levels,
+        }
+    }
+
+    pub fn levels(&self) -> &Levels {
+        &self.levels
+    }
+
+    /// Add the diagnostics of another sink after this one's. They're already left out or made
+    /// errors by its levels, which should be the same as this one's.
+    pub fn append(&mut self, other: Self) {
+        self.diagnostics.extend(other.diagnostics);
+    }
+
+    pub fn has_diagnostics(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+
+    pub fn diagnostics(&self) -> &[D] {
+        &self.diagnostics
+    }
+
+    pub fn into_diagnostics(self) -> Vec<D> {
+        self.diagnostics
+    }
+}
+
+impl<D: Stage> DiagnosticSink<D> {
+    /// The fixes the diagnostics suggest, in their order.
+    pub fn suggestions(&self) -> impl Iterator<Item = Suggestion> + '_ {
+        self.diagnostics.iter().filter_map(Stage::suggestion)
+    }
+}
+
+impl<D: Diagnostic> DiagnosticSink<D> {
+    /// Add a diagnostic, unless it's an allowed warning.
+    pub fn push_diagnostic(&mut self, diagnostic: D) {
+        if self.levels.level(&diagnostic) != Level::Allow {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn is_error(&self, diagnostic: &D) -> bool {
+        self.levels.severity(diagnostic) == Severity::Error
+    }
+
+    /// Returns if any diagnostic is an error or a denied warning.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| self.is_error(diagnostic))
+    }
+
+    /// The errors, with denied warnings.
+    pub fn errors(&self) -> impl Iterator<Item = &D> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| self.is_error(diagnostic))
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &D> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| !self.is_error(diagnostic))
+    }
+
+    /// Put the diagnostics in the order of where they point in the source. Diagnostics
+    /// pointing at the same place, and those without labels, keep their order, the latter
+    /// after the rest.
+    pub fn sort_by_span(&mut self) {
+        self.diagnostics
+            .sort_by_key(|diagnostic| offset(diagnostic).unwrap_or(usize::MAX));
+    }
+}
+
+impl<D: Stage> DiagnosticSink<D> {
+    /// A report of the stage's failure that shows its diagnostics with the source they point
+    /// into, their notes, and the fixes they suggest. Denied warnings are shown as errors.
+    pub fn into_report(self, source_code: impl SourceCode + 'static) -> Report {
+        let levels = self.levels;
+        let diagnostics = self
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let denied = levels.is_denied(&diagnostic);
+                Annotated::new(diagnostic, denied)
+            })
+            .collect();
+        Report::new(DiagnosticSink {
+            diagnostics,
+            levels: Levels::default(),
+        })
+        .with_source_code(source_code)
+    }
+}
+
+/// A diagnostic with the fix it suggests shown after its help, and its notes as related
+/// diagnostics. A denied warning is an error, with a note saying it's denied.
+#[derive(Debug)]
+struct Annotated<D> {
+    diagnostic: D,
+    notes: Vec<Note>,
+    denied: bool,
+}
+
+impl<D: Stage> Annotated<D> {
+    fn new(diagnostic: D, denied: bool) -> Self {
+        let mut notes = diagnostic.notes();
+        if denied {
+            let code = code(&diagnostic).unwrap_or_else(|| "this warning".to_owned());
+            notes.push(Note::new(format!("`{code}` is denied")));
+        }
+        Self {
+            diagnostic,
+            notes,
+            denied,
+        }
+    }
+}
+
+impl<D: Stage> Stage for Annotated<D> {
+    const NAME: &'static str = D::NAME;
+    const FAILURE: &'static str = D::FAILURE;
+
+    fn suggestion(&self) -> Option<Suggestion> {
+        self.diagnostic.suggestion()
+    }
+
+    fn notes(&self) -> Vec<Note> {
+        self.notes.clone()
+    }
+}
+
+impl<D: fmt::Display> fmt::Display for Annotated<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.diagnostic.fmt(f)
+    }
+}
+
+impl<D: Stage> std::error::Error for Annotated<D> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.diagnostic.source()
+    }
+}
+
+impl<D: Stage> Diagnostic for Annotated<D> {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.code()
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        if self.denied {
+            return Some(Severity::Error);
+        }
+        self.diagnostic.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        let Some(suggestion) = self.diagnostic.suggestion() else {
+            return self.diagnostic.help();
+        };
+        Some(match self.diagnostic.help() {
+            Some(help) => Box::new(format!("{help}\n{suggestion}")),
+            None => Box::new(suggestion),
+        })
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.diagnostic.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.diagnostic.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        if self.notes.is_empty() {
+            return self.diagnostic.related();
+        }
+        let notes = self.notes.iter().map(|note| note as &dyn Diagnostic);
+        Some(Box::new(
+            self.diagnostic.related().into_iter().flatten().chain(notes),
+        ))
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.diagnostic.diagnostic_source()
+    }
+}
+
+impl<D: Stage> fmt::Display for DiagnosticSink<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.diagnostics.len();
+        let plural = if count != 1 { "s" } else { "" };
+        write!(f, "{} failed with {count} diagnostic{plural}", D::NAME)
+    }
+}
+
+impl<D: Stage> std::error::Error for DiagnosticSink<D> {}
+
+impl<D: Stage> Diagnostic for DiagnosticSink<D> {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(D::FAILURE))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic as &dyn Diagnostic),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thiserror::Error;
+
+    #[derive(Debug, Error, Diagnostic)]
+    enum TestDiagnostic {
+        #[diagnostic(code(test::error), help("compare"))]
+        #[error("error")]
+        Error(#[label] (usize, usize)),
+
+        #[diagnostic(code(test::warning), severity(Warning))]
+        #[error("warning")]
+        Warning(#[label] (usize, usize)),
+    }
+
+    impl Stage for TestDiagnostic {
+        const NAME: &'static str = "testing";
+        const FAILURE: &'static str = "test::failure";
+
+        fn suggestion(&self) -> Option<Suggestion> {
+            match self {
+                Self::Error((offset, len)) => {
+                    Some(Suggestion::new(Span::from(*offset..offset + len), "=="))
+                }
+                Self::Warning(_) => None,
+            }
+        }
+
+        fn notes(&self) -> Vec<Note> {
+            match self {
+                Self::Error(_) => vec![Note::new("`x` is defined here").at(Span::from(3..4))],
+                Self::Warning(_) => vec![Note::new("warned about once")],
+            }
+        }
+    }
+
+    #[test]
+    fn test_sink() {
+        let mut sink = DiagnosticSink::new();
+        sink.push_diagnostic(TestDiagnostic::Warning((8, 1)));
+        sink.push_diagnostic(TestDiagnostic::Error((2, 1)));
+        assert!(sink.has_errors());
+        assert_eq!(sink.errors().count(), 1);
+        assert_eq!(sink.warnings().count(), 1);
+        assert_eq!(sink.to_string(), "testing failed with 2 diagnostics");
+        assert_eq!(code(&sink).as_deref(), Some("test::failure"));
+        assert_eq!(sink.related().unwrap().count(), 2);
+
+        sink.sort_by_span();
+        assert!(matches!(
+            sink.diagnostics(),
+            [TestDiagnostic::Error(_), TestDiagnostic::Warning(_)]
+        ));
+        assert_eq!(severity(&sink.diagnostics()[1]), Severity::Warning);
+
+        let mut warnings = DiagnosticSink::new();
+        warnings.push_diagnostic(TestDiagnostic::Warning((0, 1)));
+        assert!(!warnings.has_errors());
+        warnings.append(sink);
+        assert_eq!(warnings.into_diagnostics().len(), 3);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let mut sink = DiagnosticSink::new();
+        sink.push_diagnostic(TestDiagnostic::Error((2, 1)));
+        sink.push_diagnostic(TestDiagnostic::Warning((4, 1)));
+        let suggestions: Vec<_> = sink.suggestions().collect();
+        assert_eq!(suggestions, [Suggestion::new(Span::from(2..3), "==")]);
+        assert!(suggestions[0].is_machine_applicable());
+
+        let report = sink.into_report("if x = 1 {}");
+        let helps: Vec<_> = report
+            .related()
+            .unwrap()
+            .map(|diagnostic| diagnostic.help().map(|help| help.to_string()))
+            .collect();
+        assert_eq!(helps, [Some("compare\nreplace with `==`".to_owned()), None]);
+
+        assert_eq!(Suggestion::insert(3, ";").to_string(), "insert `;`");
+        let removal =
+            Suggestion::new(Span::from(0..2), "").with_applicability(Applicability::MaybeIncorrect);
+        assert_eq!(removal.to_string(), "remove this");
+        assert!(!removal.is_machine_applicable());
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut levels = Levels::new();
+        levels.set_warnings(Level::Allow);
+        let mut sink = DiagnosticSink::with_levels(levels.clone());
+        sink.push_diagnostic(TestDiagnostic::Warning((0, 1)));
+        sink.push_diagnostic(TestDiagnostic::Error((2, 1)));
+        assert_eq!(sink.diagnostics().len(), 1);
+
+        levels.set("test::warning", Level::Deny);
+        let mut sink = DiagnosticSink::with_levels(levels);
+        sink.push_diagnostic(TestDiagnostic::Warning((0, 1)));
+        assert!(sink.has_errors());
+        assert_eq!(sink.warnings().count(), 0);
+
+        let report = sink.into_report("x");
+        let denied = report.related().unwrap().next().unwrap();
+        assert_eq!(denied.severity(), Some(Severity::Error));
+        let notes: Vec<_> = denied
+            .related()
+            .unwrap()
+            .map(|note| note.to_string())
+            .collect();
+        assert_eq!(notes, ["warned about once", "`test::warning` is denied"]);
+    }
+
+    #[test]
+    fn test_notes() {
+        let report = report(TestDiagnostic::Error((0, 1)), "x == 1");
+        let notes: Vec<_> = report.related().unwrap().collect();
+        let [note] = notes[..] else {
+            panic!("expected one note, found {}", notes.len());
+        };
+        assert!(is_note(note));
+        assert!(!is_note(report.as_ref()));
+        assert_eq!(note.to_string(), "`x` is defined here");
+        assert_eq!(offset(note), Some(3));
+
+        let mut sink = DiagnosticSink::new();
+        sink.push_diagnostic(TestDiagnostic::Warning((0, 1)));
+        let report = sink.into_report("x");
+        let warning = report.related().unwrap().next().unwrap();
+        let notes: Vec<_> = warning
+            .related()
+            .unwrap()
+            .map(|note| note.to_string())
+            .collect();
+        assert_eq!(notes, ["warned about once"]);
+    }
+}
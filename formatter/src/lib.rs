@@ -0,0 +1,586 @@
+//! Formats programs in a canonical style: four spaces of indentation, one statement per line
+//! and single spaces around binary operators and after commas. Argument, parameter, array and
+//! map lists that don't fit in [`MAX_WIDTH`] columns are wrapped to one element per line.
+//!
+//! The syntax tree is printed again rather than the source adjusted, so comments come from the
+//! tokens of [`lexer::lex_with_comments`]. Comments keep their own lines, or stay at the end of
+//! the line they end, and at most one blank line is kept between items and statements. A
+//! comment inside an expression moves after the statement it's in.
+
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+use lexer::token::{Token, TokenKind};
+use parser::ast::*;
+use span::Span;
+
+/// The width the formatter wraps lists to fit in.
+pub const MAX_WIDTH: usize = 100;
+
+const INDENT: &str = "    ";
+
+/// The column text ends at when it starts at `column`.
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rsplit_once('\n') {
+        Some((_, last_line)) => last_line.chars().count(),
+        None => column + text.chars().count(),
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match &pattern.kind {
+        PatternKind::Wildcard => "_".to_owned(),
+        PatternKind::Literal { text, negated, .. } => {
+            let sign = if *negated { "-" } else { "" };
+            format!("{sign}{text}")
+        }
+        PatternKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let dots = if *inclusive { "..=" } else { ".." };
+            format!("{}{dots}{}", self::pattern(start), self::pattern(end))
+        }
+        PatternKind::Or(alternatives) => alternatives
+            .iter()
+            .map(self::pattern)
+            .collect::<Vec<_>>()
+            .join(" | "),
+    }
+}
+
+struct Formatter<'src> {
+    source: &'src str,
+
+    /// The spans of every comment and doc comment in the source, in order.
+    comments: Vec<Span>,
+
+    /// The first comment that hasn't been written yet.
+    next_comment: usize,
+
+    out: String,
+    indent: usize,
+
+    /// Where in the source the last thing written ends, to tell if a blank line follows it.
+    last_end: usize,
+
+    /// Whether nothing has been written in the innermost block yet, so it doesn't start with a
+    /// blank line.
+    block_start: bool,
+}
+
+impl<'src> Formatter<'src> {
+    /// The text of a comment, without any trailing whitespace.
+    fn text(&self, comment: Span) -> &'src str {
+        self.source[comment.start..comment.end].trim_end()
+    }
+
+    /// The column the output is at.
+    fn column(&self) -> usize {
+        end_column(0, &self.out)
+    }
+
+    fn indent_line(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Start a line for something starting at `start` in the source, after a blank line if
+    /// there's one before it there.
+    fn start_line(&mut self, start: usize) {
+        let gap = self.source.get(self.last_end..start).unwrap_or_default();
+        if !self.block_start && gap.matches('\n').count() > 1 {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+        self.indent_line();
+    }
+
+    /// End the line of something ending at `end` in the source, with the comment after it if
+    /// that's on the same line.
+    fn end_line(&mut self, end: usize) {
+        self.last_end = end;
+        let trailing = self
+            .comments
+            .get(self.next_comment)
+            .copied()
+            .filter(|comment| {
+                comment.start >= end && !self.source[end..comment.start].contains('\n')
+            });
+        if let Some(comment) = trailing {
+            let text = self.text(comment);
+            self.out.push(' ');
+            self.out.push_str(text);
+            self.next_comment += 1;
+            self.last_end = comment.end;
+        }
+        self.out.push('\n');
+    }
+
+    /// Write the comments before `start` in the source on their own lines.
+    fn comments_before(&mut self, start: usize) {
+        while let Some(comment) = self
+            .comments
+            .get(self.next_comment)
+            .copied()
+            .filter(|comment| comment.start < start)
+        {
+            self.next_comment += 1;
+            self.start_line(comment.start);
+            let text = self.text(comment);
+            self.out.push_str(text);
+            self.out.push('\n');
+            self.last_end = comment.end;
+        }
+    }
+
+    /// A bracketed list starting at `column`, on one line if it fits with `after` more columns
+    /// after it, or with one element per line otherwise.
+    #[allow(clippy::too_many_arguments)]
+    fn list<T>(
+        &self,
+        (open, close): (&str, &str),
+        elements: &[T],
+        trailing_comma: bool,
+        indent: usize,
+        column: usize,
+        after: usize,
+        element: impl Fn(&Self, &T, usize, usize) -> String,
+    ) -> String {
+        let mut line = open.to_owned();
+        for (i, e) in elements.iter().enumerate() {
+            if i > 0 {
+                line.push_str(", ");
+            }
+            line.push_str(&element(self, e, indent, end_column(column, &line)));
+        }
+        line.push_str(close);
+        let fits = !line.contains('\n') && end_column(column, &line) + after <= MAX_WIDTH;
+        if elements.is_empty() || fits {
+            return line;
+        }
+
+        let inner = indent + 1;
+        let mut lines = format!("{open}\n");
+        for (i, e) in elements.iter().enumerate() {
+            lines.push_str(&INDENT.repeat(inner));
+            lines.push_str(&element(self, e, inner, inner * INDENT.len()));
+            if trailing_comma || i + 1 < elements.len() {
+                lines.push(',');
+            }
+            lines.push('\n');
+        }
+        lines.push_str(&INDENT.repeat(indent));
+        lines.push_str(close);
+        lines
+    }
+
+    /// An expression starting at `column`, with any lines after its first indented by
+    /// `indent` levels.
+    fn expr(&self, expr: &Expression, indent: usize, column: usize) -> String {
+        match &expr.kind {
+            ExpressionKind::Literal { text, .. } => text.clone(),
+            ExpressionKind::Ident(ident) => ident.name.clone(),
+            ExpressionKind::Path(path) => path.to_string(),
+            ExpressionKind::Call { callee, arguments } => {
+                let callee = self.expr(callee, indent, column);
+                let column = end_column(column, &callee);
+                let arguments =
+                    self.list(("(", ")"), arguments, false, indent, column, 1, Self::expr);
+                callee + &arguments
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                format!("{operator}{}", self.expr(operand, indent, column + 1))
+            }
+            ExpressionKind::Binary {
+                lhs, operator, rhs, ..
+            } => {
+                let lhs = format!("{} {operator} ", self.expr(lhs, indent, column));
+                let rhs = self.expr(rhs, indent, end_column(column, &lhs));
+                lhs + &rhs
+            }
+            ExpressionKind::Grouping(inner) => {
+                format!("({})", self.expr(inner, indent, column + 1))
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                let mut text = format!("match {} {{", self.expr(scrutinee, indent, column + 6));
+                if arms.is_empty() {
+                    text.push('}');
+                    return text;
+                }
+
+                text.push('\n');
+                for arm in arms {
+                    let start =
+                        format!("{}{} => ", INDENT.repeat(indent + 1), pattern(&arm.pattern));
+                    let body = self.expr(&arm.body, indent + 1, end_column(0, &start));
+                    text.push_str(&start);
+                    text.push_str(&body);
+                    text.push_str(",\n");
+                }
+                text.push_str(&INDENT.repeat(indent));
+                text.push('}');
+                text
+            }
+            ExpressionKind::Array(elements) => {
+                self.list(("[", "]"), elements, true, indent, column, 1, Self::expr)
+            }
+            ExpressionKind::Map(entries) if entries.is_empty() => "[:]".to_owned(),
+            ExpressionKind::Map(entries) => {
+                self.list(("[", "]"), entries, true, indent, column, 1, Self::entry)
+            }
+            ExpressionKind::Index { array, index } => {
+                let array = self.expr(array, indent, column);
+                let index = self.expr(index, indent, end_column(column, &array) + 1);
+                format!("{array}[{index}]")
+            }
+            ExpressionKind::Cast { value, ty } => {
+                format!("{} as {}", self.expr(value, indent, column), ty.kind)
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = self.expr(start, indent, column);
+                let operator = if *inclusive { "..=" } else { ".." };
+                let end_start = end_column(column, &start) + operator.len();
+                format!("{start}{operator}{}", self.expr(end, indent, end_start))
+            }
+        }
+    }
+
+    /// An entry of a map literal starting at `column`.
+    fn entry(
+        &self,
+        (key, value): &(Expression, Expression),
+        indent: usize,
+        column: usize,
+    ) -> String {
+        let key = self.expr(key, indent, column);
+        let value = self.expr(value, indent, end_column(column, &key) + 2);
+        format!("{key}: {value}")
+    }
+
+    /// Write an expression at the end of the output, between two strings.
+    fn push_expr(&mut self, before: &str, expr: &Expression, after: &str) {
+        self.out.push_str(before);
+        let text = self.expr(expr, self.indent, self.column());
+        self.out.push_str(&text);
+        self.out.push_str(after);
+    }
+
+    /// Write a braced body whose braces are at `open` and `close` in the source, or `{}` if
+    /// it's empty and has no comments. A comment after the opening brace stays after it.
+    fn braced(&mut self, open: usize, close: usize, empty: bool, body: impl FnOnce(&mut Self)) {
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.start < close);
+        if empty && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push('{');
+        self.end_line(open + 1);
+        self.block_start = true;
+        self.indent += 1;
+        body(self);
+        self.comments_before(close);
+        self.indent -= 1;
+        self.block_start = false;
+        self.indent_line();
+        self.out.push('}');
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.braced(
+            block.span.start,
+            block.span.end - 1,
+            block.statements.is_empty(),
+            |f| {
+                for statement in &block.statements {
+                    f.statement(statement);
+                }
+            },
+        );
+    }
+
+    /// Write a statement that ends with a semicolon rather than a block.
+    fn simple_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Let {
+                name,
+                ty,
+                initializer,
+            } => {
+                let ty = ty
+                    .as_ref()
+                    .map_or_else(String::new, |ty| format!(": {}", ty.kind));
+                let start = format!("let {}{ty} = ", name.name);
+                self.push_expr(&start, initializer, ";");
+            }
+            StatementKind::Ret(None) => self.out.push_str("ret;"),
+            StatementKind::Ret(Some(value)) => self.push_expr("ret ", value, ";"),
+            // A match statement doesn't need a semicolon, but an operator after it would
+            // continue it without one.
+            StatementKind::Expression(expr) => self.push_expr("", expr, ";"),
+            _ => {
+                let source = self.source;
+                self.out
+                    .push_str(&source[statement.span.start..statement.span.end]);
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.comments_before(statement.span.start);
+        self.start_line(statement.span.start);
+
+        match &statement.kind {
+            StatementKind::If {
+                condition,
+                then_block,
+                elif_branches,
+                else_block,
+            } => {
+                self.push_expr("if ", condition, " ");
+                self.block(then_block);
+                for (condition, block) in elif_branches {
+                    self.push_expr(" elif ", condition, " ");
+                    self.block(block);
+                }
+                if let Some(block) = else_block {
+                    self.out.push_str(" else ");
+                    self.block(block);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.push_expr("while ", condition, " ");
+                self.block(body);
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.out.push_str("do ");
+                self.block(body);
+                self.push_expr(" while ", condition, ";");
+            }
+            StatementKind::For {
+                initializer,
+                condition,
+                step,
+                body,
+            } => {
+                self.out.push_str("for ");
+                self.simple_statement(initializer);
+                self.push_expr(" ", condition, "; ");
+                self.push_expr("", step, " ");
+                self.block(body);
+            }
+            StatementKind::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.push_expr(&format!("for {} in ", binding.name), iterable, " ");
+                self.block(body);
+            }
+            StatementKind::Block(block) => self.block(block),
+            StatementKind::Let { .. } | StatementKind::Ret(_) | StatementKind::Expression(_) => {
+                self.simple_statement(statement);
+            }
+        }
+
+        self.end_line(statement.span.end);
+    }
+
+    fn item(&mut self, item: &Item) {
+        self.comments_before(item.span.start);
+        self.start_line(item.span.start);
+        for attribute in &item.attributes {
+            self.out.push('@');
+            self.out.push_str(&attribute.name.name);
+            self.out.push('\n');
+            self.indent_line();
+        }
+        if item.visibility == Visibility::Public {
+            self.out.push_str("pub ");
+        }
+
+        match &item.kind {
+            ItemKind::Proc(proc) => {
+                self.out.push_str("proc ");
+                self.out.push_str(&proc.name.name);
+                let return_type = proc.return_type.kind.to_string();
+                let parameters = self.list(
+                    ("(", ")"),
+                    &proc.parameters,
+                    false,
+                    self.indent,
+                    self.column(),
+                    // The return type and the opening brace follow the parameters.
+                    return_type.len() + 3,
+                    |_, parameter, _, _| format!("{}: {}", parameter.name.name, parameter.ty.kind),
+                );
+                self.out.push_str(&parameters);
+                self.out.push(' ');
+                self.out.push_str(&return_type);
+                self.out.push(' ');
+                self.block(&proc.body);
+            }
+            ItemKind::Mod(module) => {
+                self.out.push_str("mod ");
+                self.out.push_str(&module.name.name);
+                self.out.push(' ');
+                self.braced(
+                    module.name.span.end,
+                    item.span.end - 1,
+                    module.items.is_empty(),
+                    |f| {
+                        for item in &module.items {
+                            f.item(item);
+                        }
+                    },
+                );
+            }
+            ItemKind::Const(constant) => {
+                let start = format!("const {}: {} = ", constant.name.name, constant.ty.kind);
+                self.push_expr(&start, &constant.value, ";");
+            }
+            ItemKind::Import(module) => {
+                self.out.push_str("import ");
+                self.out.push_str(&module.name);
+                self.out.push(';');
+            }
+        }
+
+        self.end_line(item.span.end);
+    }
+}
+
+/// Format a program parsed from some source. The tokens are the source's, lexed by
+/// [`lexer::lex_with_comments`] so the comments can be kept.
+pub fn format(source: &str, tokens: &[Token], program: &Program) -> String {
+    let comments = tokens
+        .iter()
+        .filter(|token| matches!(token.kind, TokenKind::Comment | TokenKind::DocComment))
+        .map(|token| token.span)
+        .collect();
+    let mut formatter = Formatter {
+        source,
+        comments,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        last_end: 0,
+        block_start: true,
+    };
+
+    for item in &program.items {
+        formatter.item(item);
+    }
+    formatter.comments_before(usize::MAX);
+    formatter.out
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::token::TokenKind;
+
+    fn format(source: &str) -> String {
+        let tokens = lexer::lex_with_comments(source).unwrap();
+        let code = tokens
+            .iter()
+            .copied()
+            .filter(|token| token.kind != TokenKind::Comment)
+            .collect();
+        let program = parser::parse(source, code).unwrap();
+        super::format(source, &tokens, &program)
+    }
+
+    #[test]
+    fn test_format_spacing() {
+        let source = "proc  main( )int{let x:[int]=[1,2 ,3];let m:[str:int]=[\"a\":1 ,\"b\" :2];\
+                      m=[ : ];for i in 0 ..=x[0]{}if x[0]<2{ret x[1]*(2+-3);}\
+                      elif true{}else{while false{}}for let i=0;i<3;i+=1{}ret match 1{0..=2=>1,_=>0};}";
+        assert_eq!(
+            format(source),
+            "proc main() int {
+    let x: [int] = [1, 2, 3];
+    let m: [str: int] = [\"a\": 1, \"b\": 2];
+    m = [:];
+    for i in 0..=x[0] {}
+    if x[0] < 2 {
+        ret x[1] * (2 + -3);
+    } elif true {} else {
+        while false {}
+    }
+    for let i = 0; i < 3; i += 1 {}
+    ret match 1 {
+        0..=2 => 1,
+        _ => 0,
+    };
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_comments_and_blank_lines() {
+        let source = "// header\n\n\n/// Docs.\n@must_use\npub proc f() int { // trailing\n\n    \
+                      let x = 1; /* a /* nested */ block */\n\n\n    // before\n    ret x;   // after\n    \
+                      // last\n}\n/* end\n   of file */\n";
+        assert_eq!(
+            format(source),
+            "// header
+
+/// Docs.
+@must_use
+pub proc f() int { // trailing
+    let x = 1; /* a /* nested */ block */
+
+    // before
+    ret x; // after
+    // last
+}
+/* end
+   of file */
+"
+        );
+    }
+
+    #[test]
+    fn test_format_wraps_long_lists() {
+        let source = "proc f(first_parameter: int, second_parameter: int, third_parameter: int, fourth_parameter: int) void {
+            call(first_argument_expression, second_argument_expression, third_argument_expression, [1, 2], fourth);
+        }";
+        assert_eq!(
+            format(source),
+            "proc f(
+    first_parameter: int,
+    second_parameter: int,
+    third_parameter: int,
+    fourth_parameter: int
+) void {
+    call(
+        first_argument_expression,
+        second_argument_expression,
+        third_argument_expression,
+        [1, 2],
+        fourth
+    );
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let source = "mod math { pub const MAX: int = 1; // max\n pub proc add(a: int, b: int) int { ret a + b; } }\n\
+                      import math; proc main() int { do { math::add(1, 2); } while false; ret 0; }";
+        let formatted = format(source);
+        assert_eq!(format(&formatted), formatted);
+    }
+}
@@ -0,0 +1,12 @@
+//! Lexes and parses arbitrary source code. However it's malformed, neither may panic, overflow
+//! the stack or loop forever, which `-timeout` catches.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    if let Ok(tokens) = lexer::lex(source) {
+        let _ = parser::parse(source, tokens);
+    }
+});
@@ -0,0 +1,117 @@
+//! Parses arbitrary sequences of tokens, including ones the lexer never makes, like operators
+//! it doesn't lex yet, ordinary comments, or an end of file with more tokens after it. Parsing
+//! them may fail, but not panic, overflow the stack or loop forever.
+
+#![no_main]
+
+use lexer::token::{
+    IdentKind::{Keyword, NonReserved},
+    IntegerBase::Decimal,
+    Keyword::*,
+    LiteralKind, Token,
+    TokenKind::{self, *},
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Every kind of token, with text it could be lexed from.
+const TOKENS: &[(TokenKind, &str)] = &[
+    (OpenParen, "("),
+    (ClosingParen, ")"),
+    (OpenCurly, "{"),
+    (ClosingCurly, "}"),
+    (OpenSquare, "["),
+    (ClosingSquare, "]"),
+    (Colon, ":"),
+    (ColonColon, "::"),
+    (Semicolon, ";"),
+    (Period, "."),
+    (PeriodPeriod, ".."),
+    (PeriodPeriodEqual, "..="),
+    (Comma, ","),
+    (At, "@"),
+    (Equal, "="),
+    (EqualEqual, "=="),
+    (FatArrow, "=>"),
+    (Plus, "+"),
+    (PlusEqual, "+="),
+    (Minus, "-"),
+    (MinusEqual, "-="),
+    (Star, "*"),
+    (StarEqual, "*="),
+    (Slash, "/"),
+    (SlashEqual, "/="),
+    (Percent, "%"),
+    (PercentEqual, "%="),
+    (Ampersand, "&"),
+    (AmpersandEqual, "&="),
+    (AmpAmp, "&&"),
+    (Bar, "|"),
+    (BarEqual, "|="),
+    (BarBar, "||"),
+    (Tilde, "~"),
+    (Bang, "!"),
+    (BangEqual, "!="),
+    (Lt, "<"),
+    (LtEqual, "<="),
+    (Gt, ">"),
+    (GtEqual, ">="),
+    (Shl, "<<"),
+    (ShlEqual, "<<="),
+    (Shr, ">>"),
+    (ShrEqual, ">>="),
+    (Ident(NonReserved), "x"),
+    (Ident(NonReserved), "_"),
+    (Ident(Keyword(Proc)), "proc"),
+    (Ident(Keyword(Let)), "let"),
+    (Ident(Keyword(Void)), "void"),
+    (Ident(Keyword(Int)), "int"),
+    (Ident(Keyword(Ret)), "ret"),
+    (Ident(Keyword(Float)), "float"),
+    (Ident(Keyword(If)), "if"),
+    (Ident(Keyword(Elif)), "elif"),
+    (Ident(Keyword(Else)), "else"),
+    (Ident(Keyword(For)), "for"),
+    (Ident(Keyword(While)), "while"),
+    (Ident(Keyword(Do)), "do"),
+    (Ident(Keyword(Bool)), "bool"),
+    (Ident(Keyword(Str)), "str"),
+    (Ident(Keyword(Match)), "match"),
+    (Ident(Keyword(Pub)), "pub"),
+    (Ident(Keyword(Mod)), "mod"),
+    (Ident(Keyword(Const)), "const"),
+    (Ident(Keyword(Import)), "import"),
+    (Ident(Keyword(In)), "in"),
+    (Ident(Keyword(Char)), "char"),
+    (Ident(Keyword(I8)), "i8"),
+    (Ident(Keyword(I16)), "i16"),
+    (Ident(Keyword(I32)), "i32"),
+    (Ident(Keyword(I64)), "i64"),
+    (Ident(Keyword(U8)), "u8"),
+    (Ident(Keyword(U16)), "u16"),
+    (Ident(Keyword(U32)), "u32"),
+    (Ident(Keyword(U64)), "u64"),
+    (Ident(Keyword(As)), "as"),
+    (Literal(LiteralKind::Character), "'c'"),
+    (Literal(LiteralKind::String), "\"text\""),
+    (Literal(LiteralKind::Integer { base: Decimal }), "1"),
+    (Literal(LiteralKind::Float), "1.5"),
+    (Literal(LiteralKind::Boolean), "true"),
+    (DocComment, "/// doc"),
+    (Comment, "// comment"),
+    (EoF, ""),
+];
+
+fuzz_target!(|choices: &[u8]| {
+    // Lay the tokens out in source text, so each spans text of its kind.
+    let mut source = String::new();
+    let mut tokens = Vec::new();
+    for &choice in choices {
+        let (kind, text) = TOKENS[usize::from(choice) % TOKENS.len()];
+        let start = source.len();
+        source.push_str(text);
+        tokens.push(Token::new(kind, (start..source.len()).into(), text));
+        source.push(' ');
+    }
+
+    let _ = parser::parse(&source, tokens);
+});
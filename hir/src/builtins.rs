@@ -0,0 +1,432 @@
+//! Procedures built into the language, which can be called from every module without being
+//! declared, or from modules importing the standard library module they're in.
+
+use crate::{stdlib::StdModule, ty::Ty};
+
+/// What a builtin accepts as an argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinParam {
+    /// A value of one type.
+    Ty(Ty),
+
+    /// A value of any type but `void`.
+    Any,
+
+    /// A `str`, an array or a map.
+    Sequence,
+
+    /// A `str` or a `char`.
+    StrOrChar,
+
+    /// An array of any type.
+    Array,
+
+    /// A value of the type of the elements of the array passed first.
+    Element,
+
+    /// A map of any types.
+    Map,
+
+    /// A value of the key type of the map passed first.
+    Key,
+
+    /// A value of the value type of the map passed first.
+    Value,
+}
+
+/// A builtin procedure. Procedures declared in the program shadow builtins with the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    /// `print(s: str) void` writes a string to standard output.
+    Print,
+
+    /// `println(s: str) void` writes a string and a newline to standard output.
+    Println,
+
+    /// `read_line() str` reads a line from standard input, without its line ending. At the end
+    /// of the input, it returns an empty string.
+    ReadLine,
+
+    /// `to_str(value) str` formats a value of any type but `void` as a string.
+    ToStr,
+
+    /// `len(s: str) int` is the length of a string in bytes, `len(array: [T]) int` is the
+    /// number of elements in an array, and `len(map: [K: V]) int` is the number of keys in a map.
+    Len,
+
+    /// `slice(s: str, start: int, end: int) str` is the part of a string from the byte at
+    /// `start` up to the byte at `end`, or an empty string if `end` is before `start`. Both must
+    /// be within the string and on char boundaries.
+    Slice,
+
+    /// `arg_count() int` is the number of arguments the program was run with. It and `arg` are
+    /// kept beside `args` for programs built with the native backends, which have no arrays.
+    ArgCount,
+
+    /// `arg(index: int) str` is the argument the program was run with at `index`, counting from
+    /// 0. It must be less than `arg_count()`.
+    Arg,
+
+    /// `args() [str]` is every argument the program was run with, in order.
+    Args,
+
+    /// `read_file(path: str) str` reads the whole of a UTF-8 file.
+    ReadFile,
+
+    /// `write_file(path: str, contents: str) void` writes a string to a file, replacing it if
+    /// it exists.
+    WriteFile,
+
+    /// `append_file(path: str, contents: str) void` writes a string to the end of a file,
+    /// creating it if it doesn't exist.
+    AppendFile,
+
+    /// `math::sqrt(x: float) float` is the square root of a float, or `nan` if it's negative.
+    Sqrt,
+
+    /// `math::abs(x: float) float` is the absolute value of a float.
+    Abs,
+
+    /// `math::pow(x: float, y: float) float` is `x` raised to the power of `y`.
+    Pow,
+
+    /// `math::floor(x: float) float` is the largest whole float less than or equal to `x`.
+    Floor,
+
+    /// `math::ceil(x: float) float` is the smallest whole float greater than or equal to `x`.
+    Ceil,
+
+    /// `math::min(x: float, y: float) float` is the smaller of two floats. If one of them is
+    /// `nan`, it's the other.
+    Min,
+
+    /// `math::max(x: float, y: float) float` is the larger of two floats. If one of them is
+    /// `nan`, it's the other.
+    Max,
+
+    /// `substr(s: str, start: int, count: int) str` is `slice(s, start, start + count)`, the
+    /// `count` bytes of a string from the byte at `start`.
+    Substr,
+
+    /// `char_at(s: str, index: int) char` is the char starting at the byte at `index`, which
+    /// must be within the string and on a char boundary.
+    CharAt,
+
+    /// `contains(s: str, pattern: str) bool` returns if a string contains another.
+    Contains,
+
+    /// `split_count(s: str, separator: str) int` is the number of parts splitting a string at
+    /// each separator gives, which is one more than the number of separators in it. An empty
+    /// separator doesn't split the string.
+    SplitCount,
+
+    /// `split(s: str, separator: str, index: int) str` is the part of a string at `index` when
+    /// it's split at each separator, counting from 0. It must be less than
+    /// `split_count(s, separator)`.
+    Split,
+
+    /// `to_int(s: str) int` parses an int written in decimal, with an optional sign, like `42`
+    /// or `-7`, and `to_int(c: char) int` is the code point of a char.
+    ToInt,
+
+    /// `to_float(s: str) float` parses a float written like a float literal or an int, with an
+    /// optional `-`, or the `inf`, `-inf` and `nan` `to_str` gives.
+    ToFloat,
+
+    /// `push(array: [T], value: T) void` adds an element to the end of an array.
+    Push,
+
+    /// `pop(array: [T]) T` removes the last element of an array and returns it. The array
+    /// must not be empty.
+    Pop,
+
+    /// `assert(condition: bool, message: str) void` stops the program with an error giving
+    /// the message if a condition is false.
+    Assert,
+
+    /// `from_int(code: int) char` is the char with a code point, which must be at most
+    /// `0x10ffff` and not a surrogate.
+    FromInt,
+
+    /// `panic(message: str) void` stops the program with an error giving the message. The
+    /// statements after a call to it are never run, so it can end a procedure returning a value.
+    Panic,
+
+    /// `rand::seed(n: int) void` seeds the generator the other `rand` procedures use, so they
+    /// give the same numbers after every call with the same seed. Without a call, it's seeded
+    /// at random, or with 0 when the program's run with `--deterministic`.
+    Seed,
+
+    /// `rand::rand_int(lo: int, hi: int) int` is a pseudo-random int from `lo` up to and
+    /// including `hi`, which must be at least `lo`.
+    RandInt,
+
+    /// `rand::rand_float() float` is a pseudo-random float from 0 up to but not including 1.
+    RandFloat,
+
+    /// `insert(map: [K: V], key: K, value: V) void` sets the value of a key in a map, adding the
+    /// key if it's new, like `map[key] = value`.
+    Insert,
+
+    /// `get(map: [K: V], key: K, default: V) V` is the value of a key in a map, or `default` if
+    /// the map doesn't have the key. Indexing a map with `map[key]` fails instead.
+    Get,
+
+    /// `remove(map: [K: V], key: K) bool` removes a key and its value from a map, returning if
+    /// the map had the key.
+    Remove,
+
+    /// `has_key(map: [K: V], key: K) bool` returns if a map has a key.
+    HasKey,
+
+    /// `keys(map: [K: V]) [K]` is a new array of the keys of a map in ascending order, which is
+    /// the order `for key in map` visits them in. Strings are ordered by their bytes, chars by
+    /// their code points, and `false` comes before `true`.
+    Keys,
+}
+
+impl Builtin {
+    pub const ALL: [Self; 39] = [
+        Self::Print,
+        Self::Println,
+        Self::ReadLine,
+        Self::ToStr,
+        Self::Len,
+        Self::Slice,
+        Self::ArgCount,
+        Self::Arg,
+        Self::Args,
+        Self::ReadFile,
+        Self::WriteFile,
+        Self::AppendFile,
+        Self::Sqrt,
+        Self::Abs,
+        Self::Pow,
+        Self::Floor,
+        Self::Ceil,
+        Self::Min,
+        Self::Max,
+        Self::Substr,
+        Self::CharAt,
+        Self::Contains,
+        Self::SplitCount,
+        Self::Split,
+        Self::ToInt,
+        Self::ToFloat,
+        Self::Push,
+        Self::Pop,
+        Self::Assert,
+        Self::FromInt,
+        Self::Panic,
+        Self::Seed,
+        Self::RandInt,
+        Self::RandFloat,
+        Self::Insert,
+        Self::Get,
+        Self::Remove,
+        Self::HasKey,
+        Self::Keys,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Print => "print",
+            Self::Println => "println",
+            Self::ReadLine => "read_line",
+            Self::ToStr => "to_str",
+            Self::Len => "len",
+            Self::Slice => "slice",
+            Self::ArgCount => "arg_count",
+            Self::Arg => "arg",
+            Self::Args => "args",
+            Self::ReadFile => "read_file",
+            Self::WriteFile => "write_file",
+            Self::AppendFile => "append_file",
+            Self::Sqrt => "sqrt",
+            Self::Abs => "abs",
+            Self::Pow => "pow",
+            Self::Floor => "floor",
+            Self::Ceil => "ceil",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Substr => "substr",
+            Self::CharAt => "char_at",
+            Self::Contains => "contains",
+            Self::SplitCount => "split_count",
+            Self::Split => "split",
+            Self::ToInt => "to_int",
+            Self::ToFloat => "to_float",
+            Self::Push => "push",
+            Self::Pop => "pop",
+            Self::Assert => "assert",
+            Self::FromInt => "from_int",
+            Self::Panic => "panic",
+            Self::Seed => "seed",
+            Self::RandInt => "rand_int",
+            Self::RandFloat => "rand_float",
+            Self::Insert => "insert",
+            Self::Get => "get",
+            Self::Remove => "remove",
+            Self::HasKey => "has_key",
+            Self::Keys => "keys",
+        }
+    }
+
+    /// The standard library module the builtin is in, or `None` if it can be called from
+    /// everywhere.
+    pub fn module(self) -> Option<StdModule> {
+        match self {
+            Self::Sqrt
+            | Self::Abs
+            | Self::Pow
+            | Self::Floor
+            | Self::Ceil
+            | Self::Min
+            | Self::Max => Some(StdModule::Math),
+            Self::Seed | Self::RandInt | Self::RandFloat => Some(StdModule::Rand),
+            _ => None,
+        }
+    }
+
+    /// Find a builtin that can be called from everywhere by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|builtin| builtin.module().is_none() && builtin.name() == name)
+    }
+
+    /// What the builtin accepts as each of its arguments.
+    pub fn params(self) -> &'static [BuiltinParam] {
+        const STR: BuiltinParam = BuiltinParam::Ty(Ty::Str);
+        const INT: BuiltinParam = BuiltinParam::Ty(Ty::INT);
+        const FLOAT: BuiltinParam = BuiltinParam::Ty(Ty::Float);
+        const BOOL: BuiltinParam = BuiltinParam::Ty(Ty::Bool);
+
+        match self {
+            Self::Print | Self::Println | Self::ReadFile | Self::ToFloat | Self::Panic => &[STR],
+            Self::WriteFile | Self::AppendFile | Self::Contains | Self::SplitCount => &[STR, STR],
+            Self::ReadLine | Self::ArgCount | Self::Args | Self::RandFloat => &[],
+            Self::Arg | Self::FromInt | Self::Seed => &[INT],
+            Self::RandInt => &[INT, INT],
+            Self::ToStr => &[BuiltinParam::Any],
+            Self::Len => &[BuiltinParam::Sequence],
+            Self::ToInt => &[BuiltinParam::StrOrChar],
+            Self::Slice | Self::Substr => &[STR, INT, INT],
+            Self::CharAt => &[STR, INT],
+            Self::Split => &[STR, STR, INT],
+            Self::Sqrt | Self::Abs | Self::Floor | Self::Ceil => &[FLOAT],
+            Self::Pow | Self::Min | Self::Max => &[FLOAT, FLOAT],
+            Self::Push => &[BuiltinParam::Array, BuiltinParam::Element],
+            Self::Pop => &[BuiltinParam::Array],
+            Self::Assert => &[BOOL, STR],
+            Self::Insert | Self::Get => {
+                &[BuiltinParam::Map, BuiltinParam::Key, BuiltinParam::Value]
+            }
+            Self::Remove | Self::HasKey => &[BuiltinParam::Map, BuiltinParam::Key],
+            Self::Keys => &[BuiltinParam::Map],
+        }
+    }
+
+    /// The type of the builtin's result, or `None` if it depends on the type of the array or
+    /// map passed first, which [`Builtin::ret_ty_for`] gives.
+    pub fn ret_ty(self) -> Option<Ty> {
+        Some(match self {
+            Self::Print
+            | Self::Println
+            | Self::WriteFile
+            | Self::AppendFile
+            | Self::Push
+            | Self::Assert
+            | Self::Panic
+            | Self::Seed
+            | Self::Insert => Ty::Void,
+            Self::ReadLine
+            | Self::ToStr
+            | Self::Slice
+            | Self::Arg
+            | Self::ReadFile
+            | Self::Substr
+            | Self::Split => Ty::Str,
+            Self::Len | Self::ArgCount | Self::SplitCount | Self::ToInt | Self::RandInt => Ty::INT,
+            Self::CharAt | Self::FromInt => Ty::Char,
+            Self::Contains | Self::Remove | Self::HasKey => Ty::Bool,
+            Self::Sqrt
+            | Self::Abs
+            | Self::Pow
+            | Self::Floor
+            | Self::Ceil
+            | Self::Min
+            | Self::Max
+            | Self::ToFloat
+            | Self::RandFloat => Ty::Float,
+            Self::Args => Ty::array_of(Ty::Str),
+            Self::Pop | Self::Get | Self::Keys => return None,
+        })
+    }
+
+    /// The type of the builtin's result when the argument passed first has a type: the type of
+    /// the elements of an array from `pop`, of the values of a map from `get`, and an array of
+    /// its keys from `keys`.
+    pub fn ret_ty_for(self, first: Ty) -> Ty {
+        self.ret_ty().unwrap_or_else(|| match (self, first) {
+            (Self::Keys, Ty::Map(key, _)) => Ty::array_of(*key),
+            (Self::Pop | Self::Get, first) => first.indexed().map_or(Ty::Error, |(_, ty)| ty),
+            _ => Ty::Error,
+        })
+    }
+
+    /// Returns if calling the builtin does more than produce a value.
+    pub fn has_side_effects(self) -> bool {
+        matches!(
+            self,
+            Self::Print
+                | Self::Println
+                | Self::ReadLine
+                | Self::ReadFile
+                | Self::WriteFile
+                | Self::AppendFile
+                | Self::Push
+                | Self::Pop
+                | Self::Assert
+                | Self::Panic
+                | Self::Seed
+                | Self::RandInt
+                | Self::RandFloat
+                | Self::Insert
+                | Self::Remove
+        )
+    }
+
+    /// Returns if calling the builtin never finishes, always stopping the program.
+    pub fn diverges(self) -> bool {
+        self == Self::Panic
+    }
+
+    /// Returns if the builtin reaches outside the program's standard I/O and arguments, to its
+    /// files, so a sandboxed program can't call it.
+    pub fn accesses_host(self) -> bool {
+        matches!(self, Self::ReadFile | Self::WriteFile | Self::AppendFile)
+    }
+
+    /// Returns if calling the builtin can fail at runtime.
+    pub fn can_fail(self) -> bool {
+        matches!(
+            self,
+            Self::Slice
+                | Self::Arg
+                | Self::ReadFile
+                | Self::WriteFile
+                | Self::AppendFile
+                | Self::Substr
+                | Self::CharAt
+                | Self::Split
+                | Self::ToInt
+                | Self::ToFloat
+                | Self::Pop
+                | Self::Assert
+                | Self::FromInt
+                | Self::Panic
+                | Self::RandInt
+        )
+    }
+}
@@ -0,0 +1,521 @@
+//! Compile-time evaluation of expressions.
+//!
+//! Evaluation is deterministic and independent of the host: integer overflow follows the
+//! program's [`Overflow`] mode at the width of each int type, division by zero and out of range
+//! shifts are errors rather than panicking, and floats follow IEEE 754 exactly.
+
+use crate::{
+    exhaustiveness::literal_value,
+    float,
+    nodes::{BinOp, Const, ConstId, Expr, ExprKind, Literal, LogicalOp, Pat, PatKind, UnOp},
+    overflow::Overflow,
+    ty::{IntTy, Ty},
+};
+use miette::Diagnostic;
+use span::Span;
+use thiserror::Error;
+
+/// Diagnostics that can happen while evaluating an expression at compile time.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum ConstEvalError {
+    #[diagnostic(
+        code(hir::const_eval::non_const),
+        help("constants can only use literals, operators, matches and other constants")
+    )]
+    #[error("{0} cannot be evaluated at compile time")]
+    NonConst(&'static str, #[label("not allowed in a constant")] Span),
+
+    #[diagnostic(code(hir::const_eval::overflow))]
+    #[error("Arithmetic overflow in constant expression")]
+    Overflow(#[label("this overflows its int type")] Span),
+
+    #[diagnostic(code(hir::const_eval::division_by_zero))]
+    #[error("Division by zero in constant expression")]
+    DivisionByZero(#[label("the divisor is zero")] Span),
+
+    #[diagnostic(
+        code(hir::const_eval::invalid_shift),
+        help("shift amounts must be at least 0 and less than the width of the type")
+    )]
+    #[error("Shift by {0} in constant expression")]
+    InvalidShift(i128, #[label("shift amount out of range")] Span),
+
+    #[diagnostic(code(hir::const_eval::cycle))]
+    #[error("Constant `{0}` depends on itself")]
+    Cycle(String, #[label("used while evaluating its own value")] Span),
+}
+
+/// Why evaluation failed.
+#[derive(Debug, Clone)]
+pub enum EvalFailure {
+    /// The expression can't be evaluated at compile time.
+    Error(ConstEvalError),
+
+    /// The expression, or a constant it uses, has already been reported as erroneous.
+    Poisoned,
+}
+
+impl From<ConstEvalError> for EvalFailure {
+    fn from(error: ConstEvalError) -> Self {
+        Self::Error(error)
+    }
+}
+
+type EvalResult = Result<Literal, EvalFailure>;
+
+/// Evaluates expressions, looking up the values of constants through a callback so constants
+/// can be evaluated on demand.
+struct Evaluator<'a> {
+    overflow: Overflow,
+    constant: &'a mut dyn FnMut(ConstId, Span) -> Option<Literal>,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> EvalResult {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(literal.clone()),
+            ExprKind::Const(id) => (self.constant)(*id, span).ok_or(EvalFailure::Poisoned),
+            ExprKind::Local(_) => Err(ConstEvalError::NonConst("Variables", span).into()),
+            ExprKind::Call { .. } | ExprKind::Builtin { .. } | ExprKind::Native { .. } => {
+                Err(ConstEvalError::NonConst("Procedure calls", span).into())
+            }
+            ExprKind::Assign { .. } | ExprKind::IndexAssign { .. } => {
+                Err(ConstEvalError::NonConst("Assignments", span).into())
+            }
+            ExprKind::Map(_) => Err(ConstEvalError::NonConst("Maps", span).into()),
+            ExprKind::Index { array, .. } if matches!(array.ty, Ty::Map(..)) => {
+                Err(ConstEvalError::NonConst("Maps", span).into())
+            }
+            ExprKind::Array(_) | ExprKind::Index { .. } => {
+                Err(ConstEvalError::NonConst("Arrays", span).into())
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand = self.eval(operand)?;
+                eval_unary(*op, operand, self.overflow, span)
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                eval_binary(*op, lhs, rhs, self.overflow, span)
+            }
+            ExprKind::Logical { lhs, op, rhs } => {
+                let Literal::Bool(lhs) = self.eval(lhs)? else {
+                    return Err(EvalFailure::Poisoned);
+                };
+
+                match (op, lhs) {
+                    (LogicalOp::And, false) => Ok(Literal::Bool(false)),
+                    (LogicalOp::Or, true) => Ok(Literal::Bool(true)),
+                    _ => self.eval(rhs),
+                }
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let value = self.eval(scrutinee)?;
+
+                // Non-exhaustive matches have already been reported.
+                let arm = arms
+                    .iter()
+                    .find(|arm| pattern_matches(&arm.pat, &value))
+                    .ok_or(EvalFailure::Poisoned)?;
+                self.eval(&arm.body)
+            }
+            ExprKind::Error => Err(EvalFailure::Poisoned),
+        }
+    }
+}
+
+fn pattern_matches(pat: &Pat, value: &Literal) -> bool {
+    match &pat.kind {
+        PatKind::Wildcard => true,
+        PatKind::Literal(literal) => literal == value,
+        PatKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let (Some(start), Some(end), Some(value)) = (
+                literal_value(start),
+                literal_value(end),
+                literal_value(value),
+            ) else {
+                return false;
+            };
+
+            start <= value && (value < end || (*inclusive && value == end))
+        }
+        PatKind::Or(alternatives) => alternatives.iter().any(|alt| pattern_matches(alt, value)),
+        PatKind::Error => false,
+    }
+}
+
+/// Apply a unary operator to a literal, reporting errors at `span`.
+pub fn eval_unary(
+    op: UnOp,
+    operand: Literal,
+    overflow: Overflow,
+    span: Span,
+) -> Result<Literal, EvalFailure> {
+    match (op, operand) {
+        (UnOp::Neg, Literal::Int(value, ty)) => overflow
+            .neg(ty, value)
+            .map(|value| Literal::Int(value, ty))
+            .ok_or_else(|| ConstEvalError::Overflow(span).into()),
+        (UnOp::Neg, Literal::Float(value)) => Ok(Literal::Float(-value)),
+        (UnOp::Not, Literal::Bool(value)) => Ok(Literal::Bool(!value)),
+        (UnOp::BitNot, Literal::Int(value, ty)) => Ok(Literal::Int(ty.not(value), ty)),
+        (UnOp::Cast(to), Literal::Int(value, ty)) => overflow
+            .fit(to, ty.value(value))
+            .map(|value| Literal::Int(value, to))
+            .ok_or_else(|| ConstEvalError::Overflow(span).into()),
+        _ => Err(EvalFailure::Poisoned),
+    }
+}
+
+fn eval_int_binary(
+    op: BinOp,
+    ty: IntTy,
+    lhs: i64,
+    rhs: i64,
+    overflow: Overflow,
+    span: Span,
+) -> EvalResult {
+    use BinOp::*;
+
+    if matches!(op, Div | Rem) && rhs == 0 {
+        return Err(ConstEvalError::DivisionByZero(span).into());
+    }
+
+    let value = match op {
+        Add | Sub | Mul | Div | Rem => overflow.arithmetic(op, ty, lhs, rhs),
+        BitAnd | BitOr | Shl | Shr => match ty.bitwise(op, lhs, rhs) {
+            Some(value) => Some(value),
+            None => return Err(ConstEvalError::InvalidShift(ty.value(rhs), span).into()),
+        },
+        _ => return compare(op, &ty.value(lhs), &ty.value(rhs)),
+    };
+
+    value
+        .map(|value| Literal::Int(value, ty))
+        .ok_or_else(|| ConstEvalError::Overflow(span).into())
+}
+
+fn compare<T: PartialOrd + ?Sized>(op: BinOp, lhs: &T, rhs: &T) -> Result<Literal, EvalFailure> {
+    use BinOp::*;
+
+    Ok(Literal::Bool(match op {
+        Eq => lhs == rhs,
+        Ne => lhs != rhs,
+        Lt => lhs < rhs,
+        Le => lhs <= rhs,
+        Gt => lhs > rhs,
+        Ge => lhs >= rhs,
+        _ => return Err(EvalFailure::Poisoned),
+    }))
+}
+
+/// Join strings, or compare them by their bytes, like they're compared at runtime.
+fn eval_str_binary(
+    op: BinOp,
+    lhs: intern::Symbol,
+    rhs: intern::Symbol,
+) -> Result<Literal, EvalFailure> {
+    if op == BinOp::Add {
+        return Ok(Literal::Str(intern::Symbol::intern(&format!("{lhs}{rhs}"))));
+    }
+    compare(op, lhs.as_str().as_bytes(), rhs.as_str().as_bytes())
+}
+
+/// Apply a binary operator to literals, reporting errors at `span`.
+pub fn eval_binary(
+    op: BinOp,
+    lhs: Literal,
+    rhs: Literal,
+    overflow: Overflow,
+    span: Span,
+) -> Result<Literal, EvalFailure> {
+    match (lhs, rhs) {
+        (Literal::Int(lhs, ty), Literal::Int(rhs, _)) => {
+            eval_int_binary(op, ty, lhs, rhs, overflow, span)
+        }
+        (Literal::Float(lhs), Literal::Float(rhs)) => {
+            float::binary(op, lhs, rhs).ok_or(EvalFailure::Poisoned)
+        }
+        (Literal::Str(lhs), Literal::Str(rhs)) => eval_str_binary(op, lhs, rhs),
+        (Literal::Char(lhs), Literal::Char(rhs)) => compare(op, &lhs, &rhs),
+        (lhs, rhs) if lhs.ty() == rhs.ty() => match op {
+            BinOp::Eq => Ok(Literal::Bool(lhs == rhs)),
+            BinOp::Ne => Ok(Literal::Bool(lhs != rhs)),
+            _ => Err(EvalFailure::Poisoned),
+        },
+        _ => Err(EvalFailure::Poisoned),
+    }
+}
+
+/// Evaluate an expression at compile time, looking up the values of the constants it uses with
+/// a callback, which returns `None` for erroneous constants.
+pub fn eval(
+    expr: &Expr,
+    overflow: Overflow,
+    constant: &mut dyn FnMut(ConstId, Span) -> Option<Literal>,
+) -> Result<Literal, EvalFailure> {
+    Evaluator { overflow, constant }.eval(expr)
+}
+
+/// Replace every subexpression that can be evaluated at compile time with its value, given the
+/// program's constants.
+///
+/// Subexpressions that fail to evaluate, such as a division by zero, are left as they are so
+/// they fail at runtime instead.
+pub fn fold(expr: &mut Expr, consts: &[Const], overflow: Overflow) {
+    match &mut expr.kind {
+        ExprKind::Literal(_) | ExprKind::Local(_) | ExprKind::Error => return,
+        ExprKind::Const(_) => {}
+        ExprKind::Call { args, .. }
+        | ExprKind::Builtin { args, .. }
+        | ExprKind::Native { args, .. } => {
+            args.iter_mut().for_each(|arg| fold(arg, consts, overflow));
+            return;
+        }
+        ExprKind::Assign { value, .. } => {
+            fold(value, consts, overflow);
+            return;
+        }
+        ExprKind::Array(elements) => {
+            elements
+                .iter_mut()
+                .for_each(|element| fold(element, consts, overflow));
+            return;
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries {
+                fold(key, consts, overflow);
+                fold(value, consts, overflow);
+            }
+            return;
+        }
+        ExprKind::Index { array, index } => {
+            fold(array, consts, overflow);
+            fold(index, consts, overflow);
+            return;
+        }
+        ExprKind::IndexAssign {
+            array,
+            index,
+            value,
+            ..
+        } => {
+            fold(array, consts, overflow);
+            fold(index, consts, overflow);
+            fold(value, consts, overflow);
+            return;
+        }
+        ExprKind::Unary { operand, .. } => fold(operand, consts, overflow),
+        ExprKind::Binary { lhs, rhs, .. } | ExprKind::Logical { lhs, rhs, .. } => {
+            fold(lhs, consts, overflow);
+            fold(rhs, consts, overflow);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            fold(scrutinee, consts, overflow);
+            arms.iter_mut()
+                .for_each(|arm| fold(&mut arm.body, consts, overflow));
+        }
+    }
+
+    if let Ok(value) = eval(expr, overflow, &mut |id, _| {
+        consts.get(id.0 as usize).map(|c| c.value.clone())
+    }) {
+        expr.kind = ExprKind::Literal(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ty::Ty;
+
+    fn literal(literal: Literal) -> Expr {
+        Expr {
+            ty: literal.ty(),
+            kind: ExprKind::Literal(literal),
+            span: Span::from(0..0),
+        }
+    }
+
+    fn binary(lhs: Expr, op: BinOp, rhs: Expr) -> Expr {
+        Expr {
+            ty: lhs.ty,
+            kind: ExprKind::Binary {
+                lhs: Box::new(lhs),
+                op,
+                rhs: Box::new(rhs),
+            },
+            span: Span::from(0..0),
+        }
+    }
+
+    fn eval_without_consts(expr: &Expr) -> Result<Literal, EvalFailure> {
+        eval(expr, Overflow::Checked, &mut |_, _| None)
+    }
+
+    #[test]
+    fn test_eval_checks_int_arithmetic() {
+        let one = || literal(Literal::Int(1, IntTy::I64));
+        let max = || literal(Literal::Int(i64::MAX, IntTy::I64));
+        let zero = || literal(Literal::Int(0, IntTy::I64));
+
+        assert!(matches!(
+            eval_without_consts(&binary(
+                one(),
+                BinOp::Shl,
+                literal(Literal::Int(4, IntTy::I64))
+            )),
+            Ok(Literal::Int(16, IntTy::I64))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(max(), BinOp::Add, one())),
+            Err(EvalFailure::Error(ConstEvalError::Overflow(_)))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(one(), BinOp::Rem, zero())),
+            Err(EvalFailure::Error(ConstEvalError::DivisionByZero(_)))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(
+                one(),
+                BinOp::Shr,
+                literal(Literal::Int(64, IntTy::I64))
+            )),
+            Err(EvalFailure::Error(ConstEvalError::InvalidShift(64, _)))
+        ));
+
+        let wrapping = eval(
+            &binary(max(), BinOp::Add, one()),
+            Overflow::Wrapping,
+            &mut |_, _| None,
+        );
+        assert!(matches!(wrapping, Ok(Literal::Int(i64::MIN, IntTy::I64))));
+        let saturating = eval(
+            &binary(one(), BinOp::Div, zero()),
+            Overflow::Saturating,
+            &mut |_, _| None,
+        );
+        assert!(matches!(
+            saturating,
+            Err(EvalFailure::Error(ConstEvalError::DivisionByZero(_)))
+        ));
+    }
+
+    #[test]
+    fn test_eval_ints_at_their_width() {
+        let u8 = |value| literal(Literal::Int(value, IntTy::U8));
+        let cast = |operand: Expr, to| Expr {
+            ty: Ty::Int(to),
+            kind: ExprKind::Unary {
+                op: UnOp::Cast(to),
+                operand: Box::new(operand),
+            },
+            span: Span::from(0..0),
+        };
+
+        assert!(matches!(
+            eval_without_consts(&binary(u8(200), BinOp::Add, u8(100))),
+            Err(EvalFailure::Error(ConstEvalError::Overflow(_)))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(u8(200), BinOp::Gt, u8(100))),
+            Ok(Literal::Bool(true))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(u8(1), BinOp::Shl, u8(8))),
+            Err(EvalFailure::Error(ConstEvalError::InvalidShift(8, _)))
+        ));
+        assert!(matches!(
+            eval_without_consts(&cast(u8(200), IntTy::I8)),
+            Err(EvalFailure::Error(ConstEvalError::Overflow(_)))
+        ));
+        assert!(matches!(
+            eval(
+                &cast(u8(200), IntTy::I8),
+                Overflow::Wrapping,
+                &mut |_, _| None
+            ),
+            Ok(Literal::Int(-56, IntTy::I8))
+        ));
+        assert!(matches!(
+            eval_without_consts(&cast(literal(Literal::Int(-1, IntTy::I64)), IntTy::U64)),
+            Err(EvalFailure::Error(ConstEvalError::Overflow(_)))
+        ));
+    }
+
+    #[test]
+    fn test_eval_joins_and_compares_strings() {
+        let str = |text| literal(Literal::Str(intern::Symbol::intern(text)));
+
+        let joined = eval_without_consts(&binary(str("ab"), BinOp::Add, str("ü")));
+        assert!(matches!(joined, Ok(Literal::Str(text)) if text.as_str() == "abü"));
+        // By bytes, so an uppercase letter is before every lowercase one, and a prefix before
+        // what it starts.
+        for (lhs, op, rhs, expected) in [
+            ("Z", BinOp::Lt, "a", true),
+            ("ab", BinOp::Lt, "abc", true),
+            ("b", BinOp::Ge, "abc", true),
+            ("é", BinOp::Gt, "z", true),
+            ("ab", BinOp::Ne, "ab", false),
+        ] {
+            assert!(matches!(
+                eval_without_consts(&binary(str(lhs), op, str(rhs))),
+                Ok(Literal::Bool(value)) if value == expected
+            ));
+        }
+        assert!(matches!(
+            eval_without_consts(&binary(str("a"), BinOp::Sub, str("a"))),
+            Err(EvalFailure::Poisoned)
+        ));
+    }
+
+    #[test]
+    fn test_eval_compares_chars_by_code_point() {
+        let char = |value| literal(Literal::Char(value));
+
+        assert!(matches!(
+            eval_without_consts(&binary(char('Z'), BinOp::Lt, char('a'))),
+            Ok(Literal::Bool(true))
+        ));
+        assert!(matches!(
+            eval_without_consts(&binary(char('é'), BinOp::Le, char('z'))),
+            Ok(Literal::Bool(false))
+        ));
+    }
+
+    #[test]
+    fn test_fold_leaves_runtime_values() {
+        let local = Expr {
+            kind: ExprKind::Local(crate::nodes::LocalId(0)),
+            ty: Ty::INT,
+            span: Span::from(0..0),
+        };
+
+        // `x + (2 * 3)` folds to `x + 6`.
+        let mut expr = binary(
+            local,
+            BinOp::Add,
+            binary(
+                literal(Literal::Int(2, IntTy::I64)),
+                BinOp::Mul,
+                literal(Literal::Int(3, IntTy::I64)),
+            ),
+        );
+        fold(&mut expr, &[], Overflow::Checked);
+
+        let ExprKind::Binary { lhs, rhs, .. } = &expr.kind else {
+            panic!("expected the addition to remain");
+        };
+        assert!(matches!(lhs.kind, ExprKind::Local(_)));
+        assert!(matches!(
+            rhs.kind,
+            ExprKind::Literal(Literal::Int(6, IntTy::I64))
+        ));
+    }
+}
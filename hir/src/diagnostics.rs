@@ -0,0 +1,312 @@
+use crate::{consteval::ConstEvalError, ty::Ty};
+use diagnostics::{Note, Stage, Suggestion};
+use miette::Diagnostic;
+use parser::ast::{BinaryOpKind, UnaryOpKind};
+use span::Span;
+use thiserror::Error;
+
+/// Diagnostics that can happen while resolving, type checking and lowering to HIR.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum LowerDiagnostic {
+    #[diagnostic(code(hir::unresolved_name))]
+    #[error("Cannot find `{0}` in this scope")]
+    UnresolvedName(String, #[label("not found in this scope")] Span),
+
+    #[diagnostic(code(hir::duplicate_proc))]
+    #[error("Procedure `{name}` is defined multiple times")]
+    DuplicateProc {
+        name: String,
+        #[label("redefined here")]
+        span: Span,
+        #[label("previous definition here")]
+        previous: Span,
+    },
+
+    #[diagnostic(code(hir::duplicate_const))]
+    #[error("Constant `{name}` is defined multiple times")]
+    DuplicateConst {
+        name: String,
+        #[label("redefined here")]
+        span: Span,
+        #[label("previous definition here")]
+        previous: Span,
+    },
+
+    #[diagnostic(code(hir::duplicate_module))]
+    #[error("Module `{name}` is defined multiple times")]
+    DuplicateModule {
+        name: String,
+        #[label("redefined here")]
+        span: Span,
+        #[label("previous definition here")]
+        previous: Span,
+    },
+
+    #[diagnostic(
+        code(hir::private_item),
+        help("mark the {kind} `pub` to use it outside its module")
+    )]
+    #[error("{kind} `{name}` is private", kind = capitalize(kind))]
+    PrivateItem {
+        kind: &'static str,
+        name: String,
+        #[label("private {kind}")]
+        span: Span,
+        #[label("`{name}` is defined here")]
+        definition: Span,
+    },
+
+    #[diagnostic(code(hir::duplicate_parameter))]
+    #[error("Parameter `{0}` is declared multiple times")]
+    DuplicateParameter(String, #[label("already declared")] Span),
+
+    #[diagnostic(code(hir::type_mismatch))]
+    #[error("Mismatched types. Expected `{expected}`, found `{found}`")]
+    TypeMismatch {
+        expected: Ty,
+        found: Ty,
+        #[label("expected `{expected}`, found `{found}`")]
+        span: Span,
+    },
+
+    #[diagnostic(code(hir::invalid_unary_operand))]
+    #[error("Cannot apply `{0}` to a value of type `{1}`")]
+    InvalidUnaryOperand(UnaryOpKind, Ty, #[label("this is `{1}`")] Span),
+
+    #[diagnostic(
+        code(hir::invalid_binary_operands),
+        help("both operands of a binary operator must have the same type")
+    )]
+    #[error("Cannot apply `{0}` to values of type `{1}` and `{2}`")]
+    InvalidBinaryOperands(BinaryOpKind, Ty, Ty, #[label("invalid operands")] Span),
+
+    #[diagnostic(
+        code(hir::invalid_assignment_target),
+        help("only variables and array elements can be assigned to")
+    )]
+    #[error("Invalid left-hand side of assignment")]
+    InvalidAssignmentTarget(#[label("cannot assign to this")] Span),
+
+    #[diagnostic(code(hir::assignment_in_condition), help("use `==` to compare values"))]
+    #[error("Assignment used as a condition")]
+    AssignmentInCondition(#[label("this assigns rather than compares")] Span),
+
+    #[diagnostic(code(hir::not_callable))]
+    #[error("Expression is not callable")]
+    NotCallable(#[label("this is not a procedure")] Span),
+
+    #[diagnostic(code(hir::proc_as_value), help("call the procedure with `{0}(...)`"))]
+    #[error("Procedure `{0}` cannot be used as a value")]
+    ProcAsValue(String, #[label("procedure used as a value")] Span),
+
+    #[diagnostic(code(hir::argument_count_mismatch))]
+    #[error("Procedure `{name}` takes {expected} argument(s) but {found} were supplied")]
+    ArgumentCountMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        #[label("expected {expected} argument(s)")]
+        span: Span,
+
+        /// Where the procedure is defined, unless it's a builtin or native.
+        definition: Option<Span>,
+    },
+
+    #[diagnostic(
+        code(hir::sandboxed),
+        help(
+            "the program is compiled in the sandbox, so it can't access files, the environment \
+             or the clock"
+        )
+    )]
+    #[error("`{0}` is unavailable in the sandbox")]
+    Sandboxed(&'static str, #[label("called here")] Span),
+
+    #[diagnostic(code(hir::void_argument))]
+    #[error("Procedure `{0}` cannot take a `void` argument")]
+    VoidArgument(String, #[label("this has type `void`")] Span),
+
+    #[diagnostic(code(hir::void_variable))]
+    #[error("Variable `{0}` cannot have type `void`")]
+    VoidVariable(String, #[label("declared here")] Span),
+
+    #[diagnostic(code(hir::void_element))]
+    #[error("Arrays and maps cannot hold `void` values")]
+    VoidElement(#[label("this is `void`")] Span),
+
+    #[diagnostic(code(hir::not_an_array))]
+    #[error("Expected an array, found `{0}`")]
+    NotAnArray(Ty, #[label("this is `{0}`")] Span),
+
+    #[diagnostic(code(hir::not_a_map))]
+    #[error("Expected a map, found `{0}`")]
+    NotAMap(Ty, #[label("this is `{0}`")] Span),
+
+    #[diagnostic(
+        code(hir::invalid_map_key),
+        help("map keys can be ints, bools, chars or strings")
+    )]
+    #[error("`{0}` cannot be the key of a map")]
+    InvalidMapKey(Ty, #[label("this is `{0}`")] Span),
+
+    #[diagnostic(code(hir::not_a_sequence))]
+    #[error("Procedure `{0}` takes a `str`, an array or a map, not `{1}`")]
+    NotASequence(String, Ty, #[label("this is `{1}`")] Span),
+
+    #[diagnostic(code(hir::not_str_or_char))]
+    #[error("Procedure `{0}` takes a `str` or a `char`, not `{1}`")]
+    NotStrOrChar(String, Ty, #[label("this is `{1}`")] Span),
+
+    #[diagnostic(code(hir::invalid_cast), help("`as` only converts between int types"))]
+    #[error("Cannot convert `{0}` to `{1}`")]
+    InvalidCast(Ty, Ty, #[label("invalid conversion")] Span),
+
+    #[diagnostic(
+        code(hir::unknown_element_type),
+        help("give the variable a type, like `let xs: [int] = [];`")
+    )]
+    #[error("Cannot infer the element type of an empty array")]
+    UnknownElementType(#[label("the type of this array is unknown")] Span),
+
+    #[diagnostic(
+        code(hir::unknown_map_type),
+        help("give the variable a type, like `let ages: [str: int] = [:];`")
+    )]
+    #[error("Cannot infer the key and value types of an empty map")]
+    UnknownMapType(#[label("the type of this map is unknown")] Span),
+
+    #[diagnostic(
+        code(hir::missing_return),
+        help("add a `ret` statement at the end of the procedure")
+    )]
+    #[error("Procedure `{0}` does not return a value on every path")]
+    MissingReturn(String, #[label("expected to return `{2}`")] Span, Ty),
+
+    #[diagnostic(code(hir::integer_literal_out_of_range))]
+    #[error("Integer literal is out of range for `{0}`")]
+    IntegerLiteralOutOfRange(Ty, #[label("does not fit in a `{0}`")] Span),
+
+    #[diagnostic(code(hir::float_literal_out_of_range))]
+    #[error("Float literal is too large")]
+    FloatLiteralOutOfRange(#[label("rounds to infinity")] Span),
+
+    #[diagnostic(code(hir::unknown_escape_sequence))]
+    #[error("Unknown escape sequence `\\{0}`")]
+    UnknownEscapeSequence(char, #[label("in this literal")] Span),
+
+    #[diagnostic(code(hir::invalid_range_pattern))]
+    #[error("Range patterns can only match `int` and `char` values, not `{0}`")]
+    InvalidRangePattern(Ty, #[label("range pattern")] Span),
+
+    #[diagnostic(code(hir::empty_range_pattern))]
+    #[error("Range pattern is empty")]
+    EmptyRangePattern(#[label("the start of this range is past its end")] Span),
+
+    #[diagnostic(code(hir::invalid_range))]
+    #[error("Ranges can only be of ints, not `{0}`")]
+    InvalidRange(Ty, #[label("the start of this range")] Span),
+
+    #[diagnostic(
+        code(hir::non_exhaustive_match),
+        help("add arms for the missing values, or a wildcard arm `_ => ...`")
+    )]
+    #[error("Match is not exhaustive; {witnesses} not covered")]
+    NonExhaustiveMatch {
+        witnesses: String,
+        #[label("{witnesses} not covered")]
+        span: Span,
+    },
+
+    #[diagnostic(
+        code(hir::unreachable_arm),
+        severity(Warning),
+        help("every value this arm matches is already matched by an earlier arm")
+    )]
+    #[error("Unreachable match arm")]
+    UnreachableArm(#[label("this arm is never taken")] Span),
+
+    #[diagnostic(
+        code(hir::unknown_attribute),
+        help("the attributes are `@must_use`, `@test` and `@bench`")
+    )]
+    #[error("Unknown attribute `@{0}`")]
+    UnknownAttribute(String, #[label("unknown attribute")] Span),
+
+    #[diagnostic(
+        code(hir::invalid_test_or_bench),
+        help("they run on their own, so nothing passes them arguments or uses their result")
+    )]
+    #[error("`@{0}` procedure `{1}` must take no parameters and return `void`")]
+    InvalidTestOrBench(&'static str, String, #[label("declared here")] Span),
+
+    #[diagnostic(code(hir::misplaced_attribute))]
+    #[error("Attribute `@{0}` cannot be applied to a module")]
+    MisplacedAttribute(String, #[label("not allowed here")] Span),
+
+    #[diagnostic(
+        code(hir::unknown_std_module),
+        help("the standard library's only module is `math`")
+    )]
+    #[error("Cannot find module `{0}` in the standard library")]
+    UnknownStdModule(String, #[label("not in the standard library")] Span),
+
+    #[diagnostic(
+        code(hir::pub_import),
+        help("an import is usable in its module and the modules nested in it")
+    )]
+    #[error("Imports cannot be `pub`")]
+    PubImport(#[label("imported here")] Span),
+
+    #[diagnostic(
+        code(hir::no_effect),
+        severity(Warning),
+        help("use the value, or remove the statement")
+    )]
+    #[error("Expression statement has no effect")]
+    NoEffect(#[label("this value is computed and then discarded")] Span),
+
+    #[diagnostic(
+        code(hir::unused_must_use),
+        severity(Warning),
+        help("store the result with `let`, or remove the call")
+    )]
+    #[error("Unused result of `{0}`, which must be used")]
+    UnusedMustUse(String, #[label("the result is discarded")] Span),
+
+    #[diagnostic(transparent)]
+    #[error(transparent)]
+    ConstEval(#[from] ConstEvalError),
+}
+
+/// Capitalize the first letter of a word, for kinds of items at the start of a message.
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+impl Stage for LowerDiagnostic {
+    const NAME: &'static str = "lowering";
+    const FAILURE: &'static str = "hir::failure";
+
+    fn suggestion(&self) -> Option<Suggestion> {
+        match self {
+            Self::AssignmentInCondition(operator) => Some(Suggestion::new(*operator, "==")),
+            _ => None,
+        }
+    }
+
+    fn notes(&self) -> Vec<Note> {
+        match self {
+            Self::ArgumentCountMismatch {
+                name,
+                definition: Some(definition),
+                ..
+            } => vec![Note::new(format!("`{name}` is defined here")).at(*definition)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub type DiagnosticSink = diagnostics::DiagnosticSink<LowerDiagnostic>;
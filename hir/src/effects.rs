@@ -0,0 +1,33 @@
+use crate::nodes::{Expr, ExprKind};
+
+/// Returns if evaluating an expression might do more than produce a value. Calls are always
+/// assumed to have side effects, since procedures aren't analyzed for purity.
+pub fn has_side_effects(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Local(_) | ExprKind::Const(_) => false,
+        // Erroneous expressions are assumed to have side effects to avoid piling warnings onto
+        // errors.
+        ExprKind::Call { .. }
+        | ExprKind::Native { .. }
+        | ExprKind::Assign { .. }
+        | ExprKind::IndexAssign { .. }
+        | ExprKind::Error => true,
+        ExprKind::Array(elements) => elements.iter().any(has_side_effects),
+        ExprKind::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| has_side_effects(key) || has_side_effects(value)),
+        ExprKind::Builtin { builtin, args } => {
+            builtin.has_side_effects() || args.iter().any(has_side_effects)
+        }
+        ExprKind::Unary { operand, .. } => has_side_effects(operand),
+        ExprKind::Binary { lhs, rhs, .. }
+        | ExprKind::Logical { lhs, rhs, .. }
+        | ExprKind::Index {
+            array: lhs,
+            index: rhs,
+        } => has_side_effects(lhs) || has_side_effects(rhs),
+        ExprKind::Match { scrutinee, arms } => {
+            has_side_effects(scrutinee) || arms.iter().any(|arm| has_side_effects(&arm.body))
+        }
+    }
+}
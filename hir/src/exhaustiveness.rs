@@ -0,0 +1,299 @@
+use crate::{
+    nodes::{Literal, MatchArm, Pat, PatKind},
+    ty::Ty,
+};
+
+/// The outcome of checking the arms of a match.
+#[derive(Debug, Default)]
+pub struct MatchCheck {
+    /// The indices of arms whose patterns only match values already matched by earlier arms.
+    pub unreachable_arms: Vec<usize>,
+
+    /// Examples of values no arm matches, rendered as patterns. Empty if the match is
+    /// exhaustive.
+    pub witnesses: Vec<String>,
+}
+
+/// A set of values, stored as sorted, disjoint, non-adjacent inclusive ranges. Bools and chars
+/// are stored as their integer values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ValueSet {
+    ranges: Vec<(i128, i128)>,
+}
+
+impl ValueSet {
+    fn range(start: i128, end: i128) -> Self {
+        Self {
+            ranges: if start <= end {
+                vec![(start, end)]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Every value of a type, or `None` if the type's values can't be enumerated as ranges.
+    fn domain(ty: Ty) -> Option<Self> {
+        match ty {
+            Ty::Bool => Some(Self::range(0, 1)),
+            Ty::Int(ty) => Some(Self::range(ty.min(), ty.max())),
+            // Surrogates aren't valid chars.
+            Ty::Char => Some(Self {
+                ranges: vec![(0, 0xD7FF), (0xE000, 0x10FFFF)],
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        let mut all = self
+            .ranges
+            .iter()
+            .chain(&other.ranges)
+            .copied()
+            .collect::<Vec<_>>();
+        all.sort_unstable();
+
+        let mut ranges: Vec<(i128, i128)> = Vec::with_capacity(all.len());
+        for (start, end) in all {
+            match ranges.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => ranges.push((start, end)),
+            }
+        }
+
+        Self { ranges }
+    }
+
+    fn difference(&self, other: &Self) -> Self {
+        let mut ranges = Vec::new();
+
+        for &(start, end) in &self.ranges {
+            let mut start = start;
+            for &(other_start, other_end) in &other.ranges {
+                if other_end < start || other_start > end {
+                    continue;
+                }
+
+                if other_start > start {
+                    ranges.push((start, other_start - 1));
+                }
+
+                start = other_end + 1;
+                if start > end {
+                    break;
+                }
+            }
+
+            if start <= end {
+                ranges.push((start, end));
+            }
+        }
+
+        Self { ranges }
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        self.difference(&self.difference(other))
+    }
+
+    fn is_subset(&self, other: &Self) -> bool {
+        self.difference(other).is_empty()
+    }
+}
+
+/// The value of a bool, int or char literal as an integer.
+pub fn literal_value(literal: &Literal) -> Option<i128> {
+    match *literal {
+        Literal::Int(value, ty) => Some(ty.value(value)),
+        Literal::Bool(value) => Some(value.into()),
+        Literal::Char(value) => Some(u32::from(value).into()),
+        Literal::Float(_) | Literal::Str(_) => None,
+    }
+}
+
+/// The set of values in the domain a pattern matches.
+fn pattern_values(pat: &Pat, domain: &ValueSet) -> ValueSet {
+    match &pat.kind {
+        // Matches containing erroneous patterns aren't checked, so this is never reached in
+        // practice.
+        PatKind::Wildcard | PatKind::Error => domain.clone(),
+        PatKind::Literal(literal) => {
+            literal_value(literal).map_or_else(ValueSet::default, |v| ValueSet::range(v, v))
+        }
+        PatKind::Range {
+            start,
+            end,
+            inclusive,
+        } => match (literal_value(start), literal_value(end)) {
+            (Some(start), Some(end)) => {
+                let end = if *inclusive { end } else { end - 1 };
+                ValueSet::range(start, end).intersection(domain)
+            }
+            _ => ValueSet::default(),
+        },
+        PatKind::Or(alternatives) => alternatives
+            .iter()
+            .fold(ValueSet::default(), |values, alternative| {
+                values.union(&pattern_values(alternative, domain))
+            }),
+    }
+}
+
+/// Render a value of a type as it would be written in a pattern.
+fn format_value(ty: Ty, value: i128) -> String {
+    match ty {
+        Ty::Bool => (value != 0).to_string(),
+        Ty::Char => format!(
+            "{:?}",
+            char::from_u32(value as u32).expect("char domains exclude surrogates")
+        ),
+        _ => value.to_string(),
+    }
+}
+
+/// Render the values no arm matches as patterns, one per range.
+fn witnesses(ty: Ty, missing: &ValueSet) -> Vec<String> {
+    let mut witnesses = Vec::new();
+
+    for &(start, end) in &missing.ranges {
+        // Bool ranges read better as their individual values.
+        if start == end || ty == Ty::Bool {
+            witnesses.extend((start..=end).map(|value| format_value(ty, value)));
+        } else {
+            witnesses.push(format!(
+                "{}..={}",
+                format_value(ty, start),
+                format_value(ty, end)
+            ));
+        }
+    }
+
+    witnesses
+}
+
+/// Collect the literals a pattern matches, returning if it also contains a wildcard.
+fn collect_literals<'a>(pat: &'a Pat, literals: &mut Vec<&'a Literal>) -> bool {
+    match &pat.kind {
+        PatKind::Literal(literal) => {
+            literals.push(literal);
+            false
+        }
+        PatKind::Or(alternatives) => {
+            let mut wildcard = false;
+            for alternative in alternatives {
+                wildcard |= collect_literals(alternative, literals);
+            }
+            wildcard
+        }
+        PatKind::Wildcard | PatKind::Range { .. } | PatKind::Error => true,
+    }
+}
+
+/// Check the arms of a match on a type whose values can't be enumerated, such as `str`. Only a
+/// wildcard makes these exhaustive.
+fn check_open(arms: &[MatchArm]) -> MatchCheck {
+    let mut check = MatchCheck::default();
+    let mut seen = Vec::new();
+    let mut wildcard = false;
+
+    for (i, arm) in arms.iter().enumerate() {
+        let mut literals = Vec::new();
+        let has_wildcard = collect_literals(&arm.pat, &mut literals);
+
+        if wildcard || (!has_wildcard && literals.iter().all(|l| seen.contains(l))) {
+            check.unreachable_arms.push(i);
+        }
+
+        wildcard |= has_wildcard;
+        seen.extend(literals);
+    }
+
+    if !wildcard {
+        check.witnesses.push("_".to_owned());
+    }
+
+    check
+}
+
+/// Check that the arms of a match on a value of a type cover every value, and find the arms
+/// that can never be taken.
+pub fn check_match(ty: Ty, arms: &[MatchArm]) -> MatchCheck {
+    let Some(domain) = ValueSet::domain(ty) else {
+        return check_open(arms);
+    };
+
+    let mut check = MatchCheck::default();
+    let mut covered = ValueSet::default();
+
+    for (i, arm) in arms.iter().enumerate() {
+        let values = pattern_values(&arm.pat, &domain);
+        if values.is_subset(&covered) {
+            check.unreachable_arms.push(i);
+        }
+
+        covered = covered.union(&values);
+    }
+
+    check.witnesses = witnesses(ty, &domain.difference(&covered));
+    check
+}
+
+/// Describe the witnesses of a non-exhaustive match, listing at most three of them.
+pub fn describe_witnesses(witnesses: &[String]) -> String {
+    const SHOWN: usize = 3;
+
+    let shown = witnesses
+        .iter()
+        .take(SHOWN)
+        .map(|witness| format!("`{witness}`"))
+        .collect::<Vec<_>>();
+
+    match witnesses.len() {
+        0 => String::new(),
+        1 => shown[0].clone(),
+        n if n <= SHOWN => format!("{} and {}", shown[..n - 1].join(", "), shown[n - 1]),
+        n => format!("{} and {} more", shown.join(", "), n - SHOWN),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_set_operations() {
+        let a = ValueSet::range(0, 10);
+        let b = ValueSet::range(3, 5).union(&ValueSet::range(8, 8));
+
+        assert_eq!(a.difference(&b).ranges, vec![(0, 2), (6, 7), (9, 10)]);
+        assert_eq!(b.union(&ValueSet::range(6, 7)).ranges, vec![(3, 8)]);
+        assert_eq!(
+            a.intersection(&ValueSet::range(9, 20)).ranges,
+            vec![(9, 10)]
+        );
+        assert!(b.is_subset(&a));
+        assert!(!a.is_subset(&b));
+    }
+
+    #[test]
+    fn test_witnesses() {
+        let domain = ValueSet::domain(Ty::Char).unwrap();
+        let missing = domain.difference(&ValueSet::range(1, 0x10FFFF));
+        assert_eq!(witnesses(Ty::Char, &missing), vec!["'\\0'"]);
+
+        let missing = ValueSet::range(0, 1);
+        assert_eq!(witnesses(Ty::Bool, &missing), vec!["false", "true"]);
+
+        let missing = ValueSet::range(3, 3).union(&ValueSet::range(7, 9));
+        assert_eq!(witnesses(Ty::INT, &missing), vec!["3", "7..=9"]);
+
+        let witnesses = ["1", "2", "3", "4", "5"].map(String::from);
+        assert_eq!(describe_witnesses(&witnesses[..2]), "`1` and `2`");
+        assert_eq!(describe_witnesses(&witnesses), "`1`, `2`, `3` and 2 more");
+    }
+}
@@ -0,0 +1,615 @@
+//! Extended explanations of the diagnostics of resolving, type checking and evaluating
+//! constants, which `mtxc explain` prints.
+
+/// Every diagnostic's code and explanation. Short codes number them in this order, so new ones
+/// go at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "hir::unresolved_name",
+        r#"A name doesn't refer to any variable, parameter, procedure or constant in scope.
+
+Variables are only in scope after their `let` and until the end of their block, and items of
+other modules are named with their module's path:
+
+    proc main() void {
+        if true {
+            let x = 1;
+        }
+        println(to_str(x));
+    }
+
+Check the name's spelling, declare it where it's used, or write its path, like `math::sqrt`."#,
+    ),
+    (
+        "hir::duplicate_proc",
+        r#"Two procedures in the same module have the same name.
+
+Procedures and constants share a namespace, and there's no overloading by parameter types:
+
+    proc area(side: int) int { ret side * side; }
+    proc area(width: int, height: int) int { ret width * height; }
+
+Give each procedure its own name, or move one of them into another module."#,
+    ),
+    (
+        "hir::duplicate_const",
+        r#"Two constants in the same module have the same name, or a constant has the name of a
+procedure.
+
+    const LIMIT: int = 10;
+    const LIMIT: int = 20;
+
+Remove one of them, or rename it."#,
+    ),
+    (
+        "hir::duplicate_module",
+        r#"Two modules in the same module have the same name.
+
+A module's items have to be in a single `mod` block:
+
+    mod shapes { pub proc square(x: int) int { ret x * x; } }
+    mod shapes { pub proc cube(x: int) int { ret x * x * x; } }
+
+Merge the blocks into one."#,
+    ),
+    (
+        "hir::private_item",
+        r#"An item is used outside its module, but isn't `pub`.
+
+Items are private to the module they're declared in, and the modules nested in it:
+
+    mod shapes {
+        proc square(x: int) int { ret x * x; }
+    }
+
+    proc main() int {
+        ret shapes::square(3);
+    }
+
+Mark the item `pub` to use it from anywhere, like `pub proc square`."#,
+    ),
+    (
+        "hir::duplicate_parameter",
+        r#"A procedure has two parameters with the same name.
+
+    proc add(x: int, x: int) int {
+        ret x + x;
+    }
+
+Rename one of them, like `proc add(x: int, y: int)`."#,
+    ),
+    (
+        "hir::type_mismatch",
+        r#"A value has a different type than where it's used needs.
+
+There are no implicit conversions, not even from `int` to `float` or from numbers to `str`:
+
+    proc main() void {
+        println(42);
+    }
+
+Convert the value explicitly, like `println(to_str(42))`, or `to_int` and `to_float` to parse
+a string."#,
+    ),
+    (
+        "hir::invalid_unary_operand",
+        r#"A unary operator is applied to a type it doesn't work on.
+
+`-` negates signed ints and `float` values, and `!` negates `bool` values:
+
+    let x = !5;
+    let y = -true;
+
+Use the operator for the value's type, like `-5` or `!true`. Unsigned ints like `u8` can't
+be negated."#,
+    ),
+    (
+        "hir::invalid_binary_operands",
+        r#"A binary operator is applied to types it doesn't work on.
+
+Both operands of an operator must have the same type, and the operator must work on it. For
+example, `+` adds numbers and concatenates strings, but doesn't mix them, or ints of different
+types:
+
+    let total = 1 + 2.5;
+    let label = "count: " + 3;
+
+Convert one side first, like `to_float(1) + 2.5`, `"count: " + to_str(3)` or `x as int`."#,
+    ),
+    (
+        "hir::invalid_assignment_target",
+        r#"The left-hand side of an assignment isn't a place that can be assigned to.
+
+Only variables, parameters and array elements can be assigned:
+
+    proc main() void {
+        len("abc") = 2;
+    }
+
+Assign to a variable instead, like `let n = len("abc");` and then `n = 2;`."#,
+    ),
+    (
+        "hir::not_callable",
+        r#"Something that isn't a procedure is called.
+
+    proc main() void {
+        let x = 1;
+        x(2);
+    }
+
+Only procedures and builtins can be called. Check that the name refers to the procedure you
+meant, and isn't shadowed by a variable."#,
+    ),
+    (
+        "hir::proc_as_value",
+        r#"A procedure is used as a value without being called.
+
+Procedures aren't values, so they can't be stored in variables or passed to other procedures:
+
+    proc one() int { ret 1; }
+
+    proc main() int {
+        let f = one;
+        ret f;
+    }
+
+Call the procedure to use its result, like `one()`."#,
+    ),
+    (
+        "hir::argument_count_mismatch",
+        r#"A procedure is called with a different number of arguments than it takes.
+
+    proc add(x: int, y: int) int { ret x + y; }
+
+    proc main() int {
+        ret add(1);
+    }
+
+Pass one argument for every parameter, like `add(1, 2)`."#,
+    ),
+    (
+        "hir::sandboxed",
+        r#"A builtin that reaches outside the program is called in a sandboxed program.
+
+Programs run with `--sandbox`, and in the REPL's sandbox, can't touch the file system, read
+environment variables or the clock, or sleep, so `read_file`, `write_file`, `append_file`,
+`env`, `clock_ms` and `sleep_ms` are errors:
+
+    proc main() str {
+        ret read_file("secrets.txt");
+    }
+
+Run the program without the sandbox, or pass it what it needs through its arguments or the
+standard input."#,
+    ),
+    (
+        "hir::void_argument",
+        r#"An expression of type `void` is passed as an argument.
+
+A `void` expression, like a call to a procedure that returns nothing, has no value to pass:
+
+    proc greet() void { println("hi"); }
+
+    proc main() void {
+        println(greet());
+    }
+
+Call the procedure on its own, like `greet();`, or have it return a value."#,
+    ),
+    (
+        "hir::void_variable",
+        r#"A variable, parameter or constant has type `void`.
+
+`void` means there's no value, so nothing can be stored with that type:
+
+    proc greet() void { println("hi"); }
+
+    proc main() void {
+        let result = greet();
+    }
+
+Call the procedure as a statement, like `greet();`, or give the variable another type."#,
+    ),
+    (
+        "hir::void_element",
+        r#"An array has an element of type `void`, or a map a value of type `void`.
+
+    proc greet() void { println("hi"); }
+
+    proc main() void {
+        let calls = [greet(), greet()];
+    }
+
+Array elements and map values have to be values, so only put expressions with a type other
+than `void` in them."#,
+    ),
+    (
+        "hir::not_an_array",
+        r#"A value is indexed, or iterated over, or passed to an array builtin, but it isn't an
+array.
+
+    proc main() int {
+        let n = 42;
+        ret n[0];
+    }
+
+Only arrays, maps and strings can be indexed, and indexing a `str` gives the `char` at a byte.
+Arrays and maps can be iterated over, and iterating over a map visits its keys."#,
+    ),
+    (
+        "hir::not_a_sequence",
+        r#"A builtin that takes a `str`, an array or a map, like `len`, is passed something else.
+
+    proc main() int {
+        ret len(42);
+    }
+
+Pass a string, an array or a map, like `len(to_str(42))` to count the digits of a number."#,
+    ),
+    (
+        "hir::unknown_element_type",
+        r#"An empty array's element type can't be inferred.
+
+The type of an array comes from its elements, so `[]` on its own could be an array of
+anything:
+
+    proc main() void {
+        let xs = [];
+    }
+
+Give the variable a type, like `let xs: [int] = [];`."#,
+    ),
+    (
+        "hir::missing_return",
+        r#"A procedure that returns a value can reach its end without returning one.
+
+Every path through the procedure has to end in a `ret` with a value, including when no branch
+of an `if` is taken:
+
+    proc sign(x: int) int {
+        if x < 0 {
+            ret -1;
+        } elif x > 0 {
+            ret 1;
+        }
+    }
+
+Add a `ret` at the end of the procedure, or an `else` branch that returns."#,
+    ),
+    (
+        "hir::integer_literal_out_of_range",
+        r#"An integer literal doesn't fit in the int type it's used as.
+
+Literals are `int`s, a signed 64-bit integer, unless another int type is expected, like the
+type of a variable or parameter. A `u8` holds 0 to 255, so neither of these fit:
+
+    let byte: u8 = 256;
+    let negative: u8 = -1;
+
+Literals too large for an `int` are `u64`s, and nothing is larger than 18446744073709551615. Use
+a wider type, or a `float` for larger numbers, like `1e20`."#,
+    ),
+    (
+        "hir::float_literal_out_of_range",
+        r#"A float literal is too large to be a finite `float`.
+
+A `float` is a 64-bit IEEE 754 number, so literals beyond about 1.8e308 would round to
+infinity:
+
+    let huge = 1e400;
+
+Use a smaller number, or `1.0 / 0.0` if you meant infinity."#,
+    ),
+    (
+        "hir::unknown_escape_sequence",
+        r#"A string or character literal has a backslash followed by a character that doesn't make
+an escape sequence.
+
+The escape sequences are `\n`, `\t`, `\r`, `\0`, `\\`, `\'` and `\"`:
+
+    let path = "C:\Users";
+
+Write `\\` for a backslash, like `"C:\\Users"`."#,
+    ),
+    (
+        "hir::invalid_range_pattern",
+        r#"A range pattern is used to match a value that isn't an `int` or a `char`.
+
+Ranges only have an order for integers and characters:
+
+    match 1.5 {
+        0.0..1.0 => 0,
+        _ => 1,
+    }
+
+Compare floats with `<` and `>` in an `if` instead."#,
+    ),
+    (
+        "hir::empty_range_pattern",
+        r#"A range pattern's start is past its end, so it matches nothing.
+
+    match n {
+        10..=1 => "small",
+        _ => "large",
+    }
+
+Swap the bounds, like `1..=10`. An exclusive range like `5..5` is empty too."#,
+    ),
+    (
+        "hir::non_exhaustive_match",
+        r#"A `match` doesn't cover every value of what it matches.
+
+A `match` has to take an arm for any value, and the error lists some values that no arm
+matches:
+
+    proc describe(n: int) str {
+        ret match n {
+            0 => "zero",
+            1..=9 => "small",
+        };
+    }
+
+Add arms for the missing values, or a wildcard arm `_ => ...` at the end."#,
+    ),
+    (
+        "hir::unreachable_arm",
+        r#"A `match` arm can never be taken, because the arms before it match all of its values.
+
+This warning often means the arms are in the wrong order:
+
+    match n {
+        _ => "other",
+        0 => "zero",
+    }
+
+Arms are tried in order, so move the more specific arm first, or remove the unreachable one."#,
+    ),
+    (
+        "hir::unknown_attribute",
+        r#"An item is marked with an attribute that doesn't exist.
+
+The attributes are `@must_use`, `@test` and `@bench`, which only apply to procedures:
+
+    @inline
+    proc twice(x: int) int { ret x * 2; }
+
+Remove the attribute, or fix its spelling."#,
+    ),
+    (
+        "hir::invalid_test_or_bench",
+        r#"A procedure marked `@test` or `@bench` takes parameters or returns a value.
+
+`mtxc test` and `mtxc bench` run these procedures on their own, so nothing could pass them
+arguments or use their result:
+
+    @test
+    proc adds(x: int) bool {
+        ret x + 1 > x;
+    }
+
+Make the procedure take nothing and return `void`, checking its results with `assert`, like
+`assert(1 + 1 == 2, "1 + 1 is 2");`."#,
+    ),
+    (
+        "hir::misplaced_attribute",
+        r#"A module is marked with an attribute, but attributes only apply to procedures.
+
+    @test
+    mod checks {}
+
+Mark the procedures inside the module instead."#,
+    ),
+    (
+        "hir::unknown_std_module",
+        r#"An `import` names a module that isn't in the standard library.
+
+The standard library's only module is `math`:
+
+    import maths;
+
+Fix the module's name, like `import math;`."#,
+    ),
+    (
+        "hir::pub_import",
+        r#"An `import` is marked `pub`.
+
+An import makes a standard library module usable in the module it's in and the modules
+nested in it, but can't be re-exported:
+
+    pub import math;
+
+Remove `pub`, and import the module wherever else it's used."#,
+    ),
+    (
+        "hir::no_effect",
+        r#"An expression statement computes a value and then discards it, without doing anything
+else.
+
+This warning usually points at a mistake, like a comparison written instead of an assignment:
+
+    proc main() void {
+        let x = 1;
+        x == 2;
+    }
+
+Use the value, like `x = 2;`, or remove the statement."#,
+    ),
+    (
+        "hir::unused_must_use",
+        r#"The result of a procedure marked `@must_use` is discarded.
+
+A procedure is marked `@must_use` when calling it is pointless unless its result is used:
+
+    @must_use
+    proc squared(x: int) int { ret x * x; }
+
+    proc main() void {
+        squared(3);
+    }
+
+Use the result, like `let nine = squared(3);`, or remove the call."#,
+    ),
+    (
+        "hir::failure",
+        r#"The program has errors found while resolving names and checking types.
+
+This error holds every diagnostic that was found, which are shown after it. Each has its own
+code to explain it. Warnings are shown on their own, and don't stop compilation."#,
+    ),
+    (
+        "hir::const_eval::non_const",
+        r#"A constant's initializer uses something that can't be evaluated at compile time.
+
+Constants are computed while compiling, so their initializers can only use literals,
+operators, `match`es and other constants. Procedure calls aren't allowed, even to builtins:
+
+    const WIDTH: int = len("hello");
+
+Write the value out, like `const WIDTH: int = 5;`, or compute it in a procedure."#,
+    ),
+    (
+        "hir::const_eval::overflow",
+        r#"A constant's value overflows an `int`.
+
+With checked overflow, which is the default, arithmetic past the range of an `int` is an error
+in constants just like when the program runs:
+
+    const BIG: int = 9223372036854775807 + 1;
+
+Use smaller values, or compile with `--overflow wrapping` or `--overflow saturating`."#,
+    ),
+    (
+        "hir::const_eval::division_by_zero",
+        r#"A constant's initializer divides by zero, or takes a remainder of zero.
+
+    const ZERO: int = 0;
+    const RATIO: int = 10 / ZERO;
+
+Check the divisor. Integer division by zero has no value, so it's always an error."#,
+    ),
+    (
+        "hir::const_eval::invalid_shift",
+        r#"A constant's initializer shifts an `int` by a negative amount, or by 64 or more.
+
+An `int` has 64 bits, so shift amounts have to be from 0 to 63. Check the amount the value is
+shifted by."#,
+    ),
+    (
+        "hir::const_eval::cycle",
+        r#"A constant's value depends on itself, directly or through other constants.
+
+    const A: int = B + 1;
+    const B: int = A * 2;
+
+Constants can use each other in any order, but not in a cycle. Write one of them out without
+using the others."#,
+    ),
+    (
+        "hir::assignment_in_condition",
+        r#"The condition of an `if`, `elif` or loop is an assignment, which usually means `==` was
+meant.
+
+`=` assigns a value and `==` compares two, and an assignment has no value to test:
+
+    proc main() void {
+        let x = 1;
+        if x = 1 {
+            println("one");
+        }
+    }
+
+Use `==` to compare, like `if x == 1`. `mtxc fix` makes this change on its own."#,
+    ),
+    (
+        "hir::not_str_or_char",
+        r#"A builtin that takes a `str` or a `char`, like `to_int`, is passed something else.
+
+    proc main() int {
+        ret to_int(true);
+    }
+
+`to_int` parses a string, or gives the code point of a char. Pass one of those, like
+`to_int("42")` or `to_int('a')`."#,
+    ),
+    (
+        "hir::invalid_cast",
+        r#"`as` converts a value to a type it can't be converted to.
+
+`as` only converts between int types, like `i32` and `u8`:
+
+    let x = 2.5 as int;
+
+Use a builtin for other conversions, like `to_int`, `to_float` and `to_str`. A value that
+doesn't fit in the new type overflows, following the program's overflow mode."#,
+    ),
+    (
+        "hir::not_a_map",
+        r#"A builtin that takes a map, like `insert` or `keys`, is passed something else.
+
+    proc main() void {
+        let names = ["ann", "bo"];
+        insert(names, 2, "cy");
+    }
+
+Pass a map, like `let ages: [str: int] = [:];`, or use the array builtins `push` and `pop`
+for arrays."#,
+    ),
+    (
+        "hir::invalid_map_key",
+        r#"A map type has keys of a type that can't be a key.
+
+    proc main() void {
+        let prices: [float: str] = [:];
+    }
+
+Keys have to be compared for equality and never change, so only ints, bools, chars and strings
+can be keys. Floats can't, since `nan` isn't equal to itself, and neither can arrays and maps,
+which can change while they're in the map. Use an int instead, like a price in cents."#,
+    ),
+    (
+        "hir::unknown_map_type",
+        r#"An empty map's key and value types can't be inferred.
+
+The type of a map comes from its entries, so `[:]` on its own could be a map of anything:
+
+    proc main() void {
+        let ages = [:];
+    }
+
+Give the variable a type, like `let ages: [str: int] = [:];`."#,
+    ),
+    (
+        "hir::invalid_range",
+        r#"A `for` loop iterates over a range whose bounds aren't ints.
+
+    for c in 'a'..='z' {
+        print(c);
+    }
+
+A range visits each int from its start up to its end, so both bounds must be ints of the same
+type. Loop over a string's chars instead, like `for c in "abc" { ... }`."#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LowerDiagnostic;
+    use diagnostics::Stage;
+
+    #[test]
+    fn test_every_diagnostic_is_explained() {
+        let sources = [include_str!("diagnostics.rs"), include_str!("consteval.rs")];
+        let mut codes: Vec<_> = sources
+            .iter()
+            .flat_map(|source| source.split("code(").skip(1))
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .filter(|code| !code.is_empty())
+            .chain([<LowerDiagnostic as Stage>::FAILURE])
+            .collect();
+        let mut explained: Vec<_> = EXPLANATIONS.iter().map(|(code, _)| *code).collect();
+        codes.sort_unstable();
+        explained.sort_unstable();
+        assert_eq!(codes, explained);
+    }
+}
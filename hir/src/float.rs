@@ -0,0 +1,220 @@
+//! The semantics of floats, shared by every way of evaluating a program so they all agree.
+//!
+//! Floats are IEEE 754 doubles. Their arithmetic never fails: dividing by zero gives an
+//! infinity, and operations without a meaningful result, like `0.0 / 0.0`, give `nan`, which
+//! every operator propagates. `nan` is unordered, so every comparison with it is false except
+//! `!=`, even against itself, and `-0.0` equals `0.0`.
+//!
+//! Floats are printed with the fewest digits that parse back to the same value, always with a
+//! decimal point or an exponent so they can't be mistaken for ints.
+
+use crate::nodes::{BinOp, Literal};
+
+/// Floats with a magnitude in this range of powers of ten are printed in decimal notation, and
+/// the rest in scientific notation.
+pub const DECIMAL_EXPONENTS: std::ops::Range<i32> = -5..16;
+
+/// Apply a binary operator to floats, or return `None` if it only applies to ints.
+pub fn binary(op: BinOp, lhs: f64, rhs: f64) -> Option<Literal> {
+    use BinOp::*;
+
+    Some(match op {
+        Add => Literal::Float(lhs + rhs),
+        Sub => Literal::Float(lhs - rhs),
+        Mul => Literal::Float(lhs * rhs),
+        Div => Literal::Float(lhs / rhs),
+        Rem => Literal::Float(lhs % rhs),
+        Eq => Literal::Bool(lhs == rhs),
+        Ne => Literal::Bool(lhs != rhs),
+        Lt => Literal::Bool(lhs < rhs),
+        Le => Literal::Bool(lhs <= rhs),
+        Gt => Literal::Bool(lhs > rhs),
+        Ge => Literal::Bool(lhs >= rhs),
+        BitAnd | BitOr | Shl | Shr => return None,
+    })
+}
+
+/// Format a float the way `to_str` and `print` do.
+///
+/// Finite floats get the shortest digits that round-trip, like `0.1`, `2.0` or `1.5e-7`, in
+/// decimal notation unless they're smaller than `1e-5` or at least `1e16`. The rest are `inf`,
+/// `-inf` and `nan`.
+pub fn format(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_owned();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-inf" } else { "inf" }.to_owned();
+    }
+
+    // Rust's scientific notation has the shortest round-tripping digits, like `-1.25e3`.
+    let scientific = format!("{value:e}");
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("scientific notation has an exponent");
+    let exponent: i32 = exponent.parse().expect("exponents are ints");
+    if value != 0.0 && !DECIMAL_EXPONENTS.contains(&exponent) {
+        return scientific;
+    }
+
+    // Print as many fractional digits as the mantissa has after the exponent shifts it, and at
+    // least one.
+    let digits = mantissa
+        .split_once('.')
+        .map_or(0, |(_, digits)| digits.len()) as i32;
+    let precision = (digits - exponent).max(1) as usize;
+    format!("{value:.precision$}")
+}
+
+/// Parse a float literal, like `1.5`, `2e10` or `6.02E+23`, to the nearest float. Returns
+/// `None` if the text isn't a float literal, or its value is too large to be finite.
+///
+/// Every float [`format`] prints, other than `inf`, `-inf` and `nan`, parses back to the same
+/// value, with a `-` in front being negation.
+pub fn parse(text: &str) -> Option<f64> {
+    let (mantissa, exponent) = text
+        .split_once(['e', 'E'])
+        .map_or((text, None), |(mantissa, exponent)| {
+            (mantissa, Some(exponent))
+        });
+    let (whole, fraction) = mantissa
+        .split_once('.')
+        .map_or((mantissa, None), |(whole, fraction)| {
+            (whole, Some(fraction))
+        });
+
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_exponent = |s: &str| is_digits(s.strip_prefix(['+', '-']).unwrap_or(s));
+    let valid = is_digits(whole)
+        && match (fraction, exponent) {
+            // Without either, it's an int.
+            (None, None) => false,
+            (Some(fraction), None) => is_digits(fraction),
+            (None, Some(exponent)) => is_exponent(exponent),
+            (Some(fraction), Some(exponent)) => is_digits(fraction) && is_exponent(exponent),
+        };
+
+    text.parse()
+        .ok()
+        .filter(|value: &f64| valid && value.is_finite())
+}
+
+/// Parse a float the way `to_float` does: an optional `-`, then a float literal, decimal digits
+/// or `inf`, or else `nan`. Returns `None` if the text isn't one of these, or it's too large to
+/// be finite.
+///
+/// Every float [`format`] prints parses back to the same value.
+pub fn from_str(text: &str) -> Option<f64> {
+    if text == "nan" {
+        return Some(f64::NAN);
+    }
+
+    let (negative, unsigned) = text
+        .strip_prefix('-')
+        .map_or((false, text), |unsigned| (true, unsigned));
+    let value = if unsigned == "inf" {
+        f64::INFINITY
+    } else if !unsigned.is_empty() && unsigned.bytes().all(|b| b.is_ascii_digit()) {
+        unsigned
+            .parse()
+            .ok()
+            .filter(|value: &f64| value.is_finite())?
+    } else {
+        parse(unsigned)?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_floats() {
+        let cases = [
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (1.0, "1.0"),
+            (0.1, "0.1"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (-1234.5, "-1234.5"),
+            (1e15, "1000000000000000.0"),
+            (1e16, "1e16"),
+            (0.00001, "0.00001"),
+            (1.5e-7, "1.5e-7"),
+            (f64::MAX, "1.7976931348623157e308"),
+            (f64::MIN_POSITIVE, "2.2250738585072014e-308"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+            (-f64::NAN, "nan"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format(value), expected);
+        }
+    }
+
+    #[test]
+    fn test_formatted_floats_round_trip() {
+        let values = [
+            0.1,
+            1.0 / 3.0,
+            123.456,
+            1e-300,
+            5e-324,
+            9007199254740993.0,
+            f64::MAX,
+        ];
+        for value in values {
+            assert_eq!(
+                parse(&format(value)).map(f64::to_bits),
+                Some(value.to_bits())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_float_literals() {
+        assert_eq!(parse("1.5"), Some(1.5));
+        assert_eq!(parse("2e10"), Some(2e10));
+        assert_eq!(parse("6.02E+23"), Some(6.02e23));
+        assert_eq!(parse("1.5e-3"), Some(0.0015));
+        assert_eq!(parse("1e400"), None);
+        for text in ["1", "inf", "nan", "-1.0", ".5", "1.", "1e", "1e+", "1.5f"] {
+            assert_eq!(parse(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_parse_float_text() {
+        assert_eq!(from_str("-2.5e3"), Some(-2500.0));
+        assert_eq!(from_str("42"), Some(42.0));
+        assert_eq!(from_str("-inf"), Some(f64::NEG_INFINITY));
+        assert!(from_str("nan").is_some_and(f64::is_nan));
+        for value in [-0.0, 1.5e-7, -1e300, f64::INFINITY] {
+            assert_eq!(
+                from_str(&format(value)).map(f64::to_bits),
+                Some(value.to_bits())
+            );
+        }
+        for text in [
+            "", "-", "+1.0", "-nan", "1e999", " 1.0", "1.0 ", "infinity", "0x10",
+        ] {
+            assert_eq!(from_str(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_nan_is_unordered() {
+        let nan = f64::NAN;
+        assert_eq!(binary(BinOp::Eq, nan, nan), Some(Literal::Bool(false)));
+        assert_eq!(binary(BinOp::Ne, nan, nan), Some(Literal::Bool(true)));
+        assert_eq!(binary(BinOp::Ge, nan, 1.0), Some(Literal::Bool(false)));
+        assert_eq!(binary(BinOp::Eq, -0.0, 0.0), Some(Literal::Bool(true)));
+        assert_eq!(
+            binary(BinOp::Div, -1.0, 0.0),
+            Some(Literal::Float(f64::NEG_INFINITY))
+        );
+        assert_eq!(binary(BinOp::Shl, 1.0, 1.0), None);
+    }
+}
@@ -0,0 +1,3006 @@
+#![feature(let_chains)]
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod builtins;
+pub mod consteval;
+mod diagnostics;
+mod effects;
+mod exhaustiveness;
+mod explanations;
+pub mod float;
+pub mod mangle;
+mod nodes;
+mod overflow;
+mod resolve;
+mod semantic;
+mod stdlib;
+pub mod switch;
+mod ty;
+mod typeck;
+
+pub use builtins::{Builtin, BuiltinParam};
+pub use consteval::ConstEvalError;
+pub use diagnostics::{DiagnosticSink, LowerDiagnostic};
+pub use explanations::EXPLANATIONS;
+pub use mangle::{demangle, mangle, Demangled};
+pub use nodes::*;
+pub use overflow::Overflow;
+pub use resolve::{ConstSignature, ProcSignature, Resolution};
+pub use semantic::{classify, SemanticToken, SemanticTokenKind};
+pub use stdlib::{StdConst, StdModule};
+pub use switch::{jump_table, JumpTable};
+pub use ty::{IntTy, Ty};
+
+use consteval::EvalFailure;
+use parser::ast::{
+    self, BinaryOpKind, ExpressionKind, ItemKind, PatternKind, StatementKind, UnaryOpKind,
+};
+use rayon::prelude::*;
+use resolve::Resolver;
+use span::{Cancel, Cancelled, Span, Spanned};
+
+/// How far a constant's initializer has been evaluated.
+#[derive(Debug, Clone)]
+enum ConstState {
+    Pending(ast::Expression),
+
+    /// The initializer is being evaluated, so using the constant again is a cycle.
+    Evaluating,
+
+    /// The constant's value, or `None` if its initializer is erroneous.
+    Done(Option<Literal>),
+}
+
+/// The attributes a procedure is marked with.
+#[derive(Debug, Default)]
+struct ProcAttributes {
+    must_use: bool,
+    test: bool,
+    bench: bool,
+}
+
+/// Resolves names, type checks and lowers an AST to HIR.
+#[derive(Debug)]
+struct LoweringContext {
+    resolver: Resolver,
+    diagnostics: DiagnosticSink,
+
+    /// The locals of the procedure currently being lowered.
+    locals: Vec<Local>,
+
+    /// The return type of the procedure currently being lowered.
+    ret_ty: Ty,
+
+    /// The procedure currently being lowered.
+    proc: Option<ProcId>,
+
+    /// Every resolved name, as in [`Program::symbols`] but not yet sorted.
+    symbols: Vec<Spanned<Symbol>>,
+
+    /// The evaluation state of every constant, indexed by [`ConstId`].
+    consts: Vec<ConstState>,
+
+    /// The native procedures the program is lowered with, indexed by [`NativeId`].
+    natives: Vec<NativeSignature>,
+    overflow: Overflow,
+    sandbox: bool,
+}
+
+impl LoweringContext {
+    fn new(options: &LowerOptions<'_>) -> Self {
+        let natives: Vec<_> = SystemNative::ALL
+            .into_iter()
+            .map(SystemNative::signature)
+            .chain(options.natives.iter().cloned())
+            .collect();
+        let mut resolver = Resolver::default();
+        resolver.declare_natives(&natives);
+
+        Self {
+            resolver,
+            diagnostics: DiagnosticSink::with_levels(options.levels.clone()),
+            locals: Vec::new(),
+            ret_ty: Ty::Void,
+            proc: None,
+            symbols: Vec::new(),
+            consts: Vec::new(),
+            natives,
+            overflow: options.overflow,
+            sandbox: options.sandbox,
+        }
+    }
+
+    /// A context for lowering procedures on another thread once every item is collected and
+    /// every constant evaluated, sharing nothing with this one but what's been resolved. What
+    /// it reports and records is taken out after each procedure, to merge in their order.
+    fn fork(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            diagnostics: DiagnosticSink::with_levels(self.diagnostics.levels().clone()),
+            locals: Vec::new(),
+            ret_ty: Ty::Void,
+            proc: None,
+            symbols: Vec::new(),
+            consts: self.consts.clone(),
+            natives: self.natives.clone(),
+            overflow: self.overflow,
+            sandbox: self.sandbox,
+        }
+    }
+
+    fn error(&mut self, diagnostic: LowerDiagnostic) {
+        self.diagnostics.push_diagnostic(diagnostic);
+    }
+
+    fn warn(&mut self, diagnostic: LowerDiagnostic) {
+        self.diagnostics.push_diagnostic(diagnostic);
+    }
+
+    /// Report a type mismatch unless the found type is acceptable.
+    fn check_ty(&mut self, expected: Ty, found: Ty, span: Span) {
+        if !expected.accepts(found) {
+            self.error(LowerDiagnostic::TypeMismatch {
+                expected,
+                found,
+                span,
+            });
+        }
+    }
+
+    /// Record what a name resolved to.
+    fn record(&mut self, span: Span, resolution: Resolution) {
+        let symbol = match resolution {
+            Resolution::Proc(id) => Symbol::Proc(id),
+            Resolution::Const(id) => Symbol::Const(id),
+            Resolution::Local(id) => Symbol::Local(
+                self.proc
+                    .expect("locals are only resolved within a procedure"),
+                id,
+            ),
+            Resolution::Builtin(builtin) => Symbol::Builtin(builtin),
+            Resolution::Native(id) => Symbol::Native(id),
+            Resolution::StdConst(constant) => Symbol::StdConst(constant),
+        };
+
+        self.symbols.push(Spanned::new(symbol, span));
+    }
+
+    fn record_module(&mut self, span: Span, module: ModuleId) {
+        self.symbols.push(Spanned::new(Symbol::Module(module), span));
+    }
+
+    /// Declare a new local in the innermost scope.
+    fn declare_local(&mut self, name: &ast::Ident, ty: Ty) -> LocalId {
+        let id = LocalId(self.locals.len() as u32);
+        self.record(name.span, Resolution::Local(id));
+        self.locals.push(Local {
+            name: name.name.clone(),
+            ty,
+            span: name.span,
+        });
+        self.resolver.declare_local(&name.name, id);
+        id
+    }
+
+    /// Declare a local introduced by lowering, which the program can't refer to by name.
+    fn declare_hidden_local(&mut self, name: &str, ty: Ty, span: Span) -> LocalId {
+        let id = LocalId(self.locals.len() as u32);
+        self.locals.push(Local {
+            name: name.to_owned(),
+            ty,
+            span,
+        });
+        id
+    }
+
+    /// Convert a type written in the source, reporting arrays and maps of `void` and maps with
+    /// keys that can't be keys.
+    fn lower_ty(&mut self, ty: &ast::Type) -> Ty {
+        match &ty.kind {
+            ast::TypeKind::Array(element) => {
+                let element_ty = self.lower_ty(element);
+                if element_ty == Ty::Void {
+                    self.error(LowerDiagnostic::VoidElement(element.span));
+                }
+                Ty::array_of(element_ty)
+            }
+            ast::TypeKind::Map(key, value) => {
+                let key_ty = self.lower_ty(key);
+                if !key_ty.is_key() {
+                    self.error(LowerDiagnostic::InvalidMapKey(key_ty, key.span));
+                }
+                let value_ty = self.lower_ty(value);
+                if value_ty == Ty::Void {
+                    self.error(LowerDiagnostic::VoidElement(value.span));
+                }
+                Ty::map_of(key_ty, value_ty)
+            }
+            kind => kind.into(),
+        }
+    }
+
+    /// Find the attributes a procedure is marked with, reporting any unknown ones.
+    fn proc_attributes(&mut self, item: &ast::Item) -> ProcAttributes {
+        let mut attributes = ProcAttributes::default();
+        for attribute in &item.attributes {
+            match attribute.name.name.as_str() {
+                "must_use" => attributes.must_use = true,
+                "test" => attributes.test = true,
+                "bench" => attributes.bench = true,
+                name => self.error(LowerDiagnostic::UnknownAttribute(
+                    name.to_owned(),
+                    attribute.span,
+                )),
+            }
+        }
+
+        attributes
+    }
+
+    /// Report the attributes of an item that doesn't take any.
+    fn reject_attributes(&mut self, item: &ast::Item) {
+        for attribute in &item.attributes {
+            self.error(LowerDiagnostic::MisplacedAttribute(
+                attribute.name.name.clone(),
+                attribute.span,
+            ));
+        }
+    }
+
+    /// Declare the modules and collect the signatures of every procedure in a module, so bodies
+    /// can reference items defined after them. Returns the procedures to lower.
+    fn collect_items<'a>(
+        &mut self,
+        items: &'a [ast::Item],
+        module: ModuleId,
+        procs: &mut Vec<(ProcId, &'a ast::Proc, Span)>,
+    ) {
+        for item in items {
+            match &item.kind {
+                ItemKind::Proc(proc) => {
+                    let ProcAttributes {
+                        must_use,
+                        test,
+                        bench,
+                    } = self.proc_attributes(item);
+                    let signature = ProcSignature {
+                        name: proc.name.name.clone(),
+                        params: proc
+                            .parameters
+                            .iter()
+                            .map(|p| self.lower_ty(&p.ty))
+                            .collect(),
+                        ret_ty: self.lower_ty(&proc.return_type),
+                        span: proc.name.span,
+                        doc: item.doc.clone(),
+                        must_use,
+                        test,
+                        bench,
+                        module,
+                        visibility: item.visibility,
+                    };
+
+                    let runnable = signature.params.is_empty() && signature.ret_ty == Ty::Void;
+                    for (marked, attribute) in [(test, "test"), (bench, "bench")] {
+                        if marked && !runnable {
+                            self.error(LowerDiagnostic::InvalidTestOrBench(
+                                attribute,
+                                proc.name.name.clone(),
+                                proc.name.span,
+                            ));
+                        }
+                    }
+
+                    match self.resolver.declare_proc(signature) {
+                        Ok(id) => {
+                            self.record(proc.name.span, Resolution::Proc(id));
+                            procs.push((id, proc, item.span));
+                        }
+                        Err(previous) => {
+                            self.error(LowerDiagnostic::DuplicateProc {
+                                name: proc.name.name.clone(),
+                                span: proc.name.span,
+                                previous,
+                            });
+                        }
+                    }
+                }
+                ItemKind::Const(constant) => {
+                    self.reject_attributes(item);
+
+                    let signature = ConstSignature {
+                        name: constant.name.name.clone(),
+                        ty: self.lower_ty(&constant.ty),
+                        span: constant.name.span,
+                        doc: item.doc.clone(),
+                        module,
+                        visibility: item.visibility,
+                    };
+
+                    match self.resolver.declare_const(signature) {
+                        Ok(id) => {
+                            self.record(constant.name.span, Resolution::Const(id));
+                            self.consts
+                                .push(ConstState::Pending(constant.value.clone()));
+                        }
+                        Err(previous) => {
+                            self.error(LowerDiagnostic::DuplicateConst {
+                                name: constant.name.name.clone(),
+                                span: constant.name.span,
+                                previous,
+                            });
+                        }
+                    }
+                }
+                ItemKind::Mod(ast_module) => {
+                    self.reject_attributes(item);
+
+                    let declared = self.resolver.declare_module(Module {
+                        name: ast_module.name.name.clone(),
+                        parent: Some(module),
+                        visibility: item.visibility,
+                        span: ast_module.name.span,
+                    });
+
+                    match declared {
+                        Ok(id) => {
+                            self.record_module(ast_module.name.span, id);
+                            self.collect_items(&ast_module.items, id, procs);
+                        }
+                        Err(previous) => {
+                            let previous = previous.span;
+                            self.error(LowerDiagnostic::DuplicateModule {
+                                name: ast_module.name.name.clone(),
+                                span: ast_module.name.span,
+                                previous,
+                            });
+                        }
+                    }
+                }
+                ItemKind::Import(name) => {
+                    self.reject_attributes(item);
+                    if item.visibility == ast::Visibility::Public {
+                        self.error(LowerDiagnostic::PubImport(item.span));
+                    }
+
+                    match StdModule::from_name(&name.name) {
+                        Some(std_module) => {
+                            self.symbols
+                                .push(Spanned::new(Symbol::StdModule(std_module), name.span));
+                            self.resolver.declare_import(module, std_module);
+                        }
+                        None => self.error(LowerDiagnostic::UnknownStdModule(
+                            name.name.clone(),
+                            name.span,
+                        )),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resolve a path to a procedure or constant, checking that each module along it and the
+    /// item itself can be used from the current module. Modules declared in the program shadow
+    /// imported standard library modules.
+    fn resolve_path(&mut self, path: &ast::Path) -> Option<Resolution> {
+        let (last, modules) = path
+            .segments
+            .split_last()
+            .expect("paths have at least two segments");
+
+        let first = &modules[0];
+        if self.resolver.resolve_module(&first.name).is_none()
+            && let Some(std_module) = self.resolver.resolve_import(&first.name)
+        {
+            self.symbols
+                .push(Spanned::new(Symbol::StdModule(std_module), first.span));
+            // Standard library modules don't contain modules.
+            let resolution = match modules {
+                [_] => std_module.value(&last.name),
+                _ => None,
+            };
+            let Some(resolution) = resolution else {
+                self.error(LowerDiagnostic::UnresolvedName(path.to_string(), last.span));
+                return None;
+            };
+
+            self.record(last.span, resolution);
+            return Some(resolution);
+        }
+
+        let mut module = None;
+        for (i, segment) in modules.iter().enumerate() {
+            let found = match module {
+                None => self.resolver.resolve_module(&segment.name),
+                Some(parent) => self.resolver.module_child(parent, &segment.name),
+            };
+
+            let Some(found) = found else {
+                let name = path.segments[..=i]
+                    .iter()
+                    .map(|s| s.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("::");
+                self.error(LowerDiagnostic::UnresolvedName(name, segment.span));
+                return None;
+            };
+
+            self.record_module(segment.span, found);
+            let definition = self.resolver.module(found);
+            if module.is_some()
+                && !self
+                    .resolver
+                    .is_accessible(definition.parent.unwrap(), definition.visibility)
+            {
+                self.error(LowerDiagnostic::PrivateItem {
+                    kind: "module",
+                    name: segment.name.clone(),
+                    span: segment.span,
+                    definition: definition.span,
+                });
+                return None;
+            }
+
+            module = Some(found);
+        }
+
+        let module = module.unwrap();
+        let Some(resolution) = self.resolver.module_value(module, &last.name) else {
+            self.error(LowerDiagnostic::UnresolvedName(path.to_string(), last.span));
+            return None;
+        };
+
+        self.record(last.span, resolution);
+        let (kind, defined_in, visibility, definition) = match resolution {
+            Resolution::Proc(id) => {
+                let signature = self.resolver.proc(id);
+                let (module, visibility) = (signature.module, signature.visibility);
+                ("procedure", module, visibility, signature.span)
+            }
+            Resolution::Const(id) => {
+                let signature = self.resolver.constant(id);
+                let (module, visibility) = (signature.module, signature.visibility);
+                ("constant", module, visibility, signature.span)
+            }
+            Resolution::Local(_)
+            | Resolution::Builtin(_)
+            | Resolution::Native(_)
+            | Resolution::StdConst(_) => {
+                unreachable!("only procedures and constants are declared in modules")
+            }
+        };
+
+        if !self.resolver.is_accessible(defined_in, visibility) {
+            self.error(LowerDiagnostic::PrivateItem {
+                kind,
+                name: last.name.clone(),
+                span: last.span,
+                definition,
+            });
+            return None;
+        }
+
+        Some(resolution)
+    }
+
+    /// Evaluate a constant's initializer if it hasn't been already, returning its value or
+    /// `None` if it's erroneous. `span` is where the constant is used, for reporting cycles.
+    fn const_value(&mut self, id: ConstId, span: Span) -> Option<Literal> {
+        let state = std::mem::replace(&mut self.consts[id.0 as usize], ConstState::Evaluating);
+        let init = match state {
+            ConstState::Pending(init) => init,
+            ConstState::Evaluating => {
+                let name = self.resolver.constant(id).name.clone();
+                self.error(ConstEvalError::Cycle(name, span).into());
+                return None;
+            }
+            ConstState::Done(value) => {
+                self.consts[id.0 as usize] = ConstState::Done(value.clone());
+                return value;
+            }
+        };
+
+        // The initializer resolves names in the constant's module, wherever it's used from.
+        let signature = self.resolver.constant(id);
+        let (ty, name, name_span) = (signature.ty, signature.name.clone(), signature.span);
+        let outer_module = self.resolver.current_module();
+        self.resolver.enter_module(signature.module);
+
+        let init = self.lower_expr(&init);
+        let value = if ty == Ty::Void {
+            self.error(LowerDiagnostic::VoidVariable(name, name_span));
+            None
+        } else if !ty.accepts(init.ty) {
+            self.check_ty(ty, init.ty, init.span);
+            None
+        } else {
+            let overflow = self.overflow;
+            match consteval::eval(&init, overflow, &mut |id, span| self.const_value(id, span)) {
+                Ok(value) => Some(value),
+                Err(EvalFailure::Error(error)) => {
+                    self.error(error.into());
+                    None
+                }
+                Err(EvalFailure::Poisoned) => None,
+            }
+        };
+
+        self.resolver.enter_module(outer_module);
+        self.consts[id.0 as usize] = ConstState::Done(value.clone());
+        value
+    }
+
+    /// Build an expression reading a constant.
+    fn const_expr(&self, id: ConstId, span: Span) -> Expr {
+        Expr {
+            kind: ExprKind::Const(id),
+            ty: self.resolver.constant(id).ty,
+            span,
+        }
+    }
+
+    fn lower_proc(&mut self, id: ProcId, proc: &ast::Proc, span: Span) -> Proc {
+        let _span = tracing::debug_span!("proc", name = proc.name.name).entered();
+        let signature = self.resolver.proc(id);
+        let (doc, module) = (signature.doc.clone(), signature.module);
+        let (test, bench) = (signature.test, signature.bench);
+        let (param_tys, ret_ty) = (signature.params.clone(), signature.ret_ty);
+        self.resolver.enter_module(module);
+        self.locals.clear();
+        self.proc = Some(id);
+        self.ret_ty = ret_ty;
+        self.resolver.push_scope();
+
+        let mut params = Vec::with_capacity(proc.parameters.len());
+        for (param, ty) in proc.parameters.iter().zip(param_tys) {
+            if proc.parameters[..params.len()]
+                .iter()
+                .any(|p| p.name.name == param.name.name)
+            {
+                self.error(LowerDiagnostic::DuplicateParameter(
+                    param.name.name.clone(),
+                    param.name.span,
+                ));
+            }
+
+            if ty == Ty::Void {
+                self.error(LowerDiagnostic::VoidVariable(
+                    param.name.name.clone(),
+                    param.span,
+                ));
+            }
+
+            params.push(self.declare_local(&param.name, ty));
+        }
+
+        let body = self.lower_block(&proc.body);
+        self.resolver.pop_scope();
+
+        if self.ret_ty != Ty::Void && !block_returns(&body) {
+            self.error(LowerDiagnostic::MissingReturn(
+                proc.name.name.clone(),
+                proc.name.span,
+                self.ret_ty,
+            ));
+        }
+
+        Proc {
+            id,
+            name: proc.name.name.clone(),
+            module,
+            params,
+            locals: std::mem::take(&mut self.locals),
+            ret_ty: self.ret_ty,
+            body,
+            span,
+            doc,
+            test,
+            bench,
+        }
+    }
+
+    /// Lower a block in a new scope.
+    fn lower_block(&mut self, block: &ast::Block) -> Block {
+        self.resolver.push_scope();
+        let stmts = block
+            .statements
+            .iter()
+            .map(|stmt| self.lower_stmt(stmt))
+            .collect();
+        self.resolver.pop_scope();
+
+        Block {
+            stmts,
+            span: block.span,
+        }
+    }
+
+    fn lower_stmt(&mut self, stmt: &ast::Statement) -> Stmt {
+        let span = stmt.span;
+
+        let kind = match &stmt.kind {
+            StatementKind::Let {
+                name,
+                ty,
+                initializer,
+            } => {
+                // The initializer is lowered before the local is declared, so it can refer to a
+                // shadowed local with the same name.
+                let (ty, init) = match ty {
+                    Some(ty) => {
+                        let ty = self.lower_ty(ty);
+                        let init = self.lower_expected(initializer, ty);
+                        self.check_ty(ty, init.ty, init.span);
+                        (ty, init)
+                    }
+                    None => {
+                        let init = self.lower_expr(initializer);
+                        (init.ty, init)
+                    }
+                };
+
+                if ty == Ty::Void {
+                    self.error(LowerDiagnostic::VoidVariable(name.name.clone(), name.span));
+                }
+
+                let local = self.declare_local(name, ty);
+                StmtKind::Let { local, init }
+            }
+            StatementKind::Ret(value) => {
+                let value = value.as_ref().map(|v| self.lower_expected(v, self.ret_ty));
+                let (found, found_span) =
+                    value.as_ref().map_or((Ty::Void, span), |v| (v.ty, v.span));
+                self.check_ty(self.ret_ty, found, found_span);
+                StmtKind::Ret(value)
+            }
+            StatementKind::If {
+                condition,
+                then_block,
+                elif_branches,
+                else_block,
+            } => {
+                let cond = self.lower_condition(condition);
+                let then_block = self.lower_block(then_block);
+                let branches = elif_branches
+                    .iter()
+                    .map(|(condition, block)| {
+                        (self.lower_condition(condition), self.lower_block(block))
+                    })
+                    .collect::<Vec<_>>();
+                let else_block = else_block.as_ref().map(|block| self.lower_block(block));
+
+                // `if a {} elif b {} else {}` becomes `if a {} else { if b {} else {} }`.
+                let else_block = branches.into_iter().rev().fold(
+                    else_block,
+                    |else_block, (cond, then_block)| {
+                        let span = else_block
+                            .as_ref()
+                            .map_or(then_block.span, |e| cond.span.coalesce_adjacent(e.span));
+                        let span = cond.span.coalesce_adjacent(span);
+
+                        Some(Block {
+                            stmts: vec![Stmt {
+                                kind: StmtKind::If {
+                                    cond,
+                                    then_block,
+                                    else_block,
+                                },
+                                span,
+                            }],
+                            span,
+                        })
+                    },
+                );
+
+                StmtKind::If {
+                    cond,
+                    then_block,
+                    else_block,
+                }
+            }
+            StatementKind::While { condition, body } => StmtKind::While {
+                cond: self.lower_condition(condition),
+                body: self.lower_block(body),
+            },
+            StatementKind::DoWhile { body, condition } => {
+                // `do { body } while cond;` becomes `loop { { body } if !cond { break; } }`.
+                let body = self.lower_block(body);
+                let cond = self.lower_condition(condition);
+                let cond_span = cond.span;
+
+                let exit = Stmt {
+                    kind: StmtKind::If {
+                        cond: Expr {
+                            kind: ExprKind::Unary {
+                                op: UnOp::Not,
+                                operand: Box::new(cond),
+                            },
+                            ty: Ty::Bool,
+                            span: cond_span,
+                        },
+                        then_block: Block {
+                            stmts: vec![Stmt {
+                                kind: StmtKind::Break,
+                                span: cond_span,
+                            }],
+                            span: cond_span,
+                        },
+                        else_block: None,
+                    },
+                    span: cond_span,
+                };
+
+                StmtKind::Loop(Block {
+                    stmts: vec![
+                        Stmt {
+                            span: body.span,
+                            kind: StmtKind::Block(body),
+                        },
+                        exit,
+                    ],
+                    span,
+                })
+            }
+            StatementKind::For {
+                initializer,
+                condition,
+                step,
+                body,
+            } => {
+                // `for init; cond; step { body }` becomes `{ init; while cond { { body } step; } }`.
+                self.resolver.push_scope();
+                let initializer = self.lower_stmt(initializer);
+                let cond = self.lower_condition(condition);
+                let body = self.lower_block(body);
+                let step = self.lower_expr(step);
+                self.check_unused(&step);
+                self.resolver.pop_scope();
+
+                let while_body = Block {
+                    span: body.span,
+                    stmts: vec![
+                        Stmt {
+                            span: body.span,
+                            kind: StmtKind::Block(body),
+                        },
+                        Stmt {
+                            span: step.span,
+                            kind: StmtKind::Expr(step),
+                        },
+                    ],
+                };
+
+                StmtKind::Block(Block {
+                    stmts: vec![
+                        initializer,
+                        Stmt {
+                            kind: StmtKind::While {
+                                cond,
+                                body: while_body,
+                            },
+                            span,
+                        },
+                    ],
+                    span,
+                })
+            }
+            StatementKind::ForIn {
+                binding,
+                iterable,
+                body,
+            } => self.lower_for_in(binding, iterable, body, span),
+            StatementKind::Block(block) => StmtKind::Block(self.lower_block(block)),
+            StatementKind::Expression(expr) => {
+                let expr = self.lower_expr(expr);
+                self.check_unused(&expr);
+                StmtKind::Expr(expr)
+            }
+        };
+
+        Stmt { kind, span }
+    }
+
+    /// Lower a loop over the elements of an array, the chars of a string, the keys of a map or
+    /// the ints in a range.
+    fn lower_for_in(
+        &mut self,
+        binding: &ast::Ident,
+        iterable: &ast::Expression,
+        body: &ast::Block,
+        span: Span,
+    ) -> StmtKind {
+        if let ExpressionKind::Range {
+            start,
+            end,
+            inclusive,
+        } = &iterable.kind
+        {
+            return self.lower_for_range(binding, start, end, *inclusive, body, span);
+        }
+
+        // `for x in xs { body }` becomes `{ let array = xs; let i = 0; while i < len(array) {
+        // let x = array[i]; { body } i += 1; } }`, where `array` and `i` are hidden from the
+        // program. The length is read on every iteration, so the loop sees elements pushed by
+        // its body. A loop over a map is a loop over `keys(map)`, so its body can change the map.
+        // A loop over a string reads `char_at(s, i)` and steps over the bytes of that char.
+        let mut iterable = self.lower_expr(iterable);
+        if let Ty::Map(..) = iterable.ty {
+            let span = iterable.span;
+            iterable = self.lower_builtin_call(Builtin::Keys, vec![iterable], span);
+        }
+        let element_ty = if iterable.ty == Ty::Str {
+            Ty::Char
+        } else {
+            self.element_ty(&iterable)
+        };
+        let (array_ty, machinery) = (iterable.ty, Machinery(iterable.span));
+        let array = self.declare_hidden_local("for array", array_ty, machinery.0);
+        let index = self.declare_hidden_local("for index", Ty::INT, machinery.0);
+
+        self.resolver.push_scope();
+        let element = self.declare_local(binding, element_ty);
+        let body = self.lower_block(body);
+        self.resolver.pop_scope();
+
+        let (init, step) = if array_ty == Ty::Str {
+            let char_at = || {
+                let args = vec![machinery.local(array, Ty::Str), machinery.local(index, Ty::INT)];
+                machinery.builtin(Builtin::CharAt, args, Ty::Char)
+            };
+            let char_str = machinery.builtin(Builtin::ToStr, vec![char_at()], Ty::Str);
+            (
+                char_at(),
+                machinery.builtin(Builtin::Len, vec![char_str], Ty::INT),
+            )
+        } else {
+            let init = machinery.expr(
+                ExprKind::Index {
+                    array: Box::new(machinery.local(array, array_ty)),
+                    index: Box::new(machinery.local(index, Ty::INT)),
+                },
+                element_ty,
+            );
+            (init, machinery.int(1, IntTy::I64))
+        };
+
+        let len = machinery.builtin(
+            Builtin::Len,
+            vec![machinery.local(array, array_ty)],
+            Ty::INT,
+        );
+        let cond = machinery.binary(machinery.local(index, Ty::INT), BinOp::Lt, len, Ty::Bool);
+        let element = Stmt {
+            kind: StmtKind::Let {
+                local: element,
+                init,
+            },
+            span: binding.span,
+        };
+        let body = Block {
+            stmts: vec![
+                element,
+                Stmt {
+                    span: body.span,
+                    kind: StmtKind::Block(body),
+                },
+                machinery.increment(index, step, Ty::INT),
+            ],
+            span,
+        };
+
+        StmtKind::Block(Block {
+            stmts: vec![
+                machinery.stmt(StmtKind::Let {
+                    local: array,
+                    init: iterable,
+                }),
+                machinery.stmt(StmtKind::Let {
+                    local: index,
+                    init: machinery.int(0, IntTy::I64),
+                }),
+                Stmt {
+                    kind: StmtKind::While { cond, body },
+                    span,
+                },
+            ],
+            span,
+        })
+    }
+
+    /// Lower a loop over the ints from the start of a range to its end, which have the int type
+    /// of the bounds.
+    fn lower_for_range(
+        &mut self,
+        binding: &ast::Ident,
+        start: &ast::Expression,
+        end: &ast::Expression,
+        inclusive: bool,
+        body: &ast::Block,
+        span: Span,
+    ) -> StmtKind {
+        // `for i in a..b { body }` becomes `{ let next = a; let end = b; while next < end {
+        // let i = next; { body } next += 1; } }`, where `next` and `end` are hidden from the
+        // program, so assigning to `i` doesn't change which ints the loop visits. An inclusive
+        // range loops with `if next <= end { loop { let i = next; { body } if next == end {
+        // break; } next += 1; } }` instead, so a range ending at the largest int doesn't
+        // overflow.
+        let (mut start, mut end) = (self.lower_expr(start), self.lower_expr(end));
+        self.coerce_int_literal(end.ty, &mut start);
+        self.coerce_int_literal(start.ty, &mut end);
+        let int_ty = match start.ty {
+            Ty::Int(int_ty) => {
+                self.check_ty(start.ty, end.ty, end.span);
+                Some(int_ty)
+            }
+            Ty::Error => None,
+            ty => {
+                self.error(LowerDiagnostic::InvalidRange(ty, start.span));
+                None
+            }
+        };
+        let ty = int_ty.map_or(Ty::Error, Ty::Int);
+
+        let machinery = Machinery(start.span.coalesce_adjacent(end.span));
+        let next = self.declare_hidden_local("for next", ty, machinery.0);
+        let last = self.declare_hidden_local("for end", ty, machinery.0);
+
+        self.resolver.push_scope();
+        let element = self.declare_local(binding, ty);
+        let body = self.lower_block(body);
+        self.resolver.pop_scope();
+
+        let element = Stmt {
+            kind: StmtKind::Let {
+                local: element,
+                init: machinery.local(next, ty),
+            },
+            span: binding.span,
+        };
+        let body = Stmt {
+            span: body.span,
+            kind: StmtKind::Block(body),
+        };
+        let step = machinery.increment(next, machinery.int(1, int_ty.unwrap_or(IntTy::I64)), ty);
+        let compare = |op| {
+            let (next, last) = (machinery.local(next, ty), machinery.local(last, ty));
+            machinery.binary(next, op, last, Ty::Bool)
+        };
+
+        let machinery_loop = if inclusive {
+            let exit = machinery.stmt(StmtKind::If {
+                cond: compare(BinOp::Eq),
+                then_block: Block {
+                    stmts: vec![machinery.stmt(StmtKind::Break)],
+                    span: machinery.0,
+                },
+                else_block: None,
+            });
+            let body = Block {
+                stmts: vec![element, body, exit, step],
+                span,
+            };
+            StmtKind::If {
+                cond: compare(BinOp::Le),
+                then_block: Block {
+                    stmts: vec![Stmt {
+                        kind: StmtKind::Loop(body),
+                        span,
+                    }],
+                    span,
+                },
+                else_block: None,
+            }
+        } else {
+            let body = Block {
+                stmts: vec![element, body, step],
+                span,
+            };
+            StmtKind::While {
+                cond: compare(BinOp::Lt),
+                body,
+            }
+        };
+
+        StmtKind::Block(Block {
+            stmts: vec![
+                machinery.stmt(StmtKind::Let {
+                    local: next,
+                    init: start,
+                }),
+                machinery.stmt(StmtKind::Let {
+                    local: last,
+                    init: end,
+                }),
+                Stmt {
+                    kind: machinery_loop,
+                    span,
+                },
+            ],
+            span,
+        })
+    }
+
+    /// Warn about an expression whose value is discarded if evaluating it does nothing, or if it
+    /// calls a procedure whose result must be used.
+    fn check_unused(&mut self, expr: &Expr) {
+        if let ExprKind::Call { callee, .. } = expr.kind {
+            let signature = self.resolver.proc(callee);
+            if signature.must_use && signature.ret_ty != Ty::Void {
+                let name = signature.name.clone();
+                self.warn(LowerDiagnostic::UnusedMustUse(name, expr.span));
+            }
+        } else if !effects::has_side_effects(expr) {
+            self.warn(LowerDiagnostic::NoEffect(expr.span));
+        }
+    }
+
+    /// Lower an expression that must evaluate to a bool. An assignment is reported as one,
+    /// since it's usually a comparison missing an `=`, rather than as a `void` value.
+    fn lower_condition(&mut self, expr: &ast::Expression) -> Expr {
+        let cond = self.lower_expr(expr);
+        if let ExpressionKind::Binary {
+            operator: BinaryOpKind::Equal,
+            operator_span,
+            ..
+        } = expr.kind
+        {
+            self.error(LowerDiagnostic::AssignmentInCondition(operator_span));
+        } else {
+            self.check_ty(Ty::Bool, cond.ty, cond.span);
+        }
+        cond
+    }
+
+    fn lower_expr(&mut self, expr: &ast::Expression) -> Expr {
+        let span = expr.span;
+        let error = Expr {
+            kind: ExprKind::Error,
+            ty: Ty::Error,
+            span,
+        };
+
+        match &expr.kind {
+            ExpressionKind::Literal { kind, text } => {
+                self.lower_literal(*kind, text, span)
+                    .map_or(error, |literal| Expr {
+                        ty: literal.ty(),
+                        kind: ExprKind::Literal(literal),
+                        span,
+                    })
+            }
+            ExpressionKind::Ident(ident) => match self.resolver.resolve(&ident.name) {
+                Some(Resolution::Local(local)) => {
+                    self.record(span, Resolution::Local(local));
+                    Expr {
+                        kind: ExprKind::Local(local),
+                        ty: self.locals[local.0 as usize].ty,
+                        span,
+                    }
+                }
+                Some(
+                    resolution @ (Resolution::Proc(_)
+                    | Resolution::Builtin(_)
+                    | Resolution::Native(_)),
+                ) => {
+                    self.record(span, resolution);
+                    self.error(LowerDiagnostic::ProcAsValue(ident.name.clone(), span));
+                    error
+                }
+                Some(Resolution::Const(id)) => {
+                    self.record(span, Resolution::Const(id));
+                    self.const_expr(id, span)
+                }
+                Some(Resolution::StdConst(constant)) => {
+                    self.record(span, Resolution::StdConst(constant));
+                    std_const_expr(constant, span)
+                }
+                None => {
+                    self.error(LowerDiagnostic::UnresolvedName(ident.name.clone(), span));
+                    error
+                }
+            },
+            ExpressionKind::Path(path) => match self.resolve_path(path) {
+                Some(Resolution::Const(id)) => self.const_expr(id, span),
+                Some(Resolution::StdConst(constant)) => std_const_expr(constant, span),
+                Some(_) => {
+                    self.error(LowerDiagnostic::ProcAsValue(path.to_string(), span));
+                    error
+                }
+                None => error,
+            },
+            ExpressionKind::Call { callee, arguments } => {
+                let mut args = arguments
+                    .iter()
+                    .map(|arg| self.lower_expr(arg))
+                    .collect::<Vec<_>>();
+
+                let callee = match &callee.kind {
+                    ExpressionKind::Ident(ident) => match self.resolver.resolve(&ident.name) {
+                        Some(Resolution::Proc(id)) => {
+                            self.record(ident.span, Resolution::Proc(id));
+                            id
+                        }
+                        Some(Resolution::Builtin(builtin)) => {
+                            self.record(ident.span, Resolution::Builtin(builtin));
+                            return self.lower_builtin_call(builtin, args, span);
+                        }
+                        Some(Resolution::Native(native)) => {
+                            self.record(ident.span, Resolution::Native(native));
+                            return self.lower_native_call(native, args, span);
+                        }
+                        Some(
+                            resolution @ (Resolution::Local(_)
+                            | Resolution::Const(_)
+                            | Resolution::StdConst(_)),
+                        ) => {
+                            self.record(ident.span, resolution);
+                            self.error(LowerDiagnostic::NotCallable(ident.span));
+                            return error;
+                        }
+                        None => {
+                            self.error(LowerDiagnostic::UnresolvedName(
+                                ident.name.clone(),
+                                ident.span,
+                            ));
+                            return error;
+                        }
+                    },
+                    ExpressionKind::Path(path) => match self.resolve_path(path) {
+                        Some(Resolution::Proc(id)) => id,
+                        Some(Resolution::Builtin(builtin)) => {
+                            return self.lower_builtin_call(builtin, args, span);
+                        }
+                        Some(_) => {
+                            self.error(LowerDiagnostic::NotCallable(path.span));
+                            return error;
+                        }
+                        None => return error,
+                    },
+                    _ => {
+                        self.error(LowerDiagnostic::NotCallable(callee.span));
+                        return error;
+                    }
+                };
+
+                let signature = self.resolver.proc(callee).clone();
+                if signature.params.len() != args.len() {
+                    self.error(LowerDiagnostic::ArgumentCountMismatch {
+                        name: signature.name,
+                        expected: signature.params.len(),
+                        found: args.len(),
+                        span,
+                        definition: Some(signature.span),
+                    });
+                    return error;
+                }
+
+                for (&expected, arg) in signature.params.iter().zip(&mut args) {
+                    self.coerce_int_literal(expected, arg);
+                    self.check_ty(expected, arg.ty, arg.span);
+                }
+
+                Expr {
+                    kind: ExprKind::Call { callee, args },
+                    ty: signature.ret_ty,
+                    span,
+                }
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                let operand = self.lower_expr(operand);
+                // A literal too large for an `int` is a `u64`, but negated it's one signed value,
+                // which is the smallest `int` at most.
+                if *operator == UnaryOpKind::Neg
+                    && let ExprKind::Literal(Literal::Int(value, IntTy::U64)) = operand.kind
+                {
+                    let Some(stored) = IntTy::I64.store(-IntTy::U64.value(value)) else {
+                        self.error(LowerDiagnostic::IntegerLiteralOutOfRange(Ty::INT, span));
+                        return error;
+                    };
+                    return Expr {
+                        kind: ExprKind::Literal(Literal::Int(stored, IntTy::I64)),
+                        ty: Ty::INT,
+                        span,
+                    };
+                }
+                let op = match operator {
+                    UnaryOpKind::Neg => UnOp::Neg,
+                    UnaryOpKind::LogNot => UnOp::Not,
+                    UnaryOpKind::BwNot => UnOp::BitNot,
+                };
+
+                let Some(ty) = typeck::unary_op_result(op, operand.ty) else {
+                    self.error(LowerDiagnostic::InvalidUnaryOperand(
+                        *operator,
+                        operand.ty,
+                        operand.span,
+                    ));
+                    return error;
+                };
+
+                Expr {
+                    kind: ExprKind::Unary {
+                        op,
+                        operand: Box::new(operand),
+                    },
+                    ty,
+                    span,
+                }
+            }
+            ExpressionKind::Binary {
+                lhs, operator, rhs, ..
+            } if operator.is_assignment() => self.lower_assignment(lhs, *operator, rhs, span),
+            ExpressionKind::Binary {
+                lhs, operator, rhs, ..
+            } => {
+                let lhs = self.lower_expr(lhs);
+                let rhs = self.lower_expr(rhs);
+                self.lower_binary(lhs, *operator, rhs, span)
+            }
+            ExpressionKind::Grouping(inner) => self.lower_expr(inner),
+            ExpressionKind::Match { scrutinee, arms } => self.lower_match(scrutinee, arms, span),
+            ExpressionKind::Array(elements) => self.lower_array(elements, None, span),
+            ExpressionKind::Map(entries) => self.lower_map(entries, None, span),
+            ExpressionKind::Cast { value, ty: target } => {
+                let value = self.lower_expr(value);
+                let to = self.lower_ty(target);
+                let (Ty::Int(_), Ty::Int(to_int)) = (value.ty, to) else {
+                    if value.ty != Ty::Error && to != Ty::Error {
+                        self.error(LowerDiagnostic::InvalidCast(value.ty, to, span));
+                    }
+                    return error;
+                };
+
+                Expr {
+                    kind: ExprKind::Unary {
+                        op: UnOp::Cast(to_int),
+                        operand: Box::new(value),
+                    },
+                    ty: to,
+                    span,
+                }
+            }
+            ExpressionKind::Range { .. } => {
+                unreachable!("the parser only produces ranges as what for loops iterate over")
+            }
+            ExpressionKind::Index { array, index } => {
+                let array = self.lower_expr(array);
+                let mut index = self.lower_expr(index);
+                // Indexing a string reads the char at a byte, so it can't be assigned to.
+                if array.ty == Ty::Str {
+                    return self.lower_builtin_call(Builtin::CharAt, vec![array, index], span);
+                }
+                let (index_ty, ty) = self.indexed_ty(&array);
+                self.coerce_int_literal(index_ty, &mut index);
+                self.check_ty(index_ty, index.ty, index.span);
+
+                Expr {
+                    kind: ExprKind::Index {
+                        array: Box::new(array),
+                        index: Box::new(index),
+                    },
+                    ty,
+                    span,
+                }
+            }
+        }
+    }
+
+    /// Lower an expression used where a value of a type is expected, so array and map literals
+    /// can take their element types from it, even when they're empty, and int literals their int
+    /// type. The caller still checks the type.
+    fn lower_expected(&mut self, expr: &ast::Expression, expected: Ty) -> Expr {
+        match (&expr.kind, expected) {
+            (ExpressionKind::Array(elements), Ty::Array(element)) => {
+                self.lower_array(elements, Some(*element), expr.span)
+            }
+            (ExpressionKind::Map(entries), Ty::Map(key, value)) => {
+                self.lower_map(entries, Some((*key, *value)), expr.span)
+            }
+            (ExpressionKind::Grouping(inner), _) => self.lower_expected(inner, expected),
+            _ => {
+                let mut lowered = self.lower_expr(expr);
+                self.coerce_int_literal(expected, &mut lowered);
+                lowered
+            }
+        }
+    }
+
+    /// Give an expression made only of int literals the int type expected of it. Literals are
+    /// `int`s, or `u64`s if they're too large for one, only when no other int type is expected.
+    fn coerce_int_literal(&mut self, expected: Ty, expr: &mut Expr) {
+        if let Ty::Int(ty) = expected
+            && expr.ty != expected
+            && is_int_literal(expr)
+        {
+            self.retype_int_literal(ty, expr);
+        }
+    }
+
+    fn retype_int_literal(&mut self, ty: IntTy, expr: &mut Expr) {
+        let value = match &mut expr.kind {
+            ExprKind::Literal(Literal::Int(value, from)) => Some(from.value(*value)),
+            // A negated literal is a negative one, which may fit where the literal doesn't.
+            ExprKind::Unary {
+                op: UnOp::Neg,
+                operand,
+            } => match operand.kind {
+                ExprKind::Literal(Literal::Int(value, from)) => Some(-from.value(value)),
+                _ if !ty.is_signed() => {
+                    let error = LowerDiagnostic::InvalidUnaryOperand(
+                        UnaryOpKind::Neg,
+                        Ty::Int(ty),
+                        operand.span,
+                    );
+                    self.error(error);
+                    None
+                }
+                _ => {
+                    self.retype_int_literal(ty, operand);
+                    None
+                }
+            },
+            ExprKind::Unary { operand, .. } => {
+                self.retype_int_literal(ty, operand);
+                None
+            }
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.retype_int_literal(ty, lhs);
+                self.retype_int_literal(ty, rhs);
+                None
+            }
+            _ => unreachable!("only int literals and operators on them are retyped"),
+        };
+
+        if let Some(value) = value {
+            let Some(stored) = ty.store(value) else {
+                self.error(LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(ty), expr.span));
+                expr.kind = ExprKind::Error;
+                expr.ty = Ty::Error;
+                return;
+            };
+            expr.kind = ExprKind::Literal(Literal::Int(stored, ty));
+        }
+        expr.ty = Ty::Int(ty);
+    }
+
+    /// Lower an array literal. Its elements have the expected type if there is one, and the
+    /// type of the first element otherwise.
+    fn lower_array(
+        &mut self,
+        elements: &[ast::Expression],
+        expected: Option<Ty>,
+        span: Span,
+    ) -> Expr {
+        let mut element_ty = expected;
+        let mut lowered = Vec::with_capacity(elements.len());
+        for element in elements {
+            let element = match element_ty {
+                Some(ty) => {
+                    let element = self.lower_expected(element, ty);
+                    self.check_ty(ty, element.ty, element.span);
+                    element
+                }
+                None => {
+                    let element = self.lower_expr(element);
+                    element_ty = Some(element.ty);
+                    element
+                }
+            };
+            lowered.push(element);
+        }
+
+        let Some(element_ty) = element_ty else {
+            self.error(LowerDiagnostic::UnknownElementType(span));
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        };
+
+        if element_ty == Ty::Void
+            && let Some(first) = lowered.first()
+        {
+            self.error(LowerDiagnostic::VoidElement(first.span));
+        }
+
+        Expr {
+            kind: ExprKind::Array(lowered),
+            ty: Ty::array_of(element_ty),
+            span,
+        }
+    }
+
+    /// Lower a map literal. Its keys and values have the expected types if there are some, and
+    /// the types of the first entry's otherwise.
+    fn lower_map(
+        &mut self,
+        entries: &[(ast::Expression, ast::Expression)],
+        expected: Option<(Ty, Ty)>,
+        span: Span,
+    ) -> Expr {
+        let mut entry_tys = expected;
+        let mut lowered = Vec::with_capacity(entries.len());
+        for (key, value) in entries {
+            let entry = match entry_tys {
+                Some((key_ty, value_ty)) => {
+                    let key = self.lower_expected(key, key_ty);
+                    self.check_ty(key_ty, key.ty, key.span);
+                    let value = self.lower_expected(value, value_ty);
+                    self.check_ty(value_ty, value.ty, value.span);
+                    (key, value)
+                }
+                None => {
+                    let key = self.lower_expr(key);
+                    let value = self.lower_expr(value);
+                    if !key.ty.is_key() {
+                        self.error(LowerDiagnostic::InvalidMapKey(key.ty, key.span));
+                    }
+                    if value.ty == Ty::Void {
+                        self.error(LowerDiagnostic::VoidElement(value.span));
+                    }
+                    entry_tys = Some((key.ty, value.ty));
+                    (key, value)
+                }
+            };
+            lowered.push(entry);
+        }
+
+        let Some((key_ty, value_ty)) = entry_tys else {
+            self.error(LowerDiagnostic::UnknownMapType(span));
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        };
+
+        Expr {
+            kind: ExprKind::Map(lowered),
+            ty: Ty::map_of(key_ty, value_ty),
+            span,
+        }
+    }
+
+    /// The type of the elements of an array, reporting an error if it isn't one.
+    fn element_ty(&mut self, array: &Expr) -> Ty {
+        match array.ty {
+            Ty::Array(element) => *element,
+            Ty::Error => Ty::Error,
+            ty => {
+                self.error(LowerDiagnostic::NotAnArray(ty, array.span));
+                Ty::Error
+            }
+        }
+    }
+
+    /// The types of the indexes and elements of an array or map, reporting an error if it's
+    /// neither.
+    fn indexed_ty(&mut self, array: &Expr) -> (Ty, Ty) {
+        array.ty.indexed().unwrap_or_else(|| {
+            if array.ty != Ty::Error {
+                self.error(LowerDiagnostic::NotAnArray(array.ty, array.span));
+            }
+            (Ty::Error, Ty::Error)
+        })
+    }
+
+    /// Lower a call to a builtin, checking its arguments.
+    fn lower_builtin_call(&mut self, builtin: Builtin, mut args: Vec<Expr>, span: Span) -> Expr {
+        let params = builtin.params();
+        if self.sandbox && builtin.accesses_host() {
+            self.error(LowerDiagnostic::Sandboxed(builtin.name(), span));
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        }
+        if params.len() != args.len() {
+            self.error(LowerDiagnostic::ArgumentCountMismatch {
+                name: builtin.name().to_owned(),
+                expected: params.len(),
+                found: args.len(),
+                span,
+                definition: None,
+            });
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        }
+
+        let first = args.first().map_or(Ty::Error, |first| first.ty);
+        for (param, arg) in params.iter().zip(&mut args) {
+            match param {
+                BuiltinParam::Ty(expected) => {
+                    self.coerce_int_literal(*expected, arg);
+                    self.check_ty(*expected, arg.ty, arg.span);
+                }
+                BuiltinParam::Any if arg.ty == Ty::Void => {
+                    let name = builtin.name().to_owned();
+                    self.error(LowerDiagnostic::VoidArgument(name, arg.span));
+                }
+                BuiltinParam::Any => {}
+                BuiltinParam::Sequence => {
+                    if !matches!(arg.ty, Ty::Str | Ty::Array(_) | Ty::Map(..) | Ty::Error) {
+                        let name = builtin.name().to_owned();
+                        self.error(LowerDiagnostic::NotASequence(name, arg.ty, arg.span));
+                    }
+                }
+                BuiltinParam::StrOrChar => {
+                    if !matches!(arg.ty, Ty::Str | Ty::Char | Ty::Error) {
+                        let name = builtin.name().to_owned();
+                        self.error(LowerDiagnostic::NotStrOrChar(name, arg.ty, arg.span));
+                    }
+                }
+                BuiltinParam::Array => {
+                    self.element_ty(arg);
+                }
+                BuiltinParam::Map => {
+                    if !matches!(arg.ty, Ty::Map(..) | Ty::Error) {
+                        self.error(LowerDiagnostic::NotAMap(arg.ty, arg.span));
+                    }
+                }
+                BuiltinParam::Element | BuiltinParam::Key | BuiltinParam::Value => {
+                    let expected = match (param, first) {
+                        (BuiltinParam::Element, Ty::Array(element)) => *element,
+                        (BuiltinParam::Key, Ty::Map(key, _)) => *key,
+                        (BuiltinParam::Value, Ty::Map(_, value)) => *value,
+                        _ => continue,
+                    };
+                    self.coerce_int_literal(expected, arg);
+                    self.check_ty(expected, arg.ty, arg.span);
+                }
+            }
+        }
+
+        let ty = builtin.ret_ty_for(first);
+        Expr {
+            kind: ExprKind::Builtin { builtin, args },
+            ty,
+            span,
+        }
+    }
+
+    /// Lower a call to a native procedure, checking its arguments.
+    fn lower_native_call(&mut self, native: NativeId, mut args: Vec<Expr>, span: Span) -> Expr {
+        if self.sandbox
+            && let Some(system) = SystemNative::from_id(native)
+        {
+            self.error(LowerDiagnostic::Sandboxed(system.name(), span));
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        }
+        let signature = &self.natives[native.0 as usize];
+        let (params, ret_ty) = (signature.params.clone(), signature.ret_ty);
+        if params.len() != args.len() {
+            self.error(LowerDiagnostic::ArgumentCountMismatch {
+                name: signature.name.clone(),
+                expected: params.len(),
+                found: args.len(),
+                span,
+                definition: None,
+            });
+            return Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            };
+        }
+
+        for (&param, arg) in params.iter().zip(&mut args) {
+            self.coerce_int_literal(param, arg);
+            self.check_ty(param, arg.ty, arg.span);
+        }
+
+        Expr {
+            kind: ExprKind::Native { native, args },
+            ty: ret_ty,
+            span,
+        }
+    }
+
+    /// Lower a match, checking that its arms are exhaustive and reachable.
+    fn lower_match(
+        &mut self,
+        scrutinee: &ast::Expression,
+        arms: &[ast::MatchArm],
+        span: Span,
+    ) -> Expr {
+        let scrutinee = self.lower_expr(scrutinee);
+
+        let mut arms = arms
+            .iter()
+            .map(|arm| MatchArm {
+                pat: self.lower_pattern(&arm.pattern, scrutinee.ty),
+                body: self.lower_expr(&arm.body),
+                span: arm.span,
+            })
+            .collect::<Vec<_>>();
+
+        // Every arm must produce a value of the same type as the first.
+        let ty = arms.first().map_or(Ty::Void, |arm| arm.body.ty);
+        for arm in arms.iter_mut().skip(1) {
+            self.coerce_int_literal(ty, &mut arm.body);
+            self.check_ty(ty, arm.body.ty, arm.body.span);
+        }
+
+        // Checking a match with erroneous patterns would only produce misleading diagnostics.
+        if scrutinee.ty != Ty::Error && !arms.iter().any(|arm| contains_error(&arm.pat)) {
+            let check = exhaustiveness::check_match(scrutinee.ty, &arms);
+            for i in check.unreachable_arms {
+                self.warn(LowerDiagnostic::UnreachableArm(arms[i].pat.span));
+            }
+
+            if !check.witnesses.is_empty() {
+                self.error(LowerDiagnostic::NonExhaustiveMatch {
+                    witnesses: exhaustiveness::describe_witnesses(&check.witnesses),
+                    span: scrutinee.span,
+                });
+            }
+        }
+
+        Expr {
+            kind: ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+            ty,
+            span,
+        }
+    }
+
+    /// Lower a pattern matching values of a type.
+    fn lower_pattern(&mut self, pattern: &ast::Pattern, ty: Ty) -> Pat {
+        let span = pattern.span;
+
+        let kind = match &pattern.kind {
+            PatternKind::Wildcard => PatKind::Wildcard,
+            PatternKind::Literal { .. } => self
+                .lower_pattern_literal(pattern, ty)
+                .map_or(PatKind::Error, PatKind::Literal),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if !matches!(ty, Ty::Int(_) | Ty::Char | Ty::Error) {
+                    self.error(LowerDiagnostic::InvalidRangePattern(ty, span));
+                }
+
+                let start = self.lower_pattern_literal(start, ty);
+                let end = self.lower_pattern_literal(end, ty);
+
+                match (start, end) {
+                    (Some(start), Some(end)) if matches!(ty, Ty::Int(_) | Ty::Char) => {
+                        let (first, last) = (
+                            exhaustiveness::literal_value(&start),
+                            exhaustiveness::literal_value(&end),
+                        );
+
+                        if first > last || (!inclusive && first == last) {
+                            self.error(LowerDiagnostic::EmptyRangePattern(span));
+                            PatKind::Error
+                        } else {
+                            PatKind::Range {
+                                start,
+                                end,
+                                inclusive: *inclusive,
+                            }
+                        }
+                    }
+                    _ => PatKind::Error,
+                }
+            }
+            PatternKind::Or(alternatives) => PatKind::Or(
+                alternatives
+                    .iter()
+                    .map(|alternative| self.lower_pattern(alternative, ty))
+                    .collect(),
+            ),
+        };
+
+        Pat { kind, span }
+    }
+
+    /// Lower a literal pattern, checking that it matches values of a type.
+    fn lower_pattern_literal(&mut self, pattern: &ast::Pattern, ty: Ty) -> Option<Literal> {
+        let PatternKind::Literal {
+            kind,
+            text,
+            negated,
+        } = &pattern.kind
+        else {
+            unreachable!("the parser only produces literal range bounds");
+        };
+
+        let literal = self.lower_literal(*kind, text, pattern.span)?;
+
+        // Int literals match ints of the type being matched.
+        if let (Literal::Int(value, from), Ty::Int(int)) = (&literal, ty) {
+            let value = from.value(*value);
+            let value = if *negated { -value } else { value };
+            let Some(stored) = int.store(value) else {
+                self.error(LowerDiagnostic::IntegerLiteralOutOfRange(ty, pattern.span));
+                return None;
+            };
+            return Some(Literal::Int(stored, int));
+        }
+
+        let literal = match literal {
+            Literal::Int(value, IntTy::I64) if *negated => Literal::Int(-value, IntTy::I64),
+            Literal::Float(value) if *negated => Literal::Float(-value),
+            literal if *negated => {
+                self.error(LowerDiagnostic::InvalidUnaryOperand(
+                    UnaryOpKind::Neg,
+                    literal.ty(),
+                    pattern.span,
+                ));
+                return None;
+            }
+            literal => literal,
+        };
+
+        if !ty.accepts(literal.ty()) {
+            self.error(LowerDiagnostic::TypeMismatch {
+                expected: ty,
+                found: literal.ty(),
+                span: pattern.span,
+            });
+            return None;
+        }
+
+        Some(literal)
+    }
+
+    /// Type check and build a binary expression from already lowered operands.
+    fn lower_binary(&mut self, lhs: Expr, operator: BinaryOpKind, rhs: Expr, span: Span) -> Expr {
+        let error = Expr {
+            kind: ExprKind::Error,
+            ty: Ty::Error,
+            span,
+        };
+
+        // A literal operand takes the int type of the other operand.
+        let (mut lhs, mut rhs) = (Box::new(lhs), Box::new(rhs));
+        self.coerce_int_literal(rhs.ty, &mut lhs);
+        self.coerce_int_literal(lhs.ty, &mut rhs);
+        let op = match operator {
+            BinaryOpKind::LogAnd | BinaryOpKind::LogOr => {
+                self.check_ty(Ty::Bool, lhs.ty, lhs.span);
+                self.check_ty(Ty::Bool, rhs.ty, rhs.span);
+
+                let op = if operator == BinaryOpKind::LogAnd {
+                    LogicalOp::And
+                } else {
+                    LogicalOp::Or
+                };
+
+                return Expr {
+                    kind: ExprKind::Logical { lhs, op, rhs },
+                    ty: Ty::Bool,
+                    span,
+                };
+            }
+            BinaryOpKind::Plus | BinaryOpKind::PlusEqual => BinOp::Add,
+            BinaryOpKind::Minus | BinaryOpKind::MinusEqual => BinOp::Sub,
+            BinaryOpKind::Mul | BinaryOpKind::MulEqual => BinOp::Mul,
+            BinaryOpKind::Div | BinaryOpKind::DivEqual => BinOp::Div,
+            BinaryOpKind::Mod | BinaryOpKind::ModEqual => BinOp::Rem,
+            BinaryOpKind::BwAnd | BinaryOpKind::BwAndEqual => BinOp::BitAnd,
+            BinaryOpKind::BwOr | BinaryOpKind::BwOrEqual => BinOp::BitOr,
+            BinaryOpKind::Shl | BinaryOpKind::ShlEqual => BinOp::Shl,
+            BinaryOpKind::Shr | BinaryOpKind::ShrEqual => BinOp::Shr,
+            BinaryOpKind::EqualEqual => BinOp::Eq,
+            BinaryOpKind::NotEqual => BinOp::Ne,
+            BinaryOpKind::Lt => BinOp::Lt,
+            BinaryOpKind::LtEqual => BinOp::Le,
+            BinaryOpKind::Gt => BinOp::Gt,
+            BinaryOpKind::GtEqual => BinOp::Ge,
+            BinaryOpKind::Equal => unreachable!("plain assignments are lowered separately"),
+        };
+
+        let Some(ty) = typeck::binary_op_result(op, lhs.ty, rhs.ty) else {
+            self.error(LowerDiagnostic::InvalidBinaryOperands(
+                operator, lhs.ty, rhs.ty, span,
+            ));
+            return error;
+        };
+
+        Expr {
+            kind: ExprKind::Binary { lhs, op, rhs },
+            ty,
+            span,
+        }
+    }
+
+    /// Lower an assignment to a local or an array element, turning compound assignments to locals
+    /// (`x += 1`) into plain ones (`x = x + 1`).
+    fn lower_assignment(
+        &mut self,
+        target: &ast::Expression,
+        operator: BinaryOpKind,
+        value: &ast::Expression,
+        span: Span,
+    ) -> Expr {
+        let error = Expr {
+            kind: ExprKind::Error,
+            ty: Ty::Error,
+            span,
+        };
+
+        let target_expr = self.lower_expr(target);
+        let value = self.lower_expected(value, target_expr.ty);
+
+        match target_expr.kind {
+            ExprKind::Local(_) | ExprKind::Index { .. } => {}
+            ExprKind::Error => return error,
+            _ => {
+                self.error(LowerDiagnostic::InvalidAssignmentTarget(target.span));
+                return error;
+            }
+        }
+
+        let compound = operator != BinaryOpKind::Equal;
+        let value = if compound {
+            self.lower_binary(target_expr.clone(), operator, value, span)
+        } else {
+            value
+        };
+
+        self.check_ty(target_expr.ty, value.ty, value.span);
+
+        let kind = match target_expr.kind {
+            ExprKind::Local(local) => ExprKind::Assign {
+                local,
+                value: Box::new(value),
+            },
+            // Reading the element again would evaluate the array and index twice, so a compound
+            // assignment keeps only the operator and its right operand.
+            ExprKind::Index { array, index } => {
+                let (op, value) = match value.kind {
+                    ExprKind::Binary { op, rhs, .. } if compound => (Some(op), rhs),
+                    _ => (None, Box::new(value)),
+                };
+                ExprKind::IndexAssign {
+                    array,
+                    index,
+                    op,
+                    value,
+                }
+            }
+            _ => unreachable!("other assignment targets are rejected above"),
+        };
+
+        Expr {
+            kind,
+            ty: Ty::Void,
+            span,
+        }
+    }
+
+    /// Convert the text of a literal into its value.
+    fn lower_literal(&mut self, kind: ast::LiteralKind, text: &str, span: Span) -> Option<Literal> {
+        match kind {
+            ast::LiteralKind::Integer => {
+                let digits = text.replace('_', "");
+                let (digits, radix) = match digits.get(..2) {
+                    Some("0b") => (&digits[2..], 2),
+                    Some("0o") => (&digits[2..], 8),
+                    Some("0x") => (&digits[2..], 16),
+                    _ => (&digits[..], 10),
+                };
+
+                // Literals too large for an `int` are `u64`s, unless they're retyped.
+                let Ok(value) = u64::from_str_radix(digits, radix) else {
+                    let largest = Ty::Int(IntTy::U64);
+                    self.error(LowerDiagnostic::IntegerLiteralOutOfRange(largest, span));
+                    return None;
+                };
+
+                let ty = if IntTy::I64.fits(value.into()) {
+                    IntTy::I64
+                } else {
+                    IntTy::U64
+                };
+                Some(Literal::Int(value as i64, ty))
+            }
+            ast::LiteralKind::Float => {
+                let value = float::parse(text);
+                if value.is_none() {
+                    self.error(LowerDiagnostic::FloatLiteralOutOfRange(span));
+                }
+
+                value.map(Literal::Float)
+            }
+            ast::LiteralKind::Boolean => Some(Literal::Bool(text == "true")),
+            ast::LiteralKind::Character => {
+                let value = self.unescape(&text[1..text.len() - 1], span)?;
+                Some(Literal::Char(value.chars().next()?))
+            }
+            ast::LiteralKind::String => {
+                let value = self.unescape(&text[1..text.len() - 1], span)?;
+                Some(Literal::Str(intern::Symbol::intern(&value)))
+            }
+        }
+    }
+
+    /// Replace the escape sequences in the contents of a character or string literal.
+    fn unescape(&mut self, contents: &str, span: Span) -> Option<String> {
+        let mut value = String::with_capacity(contents.len());
+        let mut chars = contents.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('0') => value.push('\0'),
+                Some(c @ ('\\' | '\'' | '"')) => value.push(c),
+                Some(c) => {
+                    self.error(LowerDiagnostic::UnknownEscapeSequence(c, span));
+                    return None;
+                }
+                None => return None,
+            }
+        }
+
+        Some(value)
+    }
+}
+
+/// A use of a standard library constant, which is its value.
+fn std_const_expr(constant: StdConst, span: Span) -> Expr {
+    Expr {
+        kind: ExprKind::Literal(Literal::Float(constant.value())),
+        ty: Ty::Float,
+        span,
+    }
+}
+
+/// Returns if a block always returns, regardless of which path is taken through it.
+/// Returns if an expression is made only of int literals and the operators on ints, so it can
+/// take its int type from where it's used.
+fn is_int_literal(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(Literal::Int(..)) => true,
+        ExprKind::Unary {
+            op: UnOp::Neg | UnOp::BitNot,
+            operand,
+        } => is_int_literal(operand),
+        ExprKind::Binary { lhs, op, rhs } => {
+            !op.is_comparison() && is_int_literal(lhs) && is_int_literal(rhs)
+        }
+        _ => false,
+    }
+}
+
+/// Returns if a block always returns, or stops the program with a builtin like `panic`.
+fn block_returns(block: &Block) -> bool {
+    block.stmts.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Ret(_) => true,
+        StmtKind::Expr(Expr {
+            kind: ExprKind::Builtin { builtin, .. },
+            ..
+        }) => builtin.diverges(),
+        StmtKind::Block(block) => block_returns(block),
+        StmtKind::If {
+            then_block,
+            else_block: Some(else_block),
+            ..
+        } => block_returns(then_block) && block_returns(else_block),
+        _ => false,
+    })
+}
+
+/// Builds the statements and expressions a `for` loop is lowered to, all at the span of what
+/// it iterates over.
+#[derive(Clone, Copy)]
+struct Machinery(Span);
+
+impl Machinery {
+    fn expr(self, kind: ExprKind, ty: Ty) -> Expr {
+        Expr {
+            kind,
+            ty,
+            span: self.0,
+        }
+    }
+
+    fn stmt(self, kind: StmtKind) -> Stmt {
+        Stmt { kind, span: self.0 }
+    }
+
+    fn local(self, local: LocalId, ty: Ty) -> Expr {
+        self.expr(ExprKind::Local(local), ty)
+    }
+
+    fn int(self, value: i64, ty: IntTy) -> Expr {
+        self.expr(ExprKind::Literal(Literal::Int(value, ty)), Ty::Int(ty))
+    }
+
+    fn binary(self, lhs: Expr, op: BinOp, rhs: Expr, ty: Ty) -> Expr {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        self.expr(ExprKind::Binary { lhs, op, rhs }, ty)
+    }
+
+    fn builtin(self, builtin: Builtin, args: Vec<Expr>, ty: Ty) -> Expr {
+        self.expr(ExprKind::Builtin { builtin, args }, ty)
+    }
+
+    /// `local += step`, for a local of type `ty`.
+    fn increment(self, local: LocalId, step: Expr, ty: Ty) -> Stmt {
+        let value = Box::new(self.binary(self.local(local, ty), BinOp::Add, step, ty));
+        let assign = self.expr(ExprKind::Assign { local, value }, Ty::Void);
+        self.stmt(StmtKind::Expr(assign))
+    }
+}
+
+/// Returns if a pattern failed to lower.
+fn contains_error(pat: &Pat) -> bool {
+    match &pat.kind {
+        PatKind::Error => true,
+        PatKind::Or(alternatives) => alternatives.iter().any(contains_error),
+        _ => false,
+    }
+}
+
+/// A successfully lowered program, along with the warnings reported while lowering it.
+#[derive(Debug)]
+pub struct Lowered {
+    pub program: Program,
+    pub warnings: Vec<LowerDiagnostic>,
+
+    /// Every name that resolves at the top level of the program, for completing names.
+    pub names: Vec<String>,
+}
+
+/// Settings for lowering a program, which are part of its semantics.
+#[derive(Debug, Clone, Default)]
+pub struct LowerOptions<'a> {
+    /// The native procedures the program can call as well as its own and the
+    /// [`SystemNative`]s, which they shadow.
+    pub natives: &'a [NativeSignature],
+
+    /// What integer arithmetic in the program does when it overflows.
+    pub overflow: Overflow,
+
+    /// Reject calls to builtins that access files and to system natives, so the program can be
+    /// run on untrusted input without reaching beyond its standard I/O.
+    pub sandbox: bool,
+
+    /// Which warnings to leave out, and which to make errors.
+    pub levels: ::diagnostics::Levels,
+}
+
+/// Resolve names, type check and lower a program to HIR.
+pub fn lower(program: &ast::Program) -> Result<Lowered, DiagnosticSink> {
+    lower_with(program, &LowerOptions::default())
+}
+
+/// Lower a program like [`lower`], with options other than the defaults.
+pub fn lower_with(
+    program: &ast::Program,
+    options: &LowerOptions<'_>,
+) -> Result<Lowered, DiagnosticSink> {
+    lower_cancellable(program, options, &Cancel::new())
+        .unwrap_or_else(|Cancelled| unreachable!("nothing else holds the token"))
+}
+
+/// Lower a program like [`lower_with`], stopping between constants and procedures if the token
+/// is cancelled.
+pub fn lower_cancellable(
+    program: &ast::Program,
+    options: &LowerOptions<'_>,
+    cancel: &Cancel,
+) -> Result<Result<Lowered, DiagnosticSink>, Cancelled> {
+    let mut cx = LoweringContext::new(options);
+    let mut to_lower = Vec::new();
+    {
+        let _span = tracing::info_span!("resolve", items = program.items.len()).entered();
+        cx.collect_items(&program.items, ModuleId::ROOT, &mut to_lower);
+    }
+    cancel.check()?;
+
+    let _span = tracing::info_span!(
+        "typeck",
+        consts = cx.resolver.const_count(),
+        procs = to_lower.len()
+    )
+    .entered();
+    let consts = (0..cx.resolver.const_count())
+        .map(|i| {
+            cancel.check()?;
+            let id = ConstId(i as u32);
+            let signature = cx.resolver.constant(id);
+            let span = signature.span;
+            Ok(cx.const_value(id, span).map(|value| {
+                let signature = cx.resolver.constant(id);
+                Const {
+                    id,
+                    name: signature.name.clone(),
+                    module: signature.module,
+                    visibility: signature.visibility,
+                    ty: signature.ty,
+                    value,
+                    span,
+                    doc: signature.doc.clone(),
+                }
+            }))
+        })
+        .collect::<Result<Vec<_>, Cancelled>>()?;
+
+    // Procedure bodies only read what's been resolved, so they're type checked in parallel.
+    // Each one's diagnostics and symbols are merged in the order of the procedures, so they
+    // come out the same however the work was split up.
+    let typeck = tracing::Span::current();
+    let lowered: Vec<_> = to_lower
+        .into_par_iter()
+        .map_init(
+            || cx.fork(),
+            |worker, (id, proc, span)| {
+                cancel.check()?;
+                let proc = typeck.in_scope(|| worker.lower_proc(id, proc, span));
+                let diagnostics = std::mem::take(&mut worker.diagnostics);
+                Ok((proc, diagnostics, std::mem::take(&mut worker.symbols)))
+            },
+        )
+        .collect::<Result<_, Cancelled>>()?;
+    let mut procs = Vec::with_capacity(lowered.len());
+    for (proc, diagnostics, symbols) in lowered {
+        procs.push(proc);
+        cx.diagnostics.append(diagnostics);
+        cx.symbols.extend(symbols);
+    }
+
+    if cx.diagnostics.has_errors() {
+        return Ok(Err(cx.diagnostics));
+    }
+
+    cx.symbols.sort_by_key(|symbol| symbol.span.start);
+    cx.resolver.enter_module(ModuleId::ROOT);
+    let names = cx.resolver.names_in_scope();
+
+    Ok(Ok(Lowered {
+        program: Program {
+            modules: cx.resolver.take_modules(),
+            procs,
+            consts: consts.into_iter().flatten().collect(),
+            natives: cx.natives,
+            overflow: cx.overflow,
+            symbols: cx.symbols,
+        },
+        warnings: cx.diagnostics.into_diagnostics(),
+        names,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // The crate rather than this crate's module of the same name.
+    use ::diagnostics::{Level, Levels, Stage, Suggestion};
+
+    fn lower_source(source: &str) -> anyhow::Result<Result<Lowered, DiagnosticSink>> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        Ok(lower(&ast))
+    }
+
+    #[test]
+    fn test_lower_types_every_expression() -> anyhow::Result<()> {
+        let program = lower_source("proc f(x: int) float { ret (2.5 * 2.0); }")?
+            .unwrap()
+            .program;
+        let proc = &program.procs[0];
+
+        assert_eq!(proc.ret_ty, Ty::Float);
+        assert_eq!(proc.local(proc.params[0]).ty, Ty::INT);
+
+        let StmtKind::Ret(Some(value)) = &proc.body.stmts[0].kind else {
+            panic!("expected a return statement");
+        };
+
+        // The grouping is gone, leaving the typed multiplication.
+        assert_eq!(value.ty, Ty::Float);
+        assert!(matches!(
+            value.kind,
+            ExprKind::Binary { op: BinOp::Mul, .. }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_elif_to_nested_if() -> anyhow::Result<()> {
+        let source =
+            "proc f(x: int) int { if x == 1 { ret 1; } elif x == 2 { ret 2; } else { ret 3; } }";
+        let program = lower_source(source)?.unwrap().program;
+
+        let StmtKind::If {
+            else_block: Some(else_block),
+            ..
+        } = &program.procs[0].body.stmts[0].kind
+        else {
+            panic!("expected an if statement");
+        };
+
+        let StmtKind::If {
+            else_block: Some(_),
+            ..
+        } = &else_block.stmts[0].kind
+        else {
+            panic!("expected the elif branch to be lowered to a nested if");
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_for_to_while() -> anyhow::Result<()> {
+        let source = "proc f() void { for let i = 0; i < 10; i += 1 { f(); } }";
+        let program = lower_source(source)?.unwrap().program;
+
+        let StmtKind::Block(block) = &program.procs[0].body.stmts[0].kind else {
+            panic!("expected the for loop to be lowered to a block");
+        };
+
+        assert!(matches!(block.stmts[0].kind, StmtKind::Let { .. }));
+        let StmtKind::While { body, .. } = &block.stmts[1].kind else {
+            panic!("expected a while loop");
+        };
+
+        // The step is an assignment of a binary expression after the body.
+        let StmtKind::Expr(step) = &body.stmts[1].kind else {
+            panic!("expected the step expression");
+        };
+        let ExprKind::Assign { value, .. } = &step.kind else {
+            panic!("expected the compound assignment to be lowered to an assignment");
+        };
+        assert!(matches!(
+            value.kind,
+            ExprKind::Binary { op: BinOp::Add, .. }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_arrays() -> anyhow::Result<()> {
+        let source = "proc f(xs: [[int]]) int {
+                let empty: [float] = [];
+                push(xs, [1, 2]);
+                xs[0][1] += 1;
+                for row in xs { ret pop(row); }
+                ret len(xs);
+            }";
+        let program = lower_source(source)?.unwrap().program;
+        let proc = &program.procs[0];
+
+        assert_eq!(
+            proc.local(proc.params[0]).ty,
+            Ty::array_of(Ty::array_of(Ty::INT))
+        );
+        let StmtKind::Let { local, .. } = &proc.body.stmts[0].kind else {
+            panic!("expected a let statement");
+        };
+        assert_eq!(proc.local(*local).ty, Ty::array_of(Ty::Float));
+
+        let StmtKind::Expr(assignment) = &proc.body.stmts[2].kind else {
+            panic!("expected an expression statement");
+        };
+        let ExprKind::IndexAssign {
+            array,
+            op: Some(BinOp::Add),
+            ..
+        } = &assignment.kind
+        else {
+            panic!("expected a compound assignment to an element");
+        };
+        assert_eq!(array.ty, Ty::array_of(Ty::INT));
+
+        // The loop walks the array by index.
+        let StmtKind::Block(block) = &proc.body.stmts[3].kind else {
+            panic!("expected the for loop to be lowered to a block");
+        };
+        assert!(matches!(block.stmts[2].kind, StmtKind::While { .. }));
+
+        let source = "proc f(x: int) void {
+                let xs = [];
+                let ys = [1, true];
+                x[0] = 1;
+                push(ys, 1.5);
+            }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::UnknownElementType(_),
+                LowerDiagnostic::TypeMismatch {
+                    expected: Ty::INT,
+                    found: Ty::Bool,
+                    ..
+                },
+                LowerDiagnostic::NotAnArray(Ty::INT, _),
+                LowerDiagnostic::TypeMismatch {
+                    expected: Ty::INT,
+                    found: Ty::Float,
+                    ..
+                },
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_maps() -> anyhow::Result<()> {
+        let source = "proc f(sizes: [u8: [str]]) int {
+                let ages = [\"ann\": 31, \"bo\": 27];
+                let empty: [char: bool] = [:];
+                sizes[1] = [\"one\"];
+                ages[\"bo\"] += 1;
+                insert(ages, \"cy\", get(ages, \"bo\", 0));
+                for name in ages { ret ages[name]; }
+                ret len(keys(sizes));
+            }";
+        let program = lower_source(source)?.unwrap().program;
+        let proc = &program.procs[0];
+
+        let sizes = Ty::map_of(Ty::Int(IntTy::U8), Ty::array_of(Ty::Str));
+        assert_eq!(proc.local(proc.params[0]).ty, sizes);
+        let [ages, empty] = [0, 1].map(|i| match &proc.body.stmts[i].kind {
+            StmtKind::Let { local, .. } => proc.local(*local).ty,
+            _ => panic!("expected a let statement"),
+        });
+        assert_eq!(ages, Ty::map_of(Ty::Str, Ty::INT));
+        assert_eq!(empty, Ty::map_of(Ty::Char, Ty::Bool));
+
+        // The key takes the map's key type, like an argument.
+        let StmtKind::Expr(assignment) = &proc.body.stmts[2].kind else {
+            panic!("expected an expression statement");
+        };
+        let ExprKind::IndexAssign { index, .. } = &assignment.kind else {
+            panic!("expected an assignment to a key");
+        };
+        assert_eq!(index.ty, Ty::Int(IntTy::U8));
+
+        let source = "proc f(x: int) void {
+                let m = [:];
+                let prices: [float: str] = [:];
+                let ages = [\"ann\": 31, \"bo\": true];
+                ages[1] = 2;
+                insert(x, 1, 2);
+                let nothing: [int: void] = [:];
+            }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::UnknownMapType(_),
+                LowerDiagnostic::InvalidMapKey(Ty::Float, _),
+                LowerDiagnostic::TypeMismatch {
+                    expected: Ty::INT,
+                    found: Ty::Bool,
+                    ..
+                },
+                LowerDiagnostic::TypeMismatch {
+                    expected: Ty::Str,
+                    found: Ty::INT,
+                    ..
+                },
+                LowerDiagnostic::NotAMap(Ty::INT, _),
+                LowerDiagnostic::VoidElement(_),
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_for_loops() -> anyhow::Result<()> {
+        let source = "proc f(s: str, n: u8) void {
+                for c in s {}
+                for i in n..=255 {}
+                for j in -3..3 {}
+            }";
+        let program = lower_source(source)?.unwrap().program;
+        let proc = &program.procs[0];
+
+        let ty_of = |name: &str| {
+            let local = proc.locals.iter().find(|local| local.name == name);
+            local.map(|local| local.ty)
+        };
+        assert_eq!(ty_of("c"), Some(Ty::Char));
+        // The loop variable takes the type of the bounds, and a literal bound the type of the
+        // other.
+        assert_eq!(ty_of("i"), Some(Ty::Int(IntTy::U8)));
+        assert_eq!(ty_of("j"), Some(Ty::INT));
+
+        let source = "proc f(n: i8) void {
+                for c in 'a'..='z' {}
+                for i in 0..true {}
+                for j in n..1000 {}
+            }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::InvalidRange(Ty::Char, _),
+                LowerDiagnostic::TypeMismatch {
+                    expected: Ty::INT,
+                    found: Ty::Bool,
+                    ..
+                },
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(IntTy::I8), _),
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_smallest_ints() -> anyhow::Result<()> {
+        let source = "proc f() void {
+            let a: i8 = -128;
+            let b: i16 = -32768;
+            let c: i32 = -2147483648;
+            let d: i64 = -9223372036854775808;
+            let e = -9223372036854775808;
+            let f: u8 = 0;
+            let g: u16 = 0;
+            let h: u32 = 0;
+            let i: u64 = 0;
+        }";
+        let program = lower_source(source)?.unwrap().program;
+        let inits: Vec<_> = program.procs[0]
+            .body
+            .stmts
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Let { init, .. } => &init.kind,
+                _ => panic!("expected a let statement"),
+            })
+            .collect();
+        for (init, (ty, smallest)) in inits.into_iter().zip([
+            (IntTy::I8, i8::MIN.into()),
+            (IntTy::I16, i16::MIN.into()),
+            (IntTy::I32, i32::MIN.into()),
+            (IntTy::I64, i64::MIN),
+            (IntTy::I64, i64::MIN),
+            (IntTy::U8, 0),
+            (IntTy::U16, 0),
+            (IntTy::U32, 0),
+            (IntTy::U64, 0),
+        ]) {
+            let ExprKind::Literal(Literal::Int(value, int)) = init else {
+                panic!("expected an int literal, found {init:?}");
+            };
+            assert_eq!((*value, *int), (smallest, ty));
+        }
+
+        let source = "proc f() void {
+            let a: i8 = -129;
+            let b: i32 = -9223372036854775808;
+            let c = -9223372036854775809;
+        }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(IntTy::I8), _),
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(IntTy::I32), _),
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::INT, _),
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_reports_diagnostics() -> anyhow::Result<()> {
+        let source = "proc f() int { let x = 1 + 2.0; ret y; } proc g() int { }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::InvalidBinaryOperands(BinaryOpKind::Plus, Ty::INT, Ty::Float, _),
+                LowerDiagnostic::UnresolvedName(name, _),
+                LowerDiagnostic::MissingReturn(..),
+            ] if name == "y"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_sized_ints() -> anyhow::Result<()> {
+        let source = "proc f(x: u8) i32 {
+            let low: i8 = -128;
+            let y = x + 1;
+            ret match y { 0..128 => y as i32, 128..=255 => 0 - 1 };
+        }";
+        let program = lower_source(source)?.unwrap().program;
+        let proc = &program.procs[0];
+
+        // Literals take the types of the variables and operands they're used with.
+        let StmtKind::Let { init, .. } = &proc.body.stmts[0].kind else {
+            panic!("expected a let statement");
+        };
+        assert!(matches!(
+            init.kind,
+            ExprKind::Literal(Literal::Int(-128, IntTy::I8))
+        ));
+        let StmtKind::Let { local, .. } = &proc.body.stmts[1].kind else {
+            panic!("expected a let statement");
+        };
+        assert_eq!(proc.local(*local).ty, Ty::Int(IntTy::U8));
+
+        let source = "proc f(x: u8, a: i32) void {
+            let big: u8 = 256;
+            let negative: u8 = -1;
+            let n = -x;
+            let sum = a + 1 + x;
+            let truncated = 2.5 as int;
+        }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(IntTy::U8), _),
+                LowerDiagnostic::IntegerLiteralOutOfRange(Ty::Int(IntTy::U8), _),
+                LowerDiagnostic::InvalidUnaryOperand(UnaryOpKind::Neg, Ty::Int(IntTy::U8), _),
+                LowerDiagnostic::InvalidBinaryOperands(
+                    BinaryOpKind::Plus,
+                    Ty::Int(IntTy::I32),
+                    Ty::Int(IntTy::U8),
+                    _
+                ),
+                LowerDiagnostic::InvalidCast(Ty::Float, Ty::INT, _),
+            ]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_reports_in_procedure_order() -> anyhow::Result<()> {
+        let source: String = (0..200)
+            .map(|i| format!("proc f{i}() int {{ ret y{i}; }}\n"))
+            .collect();
+        let diagnostics = lower_source(&source)?.unwrap_err();
+
+        let names: Vec<_> = diagnostics
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| match diagnostic {
+                LowerDiagnostic::UnresolvedName(name, _) => name.clone(),
+                diagnostic => panic!("unexpected diagnostic {diagnostic:?}"),
+            })
+            .collect();
+        let expected: Vec<_> = (0..200).map(|i| format!("y{i}")).collect();
+        assert_eq!(names, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_keeps_doc_comments() -> anyhow::Result<()> {
+        let source = "/// Adds one.
+            ///
+            /// Wraps on overflow.
+            proc f(x: int) int {
+                /// Not documentation.
+                ret x + 1;
+            }
+            proc g() void { }";
+        let program = lower_source(source)?.unwrap().program;
+
+        assert_eq!(
+            program.procs[0].doc.as_deref(),
+            Some("Adds one.\n\nWraps on overflow.")
+        );
+        assert_eq!(program.procs[1].doc, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_unused_value_warnings() -> anyhow::Result<()> {
+        let source = "@must_use proc f(x: int) int { ret x; }
+            proc g(x: int) void {
+                x + 1;
+                f(x) == 1;
+                f(x);
+                x = f(x);
+                for let i = 0; i < x; i + 1 { }
+            }";
+        let lowered = lower_source(source)?.unwrap();
+
+        assert!(matches!(
+            &lowered.warnings[..],
+            [
+                LowerDiagnostic::NoEffect(_),
+                LowerDiagnostic::UnusedMustUse(name, _),
+                LowerDiagnostic::NoEffect(_),
+            ] if name == "f"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_assignment_in_condition() -> anyhow::Result<()> {
+        let source = "proc f(x: int) void { while x = 1 { } }";
+        let Err(diagnostics) = lower_source(source)? else {
+            panic!("expected the assignment to be an error");
+        };
+
+        let [diagnostic] = diagnostics.diagnostics() else {
+            panic!("expected a single diagnostic");
+        };
+        assert!(matches!(
+            diagnostic,
+            LowerDiagnostic::AssignmentInCondition(_)
+        ));
+        assert_eq!(
+            diagnostic.suggestion(),
+            Some(Suggestion::new(Span::from(30..31), "=="))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_argument_count_mismatch_notes_definition() -> anyhow::Result<()> {
+        let source = "proc add(a: int, b: int) int { ret a + b; }
+            proc main() void { add(1); }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+        let [diagnostic] = diagnostics.diagnostics() else {
+            panic!("expected one diagnostic");
+        };
+
+        let [note] = &diagnostic.notes()[..] else {
+            panic!("expected one note");
+        };
+        assert_eq!(note.message, "`add` is defined here");
+        assert_eq!(note.span, Some(Span::from(5..8)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_names_in_scope() -> anyhow::Result<()> {
+        let source = "import math;
+            const LIMIT: int = 3;
+            mod shapes { pub proc square(x: int) int { ret x * x; } }
+            proc main() void { let hidden = 1; }";
+        let names = lower_source(source)?.unwrap().names;
+
+        for name in [
+            "LIMIT",
+            "shapes",
+            "main",
+            "println",
+            "math",
+            "math::sqrt",
+            "math::PI",
+        ] {
+            assert!(names.iter().any(|n| n == name), "`{name}` is missing");
+        }
+        for name in ["hidden", "square", "sqrt"] {
+            assert!(!names.iter().any(|n| n == name), "`{name}` isn't in scope");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_tests() -> anyhow::Result<()> {
+        let source = r#"@test proc adds() void { assert(1 + 1 == 2, "1 + 1 is 2"); }
+            proc helper() void { }"#;
+        let program = lower_source(source)?.unwrap().program;
+        assert!(program.procs[0].test);
+        assert!(!program.procs[1].test);
+
+        let source = "@test proc takes(x: int) void { }
+            @test proc returns() int { ret 1; }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::InvalidTestOrBench("test", first, _),
+                LowerDiagnostic::InvalidTestOrBench("test", second, _),
+            ] if first == "takes" && second == "returns"
+        ));
+
+        let source = "@bench proc measured() void { } @bench proc takes(x: int) void { }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [LowerDiagnostic::InvalidTestOrBench("bench", name, _)] if name == "takes"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_module_paths() -> anyhow::Result<()> {
+        let source = "mod math {
+                pub proc add(a: int, b: int) int { ret id(a) + b; }
+                proc id(a: int) int { ret a; }
+                pub mod inner { pub proc one() int { ret id(1); } }
+            }
+            proc f() int { ret math::add(1, math::inner::one()); }";
+        let program = lower_source(source)?.unwrap().program;
+
+        let add = program.proc_by_name("add").unwrap();
+        assert_eq!(program.symbol_name(add.id), "_MN4math3addE");
+        let one = program.proc_by_name("one").unwrap();
+        assert_eq!(program.module_path(one.module), ["math", "inner"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_std_imports() -> anyhow::Result<()> {
+        let source = "import math;
+            const TAU: float = math::PI * 2.0;
+            mod geometry {
+                pub proc area(r: float) float { ret math::PI * math::pow(r, 2.0); }
+            }
+            proc f() float { ret math::sqrt(geometry::area(1.0)) + TAU; }";
+        let program = lower_source(source)?.unwrap().program;
+
+        assert_eq!(
+            program.symbol_at(source.find("math::pow").unwrap()),
+            Some(Symbol::StdModule(StdModule::Math))
+        );
+        assert_eq!(
+            program.symbol_at(source.find("PI").unwrap()),
+            Some(Symbol::StdConst(StdConst::Pi))
+        );
+
+        let source = "import maths;
+            mod m { import math; }
+            proc f() float { ret math::sqrt(sqrt(2.0)) + m::math::E; }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::UnknownStdModule(maths, _),
+                LowerDiagnostic::UnresolvedName(sqrt, _),
+                LowerDiagnostic::UnresolvedName(math, _),
+                LowerDiagnostic::UnresolvedName(nested, _),
+            ] if maths == "maths" && sqrt == "sqrt" && math == "math" && nested == "m::math"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_reports_private_items() -> anyhow::Result<()> {
+        let source = "mod math {
+                proc id(a: int) int { ret a; }
+                mod inner { pub proc one() int { ret 1; } }
+            }
+            proc f() int { ret math::id(math::inner::one()); }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::PrivateItem { kind: "module", name: inner, .. },
+                LowerDiagnostic::PrivateItem { kind: "procedure", name: id, .. },
+            ] if inner == "inner" && id == "id"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_non_exhaustive_match() -> anyhow::Result<()> {
+        let source = "proc f(x: int, b: bool) int {
+            let y = match b { true => 1 };
+            ret match x { 0 | 1 => 0, 2..=6 => 1, 10..20 => 2 };
+        }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::NonExhaustiveMatch { witnesses: bools, .. },
+                LowerDiagnostic::NonExhaustiveMatch { witnesses: ints, .. },
+            ] if bools == "`false`"
+                && ints == "`-9223372036854775808..=-1`, `7..=9` and `20..=9223372036854775807`"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_unreachable_arm_warning() -> anyhow::Result<()> {
+        let source = "proc f(c: str) int {
+            ret match c { \"a\" => 0, _ => 1, \"b\" => 2 };
+        }";
+        let lowered = lower_source(source)?.unwrap();
+
+        assert!(matches!(
+            lowered.warnings[..],
+            [LowerDiagnostic::UnreachableArm(_)]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_evaluates_consts() -> anyhow::Result<()> {
+        let source = "const AREA: int = math::SIDE * math::SIDE;
+            mod math {
+                pub const SIDE: int = match BASE { 0 => 1, _ => BASE * 4 };
+                const BASE: int = 3;
+            }
+            proc f() int { ret AREA; }";
+        let program = lower_source(source)?.unwrap().program;
+
+        let values = program
+            .consts
+            .iter()
+            .map(|c| (c.name.as_str(), c.value.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                ("AREA", Literal::Int(144, IntTy::I64)),
+                ("SIDE", Literal::Int(12, IntTy::I64)),
+                ("BASE", Literal::Int(3, IntTy::I64)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_reports_const_eval_errors() -> anyhow::Result<()> {
+        let source = "const A: int = B + 1;
+            const B: int = A;
+            const BIG: int = 9223372036854775807 + 1;
+            const CALL: int = f();
+            proc f() int { ret 0; }";
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::ConstEval(ConstEvalError::Cycle(name, _)),
+                LowerDiagnostic::ConstEval(ConstEvalError::Overflow(_)),
+                LowerDiagnostic::ConstEval(ConstEvalError::NonConst("Procedure calls", _)),
+            ] if name == "A"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_builtins() -> anyhow::Result<()> {
+        let source = r#"proc to_str(x: int) int { ret x; }
+            proc f() void {
+                println(read_line());
+                to_str(1);
+            }"#;
+        let lowered = lower_source(source)?.unwrap();
+        let StmtKind::Expr(expr) = &lowered.program.procs[1].body.stmts[0].kind else {
+            panic!("expected an expression statement");
+        };
+        assert!(matches!(
+            &expr.kind,
+            ExprKind::Builtin { builtin: Builtin::Println, args }
+                if matches!(args[0].kind, ExprKind::Builtin { builtin: Builtin::ReadLine, .. })
+        ));
+        // The declared `to_str` shadows the builtin, and calling it has no effect to warn about.
+        assert!(lowered.warnings.is_empty());
+
+        // `panic` never finishes, so it can end a procedure returning a value.
+        let source = r#"proc sign(x: int) int {
+                if x > 0 { ret 1; } elif x == 0 { ret 0; }
+                panic("negative");
+            }"#;
+        assert!(lower_source(source)?.is_ok());
+
+        let source = r#"proc f() void {
+                print(1);
+                println();
+                to_str(print("x"));
+                let p = print;
+                assert(true);
+            }"#;
+        let diagnostics = lower_source(source)?.unwrap_err();
+
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::TypeMismatch { expected: Ty::Str, found: Ty::INT, .. },
+                LowerDiagnostic::ArgumentCountMismatch { expected: 1, found: 0, .. },
+                LowerDiagnostic::VoidArgument(name, _),
+                LowerDiagnostic::ProcAsValue(..),
+                LowerDiagnostic::ArgumentCountMismatch { expected: 2, found: 1, .. },
+            ] if name == "to_str"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_with_levels() -> anyhow::Result<()> {
+        let source = "proc main() void { 1 + 1; }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let lower_at = |level| {
+            let mut levels = Levels::new();
+            levels.set("hir::no_effect", level);
+            let options = LowerOptions {
+                levels,
+                ..Default::default()
+            };
+            lower_with(&ast, &options)
+        };
+
+        assert_eq!(lower_at(Level::Warn).unwrap().warnings.len(), 1);
+        assert!(lower_at(Level::Allow).unwrap().warnings.is_empty());
+        let denied = lower_at(Level::Deny).unwrap_err();
+        assert!(matches!(
+            denied.errors().collect::<Vec<_>>()[..],
+            [LowerDiagnostic::NoEffect(_)]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sandbox_rejects_host_builtins() -> anyhow::Result<()> {
+        let source = r#"proc main() void {
+                println(read_file("in.txt"));
+                write_file("out.txt", read_line());
+                sleep_ms(clock_ms());
+                println(env("HOME"));
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        assert!(lower(&ast).is_ok());
+
+        let options = LowerOptions {
+            sandbox: true,
+            ..Default::default()
+        };
+        let diagnostics = lower_with(&ast, &options).unwrap_err();
+        assert!(matches!(
+            diagnostics.diagnostics(),
+            [
+                LowerDiagnostic::Sandboxed("read_file", _),
+                LowerDiagnostic::Sandboxed("write_file", _),
+                LowerDiagnostic::Sandboxed("clock_ms", _),
+                LowerDiagnostic::Sandboxed("sleep_ms", _),
+                LowerDiagnostic::Sandboxed("env", _),
+            ]
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Symbol name mangling.
+//!
+//! A mangled name is `_M`, then the path to the symbol, with the symbol's own name last, between
+//! `N` and `E`. Each path segment is written as its length in bytes followed by the name. If a
+//! name isn't ASCII, it's written as `u`, the length of its UTF-8 bytes in hex, `_`, and then
+//! the hex itself, so symbols stay valid in object files and C. Generic arguments go between
+//! `I` and `E` after the last segment, using one letter per type, with an array type written as
+//! `A` followed by its element type, and a map type as `M` followed by its key and value types.
+//!
+//! For example, `add` in module `math` is `_MN4math3addE`, and `max<int>` is `_MN3maxIiEE`.
+
+use crate::ty::{IntTy, Ty};
+use std::fmt::{self, Write};
+
+/// The prefix every mangled name starts with.
+const PREFIX: &str = "_M";
+
+/// A symbol name recovered from its mangled form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Demangled {
+    /// The modules containing the symbol, outermost first.
+    pub path: Vec<String>,
+    pub name: String,
+    pub generic_args: Vec<Ty>,
+}
+
+impl fmt::Display for Demangled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for module in &self.path {
+            write!(f, "{module}::")?;
+        }
+
+        write!(f, "{}", self.name)?;
+
+        if let Some((first, rest)) = self.generic_args.split_first() {
+            write!(f, "<{first}")?;
+            for arg in rest {
+                write!(f, ", {arg}")?;
+            }
+            write!(f, ">")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn mangle_ty(mangled: &mut String, ty: Ty) {
+    mangled.push(match ty {
+        Ty::Int(ty) => int_code(ty),
+        Ty::Float => 'f',
+        Ty::Bool => 'b',
+        Ty::Char => 'c',
+        Ty::Str => 's',
+        Ty::Void => 'v',
+        Ty::Array(element) => {
+            mangled.push('A');
+            return mangle_ty(mangled, *element);
+        }
+        Ty::Map(key, value) => {
+            mangled.push('M');
+            mangle_ty(mangled, *key);
+            return mangle_ty(mangled, *value);
+        }
+        Ty::Error => unreachable!("erroneous programs are never compiled"),
+    });
+}
+
+/// The letter for each int type. `int` is `i`, and the others follow the Itanium C++ ABI where
+/// its letters are free.
+const INT_CODES: [(IntTy, char); 8] = [
+    (IntTy::I8, 'a'),
+    (IntTy::I16, 'k'),
+    (IntTy::I32, 'l'),
+    (IntTy::I64, 'i'),
+    (IntTy::U8, 'h'),
+    (IntTy::U16, 't'),
+    (IntTy::U32, 'j'),
+    (IntTy::U64, 'm'),
+];
+
+fn int_code(ty: IntTy) -> char {
+    INT_CODES.iter().find(|&&(int, _)| int == ty).unwrap().1
+}
+
+fn ty_from_code(code: char) -> Option<Ty> {
+    if let Some(&(ty, _)) = INT_CODES.iter().find(|&&(_, int)| int == code) {
+        return Some(Ty::Int(ty));
+    }
+
+    Some(match code {
+        'f' => Ty::Float,
+        'b' => Ty::Bool,
+        'c' => Ty::Char,
+        's' => Ty::Str,
+        'v' => Ty::Void,
+        _ => return None,
+    })
+}
+
+fn mangle_segment(mangled: &mut String, segment: &str) {
+    if segment.is_ascii() {
+        write!(mangled, "{}{segment}", segment.len()).unwrap();
+    } else {
+        let hex = segment
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        write!(mangled, "u{:x}_{hex}", hex.len()).unwrap();
+    }
+}
+
+/// Mangle the name of a symbol, given the path of modules containing it and its generic
+/// arguments.
+pub fn mangle(path: &[&str], name: &str, generic_args: &[Ty]) -> String {
+    let mut mangled = String::from(PREFIX);
+    mangled.push('N');
+
+    for segment in path.iter().chain([&name]) {
+        mangle_segment(&mut mangled, segment);
+    }
+
+    if !generic_args.is_empty() {
+        mangled.push('I');
+        for &ty in generic_args {
+            mangle_ty(&mut mangled, ty);
+        }
+        mangled.push('E');
+    }
+
+    mangled.push('E');
+    mangled
+}
+
+/// Reads a mangled name from front to back.
+struct Demangler<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Demangler<'a> {
+    fn eat(&mut self, c: char) -> bool {
+        if let Some(rest) = self.rest.strip_prefix(c) {
+            self.rest = rest;
+            return true;
+        }
+
+        false
+    }
+
+    /// Take the leading digits of the given radix, parsed as a length.
+    fn length(&mut self, radix: u32) -> Option<usize> {
+        let digits = self
+            .rest
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(self.rest.len());
+        let (length, rest) = self.rest.split_at(digits);
+        self.rest = rest;
+        usize::from_str_radix(length, radix).ok()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a str> {
+        let taken = self.rest.get(..len)?;
+        self.rest = &self.rest[len..];
+        Some(taken)
+    }
+
+    fn ty(&mut self) -> Option<Ty> {
+        let code = self.rest.chars().next()?;
+        self.take(1)?;
+        if code == 'A' {
+            return self.ty().map(Ty::array_of);
+        }
+        if code == 'M' {
+            return Some(Ty::map_of(self.ty()?, self.ty()?));
+        }
+
+        ty_from_code(code)
+    }
+
+    fn segment(&mut self) -> Option<String> {
+        if self.eat('u') {
+            let len = self.length(16)?;
+            if !self.eat('_') || len % 2 != 0 {
+                return None;
+            }
+
+            let hex = self.take(len)?;
+            let bytes = (0..len)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<_>>>()?;
+            return String::from_utf8(bytes).ok();
+        }
+
+        let len = self.length(10)?;
+        self.take(len).map(str::to_owned)
+    }
+}
+
+/// Recover the path, name and generic arguments of a mangled symbol, or `None` if it isn't a
+/// valid mangled name.
+pub fn demangle(symbol: &str) -> Option<Demangled> {
+    let mut demangler = Demangler {
+        rest: symbol.strip_prefix(PREFIX)?,
+    };
+
+    if !demangler.eat('N') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut generic_args = Vec::new();
+
+    loop {
+        if demangler.eat('I') {
+            while !demangler.eat('E') {
+                generic_args.push(demangler.ty()?);
+            }
+
+            if !demangler.eat('E') {
+                return None;
+            }
+            break;
+        }
+
+        if demangler.eat('E') {
+            break;
+        }
+
+        segments.push(demangler.segment()?);
+    }
+
+    let name = segments.pop()?;
+    if !demangler.rest.is_empty() {
+        return None;
+    }
+
+    Some(Demangled {
+        path: segments,
+        name,
+        generic_args,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mangle_round_trip() {
+        let mangled = mangle(&["math"], "add", &[]);
+        assert_eq!(mangled, "_MN4math3addE");
+        assert_eq!(demangle(&mangled).unwrap().to_string(), "math::add");
+
+        let mangled = mangle(&[], "max", &[Ty::INT, Ty::Float]);
+        assert_eq!(mangled, "_MN3maxIifEE");
+        assert_eq!(demangle(&mangled).unwrap().to_string(), "max<int, float>");
+
+        let mangled = mangle(&[], "sum", &[Ty::array_of(Ty::array_of(Ty::INT))]);
+        assert_eq!(mangled, "_MN3sumIAAiEE");
+        assert_eq!(demangle(&mangled).unwrap().to_string(), "sum<[[int]]>");
+
+        let map = Ty::map_of(Ty::Str, Ty::array_of(Ty::Char));
+        let mangled = mangle(&[], "count", &[map]);
+        assert_eq!(mangled, "_MN5countIMsAcEE");
+        assert_eq!(
+            demangle(&mangled).unwrap().to_string(),
+            "count<[str: [char]]>"
+        );
+
+        let mangled = mangle(&[], "f", &[Ty::Int(IntTy::U8), Ty::Int(IntTy::I32)]);
+        assert_eq!(mangled, "_MN1fIhlEE");
+        assert_eq!(demangle(&mangled).unwrap().to_string(), "f<u8, i32>");
+
+        let mangled = mangle(&["größe"], "é", &[]);
+        assert_eq!(mangled, "_MNue_6772c3b6c39f65u4_c3a9E");
+        assert_eq!(demangle(&mangled).unwrap().to_string(), "größe::é");
+    }
+
+    #[test]
+    fn test_demangle_rejects_invalid_names() {
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_MN3maE"), None);
+        assert_eq!(demangle("_MNE"), None);
+        assert_eq!(demangle("_MN3maxIxEE"), None);
+        assert_eq!(demangle("_MN3maxIAEE"), None);
+        assert_eq!(demangle("_MN3addEtrailing"), None);
+    }
+}
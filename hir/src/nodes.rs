@@ -0,0 +1,509 @@
+use crate::{
+    builtins::Builtin,
+    mangle,
+    overflow::Overflow,
+    stdlib::{StdConst, StdModule},
+    ty::{IntTy, Ty},
+};
+use parser::ast::Visibility;
+use span::{Span, Spanned};
+
+/// Identifies a module within a program. Indexes into [`Program::modules`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(pub u32);
+
+impl ModuleId {
+    /// The module containing the program's top-level items.
+    pub const ROOT: Self = Self(0);
+}
+
+/// Identifies a procedure within a program. Indexes into [`Program::procs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcId(pub u32);
+
+/// Identifies a constant within a program. Indexes into [`Program::consts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConstId(pub u32);
+
+/// Identifies a native procedure the program can call. Indexes into [`Program::natives`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NativeId(pub u32);
+
+/// Identifies a local variable (including parameters) within a procedure. Indexes into
+/// [`Proc::locals`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalId(pub u32);
+
+/// A module, procedure or local a name in the source defines or refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Module(ModuleId),
+    Proc(ProcId),
+    Const(ConstId),
+    Local(ProcId, LocalId),
+    Builtin(Builtin),
+    Native(NativeId),
+    StdModule(StdModule),
+    StdConst(StdConst),
+}
+
+/// The signature of a procedure implemented by the program's host rather than in matrix, which
+/// the program is lowered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeSignature {
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub ret_ty: Ty,
+}
+
+/// A native procedure every host provides, reaching outside the program to its environment or
+/// clock.
+///
+/// Programs are lowered with these before the natives of their options, so they take the first
+/// [`NativeId`]s, and a sandboxed program can't call them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemNative {
+    /// `env(name: str) str` is the value of an environment variable, or an empty string if it
+    /// isn't set.
+    Env,
+
+    /// `clock_ms() int` is the time in milliseconds since a point before the program started,
+    /// which only ever increases, for measuring how long something takes.
+    ClockMs,
+
+    /// `sleep_ms(ms: int) void` pauses the program for a number of milliseconds, or not at all
+    /// if it isn't positive.
+    SleepMs,
+}
+
+impl SystemNative {
+    pub const ALL: [Self; 3] = [Self::Env, Self::ClockMs, Self::SleepMs];
+
+    /// The system native a native of a program is, if it's one.
+    pub fn from_id(id: NativeId) -> Option<Self> {
+        Self::ALL.get(id.0 as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Env => "env",
+            Self::ClockMs => "clock_ms",
+            Self::SleepMs => "sleep_ms",
+        }
+    }
+
+    pub fn signature(self) -> NativeSignature {
+        let (params, ret_ty) = match self {
+            Self::Env => (vec![Ty::Str], Ty::Str),
+            Self::ClockMs => (vec![], Ty::INT),
+            Self::SleepMs => (vec![Ty::INT], Ty::Void),
+        };
+        NativeSignature {
+            name: self.name().to_owned(),
+            params,
+            ret_ty,
+        }
+    }
+}
+
+/// A fully resolved and type checked program.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub modules: Vec<Module>,
+    pub procs: Vec<Proc>,
+    pub consts: Vec<Const>,
+
+    /// The native procedures the program was lowered with, indexed by [`NativeId`].
+    pub natives: Vec<NativeSignature>,
+
+    /// What integer arithmetic does when it overflows.
+    pub overflow: Overflow,
+
+    /// The symbol every resolved name in the source defines or refers to, with the name's span,
+    /// in source order.
+    pub symbols: Vec<Spanned<Symbol>>,
+}
+
+impl Program {
+    /// Get a procedure by its id.
+    pub fn proc(&self, id: ProcId) -> &Proc {
+        &self.procs[id.0 as usize]
+    }
+
+    /// Find a procedure by name.
+    pub fn proc_by_name(&self, name: &str) -> Option<&Proc> {
+        self.procs.iter().find(|p| p.name == name)
+    }
+
+    /// Get a constant by its id.
+    pub fn constant(&self, id: ConstId) -> &Const {
+        &self.consts[id.0 as usize]
+    }
+
+    /// Get the signature of a native procedure by its id.
+    pub fn native(&self, id: NativeId) -> &NativeSignature {
+        &self.natives[id.0 as usize]
+    }
+
+    /// Get a module by its id.
+    pub fn module(&self, id: ModuleId) -> &Module {
+        &self.modules[id.0 as usize]
+    }
+
+    /// The names of the modules leading to a module from the root, outermost first.
+    pub fn module_path(&self, id: ModuleId) -> Vec<&str> {
+        let mut path = std::iter::successors(Some(id), |&id| self.module(id).parent)
+            .take_while(|&id| id != ModuleId::ROOT)
+            .map(|id| self.module(id).name.as_str())
+            .collect::<Vec<_>>();
+        path.reverse();
+        path
+    }
+
+    /// A procedure's name, qualified with the path of modules containing it, such as
+    /// `math::add`.
+    pub fn qualified_name(&self, id: ProcId) -> String {
+        let proc = self.proc(id);
+        let mut path = self.module_path(proc.module);
+        path.push(&proc.name);
+        path.join("::")
+    }
+
+    /// The mangled name of a procedure's symbol.
+    pub fn symbol_name(&self, id: ProcId) -> String {
+        let proc = self.proc(id);
+        mangle::mangle(&self.module_path(proc.module), &proc.name, &[])
+    }
+
+    /// Find the symbol a name starting at a byte offset defines or refers to.
+    pub fn symbol_at(&self, offset: usize) -> Option<Symbol> {
+        self.symbols
+            .binary_search_by_key(&offset, |symbol| symbol.span.start)
+            .ok()
+            .map(|i| self.symbols[i].node)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// The module's name, which is empty for the root module.
+    pub name: String,
+
+    /// The module containing this one, or `None` for the root module.
+    pub parent: Option<ModuleId>,
+    pub visibility: Visibility,
+
+    /// The span of the module's name in its declaration.
+    pub span: Span,
+}
+
+/// A constant, with its value evaluated at compile time.
+#[derive(Debug, Clone)]
+pub struct Const {
+    pub id: ConstId,
+    pub name: String,
+    pub module: ModuleId,
+    pub visibility: Visibility,
+    pub ty: Ty,
+    pub value: Literal,
+
+    /// The span of the constant's name in its definition.
+    pub span: Span,
+    pub doc: Option<String>,
+}
+
+/// A local variable or parameter.
+#[derive(Debug, Clone)]
+pub struct Local {
+    pub name: String,
+    pub ty: Ty,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Proc {
+    pub id: ProcId,
+    pub name: String,
+    pub module: ModuleId,
+
+    /// The locals holding the arguments, in declaration order.
+    pub params: Vec<LocalId>,
+
+    /// Every local declared in the procedure, including the parameters.
+    pub locals: Vec<Local>,
+    pub ret_ty: Ty,
+    pub body: Block,
+    pub span: Span,
+
+    /// The procedure's doc comments.
+    pub doc: Option<String>,
+
+    /// Whether the procedure is a test (`@test`), which takes no arguments and returns `void`.
+    pub test: bool,
+
+    /// Whether the procedure is a benchmark (`@bench`), which takes no arguments and returns
+    /// `void` too.
+    pub bench: bool,
+}
+
+impl Proc {
+    /// Get a local by its id.
+    pub fn local(&self, id: LocalId) -> &Local {
+        &self.locals[id.0 as usize]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    /// Initialize a local.
+    Let { local: LocalId, init: Expr },
+
+    /// Evaluate an expression for its side effects.
+    Expr(Expr),
+
+    /// Return from the procedure.
+    Ret(Option<Expr>),
+
+    /// A conditional. `elif` chains are lowered to nested ifs in the else block.
+    If {
+        cond: Expr,
+        then_block: Block,
+        else_block: Option<Block>,
+    },
+
+    /// A pre-tested loop. `for` loops, including those over arrays, are lowered to a while loop
+    /// in a block.
+    While { cond: Expr, body: Block },
+
+    /// An unconditional loop that can only be left with `Break` or `Ret`. `do`-`while` loops are
+    /// lowered to one of these.
+    Loop(Block),
+
+    /// Exit the innermost `While` or `Loop`.
+    Break,
+
+    /// A nested scope.
+    Block(Block),
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    /// An int of a type, stored like [`IntTy::wrap`] stores it.
+    Int(i64, IntTy),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+
+    /// An interned string, so literals of the same text are compared without reading it.
+    Str(intern::Symbol),
+}
+
+impl Literal {
+    /// The type of the literal's value.
+    pub fn ty(&self) -> Ty {
+        match self {
+            Self::Int(_, ty) => Ty::Int(*ty),
+            Self::Float(_) => Ty::Float,
+            Self::Bool(_) => Ty::Bool,
+            Self::Char(_) => Ty::Char,
+            Self::Str(_) => Ty::Str,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    /// -
+    Neg,
+
+    /// !
+    Not,
+
+    /// ~
+    BitNot,
+
+    /// `as`, converting an int to another int type.
+    Cast(IntTy),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    /// Returns if this operator compares its operands, producing a bool.
+    pub fn is_comparison(self) -> bool {
+        use BinOp::*;
+
+        matches!(self, Eq | Ne | Lt | Le | Gt | Ge)
+    }
+}
+
+/// Short-circuiting operators, which only evaluate their right operand when needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogicalOp {
+    /// &&
+    And,
+
+    /// ||
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Literal(Literal),
+
+    /// Read a local.
+    Local(LocalId),
+
+    /// Read a constant.
+    Const(ConstId),
+
+    /// Call a procedure.
+    Call {
+        callee: ProcId,
+        args: Vec<Expr>,
+    },
+
+    /// Call a builtin procedure.
+    Builtin {
+        builtin: Builtin,
+        args: Vec<Expr>,
+    },
+
+    /// Call a native procedure.
+    Native {
+        native: NativeId,
+        args: Vec<Expr>,
+    },
+
+    Unary {
+        op: UnOp,
+        operand: Box<Expr>,
+    },
+
+    Binary {
+        lhs: Box<Expr>,
+        op: BinOp,
+        rhs: Box<Expr>,
+    },
+
+    Logical {
+        lhs: Box<Expr>,
+        op: LogicalOp,
+        rhs: Box<Expr>,
+    },
+
+    /// Store into a local. Compound assignments (`x += 1`) are lowered to a plain assignment of a
+    /// binary expression.
+    Assign {
+        local: LocalId,
+        value: Box<Expr>,
+    },
+
+    /// Evaluate the body of the first arm whose pattern matches the scrutinee. Every match is
+    /// checked to be exhaustive, so exactly one arm is always taken.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+
+    /// Allocate a new array holding the values of the elements.
+    Array(Vec<Expr>),
+
+    /// Allocate a new map holding the entries, with the key and value of each. A key given
+    /// twice has the last value given for it.
+    Map(Vec<(Expr, Expr)>),
+
+    /// Read an element of an array, which fails if the index is out of bounds, or the value of
+    /// a key in a map, which fails if the map doesn't have the key.
+    Index {
+        array: Box<Expr>,
+        index: Box<Expr>,
+    },
+
+    /// Store into an element of an array, which fails if the index is out of bounds, or set the
+    /// value of a key in a map, adding the key if it's new. A compound assignment (`xs[i] += 1`)
+    /// has the operator applied to the element and the value, and evaluates the array and index
+    /// only once.
+    IndexAssign {
+        array: Box<Expr>,
+        index: Box<Expr>,
+        op: Option<BinOp>,
+        value: Box<Expr>,
+    },
+
+    /// An expression that failed to lower. Only produced alongside a diagnostic, so it never
+    /// appears in a successfully lowered program.
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Ty,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatKind {
+    /// Matches any value.
+    Wildcard,
+
+    /// Matches a single value. Negative literals are folded into the value.
+    Literal(Literal),
+
+    /// Matches every value between two bounds. Only `int` and `char` have range patterns.
+    Range {
+        start: Literal,
+        end: Literal,
+        inclusive: bool,
+    },
+
+    /// Matches if any alternative matches.
+    Or(Vec<Pat>),
+
+    /// A pattern that failed to lower. Like [`ExprKind::Error`], only produced alongside a
+    /// diagnostic.
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pat {
+    pub kind: PatKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pat: Pat,
+    pub body: Expr,
+    pub span: Span,
+}
@@ -0,0 +1,160 @@
+use crate::{nodes::BinOp, ty::IntTy};
+
+/// What integer arithmetic does when its result doesn't fit in its int type. A program is
+/// lowered with one mode, which every way of evaluating it honors.
+///
+/// Only overflow is affected: division by zero and shifts by at least the width of the type
+/// are errors in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Overflow {
+    /// Overflow is an error.
+    #[default]
+    Checked,
+
+    /// Results wrap around in two's complement.
+    Wrapping,
+
+    /// Results are clamped to the smallest or largest int of their type.
+    Saturating,
+}
+
+impl Overflow {
+    pub const ALL: [Self; 3] = [Self::Checked, Self::Wrapping, Self::Saturating];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Checked => "checked",
+            Self::Wrapping => "wrapping",
+            Self::Saturating => "saturating",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// Store a value as an int type, following the mode if it doesn't fit: returning `None`
+    /// if overflow is checked. This is also how `as` converts ints.
+    pub fn fit(self, ty: IntTy, value: i128) -> Option<i64> {
+        match self {
+            _ if ty.fits(value) => Some(ty.wrap(value)),
+            Self::Checked => None,
+            Self::Wrapping => Some(ty.wrap(value)),
+            Self::Saturating => Some(ty.wrap(value.clamp(ty.min(), ty.max()))),
+        }
+    }
+
+    /// Negate an int of a signed type, returning `None` if it overflows and overflow is
+    /// checked.
+    pub fn neg(self, ty: IntTy, value: i64) -> Option<i64> {
+        self.fit(ty, -ty.value(value))
+    }
+
+    /// Apply an arithmetic operator to ints of a type with a non-zero divisor, returning
+    /// `None` if it overflows and overflow is checked.
+    pub fn arithmetic(self, op: BinOp, ty: IntTy, lhs: i64, rhs: i64) -> Option<i64> {
+        use BinOp::*;
+
+        let (lhs, rhs) = (ty.value(lhs), ty.value(rhs));
+        let exact = match op {
+            Add => lhs + rhs,
+            Sub => lhs - rhs,
+            // Only the product of two large `u64`s doesn't fit in an `i128`. It's positive, and
+            // wraps around like the `i128` does, since both are truncated.
+            Mul => match lhs.checked_mul(rhs) {
+                Some(product) => product,
+                None if self == Self::Checked => return None,
+                None if self == Self::Wrapping => return Some(ty.wrap(lhs.wrapping_mul(rhs))),
+                None => return Some(ty.wrap(ty.max())),
+            },
+            Div => lhs / rhs,
+            // The remainder of the one overflowing division, the minimum by -1, is 0, but it
+            // still overflows where overflow is checked.
+            Rem if self == Self::Checked && !ty.fits(lhs / rhs) => return None,
+            Rem => lhs % rhs,
+            op => unreachable!("`{op:?}` isn't an arithmetic operator"),
+        };
+        self.fit(ty, exact)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_modes() {
+        let (min, max) = (i64::MIN, i64::MAX);
+        let int = IntTy::I64;
+
+        assert_eq!(Overflow::Checked.arithmetic(BinOp::Add, int, max, 1), None);
+        assert_eq!(
+            Overflow::Wrapping.arithmetic(BinOp::Add, int, max, 1),
+            Some(min)
+        );
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Add, int, max, 1),
+            Some(max)
+        );
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Mul, int, min, 2),
+            Some(min)
+        );
+        assert_eq!(
+            Overflow::Wrapping.arithmetic(BinOp::Div, int, min, -1),
+            Some(min)
+        );
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Div, int, min, -1),
+            Some(max)
+        );
+        assert_eq!(Overflow::Checked.arithmetic(BinOp::Rem, int, min, -1), None);
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Rem, int, min, -1),
+            Some(0)
+        );
+        assert_eq!(Overflow::Checked.neg(int, min), None);
+        assert_eq!(Overflow::Saturating.neg(int, min), Some(max));
+        assert_eq!(Overflow::from_name("wrapping"), Some(Overflow::Wrapping));
+    }
+
+    #[test]
+    fn test_overflow_at_each_width() {
+        assert_eq!(
+            Overflow::Checked.arithmetic(BinOp::Add, IntTy::I8, 127, 1),
+            None
+        );
+        assert_eq!(
+            Overflow::Wrapping.arithmetic(BinOp::Add, IntTy::I8, 127, 1),
+            Some(-128)
+        );
+        assert_eq!(
+            Overflow::Wrapping.arithmetic(BinOp::Sub, IntTy::U8, 0, 1),
+            Some(255)
+        );
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Sub, IntTy::U32, 0, 1),
+            Some(0)
+        );
+        // A `u64` past `i64::MAX` is stored with its bits.
+        assert_eq!(
+            Overflow::Checked.arithmetic(BinOp::Div, IntTy::U64, -2, 2),
+            Some(i64::MAX)
+        );
+        assert_eq!(
+            Overflow::Checked.arithmetic(BinOp::Mul, IntTy::U64, -1, -1),
+            None
+        );
+        assert_eq!(
+            Overflow::Wrapping.arithmetic(BinOp::Mul, IntTy::U64, -1, -1),
+            Some(1)
+        );
+        assert_eq!(
+            Overflow::Saturating.arithmetic(BinOp::Mul, IntTy::U64, -1, 2),
+            Some(-1)
+        );
+        assert_eq!(Overflow::Checked.fit(IntTy::U16, -1), None);
+        assert_eq!(Overflow::Wrapping.fit(IntTy::I16, 0x1_8000), Some(-0x8000));
+        assert_eq!(Overflow::Saturating.fit(IntTy::I16, 0x1_8000), Some(0x7fff));
+    }
+}
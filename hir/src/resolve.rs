@@ -0,0 +1,364 @@
+use crate::{
+    builtins::Builtin,
+    nodes::{ConstId, LocalId, Module, ModuleId, NativeId, NativeSignature, ProcId},
+    stdlib::{StdConst, StdModule},
+    ty::Ty,
+};
+use intern::Symbol;
+use parser::ast::Visibility;
+use span::Span;
+use std::collections::{HashMap, HashSet};
+
+/// The signature of a procedure. Signatures are collected before any body is lowered, so a
+/// procedure can be called before it is defined.
+#[derive(Debug, Clone)]
+pub struct ProcSignature {
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub ret_ty: Ty,
+
+    /// The span of the procedure's name in its definition.
+    pub span: Span,
+
+    /// The procedure's doc comments.
+    pub doc: Option<String>,
+
+    /// Whether discarding the procedure's result should be warned about (`@must_use`).
+    pub must_use: bool,
+
+    /// Whether the procedure is a test (`@test`), which `mtxc test` runs.
+    pub test: bool,
+
+    /// Whether the procedure is a benchmark (`@bench`), which `mtxc bench` runs.
+    pub bench: bool,
+
+    /// The module the procedure is defined in.
+    pub module: ModuleId,
+    pub visibility: Visibility,
+}
+
+/// The declaration of a constant. Its value is only known once its initializer is evaluated.
+#[derive(Debug, Clone)]
+pub struct ConstSignature {
+    pub name: String,
+    pub ty: Ty,
+
+    /// The span of the constant's name in its definition.
+    pub span: Span,
+
+    /// The constant's doc comments.
+    pub doc: Option<String>,
+
+    /// The module the constant is defined in.
+    pub module: ModuleId,
+    pub visibility: Visibility,
+}
+
+/// What a name refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Local(LocalId),
+    Proc(ProcId),
+    Const(ConstId),
+    Builtin(Builtin),
+    Native(NativeId),
+    StdConst(StdConst),
+}
+
+/// Resolves names to modules, procedures, constants and locals, tracking the module and lexical
+/// scopes of the procedure being lowered.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    /// Every module in the program, indexed by [`ModuleId`]. The root module is first.
+    modules: Vec<Module>,
+    module_names: HashMap<(ModuleId, Symbol), ModuleId>,
+
+    /// Every procedure in the program, indexed by [`ProcId`].
+    procs: Vec<ProcSignature>,
+
+    /// Every constant in the program, indexed by [`ConstId`].
+    consts: Vec<ConstSignature>,
+
+    /// The procedures and constants declared in each module, which share a namespace.
+    values: HashMap<(ModuleId, Symbol), Resolution>,
+
+    /// The native procedures the program is lowered with.
+    natives: HashMap<Symbol, NativeId>,
+
+    /// The standard library modules imported into each module.
+    imports: HashSet<(ModuleId, StdModule)>,
+
+    /// The module containing the procedure currently being lowered.
+    module: ModuleId,
+
+    /// The scopes of the procedure currently being lowered, innermost last.
+    scopes: Vec<HashMap<Symbol, LocalId>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self {
+            modules: vec![Module {
+                name: String::new(),
+                parent: None,
+                visibility: Visibility::Public,
+                span: Span::from(0..0),
+            }],
+            module_names: HashMap::new(),
+            procs: Vec::new(),
+            consts: Vec::new(),
+            values: HashMap::new(),
+            natives: HashMap::new(),
+            imports: HashSet::new(),
+            module: ModuleId::ROOT,
+            scopes: Vec::new(),
+        }
+    }
+}
+
+impl Resolver {
+    /// Declare a module, returning the previous declaration if the name is taken in its parent.
+    pub fn declare_module(&mut self, module: Module) -> Result<ModuleId, &Module> {
+        let parent = module.parent.expect("only the root module has no parent");
+        let name = Symbol::intern(&module.name);
+        if let Some(&previous) = self.module_names.get(&(parent, name)) {
+            return Err(&self.modules[previous.0 as usize]);
+        }
+
+        let id = ModuleId(self.modules.len() as u32);
+        self.module_names.insert((parent, name), id);
+        self.modules.push(module);
+        Ok(id)
+    }
+
+    /// Get a module.
+    pub fn module(&self, id: ModuleId) -> &Module {
+        &self.modules[id.0 as usize]
+    }
+
+    /// Take every declared module, indexed by [`ModuleId`].
+    pub fn take_modules(&mut self) -> Vec<Module> {
+        std::mem::take(&mut self.modules)
+    }
+
+    /// The span of the definition of a procedure or constant with a name in a module, if the
+    /// name is taken.
+    fn previous_value(&self, module: ModuleId, name: &str) -> Option<Span> {
+        match self.module_value(module, name)? {
+            Resolution::Proc(id) => Some(self.proc(id).span),
+            Resolution::Const(id) => Some(self.constant(id).span),
+            Resolution::Local(_)
+            | Resolution::Builtin(_)
+            | Resolution::Native(_)
+            | Resolution::StdConst(_) => {
+                unreachable!("only procedures and constants are declared in modules")
+            }
+        }
+    }
+
+    /// Declare a procedure in its module, returning the span of the previous definition if the
+    /// name is taken.
+    pub fn declare_proc(&mut self, signature: ProcSignature) -> Result<ProcId, Span> {
+        if let Some(previous) = self.previous_value(signature.module, &signature.name) {
+            return Err(previous);
+        }
+
+        let id = ProcId(self.procs.len() as u32);
+        self.values.insert(
+            (signature.module, Symbol::intern(&signature.name)),
+            Resolution::Proc(id),
+        );
+        self.procs.push(signature);
+        Ok(id)
+    }
+
+    /// Get the signature of a procedure.
+    pub fn proc(&self, id: ProcId) -> &ProcSignature {
+        &self.procs[id.0 as usize]
+    }
+
+    /// Declare a constant in its module, returning the span of the previous definition if the
+    /// name is taken.
+    pub fn declare_const(&mut self, signature: ConstSignature) -> Result<ConstId, Span> {
+        if let Some(previous) = self.previous_value(signature.module, &signature.name) {
+            return Err(previous);
+        }
+
+        let id = ConstId(self.consts.len() as u32);
+        self.values.insert(
+            (signature.module, Symbol::intern(&signature.name)),
+            Resolution::Const(id),
+        );
+        self.consts.push(signature);
+        Ok(id)
+    }
+
+    /// Get the declaration of a constant.
+    pub fn constant(&self, id: ConstId) -> &ConstSignature {
+        &self.consts[id.0 as usize]
+    }
+
+    /// Declare the native procedures the program can call. A later native shadows an earlier
+    /// one with the same name.
+    pub fn declare_natives(&mut self, natives: &[NativeSignature]) {
+        for (i, native) in natives.iter().enumerate() {
+            self.natives
+                .insert(Symbol::intern(&native.name), NativeId(i as u32));
+        }
+    }
+
+    /// Import a standard library module into a module, making it usable there and in the
+    /// modules nested in it. Importing a module again does nothing.
+    pub fn declare_import(&mut self, module: ModuleId, std_module: StdModule) {
+        self.imports.insert((module, std_module));
+    }
+
+    /// The number of constants declared.
+    pub fn const_count(&self) -> usize {
+        self.consts.len()
+    }
+
+    /// The module names are currently resolved in.
+    pub fn current_module(&self) -> ModuleId {
+        self.module
+    }
+
+    /// Set the module names are resolved in.
+    pub fn enter_module(&mut self, module: ModuleId) {
+        self.module = module;
+    }
+
+    /// The current module and the modules containing it, innermost first.
+    fn enclosing_modules(&self) -> impl Iterator<Item = ModuleId> + '_ {
+        std::iter::successors(Some(self.module), |&id| self.module(id).parent)
+    }
+
+    /// Enter a new lexical scope.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Leave the innermost lexical scope, forgetting the locals declared in it.
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Declare a local in the innermost scope, shadowing any previous local with the same name.
+    pub fn declare_local(&mut self, name: &str, id: LocalId) {
+        self.scopes
+            .last_mut()
+            .expect("locals are only declared within a scope")
+            .insert(Symbol::intern(name), id);
+    }
+
+    /// Resolve a name, with locals shadowing items, items in the current module shadowing those
+    /// in the modules containing it, items shadowing natives and natives shadowing builtins.
+    /// A name that was never interned can't have been declared, so it can only be a builtin.
+    pub fn resolve(&self, name: &str) -> Option<Resolution> {
+        Symbol::get(name)
+            .and_then(|symbol| self.resolve_declared(symbol))
+            .or_else(|| Builtin::from_name(name).map(Resolution::Builtin))
+    }
+
+    /// Resolve a name to a local, item or native.
+    fn resolve_declared(&self, name: Symbol) -> Option<Resolution> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .map(|&id| Resolution::Local(id))
+            .or_else(|| {
+                self.enclosing_modules()
+                    .find_map(|module| self.values.get(&(module, name)).copied())
+            })
+            .or_else(|| self.natives.get(&name).map(|&id| Resolution::Native(id)))
+    }
+
+    /// Resolve the first segment of a path to a module visible from the current module.
+    pub fn resolve_module(&self, name: &str) -> Option<ModuleId> {
+        self.enclosing_modules()
+            .find_map(|module| self.module_child(module, name))
+    }
+
+    /// Resolve the first segment of a path to a standard library module imported into the
+    /// current module or a module containing it.
+    pub fn resolve_import(&self, name: &str) -> Option<StdModule> {
+        StdModule::from_name(name).filter(|&std_module| {
+            self.enclosing_modules()
+                .any(|module| self.imports.contains(&(module, std_module)))
+        })
+    }
+
+    /// Find a module declared directly in another.
+    pub fn module_child(&self, module: ModuleId, name: &str) -> Option<ModuleId> {
+        let name = Symbol::get(name)?;
+        self.module_names.get(&(module, name)).copied()
+    }
+
+    /// Find a procedure or constant declared directly in a module.
+    pub fn module_value(&self, module: ModuleId, name: &str) -> Option<Resolution> {
+        let name = Symbol::get(name)?;
+        self.values.get(&(module, name)).copied()
+    }
+
+    /// Every name that resolves from the current module and scopes, for completing names: the
+    /// locals, procedures, constants and modules in scope, natives and builtins, and the paths
+    /// to what's in imported standard library modules, like `math::sqrt`. Sorted, without
+    /// duplicates.
+    pub fn names_in_scope(&self) -> Vec<String> {
+        let enclosing: Vec<_> = self.enclosing_modules().collect();
+        let imported: Vec<_> = self
+            .imports
+            .iter()
+            .filter(|(module, _)| enclosing.contains(module))
+            .map(|&(_, std_module)| std_module)
+            .collect();
+
+        let mut names: Vec<_> = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.keys())
+            .chain(
+                self.values
+                    .keys()
+                    .chain(self.module_names.keys())
+                    .filter(|(module, _)| enclosing.contains(module))
+                    .map(|(_, name)| name),
+            )
+            .chain(self.natives.keys())
+            .map(|name| name.as_str().to_owned())
+            .chain(
+                Builtin::ALL
+                    .into_iter()
+                    .filter(|builtin| builtin.module().is_none())
+                    .map(|builtin| builtin.name().to_owned()),
+            )
+            .chain(
+                imported
+                    .iter()
+                    .map(|std_module| std_module.name().to_owned()),
+            )
+            .chain(Builtin::ALL.into_iter().filter_map(|builtin| {
+                let std_module = builtin
+                    .module()
+                    .filter(|module| imported.contains(module))?;
+                Some(format!("{}::{}", std_module.name(), builtin.name()))
+            }))
+            .chain(
+                StdConst::ALL
+                    .into_iter()
+                    .filter(|constant| imported.contains(&constant.module()))
+                    .map(|constant| format!("{}::{}", constant.module().name(), constant.name())),
+            )
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Returns if an item with a visibility, defined in a module, can be used from the current
+    /// module. Private items can only be used in their module and the modules nested in it.
+    pub fn is_accessible(&self, module: ModuleId, visibility: Visibility) -> bool {
+        visibility == Visibility::Public || self.enclosing_modules().any(|id| id == module)
+    }
+}
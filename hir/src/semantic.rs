@@ -0,0 +1,101 @@
+use crate::nodes::{Program, Symbol};
+use lexer::token::{IdentKind, Token, TokenKind};
+use span::Span;
+
+/// How a token should be highlighted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticTokenKind {
+    Keyword,
+    Namespace,
+    Function,
+    Constant,
+    Parameter,
+    Local,
+    Type,
+    Literal,
+    Comment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub kind: SemanticTokenKind,
+    pub span: Span,
+}
+
+/// Classify a token, using the resolved symbols of the program it was lexed from to tell
+/// identifiers apart.
+fn classify_token(token: &Token, program: &Program) -> Option<SemanticTokenKind> {
+    let kind = match token.kind {
+        TokenKind::Ident(IdentKind::Keyword(keyword)) if keyword.is_type() => {
+            SemanticTokenKind::Type
+        }
+        TokenKind::Ident(IdentKind::Keyword(_)) => SemanticTokenKind::Keyword,
+        TokenKind::Ident(IdentKind::NonReserved) => match program.symbol_at(token.span.start)? {
+            Symbol::Module(_) | Symbol::StdModule(_) => SemanticTokenKind::Namespace,
+            Symbol::Proc(_) | Symbol::Builtin(_) | Symbol::Native(_) => SemanticTokenKind::Function,
+            Symbol::Const(_) | Symbol::StdConst(_) => SemanticTokenKind::Constant,
+            Symbol::Local(proc, local) if program.proc(proc).params.contains(&local) => {
+                SemanticTokenKind::Parameter
+            }
+            Symbol::Local(..) => SemanticTokenKind::Local,
+        },
+        TokenKind::Literal(_) => SemanticTokenKind::Literal,
+        TokenKind::DocComment | TokenKind::Comment => SemanticTokenKind::Comment,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+/// Classify every token that has a semantic meaning, in source order. Punctuation, operators
+/// and names that don't refer to anything (such as the `_` pattern) aren't classified.
+pub fn classify(tokens: &[Token], program: &Program) -> Vec<SemanticToken> {
+    tokens
+        .iter()
+        .filter_map(|token| {
+            classify_token(token, program).map(|kind| SemanticToken {
+                kind,
+                span: token.span,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SemanticTokenKind::*;
+
+    #[test]
+    fn test_classify() -> anyhow::Result<()> {
+        let source = "/// Doc.\nproc f(x: int) int { let y = x; ret f(y); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens.clone())?;
+        let program = crate::lower(&ast).unwrap().program;
+
+        let classified = classify(&tokens, &program)
+            .into_iter()
+            .map(|token| (&source[token.span.start..token.span.end], token.kind))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            classified,
+            [
+                ("/// Doc.", Comment),
+                ("proc", Keyword),
+                ("f", Function),
+                ("x", Parameter),
+                ("int", Type),
+                ("int", Type),
+                ("let", Keyword),
+                ("y", Local),
+                ("x", Parameter),
+                ("ret", Keyword),
+                ("f", Function),
+                ("y", Local),
+            ]
+        );
+
+        Ok(())
+    }
+}
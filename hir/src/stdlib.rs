@@ -0,0 +1,77 @@
+//! Modules of the standard library, which a module brings into scope with `import`, like
+//! `import math;`. Their procedures are builtins and their constants are literals, so importing
+//! one declares nothing in the program.
+
+use crate::{builtins::Builtin, resolve::Resolution};
+
+/// A module of the standard library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StdModule {
+    /// `math` has float procedures and constants.
+    Math,
+
+    /// `rand` has procedures giving pseudo-random numbers from a generator that can be seeded.
+    Rand,
+}
+
+impl StdModule {
+    pub const ALL: [Self; 2] = [Self::Math, Self::Rand];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Math => "math",
+            Self::Rand => "rand",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|module| module.name() == name)
+    }
+
+    /// Find a procedure or constant in the module.
+    pub fn value(self, name: &str) -> Option<Resolution> {
+        let builtin = Builtin::ALL
+            .into_iter()
+            .find(|builtin| builtin.module() == Some(self) && builtin.name() == name);
+        builtin.map(Resolution::Builtin).or_else(|| {
+            StdConst::ALL
+                .into_iter()
+                .find(|constant| constant.module() == self && constant.name() == name)
+                .map(Resolution::StdConst)
+        })
+    }
+}
+
+/// A constant in a standard library module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StdConst {
+    /// `math::PI: float`, the ratio of a circle's circumference to its diameter.
+    Pi,
+
+    /// `math::E: float`, Euler's number.
+    E,
+}
+
+impl StdConst {
+    pub const ALL: [Self; 2] = [Self::Pi, Self::E];
+
+    pub fn module(self) -> StdModule {
+        match self {
+            Self::Pi | Self::E => StdModule::Math,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pi => "PI",
+            Self::E => "E",
+        }
+    }
+
+    pub fn value(self) -> f64 {
+        match self {
+            Self::Pi => std::f64::consts::PI,
+            Self::E => std::f64::consts::E,
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Jump tables for matches on ints.
+//!
+//! Testing each arm's pattern in turn takes time proportional to the number of arms, but when
+//! the patterns are literals and ranges packed closely together, the arm a value matches can
+//! instead be looked up in a table indexed by the value. Every backend that compiles matches
+//! this way shares the decision of when a table pays off.
+
+use crate::{
+    exhaustiveness::literal_value,
+    nodes::{MatchArm, Pat, PatKind},
+    ty::Ty,
+};
+
+/// The fewest literal and range patterns a match needs for a table to be worth building. Below
+/// this, testing the patterns in turn is about as fast as the bounds check and the indirect
+/// jump.
+pub const MIN_CASES: usize = 4;
+
+/// The most entries a table can have, so a match over a wide range of values doesn't make a
+/// huge one.
+pub const MAX_ENTRIES: usize = 1024;
+
+/// A table from each value from `low` up to the arm of a match it selects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    /// The value of the first entry. Every entry's value fits in an `i64`, whatever the type of
+    /// the scrutinee.
+    pub low: i64,
+
+    /// The index of the arm each value selects.
+    pub arms: Vec<usize>,
+
+    /// The arm values outside the table select.
+    pub default: usize,
+}
+
+impl JumpTable {
+    /// The arm a value selects.
+    pub fn arm(&self, value: i128) -> usize {
+        usize::try_from(value - i128::from(self.low))
+            .ok()
+            .and_then(|index| self.arms.get(index))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// The arms the table can select, in order, each once. Arms after a wildcard are never
+    /// selected.
+    pub fn targets(&self) -> Vec<usize> {
+        let mut targets: Vec<_> = self.arms.iter().copied().chain([self.default]).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+}
+
+/// The table for a match, if it's on an int and dense enough to be faster than testing its
+/// patterns in turn.
+///
+/// At least half the entries between its smallest and largest values have to select an arm
+/// other than the default. Only the arms before the first wildcard can be in the table, and the
+/// first wildcard arm is the default. Without one, the match is exhaustive, so every value the
+/// scrutinee can have is in the table and the default is never selected.
+pub fn jump_table(scrutinee: Ty, arms: &[MatchArm]) -> Option<JumpTable> {
+    let Ty::Int(_) = scrutinee else {
+        return None;
+    };
+
+    let mut cases = Vec::new();
+    let mut default = arms.len().checked_sub(1)?;
+    for (i, arm) in arms.iter().enumerate() {
+        if matches!(arm.pat.kind, PatKind::Wildcard) {
+            default = i;
+            break;
+        }
+        ranges(&arm.pat, &mut |start, end| cases.push((start, end, i)))?;
+    }
+    if cases.len() < MIN_CASES {
+        return None;
+    }
+
+    let low = cases.iter().map(|&(start, ..)| start).min()?;
+    let high = cases.iter().map(|&(_, end, _)| end).max()?;
+    let len = usize::try_from(high - low + 1).ok()?;
+    if len > MAX_ENTRIES || i64::try_from(high).is_err() {
+        return None;
+    }
+
+    // The first arm to match a value selects it.
+    let mut entries = vec![None; len];
+    for (start, end, arm) in cases {
+        for entry in &mut entries[(start - low) as usize..=(end - low) as usize] {
+            entry.get_or_insert(arm);
+        }
+    }
+    if entries.iter().flatten().count() * 2 < len {
+        return None;
+    }
+
+    Some(JumpTable {
+        low: i64::try_from(low).ok()?,
+        arms: entries
+            .into_iter()
+            .map(|entry| entry.unwrap_or(default))
+            .collect(),
+        default,
+    })
+}
+
+/// Call `range` with the bounds of each inclusive range of values a pattern matches, or return
+/// `None` if it isn't made of literals and ranges. Empty ranges are skipped.
+fn ranges(pat: &Pat, range: &mut impl FnMut(i128, i128)) -> Option<()> {
+    match &pat.kind {
+        PatKind::Literal(literal) => {
+            let value = literal_value(literal)?;
+            range(value, value);
+        }
+        PatKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let (start, end) = (literal_value(start)?, literal_value(end)?);
+            let end = if *inclusive { end } else { end - 1 };
+            if start <= end {
+                range(start, end);
+            }
+        }
+        PatKind::Or(alternatives) => {
+            for alternative in alternatives {
+                ranges(alternative, range)?;
+            }
+        }
+        PatKind::Wildcard | PatKind::Error => return None,
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nodes::{Expr, ExprKind, Literal},
+        ty::IntTy,
+    };
+    use span::Span;
+
+    fn arm(kind: PatKind) -> MatchArm {
+        let span = Span::from(0..0);
+        MatchArm {
+            pat: Pat { kind, span },
+            body: Expr {
+                kind: ExprKind::Error,
+                ty: Ty::Error,
+                span,
+            },
+            span,
+        }
+    }
+
+    fn int(value: i64) -> PatKind {
+        PatKind::Literal(Literal::Int(value, IntTy::I64))
+    }
+
+    fn range(start: i64, end: i64) -> PatKind {
+        PatKind::Range {
+            start: Literal::Int(start, IntTy::I64),
+            end: Literal::Int(end, IntTy::I64),
+            inclusive: false,
+        }
+    }
+
+    #[test]
+    fn test_jump_tables() {
+        let arms = [
+            arm(int(3)),
+            arm(PatKind::Or(vec![
+                Pat {
+                    kind: int(5),
+                    span: Span::from(0..0),
+                },
+                Pat {
+                    kind: int(1),
+                    span: Span::from(0..0),
+                },
+            ])),
+            arm(range(2, 5)),
+            arm(PatKind::Wildcard),
+            arm(int(6)),
+        ];
+        let table = jump_table(Ty::INT, &arms).unwrap();
+        assert_eq!(table.low, 1);
+        assert_eq!(table.arms, [1, 2, 0, 2, 1]);
+        assert_eq!(table.default, 3);
+        assert_eq!(table.targets(), [0, 1, 2, 3]);
+        assert_eq!(table.arm(4), 2);
+        assert_eq!(table.arm(6), 3);
+        assert_eq!(table.arm(-1), 3);
+
+        // Too few cases, too sparse, too wide and not on an int.
+        assert_eq!(jump_table(Ty::INT, &arms[..2]), None);
+        let sparse = [0, 10, 20, 30].map(|value| arm(int(value)));
+        assert_eq!(jump_table(Ty::INT, &sparse), None);
+        let wide = [arm(range(0, 2000)), arm(int(1)), arm(int(2)), arm(int(3))];
+        assert_eq!(jump_table(Ty::INT, &wide), None);
+        assert_eq!(jump_table(Ty::Char, &arms), None);
+
+        // Without a wildcard, the last arm is the default.
+        let exhaustive = [0, 1, 2, 3].map(|value| arm(int(value)));
+        assert_eq!(jump_table(Ty::INT, &exhaustive).unwrap().default, 3);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::nodes::BinOp;
+use parser::ast::TypeKind;
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{Mutex, OnceLock},
+};
+
+/// The width and signedness of an int type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntTy {
+    I8,
+    I16,
+    I32,
+
+    /// The type of `int`, which is another name for `i64`.
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntTy {
+    pub const ALL: [Self; 8] = [
+        Self::I8,
+        Self::I16,
+        Self::I32,
+        Self::I64,
+        Self::U8,
+        Self::U16,
+        Self::U32,
+        Self::U64,
+    ];
+
+    /// The name of the type in the source. `i64` is written `int`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "int",
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::I8 | Self::U8 => 8,
+            Self::I16 | Self::U16 => 16,
+            Self::I32 | Self::U32 => 32,
+            Self::I64 | Self::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Self::I8 | Self::I16 | Self::I32 | Self::I64)
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() {
+            -(1 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(self) -> i128 {
+        (1 << (self.bits() - u32::from(self.is_signed()))) - 1
+    }
+
+    pub fn fits(self, value: i128) -> bool {
+        (self.min()..=self.max()).contains(&value)
+    }
+
+    /// The value of an int of this type from how it's stored. Every int is stored in an `i64`,
+    /// and a `u64` past `i64::MAX` is stored as the `i64` with the same bits.
+    pub fn value(self, stored: i64) -> i128 {
+        if self == Self::U64 {
+            (stored as u64).into()
+        } else {
+            stored.into()
+        }
+    }
+
+    /// How a value is stored as this type, wrapping it around in two's complement if it
+    /// doesn't fit: it's truncated to the type's width, then sign or zero extended.
+    pub fn wrap(self, value: i128) -> i64 {
+        let unused = 128 - self.bits();
+        if self.is_signed() {
+            ((value << unused) >> unused) as i64
+        } else {
+            (((value as u128) << unused) >> unused) as i64
+        }
+    }
+
+    /// How a value is stored as this type, or `None` if it doesn't fit.
+    pub fn store(self, value: i128) -> Option<i64> {
+        self.fits(value).then(|| self.wrap(value))
+    }
+
+    /// Flip every bit of an int of this type.
+    pub fn not(self, value: i64) -> i64 {
+        self.wrap((!value).into())
+    }
+
+    /// Apply a bitwise operator or shift to ints of this type, or `None` if a shift amount
+    /// isn't between 0 and the type's width. Right shifts are arithmetic if the type is signed
+    /// and logical if it isn't.
+    pub fn bitwise(self, op: BinOp, lhs: i64, rhs: i64) -> Option<i64> {
+        if matches!(op, BinOp::Shl | BinOp::Shr)
+            && !(0..self.bits().into()).contains(&self.value(rhs))
+        {
+            return None;
+        }
+
+        Some(match op {
+            BinOp::BitAnd => lhs & rhs,
+            BinOp::BitOr => lhs | rhs,
+            BinOp::Shl => self.wrap(((lhs as u64) << rhs).into()),
+            BinOp::Shr => self.wrap(self.value(lhs) >> rhs),
+            op => unreachable!("`{op:?}` isn't a bitwise operator"),
+        })
+    }
+}
+
+/// The type of a value in the HIR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int(IntTy),
+    Float,
+    Bool,
+    Char,
+    Str,
+    Void,
+
+    /// A growable array of elements of a type. Element types are interned, so types stay
+    /// cheap to copy: make array types with [`Ty::array_of`].
+    Array(&'static Self),
+
+    /// A map from keys of a type to values of another, interned like array elements: make map
+    /// types with [`Ty::map_of`]. Only types whose values can be compared and never change can
+    /// be keys, which [`Ty::is_key`] checks.
+    Map(&'static Self, &'static Self),
+
+    /// The type of an expression that failed to type check. It is compatible with every other
+    /// type so a single mistake doesn't cascade into more diagnostics.
+    Error,
+}
+
+impl Ty {
+    /// The type of `int`.
+    pub const INT: Self = Self::Int(IntTy::I64);
+
+    /// The type of arrays with elements of a type.
+    pub fn array_of(element: Self) -> Self {
+        Self::Array(intern(element))
+    }
+
+    /// The type of maps from keys of a type to values of another.
+    pub fn map_of(key: Self, value: Self) -> Self {
+        Self::Map(intern(key), intern(value))
+    }
+
+    /// The type of the elements of an array type, or `None` if this isn't an array type.
+    pub fn element(self) -> Option<Self> {
+        match self {
+            Self::Array(element) => Some(*element),
+            _ => None,
+        }
+    }
+
+    /// The types of the indexes and elements of an indexable type: `int` and the element type
+    /// of an array, and the key and value types of a map. `None` if this can't be indexed.
+    pub fn indexed(self) -> Option<(Self, Self)> {
+        match self {
+            Self::Array(element) => Some((Self::INT, *element)),
+            Self::Map(key, value) => Some((*key, *value)),
+            _ => None,
+        }
+    }
+
+    /// Returns if a value of type `found` can be used where this type is expected.
+    pub fn accepts(self, found: Self) -> bool {
+        match (self, found) {
+            (Self::Array(expected), Self::Array(found)) => expected.accepts(*found),
+            (Self::Map(expected_key, expected), Self::Map(found_key, found)) => {
+                expected_key.accepts(*found_key) && expected.accepts(*found)
+            }
+            _ => self == found || self == Self::Error || found == Self::Error,
+        }
+    }
+
+    /// Returns if values of this type can be the keys of a map. Floats can't, since `nan`
+    /// isn't equal to itself, and neither can arrays and maps, which can change.
+    pub fn is_key(self) -> bool {
+        matches!(
+            self,
+            Self::Int(_) | Self::Bool | Self::Char | Self::Str | Self::Error
+        )
+    }
+
+    /// Returns if this type is a numeric type or not.
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Self::Int(_) | Self::Float)
+    }
+}
+
+/// The single copy of a type that array and map types refer to.
+fn intern(ty: Ty) -> &'static Ty {
+    static INTERNED: OnceLock<Mutex<HashSet<&'static Ty>>> = OnceLock::new();
+
+    let mut interned = INTERNED.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(&ty) = interned.get(&ty) {
+        return ty;
+    }
+    let ty = &*Box::leak(Box::new(ty));
+    interned.insert(ty);
+    ty
+}
+
+impl From<&TypeKind> for Ty {
+    fn from(kind: &TypeKind) -> Self {
+        match kind {
+            TypeKind::Int | TypeKind::I64 => Self::INT,
+            TypeKind::I8 => Self::Int(IntTy::I8),
+            TypeKind::I16 => Self::Int(IntTy::I16),
+            TypeKind::I32 => Self::Int(IntTy::I32),
+            TypeKind::U8 => Self::Int(IntTy::U8),
+            TypeKind::U16 => Self::Int(IntTy::U16),
+            TypeKind::U32 => Self::Int(IntTy::U32),
+            TypeKind::U64 => Self::Int(IntTy::U64),
+            TypeKind::Float => Self::Float,
+            TypeKind::Bool => Self::Bool,
+            TypeKind::Char => Self::Char,
+            TypeKind::Str => Self::Str,
+            TypeKind::Void => Self::Void,
+            TypeKind::Array(element) => Self::array_of((&element.kind).into()),
+            TypeKind::Map(key, value) => Self::map_of((&key.kind).into(), (&value.kind).into()),
+        }
+    }
+}
+
+impl fmt::Display for IntTy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Ty::*;
+
+        match self {
+            Int(ty) => write!(f, "{ty}"),
+            Float => write!(f, "float"),
+            Bool => write!(f, "bool"),
+            Char => write!(f, "char"),
+            Str => write!(f, "str"),
+            Void => write!(f, "void"),
+            Array(element) => write!(f, "[{element}]"),
+            Map(key, value) => write!(f, "[{key}: {value}]"),
+            Error => write!(f, "{{error}}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ints_are_stored_in_their_width() {
+        assert_eq!((IntTy::I8.min(), IntTy::I8.max()), (-128, 127));
+        assert_eq!(IntTy::U64.max(), u64::MAX.into());
+        assert_eq!(IntTy::I8.wrap(128), -128);
+        assert_eq!(IntTy::U8.wrap(-1), 255);
+        assert_eq!(IntTy::U16.wrap(0x1_2345), 0x2345);
+        assert_eq!(IntTy::U64.wrap(u64::MAX.into()), -1);
+        assert_eq!(IntTy::U64.value(-1), u64::MAX.into());
+        assert_eq!(IntTy::I32.store(1 << 31), None);
+        assert_eq!(Ty::Int(IntTy::U8).to_string(), "u8");
+        assert_eq!(Ty::INT.to_string(), "int");
+    }
+
+    #[test]
+    fn test_bitwise_ops_at_each_width() {
+        assert_eq!(IntTy::U8.not(0), 255);
+        assert_eq!(IntTy::I8.not(0), -1);
+        assert_eq!(IntTy::U8.bitwise(BinOp::Shl, 0x81, 1), Some(2));
+        assert_eq!(IntTy::I8.bitwise(BinOp::Shl, 0x40, 1), Some(-128));
+        assert_eq!(IntTy::I8.bitwise(BinOp::Shr, -128, 7), Some(-1));
+        assert_eq!(IntTy::U64.bitwise(BinOp::Shr, -1, 63), Some(1));
+        assert_eq!(IntTy::U8.bitwise(BinOp::Shl, 1, 8), None);
+        assert_eq!(IntTy::U64.bitwise(BinOp::Shl, 1, -1), None);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    nodes::{BinOp, UnOp},
+    ty::Ty,
+};
+
+/// The type produced by applying a unary operator to an operand, or `None` if the operator
+/// can't be applied to it.
+pub fn unary_op_result(op: UnOp, operand: Ty) -> Option<Ty> {
+    if operand == Ty::Error {
+        return Some(Ty::Error);
+    }
+
+    match (op, operand) {
+        (UnOp::Neg, Ty::Int(ty)) if ty.is_signed() => Some(operand),
+        (UnOp::Neg, Ty::Float) => Some(operand),
+        (UnOp::Not, Ty::Bool) => Some(Ty::Bool),
+        (UnOp::BitNot, Ty::Int(_)) => Some(operand),
+        (UnOp::Cast(to), Ty::Int(_)) => Some(Ty::Int(to)),
+        _ => None,
+    }
+}
+
+/// The type produced by applying a binary operator to two operands, or `None` if the operator
+/// can't be applied to them. There are no implicit conversions, so both operands must always
+/// have the same type, including ints of the same width. Strings are joined by `+` and ordered
+/// by their bytes, and chars are ordered by their code points.
+pub fn binary_op_result(op: BinOp, lhs: Ty, rhs: Ty) -> Option<Ty> {
+    use BinOp::*;
+
+    if lhs == Ty::Error || rhs == Ty::Error {
+        return Some(if op.is_comparison() {
+            Ty::Bool
+        } else {
+            Ty::Error
+        });
+    }
+
+    if lhs != rhs {
+        return None;
+    }
+
+    match op {
+        Add | Sub | Mul | Div | Rem if lhs.is_numeric() => Some(lhs),
+        Add if lhs == Ty::Str => Some(Ty::Str),
+        BitAnd | BitOr | Shl | Shr if matches!(lhs, Ty::Int(_)) => Some(lhs),
+        Lt | Le | Gt | Ge if lhs.is_numeric() || matches!(lhs, Ty::Str | Ty::Char) => {
+            Some(Ty::Bool)
+        }
+        Eq | Ne if lhs != Ty::Void => Some(Ty::Bool),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ty::IntTy;
+
+    #[test]
+    fn test_binary_op_result() {
+        assert_eq!(
+            binary_op_result(BinOp::Add, Ty::INT, Ty::INT),
+            Some(Ty::INT)
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Div, Ty::Float, Ty::Float),
+            Some(Ty::Float)
+        );
+        assert_eq!(binary_op_result(BinOp::Add, Ty::INT, Ty::Float), None);
+        assert_eq!(
+            binary_op_result(BinOp::Shl, Ty::Int(IntTy::U8), Ty::Int(IntTy::U8)),
+            Some(Ty::Int(IntTy::U8))
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Add, Ty::INT, Ty::Int(IntTy::I32)),
+            None
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Add, Ty::Str, Ty::Str),
+            Some(Ty::Str)
+        );
+        assert_eq!(binary_op_result(BinOp::Sub, Ty::Str, Ty::Str), None);
+        assert_eq!(binary_op_result(BinOp::Shl, Ty::Float, Ty::Float), None);
+        assert_eq!(
+            binary_op_result(BinOp::Lt, Ty::INT, Ty::INT),
+            Some(Ty::Bool)
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Eq, Ty::Str, Ty::Str),
+            Some(Ty::Bool)
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Ge, Ty::Str, Ty::Str),
+            Some(Ty::Bool)
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Lt, Ty::Char, Ty::Char),
+            Some(Ty::Bool)
+        );
+        assert_eq!(binary_op_result(BinOp::Add, Ty::Char, Ty::Char), None);
+        assert_eq!(binary_op_result(BinOp::Lt, Ty::Bool, Ty::Bool), None);
+        assert_eq!(binary_op_result(BinOp::Eq, Ty::Void, Ty::Void), None);
+        assert_eq!(
+            binary_op_result(BinOp::Lt, Ty::Error, Ty::INT),
+            Some(Ty::Bool)
+        );
+        assert_eq!(
+            binary_op_result(BinOp::Mul, Ty::Error, Ty::INT),
+            Some(Ty::Error)
+        );
+    }
+
+    #[test]
+    fn test_unary_op_result() {
+        assert_eq!(unary_op_result(UnOp::Neg, Ty::Float), Some(Ty::Float));
+        assert_eq!(unary_op_result(UnOp::Not, Ty::Bool), Some(Ty::Bool));
+        assert_eq!(unary_op_result(UnOp::Not, Ty::INT), None);
+        assert_eq!(unary_op_result(UnOp::BitNot, Ty::INT), Some(Ty::INT));
+        assert_eq!(
+            unary_op_result(UnOp::Neg, Ty::Int(IntTy::I8)),
+            Some(Ty::Int(IntTy::I8))
+        );
+        assert_eq!(unary_op_result(UnOp::Neg, Ty::Int(IntTy::U8)), None);
+        assert_eq!(
+            unary_op_result(UnOp::Cast(IntTy::U16), Ty::INT),
+            Some(Ty::Int(IntTy::U16))
+        );
+        assert_eq!(unary_op_result(UnOp::Cast(IntTy::U16), Ty::Float), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Interned strings, for the names and string literals of programs. Interning a string gives a
+//! [`Symbol`] standing for it, the same one every time the same string is interned, so symbols
+//! are compared and hashed as numbers rather than by their text.
+//!
+//! Symbols are shared by every thread: the lexer interns names as it finds them, in parallel
+//! for each file, and later stages find them already interned. An interned string is kept until
+//! the process exits, like the names of a program being compiled.
+//!
+//! The keywords are interned first, so each has a symbol known up front, in [`kw`].
+
+#![warn(rust_2018_idioms)]
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{OnceLock, RwLock},
+};
+
+/// An interned string.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+macro_rules! keywords {
+    ($($name:ident: $text:literal,)*) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[repr(u32)]
+        enum Keyword {
+            $($name,)*
+        }
+
+        /// The text of each keyword, in the order of their symbols.
+        const KEYWORDS: &[&str] = &[$($text,)*];
+
+        /// The symbols of the keywords, including the literals `true` and `false`.
+        pub mod kw {
+            use super::{Keyword, Symbol};
+
+            $(pub const $name: Symbol = Symbol(Keyword::$name as u32);)*
+
+            /// Every keyword.
+            pub const ALL: &[Symbol] = &[$($name,)*];
+        }
+    };
+}
+
+keywords! {
+    PROC: "proc",
+    LET: "let",
+    VOID: "void",
+    INT: "int",
+    RET: "ret",
+    FLOAT: "float",
+    IF: "if",
+    ELIF: "elif",
+    ELSE: "else",
+    FOR: "for",
+    WHILE: "while",
+    DO: "do",
+    BOOL: "bool",
+    STR: "str",
+    MATCH: "match",
+    PUB: "pub",
+    MOD: "mod",
+    CONST: "const",
+    IMPORT: "import",
+    IN: "in",
+    CHAR: "char",
+    I8: "i8",
+    I16: "i16",
+    I32: "i32",
+    I64: "i64",
+    U8: "u8",
+    U16: "u16",
+    U32: "u32",
+    U64: "u64",
+    AS: "as",
+    TRUE: "true",
+    FALSE: "false",
+}
+
+/// Every interned string, by its symbol and the other way round.
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut interner = Self {
+            symbols: HashMap::new(),
+            strings: Vec::new(),
+        };
+        for keyword in KEYWORDS {
+            interner.insert(keyword);
+        }
+        interner
+    }
+
+    fn insert(&mut self, text: &'static str) -> Symbol {
+        let symbol = Symbol(self.strings.len() as u32);
+        self.symbols.insert(text, symbol);
+        self.strings.push(text);
+        symbol
+    }
+}
+
+/// The interner every thread shares, made with the keywords the first time it's used.
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+
+    INTERNER.get_or_init(|| RwLock::new(Interner::new()))
+}
+
+impl Symbol {
+    /// The symbol of a string, interning it if it hasn't been.
+    pub fn intern(text: &str) -> Self {
+        if let Some(symbol) = Self::get(text) {
+            return symbol;
+        }
+        let mut interner = interner().write().expect("the interner isn't poisoned");
+        // Another thread may have interned it since.
+        if let Some(&symbol) = interner.symbols.get(text) {
+            return symbol;
+        }
+        interner.insert(Box::leak(text.into()))
+    }
+
+    /// The symbol of a string if it's been interned. A string that hasn't been can't be the
+    /// name of anything interned, so looking a name up needn't intern it.
+    pub fn get(text: &str) -> Option<Self> {
+        let interner = interner().read().expect("the interner isn't poisoned");
+        interner.symbols.get(text).copied()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let interner = interner().read().expect("the interner isn't poisoned");
+        interner.strings[self.0 as usize]
+    }
+
+    pub fn is_keyword(self) -> bool {
+        (self.0 as usize) < KEYWORDS.len()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Self {
+        Self::intern(text)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interns_each_string_once() {
+        let name = Symbol::intern("interned_name");
+        assert_eq!(Symbol::intern(&String::from("interned_name")), name);
+        assert_ne!(Symbol::intern("another_name"), name);
+        assert_eq!(name.as_str(), "interned_name");
+        assert_eq!(
+            format!("{name} {name:?}"),
+            "interned_name \"interned_name\""
+        );
+
+        assert_eq!(Symbol::get("never_interned"), None);
+        assert_eq!(Symbol::get("interned_name"), Some(name));
+    }
+
+    #[test]
+    fn test_keywords_are_interned_first() {
+        assert_eq!(Symbol::intern("proc"), kw::PROC);
+        assert_eq!(kw::FALSE.as_str(), "false");
+        assert!(kw::ALL.iter().all(|keyword| keyword.is_keyword()));
+        assert!(!Symbol::intern("procedure").is_keyword());
+    }
+
+    #[test]
+    fn test_interns_from_several_threads() {
+        let symbols: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| Symbol::intern("shared_between_threads")))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        assert!(symbols.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+}
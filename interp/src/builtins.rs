@@ -0,0 +1,320 @@
+//! The runtime implementation of builtins, shared by the interpreter and the VM.
+
+use crate::{heap::Heap, rng::Rng, value::Key, RunResult, RuntimeError, Value};
+use hir::{Builtin, SystemNative, Ty};
+use span::Span;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{BufRead, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The generator of the `rand` module, which the I/O owns or borrows from a caller keeping it
+/// between runs.
+enum RngSource<'a> {
+    Owned(Rng),
+    Borrowed(&'a mut Rng),
+}
+
+/// Where builtins read input from and write output to, the arguments the program was run
+/// with, whether it can access files, the environment and the clock, and the generator of the
+/// `rand` module.
+pub struct Io<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    args: &'a [String],
+    sandboxed: bool,
+
+    /// When the I/O was made, which `clock_ms` counts from.
+    start: Instant,
+    rng: RngSource<'a>,
+}
+
+impl<'a> Io<'a> {
+    /// Make I/O for a program run without arguments, which can access files, with a generator
+    /// seeded at random.
+    pub fn new(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> Self {
+        Self {
+            input,
+            output,
+            args: &[],
+            sandboxed: false,
+            start: Instant::now(),
+            rng: RngSource::Owned(Rng::random()),
+        }
+    }
+
+    /// Set the arguments `arg_count` and `arg` give the program.
+    pub fn with_args(mut self, args: &'a [String]) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Stop the program from accessing files, the environment and the clock, so the builtins
+    /// doing so fail.
+    pub fn sandboxed(mut self) -> Self {
+        self.sandboxed = true;
+        self
+    }
+
+    /// Seed the generator, so the program gets the same numbers every time it's run until it
+    /// seeds it itself.
+    pub fn seeded(mut self, seed: u64) -> Self {
+        self.rng = RngSource::Owned(Rng::seeded(seed));
+        self
+    }
+
+    /// Use a generator kept between runs, so the numbers of one run follow on from the last.
+    pub fn with_rng(mut self, rng: &'a mut Rng) -> Self {
+        self.rng = RngSource::Borrowed(rng);
+        self
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        match &mut self.rng {
+            RngSource::Owned(rng) => rng,
+            RngSource::Borrowed(rng) => rng,
+        }
+    }
+}
+
+fn write_str(io: &mut Io<'_>, s: &str, span: Span) -> RunResult<()> {
+    io.output
+        .write_all(s.as_bytes())
+        .map_err(|error| RuntimeError::Io(error.to_string(), span))
+}
+
+/// Check that a builtin or system native reaching outside the program can run, which it can't
+/// in the sandbox.
+fn unsandboxed(io: &Io<'_>, name: &'static str, span: Span) -> RunResult<()> {
+    if io.sandboxed {
+        return Err(RuntimeError::Sandboxed(name, span));
+    }
+    Ok(())
+}
+
+/// Run a file builtin, unless the program is sandboxed, describing any error with the path.
+fn access_file<T>(
+    io: &Io<'_>,
+    builtin: Builtin,
+    path: &str,
+    span: Span,
+    access: impl FnOnce(&str) -> std::io::Result<T>,
+) -> RunResult<T> {
+    unsandboxed(io, builtin.name(), span)?;
+    access(path).map_err(|error| RuntimeError::Io(format!("`{path}`: {error}"), span))
+}
+
+/// The char starting at a byte of a string.
+fn char_at(s: &str, index: i64, span: Span) -> RunResult<char> {
+    let start = usize::try_from(index)
+        .ok()
+        .filter(|&start| start < s.len())
+        .ok_or(RuntimeError::IndexOutOfBounds(index, s.len(), span))?;
+    s.get(start..)
+        .and_then(|rest| rest.chars().next())
+        .ok_or(RuntimeError::NotCharBoundary(index, span))
+}
+
+/// The parts of a string between separators. An empty separator doesn't split it.
+fn split<'s>(s: &'s str, separator: &str) -> Vec<&'s str> {
+    if separator.is_empty() {
+        return vec![s];
+    }
+    s.split(separator).collect()
+}
+
+/// Call a builtin with arguments of the types it expects.
+pub fn call(
+    builtin: Builtin,
+    args: Vec<Value>,
+    io: &mut Io<'_>,
+    heap: &mut Heap,
+    span: Span,
+) -> RunResult<Value> {
+    match (builtin, args.as_slice()) {
+        (Builtin::Print, [Value::Str(s)]) => write_str(io, s, span)?,
+        (Builtin::Println, [Value::Str(s)]) => {
+            write_str(io, s, span)?;
+            write_str(io, "\n", span)?;
+        }
+        (Builtin::ReadLine, []) => {
+            // Show any prompt printed before asking for input.
+            let mut line = String::new();
+            io.output
+                .flush()
+                .and_then(|()| io.input.read_line(&mut line))
+                .map_err(|error| RuntimeError::Io(error.to_string(), span))?;
+
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            return Ok(heap.alloc_str(&line));
+        }
+        (Builtin::ToStr, [value]) => return Ok(heap.alloc_str(&value.to_string())),
+        (Builtin::Len, [Value::Str(s)]) => return Ok(Value::int(s.len() as i64)),
+        (Builtin::Len, [Value::Array(array)]) => {
+            return Ok(Value::int(array.borrow().len() as i64));
+        }
+        (Builtin::Len, [Value::Map(map)]) => return Ok(Value::int(map.borrow().len() as i64)),
+        (Builtin::Push, [Value::Array(array), value]) => heap.push(array, value.clone()),
+        (Builtin::Pop, [Value::Array(array)]) => return heap.pop(array, span),
+        (Builtin::Insert, [Value::Map(map), key, value]) => heap.insert(map, key, value.clone()),
+        (Builtin::Get, [Value::Map(map), key, default]) => {
+            let value = map.borrow().get(&Key::new(key)).cloned();
+            return Ok(value.unwrap_or_else(|| default.clone()));
+        }
+        (Builtin::Remove, [Value::Map(map), key]) => {
+            let removed = map.borrow_mut().remove(&Key::new(key));
+            return Ok(Value::Bool(removed.is_some()));
+        }
+        (Builtin::HasKey, [Value::Map(map), key]) => {
+            return Ok(Value::Bool(map.borrow().contains_key(&Key::new(key))));
+        }
+        (Builtin::Keys, [Value::Map(map)]) => {
+            let mut keys: Vec<_> = map.borrow().keys().cloned().collect();
+            keys.sort();
+            return Ok(heap.alloc_array(keys.iter().map(Key::value).collect()));
+        }
+        (Builtin::Slice, [Value::Str(s), Value::Int(start, _), Value::Int(end, _)]) => {
+            return heap.slice(s, *start, *end, span);
+        }
+        (Builtin::ArgCount, []) => return Ok(Value::int(io.args.len() as i64)),
+        (Builtin::Arg, [Value::Int(index, _)]) => {
+            let arg = usize::try_from(*index)
+                .ok()
+                .and_then(|index| io.args.get(index))
+                .ok_or(RuntimeError::IndexOutOfBounds(*index, io.args.len(), span))?;
+            return Ok(heap.alloc_str(arg));
+        }
+        (Builtin::Args, []) => {
+            let args = io.args.iter().map(|arg| heap.alloc_str(arg)).collect();
+            return Ok(heap.alloc_array(args));
+        }
+        (Builtin::ReadFile, [Value::Str(path)]) => {
+            let contents = access_file(io, builtin, path, span, |path| fs::read_to_string(path))?;
+            return Ok(heap.alloc_str(&contents));
+        }
+        (Builtin::WriteFile, [Value::Str(path), Value::Str(contents)]) => {
+            access_file(io, builtin, path, span, |path| fs::write(path, &**contents))?;
+        }
+        (Builtin::AppendFile, [Value::Str(path), Value::Str(contents)]) => {
+            access_file(io, builtin, path, span, |path| {
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)?
+                    .write_all(contents.as_bytes())
+            })?;
+        }
+        (Builtin::Sqrt, [Value::Float(x)]) => return Ok(Value::Float(x.sqrt())),
+        (Builtin::Abs, [Value::Float(x)]) => return Ok(Value::Float(x.abs())),
+        (Builtin::Pow, [Value::Float(x), Value::Float(y)]) => return Ok(Value::Float(x.powf(*y))),
+        (Builtin::Floor, [Value::Float(x)]) => return Ok(Value::Float(x.floor())),
+        (Builtin::Ceil, [Value::Float(x)]) => return Ok(Value::Float(x.ceil())),
+        // Like C's `fmin` and `fmax`, which ignore `nan`s.
+        (Builtin::Min, [Value::Float(x), Value::Float(y)]) => return Ok(Value::Float(x.min(*y))),
+        (Builtin::Max, [Value::Float(x), Value::Float(y)]) => return Ok(Value::Float(x.max(*y))),
+        (Builtin::Substr, [Value::Str(s), Value::Int(start, _), Value::Int(count, _)]) => {
+            return heap.slice(s, *start, start.saturating_add(*count), span);
+        }
+        (Builtin::CharAt, [Value::Str(s), Value::Int(index, _)]) => {
+            return Ok(Value::Char(char_at(s, *index, span)?));
+        }
+        (Builtin::Contains, [Value::Str(s), Value::Str(pattern)]) => {
+            return Ok(Value::Bool(s.contains(&**pattern)));
+        }
+        (Builtin::SplitCount, [Value::Str(s), Value::Str(separator)]) => {
+            return Ok(Value::int(split(s, separator).len() as i64));
+        }
+        (Builtin::Split, [Value::Str(s), Value::Str(separator), Value::Int(index, _)]) => {
+            let parts = split(s, separator);
+            let part = usize::try_from(*index)
+                .ok()
+                .and_then(|index| parts.get(index))
+                .ok_or(RuntimeError::IndexOutOfBounds(*index, parts.len(), span))?;
+            return Ok(heap.alloc_str(part));
+        }
+        (Builtin::ToInt, [Value::Str(s)]) => {
+            let value = s
+                .parse()
+                .map_err(|_| RuntimeError::InvalidNumber(s.to_string(), Ty::INT, span))?;
+            return Ok(Value::int(value));
+        }
+        (Builtin::ToInt, [Value::Char(c)]) => return Ok(Value::int(u32::from(*c).into())),
+        (Builtin::FromInt, [Value::Int(code, _)]) => {
+            let c = u32::try_from(*code)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(RuntimeError::InvalidChar(*code, span))?;
+            return Ok(Value::Char(c));
+        }
+        (Builtin::ToFloat, [Value::Str(s)]) => {
+            let value = hir::float::from_str(s)
+                .ok_or_else(|| RuntimeError::InvalidNumber(s.to_string(), Ty::Float, span))?;
+            return Ok(Value::Float(value));
+        }
+        (Builtin::Assert, [Value::Bool(condition), Value::Str(message)]) => {
+            if !condition {
+                return Err(RuntimeError::AssertionFailed(message.to_string(), span));
+            }
+        }
+        (Builtin::Panic, [Value::Str(message)]) => {
+            return Err(RuntimeError::Panicked(message.to_string(), span));
+        }
+        (Builtin::Seed, [Value::Int(seed, _)]) => *io.rng() = Rng::seeded(*seed as u64),
+        (Builtin::RandInt, [Value::Int(lo, _), Value::Int(hi, _)]) => {
+            let n = io
+                .rng()
+                .int_in(*lo, *hi)
+                .ok_or(RuntimeError::EmptyRange(*lo, *hi, span))?;
+            return Ok(Value::int(n));
+        }
+        (Builtin::RandFloat, []) => return Ok(Value::Float(io.rng().float())),
+        (builtin, args) => {
+            unreachable!(
+                "arguments to `{}` are type checked, found {args:?}",
+                builtin.name()
+            )
+        }
+    }
+
+    Ok(Value::Void)
+}
+
+/// Run a system native, unless the program is sandboxed.
+pub(crate) fn call_system(
+    native: SystemNative,
+    args: &[Value],
+    io: &mut Io<'_>,
+    heap: &mut Heap,
+    span: Span,
+) -> RunResult<Value> {
+    unsandboxed(io, native.name(), span)?;
+    match (native, args) {
+        (SystemNative::Env, [Value::Str(name)]) => {
+            // Variables that aren't valid UTF-8 can't be strings, so they read as unset.
+            let value = env::var(&**name).unwrap_or_default();
+            Ok(heap.alloc_str(&value))
+        }
+        (SystemNative::ClockMs, []) => {
+            let elapsed = io.start.elapsed().as_millis();
+            Ok(Value::int(elapsed.try_into().unwrap_or(i64::MAX)))
+        }
+        (SystemNative::SleepMs, [Value::Int(ms, _)]) => {
+            // Output written before sleeping is shown while the program waits.
+            io.output
+                .flush()
+                .map_err(|error| RuntimeError::Io(error.to_string(), span))?;
+            thread::sleep(Duration::from_millis((*ms).max(0) as u64));
+            Ok(Value::Void)
+        }
+        (native, args) => {
+            unreachable!(
+                "arguments to `{}` are type checked, found {args:?}",
+                native.name()
+            )
+        }
+    }
+}
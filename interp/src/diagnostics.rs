@@ -0,0 +1,229 @@
+use hir::Ty;
+use miette::{Diagnostic, LabeledSpan};
+use span::Span;
+use std::fmt;
+use thiserror::Error;
+
+/// A procedure call that was active when a program stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    /// The qualified name of the called procedure.
+    pub proc: String,
+
+    /// Where the procedure was called, or its definition for `main`.
+    pub call_span: Span,
+}
+
+/// The calls that were active when a program stopped, innermost first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallTrace(pub Vec<TraceFrame>);
+
+impl fmt::Display for CallTrace {
+    /// Lists the called procedures, collapsing runs of calls to the same one, which recursion
+    /// makes long.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "call trace, innermost first:")?;
+
+        let mut frames = self.0.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut count = 1;
+            while frames.next_if(|next| next.proc == frame.proc).is_some() {
+                count += 1;
+            }
+
+            write!(f, "\n    {}", frame.proc)?;
+            if count > 1 {
+                write!(f, " ({count} calls)")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CallTrace {
+    /// Labels for the calls, except the outermost one, which is the program starting `main`.
+    /// Runs of calls from the same place are labeled once.
+    fn call_labels(&self) -> Vec<LabeledSpan> {
+        let calls = self.0.split_last().map_or(&[][..], |(_, calls)| calls);
+        let mut labels = Vec::new();
+
+        let mut calls = calls.iter().peekable();
+        while let Some(call) = calls.next() {
+            let mut count = 1;
+            while calls
+                .next_if(|next| next.call_span == call.call_span)
+                .is_some()
+            {
+                count += 1;
+            }
+
+            let label = match count {
+                1 => format!("`{}` called here", call.proc),
+                _ => format!("`{}` called here ({count} times)", call.proc),
+            };
+            labels.push(LabeledSpan::new_with_span(Some(label), call.call_span));
+        }
+
+        labels
+    }
+}
+
+/// A runtime error, with the calls that were active when it stopped the program.
+#[derive(Debug, Clone, Error)]
+#[error("{error}")]
+pub struct RunError {
+    pub error: RuntimeError,
+    pub trace: CallTrace,
+}
+
+impl From<RuntimeError> for RunError {
+    /// An error stopping the program before it starts, with no calls active.
+    fn from(error: RuntimeError) -> Self {
+        Self {
+            error,
+            trace: CallTrace::default(),
+        }
+    }
+}
+
+/// Shows the error, with the calls leading to it labeled and listed after its help.
+impl Diagnostic for RunError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.error.code()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        if self.trace.0.is_empty() {
+            return self.error.help();
+        }
+
+        let help = self.error.help().map_or_else(
+            || self.trace.to_string(),
+            |help| format!("{help}\n{}", self.trace),
+        );
+        Some(Box::new(help))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let labels = self.error.labels().into_iter().flatten();
+        Some(Box::new(labels.chain(self.trace.call_labels())))
+    }
+}
+
+/// Errors that stop a running program.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum RuntimeError {
+    #[diagnostic(
+        code(interp::no_main),
+        help("add a `proc main() void` or `proc main() int` at the top level")
+    )]
+    #[error("The program has no `main` procedure")]
+    NoMain,
+
+    #[diagnostic(code(interp::main_has_parameters))]
+    #[error("`main` cannot take parameters")]
+    MainHasParameters(#[label("declared here")] Span),
+
+    #[diagnostic(code(interp::overflow))]
+    #[error("Arithmetic overflow")]
+    Overflow(#[label("this overflows its int type")] Span),
+
+    #[diagnostic(code(interp::division_by_zero))]
+    #[error("Division by zero")]
+    DivisionByZero(#[label("the divisor is zero")] Span),
+
+    #[diagnostic(
+        code(interp::invalid_shift),
+        help("shift amounts must be at least 0 and less than the width of the type")
+    )]
+    #[error("Shift by {0}")]
+    InvalidShift(i128, #[label("shift amount out of range")] Span),
+
+    #[diagnostic(
+        code(interp::stack_overflow),
+        help("procedure calls can be nested at most {0} deep")
+    )]
+    #[error("Stack overflow in matrix program")]
+    StackOverflow(usize, #[label("this call is too deeply nested")] Span),
+
+    #[diagnostic(
+        code(interp::out_of_fuel),
+        help("the program can take at most {0} steps")
+    )]
+    #[error("Execution budget exhausted")]
+    OutOfFuel(u64, #[label("the budget ran out here")] Span),
+
+    #[diagnostic(code(interp::index_out_of_bounds))]
+    #[error("Index {0} is out of bounds for length {1}")]
+    IndexOutOfBounds(i64, usize, #[label("indexed here")] Span),
+
+    #[diagnostic(code(interp::pop_empty))]
+    #[error("Cannot pop from an empty array")]
+    PopEmpty(#[label("popped here")] Span),
+
+    #[diagnostic(
+        code(interp::missing_key),
+        help("check for the key with `has_key`, or read it with `get`, which takes a default")
+    )]
+    #[error("The map has no key {0}")]
+    MissingKey(String, #[label("indexed here")] Span),
+
+    #[diagnostic(
+        code(interp::empty_range),
+        help("`rand::rand_int(lo, hi)` includes both ends, so `hi` must be at least `lo`")
+    )]
+    #[error("Empty range from {0} to {1}")]
+    EmptyRange(i64, i64, #[label("in this call")] Span),
+
+    #[diagnostic(code(interp::assertion_failed))]
+    #[error("Assertion failed: {0}")]
+    AssertionFailed(String, #[label("this assertion is false")] Span),
+
+    #[diagnostic(code(interp::panicked))]
+    #[error("Panicked: {0}")]
+    Panicked(String, #[label("panicked here")] Span),
+
+    #[diagnostic(
+        code(interp::not_char_boundary),
+        help("strings are indexed by byte, and chars can span several bytes")
+    )]
+    #[error("Byte {0} is inside a char")]
+    NotCharBoundary(i64, #[label("sliced here")] Span),
+
+    #[diagnostic(
+        code(interp::invalid_number),
+        help("ints are written like `-42`, and floats like `1.5`, `2e10` or `inf`")
+    )]
+    #[error("`{0}` isn't a valid `{1}`")]
+    InvalidNumber(String, Ty, #[label("parsed here")] Span),
+
+    #[diagnostic(
+        code(interp::invalid_char),
+        help("chars are code points up to 0x10ffff, other than the surrogates 0xd800 to 0xdfff")
+    )]
+    #[error("{0} isn't the code point of a char")]
+    InvalidChar(i64, #[label("converted here")] Span),
+
+    #[diagnostic(code(interp::io))]
+    #[error("I/O error: {0}")]
+    Io(String, #[label("in this call")] Span),
+
+    #[diagnostic(
+        code(interp::sandboxed),
+        help("the program is sandboxed, so it can't access files, the environment or the clock")
+    )]
+    #[error("`{0}` is unavailable in the sandbox")]
+    Sandboxed(&'static str, #[label("called here")] Span),
+
+    #[diagnostic(code(interp::native))]
+    #[error("`{0}` failed: {1}")]
+    Native(String, String, #[label("in this call")] Span),
+
+    #[diagnostic(
+        code(interp::mismatched_natives),
+        help("lower the program with the signatures of the natives it's run with")
+    )]
+    #[error("The program was lowered with different native procedures than it's run with")]
+    MismatchedNatives,
+}
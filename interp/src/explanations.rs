@@ -0,0 +1,265 @@
+//! Extended explanations of the errors that stop a running program, which `mtxc explain`
+//! prints. The VM stops with the same errors.
+
+/// Every error's code and explanation. Short codes number them in this order, so new ones go
+/// at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "interp::no_main",
+        r#"The program is run, but has no `main` procedure to start from.
+
+`main` has to be declared at the top level, not in a module, and take no parameters:
+
+    proc main() void {
+        println("hello");
+    }
+
+It can return `void`, or an `int` that becomes the process's exit code."#,
+    ),
+    (
+        "interp::main_has_parameters",
+        r#"The program's `main` procedure takes parameters.
+
+Nothing calls `main` with arguments, so it can't have parameters:
+
+    proc main(name: str) void {
+        println("hello " + name);
+    }
+
+Read the command line with `arg_count()` and `arg(i)` instead, like `let name = arg(0);`."#,
+    ),
+    (
+        "interp::overflow",
+        r#"An arithmetic operation overflowed an `int`.
+
+An `int` is a signed 64-bit integer. With checked overflow, which is the default, a result
+outside its range stops the program:
+
+    proc main() int {
+        let x = 9223372036854775807;
+        ret x + 1;
+    }
+
+Check the values before the operation, or run with `--overflow wrapping` or
+`--overflow saturating` for arithmetic that wraps around or clamps instead."#,
+    ),
+    (
+        "interp::division_by_zero",
+        r#"An `int` is divided by zero, or a remainder of zero is taken.
+
+    proc average(total: int, count: int) int {
+        ret total / count;
+    }
+
+    proc main() int {
+        ret average(10, 0);
+    }
+
+Check the divisor first. Dividing a `float` by zero isn't an error, and gives an infinity or
+NaN."#,
+    ),
+    (
+        "interp::invalid_shift",
+        r#"An `int` is shifted by a negative amount, or by 64 or more.
+
+An `int` has 64 bits, so shift amounts have to be from 0 to 63. Check the amount before
+shifting."#,
+    ),
+    (
+        "interp::stack_overflow",
+        r#"Procedure calls are nested deeper than the limit, which is usually unbounded recursion.
+
+    proc countdown(n: int) int {
+        ret countdown(n - 1);
+    }
+
+Make sure every recursive procedure has a case that returns without calling itself, like
+`if n == 0 { ret 0; }`. For recursion that really is that deep, raise the limit with
+`--max-call-depth`."#,
+    ),
+    (
+        "interp::out_of_fuel",
+        r#"The program took more steps than `--fuel` allows.
+
+Fuel bounds how long a program can run, so a program that loops forever is stopped:
+
+    proc main() void {
+        while true {}
+    }
+
+Check the program's loops for conditions that never become false, or give it more fuel."#,
+    ),
+    (
+        "interp::index_out_of_bounds",
+        r#"An array or string is indexed past its end, or with a negative index.
+
+Indexes start at 0, so the last element of an array of length `n` is at `n - 1`:
+
+    proc main() int {
+        let xs = [1, 2, 3];
+        ret xs[3];
+    }
+
+Check the index against `len` first."#,
+    ),
+    (
+        "interp::pop_empty",
+        r#"`pop` is called on an empty array.
+
+    proc main() int {
+        let xs: [int] = [];
+        ret pop(xs);
+    }
+
+Check that `len(xs) > 0` before popping."#,
+    ),
+    (
+        "interp::assertion_failed",
+        r#"`assert` is called with a condition that's false.
+
+Assertions check what a program assumes, and are how `@test` procedures fail. The error
+gives the message passed with the condition:
+
+    @test
+    proc adds() void {
+        assert(1 + 1 == 3, "1 + 1 is 3");
+    }
+
+Fix the code the assertion checks, or the assertion if it's what's wrong."#,
+    ),
+    (
+        "interp::not_char_boundary",
+        r#"A string is sliced at a byte that's in the middle of a character.
+
+Strings are UTF-8 and indexed by byte, and characters outside ASCII take several bytes:
+
+    proc main() str {
+        ret slice("ü", 0, 1);
+    }
+
+Slice at the boundaries between characters, like `slice("ü", 0, 2)`."#,
+    ),
+    (
+        "interp::invalid_number",
+        r#"`to_int` or `to_float` is given text that isn't a number of that type.
+
+    proc main() int {
+        ret to_int("12a");
+    }
+
+Ints are written like `-42`, and floats like `1.5`, `2e10` or `inf`. Check input from the user
+before converting it, and trim any whitespace around it."#,
+    ),
+    (
+        "interp::io",
+        r#"Reading or writing a file, or the standard input or output, failed.
+
+The error includes the reason from the operating system, such as a missing file or a
+permission that's denied:
+
+    proc main() str {
+        ret read_file("missing.txt");
+    }
+
+Check the path, and that the program is allowed to access it."#,
+    ),
+    (
+        "interp::sandboxed",
+        r#"A sandboxed program tries to access a file.
+
+This is caught while compiling for programs compiled in the sandbox, but a program compiled
+without it can still be run in one:
+
+    proc main() str {
+        ret read_file("notes.txt");
+    }
+
+Run the program without `--sandbox`, or pass it what it needs through the standard input."#,
+    ),
+    (
+        "interp::native",
+        r#"A native procedure, which the program embedding matrix provides, returned an error.
+
+The error's message comes from the native procedure itself, and says what went wrong. Check
+the arguments the program passes to it."#,
+    ),
+    (
+        "interp::mismatched_natives",
+        r#"A program is run with different native procedures than it was compiled with.
+
+Calls to native procedures are resolved while lowering the program, so it has to be run with
+exactly the natives, with the same signatures, that it was lowered with. This is a mistake in
+the program embedding matrix, rather than in the matrix program."#,
+    ),
+    (
+        "interp::invalid_char",
+        r#"`from_int` is given a number that isn't the code point of a char.
+
+    proc main() char {
+        ret from_int(-1);
+    }
+
+Chars are Unicode scalar values: code points from 0 to 0x10ffff, other than the surrogates
+from 0xd800 to 0xdfff, which only appear in UTF-16."#,
+    ),
+    (
+        "interp::panicked",
+        r#"`panic` is called, which always stops the program with its message.
+
+It marks code that should never be reached, or a state the program can't continue from:
+
+    proc digit(c: char) int {
+        if c == '0' { ret 0; } elif c == '1' { ret 1; }
+        panic("not a binary digit");
+    }
+
+Find out why the program reached the `panic` from its message and the calls it was in. In an
+`@test` procedure, it fails the test like a false `assert` does."#,
+    ),
+    (
+        "interp::empty_range",
+        r#"`rand::rand_int` is asked for an int from a range with none in it.
+
+Both ends of the range are included, so `rand::rand_int(1, 6)` rolls a die, but the second
+must be at least the first:
+
+    import rand;
+
+    proc main() int {
+        ret rand::rand_int(6, 1);
+    }
+
+Swap the ends, or check them before asking for a number between them."#,
+    ),
+    (
+        "interp::missing_key",
+        r#"A map is indexed with a key it doesn't have.
+
+    proc main() int {
+        let ages = ["ann": 31, "bo": 27];
+        ret ages["cy"];
+    }
+
+Check that the map has the key with `has_key(ages, "cy")` first, or read it with
+`get(ages, "cy", 0)`, which gives the default passed last when the key is missing."#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_error_is_explained() {
+        let mut codes: Vec<_> = include_str!("diagnostics.rs")
+            .split("code(")
+            .skip(1)
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .filter(|code| !code.is_empty())
+            .collect();
+        let mut explained: Vec<_> = EXPLANATIONS.iter().map(|(code, _)| *code).collect();
+        codes.sort_unstable();
+        explained.sort_unstable();
+        assert_eq!(codes, explained);
+    }
+}
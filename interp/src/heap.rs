@@ -0,0 +1,320 @@
+//! The heap holding the strings, arrays and maps created while a program runs.
+//!
+//! Allocations are reference counted: every [`Value`] handle owns a count, and an allocation is
+//! freed as soon as its last handle is dropped, whether that's by overwriting a local, returning
+//! from a call or consuming a temporary. There's no pause for collection, and freeing is
+//! deterministic, so both ways of running a program use memory the same way.
+//!
+//! Reference counting can't free cycles, so the heap never lets one form. Strings are immutable
+//! and hold no handles. Arrays and maps are the only values holding other values, and storing
+//! an array or map into either stores a deep copy of it. The copy is new, so nothing can reach
+//! the array or map being stored into from it, and every array or map is owned by at most one
+//! other. Map keys are never arrays or maps.
+//!
+//! The heap keeps weak references to its allocations, so tests can check that a program
+//! didn't leak anything.
+
+use crate::{value::Key, RunResult, RuntimeError, Value};
+use span::Span;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+/// How many allocations are tracked before dead ones are first pruned.
+const INITIAL_PRUNE_AT: usize = 64;
+
+enum Allocation {
+    Str(Weak<str>),
+    Array(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<HashMap<Key, Value>>>),
+}
+
+impl Allocation {
+    fn is_live(&self) -> bool {
+        match self {
+            Self::Str(weak) => weak.strong_count() > 0,
+            Self::Array(weak) => weak.strong_count() > 0,
+            Self::Map(weak) => weak.strong_count() > 0,
+        }
+    }
+}
+
+pub struct Heap {
+    allocations: Vec<Allocation>,
+
+    /// How many allocations can be tracked before dead ones are pruned. Weak references keep
+    /// the memory of their allocation, so they can't be kept around forever.
+    prune_at: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            allocations: Vec::new(),
+            prune_at: INITIAL_PRUNE_AT,
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn track(&mut self, allocation: Allocation) {
+        if self.allocations.len() == self.prune_at {
+            self.allocations.retain(Allocation::is_live);
+            self.prune_at = (self.allocations.len() * 2).max(INITIAL_PRUNE_AT);
+        }
+        self.allocations.push(allocation);
+    }
+
+    pub fn alloc_str(&mut self, s: &str) -> Value {
+        let s = Rc::<str>::from(s);
+        self.track(Allocation::Str(Rc::downgrade(&s)));
+        Value::Str(s)
+    }
+
+    pub fn alloc_array(&mut self, elements: Vec<Value>) -> Value {
+        // Elements are stored like any other value, so arrays in them are copied.
+        let elements = elements
+            .into_iter()
+            .map(|element| self.deep_copy(element))
+            .collect();
+        let array = Rc::new(RefCell::new(elements));
+        self.track(Allocation::Array(Rc::downgrade(&array)));
+        Value::Array(array)
+    }
+
+    /// Allocate a map holding entries, with the key and value of each. A key given twice has
+    /// the last value given for it.
+    pub fn alloc_map(&mut self, entries: Vec<(Value, Value)>) -> Value {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (Key::new(&key), self.deep_copy(value)))
+            .collect();
+        self.map_of(entries)
+    }
+
+    fn map_of(&mut self, entries: HashMap<Key, Value>) -> Value {
+        let map = Rc::new(RefCell::new(entries));
+        self.track(Allocation::Map(Rc::downgrade(&map)));
+        Value::Map(map)
+    }
+
+    /// Copy every array and map within a value, sharing strings, which can't change.
+    fn deep_copy(&mut self, value: Value) -> Value {
+        match value {
+            Value::Array(array) => {
+                let elements = array.borrow().clone();
+                self.alloc_array(elements)
+            }
+            Value::Map(map) => {
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.deep_copy(value.clone())))
+                    .collect();
+                self.map_of(entries)
+            }
+            value => value,
+        }
+    }
+
+    /// The number of allocations still reachable from a handle.
+    pub fn live(&mut self) -> usize {
+        self.allocations.retain(Allocation::is_live);
+        self.allocations.len()
+    }
+
+    /// Join two strings into a new one.
+    pub fn concat(&mut self, lhs: &str, rhs: &str) -> Value {
+        self.alloc_str(&format!("{lhs}{rhs}"))
+    }
+
+    /// Copy the bytes of a string from `start` up to `end` into a new one.
+    pub fn slice(&mut self, s: &str, start: i64, end: i64, span: Span) -> RunResult<Value> {
+        let index = |index: i64| {
+            usize::try_from(index)
+                .ok()
+                .filter(|&index| index <= s.len())
+                .ok_or(RuntimeError::IndexOutOfBounds(index, s.len(), span))
+        };
+        let (start, end) = (index(start)?, index(end)?);
+
+        s.get(start..end.max(start))
+            .map(|slice| self.alloc_str(slice))
+            .ok_or_else(|| {
+                let split = if s.is_char_boundary(start) {
+                    end
+                } else {
+                    start
+                };
+                RuntimeError::NotCharBoundary(split as i64, span)
+            })
+    }
+
+    /// Read an element of an array.
+    pub fn load(&self, array: &RefCell<Vec<Value>>, index: i64, span: Span) -> RunResult<Value> {
+        let index = element_index(array, index, span)?;
+        Ok(array.borrow()[index].clone())
+    }
+
+    /// Store a value into an array, copying it if it's an array itself.
+    pub fn store(
+        &mut self,
+        array: &RefCell<Vec<Value>>,
+        index: i64,
+        value: Value,
+        span: Span,
+    ) -> RunResult<()> {
+        let index = element_index(array, index, span)?;
+        let value = self.deep_copy(value);
+        array.borrow_mut()[index] = value;
+        Ok(())
+    }
+
+    /// Append a value to an array, copying it if it's an array itself.
+    pub fn push(&mut self, array: &RefCell<Vec<Value>>, value: Value) {
+        let value = self.deep_copy(value);
+        array.borrow_mut().push(value);
+    }
+
+    /// Remove the last element of an array and return it.
+    pub fn pop(&mut self, array: &RefCell<Vec<Value>>, span: Span) -> RunResult<Value> {
+        array.borrow_mut().pop().ok_or(RuntimeError::PopEmpty(span))
+    }
+
+    /// Read the value of a key in a map.
+    pub fn get(
+        &self,
+        map: &RefCell<HashMap<Key, Value>>,
+        key: &Value,
+        span: Span,
+    ) -> RunResult<Value> {
+        map.borrow().get(&Key::new(key)).cloned().ok_or_else(|| {
+            // Quote strings and chars, so an empty string can be told apart.
+            let key = match key {
+                Value::Str(s) => format!("{s:?}"),
+                Value::Char(c) => format!("{c:?}"),
+                key => key.to_string(),
+            };
+            RuntimeError::MissingKey(key, span)
+        })
+    }
+
+    /// Set the value of a key in a map, copying it if it's an array or map itself.
+    pub fn insert(&mut self, map: &RefCell<HashMap<Key, Value>>, key: &Value, value: Value) {
+        let value = self.deep_copy(value);
+        map.borrow_mut().insert(Key::new(key), value);
+    }
+
+    /// Read an element of an array or the value of a key in a map.
+    pub fn load_indexed(&self, indexed: &Value, index: &Value, span: Span) -> RunResult<Value> {
+        match (indexed, index) {
+            (Value::Array(array), &Value::Int(index, _)) => self.load(array, index, span),
+            (Value::Map(map), key) => self.get(map, key, span),
+            _ => unreachable!("indexing is type checked"),
+        }
+    }
+
+    /// Store a value into an element of an array or as the value of a key in a map.
+    pub fn store_indexed(
+        &mut self,
+        indexed: &Value,
+        index: &Value,
+        value: Value,
+        span: Span,
+    ) -> RunResult<()> {
+        match (indexed, index) {
+            (Value::Array(array), &Value::Int(index, _)) => self.store(array, index, value, span),
+            (Value::Map(map), key) => {
+                self.insert(map, key, value);
+                Ok(())
+            }
+            _ => unreachable!("indexing is type checked"),
+        }
+    }
+}
+
+/// Check that an index is within an array.
+fn element_index(array: &RefCell<Vec<Value>>, index: i64, span: Span) -> RunResult<usize> {
+    let len = array.borrow().len();
+    usize::try_from(index)
+        .ok()
+        .filter(|&index| index < len)
+        .ok_or(RuntimeError::IndexOutOfBounds(index, len, span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heap_frees_unreachable_values() -> RunResult<()> {
+        let span = Span::from(0..0);
+        let mut heap = Heap::new();
+
+        let greeting = heap.concat("hello, ", "world");
+        let Value::Str(s) = &greeting else {
+            unreachable!("concatenation makes a string");
+        };
+        assert_eq!(heap.slice(s, 7, 12, span)?, Value::Str("world".into()));
+        assert!(matches!(
+            heap.slice(s, 3, 13, span),
+            Err(RuntimeError::IndexOutOfBounds(13, 12, _))
+        ));
+        assert!(matches!(
+            heap.slice("ü", 1, 2, span),
+            Err(RuntimeError::NotCharBoundary(1, _))
+        ));
+        // The slice was dropped as soon as it was compared.
+        assert_eq!(heap.live(), 1);
+
+        // Storing an array into itself stores a copy, so no cycle forms.
+        let array = heap.alloc_array(vec![greeting.clone(), Value::int(1)]);
+        let Value::Array(elements) = &array else {
+            unreachable!("arrays are allocated as arrays");
+        };
+        heap.store(elements, 1, array.clone(), span)?;
+        assert_eq!(array.to_string(), "[hello, world, [hello, world, 1]]");
+        assert!(matches!(
+            heap.store(elements, 2, Value::int(0), span),
+            Err(RuntimeError::IndexOutOfBounds(2, 2, _))
+        ));
+        assert_eq!(heap.live(), 3);
+
+        // Pushing copies too, and popping hands back the copy.
+        heap.push(elements, array.clone());
+        assert_eq!(heap.load(elements, 2, span)?, heap.pop(elements, span)?);
+        assert_eq!(heap.pop(elements, span)?.to_string(), "[hello, world, 1]");
+        heap.pop(elements, span)?;
+        assert!(matches!(
+            heap.pop(elements, span),
+            Err(RuntimeError::PopEmpty(_))
+        ));
+        assert_eq!(heap.live(), 2);
+
+        drop((greeting, array));
+        assert_eq!(heap.live(), 0);
+
+        // Maps copy the values stored into them too.
+        let map = heap.alloc_map(vec![(Value::int(2), Value::Bool(true))]);
+        let Value::Map(entries) = &map else {
+            unreachable!("maps are allocated as maps");
+        };
+        heap.insert(entries, &Value::int(1), map.clone());
+        assert_eq!(map.to_string(), "[1: [2: true], 2: true]");
+        assert!(matches!(
+            heap.get(entries, &Value::int(3), span),
+            Err(RuntimeError::MissingKey(key, _)) if key == "3"
+        ));
+        assert_eq!(heap.live(), 2);
+        drop(map);
+        assert_eq!(heap.live(), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1134 @@
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+pub mod builtins;
+mod diagnostics;
+mod explanations;
+pub mod heap;
+pub mod natives;
+pub mod ops;
+mod rng;
+mod value;
+
+pub use builtins::Io;
+pub use diagnostics::{CallTrace, RunError, RuntimeError, TraceFrame};
+pub use explanations::EXPLANATIONS;
+pub use heap::Heap;
+pub use natives::Natives;
+pub use rng::Rng;
+pub use value::{Key, Value};
+
+use hir::{
+    Block, Expr, ExprKind, LogicalOp, ModuleId, Pat, PatKind, ProcId, Program, Stmt, StmtKind,
+};
+use span::Span;
+
+/// How deeply procedure calls can be nested before the program is stopped, by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+/// How much of the host's stack the interpreter can use for each procedure call. It's generous,
+/// since nested expressions and blocks within a call recurse too.
+const STACK_PER_CALL: usize = 64 * 1024;
+
+/// Settings for running a program, shared by the interpreter and the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// How deeply procedure calls can be nested before the program is stopped with a stack
+    /// overflow. Tail calls reuse their caller's frame, so they don't nest.
+    pub max_call_depth: usize,
+
+    /// How many steps the program can take before it's stopped, or `None` to let it run until
+    /// it finishes. The interpreter takes a step for every statement and loop iteration, and
+    /// the VM for every instruction. Steps are counted rather than timed, so whether a program
+    /// runs out doesn't depend on how fast the host is.
+    pub fuel: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            fuel: None,
+        }
+    }
+}
+
+impl Options {
+    /// The size of the host stack the interpreter needs to reach the maximum call depth. The
+    /// interpreter recurses on the host's stack, so a thread this big can run programs up to
+    /// the limit without the host process itself overflowing.
+    pub fn interpreter_stack_size(&self) -> usize {
+        self.max_call_depth
+            .saturating_add(1)
+            .saturating_mul(STACK_PER_CALL)
+    }
+}
+
+/// How control leaves a statement.
+enum Flow {
+    /// Continue with the next statement.
+    Next,
+
+    /// Exit the innermost loop.
+    Break,
+
+    /// Return from the procedure with a value.
+    Ret(Value),
+
+    /// Return from the procedure with the result of calling another, reusing its frame.
+    TailCall {
+        callee: ProcId,
+        args: Vec<Value>,
+        span: Span,
+    },
+}
+
+pub type RunResult<T> = Result<T, RuntimeError>;
+
+/// An active procedure call.
+struct Frame {
+    proc: ProcId,
+
+    /// Where the procedure was called.
+    call_span: Span,
+    locals: Vec<Value>,
+}
+
+/// Runs a lowered program by walking its HIR.
+struct Interpreter<'a, 'n> {
+    program: &'a Program,
+    io: Io<'a>,
+    natives: &'n Natives,
+    heap: Heap,
+    options: Options,
+
+    /// Every active call, innermost last.
+    frames: Vec<Frame>,
+
+    /// The number of steps taken so far.
+    steps: u64,
+}
+
+impl<'a, 'n> Interpreter<'a, 'n> {
+    fn new(program: &'a Program, io: Io<'a>, natives: &'n Natives, options: Options) -> Self {
+        Self {
+            program,
+            io,
+            natives,
+            heap: Heap::new(),
+            options,
+            frames: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    fn frame(&mut self) -> &mut Vec<Value> {
+        &mut self
+            .frames
+            .last_mut()
+            .expect("code only runs within a procedure call")
+            .locals
+    }
+
+    fn trace(&self) -> CallTrace {
+        let frames = self.frames.iter().rev().map(|frame| TraceFrame {
+            proc: self.program.qualified_name(frame.proc),
+            call_span: frame.call_span,
+        });
+        CallTrace(frames.collect())
+    }
+
+    /// Take a step, or stop the program if its fuel has run out.
+    fn step(&mut self, span: Span) -> RunResult<()> {
+        if self.options.fuel == Some(self.steps) {
+            return Err(RuntimeError::OutOfFuel(self.steps, span));
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn call(&mut self, mut id: ProcId, mut args: Vec<Value>, mut span: Span) -> RunResult<Value> {
+        if self.frames.len() == self.options.max_call_depth {
+            let depth = self.options.max_call_depth;
+            return Err(RuntimeError::StackOverflow(depth, span));
+        }
+
+        // Tail calls run in the same frame, so they don't nest any deeper.
+        loop {
+            // An error leaves the frames as they were, for the trace.
+            let flow = self.exec_call(id, args, span)?;
+            self.frames.pop();
+
+            match flow {
+                Flow::Ret(value) => return Ok(value),
+                Flow::TailCall {
+                    callee,
+                    args: callee_args,
+                    span: call_span,
+                } => (id, args, span) = (callee, callee_args, call_span),
+                // Lowering checks that non-void procedures always return.
+                Flow::Next => return Ok(Value::Void),
+                Flow::Break => unreachable!("breaks only appear in loops"),
+            }
+        }
+    }
+
+    /// Push a frame for a call and run the procedure's body in it, leaving the frame in place.
+    fn exec_call(&mut self, id: ProcId, args: Vec<Value>, span: Span) -> RunResult<Flow> {
+        let proc = self.program.proc(id);
+        let mut locals = vec![Value::Void; proc.locals.len()];
+        for (&param, arg) in proc.params.iter().zip(args) {
+            locals[param.0 as usize] = arg;
+        }
+
+        self.frames.push(Frame {
+            proc: id,
+            call_span: span,
+            locals,
+        });
+        self.exec_block(&proc.body)
+    }
+
+    fn exec_block(&mut self, block: &Block) -> RunResult<Flow> {
+        for stmt in &block.stmts {
+            match self.exec_stmt(stmt)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> RunResult<Flow> {
+        self.step(stmt.span)?;
+        match &stmt.kind {
+            StmtKind::Let { local, init } => {
+                let value = self.eval(init)?;
+                self.frame()[local.0 as usize] = value;
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(expr)?;
+            }
+            // The outermost frame is kept, so traces always show where the program started.
+            StmtKind::Ret(Some(Expr {
+                kind: ExprKind::Call { callee, args },
+                span,
+                ..
+            })) if self.frames.len() > 1 => {
+                let args = self.eval_args(args)?;
+                return Ok(Flow::TailCall {
+                    callee: *callee,
+                    args,
+                    span: *span,
+                });
+            }
+            StmtKind::Ret(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::Void,
+                };
+                return Ok(Flow::Ret(value));
+            }
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                if self.eval_condition(cond)? {
+                    return self.exec_block(then_block);
+                } else if let Some(else_block) = else_block {
+                    return self.exec_block(else_block);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.eval_condition(cond)? {
+                    self.step(body.span)?;
+                    match self.exec_block(body)? {
+                        Flow::Next => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::Loop(body) => loop {
+                self.step(body.span)?;
+                match self.exec_block(body)? {
+                    Flow::Next => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+            },
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Block(block) => return self.exec_block(block),
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn eval_args(&mut self, args: &[Expr]) -> RunResult<Vec<Value>> {
+        args.iter().map(|arg| self.eval(arg)).collect()
+    }
+
+    fn eval_condition(&mut self, cond: &Expr) -> RunResult<bool> {
+        Ok(self.eval(cond)?.is_truthy())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> RunResult<Value> {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(literal.into()),
+            ExprKind::Local(local) => Ok(self.frame()[local.0 as usize].clone()),
+            ExprKind::Const(id) => Ok((&self.program.constant(*id).value).into()),
+            ExprKind::Call { callee, args } => {
+                let args = self.eval_args(args)?;
+                self.call(*callee, args, span)
+            }
+            ExprKind::Builtin { builtin, args } => {
+                let args = self.eval_args(args)?;
+                builtins::call(*builtin, args, &mut self.io, &mut self.heap, span)
+            }
+            ExprKind::Native { native, args } => {
+                let args = self.eval_args(args)?;
+                self.natives
+                    .call(*native, &args, &mut self.io, &mut self.heap, span)
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand = self.eval(operand)?;
+                ops::unary(*op, operand, self.program.overflow, span)
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                ops::binary(&mut self.heap, *op, lhs, rhs, self.program.overflow, span)
+            }
+            ExprKind::Logical { lhs, op, rhs } => {
+                let lhs = self.eval_condition(lhs)?;
+                match (op, lhs) {
+                    (LogicalOp::And, false) => Ok(Value::Bool(false)),
+                    (LogicalOp::Or, true) => Ok(Value::Bool(true)),
+                    _ => self.eval(rhs),
+                }
+            }
+            ExprKind::Assign { local, value } => {
+                let value = self.eval(value)?;
+                self.frame()[local.0 as usize] = value;
+                Ok(Value::Void)
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let value = self.eval(scrutinee)?;
+                let arm = arms
+                    .iter()
+                    .find(|arm| pattern_matches(&arm.pat, &value))
+                    .expect("matches are checked to be exhaustive");
+                self.eval(&arm.body)
+            }
+            ExprKind::Array(elements) => {
+                let elements = self.eval_args(elements)?;
+                Ok(self.heap.alloc_array(elements))
+            }
+            ExprKind::Map(entries) => {
+                let mut evaluated = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    evaluated.push((self.eval(key)?, self.eval(value)?));
+                }
+                Ok(self.heap.alloc_map(evaluated))
+            }
+            ExprKind::Index { array, index } => {
+                let (array, index) = (self.eval(array)?, self.eval(index)?);
+                self.heap.load_indexed(&array, &index, span)
+            }
+            ExprKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                let (array, index) = (self.eval(array)?, self.eval(index)?);
+                let mut value = self.eval(value)?;
+                if let Some(op) = *op {
+                    let element = self.heap.load_indexed(&array, &index, span)?;
+                    value = ops::binary(
+                        &mut self.heap,
+                        op,
+                        element,
+                        value,
+                        self.program.overflow,
+                        span,
+                    )?;
+                }
+                self.heap.store_indexed(&array, &index, value, span)?;
+                Ok(Value::Void)
+            }
+            ExprKind::Error => unreachable!("erroneous programs are never run"),
+        }
+    }
+}
+
+fn pattern_matches(pat: &Pat, value: &Value) -> bool {
+    match &pat.kind {
+        PatKind::Wildcard => true,
+        PatKind::Literal(literal) => Value::from(literal) == *value,
+        PatKind::Range {
+            start,
+            end,
+            inclusive,
+        } => value.in_range(&start.into(), &end.into(), *inclusive),
+        PatKind::Or(alternatives) => alternatives.iter().any(|alt| pattern_matches(alt, value)),
+        PatKind::Error => unreachable!("erroneous programs are never run"),
+    }
+}
+
+/// Run a program's `main` procedure with the standard input and output, returning its result.
+///
+/// The interpreter recurses on the current thread's stack, which must be at least
+/// [`Options::interpreter_stack_size`].
+pub fn run(program: &Program, options: Options) -> Result<Value, RunError> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    run_with_io(program, Io::new(&mut input, &mut output), options)
+}
+
+/// Run a program's `main` procedure, returning its result.
+pub fn run_with_io<'a>(
+    program: &'a Program,
+    io: Io<'a>,
+    options: Options,
+) -> Result<Value, RunError> {
+    run_with_natives(program, io, &Natives::new(), options)
+}
+
+/// Run a program like [`run_with_io`], letting it call the natives it was lowered with.
+pub fn run_with_natives<'a>(
+    program: &'a Program,
+    io: Io<'a>,
+    natives: &Natives,
+    options: Options,
+) -> Result<Value, RunError> {
+    if !natives.matches(&program.natives) {
+        return Err(RuntimeError::MismatchedNatives.into());
+    }
+
+    let main = program
+        .procs
+        .iter()
+        .find(|proc| proc.module == ModuleId::ROOT && proc.name == "main")
+        .ok_or(RuntimeError::NoMain)?;
+
+    if !main.params.is_empty() {
+        return Err(RuntimeError::MainHasParameters(main.span).into());
+    }
+
+    let mut interpreter = Interpreter::new(program, io, natives, options);
+    interpreter
+        .call(main.id, Vec::new(), main.span)
+        .map_err(|error| RunError {
+            error,
+            trace: interpreter.trace(),
+        })
+}
+
+/// Run a procedure of a program that takes no parameters, like a test, instead of its `main`,
+/// returning its result.
+pub fn run_proc<'a>(
+    program: &'a Program,
+    proc: ProcId,
+    io: Io<'a>,
+    options: Options,
+) -> Result<Value, RunError> {
+    let natives = Natives::new();
+    if !natives.matches(&program.natives) {
+        return Err(RuntimeError::MismatchedNatives.into());
+    }
+    let proc = program.proc(proc);
+    assert!(proc.params.is_empty(), "`{}` takes parameters", proc.name);
+
+    let mut interpreter = Interpreter::new(program, io, &natives, options);
+    interpreter
+        .call(proc.id, Vec::new(), proc.span)
+        .map_err(|error| RunError {
+            error,
+            trace: interpreter.trace(),
+        })
+}
+
+/// Call a procedure of a program, returning its result and the values its locals were left
+/// with, so a REPL can keep them for its next input.
+pub fn call_keeping_locals<'a>(
+    program: &'a Program,
+    proc: ProcId,
+    args: Vec<Value>,
+    io: Io<'a>,
+    options: Options,
+) -> Result<(Value, Vec<Value>), RunError> {
+    let natives = Natives::new();
+    if !natives.matches(&program.natives) {
+        return Err(RuntimeError::MismatchedNatives.into());
+    }
+
+    let mut interpreter = Interpreter::new(program, io, &natives, options);
+    let span = program.proc(proc).span;
+    let flow = interpreter
+        .exec_call(proc, args, span)
+        .map_err(|error| RunError {
+            error,
+            trace: interpreter.trace(),
+        })?;
+    let frame = interpreter.frames.pop().expect("the call's frame is kept");
+
+    // The outermost frame is kept, so its `ret`s of calls aren't tail calls.
+    let value = match flow {
+        Flow::Ret(value) => value,
+        Flow::Next => Value::Void,
+        Flow::TailCall { .. } | Flow::Break => unreachable!("the outermost call only returns"),
+    };
+    Ok((value, frame.locals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hir::Ty;
+    use miette::Diagnostic;
+    use std::{cell::Cell, rc::Rc};
+
+    fn run_source(source: &str) -> anyhow::Result<Result<Value, RunError>> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        Ok(run(&program, Options::default()))
+    }
+
+    /// Run a program on a thread with enough stack for its call depth, like the CLI does,
+    /// returning its result as a string.
+    fn run_on_big_stack(
+        source: &str,
+        options: Options,
+    ) -> anyhow::Result<Result<String, RunError>> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let result = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(options.interpreter_stack_size())
+                .spawn_scoped(scope, || {
+                    run(&program, options).map(|value| value.to_string())
+                })
+                .map(|thread| thread.join().unwrap())
+        })?;
+        Ok(result)
+    }
+
+    #[test]
+    fn test_run_control_flow_and_calls() -> anyhow::Result<()> {
+        let source = "const LIMIT: int = 10;
+            proc fib(n: int) int {
+                if n < 2 { ret n; }
+                ret fib(n - 1) + fib(n - 2);
+            }
+            proc main() int {
+                let total = 0;
+                for let i = 0; i < LIMIT; i += 1 {
+                    if i % 2 == 0 { total += fib(i); }
+                }
+                let n = 0;
+                do { n += 1; } while n < 5;
+                while n < 8 { n += 1; }
+                ret total * 100 + match n { 0..8 => 0, 8 => 1, _ => 2 };
+            }";
+
+        // fib(0) + fib(2) + fib(4) + fib(6) + fib(8) = 0 + 1 + 3 + 8 + 21
+        assert_eq!(run_source(source)?.unwrap(), Value::int(3301));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_reports_runtime_errors() -> anyhow::Result<()> {
+        let source = "proc half(n: int) int { ret n / (n - n); }
+            proc main() int { ret half(4); }";
+        let error = run_source(source)?.unwrap_err();
+        let division = source.find("n / (n - n)").unwrap();
+        assert!(matches!(
+            error.error,
+            RuntimeError::DivisionByZero(span) if span.start == division
+        ));
+        let labels = error.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels[1].label(), Some("`half` called here"));
+        assert_eq!(labels[1].offset(), source.find("half(4)").unwrap());
+        assert_eq!(labels.len(), 2);
+
+        let source =
+            "proc f(n: int) int { let m = f(n + 1); ret m; } proc main() int { ret f(0); }";
+        let error = run_on_big_stack(source, Options::default())?.unwrap_err();
+        assert!(matches!(
+            error.error,
+            RuntimeError::StackOverflow(DEFAULT_MAX_CALL_DEPTH, _)
+        ));
+        assert_eq!(error.trace.0.len(), DEFAULT_MAX_CALL_DEPTH);
+        assert_eq!(
+            error.help().unwrap().to_string(),
+            "procedure calls can be nested at most 256 deep
+call trace, innermost first:
+    f (255 calls)
+    main"
+        );
+        let labels = error
+            .labels()
+            .unwrap()
+            .map(|label| label.label().map(str::to_owned));
+        assert_eq!(
+            labels.collect::<Vec<_>>(),
+            [
+                Some("this call is too deeply nested".to_owned()),
+                Some("`f` called here (254 times)".to_owned()),
+                Some("`f` called here".to_owned()),
+            ]
+        );
+
+        let source = "proc start() void {}";
+        assert!(matches!(
+            run_source(source)?,
+            Err(RunError {
+                error: RuntimeError::NoMain,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_builtins() -> anyhow::Result<()> {
+        let source = r#"proc main() void {
+                print("name? ");
+                let name = read_line();
+                if name == "world" { println("hello"); }
+                println(to_str(6 * 7));
+                print(to_str(true));
+                print(to_str('x'));
+                println(to_str(2.5));
+                println(read_line());
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let mut input: &[u8] = b"world\r\n";
+        let mut output = Vec::new();
+        run_with_io(
+            &program,
+            Io::new(&mut input, &mut output),
+            Options::default(),
+        )?;
+        assert_eq!(String::from_utf8(output)?, "name? hello\n42\ntruex2.5\n\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_proc_with_assertions() -> anyhow::Result<()> {
+        let source = r#"proc check(n: int) void { assert(n % 2 == 0, "odd"); }
+            proc passes() void { check(4); }
+            proc fails() void { check(3); }
+            proc panics() int { panic("never " + "returns"); }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        let run = |name| {
+            let (mut input, mut output) = (&b""[..], Vec::new());
+            let io = Io::new(&mut input, &mut output);
+            run_proc(
+                &program,
+                program.proc_by_name(name).unwrap().id,
+                io,
+                Options::default(),
+            )
+        };
+
+        assert_eq!(run("passes")?, Value::Void);
+        let error = run("fails").unwrap_err();
+        let assertion = source.find("n % 2 == 0").unwrap();
+        assert!(matches!(
+            error.error,
+            RuntimeError::AssertionFailed(ref message, span)
+                if message == "odd" && span.start == assertion - 7
+        ));
+        assert_eq!(error.trace.0.len(), 2);
+
+        let error = run("panics").unwrap_err();
+        assert_eq!(error.error.to_string(), "Panicked: never returns");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_string_builtins() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let csv = "3,-4.5,héllo";
+                let total = to_float(split(csv, ",", 1)) + to_float(to_str(to_int(split(csv, ",", 0))));
+                let word = split(csv, ",", split_count(csv, ",") - 1);
+                if contains(word, "ll") {
+                    if char_at(word, 1) == 'é' {
+                        ret to_str(total) + substr(word, 3, 3) + to_str(split_count(csv, ""));
+                    }
+                }
+                ret "";
+            }"#;
+        assert_eq!(run_source(source)??, Value::Str("-1.5llo1".into()));
+
+        let source = r#"proc main() int { ret to_int("12a"); }"#;
+        assert!(matches!(
+            run_source(source)?.unwrap_err().error,
+            RuntimeError::InvalidNumber(text, Ty::INT, _) if text == "12a"
+        ));
+        let source = r#"proc main() bool { ret char_at("é", 1) == 'e'; }"#;
+        assert!(matches!(
+            run_source(source)?.unwrap_err().error,
+            RuntimeError::NotCharBoundary(1, _)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_string_operators() -> anyhow::Result<()> {
+        // The strings are built at runtime, so nothing is folded before the program runs.
+        let source = r#"proc order(a: str, b: str) str {
+                if a < b { ret "<"; } elif a > b { ret ">"; }
+                ret "=";
+            }
+            proc main() str {
+                let ab = "a" + to_str(2);
+                ret order(ab, "a2") + order(ab, "a") + order("B", "a") + order("", ab)
+                    + to_str(ab != "a2") + to_str(ab == "a" + "2");
+            }"#;
+        assert_eq!(run_source(source)??, Value::Str("=><<falsetrue".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_shift_and_logical_operators() -> anyhow::Result<()> {
+        let source = "proc main() str {
+                let x = 1 << 4;
+                x <<= 2;
+                x >>= 3;
+                x |= 3;
+                x &= 6;
+                let y = 100 >> 2;
+                let ok = x <= 2 && y >= 25 || x == y;
+                ret to_str(x) + \",\" + to_str(y) + \",\" + to_str(ok);
+            }";
+        assert_eq!(run_source(source)??, Value::Str("2,25,true".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_chars() -> anyhow::Result<()> {
+        let source = r#"proc next(c: char) char { ret from_int(to_int(c) + 1); }
+            proc main() str {
+                let word = "héllo";
+                let c: char = word[1];
+                if c > 'z' {
+                    ret to_str(c) + to_str(next(word[0])) + to_str(to_int(c));
+                }
+                ret "";
+            }"#;
+        assert_eq!(run_source(source)??, Value::Str("éi233".into()));
+
+        let source = "proc main() char { ret from_int(0xd800); }";
+        assert!(matches!(
+            run_source(source)?.unwrap_err().error,
+            RuntimeError::InvalidChar(0xd800, _)
+        ));
+        let source = r#"proc main() char { ret "é"[1]; }"#;
+        assert!(matches!(
+            run_source(source)?.unwrap_err().error,
+            RuntimeError::NotCharBoundary(1, _)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_math_module() -> anyhow::Result<()> {
+        let source = "import math;
+            proc main() float {
+                ret math::sqrt(16.0) + math::abs(-2.5) + math::pow(2.0, 10.0)
+                    + math::floor(math::PI) + math::ceil(math::E)
+                    + math::min(1.0, 0.0 / 0.0) + math::max(-1.0, 4.0);
+            }";
+        assert_eq!(run_source(source)??, Value::Float(1041.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_rand_module() -> anyhow::Result<()> {
+        let source = r#"import rand;
+            proc roll() str {
+                let rolls = "";
+                for let i = 0; i < 5; i += 1 { rolls = rolls + to_str(rand::rand_int(1, 6)); }
+                ret rolls + " " + to_str(rand::rand_float() < 1.0);
+            }
+            proc main() bool {
+                rand::seed(42);
+                let first = roll();
+                rand::seed(42);
+                if first == roll() { ret rand::rand_int(7, 7) == 7; }
+                ret false;
+            }"#;
+        assert_eq!(run_source(source)??, Value::Bool(true));
+
+        // Unseeded programs are seeded by the I/O.
+        let source = "import rand; proc main() int { ret rand::rand_int(0, 1000000); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        let run = |seed| {
+            let (mut input, mut output) = (&b""[..], Vec::new());
+            let io = Io::new(&mut input, &mut output).seeded(seed);
+            run_with_io(&program, io, Options::default())
+        };
+        assert_eq!(run(7)?, run(7)?);
+
+        let mut rng = Rng::seeded(7);
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = Io::new(&mut input, &mut output).with_rng(&mut rng);
+        assert_eq!(run_with_io(&program, io, Options::default())?, run(7)?);
+        assert_ne!(rng, Rng::seeded(7));
+
+        let source = "import rand; proc main() int { ret rand::rand_int(6, 1); }";
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::EmptyRange(6, 1, _))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_args() -> anyhow::Result<()> {
+        let source = r#"proc main() int {
+                for let i = 0; i < arg_count(); i += 1 { print(arg(i) + " "); }
+                ret len(arg(arg_count()));
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let args = ["one".to_owned(), "two words".to_owned()];
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = Io::new(&mut input, &mut output).with_args(&args);
+        let error = run_with_io(&program, io, Options::default()).unwrap_err();
+        assert!(matches!(
+            error.error,
+            RuntimeError::IndexOutOfBounds(2, 2, _)
+        ));
+        assert_eq!(String::from_utf8(output)?, "one two words ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_args_as_an_array() -> anyhow::Result<()> {
+        let source = r#"proc main() int {
+                let all = args();
+                print(all[1] + "|" + all[0]);
+                ret len(all);
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let args = ["one".to_owned(), "two words".to_owned()];
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = Io::new(&mut input, &mut output).with_args(&args);
+        assert_eq!(
+            run_with_io(&program, io, Options::default())?,
+            Value::int(2)
+        );
+        assert_eq!(String::from_utf8(output)?, "two words|one");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_file_builtins() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let path = arg(0);
+                write_file(path, "one\n");
+                append_file(path, "two\n");
+                ret read_file(path);
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let path = std::env::temp_dir().join(format!("matrix-files-{}", std::process::id()));
+        let args = [path.display().to_string()];
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = Io::new(&mut input, &mut output).with_args(&args);
+        let value = run_with_io(&program, io, Options::default())?;
+        assert_eq!(value, Value::Str("one\ntwo\n".into()));
+        std::fs::remove_file(&path)?;
+
+        let io = Io::new(&mut input, &mut output)
+            .with_args(&args)
+            .sandboxed();
+        let error = run_with_io(&program, io, Options::default()).unwrap_err();
+        assert!(matches!(
+            error.error,
+            RuntimeError::Sandboxed("write_file", _)
+        ));
+        assert!(!path.exists());
+
+        let source = r#"proc main() str { ret read_file("/nonexistent/matrix"); }"#;
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::Io(message, _)) if message.starts_with("`/nonexistent/matrix`: ")
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_env_and_clock_builtins() -> anyhow::Result<()> {
+        // Cargo sets the name of the package whose tests it runs.
+        let source = r#"proc main() str {
+                let start = clock_ms();
+                sleep_ms(20);
+                sleep_ms(-1);
+                let slept = clock_ms() - start > 19;
+                ret env("CARGO_PKG_NAME") + env("MATRIX_UNSET_VARIABLE") + to_str(slept);
+            }"#;
+        assert_eq!(run_source(source)??, Value::Str("interptrue".into()));
+
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = Io::new(&mut input, &mut output).sandboxed();
+        let error = run_with_io(&program, io, Options::default()).unwrap_err();
+        assert!(matches!(
+            error.error,
+            RuntimeError::Sandboxed("clock_ms", _)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_strings_without_leaks() -> anyhow::Result<()> {
+        let source = r#"const GREETING: str = "hello" + ", ";
+            proc shout(s: str) str { ret s + "!"; }
+            proc main() str {
+                let name = "wörld";
+                let result = "";
+                for let i = 0; i < 3; i += 1 {
+                    result = shout(GREETING + name);
+                }
+                ret slice(result, 0, 5) + to_str(len(result)) + slice(result, 7, len(result));
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        let main = program
+            .procs
+            .iter()
+            .find(|proc| proc.name == "main")
+            .unwrap();
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let natives = Natives::new();
+        let mut interpreter = Interpreter::new(
+            &program,
+            Io::new(&mut input, &mut output),
+            &natives,
+            Options::default(),
+        );
+        let value = interpreter.call(main.id, Vec::new(), main.span)?;
+        assert_eq!(value, Value::Str("hello14wörld!".into()));
+
+        // Only the result is still alive.
+        assert_eq!(interpreter.heap.live(), 1);
+        drop(value);
+        assert_eq!(interpreter.heap.live(), 0);
+
+        let source = r#"proc main() str { ret slice("ü", 0, 1); }"#;
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::NotCharBoundary(1, _))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_arrays() -> anyhow::Result<()> {
+        let source = "proc sum(xs: [int]) int {
+                let total = 0;
+                for x in xs { total += x; }
+                ret total;
+            }
+            proc main() str {
+                let xs: [int] = [];
+                for let i = 0; i < 5; i += 1 { push(xs, i * i); }
+                xs[1] += 10;
+                let grid = [xs, [sum(xs)]];
+                push(xs, pop(grid[0]) + len(grid));
+                ret to_str(grid) + to_str(xs);
+            }";
+        assert_eq!(
+            run_source(source)?.unwrap(),
+            Value::Str("[[0, 11, 4, 9], [40]][0, 11, 4, 9, 16, 18]".into())
+        );
+
+        let source = "proc main() int { let xs = [1, 2]; ret xs[2]; }";
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::IndexOutOfBounds(2, 2, _))
+        ));
+
+        let source = "proc main() int { let xs = [1]; pop(xs); ret pop(xs); }";
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::PopEmpty(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_maps() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let ages = ["bo": 27, "ann": 31];
+                ages["cy"] = 40;
+                ages["bo"] += 1;
+                insert(ages, "dee", get(ages, "ed", 5));
+                let names = "";
+                for name in ages {
+                    names = names + name;
+                    if name == "bo" { remove(ages, "cy"); }
+                }
+                let groups: [bool: [int]] = [true: [1]];
+                push(groups[true], len(ages));
+                let shared = groups;
+                groups[false] = groups[true];
+                push(groups[false], 0);
+                ret names + to_str(ages) + to_str(has_key(ages, "cy")) + to_str(shared);
+            }"#;
+        // The loop visits the keys the map had when it started, and storing a map's value into
+        // it stores a copy.
+        assert_eq!(
+            run_source(source)?.unwrap().to_string(),
+            "annbocydee[ann: 31, bo: 28, dee: 5]false[false: [1, 3, 0], true: [1, 3]]"
+        );
+
+        let source = r#"proc main() int { let ages = ["ann": 31]; ret ages[""]; }"#;
+        assert!(matches!(
+            run_source(source)?.map_err(|error| error.error),
+            Err(RuntimeError::MissingKey(key, _)) if key == r#""""#
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_for_loops() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let out = "";
+                for i in 0..4 {
+                    out += to_str(i);
+                    i = 10;
+                }
+                for b in 250 as u8..=255 { out += to_str(b % 2); }
+                for i in 2..=1 { out += "never"; }
+                for c in "añ€" { out += to_str(c) + "."; }
+                ret out;
+            }"#;
+        assert_eq!(
+            run_source(source)?.unwrap(),
+            Value::Str("0123010101a.ñ.€.".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_natives() -> anyhow::Result<()> {
+        let calls = Rc::new(Cell::new(0));
+        let mut natives = Natives::new();
+        let counter = Rc::clone(&calls);
+        natives
+            .register_fn("tick", move || counter.set(counter.get() + 1))
+            .register_fn("shout", |s: String| s.to_uppercase() + "!")
+            .register_fn("checked_div", |a: i64, b: i64| {
+                a.checked_div(b).ok_or("division by zero")
+            });
+
+        let lower = |source: &str| -> anyhow::Result<_> {
+            let tokens = lexer::lex(source)?;
+            let ast = parser::parse(source, tokens)?;
+            let signatures = natives.signatures();
+            let options = hir::LowerOptions {
+                natives: &signatures,
+                ..Default::default()
+            };
+            Ok(hir::lower_with(&ast, &options))
+        };
+        let run = |program: &Program| {
+            let (mut input, mut output) = (&b""[..], Vec::new());
+            let io = Io::new(&mut input, &mut output);
+            run_with_natives(program, io, &natives, Options::default())
+        };
+
+        let source = r#"proc main() str {
+                tick();
+                tick();
+                ret shout("hey") + to_str(checked_div(7, 2));
+            }"#;
+        let program = lower(source)?.unwrap().program;
+        assert_eq!(run(&program)?, Value::Str("HEY!3".into()));
+        assert_eq!(calls.get(), 2);
+
+        let source = "proc main() int { ret checked_div(1, 0); }";
+        let error = run(&lower(source)?.unwrap().program).unwrap_err();
+        assert!(matches!(
+            error.error,
+            RuntimeError::Native(name, message, _)
+                if name == "checked_div" && message == "division by zero"
+        ));
+
+        // Calls to natives are type checked.
+        let source = "proc main() void { tick(1); shout(2); }";
+        assert_eq!(lower(source)?.unwrap_err().into_diagnostics().len(), 2);
+
+        // A program can only run with the natives it was lowered with.
+        let source = "proc main() void {}";
+        let program = hir::lower(&parser::parse(source, lexer::lex(source)?)?)?.program;
+        assert!(matches!(
+            run(&program).unwrap_err().error,
+            RuntimeError::MismatchedNatives
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_deep_recursion_on_a_big_stack() -> anyhow::Result<()> {
+        let source = "proc count(n: int) int { if n == 0 { ret 0; } ret 1 + count(n - 1); }
+            proc main() int { ret count(5000); }";
+        let options = Options {
+            max_call_depth: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(run_on_big_stack(source, options)??, "5000");
+
+        Ok(())
+    }
+}
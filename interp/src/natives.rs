@@ -0,0 +1,336 @@
+//! Native procedures, which let a program embedding the interpreter extend matrix with Rust
+//! functions.
+//!
+//! Natives are registered with their Rust types, which give their matrix signatures. A program
+//! is lowered with [`Natives::signatures`], so calls to natives are resolved and type checked
+//! like calls to any other procedure, then run with the same natives. Every program can also
+//! call the [`SystemNative`]s, which the interpreter and the VM run here too.
+
+use crate::{builtins, heap::Heap, Io, RunResult, RuntimeError, Value};
+use hir::{IntTy, NativeId, NativeSignature, SystemNative, Ty};
+use span::Span;
+use std::{fmt, rc::Rc};
+
+/// A Rust type native procedures can take as a parameter.
+pub trait FromValue: Sized {
+    /// The matrix type of arguments for parameters of this type.
+    const TY: Ty;
+
+    /// Convert an argument, or return `None` if it has the wrong type.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+/// A Rust type native procedures can return.
+pub trait IntoValue {
+    /// The matrix type of the values this type converts to.
+    const TY: Ty;
+
+    /// Convert a result, or return the message of an error stopping the program.
+    fn into_value(self, heap: &mut Heap) -> Result<Value, String>;
+}
+
+macro_rules! impl_scalar {
+    ($($rust:ty => $variant:ident),*) => {
+        $(
+            impl FromValue for $rust {
+                const TY: Ty = Ty::$variant;
+
+                fn from_value(value: &Value) -> Option<Self> {
+                    match *value {
+                        Value::$variant(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl IntoValue for $rust {
+                const TY: Ty = Ty::$variant;
+
+                fn into_value(self, _: &mut Heap) -> Result<Value, String> {
+                    Ok(Value::$variant(self))
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar!(f64 => Float, bool => Bool, char => Char);
+
+macro_rules! impl_int {
+    ($($rust:ty => $ty:ident),*) => {
+        $(
+            impl FromValue for $rust {
+                const TY: Ty = Ty::Int(IntTy::$ty);
+
+                fn from_value(value: &Value) -> Option<Self> {
+                    match *value {
+                        Value::Int(value, IntTy::$ty) => Some(value as $rust),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl IntoValue for $rust {
+                const TY: Ty = Ty::Int(IntTy::$ty);
+
+                fn into_value(self, _: &mut Heap) -> Result<Value, String> {
+                    Ok(Value::Int(IntTy::$ty.wrap(self.into()), IntTy::$ty))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64, u8 => U8, u16 => U16, u32 => U32, u64 => U64
+);
+
+impl FromValue for Rc<str> {
+    const TY: Ty = Ty::Str;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Str(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    const TY: Ty = Ty::Str;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Rc::<str>::from_value(value).map(|s| s.to_string())
+    }
+}
+
+impl IntoValue for String {
+    const TY: Ty = Ty::Str;
+
+    fn into_value(self, heap: &mut Heap) -> Result<Value, String> {
+        Ok(heap.alloc_str(&self))
+    }
+}
+
+impl IntoValue for &str {
+    const TY: Ty = Ty::Str;
+
+    fn into_value(self, heap: &mut Heap) -> Result<Value, String> {
+        Ok(heap.alloc_str(self))
+    }
+}
+
+impl IntoValue for () {
+    const TY: Ty = Ty::Void;
+
+    fn into_value(self, _: &mut Heap) -> Result<Value, String> {
+        Ok(Value::Void)
+    }
+}
+
+/// Natives can fail, stopping the program with the error's message.
+impl<T: IntoValue, E: fmt::Display> IntoValue for Result<T, E> {
+    const TY: Ty = T::TY;
+
+    fn into_value(self, heap: &mut Heap) -> Result<Value, String> {
+        self.map_err(|error| error.to_string())?.into_value(heap)
+    }
+}
+
+/// A Rust function that can be registered as a native procedure.
+///
+/// It's implemented for functions of up to four parameters of [`FromValue`] types, returning an
+/// [`IntoValue`] type, where `Args` is the tuple of parameter types.
+pub trait NativeFn<Args>: 'static {
+    fn params() -> Vec<Ty>;
+    fn ret_ty() -> Ty;
+
+    /// Call the function with arguments of the types of its parameters.
+    fn call(&self, args: &[Value], heap: &mut Heap) -> Result<Value, String>;
+}
+
+fn argument<T: FromValue>(value: &Value) -> Result<T, String> {
+    T::from_value(value).ok_or_else(|| {
+        let found = value.type_name();
+        format!("expected an argument of type `{}`, found `{found}`", T::TY)
+    })
+}
+
+macro_rules! impl_native_fn {
+    ($($param:ident $arg:ident),*) => {
+        impl<F, R, $($param),*> NativeFn<($($param,)*)> for F
+        where
+            F: Fn($($param),*) -> R + 'static,
+            R: IntoValue,
+            $($param: FromValue,)*
+        {
+            fn params() -> Vec<Ty> {
+                vec![$($param::TY),*]
+            }
+
+            fn ret_ty() -> Ty {
+                R::TY
+            }
+
+            fn call(&self, args: &[Value], heap: &mut Heap) -> Result<Value, String> {
+                let [$($arg),*] = args else {
+                    let expected = Self::params().len();
+                    return Err(format!("expected {expected} arguments, found {}", args.len()));
+                };
+                self($(argument::<$param>($arg)?),*).into_value(heap)
+            }
+        }
+    };
+}
+
+impl_native_fn!();
+impl_native_fn!(A a);
+impl_native_fn!(A a, B b);
+impl_native_fn!(A a, B b, C c);
+impl_native_fn!(A a, B b, C c, D d);
+
+type Callback = Box<dyn Fn(&[Value], &mut Heap) -> Result<Value, String>>;
+
+struct Native {
+    signature: NativeSignature,
+    callback: Callback,
+}
+
+/// The native procedures a program can call besides the system natives.
+#[derive(Default)]
+pub struct Natives {
+    natives: Vec<Native>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a Rust function as a native procedure, which programs call by name like any
+    /// other procedure. Natives shadow builtins and are shadowed by the program's own items.
+    ///
+    /// Natives are `Fn`s, so ones with state should keep it in a `Cell` or `RefCell`.
+    pub fn register_fn<Args, F: NativeFn<Args>>(&mut self, name: &str, f: F) -> &mut Self {
+        self.natives.push(Native {
+            signature: NativeSignature {
+                name: name.to_owned(),
+                params: F::params(),
+                ret_ty: F::ret_ty(),
+            },
+            callback: Box::new(move |args, heap| f.call(args, heap)),
+        });
+        self
+    }
+
+    /// The signatures of the natives, to lower programs calling them with.
+    pub fn signatures(&self) -> Vec<NativeSignature> {
+        self.natives
+            .iter()
+            .map(|native| native.signature.clone())
+            .collect()
+    }
+
+    /// Returns if a program lowered with these natives calls natives with the signatures it
+    /// has, which start with the system natives'.
+    pub fn matches(&self, signatures: &[NativeSignature]) -> bool {
+        SystemNative::ALL
+            .into_iter()
+            .map(SystemNative::signature)
+            .chain(self.signatures())
+            .eq(signatures.iter().cloned())
+    }
+
+    /// Call a native with arguments of the types it expects. System natives fail in the
+    /// sandbox.
+    pub fn call(
+        &self,
+        id: NativeId,
+        args: &[Value],
+        io: &mut Io<'_>,
+        heap: &mut Heap,
+        span: Span,
+    ) -> RunResult<Value> {
+        if let Some(system) = SystemNative::from_id(id) {
+            return builtins::call_system(system, args, io, heap, span);
+        }
+        let native = &self.natives[id.0 as usize - SystemNative::ALL.len()];
+        (native.callback)(args, heap)
+            .map_err(|message| RuntimeError::Native(native.signature.name.clone(), message, span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natives_marshal_values() {
+        let mut natives = Natives::new();
+        natives
+            .register_fn("repeat", |s: String, n: i64| s.repeat(n as usize))
+            .register_fn("sqrt", |x: f64| {
+                if x < 0.0 {
+                    return Err(format!("{x} has no square root"));
+                }
+                Ok(x.sqrt())
+            })
+            .register_fn("low_byte", |x: u32| x as u8);
+
+        let signatures = natives.signatures();
+        assert_eq!(signatures[0].params, [Ty::Str, Ty::INT]);
+        assert_eq!(signatures[2].params, [Ty::Int(IntTy::U32)]);
+        assert_eq!(signatures[2].ret_ty, Ty::Int(IntTy::U8));
+        assert_eq!(signatures[0].ret_ty, Ty::Str);
+        assert_eq!(signatures[1].ret_ty, Ty::Float);
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let mut io = Io::new(&mut input, &mut output);
+        let (mut heap, span) = (Heap::new(), Span::from(0..0));
+        let id = |i: usize| NativeId((SystemNative::ALL.len() + i) as u32);
+        let args = [Value::Str("ab".into()), Value::int(3)];
+        assert_eq!(
+            natives
+                .call(id(0), &args, &mut io, &mut heap, span)
+                .unwrap(),
+            Value::Str("ababab".into())
+        );
+        assert!(matches!(
+            natives.call(id(1), &[Value::Float(-4.0)], &mut io, &mut heap, span),
+            Err(RuntimeError::Native(name, message, _))
+                if name == "sqrt" && message == "-4 has no square root"
+        ));
+        assert!(matches!(
+            natives.call(id(1), &[Value::int(4)], &mut io, &mut heap, span),
+            Err(RuntimeError::Native(_, message, _))
+                if message == "expected an argument of type `float`, found `int`"
+        ));
+        assert_eq!(
+            natives
+                .call(
+                    id(2),
+                    &[Value::Int(0x1ff, IntTy::U32)],
+                    &mut io,
+                    &mut heap,
+                    span
+                )
+                .unwrap(),
+            Value::Int(0xff, IntTy::U8)
+        );
+    }
+
+    #[test]
+    fn test_system_natives_fail_in_the_sandbox() {
+        let natives = Natives::new();
+        assert!(natives.matches(&SystemNative::ALL.map(SystemNative::signature)));
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let mut io = Io::new(&mut input, &mut output).sandboxed();
+        let (mut heap, span) = (Heap::new(), Span::from(0..0));
+        let id = NativeId(SystemNative::ClockMs as u32);
+        assert!(matches!(
+            natives.call(id, &[], &mut io, &mut heap, span),
+            Err(RuntimeError::Sandboxed("clock_ms", _))
+        ));
+    }
+}
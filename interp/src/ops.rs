@@ -0,0 +1,105 @@
+//! The semantics of operators, shared by every way of running a program so they all agree.
+//!
+//! Integer overflow follows the program's [`Overflow`] mode at the width of each int type, and
+//! division by zero and shifts by at least the width are runtime errors. Floats follow the rules
+//! of [`hir::float`]. Strings compare by their bytes, like C's `strcmp`, chars by their code
+//! points, and every other value only compares for equality, by contents. Adding strings joins
+//! them into a new string on the heap.
+
+use crate::{heap::Heap, RunResult, RuntimeError, Value};
+use hir::{float, BinOp, IntTy, Overflow, UnOp};
+use span::Span;
+
+/// Apply a unary operator to a type checked operand.
+pub fn unary(op: UnOp, operand: Value, overflow: Overflow, span: Span) -> RunResult<Value> {
+    Ok(match (op, operand) {
+        (UnOp::Neg, Value::Int(value, ty)) => {
+            let value = overflow
+                .neg(ty, value)
+                .ok_or(RuntimeError::Overflow(span))?;
+            Value::Int(value, ty)
+        }
+        (UnOp::Neg, Value::Float(value)) => Value::Float(-value),
+        (UnOp::Not, Value::Bool(value)) => Value::Bool(!value),
+        (UnOp::BitNot, Value::Int(value, ty)) => Value::Int(ty.not(value), ty),
+        (UnOp::Cast(to), Value::Int(value, ty)) => {
+            let value = overflow
+                .fit(to, ty.value(value))
+                .ok_or(RuntimeError::Overflow(span))?;
+            Value::Int(value, to)
+        }
+        (op, operand) => unreachable!("`{op:?}` is type checked, found a {}", operand.type_name()),
+    })
+}
+
+fn int_binary(
+    op: BinOp,
+    ty: IntTy,
+    lhs: i64,
+    rhs: i64,
+    overflow: Overflow,
+    span: Span,
+) -> RunResult<Value> {
+    use BinOp::*;
+
+    if matches!(op, Div | Rem) && rhs == 0 {
+        return Err(RuntimeError::DivisionByZero(span));
+    }
+
+    let value = match op {
+        Add | Sub | Mul | Div | Rem => overflow.arithmetic(op, ty, lhs, rhs),
+        BitAnd | BitOr | Shl | Shr => match ty.bitwise(op, lhs, rhs) {
+            Some(value) => Some(value),
+            None => return Err(RuntimeError::InvalidShift(ty.value(rhs), span)),
+        },
+        _ => return Ok(compare(op, &ty.value(lhs), &ty.value(rhs))),
+    };
+
+    value
+        .map(|value| Value::Int(value, ty))
+        .ok_or(RuntimeError::Overflow(span))
+}
+
+fn compare<T: PartialOrd + ?Sized>(op: BinOp, lhs: &T, rhs: &T) -> Value {
+    use BinOp::*;
+
+    Value::Bool(match op {
+        Eq => lhs == rhs,
+        Ne => lhs != rhs,
+        Lt => lhs < rhs,
+        Le => lhs <= rhs,
+        Gt => lhs > rhs,
+        Ge => lhs >= rhs,
+        _ => unreachable!("`{op:?}` isn't a comparison"),
+    })
+}
+
+/// Apply a binary operator to type checked operands.
+pub fn binary(
+    heap: &mut Heap,
+    op: BinOp,
+    lhs: Value,
+    rhs: Value,
+    overflow: Overflow,
+    span: Span,
+) -> RunResult<Value> {
+    match (lhs, rhs) {
+        (Value::Int(lhs, ty), Value::Int(rhs, _)) => int_binary(op, ty, lhs, rhs, overflow, span),
+        (Value::Float(lhs), Value::Float(rhs)) => {
+            let result = float::binary(op, lhs, rhs).expect("bitwise operators only apply to ints");
+            Ok(Value::from(&result))
+        }
+        (Value::Str(lhs), Value::Str(rhs)) if op == BinOp::Add => Ok(heap.concat(&lhs, &rhs)),
+        (Value::Str(lhs), Value::Str(rhs)) => Ok(compare(op, lhs.as_bytes(), rhs.as_bytes())),
+        (Value::Char(lhs), Value::Char(rhs)) => Ok(compare(op, &lhs, &rhs)),
+        (lhs, rhs) => match op {
+            BinOp::Eq => Ok(Value::Bool(lhs == rhs)),
+            BinOp::Ne => Ok(Value::Bool(lhs != rhs)),
+            _ => unreachable!(
+                "`{op:?}` is type checked, found a {} and a {}",
+                lhs.type_name(),
+                rhs.type_name()
+            ),
+        },
+    }
+}
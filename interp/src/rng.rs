@@ -0,0 +1,75 @@
+//! The generator behind the `rand` module, SplitMix64. It's small and fast and passes the usual
+//! statistical tests, and native executables implement the same one, so a seed gives the same
+//! numbers however a program runs. It isn't meant for cryptography.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// A seedable pseudo-random number generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator giving the numbers of a seed, the same every time.
+    pub fn seeded(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A generator seeded at random, differently for every one.
+    pub fn random() -> Self {
+        Self::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// An int from `lo` up to and including `hi`, or `None` if `hi` is less than `lo`.
+    pub fn int_in(&mut self, lo: i64, hi: i64) -> Option<i64> {
+        if hi < lo {
+            return None;
+        }
+
+        // Scaling by the size of the range, which can be up to 2^64, keeps the high bits of the
+        // product. Its bias is too small to notice for any range a program can ask for.
+        let size = (i128::from(hi) - i128::from(lo) + 1) as u128;
+        let offset = (u128::from(self.next_u64()) * size) >> 64;
+        Some(lo.wrapping_add(offset as i64))
+    }
+
+    /// A float from 0 up to but not including 1, evenly spaced by 2^-53.
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeds_repeat_their_numbers() {
+        let mut rng = Rng::seeded(1234567);
+        // The first output of SplitMix64 for this seed, from its reference implementation.
+        assert_eq!(rng.next_u64(), 6457827717110365317);
+        assert_eq!(Rng::seeded(7).next_u64(), Rng::seeded(7).next_u64());
+
+        for _ in 0..1000 {
+            let n = rng.int_in(-3, 3).unwrap();
+            assert!((-3..=3).contains(&n));
+            let x = rng.float();
+            assert!((0.0..1.0).contains(&x));
+        }
+        assert_eq!(rng.int_in(5, 5), Some(5));
+        assert_eq!(rng.int_in(5, 4), None);
+        assert!(rng.int_in(i64::MIN, i64::MAX).is_some());
+    }
+}
@@ -0,0 +1,204 @@
+use hir::{float, IntTy, Literal, ProcId};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+/// A value produced while running a program.
+///
+/// Strings, arrays and maps are handles: cloning a value shares the string, array or map rather
+/// than copying it, so values stay cheap to move between locals and the stack.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// An int of a type, stored as [`IntTy::wrap`] stores it.
+    Int(i64, IntTy),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+
+    /// An immutable string.
+    Str(Rc<str>),
+
+    /// An array, shared with every handle to it, so writes through one are seen by all.
+    Array(Rc<RefCell<Vec<Self>>>),
+
+    /// A map, shared like an array.
+    Map(Rc<RefCell<HashMap<Key, Self>>>),
+
+    /// A reference to a procedure.
+    Proc(ProcId),
+
+    /// The result of a `void` procedure or an assignment.
+    Void,
+}
+
+impl Value {
+    /// An `int`.
+    pub fn int(value: i64) -> Self {
+        Self::Int(value, IntTy::I64)
+    }
+
+    /// The name of the value's type, for messages about values of the wrong type.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Int(_, ty) => ty.name(),
+            Self::Float(_) => "float",
+            Self::Bool(_) => "bool",
+            Self::Char(_) => "char",
+            Self::Str(_) => "str",
+            Self::Array(_) => "array",
+            Self::Map(_) => "map",
+            Self::Proc(_) => "proc",
+            Self::Void => "void",
+        }
+    }
+
+    /// Whether a condition holds. Only bools are conditions, which the type checker ensures.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Self::Bool(value) => value,
+            _ => unreachable!(
+                "conditions are type checked to be bools, found a {}",
+                self.type_name()
+            ),
+        }
+    }
+
+    /// The value as an integer, if it's a bool, int or char, for comparing against range
+    /// patterns.
+    pub fn as_integer(&self) -> Option<i128> {
+        match *self {
+            Self::Int(value, ty) => Some(ty.value(value)),
+            Self::Bool(value) => Some(value.into()),
+            Self::Char(value) => Some(u32::from(value).into()),
+            Self::Float(_)
+            | Self::Str(_)
+            | Self::Array(_)
+            | Self::Map(_)
+            | Self::Proc(_)
+            | Self::Void => None,
+        }
+    }
+
+    /// Whether the value is within the range from `start` to `end`, which only holds for
+    /// values with an integer value.
+    pub fn in_range(&self, start: &Self, end: &Self, inclusive: bool) -> bool {
+        let (Some(start), Some(end), Some(value)) =
+            (start.as_integer(), end.as_integer(), self.as_integer())
+        else {
+            return false;
+        };
+
+        start <= value && (value < end || (inclusive && value == end))
+    }
+}
+
+/// The key of an entry in a map, which is an int, a bool, a char or a string. Keys order the way
+/// `keys` lists them, with ints by their values, since the keys of a map all have one type.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Key {
+    Int(i128, IntTy),
+    Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+}
+
+impl Key {
+    /// The key a value is stored as. Only the types [`hir::Ty::is_key`] accepts are keys,
+    /// which the type checker ensures.
+    pub fn new(value: &Value) -> Self {
+        match value {
+            Value::Int(value, ty) => Self::Int(ty.value(*value), *ty),
+            Value::Bool(value) => Self::Bool(*value),
+            Value::Char(value) => Self::Char(*value),
+            Value::Str(value) => Self::Str(Rc::clone(value)),
+            _ => unreachable!("map keys are type checked, found a {}", value.type_name()),
+        }
+    }
+
+    /// The value the key was made from.
+    pub fn value(&self) -> Value {
+        match self {
+            Self::Int(value, ty) => Value::Int(ty.wrap(*value), *ty),
+            Self::Bool(value) => Value::Bool(*value),
+            Self::Char(value) => Value::Char(*value),
+            Self::Str(value) => Value::Str(Rc::clone(value)),
+        }
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Int(value, ty) => Self::Int(*value, *ty),
+            Literal::Float(value) => Self::Float(*value),
+            Literal::Bool(value) => Self::Bool(*value),
+            Literal::Char(value) => Self::Char(*value),
+            Literal::Str(value) => Self::Str(value.as_str().into()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value, ty) => write!(f, "{}", ty.value(*value)),
+            Self::Float(value) => write!(f, "{}", float::format(*value)),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Char(value) => write!(f, "{value}"),
+            Self::Str(value) => write!(f, "{value}"),
+            Self::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Map(entries) => {
+                let entries = entries.borrow();
+                let mut keys: Vec<_> = entries.keys().collect();
+                keys.sort();
+
+                write!(f, "[")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key.value(), entries[key])?;
+                }
+                write!(f, "]")
+            }
+            Self::Proc(id) => write!(f, "<proc {}>", id.0),
+            Self::Void => write!(f, "void"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_handles_and_display() {
+        let array = Value::Array(Rc::new(RefCell::new(vec![
+            Value::int(1),
+            Value::Str("two".into()),
+        ])));
+        let shared = array.clone();
+        if let Value::Array(elements) = &array {
+            elements.borrow_mut().push(Value::Char('3'));
+        }
+
+        assert_eq!(shared.to_string(), "[1, two, 3]");
+        assert_eq!(array, shared);
+        assert_ne!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_eq!(Value::Proc(ProcId(2)).type_name(), "proc");
+        assert_eq!(Value::Int(-1, IntTy::U64).to_string(), u64::MAX.to_string());
+        assert_eq!(Value::Int(-1, IntTy::I8).type_name(), "i8");
+
+        let (start, end) = (Value::Char('a'), Value::Char('z'));
+        assert!(Value::Char('z').in_range(&start, &end, true));
+        assert!(!Value::Char('z').in_range(&start, &end, false));
+        assert!(!Value::Str("a".into()).in_range(&start, &end, true));
+    }
+}
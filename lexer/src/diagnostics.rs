@@ -1,4 +1,4 @@
-use ariadne::ReportKind;
+use diagnostics::Stage;
 use miette::Diagnostic;
 use span::Span;
 use thiserror::Error;
@@ -37,26 +37,18 @@ pub enum LexDiagnostic {
     )]
     #[error("Unterminated string literal. Expected closing quote")]
     UnterminatedStringLiteral(#[label("unterminated string literal here")] Span),
-}
 
-#[derive(Debug, Default, Error, Diagnostic)]
-#[diagnostic(code(lexer::failure))]
-#[error("lexing failed with {} diagnostic{}", diagnostics.len(), if diagnostics.len() != 1 { "s" } else { "" })]
-pub struct DiagnosticSink {
-    #[related]
-    diagnostics: Vec<LexDiagnostic>,
+    #[diagnostic(
+        code(lexer::unterminated_block_comment),
+        help("add a `*/` where the comment ends")
+    )]
+    #[error("Unterminated block comment. Expected `*/`")]
+    UnterminatedBlockComment(#[label("this comment is never closed")] Span),
 }
 
-impl DiagnosticSink {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn push_diagnostic(&mut self, diagnostic: LexDiagnostic) {
-        self.diagnostics.push(diagnostic);
-    }
-
-    pub fn has_diagnostics(&self) -> bool {
-        !self.diagnostics.is_empty()
-    }
+impl Stage for LexDiagnostic {
+    const NAME: &'static str = "lexing";
+    const FAILURE: &'static str = "lexer::failure";
 }
+
+pub type DiagnosticSink = diagnostics::DiagnosticSink<LexDiagnostic>;
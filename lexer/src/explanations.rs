@@ -0,0 +1,110 @@
+//! Extended explanations of the lexer's diagnostics, which `mtxc explain` prints.
+
+/// Every diagnostic's code and explanation. Short codes number them in this order, so new ones
+/// go at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "lexer::unexpected_character",
+        r#"A character in the source doesn't start any token.
+
+Outside of string and character literals and comments, a program can only use letters, digits,
+whitespace and the punctuation its operators are made of. For example, `$` isn't part of any
+token:
+
+    proc main() void {
+        let $price = 10;
+    }
+
+Rename the variable to use only letters, digits and underscores, like `price`."#,
+    ),
+    (
+        "lexer::empty_character_literal",
+        r#"A character literal has nothing between its quotes.
+
+A `char` is exactly one codepoint, so there's no empty one:
+
+    let c = '';
+
+Write the character between the quotes, like `' '` for a space. Use a string if the text can
+be empty, like `""`."#,
+    ),
+    (
+        "lexer::unterminated_string_literal",
+        r#"A string literal has no closing double quote.
+
+The string goes on to the end of the file, so everything after its opening quote is part of it:
+
+    proc main() void {
+        println("hello);
+    }
+
+Add the closing quote where the string ends, like `println("hello");`. A double quote inside a
+string is written `\"`."#,
+    ),
+    (
+        "lexer::unterminated_character_literal",
+        r#"A character literal has no closing single quote.
+
+    let c = 'a;
+
+Add the closing quote right after the character, like `'a'`. A single quote as a character is
+written `'\''`."#,
+    ),
+    (
+        "lexer::character_lit_one_codepoint",
+        r#"A character literal holds more than one codepoint.
+
+A `char` is a single Unicode codepoint, so the quotes can only hold one:
+
+    let greeting = 'hi';
+
+Use double quotes for text of any length, like `"hi"`. Some characters that look like one
+symbol, such as many emoji, are made of several codepoints, and need a string too."#,
+    ),
+    (
+        "lexer::failure",
+        r#"The program couldn't be split into tokens.
+
+This error holds every diagnostic the lexer found, which are shown after it. Each has its own
+code to explain it. Nothing is parsed or checked until they're fixed."#,
+    ),
+    (
+        "lexer::unterminated_block_comment",
+        r#"A block comment has no closing `*/`.
+
+The comment goes on to the end of the file, so everything after its opening `/*` is commented
+out:
+
+    /* Adds two numbers.
+    proc add(a: int, b: int) int {
+        ret a + b;
+    }
+
+Add a `*/` where the comment ends. Block comments nest, so a `/*` inside one needs a `*/` of its
+own before the one that closes the outer comment:
+
+    /* Old version: /* adds two numbers */ */"#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LexDiagnostic;
+    use diagnostics::Stage;
+
+    #[test]
+    fn test_every_diagnostic_is_explained() {
+        let mut codes: Vec<_> = include_str!("diagnostics.rs")
+            .split("code(")
+            .skip(1)
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .filter(|code| !code.is_empty())
+            .chain([<LexDiagnostic as Stage>::FAILURE])
+            .collect();
+        let mut explained: Vec<_> = EXPLANATIONS.iter().map(|(code, _)| *code).collect();
+        codes.sort_unstable();
+        explained.sort_unstable();
+        assert_eq!(codes, explained);
+    }
+}
@@ -3,13 +3,15 @@
 #![allow(clippy::missing_const_for_fn, unused)]
 
 mod diagnostics;
+mod explanations;
 pub mod token;
 
-use diagnostics::{
-    DiagnosticSink,
-    LexDiagnostic::{self, *},
-};
-use span::Span;
+pub use diagnostics::{DiagnosticSink, LexDiagnostic};
+pub use explanations::EXPLANATIONS;
+
+use diagnostics::LexDiagnostic::*;
+use intern::{kw, Symbol};
+use span::{Cancel, Cancelled, Span};
 use std::{collections::HashMap, iter::Peekable, str::Chars, sync::LazyLock};
 use token::{
     IdentKind::*,
@@ -20,49 +22,82 @@ use token::{
 };
 use unicode_xid::UnicodeXID;
 
-static KEYWORDS: LazyLock<HashMap<&str, TokenKind>> = LazyLock::new(|| {
+static KEYWORDS: LazyLock<HashMap<Symbol, TokenKind>> = LazyLock::new(|| {
     use crate::token::{Keyword::*, LiteralKind::Boolean};
 
     HashMap::from([
-        ("proc", Ident(Keyword(Proc))),
-        ("let", Ident(Keyword(Let))),
-        ("void", Ident(Keyword(Void))),
-        ("int", Ident(Keyword(Int))),
-        ("ret", Ident(Keyword(Ret))),
-        ("float", Ident(Keyword(Float))),
-        ("if", Ident(Keyword(If))),
-        ("elif", Ident(Keyword(Elif))),
-        ("else", Ident(Keyword(Else))),
-        ("for", Ident(Keyword(For))),
-        ("while", Ident(Keyword(While))),
-        ("do", Ident(Keyword(Do))),
-        ("bool", Ident(Keyword(Bool))),
-        ("str", Ident(Keyword(Str))),
-        ("true", Literal(Boolean)),
-        ("false", Literal(Boolean)),
+        (kw::PROC, Ident(Keyword(Proc))),
+        (kw::LET, Ident(Keyword(Let))),
+        (kw::VOID, Ident(Keyword(Void))),
+        (kw::INT, Ident(Keyword(Int))),
+        (kw::RET, Ident(Keyword(Ret))),
+        (kw::FLOAT, Ident(Keyword(Float))),
+        (kw::IF, Ident(Keyword(If))),
+        (kw::ELIF, Ident(Keyword(Elif))),
+        (kw::ELSE, Ident(Keyword(Else))),
+        (kw::FOR, Ident(Keyword(For))),
+        (kw::WHILE, Ident(Keyword(While))),
+        (kw::DO, Ident(Keyword(Do))),
+        (kw::BOOL, Ident(Keyword(Bool))),
+        (kw::STR, Ident(Keyword(Str))),
+        (kw::MATCH, Ident(Keyword(Match))),
+        (kw::PUB, Ident(Keyword(Pub))),
+        (kw::MOD, Ident(Keyword(Mod))),
+        (kw::CONST, Ident(Keyword(Const))),
+        (kw::IMPORT, Ident(Keyword(Import))),
+        (kw::IN, Ident(Keyword(In))),
+        (kw::CHAR, Ident(Keyword(Char))),
+        (kw::I8, Ident(Keyword(I8))),
+        (kw::I16, Ident(Keyword(I16))),
+        (kw::I32, Ident(Keyword(I32))),
+        (kw::I64, Ident(Keyword(I64))),
+        (kw::U8, Ident(Keyword(U8))),
+        (kw::U16, Ident(Keyword(U16))),
+        (kw::U32, Ident(Keyword(U32))),
+        (kw::U64, Ident(Keyword(U64))),
+        (kw::AS, Ident(Keyword(As))),
+        (kw::TRUE, Literal(Boolean)),
+        (kw::FALSE, Literal(Boolean)),
     ])
 });
 
 #[derive(Debug)]
 struct Lexer<'src> {
+    /// The source code.
+    text: &'src str,
+
     /// An iterator over the characters of the source code.
     source: Peekable<Chars<'src>>,
 
-    /// Indicates where the lexer currently is in the source code.
-    cursor: (usize, usize),
+    /// The byte offset the lexer is currently at in the source code.
+    cursor: usize,
+
+    /// The byte offset at which the token currently being lexed starts.
+    token_start: usize,
+
+    /// Whether ordinary comments become tokens rather than being skipped.
+    keep_comments: bool,
 }
 
 impl<'src> Lexer<'src> {
-    fn new(source: &'src str) -> Self {
+    fn new(source: &'src str, keep_comments: bool) -> Self {
         Self {
+            text: source,
             source: source.chars().peekable(),
-            cursor: (1, 1),
+            cursor: 0,
+            token_start: 0,
+            keep_comments,
         }
     }
 
-    /// Create a new token.
-    fn create_token(&self, token_kind: TokenKind, token_len: usize) -> Token {
-        Token::new(token_kind, Span::new(token_len, self.cursor.1))
+    /// The span of the token currently being lexed.
+    fn token_span(&self) -> Span {
+        Span::from(self.token_start..self.cursor)
+    }
+
+    /// Create a new token spanning from the start of the current token to the cursor.
+    fn create_token(&self, token_kind: TokenKind) -> Token {
+        Token::new(token_kind, self.token_span(), &self.text[self.token_start..self.cursor])
     }
 
     /// Peek the next character in the source.
@@ -72,15 +107,9 @@ impl<'src> Lexer<'src> {
 
     /// Advance to the next character in the source.
     fn advance(&mut self) -> Option<char> {
-        self.cursor.1 += 1;
-        self.source.next()
-    }
-
-    /// Advance and invoke a callback.
-    fn advance_with_callback(&mut self, mut cb: impl FnMut()) -> Option<char> {
-        let next = self.advance();
-        cb();
-        next
+        let next = self.source.next()?;
+        self.cursor += next.len_utf8();
+        Some(next)
     }
 
     /// Check if the next character is a specified character, returning whether it was consumed or not.
@@ -95,6 +124,11 @@ impl<'src> Lexer<'src> {
         false
     }
 
+    /// Peek the character after the next one in the source.
+    fn peek_second(&self) -> Option<char> {
+        self.source.clone().nth(1)
+    }
+
     /// Check if the end of the source has been reached.
     fn at_end(&mut self) -> bool {
         self.peek().is_none()
@@ -107,102 +141,126 @@ impl<'src> Lexer<'src> {
         if_next: TokenKind,
         fallback: TokenKind,
     ) -> Token {
-        self.next_is(check_next)
-            .then(|| self.create_token(if_next, 2))
-            .unwrap_or_else(|| self.create_token(fallback, 1))
+        if self.next_is(check_next) {
+            self.create_token(if_next)
+        } else {
+            self.create_token(fallback)
+        }
     }
 
     /// Lex an identifier.
-    fn lex_ident(&mut self, first_char: char) -> Token {
-        let mut ident = std::string::String::from(first_char);
-
+    fn lex_ident(&mut self) -> Token {
         while !self.at_end() && UnicodeXID::is_xid_continue(*self.peek().unwrap()) {
-            ident.push(self.advance().unwrap());
+            self.advance();
         }
 
-        let token_kind = KEYWORDS
-            .get_key_value(ident.as_str())
-            .map(|(_, tk)| *tk)
-            .unwrap_or(Ident(NonReserved));
-        self.create_token(token_kind, ident.len())
+        // Interning the name here means later stages find it already interned.
+        let ident = Symbol::intern(&self.text[self.token_start..self.cursor]);
+        let token_kind = KEYWORDS.get(&ident).copied().unwrap_or(Ident(NonReserved));
+        Token {
+            kind: token_kind,
+            span: self.token_span(),
+            symbol: Some(ident),
+        }
     }
 
-    /// Lex a character literal.
-    // TODO: handle when the literal contains an escaped single quote. currently,
-    // it will panic as it believes the escaped quote is the closing quote.
-    fn lex_char_literal(&mut self) -> Result<Token, LexDiagnostic> {
-        let mut len = 1;
+    /// Lex a comment, after its leading `//`. Doc comments (`/// ...`, but not `//// ...`)
+    /// become tokens, while ordinary comments are skipped like whitespace unless they're kept,
+    /// giving no token.
+    fn lex_comment(&mut self) -> Option<Token> {
+        let is_doc = self.peek() == Some(&'/') && self.peek_second() != Some('/');
+
+        while !self.at_end() && *self.peek().unwrap() != '\n' {
+            self.advance();
+        }
 
-        if self.at_end() {
-            return Err(UnterminatedCharacterLiteral(Span::new(len, self.cursor.1)));
+        if is_doc {
+            Some(self.create_token(DocComment))
+        } else if self.keep_comments {
+            Some(self.create_token(Comment))
+        } else {
+            None
         }
+    }
 
-        while !self.at_end() && self.peek().unwrap() != &'\'' {
-            self.advance_with_callback(|| len += 1);
+    /// Lex a block comment, after its leading `/*`. Block comments nest, so each `/*` inside one
+    /// needs a `*/` of its own. Like ordinary comments, they give no token unless they're kept.
+    fn lex_block_comment(&mut self) -> Result<Option<Token>, LexDiagnostic> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some('/') if self.next_is('*') => depth += 1,
+                Some('*') if self.next_is('/') => depth -= 1,
+                Some(_) => {}
+                None => {
+                    let opening = Span::from(self.token_start..self.token_start + 2);
+                    return Err(UnterminatedBlockComment(opening));
+                }
+            }
         }
 
-        // if self.next_is('\'') {
-        //     consumed.push('\'');
-        // }
+        Ok(self.keep_comments.then(|| self.create_token(Comment)))
+    }
+
+    /// Lex a character literal.
+    fn lex_char_literal(&mut self) -> Result<Token, LexDiagnostic> {
+        let mut codepoints = 0;
 
-        if len == 2 {
-            return Err(EmptyCharacterLiteral(Span::new(len + 1, self.cursor.1 - 1)));
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(UnterminatedCharacterLiteral(self.token_span())),
+                Some('\'') => {
+                    self.advance();
+                    break;
+                }
+                Some('\\') => {
+                    // An escape sequence only counts as a single codepoint.
+                    self.advance();
+                    self.advance();
+                    codepoints += 1;
+                }
+                Some(_) => {
+                    self.advance();
+                    codepoints += 1;
+                }
+            }
+        }
+
+        match codepoints {
+            0 => Err(EmptyCharacterLiteral(self.token_span())),
+            1 => Ok(self.create_token(Literal(Character))),
+            _ => Err(CharacterLiteralOneCodePoint(self.token_span())),
         }
-        // if !self.next_is('\'') {
-        //     return Err(UnterminatedCharacterLiteral(Span::new(
-        //         consumed.len(),
-        //         self.cursor.1,
-        //     )));
-        // }
-
-        // if len > 2 {
-        //     return Err(CharacterLiteralOneCodePoint(Span::new(
-        //         len,
-        //         self.cursor.1 - 1,
-        //     )));
-        // }
-
-        // if len > 2 {
-        //     return Err(CharacterLiteralOneCodePoint(Span::new(
-        //         len,
-        //         self.cursor.1 - 1,
-        //     )));
-        // }
-        Ok(self.create_token(Literal(Character), len))
     }
 
     /// Lex a string literal.
-    // TODO: handle when the literal contains an escaped double quote. currently,
-    // it will panic as it believes the escaped quote is the closing quote.
     fn lex_string_literal(&mut self) -> Result<Token, LexDiagnostic> {
-        let mut len = 1; // The opening quote has already been consumed.
-
-        while !self.at_end() && self.peek().unwrap() != &'"' {
-            self.advance_with_callback(|| len += 1);
+        while let Some(&c) = self.peek()
+            && c != '"'
+        {
+            // Skip over the escaped character so an escaped quote doesn't terminate the literal.
+            if self.advance() == Some('\\') {
+                self.advance();
+            }
         }
 
         if !self.next_is('"') {
-            let span = Span::new(len, self.cursor.1);
-
-            return Err(UnterminatedStringLiteral(span));
+            return Err(UnterminatedStringLiteral(self.token_span()));
         }
 
-        len += 1;
-        Ok(self.create_token(Literal(String), len))
+        Ok(self.create_token(Literal(String)))
     }
 
     // Lex a numerical literal.
     // TODO: handle when a literal with a base is empty doesn't have any digits or when it has invalid digits for that base.
     fn lex_numerical_literal(&mut self, first_digit: char) -> Token {
-        let mut len = 1; // The first digit has already been consumed.
-
         // The literal is an integer with a base specified.
         if first_digit == '0'
             && self
                 .peek()
                 .is_some_and(|&c| c == 'b' || c == 'o' || c == 'x')
         {
-            let base = match self.advance_with_callback(|| len += 1).unwrap() {
+            let base = match self.advance().unwrap() {
                 'b' => Binary,
                 'o' => Octal,
                 'x' => Hexadecimal,
@@ -216,7 +274,7 @@ impl<'src> Lexer<'src> {
                             || self.peek().unwrap() == &'1'
                             || self.peek().unwrap() == &'_')
                     {
-                        self.advance_with_callback(|| len += 1);
+                        self.advance();
                     }
                 }
                 Octal => {
@@ -224,7 +282,7 @@ impl<'src> Lexer<'src> {
                         && (self.peek().unwrap().is_ascii_octdigit()
                             || self.peek().unwrap() == &'_')
                     {
-                        self.advance_with_callback(|| len += 1);
+                        self.advance();
                     }
                 }
                 Hexadecimal => {
@@ -232,84 +290,155 @@ impl<'src> Lexer<'src> {
                         && (self.peek().unwrap().is_ascii_hexdigit()
                             || self.peek().unwrap() == &'_')
                     {
-                        self.advance_with_callback(|| len += 1);
+                        self.advance();
                     }
                 }
                 _ => unreachable!(),
             }
 
-            self.create_token(Literal(Integer { base }), len)
+            self.create_token(Literal(Integer { base }))
         } else {
-            while !self.at_end() && self.peek().unwrap().is_numeric() {
-                self.advance_with_callback(|| len += 1);
+            while !self.at_end() && self.peek().unwrap().is_ascii_digit() {
+                self.advance();
+            }
+
+            // We have a float. A dot that isn't followed by a digit belongs to a range instead (1..5).
+            let has_fraction =
+                self.peek() == Some(&'.') && self.peek_second().is_some_and(|c| c.is_ascii_digit());
+            if has_fraction {
+                self.advance(); // Consume the dot.
+
+                while !self.at_end() && self.peek().unwrap().is_ascii_digit() {
+                    self.advance();
+                }
             }
 
-            // We have a float.
-            if let Some(&next) = self.peek()
-                && next == '.'
-            {
-                self.advance_with_callback(|| len += 1); // Consume the dot.
+            // An exponent also makes a float (1e10, 2.5E-3), if it has digits.
+            let mut exponent = self.source.clone().peekable();
+            let has_exponent = exponent.next_if(|&c| c == 'e' || c == 'E').is_some() && {
+                exponent.next_if(|&c| c == '+' || c == '-');
+                exponent.peek().is_some_and(char::is_ascii_digit)
+            };
+            if has_exponent {
+                self.advance(); // Consume the `e`.
+                if !self.next_is('+') {
+                    self.next_is('-');
+                }
 
-                while !self.at_end() && self.peek().unwrap().is_numeric() {
-                    self.advance_with_callback(|| len += 1);
+                while !self.at_end() && self.peek().unwrap().is_ascii_digit() {
+                    self.advance();
                 }
+            }
 
-                return self.create_token(Literal(Float), len);
+            if has_fraction || has_exponent {
+                return self.create_token(Literal(Float));
             }
 
-            self.create_token(Literal(Integer { base: Decimal }), len)
+            self.create_token(Literal(Integer { base: Decimal }))
         }
     }
 
     /// Lex a token.
-    /// TODO: lexing for <<, <<=, >>, >>=, &=, &&, |=, ||
     fn lex_token(&mut self) -> Result<Token, LexDiagnostic> {
-        let Some(ch) = self.advance() else {
-            return Ok(self.create_token(EoF, 0));
-        };
+        // Whitespace and skipped comments are passed over in a loop rather than by lexing the
+        // token after them recursively, which a long run of them would overflow the stack with.
+        loop {
+            self.token_start = self.cursor;
+
+            let Some(ch) = self.advance() else {
+                return Ok(self.create_token(EoF));
+            };
 
-        match ch {
-            '(' => Ok(self.create_token(OpenParen, 1)),
-            ')' => Ok(self.create_token(ClosingParen, 1)),
-            '{' => Ok(self.create_token(OpenCurly, 1)),
-            '}' => Ok(self.create_token(ClosingCurly, 1)),
-            '[' => Ok(self.create_token(OpenSquare, 1)),
-            ']' => Ok(self.create_token(ClosingSquare, 1)),
-            ':' => Ok(self.create_token(Colon, 1)),
-            ';' => Ok(self.create_token(Semicolon, 1)),
-            '.' => Ok(self.create_token(Period, 1)),
-            ',' => Ok(self.create_token(Comma, 1)),
-            '=' => Ok(self.lex_potentially_longer_operator('=', EqualEqual, Equal)),
-            '+' => Ok(self.lex_potentially_longer_operator('=', PlusEqual, Plus)),
-            '-' => Ok(self.lex_potentially_longer_operator('=', MinusEqual, Minus)),
-            '*' => Ok(self.lex_potentially_longer_operator('=', StarEqual, Star)),
-            '/' => Ok(self.lex_potentially_longer_operator('=', SlashEqual, Slash)),
-            '%' => Ok(self.lex_potentially_longer_operator('=', PercentEqual, Percent)),
-            '&' => Ok(self.create_token(Ampersand, 1)),
-            '|' => Ok(self.create_token(Bar, 1)),
-            '~' => Ok(self.create_token(Tilde, 1)),
-            '!' => Ok(self.lex_potentially_longer_operator('=', BangEqual, Bang)),
-            '<' => Ok(self.create_token(Lt, 1)),
-            '>' => Ok(self.create_token(Gt, 1)),
-            '"' => self.lex_string_literal(),
-            '\'' => self.lex_char_literal(),
-            ch if UnicodeXID::is_xid_start(ch) || ch == '_' => Ok(self.lex_ident(ch)),
-            ch if ch.is_numeric() => Ok(self.lex_numerical_literal(ch)),
-            ch if ch.is_whitespace() => self.lex_token(),
-            _ => Err(LexDiagnostic::UnexpectedCharacter(
-                ch,
-                Span::new(1, self.cursor.1 - 1),
-            )),
+            return match ch {
+                '(' => Ok(self.create_token(OpenParen)),
+                ')' => Ok(self.create_token(ClosingParen)),
+                '{' => Ok(self.create_token(OpenCurly)),
+                '}' => Ok(self.create_token(ClosingCurly)),
+                '[' => Ok(self.create_token(OpenSquare)),
+                ']' => Ok(self.create_token(ClosingSquare)),
+                ':' => Ok(self.lex_potentially_longer_operator(':', ColonColon, Colon)),
+                ';' => Ok(self.create_token(Semicolon)),
+                '.' if self.next_is('.') => {
+                    Ok(self.lex_potentially_longer_operator('=', PeriodPeriodEqual, PeriodPeriod))
+                }
+                '.' => Ok(self.create_token(Period)),
+                ',' => Ok(self.create_token(Comma)),
+                '@' => Ok(self.create_token(At)),
+                '=' if self.next_is('>') => Ok(self.create_token(FatArrow)),
+                '=' => Ok(self.lex_potentially_longer_operator('=', EqualEqual, Equal)),
+                '+' => Ok(self.lex_potentially_longer_operator('=', PlusEqual, Plus)),
+                '-' => Ok(self.lex_potentially_longer_operator('=', MinusEqual, Minus)),
+                '*' => Ok(self.lex_potentially_longer_operator('=', StarEqual, Star)),
+                '/' if self.next_is('/') => match self.lex_comment() {
+                    Some(token) => Ok(token),
+                    None => continue,
+                },
+                '/' if self.next_is('*') => match self.lex_block_comment()? {
+                    Some(token) => Ok(token),
+                    None => continue,
+                },
+                '/' => Ok(self.lex_potentially_longer_operator('=', SlashEqual, Slash)),
+                '%' => Ok(self.lex_potentially_longer_operator('=', PercentEqual, Percent)),
+                '&' if self.next_is('&') => Ok(self.create_token(AmpAmp)),
+                '&' => Ok(self.lex_potentially_longer_operator('=', AmpersandEqual, Ampersand)),
+                '|' if self.next_is('|') => Ok(self.create_token(BarBar)),
+                '|' => Ok(self.lex_potentially_longer_operator('=', BarEqual, Bar)),
+                '~' => Ok(self.create_token(Tilde)),
+                '!' => Ok(self.lex_potentially_longer_operator('=', BangEqual, Bang)),
+                '<' if self.next_is('<') => {
+                    Ok(self.lex_potentially_longer_operator('=', ShlEqual, Shl))
+                }
+                '<' => Ok(self.lex_potentially_longer_operator('=', LtEqual, Lt)),
+                '>' if self.next_is('>') => {
+                    Ok(self.lex_potentially_longer_operator('=', ShrEqual, Shr))
+                }
+                '>' => Ok(self.lex_potentially_longer_operator('=', GtEqual, Gt)),
+                '"' => self.lex_string_literal(),
+                '\'' => self.lex_char_literal(),
+                ch if UnicodeXID::is_xid_start(ch) || ch == '_' => Ok(self.lex_ident()),
+                ch if ch.is_ascii_digit() => Ok(self.lex_numerical_literal(ch)),
+                ch if ch.is_whitespace() => continue,
+                _ => Err(LexDiagnostic::UnexpectedCharacter(ch, self.token_span())),
+            };
         }
     }
 }
 
 pub fn lex(code: &str) -> Result<Vec<Token>, DiagnosticSink> {
-    let mut lexer = Lexer::new(code);
+    lex_tokens(Lexer::new(code, false), &Cancel::new())
+        .unwrap_or_else(|Cancelled| unreachable!("nothing else holds the token"))
+}
+
+/// Lex source code like [`lex`], stopping between tokens if the token is cancelled.
+pub fn lex_cancellable(
+    code: &str,
+    cancel: &Cancel,
+) -> Result<Result<Vec<Token>, DiagnosticSink>, Cancelled> {
+    lex_tokens(Lexer::new(code, false), cancel)
+}
+
+/// Lex source code, keeping ordinary comments as [`Comment`] tokens, for tools like the
+/// formatter that reproduce the source. The parser doesn't expect them, so they have to be
+/// taken out before parsing.
+pub fn lex_with_comments(code: &str) -> Result<Vec<Token>, DiagnosticSink> {
+    lex_tokens(Lexer::new(code, true), &Cancel::new())
+        .unwrap_or_else(|Cancelled| unreachable!("nothing else holds the token"))
+}
+
+/// Every reserved word, including the literals `true` and `false`, in no particular order.
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    KEYWORDS.keys().map(|keyword| keyword.as_str())
+}
+
+fn lex_tokens(
+    mut lexer: Lexer<'_>,
+    cancel: &Cancel,
+) -> Result<Result<Vec<Token>, DiagnosticSink>, Cancelled> {
     let mut tokens = Vec::<Token>::new();
     let mut diagnostics = DiagnosticSink::new();
 
     loop {
+        cancel.check()?;
         match lexer.lex_token() {
             Ok(token) => {
                 tokens.push(token);
@@ -323,20 +452,37 @@ pub fn lex(code: &str) -> Result<Vec<Token>, DiagnosticSink> {
     }
 
     if diagnostics.has_diagnostics() {
-        return Err(diagnostics);
+        return Ok(Err(diagnostics));
     }
 
-    Ok(tokens)
+    Ok(Ok(tokens))
+}
+
+/// How many more delimiters some tokens open than they close, counting parentheses, braces and
+/// square brackets alike. It's negative if they close more than they open.
+pub fn delimiter_depth(tokens: &[Token]) -> isize {
+    tokens.iter().fold(0, |depth, token| {
+        if token.kind.is_open_delimiter() {
+            depth + 1
+        } else if token.kind.is_closing_delimiter() {
+            depth - 1
+        } else {
+            depth
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use crate::token::{IdentKind::*, IntegerBase::*, Token, TokenKind::*};
+    use miette::Diagnostic;
     use pretty_assertions::assert_eq as pretty_assert_eq;
+    use proptest::{prelude::*, test_runner::TestCaseError};
+    use span::Span;
 
     #[test]
     fn test_lex_delimiters() -> anyhow::Result<()> {
-        let source = "(){}[]:;.,";
+        let source = "(){}[]:;.,@::";
         let tokens = super::lex(source)?;
 
         pretty_assert_eq!(
@@ -344,47 +490,68 @@ mod tests {
             [
                 Token {
                     kind: OpenParen,
-                    span: (1..2).into(),
+                    span: (0..1).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: ClosingParen,
-                    span: (2..3).into(),
+                    span: (1..2).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: OpenCurly,
-                    span: (3..4).into(),
+                    span: (2..3).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: ClosingCurly,
-                    span: (4..5).into(),
+                    span: (3..4).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: OpenSquare,
-                    span: (5..6).into()
+                    span: (4..5).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: ClosingSquare,
-                    span: (6..7).into(),
+                    span: (5..6).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Colon,
-                    span: (7..8).into()
+                    span: (6..7).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Semicolon,
-                    span: (8..9).into()
+                    span: (7..8).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Period,
-                    span: (9..10).into()
+                    span: (8..9).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Comma,
-                    span: (10..11).into()
+                    span: (9..10).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: At,
+                    span: (10..11).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: ColonColon,
+                    span: (11..13).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: EoF,
-                    span: (12..12).into(),
+                    span: (13..13).into(),
+                    symbol: None,
                 }
             ]
         );
@@ -402,83 +569,103 @@ mod tests {
             [
                 Token {
                     kind: Equal,
-                    span: (1..2).into(),
+                    span: (0..1).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: EqualEqual,
-                    span: (3..5).into(),
+                    span: (2..4).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Plus,
-                    span: (6..7).into(),
+                    span: (5..6).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: PlusEqual,
-                    span: (8..10).into(),
+                    span: (7..9).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Minus,
-                    span: (11..12).into(),
+                    span: (10..11).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: MinusEqual,
-                    span: (13..15).into(),
+                    span: (12..14).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Star,
-                    span: (16..17).into(),
+                    span: (15..16).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: StarEqual,
-                    span: (18..20).into(),
+                    span: (17..19).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Slash,
-                    span: (21..22).into(),
+                    span: (20..21).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: SlashEqual,
-                    span: (23..25).into(),
+                    span: (22..24).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Percent,
-                    span: (26..27).into(),
+                    span: (25..26).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: PercentEqual,
-                    span: (28..30).into(),
+                    span: (27..29).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Ampersand,
-                    span: (31..32).into(),
+                    span: (30..31).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Bar,
-                    span: (33..34).into(),
+                    span: (32..33).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Tilde,
-                    span: (35..36).into(),
+                    span: (34..35).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Bang,
-                    span: (37..38).into(),
+                    span: (36..37).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: BangEqual,
-                    span: (39..41).into()
+                    span: (38..40).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Lt,
-                    span: (42..43).into(),
+                    span: (41..42).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Gt,
-                    span: (44..45).into(),
+                    span: (43..44).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: EoF,
-                    span: (46..46).into(),
+                    span: (44..44).into(),
+                    symbol: None,
                 },
             ]
         );
@@ -486,11 +673,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lex_multi_character_operators() -> anyhow::Result<()> {
+        // The longest operator is lexed first, so runs of characters split after it.
+        let source = "<< <<= >> >>= && &= || |= <= >= <<<=>>>|||&&&";
+        let kinds: Vec<_> = super::lex(source)?
+            .iter()
+            .map(|token| (token.kind, token.span))
+            .collect();
+
+        pretty_assert_eq!(
+            kinds,
+            [
+                (Shl, (0..2).into()),
+                (ShlEqual, (3..6).into()),
+                (Shr, (7..9).into()),
+                (ShrEqual, (10..13).into()),
+                (AmpAmp, (14..16).into()),
+                (AmpersandEqual, (17..19).into()),
+                (BarBar, (20..22).into()),
+                (BarEqual, (23..25).into()),
+                (LtEqual, (26..28).into()),
+                (GtEqual, (29..31).into()),
+                (Shl, (32..34).into()),
+                (LtEqual, (34..36).into()),
+                (Shr, (36..38).into()),
+                (Gt, (38..39).into()),
+                (BarBar, (39..41).into()),
+                (Bar, (41..42).into()),
+                (AmpAmp, (42..44).into()),
+                (Ampersand, (44..45).into()),
+                (EoF, (45..45).into()),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_lex_keywords() -> anyhow::Result<()> {
         use crate::token::Keyword::*;
 
-        let source = "proc let void int ret float if elif else for while do";
+        let source =
+            "proc let void int ret float if elif else for while do match pub mod const import in";
         let tokens = super::lex(source)?;
 
         pretty_assert_eq!(
@@ -498,55 +723,98 @@ mod tests {
             [
                 Token {
                     kind: Ident(Keyword(Proc)),
-                    span: (1..5).into(),
+                    span: (0..4).into(),
+                    symbol: Some("proc".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Let)),
-                    span: (6..9).into(),
+                    span: (5..8).into(),
+                    symbol: Some("let".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Void)),
-                    span: (10..14).into(),
+                    span: (9..13).into(),
+                    symbol: Some("void".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Int)),
-                    span: (15..18).into(),
+                    span: (14..17).into(),
+                    symbol: Some("int".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Ret)),
-                    span: (19..22).into(),
+                    span: (18..21).into(),
+                    symbol: Some("ret".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Float)),
-                    span: (23..28).into(),
+                    span: (22..27).into(),
+                    symbol: Some("float".into()),
                 },
                 Token {
                     kind: Ident(Keyword(If)),
-                    span: (29..31).into(),
+                    span: (28..30).into(),
+                    symbol: Some("if".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Elif)),
-                    span: (32..36).into(),
+                    span: (31..35).into(),
+                    symbol: Some("elif".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Else)),
-                    span: (37..41).into(),
+                    span: (36..40).into(),
+                    symbol: Some("else".into()),
                 },
                 Token {
                     kind: Ident(Keyword(For)),
-                    span: (42..45).into(),
+                    span: (41..44).into(),
+                    symbol: Some("for".into()),
                 },
                 Token {
                     kind: Ident(Keyword(While)),
-                    span: (46..51).into(),
+                    span: (45..50).into(),
+                    symbol: Some("while".into()),
                 },
                 Token {
                     kind: Ident(Keyword(Do)),
-                    span: (52..54).into(),
+                    span: (51..53).into(),
+                    symbol: Some("do".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(Match)),
+                    span: (54..59).into(),
+                    symbol: Some("match".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(Pub)),
+                    span: (60..63).into(),
+                    symbol: Some("pub".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(Mod)),
+                    span: (64..67).into(),
+                    symbol: Some("mod".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(Const)),
+                    span: (68..73).into(),
+                    symbol: Some("const".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(Import)),
+                    span: (74..80).into(),
+                    symbol: Some("import".into()),
+                },
+                Token {
+                    kind: Ident(Keyword(In)),
+                    span: (81..83).into(),
+                    symbol: Some("in".into()),
                 },
                 Token {
                     kind: EoF,
-                    span: (55..55).into(),
+                    span: (83..83).into(),
+                    symbol: None,
                 },
             ]
         );
@@ -564,31 +832,38 @@ mod tests {
             [
                 Token {
                     kind: Ident(NonReserved),
-                    span: (1..3).into(),
+                    span: (0..2).into(),
+                    symbol: Some("_x".into()),
                 },
                 Token {
                     kind: Ident(NonReserved),
-                    span: (4..5).into(),
+                    span: (3..4).into(),
+                    symbol: Some("y".into()),
                 },
                 Token {
                     kind: Ident(NonReserved),
-                    span: (6..7).into(),
+                    span: (5..6).into(),
+                    symbol: Some("z".into()),
                 },
                 Token {
                     kind: Ident(NonReserved),
-                    span: (8..12).into(),
+                    span: (7..11).into(),
+                    symbol: Some("_foo".into()),
                 },
                 Token {
                     kind: Ident(NonReserved),
-                    span: (13..16).into(),
+                    span: (12..15).into(),
+                    symbol: Some("bar".into()),
                 },
                 Token {
                     kind: Ident(NonReserved),
-                    span: (17..20).into(),
+                    span: (16..19).into(),
+                    symbol: Some("baz".into()),
                 },
                 Token {
                     kind: EoF,
-                    span: (21..21).into(),
+                    span: (19..19).into(),
+                    symbol: None,
                 }
             ]
         );
@@ -608,59 +883,482 @@ mod tests {
             [
                 Token {
                     kind: Literal(Integer { base: Decimal }),
-                    span: (1..2).into(),
+                    span: (0..1).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Decimal }),
-                    span: (3..6).into(),
+                    span: (2..5).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Binary }),
-                    span: (7..17).into(),
+                    span: (6..16).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Binary }),
-                    span: (18..29).into(),
+                    span: (17..28).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Hexadecimal }),
-                    span: (30..34).into(),
+                    span: (29..33).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Hexadecimal }),
-                    span: (35..42).into(),
+                    span: (34..41).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Hexadecimal }),
-                    span: (43..48).into(),
+                    span: (42..47).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Integer { base: Octal }),
-                    span: (49..53).into(),
+                    span: (48..52).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Float),
-                    span: (54..58).into(),
+                    span: (53..57).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Float),
-                    span: (59..66).into(),
+                    span: (58..65).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(Character),
-                    span: (67..70).into()
+                    span: (66..69).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: Literal(String),
-                    span: (71..75).into(),
+                    span: (70..74).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: EoF,
+                    span: (74..74).into(),
+                    symbol: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_ranges_and_arms() -> anyhow::Result<()> {
+        use crate::token::LiteralKind::*;
+
+        let source = "1..5 1..=5 1.5 _ => 2";
+        let tokens = super::lex(source)?;
+
+        pretty_assert_eq!(
+            tokens,
+            [
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (0..1).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: PeriodPeriod,
+                    span: (1..3).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (3..4).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (5..6).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: PeriodPeriodEqual,
+                    span: (6..9).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (9..10).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Float),
+                    span: (11..14).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Ident(NonReserved),
+                    span: (15..16).into(),
+                    symbol: Some("_".into()),
+                },
+                Token {
+                    kind: FatArrow,
+                    span: (17..19).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (20..21).into(),
+                    symbol: None,
                 },
                 Token {
                     kind: EoF,
-                    span: (76..76).into(),
+                    span: (21..21).into(),
+                    symbol: None,
                 },
             ]
         );
 
         Ok(())
     }
+
+    #[test]
+    fn test_lex_float_exponents() -> anyhow::Result<()> {
+        use crate::token::LiteralKind::*;
+
+        let source = "1e10 2.5E-3 6e+2 1em";
+        let tokens = super::lex(source)?;
+
+        pretty_assert_eq!(
+            tokens,
+            [
+                Token {
+                    kind: Literal(Float),
+                    span: (0..4).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Float),
+                    span: (5..11).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Float),
+                    span: (12..16).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Literal(Integer { base: Decimal }),
+                    span: (17..18).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Ident(NonReserved),
+                    span: (18..20).into(),
+                    symbol: Some("em".into()),
+                },
+                Token {
+                    kind: EoF,
+                    span: (20..20).into(),
+                    symbol: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_with_comments() -> anyhow::Result<()> {
+        let source = "x // comment\n/// doc";
+        let tokens = super::lex_with_comments(source)?;
+
+        pretty_assert_eq!(
+            tokens,
+            [
+                Token {
+                    kind: Ident(NonReserved),
+                    span: (0..1).into(),
+                    symbol: Some("x".into()),
+                },
+                Token {
+                    kind: Comment,
+                    span: (2..12).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: DocComment,
+                    span: (13..20).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: EoF,
+                    span: (20..20).into(),
+                    symbol: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_comments() -> anyhow::Result<()> {
+        use crate::token::Keyword::*;
+
+        let source = "// comment\n/// doc\n//// not doc\nproc";
+        let tokens = super::lex(source)?;
+
+        pretty_assert_eq!(
+            tokens,
+            [
+                Token {
+                    kind: DocComment,
+                    span: (11..18).into(),
+                    symbol: None,
+                },
+                Token {
+                    kind: Ident(Keyword(Proc)),
+                    span: (32..36).into(),
+                    symbol: Some("proc".into()),
+                },
+                Token {
+                    kind: EoF,
+                    span: (36..36).into(),
+                    symbol: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lex_block_comments() -> anyhow::Result<()> {
+        use crate::token::Keyword::*;
+
+        let source = "/* a /* nested */ comment\n*/ proc /**/x";
+        assert_eq!(
+            super::lex(source)?,
+            [
+                Token {
+                    kind: Ident(Keyword(Proc)),
+                    span: (29..33).into(),
+                    symbol: Some("proc".into()),
+                },
+                Token {
+                    kind: Ident(NonReserved),
+                    span: (38..39).into(),
+                    symbol: Some("x".into()),
+                },
+                Token {
+                    kind: EoF,
+                    span: (39..39).into(),
+                    symbol: None,
+                },
+            ]
+        );
+
+        let kinds: Vec<_> = super::lex_with_comments(source)?
+            .iter()
+            .map(|token| (token.kind, token.span))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (Comment, (0..28).into()),
+                (Ident(Keyword(Proc)), (29..33).into()),
+                (Comment, (34..38).into()),
+                (Ident(NonReserved), (38..39).into()),
+                (EoF, (39..39).into()),
+            ]
+        );
+
+        let failure = super::lex("x /* /* */").unwrap_err();
+        assert!(matches!(
+            failure.diagnostics(),
+            [super::UnterminatedBlockComment(span)] if *span == Span::from(2..4)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delimiter_depth() -> anyhow::Result<()> {
+        assert_eq!(super::delimiter_depth(&super::lex("proc f() void {")?), 1);
+        assert_eq!(super::delimiter_depth(&super::lex("{ [(1)] ")?), 1);
+        assert_eq!(super::delimiter_depth(&super::lex("f(x)")?), 0);
+        assert_eq!(super::delimiter_depth(&super::lex("])")?), -2);
+
+        Ok(())
+    }
+
+    /// Pieces of programs, put together in any order to make text that mostly lexes, unlike
+    /// arbitrary strings, with the multi-byte characters and line endings spans go wrong on.
+    const PIECES: &[&str] = &[
+        "proc",
+        "main",
+        "ret",
+        "let",
+        "x",
+        "_y",
+        "é",
+        "名前",
+        " ",
+        "  ",
+        "\t",
+        "\n",
+        "\r\n",
+        "(",
+        ")",
+        "{",
+        "}",
+        "[",
+        "]",
+        ":",
+        "::",
+        ";",
+        ".",
+        "..",
+        "..=",
+        ",",
+        "@",
+        "=",
+        "==",
+        "=>",
+        "+",
+        "+=",
+        "-",
+        "*",
+        "/",
+        "/=",
+        "%",
+        "&",
+        "|",
+        "~",
+        "!",
+        "!=",
+        "<",
+        ">",
+        "<<",
+        "<<=",
+        ">>",
+        ">>=",
+        "&&",
+        "&=",
+        "||",
+        "|=",
+        "<=",
+        ">=",
+        "0",
+        "42",
+        "1.5",
+        "2e10",
+        "3E-2",
+        "0x1f",
+        "0b10",
+        "0o7",
+        "1_000",
+        "\"text\"",
+        "\"é\\n\"",
+        "\"multi\nline\"",
+        "'c'",
+        "'é'",
+        "'\\''",
+        "// comment\n",
+        "/// doc\n",
+        "//// not doc\n",
+        "// é\r\n",
+        "/* block */",
+        "/* /* nested */ */",
+        "/*",
+        "*/",
+        "\"",
+        "'",
+        "$",
+        "#",
+    ];
+
+    fn program() -> impl Strategy<Value = String> {
+        prop::collection::vec(prop::sample::select(PIECES), 0..32)
+            .prop_map(|pieces| pieces.concat())
+    }
+
+    /// Check that tokens are in order within the source without overlapping, that each is the
+    /// only token lexed from the text it spans, and that there's nothing but what `lex` skips
+    /// between them.
+    fn check_tokens(
+        source: &str,
+        tokens: &[Token],
+        lex: fn(&str) -> Result<Vec<Token>, super::DiagnosticSink>,
+    ) -> Result<(), TestCaseError> {
+        let lexed = |text: &str| {
+            lex(text).map_err(|_| TestCaseError::fail(format!("{text:?} doesn't lex on its own")))
+        };
+        let eof = |offset: usize| Token::new(EoF, (offset..offset).into(), "");
+
+        let (&last, tokens) = tokens.split_last().unwrap();
+        prop_assert_eq!(last, eof(source.len()));
+
+        let mut previous_end = 0;
+        for &token in tokens {
+            let Span { start, end } = token.span;
+            prop_assert!(
+                previous_end <= start && start < end && end <= source.len(),
+                "{:?} overlaps the token before it, ending at {}, or is out of bounds",
+                token,
+                previous_end
+            );
+            prop_assert!(source.is_char_boundary(start) && source.is_char_boundary(end));
+
+            prop_assert_eq!(
+                lexed(&source[previous_end..start])?,
+                [eof(start - previous_end)]
+            );
+            let lexeme = &source[start..end];
+            let alone = Token::new(token.kind, (0..lexeme.len()).into(), lexeme);
+            prop_assert_eq!(lexed(lexeme)?, [alone, eof(lexeme.len())]);
+            previous_end = end;
+        }
+
+        Ok(())
+    }
+
+    /// Check that a failure's diagnostics label text within the source.
+    fn check_diagnostics(
+        source: &str,
+        failure: &super::DiagnosticSink,
+    ) -> Result<(), TestCaseError> {
+        for diagnostic in failure.diagnostics() {
+            for label in diagnostic.labels().into_iter().flatten() {
+                let (start, end) = (label.offset(), label.offset() + label.len());
+                prop_assert!(end <= source.len(), "{:?} is out of bounds", diagnostic);
+                prop_assert!(source.is_char_boundary(start) && source.is_char_boundary(end));
+            }
+        }
+
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_spans_of_programs(source in program()) {
+            match super::lex(&source) {
+                Ok(tokens) => check_tokens(&source, &tokens, super::lex)?,
+                Err(failure) => check_diagnostics(&source, &failure)?,
+            }
+            if let Ok(tokens) = super::lex_with_comments(&source) {
+                check_tokens(&source, &tokens, super::lex_with_comments)?;
+            }
+        }
+
+        #[test]
+        fn test_spans_of_any_text(source in any::<String>()) {
+            match super::lex(&source) {
+                Ok(tokens) => check_tokens(&source, &tokens, super::lex)?,
+                Err(failure) => check_diagnostics(&source, &failure)?,
+            }
+        }
+    }
 }
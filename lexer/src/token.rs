@@ -1,4 +1,6 @@
+use intern::Symbol;
 use span::Span;
+use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Keyword {
@@ -16,6 +18,22 @@ pub enum Keyword {
     Do,
     Bool,
     Str,
+    Match,
+    Pub,
+    Mod,
+    Const,
+    Import,
+    In,
+    Char,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    As,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -83,21 +101,36 @@ pub enum TokenKind {
     /// :
     Colon,
 
+    /// ::
+    ColonColon,
+
     /// ;
     Semicolon,
 
     /// .
     Period,
 
+    /// ..
+    PeriodPeriod,
+
+    /// ..=
+    PeriodPeriodEqual,
+
     /// ,
     Comma,
 
+    /// @
+    At,
+
     /// =
     Equal,
 
     /// ==
     EqualEqual,
 
+    /// =>
+    FatArrow,
+
     /// +
     Plus,
 
@@ -185,6 +218,15 @@ pub enum TokenKind {
     /// Literals.
     Literal(LiteralKind),
 
+    /// A doc comment (/// ...), documenting the item that follows it.
+    DocComment,
+
+    /// An ordinary comment (// ..., /* ... */). Only lexed by [`lex_with_comments`], since comments are
+    /// otherwise skipped like whitespace.
+    ///
+    /// [`lex_with_comments`]: crate::lex_with_comments
+    Comment,
+
     /// End of file.
     EoF,
 }
@@ -243,22 +285,204 @@ impl TokenKind {
         matches!(self, Lt | LtEqual | Gt | GtEqual)
     }
 
+    /// Returns if this token kind is an assignment or compound assignment operator or not.
+    pub fn is_assignment_op(self) -> bool {
+        use TokenKind::{
+            AmpersandEqual, BarEqual, Equal, MinusEqual, PercentEqual, PlusEqual, ShlEqual,
+            ShrEqual, SlashEqual, StarEqual,
+        };
+
+        matches!(
+            self,
+            Equal
+                | PlusEqual
+                | MinusEqual
+                | StarEqual
+                | SlashEqual
+                | PercentEqual
+                | AmpersandEqual
+                | BarEqual
+                | ShlEqual
+                | ShrEqual
+        )
+    }
+
     /// Returns if this token kind is an equality operator or not.
     pub fn is_equality_op(self) -> bool {
         use TokenKind::{BangEqual, EqualEqual};
 
         matches!(self, BangEqual | EqualEqual)
     }
+
+    /// Returns if this token kind opens a parenthesis, brace or square bracket or not.
+    pub fn is_open_delimiter(self) -> bool {
+        use TokenKind::{OpenCurly, OpenParen, OpenSquare};
+
+        matches!(self, OpenParen | OpenCurly | OpenSquare)
+    }
+
+    /// Returns if this token kind closes a parenthesis, brace or square bracket or not.
+    pub fn is_closing_delimiter(self) -> bool {
+        use TokenKind::{ClosingCurly, ClosingParen, ClosingSquare};
+
+        matches!(self, ClosingParen | ClosingCurly | ClosingSquare)
+    }
+}
+
+impl Keyword {
+    /// Returns if the keyword names a type.
+    pub fn is_type(self) -> bool {
+        use Keyword::*;
+
+        matches!(
+            self,
+            Void | Int | Float | Bool | Str | Char | I8 | I16 | I32 | I64 | U8 | U16 | U32 | U64
+        )
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Keyword::*;
+
+        write!(
+            f,
+            "{}",
+            match self {
+                Proc => "proc",
+                Let => "let",
+                Void => "void",
+                Int => "int",
+                Ret => "ret",
+                Float => "float",
+                If => "if",
+                Elif => "elif",
+                Else => "else",
+                For => "for",
+                While => "while",
+                Do => "do",
+                Bool => "bool",
+                Str => "str",
+                Match => "match",
+                Pub => "pub",
+                Mod => "mod",
+                Const => "const",
+                Import => "import",
+                In => "in",
+                Char => "char",
+                I8 => "i8",
+                I16 => "i16",
+                I32 => "i32",
+                I64 => "i64",
+                U8 => "u8",
+                U16 => "u16",
+                U32 => "u32",
+                U64 => "u64",
+                As => "as",
+            }
+        )
+    }
+}
+
+impl fmt::Display for LiteralKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LiteralKind::*;
+
+        write!(
+            f,
+            "{}",
+            match self {
+                Character => "character literal",
+                String => "string literal",
+                Integer { base: _ } => "integer literal",
+                Float => "float literal",
+                Boolean => "boolean literal",
+            }
+        )
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TokenKind::*;
+
+        let symbol = match self {
+            OpenParen => "(",
+            ClosingParen => ")",
+            OpenCurly => "{",
+            ClosingCurly => "}",
+            OpenSquare => "[",
+            ClosingSquare => "]",
+            Colon => ":",
+            ColonColon => "::",
+            Semicolon => ";",
+            Period => ".",
+            PeriodPeriod => "..",
+            PeriodPeriodEqual => "..=",
+            Comma => ",",
+            At => "@",
+            Equal => "=",
+            EqualEqual => "==",
+            FatArrow => "=>",
+            Plus => "+",
+            PlusEqual => "+=",
+            Minus => "-",
+            MinusEqual => "-=",
+            Star => "*",
+            StarEqual => "*=",
+            Slash => "/",
+            SlashEqual => "/=",
+            Percent => "%",
+            PercentEqual => "%=",
+            Ampersand => "&",
+            AmpersandEqual => "&=",
+            AmpAmp => "&&",
+            Bar => "|",
+            BarEqual => "|=",
+            BarBar => "||",
+            Tilde => "~",
+            Bang => "!",
+            BangEqual => "!=",
+            Lt => "<",
+            LtEqual => "<=",
+            Gt => ">",
+            GtEqual => ">=",
+            Shl => "<<",
+            ShlEqual => "<<=",
+            Shr => ">>",
+            ShrEqual => ">>=",
+            Ident(IdentKind::NonReserved) => return write!(f, "identifier"),
+            Ident(IdentKind::Keyword(keyword)) => return write!(f, "keyword `{keyword}`"),
+            Literal(kind) => return write!(f, "{kind}"),
+            DocComment => return write!(f, "doc comment"),
+            Comment => return write!(f, "comment"),
+            EoF => return write!(f, "end of file"),
+        };
+
+        write!(f, "`{symbol}`")
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
+
+    /// The interned name of an identifier or keyword, so the parser needn't slice it out of the
+    /// source. `None` for every other kind of token: a literal's text stays in the source its
+    /// span covers, so editing literals doesn't grow the interner.
+    pub symbol: Option<Symbol>,
 }
 
 impl Token {
-    pub(crate) fn new(kind: TokenKind, span: Span) -> Self {
-        Self { kind, span }
+    /// A token lexed from `text`, which is kept if the token is an identifier.
+    pub fn new(kind: TokenKind, span: Span, text: &str) -> Self {
+        let symbol = matches!(kind, TokenKind::Ident(_)).then(|| Symbol::intern(text));
+        Self { kind, span, symbol }
+    }
+
+    /// The name of an identifier or keyword, or `None` for any other kind of token.
+    pub fn text(self) -> Option<&'static str> {
+        self.symbol.map(Symbol::as_str)
     }
 }
@@ -0,0 +1,493 @@
+//! Analysis of an open document: its diagnostics and the fixes they suggest, and the names it
+//! defines and refers to.
+//!
+//! A document is lexed, parsed and lowered on its own, like a program of one file. The outline
+//! comes from the syntax tree, so it's there as long as the document parses, but definitions
+//! and hovers need the resolved symbols, which only a document that lowers without errors has.
+//!
+//! Analyzing a document stops if its token is cancelled, since a change to the document makes
+//! the analysis out of date before it's done.
+
+use diagnostics::{DiagnosticSink, Note, Stage, Suggestion};
+use hir::{Builtin, BuiltinParam, Literal, Program, Symbol};
+use matrix_compiler::{Cancel, Cancelled, Diagnostics, Lexed, Parsed, Session, Sources};
+use miette::Severity;
+use parser::ast;
+use span::{Span, Spanned};
+
+/// The name a document is compiled under. Diagnostics are sent with the document's URI, so it's
+/// never shown.
+const DOCUMENT_NAME: &str = "<document>";
+
+/// A diagnostic reported on a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+
+    /// The diagnostic's message, followed by its help if it has any, and its notes that don't
+    /// point into the document.
+    pub message: String,
+
+    /// The span of its first label.
+    pub span: Span,
+
+    /// The span and text of each of its other labels, then of its notes that point into the
+    /// document.
+    pub related: Vec<(Span, String)>,
+
+    /// The fix it suggests, if it has one.
+    pub suggestion: Option<Suggestion>,
+}
+
+impl Diagnostic {
+    fn new(
+        diagnostic: &dyn miette::Diagnostic,
+        suggestion: Option<Suggestion>,
+        notes: Vec<Note>,
+    ) -> Self {
+        let mut labels = diagnostic.labels().into_iter().flatten().map(|label| {
+            let span = Span::from(label.offset()..label.offset() + label.len());
+            (span, label.label().map(str::to_owned))
+        });
+        let span = labels
+            .next()
+            .map_or_else(|| Span::from(0..0), |(span, _)| span);
+        let mut message = diagnostic.help().map_or_else(
+            || diagnostic.to_string(),
+            |help| format!("{diagnostic}\n\nhelp: {help}"),
+        );
+        let mut related: Vec<_> = labels
+            .map(|(span, text)| (span, text.unwrap_or_else(|| diagnostic.to_string())))
+            .collect();
+        for note in notes {
+            match note.span {
+                Some(span) => related.push((span, note.message)),
+                None => message.push_str(&format!("\n\nnote: {}", note.message)),
+            }
+        }
+
+        Self {
+            severity: diagnostic.severity().unwrap_or(Severity::Error),
+            code: diagnostic.code().map(|code| code.to_string()),
+            related,
+            message,
+            span,
+            suggestion,
+        }
+    }
+}
+
+/// Add a stage's diagnostic, with its notes and the fix it suggests.
+fn collect_stage<D: Stage>(diagnostic: &D, diagnostics: &mut Vec<Diagnostic>) {
+    diagnostics.push(Diagnostic::new(
+        diagnostic,
+        diagnostic.suggestion(),
+        diagnostic.notes(),
+    ));
+}
+
+fn collect_sink<D: Stage>(sink: &DiagnosticSink<D>, diagnostics: &mut Vec<Diagnostic>) {
+    for diagnostic in sink.diagnostics() {
+        collect_stage(diagnostic, diagnostics);
+    }
+}
+
+/// Add every diagnostic a session found, whichever stage found it.
+fn collect_session(found: &Diagnostics, diagnostics: &mut Vec<Diagnostic>) {
+    for (_, sink) in &found.lex {
+        collect_sink(sink, diagnostics);
+    }
+    collect_sink(&found.parse, diagnostics);
+    collect_sink(&found.check, diagnostics);
+}
+
+/// What an item of a document's outline is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Module,
+    Proc,
+    Const,
+}
+
+/// An item of a document's outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+
+    /// The span of the whole item.
+    pub span: Span,
+    pub name_span: Span,
+
+    /// The items of a module.
+    pub children: Vec<Self>,
+}
+
+fn outline(items: &[ast::Item]) -> Vec<DocumentSymbol> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let (name, kind, children) = match &item.kind {
+                ast::ItemKind::Proc(proc) => (&proc.name, SymbolKind::Proc, Vec::new()),
+                ast::ItemKind::Mod(module) => {
+                    (&module.name, SymbolKind::Module, outline(&module.items))
+                }
+                ast::ItemKind::Const(constant) => (&constant.name, SymbolKind::Const, Vec::new()),
+                ast::ItemKind::Import(_) => return None,
+            };
+            Some(DocumentSymbol {
+                name: name.name.clone(),
+                kind,
+                span: item.span,
+                name_span: name.span,
+                children,
+            })
+        })
+        .collect()
+}
+
+/// How a builtin's parameter is shown in its signature, with `T` for the type of the
+/// elements of the array it takes, and `K` and `V` for the key and value types of the map.
+fn builtin_param(param: BuiltinParam) -> String {
+    match param {
+        BuiltinParam::Ty(ty) => ty.to_string(),
+        BuiltinParam::Any => "any".to_owned(),
+        BuiltinParam::Sequence => "str | [T] | [K: V]".to_owned(),
+        BuiltinParam::StrOrChar => "str | char".to_owned(),
+        BuiltinParam::Array => "[T]".to_owned(),
+        BuiltinParam::Element => "T".to_owned(),
+        BuiltinParam::Map => "[K: V]".to_owned(),
+        BuiltinParam::Key => "K".to_owned(),
+        BuiltinParam::Value => "V".to_owned(),
+    }
+}
+
+fn builtin_signature(builtin: Builtin) -> String {
+    let params: Vec<_> = builtin
+        .params()
+        .iter()
+        .copied()
+        .map(builtin_param)
+        .collect();
+    let ret_ty = builtin.ret_ty().map_or_else(
+        || match builtin {
+            Builtin::Get => "V".to_owned(),
+            Builtin::Keys => "[K]".to_owned(),
+            _ => "T".to_owned(),
+        },
+        |ty| ty.to_string(),
+    );
+    let module = builtin
+        .module()
+        .map_or_else(String::new, |module| format!("{}::", module.name()));
+    format!(
+        "proc {module}{}({}) {ret_ty}",
+        builtin.name(),
+        params.join(", ")
+    )
+}
+
+fn literal(value: &Literal) -> String {
+    match value {
+        Literal::Int(n, ty) => ty.value(*n).to_string(),
+        Literal::Float(x) => format!("{x:?}"),
+        Literal::Bool(b) => b.to_string(),
+        Literal::Char(c) => format!("{c:?}"),
+        Literal::Str(s) => format!("{s:?}"),
+    }
+}
+
+/// The analysis of a document.
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// The document's syntax tree, if it parses.
+    ast: Option<ast::Program>,
+
+    /// The document's program, if it lowers without errors.
+    program: Option<Program>,
+}
+
+impl Analysis {
+    /// Analyze a document, unless the token is cancelled first.
+    pub fn new(text: &str, cancel: &Cancel) -> Result<Self, Cancelled> {
+        let sources = Sources::new([(DOCUMENT_NAME.to_owned(), text.to_owned())]);
+        let session = Session::new(sources).cancel_on(cancel.clone());
+        let parsed = match session.lex().and_then(Lexed::parse) {
+            Ok(parsed) => Some(parsed),
+            Err(error) if error.is_cancelled() => return Err(Cancelled),
+            Err(_) => None,
+        };
+        let program = match parsed.as_ref().map(Parsed::check) {
+            Some(Ok(checked)) => Some(checked.program),
+            Some(Err(error)) if error.is_cancelled() => return Err(Cancelled),
+            Some(Err(_)) | None => None,
+        };
+        let mut diagnostics = Vec::new();
+        collect_session(&session.diagnostics(), &mut diagnostics);
+
+        Ok(Self {
+            diagnostics,
+            ast: parsed.map(Parsed::into_ast),
+            program,
+        })
+    }
+
+    /// The name at an offset, counting the offset just after it, and the symbol it defines or
+    /// refers to.
+    fn symbol_at(&self, offset: usize) -> Option<Spanned<Symbol>> {
+        let symbols = &self.program.as_ref()?.symbols;
+        let i = symbols.partition_point(|symbol| symbol.span.start <= offset);
+        let symbol = *symbols.get(i.checked_sub(1)?)?;
+        (offset <= symbol.span.end).then_some(symbol)
+    }
+
+    /// The span of the name defining what the name at an offset refers to. Builtins and the
+    /// standard library aren't defined in the document, so they have none.
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        let program = self.program.as_ref()?;
+        match *self.symbol_at(offset)? {
+            Symbol::Module(id) => Some(program.module(id).span),
+            Symbol::Proc(id) => {
+                // A procedure's own name is the first name in it that refers to it.
+                let start = program.proc(id).span.start;
+                program
+                    .symbols
+                    .iter()
+                    .find(|symbol| symbol.span.start >= start && symbol.node == Symbol::Proc(id))
+                    .map(|symbol| symbol.span)
+            }
+            Symbol::Const(id) => Some(program.constant(id).span),
+            Symbol::Local(proc, local) => Some(program.proc(proc).local(local).span),
+            Symbol::Builtin(_) | Symbol::Native(_) | Symbol::StdModule(_) | Symbol::StdConst(_) => {
+                None
+            }
+        }
+    }
+
+    /// The span of the name at an offset, and Markdown describing what it refers to: its
+    /// signature or type, then its doc comments.
+    pub fn hover(&self, offset: usize) -> Option<(Span, String)> {
+        let program = self.program.as_ref()?;
+        let Spanned { node: symbol, span } = self.symbol_at(offset)?;
+        let (signature, doc) = match symbol {
+            Symbol::Module(id) => (format!("mod {}", program.module_path(id).join("::")), None),
+            Symbol::Proc(id) => {
+                let proc = program.proc(id);
+                let params: Vec<_> = proc
+                    .params
+                    .iter()
+                    .map(|&param| {
+                        let local = proc.local(param);
+                        format!("{}: {}", local.name, local.ty)
+                    })
+                    .collect();
+                let signature = format!(
+                    "proc {}({}) {}",
+                    program.qualified_name(id),
+                    params.join(", "),
+                    proc.ret_ty
+                );
+                (signature, proc.doc.as_deref())
+            }
+            Symbol::Const(id) => {
+                let constant = program.constant(id);
+                let signature = format!(
+                    "const {}: {} = {}",
+                    constant.name,
+                    constant.ty,
+                    literal(&constant.value)
+                );
+                (signature, constant.doc.as_deref())
+            }
+            Symbol::Local(proc, local) => {
+                let local = program.proc(proc).local(local);
+                (format!("let {}: {}", local.name, local.ty), None)
+            }
+            Symbol::Builtin(builtin) => (builtin_signature(builtin), None),
+            Symbol::Native(id) => {
+                let native = program.native(id);
+                let params: Vec<_> = native.params.iter().map(ToString::to_string).collect();
+                let signature = format!(
+                    "proc {}({}) {}",
+                    native.name,
+                    params.join(", "),
+                    native.ret_ty
+                );
+                (signature, None)
+            }
+            Symbol::StdModule(module) => (format!("mod {}", module.name()), None),
+            Symbol::StdConst(constant) => {
+                let signature = format!(
+                    "const {}::{}: float = {:?}",
+                    constant.module().name(),
+                    constant.name(),
+                    constant.value()
+                );
+                (signature, None)
+            }
+        };
+
+        let mut markdown = format!("```matrix\n{signature}\n```");
+        if let Some(doc) = doc {
+            markdown.push_str("\n\n");
+            markdown.push_str(doc);
+        }
+        Some((span, markdown))
+    }
+
+    /// The diagnostics overlapping a span that suggest fixes, with their fixes.
+    pub fn suggestions(&self, span: Span) -> Vec<(Diagnostic, Suggestion)> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.span.intersects(span))
+            .filter_map(|diagnostic| {
+                let suggestion = diagnostic.suggestion.clone()?;
+                Some((diagnostic.clone(), suggestion))
+            })
+            .collect()
+    }
+
+    /// The document's outline: its procedures, constants and modules, with their items.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        self.ast
+            .as_ref()
+            .map_or_else(Vec::new, |ast| outline(&ast.items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+/// Adds one.
+proc inc(x: int) int { ret x + 1; }
+
+mod math2 {
+    pub const TWO: int = 2;
+}
+
+proc main() int { let y = inc(math2::TWO); ret y; }
+";
+
+    fn at(needle: &str) -> usize {
+        SOURCE.rfind(needle).unwrap()
+    }
+
+    fn analyze(text: &str) -> Analysis {
+        Analysis::new(text, &Cancel::new()).unwrap()
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let analysis = analyze(SOURCE);
+        assert_eq!(analysis.diagnostics, []);
+
+        let definition = analysis.definition(at("inc(math2")).unwrap();
+        assert_eq!(&SOURCE[definition.start..definition.end], "inc");
+        assert_eq!(definition.start, SOURCE.find("inc").unwrap());
+        assert_eq!(
+            analysis.definition(at("TWO)")).map(|span| span.start),
+            SOURCE.find("TWO")
+        );
+        assert_eq!(
+            analysis.definition(at("y;")).map(|span| span.start),
+            SOURCE.find("y =")
+        );
+
+        let (span, markdown) = analysis.hover(at("inc(math2") + 3).unwrap();
+        assert_eq!(span.start, at("inc(math2"));
+        assert_eq!(
+            markdown,
+            "```matrix\nproc inc(x: int) int\n```\n\nAdds one."
+        );
+        assert_eq!(
+            analysis.hover(at("TWO)")).unwrap().1,
+            "```matrix\nconst TWO: int = 2\n```"
+        );
+        assert_eq!(analysis.hover(at("ret y")), None);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let analysis = analyze("proc main( int {");
+        assert!(!analysis.diagnostics.is_empty());
+        assert_eq!(analysis.symbols(), []);
+
+        let analysis = analyze("proc main() int { ret \"a\"; }");
+        let [diagnostic] = &analysis.diagnostics[..] else {
+            panic!("expected one diagnostic, found {:?}", analysis.diagnostics);
+        };
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            &"proc main() int { ret \"a\"; }"[diagnostic.span.start..diagnostic.span.end],
+            "\"a\""
+        );
+    }
+
+    #[test]
+    fn test_notes() {
+        let text = "proc f(x: int) void {}\nproc main() void { f(); }";
+        let analysis = analyze(text);
+        let [diagnostic] = &analysis.diagnostics[..] else {
+            panic!("expected one diagnostic, found {:?}", analysis.diagnostics);
+        };
+        assert_eq!(
+            diagnostic.related,
+            [(Span::from(5..6), "`f` is defined here".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let text = "proc main() int { ret 1 }";
+        let analysis = analyze(text);
+        let end = text.len();
+        let suggestions = analysis.suggestions(Span::from(0..end));
+        let [(diagnostic, suggestion)] = &suggestions[..] else {
+            panic!("expected one suggestion, found {suggestions:?}");
+        };
+        assert_eq!(
+            diagnostic.code.as_deref(),
+            Some("parser::missing_semicolon")
+        );
+        assert_eq!(
+            *suggestion,
+            Suggestion::insert(text.find(" }").unwrap(), ";")
+        );
+        assert_eq!(analysis.suggestions(Span::from(0..4)), []);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let cancel = Cancel::new();
+        cancel.cancel();
+        assert!(Analysis::new(SOURCE, &cancel).is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let names = |symbols: &[DocumentSymbol]| {
+            symbols
+                .iter()
+                .map(|symbol| (symbol.name.clone(), symbol.kind, symbol.children.len()))
+                .collect::<Vec<_>>()
+        };
+        let symbols = analyze(SOURCE).symbols();
+
+        assert_eq!(
+            names(&symbols),
+            [
+                ("inc".to_owned(), SymbolKind::Proc, 0),
+                ("math2".to_owned(), SymbolKind::Module, 1),
+                ("main".to_owned(), SymbolKind::Proc, 0),
+            ]
+        );
+        assert_eq!(
+            names(&symbols[1].children),
+            [("TWO".to_owned(), SymbolKind::Const, 0)]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Converting between byte offsets and the positions of the Language Server Protocol, which
+//! count lines from 0 and columns in UTF-16 code units.
+
+/// Finds the positions of offsets in a document, and the offsets of positions.
+pub struct LineIndex {
+    /// The offset every line starts at.
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { starts }
+    }
+
+    /// The line and UTF-16 column of an offset.
+    pub fn position(&self, text: &str, offset: usize) -> (u32, u32) {
+        let offset = offset.min(text.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let before = text.get(self.starts[line]..offset).unwrap_or_default();
+        let column = before.chars().map(char::len_utf16).sum::<usize>();
+        (line as u32, column as u32)
+    }
+
+    /// The offset of a line and UTF-16 column. Columns past the end of the line are clamped
+    /// to it, and lines past the end of the document to its end.
+    pub fn offset(&self, text: &str, line: u32, column: u32) -> usize {
+        let Some(&start) = self.starts.get(line as usize) else {
+            return text.len();
+        };
+        let end = self
+            .starts
+            .get(line as usize + 1)
+            .map_or(text.len(), |&next| next - 1);
+
+        let mut units = 0;
+        for (i, c) in text[start..end].char_indices() {
+            if units >= column as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_count_utf16() {
+        let text = "let x;\nlet 𝑥 = 'ü';\n";
+        let lines = LineIndex::new(text);
+
+        assert_eq!(lines.position(text, 0), (0, 0));
+        assert_eq!(lines.position(text, 7), (1, 0));
+        // `𝑥` takes four bytes and two UTF-16 code units.
+        assert_eq!(lines.position(text, 15), (1, 6));
+        assert_eq!(lines.position(text, text.len()), (2, 0));
+
+        assert_eq!(lines.offset(text, 1, 6), 15);
+        assert_eq!(lines.offset(text, 0, 80), 6);
+        assert_eq!(lines.offset(text, 9, 0), text.len());
+    }
+}
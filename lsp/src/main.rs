@@ -0,0 +1,476 @@
+//! A language server for matrix, speaking the Language Server Protocol over standard I/O.
+//!
+//! It publishes the diagnostics of every open document as it changes, and answers requests for
+//! definitions, hovers, document outlines and the quick fixes diagnostics suggest. Documents
+//! are synced whole on every change.
+//!
+//! Messages are read on their own thread, and handled in batches of every message that arrived
+//! while the last batch was handled. A document is only analyzed once per batch however many
+//! times it changed, so a client typing quickly doesn't queue an analysis per keystroke, and
+//! requests cancelled before their batch are answered as cancelled without being handled.
+//!
+//! A change to a document read while its old text is being analyzed cancels the analysis, so
+//! the server moves on to the new text sooner. Requests that needed it are answered as the
+//! content having been modified, and its diagnostics are left for the new text's analysis.
+
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod analysis;
+mod lines;
+mod rpc;
+
+use analysis::{Analysis, DocumentSymbol, SymbolKind};
+use lines::LineIndex;
+use matrix_compiler::Cancel;
+use miette::Severity;
+use serde_json::{json, Value};
+use span::Span;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    process::ExitCode,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// The token cancelling the analysis of each open document's text, shared with the thread
+/// reading messages so it can cancel the analysis of text a change replaces.
+type Analyzing = Arc<Mutex<HashMap<String, Cancel>>>;
+
+/// An open document.
+struct Document {
+    text: String,
+    version: Option<i64>,
+    lines: LineIndex,
+
+    /// The document's analysis, or `None` if it changed since it was last analyzed.
+    analysis: Option<Analysis>,
+
+    /// Cancelled once a newer text of the document is read.
+    cancel: Cancel,
+}
+
+impl Document {
+    fn new(text: String, version: Option<i64>) -> Self {
+        Self {
+            lines: LineIndex::new(&text),
+            text,
+            version,
+            analysis: None,
+            cancel: Cancel::new(),
+        }
+    }
+
+    /// The document's analysis, or `None` if a newer text was read while analyzing it.
+    fn analysis(&mut self) -> Option<&Analysis> {
+        if self.analysis.is_none() {
+            self.analysis = Some(Analysis::new(&self.text, &self.cancel).ok()?);
+        }
+        self.analysis.as_ref()
+    }
+
+    fn range(&self, span: Span) -> Value {
+        let (start_line, start_column) = self.lines.position(&self.text, span.start);
+        let (end_line, end_column) = self.lines.position(&self.text, span.end);
+        json!({
+            "start": { "line": start_line, "character": start_column },
+            "end": { "line": end_line, "character": end_column },
+        })
+    }
+
+    /// The offset of a position given as a request's parameter.
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()?;
+        let column = position["character"].as_u64()?;
+        Some(self.lines.offset(&self.text, line as u32, column as u32))
+    }
+}
+
+/// The result of a request, or its error code and message.
+type Response = Result<Value, (i64, String)>;
+
+fn invalid_params() -> (i64, String) {
+    (
+        rpc::INVALID_PARAMS,
+        "missing or invalid parameters".to_owned(),
+    )
+}
+
+fn content_modified() -> (i64, String) {
+    (
+        rpc::CONTENT_MODIFIED,
+        "the document changed while it was analyzed".to_owned(),
+    )
+}
+
+/// Cancel the analysis of a document's text if a message changes or closes the document.
+fn cancel_analysis(analyzing: &Analyzing, message: &Value) {
+    let method = message["method"].as_str();
+    if !matches!(
+        method,
+        Some("textDocument/didChange" | "textDocument/didClose")
+    ) {
+        return;
+    }
+    let uri = message["params"]["textDocument"]["uri"].as_str();
+    if let Some(cancel) = uri.and_then(|uri| analyzing.lock().unwrap().get(uri).cloned()) {
+        cancel.cancel();
+    }
+}
+
+struct Server<W> {
+    out: W,
+    documents: HashMap<String, Document>,
+    analyzing: Analyzing,
+    initialized: bool,
+    shut_down: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn new(out: W, analyzing: Analyzing) -> Self {
+        Self {
+            out,
+            documents: HashMap::new(),
+            analyzing,
+            initialized: false,
+            shut_down: false,
+        }
+    }
+
+    /// Open a document, or replace its text, with a new token for cancelling its analysis.
+    fn open(&mut self, uri: &str, document: Document) {
+        self.analyzing
+            .lock()
+            .unwrap()
+            .insert(uri.to_owned(), document.cancel.clone());
+        self.documents.insert(uri.to_owned(), document);
+    }
+
+    /// Handle a batch of messages, then publish the diagnostics of the documents they changed.
+    /// Returns the code to exit with once the client sends `exit`.
+    fn handle(&mut self, messages: Vec<Value>) -> io::Result<Option<ExitCode>> {
+        let cancelled: HashSet<_> = messages
+            .iter()
+            .filter(|message| message["method"] == "$/cancelRequest")
+            .map(|message| message["params"]["id"].clone())
+            .map(|id| id.to_string())
+            .collect();
+
+        let mut changed = Vec::new();
+        for message in messages {
+            let Some(method) = message["method"].as_str() else {
+                // The server sends no requests, so there are no responses to handle.
+                continue;
+            };
+            let params = &message["params"];
+            match message.get("id") {
+                Some(id) => {
+                    let response = if cancelled.contains(&id.to_string()) {
+                        Err((rpc::REQUEST_CANCELLED, "request cancelled".to_owned()))
+                    } else {
+                        self.request(method, params)
+                    };
+                    let response = match response {
+                        Ok(result) => rpc::response(id, result),
+                        Err((code, text)) => rpc::error_response(id, code, &text),
+                    };
+                    rpc::write(&mut self.out, &response)?;
+                }
+                None if method == "exit" => {
+                    let code = if self.shut_down {
+                        ExitCode::SUCCESS
+                    } else {
+                        ExitCode::FAILURE
+                    };
+                    return Ok(Some(code));
+                }
+                None => {
+                    if let Some(uri) = self.notification(method, params)? {
+                        changed.push(uri);
+                    }
+                }
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+        for uri in changed {
+            self.publish_diagnostics(&uri)?;
+        }
+        Ok(None)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Response {
+        if self.shut_down {
+            return Err((rpc::INVALID_REQUEST, "the server was shut down".to_owned()));
+        }
+        if method == "initialize" {
+            self.initialized = true;
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1 },
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "codeActionProvider": true,
+                },
+                "serverInfo": { "name": "matrix-lsp", "version": env!("CARGO_PKG_VERSION") },
+            }));
+        }
+        if !self.initialized {
+            return Err((
+                rpc::SERVER_NOT_INITIALIZED,
+                "the server wasn't initialized".to_owned(),
+            ));
+        }
+
+        match method {
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/codeAction" => self.code_actions(params),
+            _ => Err((rpc::METHOD_NOT_FOUND, format!("unknown method `{method}`"))),
+        }
+    }
+
+    /// Handle a notification, returning the URI of the document it changed if it did.
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<Option<String>> {
+        let document = &params["textDocument"];
+        let Some(uri) = document["uri"].as_str() else {
+            return Ok(None);
+        };
+        let version = document["version"].as_i64();
+        match method {
+            "textDocument/didOpen" => {
+                let text = document["text"].as_str().unwrap_or_default().to_owned();
+                self.open(uri, Document::new(text, version));
+            }
+            "textDocument/didChange" => {
+                // Changes are whole documents, so only the last one matters.
+                let changes = params["contentChanges"].as_array();
+                let Some(text) = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return Ok(None);
+                };
+                self.open(uri, Document::new(text.to_owned(), version));
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.analyzing.lock().unwrap().remove(uri);
+                let params = json!({ "uri": uri, "diagnostics": [] });
+                let notification = rpc::notification("textDocument/publishDiagnostics", params);
+                rpc::write(&mut self.out, &notification)?;
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(uri.to_owned()))
+    }
+
+    /// The open document and offset a request's parameters are at.
+    fn document_at(&mut self, params: &Value) -> Result<(&mut Document, usize), (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(invalid_params)?;
+        let document = self
+            .documents
+            .get_mut(uri)
+            .ok_or_else(|| (rpc::INVALID_PARAMS, format!("`{uri}` isn't open")))?;
+        let offset = document
+            .offset(&params["position"])
+            .ok_or_else(invalid_params)?;
+        Ok((document, offset))
+    }
+
+    fn definition(&mut self, params: &Value) -> Response {
+        let uri = params["textDocument"]["uri"].clone();
+        let (document, offset) = self.document_at(params)?;
+        let location = document
+            .analysis()
+            .ok_or_else(content_modified)?
+            .definition(offset)
+            .map(|span| json!({ "uri": uri, "range": document.range(span) }));
+        Ok(location.unwrap_or(Value::Null))
+    }
+
+    fn hover(&mut self, params: &Value) -> Response {
+        let (document, offset) = self.document_at(params)?;
+        let analysis = document.analysis().ok_or_else(content_modified)?;
+        let hover = analysis.hover(offset).map(|(span, markdown)| {
+            json!({
+                "contents": { "kind": "markdown", "value": markdown },
+                "range": document.range(span),
+            })
+        });
+        Ok(hover.unwrap_or(Value::Null))
+    }
+
+    fn document_symbols(&mut self, params: &Value) -> Response {
+        fn symbol(document: &Document, item: &DocumentSymbol) -> Value {
+            let kind = match item.kind {
+                SymbolKind::Module => 2,
+                SymbolKind::Proc => 12,
+                SymbolKind::Const => 14,
+            };
+            let children: Vec<_> = item
+                .children
+                .iter()
+                .map(|child| symbol(document, child))
+                .collect();
+            json!({
+                "name": item.name,
+                "kind": kind,
+                "range": document.range(item.span),
+                "selectionRange": document.range(item.name_span),
+                "children": children,
+            })
+        }
+
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(invalid_params)?;
+        let document = self
+            .documents
+            .get_mut(uri)
+            .ok_or_else(|| (rpc::INVALID_PARAMS, format!("`{uri}` isn't open")))?;
+        let symbols = document.analysis().ok_or_else(content_modified)?.symbols();
+        let symbols: Vec<_> = symbols.iter().map(|s| symbol(document, s)).collect();
+        Ok(Value::Array(symbols))
+    }
+
+    /// The quick fixes suggested by the diagnostics in a range.
+    fn code_actions(&mut self, params: &Value) -> Response {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(invalid_params)?;
+        let document = self
+            .documents
+            .get_mut(uri)
+            .ok_or_else(|| (rpc::INVALID_PARAMS, format!("`{uri}` isn't open")))?;
+        let range = &params["range"];
+        let start = document
+            .offset(&range["start"])
+            .ok_or_else(invalid_params)?;
+        let end = document.offset(&range["end"]).ok_or_else(invalid_params)?;
+        let suggestions = document
+            .analysis()
+            .ok_or_else(content_modified)?
+            .suggestions(Span::from(start..end));
+        let actions: Vec<_> = suggestions
+            .into_iter()
+            .map(|(diagnostic, suggestion)| {
+                let mut title = suggestion.to_string();
+                title[..1].make_ascii_uppercase();
+                let edit = json!({
+                    "range": document.range(suggestion.span),
+                    "newText": suggestion.replacement,
+                });
+                json!({
+                    "title": title,
+                    "kind": "quickfix",
+                    "diagnostics": [{
+                        "range": document.range(diagnostic.span),
+                        "code": diagnostic.code,
+                        "source": "matrix",
+                        "message": diagnostic.message,
+                    }],
+                    "isPreferred": suggestion.is_machine_applicable(),
+                    "edit": { "changes": { uri: [edit] } },
+                })
+            })
+            .collect();
+        Ok(Value::Array(actions))
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let Some(document) = self.documents.get_mut(uri) else {
+            return Ok(());
+        };
+        let Some(analysis) = document.analysis() else {
+            return Ok(());
+        };
+        let diagnostics: Vec<_> = analysis
+            .diagnostics
+            .clone()
+            .into_iter()
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                    Severity::Advice => 3,
+                };
+                let related: Vec<_> = diagnostic
+                    .related
+                    .iter()
+                    .map(|(span, message)| {
+                        json!({
+                            "location": { "uri": uri, "range": document.range(*span) },
+                            "message": message,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "range": document.range(diagnostic.span),
+                    "severity": severity,
+                    "code": diagnostic.code,
+                    "source": "matrix",
+                    "message": diagnostic.message,
+                    "relatedInformation": related,
+                })
+            })
+            .collect();
+
+        let params = json!({ "uri": uri, "version": document.version, "diagnostics": diagnostics });
+        let notification = rpc::notification("textDocument/publishDiagnostics", params);
+        rpc::write(&mut self.out, &notification)
+    }
+}
+
+fn main() -> ExitCode {
+    let (sender, receiver) = mpsc::channel();
+    let analyzing = Analyzing::default();
+    let reader = Arc::clone(&analyzing);
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            match rpc::read(&mut stdin) {
+                Ok(Some(message)) => {
+                    cancel_analysis(&reader, &message);
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("matrix-lsp: can't read a message: {error}");
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut server = Server::new(io::stdout().lock(), analyzing);
+    // The client closing the input without `exit` ends the server as if it had sent it.
+    while let Ok(message) = receiver.recv() {
+        let messages = std::iter::once(message)
+            .chain(receiver.try_iter())
+            .collect();
+        match server.handle(messages) {
+            Ok(None) => {}
+            Ok(Some(code)) => return code,
+            Err(error) => {
+                eprintln!("matrix-lsp: can't write a message: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    ExitCode::FAILURE
+}
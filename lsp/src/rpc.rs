@@ -0,0 +1,105 @@
+//! JSON-RPC messages, framed by a `Content-Length` header as the Language Server Protocol
+//! sends them over standard I/O.
+
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+/// Error code for a request the server has no method for.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Error code for a request that isn't valid at this point, like one after `shutdown`.
+pub const INVALID_REQUEST: i64 = -32600;
+
+/// Error code for a request whose parameters don't have the fields its method needs.
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// Error code for a request sent before `initialize`.
+pub const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// Error code for a request the client cancelled before it was handled.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// Error code for a request about a document that changed while it was handled.
+pub const CONTENT_MODIFIED: i64 = -32801;
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Read a message, or return `None` at the end of the input.
+pub fn read(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let content_length = header
+            .split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"));
+        if let Some((_, value)) = content_length {
+            length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("a message has no `Content-Length`"))?;
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+/// Write a message.
+pub fn write(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    writer.flush()
+}
+
+/// A successful response to a request.
+pub fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// A response to a request that failed.
+pub fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_written_messages() -> io::Result<()> {
+        let first = response(&json!(1), json!({ "text": "ü" }));
+        let second = notification("exit", Value::Null);
+        let mut framed = Vec::new();
+        write(&mut framed, &first)?;
+        write(&mut framed, &second)?;
+
+        let mut reader = &framed[..];
+        assert_eq!(read(&mut reader)?, Some(first));
+        assert_eq!(read(&mut reader)?, Some(second));
+        assert_eq!(read(&mut reader)?, None);
+
+        let mut truncated = &b"Content-Length: 10\r\n\r\n{}"[..];
+        assert!(read(&mut truncated).is_err());
+
+        Ok(())
+    }
+}
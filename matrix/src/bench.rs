@@ -0,0 +1,87 @@
+//! Statistics over the times of benchmarks' runs, and the table comparing them, for `bench`.
+
+use std::{fmt::Write, time::Duration};
+
+/// Statistics over the times of a benchmark's runs, in seconds.
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+
+    /// The sample standard deviation, or 0 for a single run.
+    pub stddev: f64,
+    pub samples: usize,
+}
+
+impl Stats {
+    /// Take statistics over at least one run's time.
+    pub fn new(times: &[Duration]) -> Self {
+        let mut seconds: Vec<_> = times.iter().map(Duration::as_secs_f64).collect();
+        seconds.sort_by(f64::total_cmp);
+
+        let n = seconds.len();
+        let mean = seconds.iter().sum::<f64>() / n as f64;
+        let median = if n % 2 == 0 {
+            (seconds[n / 2 - 1] + seconds[n / 2]) / 2.0
+        } else {
+            seconds[n / 2]
+        };
+        let variance = if n > 1 {
+            seconds.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64
+        } else {
+            0.0
+        };
+
+        Self {
+            mean,
+            median,
+            stddev: variance.sqrt(),
+            samples: n,
+        }
+    }
+}
+
+/// A time in the largest unit it's at least one of, from nanoseconds to seconds.
+fn time(seconds: f64) -> String {
+    let (value, unit) = match seconds {
+        s if s >= 1.0 => (s, "s"),
+        s if s >= 1e-3 => (s * 1e3, "ms"),
+        s if s >= 1e-6 => (s * 1e6, "µs"),
+        s => (s * 1e9, "ns"),
+    };
+    format!("{value:.2} {unit}")
+}
+
+/// A table of benchmarks' statistics, comparing each mean to the fastest one.
+pub fn table(results: &[(String, Stats)]) -> String {
+    let fastest = results
+        .iter()
+        .map(|(_, stats)| stats.mean)
+        .fold(f64::INFINITY, f64::min);
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["benchmark".len()])
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:<width$}  {:>12}  {:>12}  {:>12}  {:>8}  {:>8}",
+        "benchmark", "mean", "median", "stddev", "relative", "samples"
+    )
+    .unwrap();
+    for (name, stats) in results {
+        writeln!(
+            out,
+            "{name:<width$}  {:>12}  {:>12}  {:>12}  {:>8}  {:>8}",
+            time(stats.mean),
+            time(stats.median),
+            time(stats.stddev),
+            format!("{:.2}x", stats.mean / fastest),
+            stats.samples
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,230 @@
+//! Configs setting the defaults of mtxc's flags for the programs in a directory and the
+//! directories in it. A config is a `.matrix.toml`, or the `[tool.matrix]` table of a project's
+//! `matrix.toml`:
+//!
+//! ```toml
+//! warnings = "deny"            # `--warnings`
+//! deny = ["no_effect"]         # `--deny`
+//! allow = ["unreachable_arm"]  # `--allow`
+//! overflow = "wrapping"        # `--overflow`
+//! error-limit = 50             # `--error-limit`
+//! message-format = "human"     # `--message-format`
+//! color = "never"              # `--color`
+//! diagnostic-style = "ariadne" # `--diagnostic-style`
+//! locale = "fr"                # `--locale`
+//! ```
+//!
+//! Every key can be left out, and flags given on the command line override the config's.
+
+use crate::{manifest, parse_lint, parse_overflow, Cli, STDIN_PATH};
+use clap::{parser::ValueSource, ArgMatches, ValueEnum};
+use miette::{IntoDiagnostic, LabeledSpan, NamedSource};
+use serde::Deserialize;
+use std::{env, fs, path::Path};
+use toml::Spanned;
+
+/// The name of a config of its own, rather than in a project's manifest.
+pub const FILE_NAME: &str = ".matrix.toml";
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    warnings: Option<Spanned<String>>,
+    deny: Option<Vec<Spanned<String>>>,
+    allow: Option<Vec<Spanned<String>>>,
+    overflow: Option<Spanned<String>>,
+    error_limit: Option<usize>,
+    message_format: Option<Spanned<String>>,
+    color: Option<Spanned<String>>,
+    diagnostic_style: Option<Spanned<String>>,
+    locale: Option<String>,
+
+    /// The path and text of the file the config was read from, for pointing errors into it.
+    #[serde(skip)]
+    source: Option<(String, String)>,
+}
+
+impl Config {
+    /// Read the config closest to a program's path: the one in its directory or the closest
+    /// of its parents with one, a `.matrix.toml` there coming before a `matrix.toml`. Without
+    /// a path, like for the standard input, it's found from the current directory instead.
+    /// Without any config, nothing is changed.
+    pub fn find(path: Option<&Path>) -> miette::Result<Self> {
+        let current = env::current_dir().into_diagnostic()?;
+        let path = match path {
+            Some(path) if path != Path::new(STDIN_PATH) => current.join(path),
+            _ => current,
+        };
+        let start = if path.is_dir() {
+            &path
+        } else {
+            path.parent().unwrap_or(&path)
+        };
+
+        for dir in start.ancestors() {
+            let own = dir.join(FILE_NAME);
+            if own.is_file() {
+                let text = fs::read_to_string(&own).into_diagnostic()?;
+                return Self::parse(&own, text);
+            }
+            if dir.join(manifest::FILE_NAME).is_file() {
+                if let Some(config) = manifest::Manifest::read(dir)?.config {
+                    return Ok(config);
+                }
+            }
+        }
+        Ok(Self::default())
+    }
+
+    /// Read a `.matrix.toml`.
+    fn parse(path: &Path, text: String) -> miette::Result<Self> {
+        let mut config: Self = toml::from_str(&text).map_err(|error| {
+            let labels = error.span().map(|span| LabeledSpan::at(span, "here"));
+            miette::miette!(
+                labels = labels.into_iter().collect::<Vec<_>>(),
+                "Invalid config: {}",
+                error.message()
+            )
+            .with_source_code(NamedSource::new(path.display().to_string(), text.clone()))
+        })?;
+        config.source = Some((path.display().to_string(), text));
+        Ok(config)
+    }
+
+    /// Note the manifest a config was read from, for pointing errors into it.
+    pub fn with_source(mut self, path: &Path, text: &str) -> Self {
+        self.source = Some((path.display().to_string(), text.to_owned()));
+        self
+    }
+
+    /// Read one of the config's values as its flag reads it.
+    fn value<T>(
+        &self,
+        key: &str,
+        value: &Spanned<String>,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> miette::Result<T> {
+        parse(value.get_ref()).map_err(|error| {
+            let error = miette::miette!(
+                labels = vec![LabeledSpan::at(value.span(), "this value")],
+                "Invalid config: `{key}` is invalid: {error}"
+            );
+            match &self.source {
+                Some((name, text)) => error.with_source_code(NamedSource::new(name, text.clone())),
+                None => error,
+            }
+        })
+    }
+
+    /// Set the flags that weren't given on the command line to the config's values.
+    pub fn apply(&self, args: &mut Cli, matches: &ArgMatches) -> miette::Result<()> {
+        fn value_enum<T: ValueEnum>(value: &str) -> Result<T, String> {
+            T::from_str(value, false).map_err(|_| {
+                let names: Vec<_> = T::value_variants()
+                    .iter()
+                    .filter_map(|variant| variant.to_possible_value())
+                    .map(|value| value.get_name().to_owned())
+                    .collect();
+                format!("expected one of {}", names.join(", "))
+            })
+        }
+
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let Some(warnings) = self.warnings.as_ref().filter(|_| unset("warnings")) {
+            args.warnings = self.value("warnings", warnings, value_enum)?;
+        }
+        if let Some(deny) = self.deny.as_ref().filter(|_| unset("deny")) {
+            args.deny = deny
+                .iter()
+                .map(|code| self.value("deny", code, parse_lint))
+                .collect::<miette::Result<_>>()?;
+        }
+        if let Some(allow) = self.allow.as_ref().filter(|_| unset("allow")) {
+            args.allow = allow
+                .iter()
+                .map(|code| self.value("allow", code, parse_lint))
+                .collect::<miette::Result<_>>()?;
+        }
+        if let Some(overflow) = self.overflow.as_ref().filter(|_| unset("overflow")) {
+            args.overflow = self.value("overflow", overflow, parse_overflow)?;
+        }
+        if let Some(error_limit) = self.error_limit.filter(|_| unset("error_limit")) {
+            args.error_limit = error_limit;
+        }
+        if let Some(format) = self
+            .message_format
+            .as_ref()
+            .filter(|_| unset("message_format"))
+        {
+            args.message_format = self.value("message-format", format, value_enum)?;
+        }
+        if let Some(color) = self.color.as_ref().filter(|_| unset("color")) {
+            args.color = self.value("color", color, value_enum)?;
+        }
+        if let Some(style) = self
+            .diagnostic_style
+            .as_ref()
+            .filter(|_| unset("diagnostic_style"))
+        {
+            args.diagnostic_style = self.value("diagnostic-style", style, value_enum)?;
+        }
+        if let Some(locale) = self.locale.as_ref().filter(|_| unset("locale")) {
+            args.locale = locale.clone();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `overflow` of the config found for a path.
+    fn overflow(path: &Path) -> miette::Result<Option<String>> {
+        Ok(Config::find(Some(path))?.overflow.map(Spanned::into_inner))
+    }
+
+    #[test]
+    fn test_find_upward() -> miette::Result<()> {
+        let root = tempfile::tempdir().into_diagnostic()?;
+        let nested = root.path().join("a/b");
+        fs::create_dir_all(&nested).into_diagnostic()?;
+        let program = nested.join("main.mtx");
+        assert_eq!(overflow(&program)?, None);
+
+        fs::write(root.path().join(FILE_NAME), "overflow = \"wrapping\"\n").into_diagnostic()?;
+        assert_eq!(overflow(&program)?.as_deref(), Some("wrapping"));
+        assert_eq!(overflow(&nested)?.as_deref(), Some("wrapping"));
+
+        // A manifest without a config doesn't stop the search.
+        let manifest = "[package]\nname = \"b\"\n";
+        fs::write(nested.join(manifest::FILE_NAME), manifest).into_diagnostic()?;
+        assert_eq!(overflow(&program)?.as_deref(), Some("wrapping"));
+
+        // The closest config is read, whichever kind it is.
+        let manifest = "[package]\nname = \"b\"\n\n[tool.matrix]\noverflow = \"saturating\"\n";
+        fs::write(nested.join(manifest::FILE_NAME), manifest).into_diagnostic()?;
+        assert_eq!(overflow(&program)?.as_deref(), Some("saturating"));
+        assert_eq!(overflow(root.path())?.as_deref(), Some("wrapping"));
+
+        // A `.matrix.toml` comes before a manifest in the same directory.
+        fs::write(nested.join(FILE_NAME), "overflow = \"checked\"\n").into_diagnostic()?;
+        assert_eq!(overflow(&program)?.as_deref(), Some("checked"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_invalid_config() -> miette::Result<()> {
+        let root = tempfile::tempdir().into_diagnostic()?;
+        fs::write(root.path().join(FILE_NAME), "overflw = \"wrapping\"\n").into_diagnostic()?;
+        let Err(report) = Config::find(Some(root.path())) else {
+            panic!("the config was read");
+        };
+        let error = report.to_string();
+        assert!(
+            error.starts_with("Invalid config: unknown field `overflw`"),
+            "{error}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Reports of which lines and branches of a program's files ran, for `--coverage`.
+
+use matrix_compiler::Sources;
+use span::Span;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+};
+
+/// How often the lines and branches of one of a program's files ran.
+struct FileCoverage {
+    name: String,
+
+    /// How many times each line with code on it ran, by line number from 1. A line ran as
+    /// many times as the instruction from it that ran the most.
+    lines: BTreeMap<usize, u64>,
+
+    /// The line of each condition, with how many times it jumped and how many times it fell
+    /// through, in the order they're in the file.
+    branches: Vec<(usize, u64, u64)>,
+}
+
+impl FileCoverage {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&count| count > 0).count()
+    }
+
+    /// Each branch counts as two, one for each way it can go.
+    fn branches_hit(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|&(_, taken, not_taken)| usize::from(taken > 0) + usize::from(not_taken > 0))
+            .sum()
+    }
+}
+
+/// The coverage of every file of a program.
+pub struct Report {
+    files: Vec<FileCoverage>,
+}
+
+impl Report {
+    /// Sort what ran in some bytecode into the lines of the program's files it was compiled
+    /// from.
+    pub fn new(sources: &Sources, coverage: &vm::Coverage<'_>) -> Self {
+        let files: Vec<_> = sources.files().collect();
+        let lines: Vec<_> = files
+            .iter()
+            .map(|file| vm::debug::Lines::new(file.text))
+            .collect();
+        let mut report = Self {
+            files: files
+                .iter()
+                .map(|file| FileCoverage {
+                    name: file.name.to_owned(),
+                    lines: BTreeMap::new(),
+                    branches: Vec::new(),
+                })
+                .collect(),
+        };
+
+        // The file an offset is in and its line there.
+        let locate = |offset: usize| {
+            if offset > sources.text().len() {
+                return None;
+            }
+            let full = sources.locate(Span::from(offset..offset));
+            let (line, _) = lines[full.file.index()].locate(full.span.start);
+            Some((full.file.index(), line))
+        };
+        for (span, count) in coverage.instrs() {
+            if let Some((index, line)) = locate(span.start) {
+                let hits = report.files[index].lines.entry(line).or_default();
+                *hits = (*hits).max(count);
+            }
+        }
+        for branch in coverage.branches() {
+            if let Some((index, line)) = locate(branch.span.start) {
+                let branches = &mut report.files[index].branches;
+                branches.push((line, branch.taken, branch.not_taken));
+            }
+        }
+        report
+    }
+
+    /// The report as an lcov tracefile, which tools like `genhtml` turn into HTML pages.
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for file in &self.files {
+            writeln!(lcov, "TN:\nSF:{}", file.name).unwrap();
+            for (block, &(line, taken, not_taken)) in file.branches.iter().enumerate() {
+                // A branch whose condition never ran is `-` rather than 0 either way.
+                let ran = taken + not_taken > 0;
+                for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                    let count = if ran {
+                        count.to_string()
+                    } else {
+                        "-".to_owned()
+                    };
+                    writeln!(lcov, "BRDA:{line},{block},{branch},{count}").unwrap();
+                }
+            }
+            writeln!(
+                lcov,
+                "BRF:{}\nBRH:{}",
+                file.branches.len() * 2,
+                file.branches_hit()
+            )
+            .unwrap();
+            for (line, count) in &file.lines {
+                writeln!(lcov, "DA:{line},{count}").unwrap();
+            }
+            writeln!(
+                lcov,
+                "LF:{}\nLH:{}\nend_of_record",
+                file.lines.len(),
+                file.lines_hit()
+            )
+            .unwrap();
+        }
+        lcov
+    }
+}
+
+/// The share of some things that were hit, as a percentage, or `-` if there weren't any.
+fn percent(hit: usize, total: usize) -> String {
+    if total == 0 {
+        "-".to_owned()
+    } else {
+        format!("{:.1}%", hit as f64 * 100.0 / total as f64)
+    }
+}
+
+/// Shows a table of how many lines and branches of each file ran.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .files
+            .iter()
+            .map(|file| file.name.len())
+            .chain(["file".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:<width$}  {:>14}  {:>8}  {:>14}  {:>8}",
+            "file", "lines", "", "branches", ""
+        )?;
+        for file in &self.files {
+            let (lines, branches) = (file.lines.len(), file.branches.len() * 2);
+            let (lines_hit, branches_hit) = (file.lines_hit(), file.branches_hit());
+            writeln!(
+                f,
+                "{:<width$}  {:>14}  {:>8}  {:>14}  {:>8}",
+                file.name,
+                format!("{lines_hit}/{lines}"),
+                percent(lines_hit, lines),
+                format!("{branches_hit}/{branches}"),
+                percent(branches_hit, branches)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+//! The `debug` command, which runs a program on the VM a line at a time, stopping at
+//! breakpoints to show where it is, the calls it's in and their variables.
+//!
+//! Breakpoints and lines are found through the bytecode's line tables: a line starts at each
+//! instruction compiled from a different line than the one before it. Variables are the
+//! locals of the procedure a call runs, each of which the VM keeps in the register numbered by
+//! its id.
+
+use crate::editor::{Editor, Line};
+use interp::{RunError, Value};
+use matrix_compiler::sources::{SourceFile, Sources};
+use span::Span;
+use std::io;
+use vm::{debug::Lines, Machine, Step};
+
+/// What `help` prints.
+const HELP: &str = "\
+break FILE:LINE, b     stop whenever the program gets to a line; FILE can be left out for the
+                       first file
+clear FILE:LINE        remove a breakpoint
+breakpoints            list the breakpoints
+step, s                run to the next line, going into calls
+next, n                run to the next line of this call or its callers
+finish                 run until this call returns
+continue, c            run until a breakpoint
+backtrace, bt          list the calls the program is in, innermost first
+frame N, f N           look at the variables of the Nth call of `backtrace`
+print NAME, p NAME     print a variable
+locals                 print every variable declared so far
+quit, q                stop debugging";
+
+/// The commands, for completing them.
+const COMMANDS: &[&str] = &[
+    "break",
+    "clear",
+    "breakpoints",
+    "step",
+    "next",
+    "finish",
+    "continue",
+    "backtrace",
+    "frame",
+    "print",
+    "locals",
+    "help",
+    "quit",
+];
+
+/// A line the program stops at.
+struct Breakpoint {
+    /// The file, by its index in the program's files, and the line in it, from 1.
+    file: usize,
+    line: usize,
+
+    /// The function and offset of every instruction starting the line.
+    starts: Vec<(u32, usize)>,
+}
+
+/// How far running goes before stopping again, unless a breakpoint stops it first.
+#[derive(Clone, Copy)]
+enum Run {
+    /// To another line, or into or out of a call.
+    Step,
+
+    /// To another line, not counting the lines of calls it makes.
+    Next,
+
+    /// Out of the call.
+    Finish,
+
+    /// Only to breakpoints.
+    Continue,
+}
+
+struct Debugger<'a> {
+    sources: &'a Sources,
+    program: &'a hir::Program,
+    bytecode: &'a vm::Bytecode,
+    files: Vec<SourceFile<'a>>,
+    lines: Vec<Lines<'a>>,
+    breakpoints: Vec<Breakpoint>,
+
+    /// The call `print` and `locals` look in, counted from the innermost.
+    frame: usize,
+}
+
+impl<'a> Debugger<'a> {
+    /// The file, line and column an offset into the program's text is at.
+    fn locate(&self, offset: usize) -> Option<(usize, usize, usize)> {
+        if offset > self.sources.text().len() {
+            return None;
+        }
+        let full = self.sources.locate(Span::from(offset..offset));
+        let (line, column) = self.lines[full.file.index()].locate(full.span.start);
+        Some((full.file.index(), line, column))
+    }
+
+    /// The file and line of the instruction at an offset of a function.
+    fn line_at(&self, function: u32, offset: usize) -> Option<(usize, usize)> {
+        let span = self.bytecode.functions[function as usize]
+            .lines
+            .span_at(offset);
+        self.locate(span.start).map(|(file, line, _)| (file, line))
+    }
+
+    /// Where a span starts, as `name:line:column`.
+    fn describe(&self, span: Span) -> String {
+        match self.locate(span.start) {
+            Some((file, line, column)) => format!("{}:{line}:{column}", self.files[file].name),
+            None => "<unknown>".to_owned(),
+        }
+    }
+
+    /// Read a breakpoint's location, `FILE:LINE` or `LINE`, finding the instructions starting
+    /// the line.
+    fn breakpoint(&self, location: &str) -> Result<Breakpoint, String> {
+        let (name, line) = location.rsplit_once(':').unwrap_or(("", location));
+        let line: usize = line
+            .parse()
+            .map_err(|_| format!("`{line}` isn't a line number"))?;
+        let file = if name.is_empty() {
+            0
+        } else {
+            self.files
+                .iter()
+                .position(|file| file.name == name)
+                .ok_or_else(|| format!("the program has no file `{name}`"))?
+        };
+
+        let mut starts = Vec::new();
+        for (i, function) in self.bytecode.functions.iter().enumerate() {
+            let mut previous = None;
+            for offset in 0..function.code.len() {
+                let here = self.line_at(i as u32, offset);
+                if here == Some((file, line)) && here != previous {
+                    starts.push((i as u32, offset));
+                }
+                previous = here;
+            }
+        }
+        if starts.is_empty() {
+            return Err(format!(
+                "there's no code on line {line} of `{}`",
+                self.files[file].name
+            ));
+        }
+        Ok(Breakpoint { file, line, starts })
+    }
+
+    /// Whether the machine is about to run the start of a line with a breakpoint.
+    fn at_breakpoint(&self, machine: &Machine<'_>) -> bool {
+        machine.position().is_some_and(|position| {
+            self.breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.starts.contains(&position))
+        })
+    }
+
+    /// Run the program until it gets as far as asked, or to a breakpoint, or finishes.
+    fn run(&self, machine: &mut Machine<'_>, run: Run) -> Result<Step, RunError> {
+        let depth = machine.depth();
+        let line = machine
+            .position()
+            .and_then(|(function, offset)| self.line_at(function, offset));
+        loop {
+            if let Step::Finished(value) = machine.step()? {
+                return Ok(Step::Finished(value));
+            }
+            if self.at_breakpoint(machine) {
+                return Ok(Step::Running);
+            }
+
+            let here = machine
+                .position()
+                .and_then(|(function, offset)| self.line_at(function, offset));
+            let arrived = match run {
+                Run::Step => machine.depth() != depth || here != line,
+                Run::Next => machine.depth() < depth || (machine.depth() == depth && here != line),
+                Run::Finish => machine.depth() < depth,
+                Run::Continue => false,
+            };
+            if arrived {
+                return Ok(Step::Running);
+            }
+        }
+    }
+
+    /// Show where the program has stopped, with the line it's on.
+    fn show_position(&self, machine: &Machine<'_>) {
+        let (Some(function), Some((_, span))) = (machine.function(), machine.next_instr()) else {
+            return;
+        };
+        println!("stopped in {} at {}", function.name, self.describe(span));
+        if let Some((file, line, _)) = self.locate(span.start) {
+            let text = self.files[file]
+                .text
+                .lines()
+                .nth(line - 1)
+                .unwrap_or_default();
+            println!("{line:>5} | {text}");
+        }
+    }
+
+    /// The variables of a call declared before where it's at, each name's latest declaration
+    /// hiding the ones before it, with their values.
+    fn variables<'m>(&self, frame: &vm::CallFrame<'m>) -> Vec<(&'a str, &'m Value)> {
+        let program = self.program;
+        let proc = &program.procs[frame.function as usize];
+        let mut variables: Vec<(&str, &Value)> = Vec::new();
+        for (i, local) in proc.locals.iter().enumerate() {
+            let param = proc.params.iter().any(|param| param.0 as usize == i);
+            if !param && local.span.start > frame.span.start {
+                continue;
+            }
+            variables.retain(|(name, _)| *name != local.name);
+            variables.push((&local.name, &frame.registers[i]));
+        }
+        variables
+    }
+
+    /// Run a command, returning how far to run the program if it runs it.
+    fn command(&mut self, machine: &Machine<'_>, input: &str) -> Option<Run> {
+        let mut words = input.split_whitespace();
+        let (command, argument) = (words.next()?, words.next());
+        let frames = machine.frames();
+        match (command, argument) {
+            ("step" | "s", None) => return Some(Run::Step),
+            ("next" | "n", None) => return Some(Run::Next),
+            ("finish", None) => return Some(Run::Finish),
+            ("continue" | "c", None) => return Some(Run::Continue),
+            ("break" | "b", Some(location)) => match self.breakpoint(location) {
+                Ok(breakpoint) => {
+                    let name = self.files[breakpoint.file].name;
+                    println!("breakpoint at {name}:{}", breakpoint.line);
+                    self.breakpoints.push(breakpoint);
+                }
+                Err(error) => eprintln!("error: {error}"),
+            },
+            ("clear", Some(location)) => match self.breakpoint(location) {
+                Ok(cleared) => self.breakpoints.retain(|breakpoint| {
+                    (breakpoint.file, breakpoint.line) != (cleared.file, cleared.line)
+                }),
+                Err(error) => eprintln!("error: {error}"),
+            },
+            ("breakpoints", None) => {
+                for breakpoint in &self.breakpoints {
+                    let name = self.files[breakpoint.file].name;
+                    println!("{name}:{}", breakpoint.line);
+                }
+            }
+            ("backtrace" | "bt", None) => {
+                for (i, frame) in frames.iter().enumerate() {
+                    let marker = if i == self.frame { '*' } else { ' ' };
+                    let name = &self.bytecode.functions[frame.function as usize].name;
+                    println!("{marker}{i:>3}  {name} at {}", self.describe(frame.span));
+                }
+            }
+            ("frame" | "f", Some(index)) => match index.parse() {
+                Ok(index) if index < frames.len() => {
+                    self.frame = index;
+                    let frame = &frames[index];
+                    let name = &self.bytecode.functions[frame.function as usize].name;
+                    println!("{index:>4}  {name} at {}", self.describe(frame.span));
+                }
+                _ => eprintln!("error: there are {} calls, from 0", frames.len()),
+            },
+            ("print" | "p", Some(name)) => {
+                let variables = self.variables(&frames[self.frame]);
+                match variables.iter().find(|(variable, _)| *variable == name) {
+                    Some((_, Value::Str(s))) => println!("{name} = {s:?}"),
+                    Some((_, value)) => println!("{name} = {value}"),
+                    None => eprintln!("error: there's no variable `{name}` here"),
+                }
+            }
+            ("locals", None) => {
+                for (name, value) in self.variables(&frames[self.frame]) {
+                    match value {
+                        Value::Str(s) => println!("{name} = {s:?}"),
+                        value => println!("{name} = {value}"),
+                    }
+                }
+            }
+            ("help" | "h", None) => println!("{HELP}"),
+            _ => eprintln!("error: unknown command `{input}`; `help` lists them"),
+        }
+        None
+    }
+
+    /// The commands and variable names starting with a word.
+    fn completions(&self, machine: &Machine<'_>, word: &str) -> Vec<String> {
+        let frames = machine.frames();
+        let variables = frames
+            .get(self.frame)
+            .map(|frame| self.variables(frame))
+            .unwrap_or_default();
+        COMMANDS
+            .iter()
+            .copied()
+            .chain(variables.into_iter().map(|(name, _)| name))
+            .filter(|name| name.starts_with(word))
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Debug a program, reading commands from the standard input, starting with breakpoints at
+/// some locations. Returns how the program finished, or `None` if debugging was quit first.
+pub fn run(
+    sources: &Sources,
+    program: &hir::Program,
+    bytecode: &vm::Bytecode,
+    breakpoints: &[String],
+    options: interp::Options,
+) -> miette::Result<Option<Result<Value, RunError>>> {
+    let files: Vec<_> = sources.files().collect();
+    let lines = files.iter().map(|file| Lines::new(file.text)).collect();
+    let mut debugger = Debugger {
+        sources,
+        program,
+        bytecode,
+        files,
+        lines,
+        breakpoints: Vec::new(),
+        frame: 0,
+    };
+    for location in breakpoints {
+        let breakpoint = debugger
+            .breakpoint(location)
+            .map_err(|error| miette::miette!("Can't set a breakpoint at `{location}`: {error}"))?;
+        debugger.breakpoints.push(breakpoint);
+    }
+
+    // The program reads nothing, since its standard input is where commands come from.
+    let (mut input, mut output) = (io::empty(), io::stdout().lock());
+    let io = interp::Io::new(&mut input, &mut output);
+    let mut machine = match Machine::new(bytecode, io, options) {
+        Ok(machine) => machine,
+        Err(error) => return Ok(Some(Err(error))),
+    };
+    let mut editor = Editor::new();
+    debugger.show_position(&machine);
+    loop {
+        let line = editor
+            .read_line("(debug) ", |word| debugger.completions(&machine, word))
+            .map_err(|error| miette::miette!("Cannot read input: {error}"))?;
+        let input = match line {
+            Line::Entered(input) => input,
+            Line::Interrupted => continue,
+            Line::Eof => return Ok(None),
+        };
+        editor.add_history(&input);
+        if matches!(input.trim(), "quit" | "q") {
+            return Ok(None);
+        }
+
+        let Some(run) = debugger.command(&machine, &input) else {
+            continue;
+        };
+        match debugger.run(&mut machine, run) {
+            Ok(Step::Running) => {
+                debugger.frame = 0;
+                debugger.show_position(&machine);
+            }
+            Ok(Step::Finished(value)) => {
+                println!("finished, returning {value}");
+                return Ok(Some(Ok(value)));
+            }
+            Err(error) => return Ok(Some(Err(error))),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Line editing for the REPL on a terminal: moving around and editing the line, recalling
+//! earlier lines from a history kept between sessions, and completing names with tab. When the
+//! standard input isn't a terminal, lines are read as they are.
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::PathBuf,
+};
+
+/// How many lines the history keeps, forgetting the oldest past it.
+const HISTORY_LIMIT: usize = 1000;
+
+/// What reading a line ended with.
+pub enum Line {
+    /// A line that was entered, without its line break.
+    Entered(String),
+
+    /// Ctrl-C, which drops the line and any input it continues.
+    Interrupted,
+
+    /// The end of the input, or Ctrl-D on an empty line.
+    Eof,
+}
+
+/// A key pressed while editing a line.
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    KillToStart,
+    KillToEnd,
+    KillWord,
+    ClearScreen,
+    Interrupt,
+    Eof,
+    Ignored,
+}
+
+/// Reads lines, keeping a history of them.
+pub struct Editor {
+    /// Whether the standard input is a terminal, so lines are edited. Only their history is
+    /// kept.
+    interactive: bool,
+    history: Vec<String>,
+
+    /// The file the history is saved in, if there's a home directory to keep it in.
+    history_path: Option<PathBuf>,
+}
+
+/// The file the history is kept in: `MTXC_HISTORY`, or `.mtxc_history` in the home directory.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("MTXC_HISTORY")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".mtxc_history")))
+}
+
+/// The longest prefix that every candidate starts with.
+fn common_prefix(candidates: &[String]) -> &str {
+    let first = &candidates[0];
+    let len = candidates[1..].iter().fold(first.len(), |len, candidate| {
+        first[..len]
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(candidate.len()), |((i, _), _)| i)
+    });
+    &first[..len]
+}
+
+impl Editor {
+    /// Start editing with the history saved by earlier sessions.
+    pub fn new() -> Self {
+        let interactive = io::stdin().is_terminal();
+        let history_path = interactive.then(history_path).flatten();
+        let mut history: Vec<_> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_owned).collect())
+            .unwrap_or_default();
+        history.drain(..history.len().saturating_sub(HISTORY_LIMIT));
+        Self {
+            interactive,
+            history,
+            history_path,
+        }
+    }
+
+    /// Remember a line that was entered, saving it for later sessions too. Empty lines and
+    /// repeats of the line before aren't kept.
+    pub fn add_history(&mut self, line: &str) {
+        let repeated = self.history.last().is_some_and(|last| last == line);
+        if !self.interactive || line.trim().is_empty() || repeated {
+            return;
+        }
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+        self.history.push(line.to_owned());
+
+        // The history is only a convenience, so failing to save it isn't an error.
+        if let Some(path) = &self.history_path {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            let _ = file.and_then(|mut file| writeln!(file, "{line}"));
+        }
+    }
+
+    /// Read a line after a prompt. On a terminal, the line can be edited, tab completes the
+    /// name before the cursor with the candidates `complete` gives for it, and up and down go
+    /// through the history.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: impl Fn(&str) -> Vec<String>,
+    ) -> io::Result<Line> {
+        let mut stdout = io::stdout();
+        write!(stdout, "{prompt}")?;
+        stdout.flush()?;
+        if !self.interactive {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Line::Eof);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            return Ok(Line::Entered(
+                line.strip_suffix('\r').unwrap_or(line).to_owned(),
+            ));
+        }
+
+        let _raw = RawMode::enable()?;
+        let mut editing = Editing {
+            prompt,
+            line: Vec::new(),
+            cursor: 0,
+            // The line being entered, past the end of the history, is kept while going
+            // through the history.
+            history_index: self.history.len(),
+            unsent: Vec::new(),
+        };
+        loop {
+            match read_key()? {
+                Key::Char(c) => {
+                    editing.line.insert(editing.cursor, c);
+                    editing.cursor += 1;
+                }
+                Key::Enter => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Line::Entered(editing.line.iter().collect()));
+                }
+                Key::Backspace if editing.cursor > 0 => {
+                    editing.cursor -= 1;
+                    editing.line.remove(editing.cursor);
+                }
+                Key::Delete if editing.cursor < editing.line.len() => {
+                    editing.line.remove(editing.cursor);
+                }
+                Key::Left => editing.cursor = editing.cursor.saturating_sub(1),
+                Key::Right => editing.cursor = (editing.cursor + 1).min(editing.line.len()),
+                Key::Home => editing.cursor = 0,
+                Key::End => editing.cursor = editing.line.len(),
+                Key::Up if editing.history_index > 0 => {
+                    if editing.history_index == self.history.len() {
+                        editing.unsent = editing.line.clone();
+                    }
+                    editing.history_index -= 1;
+                    editing.set_line(self.history[editing.history_index].chars().collect());
+                }
+                Key::Down if editing.history_index < self.history.len() => {
+                    editing.history_index += 1;
+                    let line = match self.history.get(editing.history_index) {
+                        Some(line) => line.chars().collect(),
+                        None => editing.unsent.clone(),
+                    };
+                    editing.set_line(line);
+                }
+                Key::Tab => editing.complete(&complete, &mut stdout)?,
+                Key::KillToStart => {
+                    editing.line.drain(..editing.cursor);
+                    editing.cursor = 0;
+                }
+                Key::KillToEnd => editing.line.truncate(editing.cursor),
+                Key::KillWord => {
+                    let start = editing.word_start(|c| !c.is_whitespace(), true);
+                    editing.line.drain(start..editing.cursor);
+                    editing.cursor = start;
+                }
+                Key::ClearScreen => write!(stdout, "\x1b[H\x1b[2J")?,
+                Key::Interrupt => {
+                    write!(stdout, "^C\r\n")?;
+                    return Ok(Line::Interrupted);
+                }
+                Key::Eof if editing.line.is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Line::Eof);
+                }
+                Key::Eof if editing.cursor < editing.line.len() => {
+                    editing.line.remove(editing.cursor);
+                }
+                _ => {}
+            }
+            editing.refresh(&mut stdout)?;
+        }
+    }
+}
+
+/// A line being edited.
+struct Editing<'a> {
+    prompt: &'a str,
+    line: Vec<char>,
+
+    /// Where the cursor is in the line, in chars.
+    cursor: usize,
+
+    /// Which line of the history is shown, or the history's length for the line being entered.
+    history_index: usize,
+
+    /// The line that was being entered before going back through the history.
+    unsent: Vec<char>,
+}
+
+impl Editing<'_> {
+    /// Replace the line, with the cursor at its end.
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    /// Where the word before the cursor starts, given the chars words are made of. With
+    /// `skip_spaces`, spaces just before the cursor are part of the word.
+    fn word_start(&self, in_word: impl Fn(char) -> bool, skip_spaces: bool) -> usize {
+        let before = &self.line[..self.cursor];
+        let end = if skip_spaces {
+            before
+                .iter()
+                .rposition(|c| !c.is_whitespace())
+                .map_or(0, |i| i + 1)
+        } else {
+            before.len()
+        };
+        before[..end]
+            .iter()
+            .rposition(|&c| !in_word(c))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Complete the name before the cursor: with the only candidate, or as far as every
+    /// candidate agrees, or else by listing them.
+    fn complete(
+        &mut self,
+        complete: impl Fn(&str) -> Vec<String>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let start = self.word_start(|c| c.is_alphanumeric() || c == '_' || c == ':', false);
+        let word: String = self.line[start..self.cursor].iter().collect();
+        let mut candidates: Vec<_> = complete(&word)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&word))
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        if candidates.is_empty() {
+            return write!(out, "\x07");
+        }
+
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > word.len() {
+            let rest: Vec<_> = prefix[word.len()..].chars().collect();
+            let at = self.cursor;
+            self.cursor += rest.len();
+            self.line.splice(at..at, rest);
+        } else if candidates.len() > 1 {
+            write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+        }
+        Ok(())
+    }
+
+    /// Draw the prompt and the line over the terminal's current line, with the cursor where
+    /// it is in the line.
+    fn refresh(&self, out: &mut impl Write) -> io::Result<()> {
+        let line: String = self.line.iter().collect();
+        write!(out, "\r{}{line}\x1b[K", self.prompt)?;
+        let after = self.line.len() - self.cursor;
+        if after > 0 {
+            write!(out, "\x1b[{after}D")?;
+        }
+        out.flush()
+    }
+}
+
+/// Read a byte of the standard input.
+fn read_byte() -> io::Result<u8> {
+    let mut byte = [0];
+    io::stdin().lock().read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Read the next key pressed, decoding escape sequences and UTF-8.
+fn read_key() -> io::Result<Key> {
+    let byte = match read_byte() {
+        Ok(byte) => byte,
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(Key::Eof),
+        Err(error) => return Err(error),
+    };
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillToEnd,
+        0x0c => Key::ClearScreen,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape()?,
+        byte if byte < 0x20 => Key::Ignored,
+        byte => {
+            // The length of a UTF-8 sequence is the number of leading ones of its first byte.
+            let mut bytes = vec![byte];
+            for _ in 1..byte.leading_ones() {
+                bytes.push(read_byte()?);
+            }
+            std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|text| text.chars().next())
+                .map_or(Key::Ignored, Key::Char)
+        }
+    })
+}
+
+/// Read the rest of an escape sequence, like the `[A` of the up arrow.
+fn read_escape() -> io::Result<Key> {
+    let introducer = read_byte()?;
+    if introducer != b'[' && introducer != b'O' {
+        return Ok(Key::Ignored);
+    }
+
+    // Parameters come before a final byte from `@` to `~`.
+    let mut parameters = Vec::new();
+    let last = loop {
+        let byte = read_byte()?;
+        if (0x40..=0x7e).contains(&byte) {
+            break byte;
+        }
+        parameters.push(byte);
+    };
+    Ok(match (last, &parameters[..]) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', b"1" | b"7") => Key::Home,
+        (b'F', _) | (b'~', b"4" | b"8") => Key::End,
+        (b'~', b"3") => Key::Delete,
+        _ => Key::Ignored,
+    })
+}
+
+/// The terminal in raw mode, reading each key as it's pressed without echoing it, until it's
+/// dropped.
+struct RawMode {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    fn enable() -> io::Result<Self> {
+        // SAFETY: `tcgetattr` fills in the whole struct, and it's only read if that succeeded.
+        let original = unsafe {
+            let mut termios = std::mem::MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+
+        let mut raw = original;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid `termios`, copied from the one the terminal had.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { original })
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> io::Result<Self> {
+        Ok(Self {})
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: `original` is the `termios` the terminal had before raw mode.
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+        }
+    }
+}
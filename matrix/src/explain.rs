@@ -0,0 +1,62 @@
+//! The registry of every crate's diagnostic codes and their extended explanations, for
+//! `explain`.
+
+use std::fmt::Write;
+
+/// Each crate's explanations, with the number its short codes start after. Every crate has a
+/// hundred short codes to itself, so adding a diagnostic doesn't renumber another crate's.
+const CRATES: &[(usize, &[(&str, &str)])] = &[
+    (0, lexer::EXPLANATIONS),
+    (100, parser::EXPLANATIONS),
+    (200, hir::EXPLANATIONS),
+    (300, interp::EXPLANATIONS),
+    (400, vm::EXPLANATIONS),
+    (500, codegen_x86::EXPLANATIONS),
+    #[cfg(feature = "llvm")]
+    (600, codegen_llvm::EXPLANATIONS),
+];
+
+/// A diagnostic code with its short code and explanation.
+pub struct Entry {
+    pub code: &'static str,
+    pub short: String,
+    pub explanation: &'static str,
+}
+
+/// Every code, in the order of their short codes.
+pub fn entries() -> impl Iterator<Item = Entry> {
+    CRATES.iter().flat_map(|&(base, explanations)| {
+        explanations
+            .iter()
+            .enumerate()
+            .map(move |(index, &(code, explanation))| Entry {
+                code,
+                short: format!("E{:04}", base + index + 1),
+                explanation,
+            })
+    })
+}
+
+/// The entry of a code, written in full like `lexer::unterminated_string_literal` or short like
+/// `E0003`, in any case.
+pub fn lookup(code: &str) -> Option<Entry> {
+    entries().find(|entry| {
+        entry.code.eq_ignore_ascii_case(code) || entry.short.eq_ignore_ascii_case(code)
+    })
+}
+
+/// A code's explanation, under a heading naming it.
+pub fn explanation(entry: &Entry) -> String {
+    format!(
+        "{} ({})\n\n{}\n",
+        entry.code, entry.short, entry.explanation
+    )
+}
+
+/// Every code with its short code, one per line.
+pub fn list() -> String {
+    entries().fold(String::new(), |mut list, entry| {
+        let _ = writeln!(list, "{}  {}", entry.short, entry.code);
+        list
+    })
+}
@@ -0,0 +1,121 @@
+//! Collecting the fixes a program's diagnostics suggest, and applying them to its files, for
+//! `fix`.
+
+use diagnostics::{Stage, Suggestion};
+use matrix_compiler::sources::{SourceFile, Sources};
+use span::Span;
+use std::fmt;
+
+/// How many times a program is checked and fixed before giving up. Fixing can reveal more to
+/// fix, like the parser finding the next missing semicolon in an item once the first is added.
+pub const MAX_PASSES: usize = 100;
+
+/// The fixes a program's diagnostics suggest that can be applied without judgement, with spans
+/// into its text. Files that don't lex have none, and the program is only type checked if every
+/// file parses.
+pub fn suggestions(sources: &Sources) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    let mut items = Vec::new();
+    let mut parsed = true;
+    for file in sources.files() {
+        let Ok(mut tokens) = lexer::lex(file.text) else {
+            parsed = false;
+            continue;
+        };
+        for token in &mut tokens {
+            token.span.start += file.start;
+            token.span.end += file.start;
+        }
+        match parser::parse(sources.text(), tokens) {
+            Ok(ast) => items.extend(ast.items),
+            Err(diagnostics) => {
+                parsed = false;
+                suggestions.extend(diagnostics.suggestions());
+            }
+        }
+    }
+    if parsed {
+        let diagnostics = match hir::lower(&parser::ast::Program { items }) {
+            Ok(lowered) => lowered.warnings,
+            Err(diagnostics) => diagnostics.into_diagnostics(),
+        };
+        suggestions.extend(diagnostics.iter().filter_map(Stage::suggestion));
+    }
+    suggestions.retain(Suggestion::is_machine_applicable);
+    suggestions
+}
+
+/// A fix applied to a file.
+pub struct Fix {
+    /// The line and column it was made at, from 1, in the text it was applied to.
+    pub line: usize,
+    pub column: usize,
+
+    /// The text it replaced, which is empty for an insertion.
+    pub replaced: String,
+    pub replacement: String,
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        if self.replaced.is_empty() {
+            write!(f, "insert `{}`", self.replacement)
+        } else {
+            write!(f, "replace `{}` with `{}`", self.replaced, self.replacement)
+        }
+    }
+}
+
+/// A file's text with fixes applied.
+pub struct Fixed {
+    pub text: String,
+    pub fixes: Vec<Fix>,
+
+    /// How many fixes were left out because they overlap one that was applied.
+    pub conflicts: usize,
+}
+
+/// Apply the fixes that fall in a file. Fixes are applied from its start, and any that overlaps
+/// one before it, or inserts at the same place, is left out rather than guessing how they
+/// combine.
+pub fn apply(file: &SourceFile<'_>, suggestions: &[Suggestion]) -> Fixed {
+    let whole = Span::from(file.start..file.start + file.text.len());
+    let mut suggestions: Vec<_> = suggestions
+        .iter()
+        .filter(|suggestion| whole.contains(suggestion.span))
+        .collect();
+    suggestions.sort_by_key(|suggestion| (suggestion.span.start, suggestion.span.end));
+    suggestions.dedup();
+
+    let mut fixed = Fixed {
+        text: String::with_capacity(file.text.len()),
+        fixes: Vec::new(),
+        conflicts: 0,
+    };
+    let mut previous: Option<Span> = None;
+    let mut copied = 0;
+    for suggestion in suggestions {
+        let span = Span::from(suggestion.span.start - file.start..suggestion.span.end - file.start);
+        if previous
+            .is_some_and(|previous| span.start < previous.end || span.start == previous.start)
+        {
+            fixed.conflicts += 1;
+            continue;
+        }
+
+        let (line, column) = file.line_column(span.start);
+        fixed.fixes.push(Fix {
+            line,
+            column,
+            replaced: file.text[span.start..span.end].to_owned(),
+            replacement: suggestion.replacement.clone(),
+        });
+        fixed.text.push_str(&file.text[copied..span.start]);
+        fixed.text.push_str(&suggestion.replacement);
+        copied = span.end;
+        previous = Some(span);
+    }
+    fixed.text.push_str(&file.text[copied..]);
+    fixed
+}
@@ -0,0 +1,93 @@
+//! Source highlighted by the semantic classification of its tokens, for `highlight`: with ANSI
+//! escapes for a terminal, or as HTML for documentation. Text between tokens is kept as it is.
+
+use hir::{SemanticToken, SemanticTokenKind};
+
+/// Write a source with each of its classified tokens wrapped by `wrap`, and the text around
+/// them by `plain`.
+fn write(
+    source: &str,
+    tokens: &[SemanticToken],
+    mut plain: impl FnMut(&mut String, &str),
+    mut wrap: impl FnMut(&mut String, SemanticTokenKind, &str),
+) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut end = 0;
+    for token in tokens {
+        plain(&mut out, &source[end..token.span.start]);
+        wrap(
+            &mut out,
+            token.kind,
+            &source[token.span.start..token.span.end],
+        );
+        end = token.span.end;
+    }
+    plain(&mut out, &source[end..]);
+    out
+}
+
+/// The SGR parameters a kind of token is shown with, or `None` if it's left as it is.
+fn ansi_style(kind: SemanticTokenKind) -> Option<&'static str> {
+    Some(match kind {
+        SemanticTokenKind::Keyword => "35",
+        SemanticTokenKind::Namespace => "36",
+        SemanticTokenKind::Function => "34",
+        SemanticTokenKind::Constant => "33",
+        SemanticTokenKind::Parameter => "3",
+        SemanticTokenKind::Type => "96",
+        SemanticTokenKind::Literal => "32",
+        SemanticTokenKind::Comment => "90",
+        SemanticTokenKind::Local => return None,
+    })
+}
+
+/// Highlight a source with ANSI escapes.
+pub fn ansi(source: &str, tokens: &[SemanticToken]) -> String {
+    write(
+        source,
+        tokens,
+        |out, text| out.push_str(text),
+        |out, kind, text| match ansi_style(kind) {
+            Some(style) => out.push_str(&format!("\x1b[{style}m{text}\x1b[0m")),
+            None => out.push_str(text),
+        },
+    )
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// The class of the `<span>` wrapping a kind of token.
+fn html_class(kind: SemanticTokenKind) -> &'static str {
+    match kind {
+        SemanticTokenKind::Keyword => "keyword",
+        SemanticTokenKind::Namespace => "namespace",
+        SemanticTokenKind::Function => "function",
+        SemanticTokenKind::Constant => "constant",
+        SemanticTokenKind::Parameter => "parameter",
+        SemanticTokenKind::Local => "local",
+        SemanticTokenKind::Type => "type",
+        SemanticTokenKind::Literal => "literal",
+        SemanticTokenKind::Comment => "comment",
+    }
+}
+
+/// Highlight a source as a `<pre class="matrix">` element, with each token in a `<span>` of
+/// the class of its kind, for a stylesheet to color.
+pub fn html(source: &str, tokens: &[SemanticToken]) -> String {
+    let code = write(source, tokens, escape_html, |out, kind, text| {
+        out.push_str(&format!("<span class=\"{}\">", html_class(kind)));
+        escape_html(out, text);
+        out.push_str("</span>");
+    });
+    format!("<pre class=\"matrix\"><code>{code}</code></pre>\n")
+}
@@ -0,0 +1,82 @@
+//! Diagnostics as JSON, for `--message-format json`, so tools can read them without parsing
+//! what's drawn for humans.
+//!
+//! The diagnostics are an array of objects, each with its code, its severity, its message and
+//! help, its labels and its notes. Labels and notes that point into the source have the file
+//! they're in and the lines and columns they cover, counting from 1.
+
+use crate::sarif::{self, json_string, Exported, Region};
+use miette::{Report, Severity};
+
+fn optional(text: Option<&str>) -> String {
+    text.map_or_else(|| "null".to_owned(), json_string)
+}
+
+fn region(region: &Region<'_>) -> String {
+    format!(
+        "\"file\": {}, \"start_line\": {}, \"start_column\": {}, \"end_line\": {}, \"end_column\": {}",
+        json_string(&region.file),
+        region.start_line,
+        region.start_column,
+        region.end_line,
+        region.end_column
+    )
+}
+
+fn diagnostic(exported: &Exported<'_>) -> String {
+    let Exported {
+        diagnostic, source, ..
+    } = *exported;
+    let severity = match diagnostic.severity() {
+        Some(Severity::Warning) => "warning",
+        Some(Severity::Advice) => "advice",
+        Some(Severity::Error) | None => "error",
+    };
+    let labels: Vec<_> = diagnostic
+        .labels()
+        .into_iter()
+        .flatten()
+        .filter_map(|label| {
+            let region = sarif::region(source?, &label)?;
+            Some(format!(
+                "{{ {}, \"message\": {} }}",
+                self::region(&region),
+                optional(region.message)
+            ))
+        })
+        .collect();
+    let notes: Vec<_> = exported
+        .note_labels()
+        .map(|(text, label)| {
+            let region = label
+                .as_ref()
+                .and_then(|label| sarif::region(source?, label));
+            match region {
+                Some(region) => format!(
+                    "{{ \"message\": {}, {} }}",
+                    json_string(&text),
+                    self::region(&region)
+                ),
+                None => format!("{{ \"message\": {} }}", json_string(&text)),
+            }
+        })
+        .collect();
+
+    format!(
+        "  {{ \"code\": {}, \"severity\": \"{severity}\", \"message\": {}, \"help\": {}, \"labels\": [{}], \"notes\": [{}] }}",
+        optional(diagnostic.code().map(|code| code.to_string()).as_deref()),
+        json_string(&diagnostic.to_string()),
+        optional(diagnostic.help().map(|help| help.to_string()).as_deref()),
+        labels.join(", "),
+        notes.join(", ")
+    )
+}
+
+/// Every diagnostic some reports hold as a JSON array.
+pub fn diagnostics(reports: &[Report]) -> String {
+    let diagnostics: Vec<_> = sarif::exported(reports).iter().map(diagnostic).collect();
+    if diagnostics.is_empty() {
+        return "[]\n".to_owned();
+    }
+    format!("[\n{}\n]\n", diagnostics.join(",\n"))
+}
@@ -0,0 +1,365 @@
+//! Diagnostics in other languages, for `--locale`. Each language has a catalog in `locales/`,
+//! which gives the messages, help and labels of diagnostics by their code, so diagnostics are
+//! translated without changing the crates that report them.
+//!
+//! A catalog's entry has the English message as a template, like
+//! ``Cannot find `{name}` in this scope``. The parts of a diagnostic's message standing for its
+//! placeholders are read from it, then written into the entry's translation. A diagnostic
+//! without an entry, or whose message doesn't match its entry's English, stays in English.
+
+use miette::{Diagnostic, LabeledSpan, ReportHandler, Severity, SourceCode};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// The catalog of each language besides English, by its code.
+const CATALOGS: &[(&str, &str)] = &[("fr", include_str!("../locales/fr.toml"))];
+
+/// A diagnostic's translations.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    english: String,
+    message: String,
+    help: Option<String>,
+
+    /// The translation of each label, by its English template.
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// The translations of diagnostics into a language, by their code.
+pub struct Catalog {
+    entries: HashMap<String, Entry>,
+}
+
+impl Catalog {
+    /// The catalog of a locale, like `fr` or `fr_FR.UTF-8`, or `None` for English, which
+    /// needs none.
+    pub fn load(locale: &str) -> miette::Result<Option<Arc<Self>>> {
+        let language = locale
+            .split(['_', '-', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if language == "en" || locale == "C" || locale == "POSIX" {
+            return Ok(None);
+        }
+        let Some(&(_, text)) = CATALOGS.iter().find(|&&(code, _)| code == language) else {
+            let mut languages = vec!["en"];
+            languages.extend(CATALOGS.iter().map(|&(code, _)| code));
+            return Err(miette::miette!(
+                help = format!("use one of {}", languages.join(", ")),
+                "There are no diagnostics in the language of `{locale}`"
+            ));
+        };
+        let entries = toml::from_str(text).expect("the catalogs are valid");
+        Ok(Some(Arc::new(Self { entries })))
+    }
+}
+
+/// A piece of a template: text to match as it is, or a placeholder's name.
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template into its text and placeholders. A `{` not starting a placeholder's name
+/// and `}` is text.
+fn pieces(template: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let name_len = rest[open + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - open - 1);
+        let close = open + 1 + name_len;
+        if name_len == 0 || !rest[close..].starts_with('}') {
+            pieces.push(Piece::Text(&rest[..open + 1]));
+            rest = &rest[open + 1..];
+            continue;
+        }
+        pieces.push(Piece::Text(&rest[..open]));
+        pieces.push(Piece::Placeholder(&rest[open + 1..close]));
+        rest = &rest[close + 1..];
+    }
+    pieces.push(Piece::Text(rest));
+    pieces.retain(|piece| !matches!(piece, Piece::Text("")));
+    pieces
+}
+
+/// What each placeholder of a template stands for in a text, if the text matches it. Each
+/// placeholder takes the text up to the first place the rest of the template's text follows.
+fn captures<'a>(template: &'a str, text: &str) -> Option<HashMap<&'a str, String>> {
+    let pieces = pieces(template);
+    let mut captures = HashMap::new();
+    let mut rest = text;
+    for (i, piece) in pieces.iter().enumerate() {
+        match *piece {
+            Piece::Text(expected) => rest = rest.strip_prefix(expected)?,
+            Piece::Placeholder(name) => {
+                let end = match pieces.get(i + 1) {
+                    Some(Piece::Text(next)) => rest.find(next)?,
+                    Some(Piece::Placeholder(_)) => 0,
+                    None => rest.len(),
+                };
+                captures.insert(name, rest[..end].to_owned());
+                rest = &rest[end..];
+            }
+        }
+    }
+    rest.is_empty().then_some(captures)
+}
+
+/// Write what placeholders stand for into a template, or return `None` if it has one that
+/// wasn't captured.
+fn fill(template: &str, captures: &HashMap<&str, String>) -> Option<String> {
+    pieces(template)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Some(text),
+            Piece::Placeholder(name) => captures.get(name).map(String::as_str),
+        })
+        .collect()
+}
+
+/// Translate a text from a template into another, or return `None` if it doesn't match.
+fn translate(english: &str, translation: &str, text: &str) -> Option<String> {
+    fill(translation, &captures(english, text)?)
+}
+
+/// A diagnostic as a catalog translates it, with its related diagnostics translated too.
+#[derive(Debug)]
+struct Translated<'a> {
+    diagnostic: &'a dyn Diagnostic,
+    message: String,
+    help: Option<String>,
+    labels: Option<Vec<LabeledSpan>>,
+    related: Vec<Translated<'a>>,
+}
+
+impl<'a> Translated<'a> {
+    fn new(diagnostic: &'a dyn Diagnostic, catalog: &Catalog) -> Self {
+        let message = diagnostic.to_string();
+        let entry = diagnostic
+            .code()
+            .and_then(|code| catalog.entries.get(&code.to_string()));
+        let parts = entry.and_then(|entry| captures(&entry.english, &message));
+        let (message, help) = match (entry, &parts) {
+            (Some(entry), Some(parts)) => (
+                fill(&entry.message, parts).unwrap_or(message),
+                entry
+                    .help
+                    .as_ref()
+                    .and_then(|help| fill(help, parts))
+                    .or_else(|| diagnostic.help().map(|help| help.to_string())),
+            ),
+            _ => (message, diagnostic.help().map(|help| help.to_string())),
+        };
+        let labels = diagnostic.labels().map(|labels| {
+            labels
+                .map(|label| {
+                    let text = label.label().map(|text| {
+                        entry
+                            .and_then(|entry| {
+                                entry.labels.iter().find_map(|(english, translation)| {
+                                    translate(english, translation, text)
+                                })
+                            })
+                            .unwrap_or_else(|| text.to_owned())
+                    });
+                    LabeledSpan::new_with_span(text, *label.inner())
+                })
+                .collect()
+        });
+        let related = diagnostic
+            .related()
+            .into_iter()
+            .flatten()
+            .map(|related| Self::new(related, catalog))
+            .collect();
+
+        Self {
+            diagnostic,
+            message,
+            help,
+            labels,
+            related,
+        }
+    }
+}
+
+impl fmt::Display for Translated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Translated<'_> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.diagnostic.source()
+    }
+}
+
+impl Diagnostic for Translated<'_> {
+    fn code<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        self.diagnostic.code()
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        self.diagnostic.severity()
+    }
+
+    fn help<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn url<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        self.diagnostic.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.diagnostic.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let labels = self.labels.as_ref()?;
+        Some(Box::new(labels.iter().cloned()))
+    }
+
+    fn related<'b>(&'b self) -> Option<Box<dyn Iterator<Item = &'b dyn Diagnostic> + 'b>> {
+        if self.related.is_empty() {
+            return None;
+        }
+        Some(Box::new(
+            self.related
+                .iter()
+                .map(|related| related as &dyn Diagnostic),
+        ))
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.diagnostic.diagnostic_source()
+    }
+}
+
+/// A report handler drawing diagnostics as another handler does, once a catalog has
+/// translated them.
+pub struct Localized {
+    pub handler: Box<dyn ReportHandler>,
+    pub catalog: Arc<Catalog>,
+}
+
+impl ReportHandler for Localized {
+    fn debug(&self, diagnostic: &dyn Diagnostic, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return fmt::Debug::fmt(diagnostic, f);
+        }
+        self.handler
+            .debug(&Translated::new(diagnostic, &self.catalog), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_templates() {
+        let english = "Procedure `{name}` takes {expected} argument(s)";
+        let french = "La procédure `{name}` prend {expected} argument(s)";
+        assert_eq!(
+            translate(english, french, "Procedure `add` takes 2 argument(s)").as_deref(),
+            Some("La procédure `add` prend 2 argument(s)")
+        );
+        assert_eq!(translate(english, french, "Procedure `add` takes"), None);
+        assert_eq!(
+            translate("{a}{b} and {", "{b}, {a} et {", "xy and {").as_deref(),
+            Some("xy,  et {"),
+            "a placeholder right after another captures nothing, and a lone `{{` is text"
+        );
+    }
+
+    #[test]
+    fn test_load_locales() {
+        for english in ["en", "en_GB.UTF-8", "C", "POSIX"] {
+            assert!(Catalog::load(english).unwrap().is_none(), "{english}");
+        }
+        assert!(Catalog::load("FR").unwrap().is_some());
+        let error = Catalog::load("xx_YY").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "There are no diagnostics in the language of `xx_YY`"
+        );
+    }
+
+    /// The names of a template's placeholders.
+    fn placeholders(template: &str) -> Vec<&str> {
+        pieces(template)
+            .into_iter()
+            .filter_map(|piece| match piece {
+                Piece::Placeholder(name) => Some(name),
+                Piece::Text(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_catalogs_only_use_their_placeholders() {
+        for (code, text) in CATALOGS {
+            let entries: HashMap<String, Entry> = toml::from_str(text).unwrap();
+            for (key, entry) in &entries {
+                let english = placeholders(&entry.english);
+                let known = |template: &str| {
+                    placeholders(template)
+                        .iter()
+                        .all(|name| english.contains(name))
+                };
+                assert!(known(&entry.message), "{code}: {key}");
+                assert!(
+                    entry.help.as_deref().into_iter().all(known),
+                    "{code}: {key}"
+                );
+                for (english, translation) in &entry.labels {
+                    let english = placeholders(english);
+                    assert!(
+                        placeholders(translation)
+                            .iter()
+                            .all(|name| english.contains(name)),
+                        "{code}: {key}: {translation}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_translate_diagnostics() {
+        let catalog = Catalog::load("fr_FR.UTF-8").unwrap().unwrap();
+        let report = miette::miette!(
+            code = "hir::type_mismatch",
+            labels = vec![LabeledSpan::at(0..4, "expected `int`, found `bool`")],
+            "Mismatched types. Expected `int`, found `bool`"
+        );
+        let translated = Translated::new(report.as_ref(), &catalog);
+        assert_eq!(
+            translated.to_string(),
+            "Types incompatibles. `int` attendu, `bool` trouvé"
+        );
+        let label = translated.labels().unwrap().next().unwrap();
+        assert_eq!(label.label(), Some("`int` attendu, `bool` trouvé"));
+
+        // Messages that don't match their entry, and codes without one, stay in English.
+        for (code, message) in [
+            ("hir::type_mismatch", "Types differ"),
+            ("matrix::test", "Something's off"),
+        ] {
+            let report = miette::miette!(code = code, "{message}");
+            assert_eq!(
+                Translated::new(report.as_ref(), &catalog).to_string(),
+                message
+            );
+        }
+    }
+}
@@ -1,34 +1,2142 @@
 #![warn(rust_2018_idioms)]
 
-use clap::Parser as CliParser;
-use miette::{Diagnostic, IntoDiagnostic, NamedSource, Report, SourceCode};
-use std::{fs, path::PathBuf};
+mod bench;
+mod config;
+mod coverage;
+mod debugger;
+mod editor;
+mod explain;
+mod fix;
+mod highlight;
+mod json;
+mod locale;
+mod manifest;
+mod query;
+mod render;
+mod repl;
+mod sarif;
+mod scaffold;
+mod tokens;
+
+use clap::{CommandFactory, FromArgMatches, Parser as CliParser, Subcommand, ValueEnum};
+use diagnostics::{DiagnosticSink, Level, Levels, Stage};
+use matrix_compiler::{Session, Sources, Veto};
+use miette::{Diagnostic, IntoDiagnostic, Report, SourceCode};
+use span::SourceMap;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::{
+    env, fmt, fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+
+/// Exit code for a program with errors: one that doesn't compile, or that stops with an error
+/// when it's run. `test` exits with it for failed tests, `fmt --check` for files that aren't
+/// formatted, and `query` when nothing matches.
+const PROGRAM_ERROR: u8 = 1;
+
+/// Exit code for arguments clap rejects, paths that can't be read or written, and other
+/// mistakes using mtxc rather than in the program.
+const USAGE_ERROR: u8 = 2;
+
+/// Exit codes of mtxc, shown after `--help`. Panics exit with 101, like any Rust program's.
+const EXIT_CODES: &str = "\
+Exit codes:
+  0    Success
+  1    The program has errors, or stopped with an error when it was run, or some of its
+       tests failed, or `fmt --check` found files that aren't formatted
+  2    Bad arguments, or a file couldn't be read or written
+  101  An internal compiler error, which is a bug in mtxc
+
+`run` exits with the int the program's `main` returns instead, if it returns one.";
 
 #[derive(CliParser)]
+#[command(subcommand_negates_reqs = true, after_help = EXIT_CODES)]
 struct Cli {
-    /// Path to the program file.
-    program_path: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Paths to the program's files, or to directories of them, or `-` to read it from the
+    /// standard input. It's compiled and checked for errors, printing only what `--emit` asks
+    /// for.
+    #[arg(required = true)]
+    program_paths: Vec<PathBuf>,
+
+    /// Print the program at some stages of compilation, in the order they happen. Takes a
+    /// comma-separated list, or can be given more than once.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    emit: Vec<Emit>,
+
+    /// How hard to optimize, from 0 to 3. The MIR pipeline treats 3 like 2, but LLVM doesn't.
+    /// Defaults to 0, or for `build` to a project's `opt-level`.
+    #[arg(short = 'O', global = true, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: Option<u8>,
+
+    /// Print the MIR to stderr after every run of an optimization pass, such as `const-prop`.
+    #[arg(long, value_name = "PASS", value_parser = parse_pass)]
+    print_ir_after: Vec<mir::opt::Pass>,
+
+    /// Leave an optimization pass out of the `-O` pipeline, such as to find which pass
+    /// miscompiles a program.
+    #[arg(long, global = true, value_name = "PASS", value_parser = parse_pass)]
+    disable_pass: Vec<mir::opt::Pass>,
+
+    /// Log each phase of compilation to stderr with how long it took and how much it worked
+    /// on, and print what the optimization passes removed. Give it twice to log each
+    /// procedure and pass too. `MTXC_LOG` filters the logs instead, like `RUST_LOG` does,
+    /// such as `MTXC_LOG=hir=debug`.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Check that the syntax tree the parser builds keeps its invariants, like every node's
+    /// span covering its children's, and stop with an error at the first node that breaks one.
+    /// Debug builds always check them.
+    #[arg(long, global = true)]
+    verify_ast: bool,
+
+    /// Where to write the object file of `--emit obj`. Defaults to the program's path with an
+    /// `.o` extension.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// What integer arithmetic does on overflow: `checked` stops the program with an error,
+    /// `wrapping` wraps around and `saturating` clamps to the smallest or largest `int`.
+    #[arg(long, global = true, default_value = "checked", value_parser = parse_overflow)]
+    overflow: hir::Overflow,
+
+    /// How to report warnings and errors. The REPL always reports them for humans.
+    #[arg(long, global = true, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+
+    /// How many diagnostics to show before only counting the rest, or 0 to show them all. An
+    /// error with many related diagnostics counts each of them.
+    #[arg(long, global = true, value_name = "N", default_value_t = 20)]
+    error_limit: usize,
+
+    /// When to color diagnostics and logs. Reports are wrapped to `COLUMNS`, or to the width
+    /// of the terminal stderr is, or to 80 columns when it isn't one.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorChoice,
+
+    /// How to draw diagnostics for humans.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    diagnostic_style: DiagnosticStyle,
+
+    /// What to do with warnings. Denied warnings are reported, then stop the program from
+    /// compiling like an error.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    warnings: WarningLevel,
+
+    /// Make the warnings with a code errors, like `--deny hir::no_effect`, or every warning
+    /// with `--deny warnings`. A code can be given without its crate, like `no_effect`, or
+    /// short, like `E0234`. Takes a comma-separated list, or can be given more than once.
+    /// Overrides `--warnings`, and `--allow` for the same code.
+    #[arg(
+        long,
+        global = true,
+        value_name = "CODE",
+        value_delimiter = ',',
+        value_parser = parse_lint
+    )]
+    deny: Vec<String>,
+
+    /// Leave out the warnings with a code, like `--allow hir::unreachable_arm`, or every
+    /// warning with `--allow warnings`. Codes are given like `--deny`'s, and override
+    /// `--warnings`.
+    #[arg(
+        long,
+        global = true,
+        value_name = "CODE",
+        value_delimiter = ',',
+        value_parser = parse_lint
+    )]
+    allow: Vec<String>,
+
+    /// The language to draw diagnostics for humans in, like `fr`. Diagnostics without a
+    /// translation are in English, as are JSON and SARIF reports.
+    #[arg(long, global = true, value_name = "LOCALE", default_value = "en")]
+    locale: String,
+
+    /// Stop programs from reading or writing files, reading the environment or the clock, or
+    /// sleeping. Programs calling the builtins doing so don't compile, and bytecode files
+    /// calling them stop with an error if they do.
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// How deeply procedure calls can be nested before the program, or a REPL input, is
+    /// stopped.
+    #[arg(long, global = true, default_value_t = interp::DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// How many steps the program, or each test or REPL input, can take before it's stopped:
+    /// one for every statement and loop iteration interpreted, or every instruction the VM
+    /// runs.
+    #[arg(long, global = true, value_name = "STEPS")]
+    fuel: Option<u64>,
+}
+
+impl Cli {
+    /// How programs are run, from `--max-call-depth` and `--fuel`.
+    fn run_options(&self) -> interp::Options {
+        interp::Options {
+            max_call_depth: self.max_call_depth,
+            fuel: self.fuel,
+        }
+    }
+
+    /// How programs are lowered, from the flags for overflow, warnings and the sandbox.
+    fn lower_options(&self) -> hir::LowerOptions<'_> {
+        hir::LowerOptions {
+            overflow: self.overflow,
+            levels: levels(self),
+            sandbox: self.sandbox,
+            ..Default::default()
+        }
+    }
+
+    /// The first of the program's paths, which the config is found from, if the command takes
+    /// any.
+    fn first_path(&self) -> Option<&Path> {
+        let paths = match &self.command {
+            None => &self.program_paths,
+            Some(
+                Command::Run { program_paths, .. }
+                | Command::Debug { program_paths, .. }
+                | Command::Check { program_paths }
+                | Command::Test { program_paths, .. }
+                | Command::Bench { program_paths, .. }
+                | Command::Fmt { program_paths, .. }
+                | Command::Fix { program_paths, .. }
+                | Command::Build { program_paths, .. }
+                | Command::Query { program_paths, .. },
+            ) => program_paths,
+            Some(Command::Highlight { program_path, .. }) => return Some(program_path),
+            Some(
+                Command::Repl { .. }
+                | Command::New { .. }
+                | Command::Init { .. }
+                | Command::Explain { .. },
+            ) => return None,
+        };
+        paths.first().map(PathBuf::as_path)
+    }
+}
+
+/// When `--color` colors output.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum ColorChoice {
+    /// When what's colored is written to a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether diagnostics, which are written to stderr, are colored.
+    fn enabled(self) -> bool {
+        self.enabled_for(&io::stderr())
+    }
+
+    /// Whether what's written to a stream is colored.
+    fn enabled_for(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            Self::Auto => {
+                stream.is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// How `--diagnostic-style` draws diagnostics.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum DiagnosticStyle {
+    /// With miette, underlining labels below the source, and wrapping to the width.
+    #[default]
+    Miette,
+
+    /// With ariadne, pointing labels into the source with arrows beside it.
+    Ariadne,
+}
+
+/// What `--warnings` does with warnings.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum WarningLevel {
+    /// Don't report them.
+    Allow,
+
+    /// Report them, but compile the program anyway.
+    #[default]
+    Warn,
+
+    /// Report them, and don't compile a program with any.
+    Deny,
+}
+
+impl From<WarningLevel> for Level {
+    fn from(level: WarningLevel) -> Self {
+        match level {
+            WarningLevel::Allow => Self::Allow,
+            WarningLevel::Warn => Self::Warn,
+            WarningLevel::Deny => Self::Deny,
+        }
+    }
+}
+
+/// The formats `--message-format` reports diagnostics in.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MessageFormat {
+    /// Printed to stderr as they're found, with the source around them.
+    Human,
+
+    /// A SARIF 2.1.0 log of every diagnostic, printed to stdout once the command finishes.
+    /// The command exits with an error if there were errors, but doesn't print them.
+    Sarif,
+
+    /// A JSON array of every diagnostic, with its labels and notes, printed to stdout once the
+    /// command finishes, like a SARIF log.
+    Json,
+}
+
+/// The stages of compilation `--emit` can print, in the order they're printed.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Emit {
+    /// Every token, with the line and column it starts at and its text.
+    Tokens,
+
+    /// Every token as a JSON array of objects, with its file, kind, text and byte range.
+    #[value(name = "tokens=json")]
+    TokensJson,
+
+    /// Every token as CSV, with its file, kind, text and byte range.
+    #[value(name = "tokens=csv")]
+    TokensCsv,
+
+    /// The syntax tree the parser builds.
+    Ast,
+
+    /// The high-level IR, with names resolved and types checked.
+    Hir,
+
+    /// The SSA mid-level IR.
+    Mir,
+
+    /// x86-64 assembly, which `cc` can assemble and link into an executable.
+    Asm,
+
+    /// LLVM IR, optimized at the `-O` level. Needs the `llvm` feature.
+    LlvmIr,
+
+    /// A native object file, optimized at the `-O` level. Needs the `llvm` feature.
+    Obj,
+
+    /// VM bytecode, with the line and column each instruction was compiled from. Bytecode
+    /// files can be disassembled too.
+    Bytecode,
+}
+
+/// How `run --profile` prints the profile.
+#[derive(Clone, Copy, ValueEnum)]
+enum ProfileFormat {
+    /// Tables of procedures and instructions.
+    Table,
+
+    /// A JSON object, with times in nanoseconds.
+    Json,
+}
+
+/// How `highlight` writes the highlighted source.
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum HighlightFormat {
+    /// Colored with ANSI escapes, for a terminal.
+    #[default]
+    Ansi,
+
+    /// A `<pre>` element with a classed `<span>` around each token, for a stylesheet to color.
+    Html,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a program with the interpreter, or a bytecode file made by `build` with the VM. The
+    /// process exits with the int `main` returns, if it returns one.
+    Run {
+        /// Paths to the program's files or directories of them, or to a bytecode file, or `-`
+        /// to read it from the standard input, which leaves the program nothing to read.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// Compile the program to bytecode and run it on the VM instead of interpreting it.
+        #[arg(long)]
+        vm: bool,
+
+        /// Print every instruction the VM runs to stderr, with the registers it runs with.
+        /// Implies `--vm`.
+        #[arg(long)]
+        trace: bool,
+
+        /// Print how many times each procedure was called, how long it ran and how many times
+        /// each instruction ran to stderr once the program stops, as tables or, with
+        /// `--profile=json`, as JSON. Implies `--vm`.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "table",
+            conflicts_with = "trace"
+        )]
+        profile: Option<ProfileFormat>,
+
+        /// Write an lcov tracefile of how many times each line and branch of the program ran to
+        /// a path once it stops, and print a table of how much of each file ran to stderr.
+        /// Implies `--vm`.
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["trace", "profile"])]
+        coverage: Option<PathBuf>,
+
+        /// Seed the `rand` module's generator with 0 rather than at random, so the program
+        /// gets the same numbers every time it's run.
+        #[arg(long)]
+        deterministic: bool,
+
+        /// Arguments for the program, which it reads with `args()`. `arg_count()` and
+        /// `arg(index)` read them one at a time, for the native backends, which have no arrays.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Run a program on the VM a line at a time, reading commands from the standard input to
+    /// set breakpoints, step through it, and look at the calls it's in and their variables.
+    /// `help` lists the commands. The program reads nothing from the standard input.
+    Debug {
+        /// Paths to the program's files or directories of them.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// Stop at a line from the start, given as `FILE:LINE`, or `LINE` in the first file.
+        /// Can be given more than once.
+        #[arg(long = "break", value_name = "LOCATION")]
+        breakpoints: Vec<String>,
+    },
+
+    /// Read statements, expressions and items from the standard input, running each as it's
+    /// entered. Variables and items are kept for later inputs, and the value of an expression
+    /// without a semicolon is printed. `:save FILE` saves the session's items and variables,
+    /// and `:load FILE` runs a file's lines as inputs, resuming a saved session.
+    Repl {
+        /// Files to run as inputs before the first prompt, like a prelude or a saved session.
+        #[arg(long, value_name = "FILE")]
+        load: Vec<PathBuf>,
+    },
+
+    /// Lex, parse and type check a program without compiling or running it, printing only its
+    /// diagnostics. Exits with an error only if the program has errors, not for warnings.
+    Check {
+        /// Paths to the program's files or directories of them, or `-` to read it from the
+        /// standard input.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+    },
+
+    /// Run the procedures of a program marked `@test`, each in an interpreter of its own, and
+    /// report the ones that failed by stopping with an error, like a false `assert` or a call to
+    /// `panic`, with its message. What tests print is only shown for the ones that failed.
+    Test {
+        /// Paths to the program's files or directories of them, or `-` to read it from the
+        /// standard input.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// Run the tests on the VM instead, writing an lcov tracefile of how many times each
+        /// line and branch of the program ran over all of them to a path, and print a table of
+        /// how much of each file ran.
+        #[arg(long, value_name = "PATH")]
+        coverage: Option<PathBuf>,
+
+        /// Seed the `rand` module's generator with 0 for every test rather than at random, so
+        /// tests get the same numbers every time they're run.
+        #[arg(long)]
+        deterministic: bool,
+    },
+
+    /// Run the procedures of a program marked `@bench` many times each on the VM, and print
+    /// the mean, median and standard deviation of how long they took, compared to the fastest.
+    Bench {
+        /// Paths to the program's files or directories of them, or `-` to read it from the
+        /// standard input.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// How many untimed runs of each benchmark to do first.
+        #[arg(long, default_value_t = 3)]
+        warmup: u32,
+
+        /// How many timed runs of each benchmark to take statistics over.
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+        samples: u32,
+    },
+
+    /// Format programs in the canonical style, rewriting any files that change.
+    Fmt {
+        /// Paths to the files to format or directories of them, or `-` to format the standard
+        /// input to the standard output.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// Don't rewrite anything, but list the files that aren't formatted and exit with an
+        /// error if there are any.
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Apply the fixes that diagnostics suggest to a program's files, like adding missing
+    /// semicolons, printing each one made. Fixes that overlap each other are made one at a
+    /// time, checking the program again in between.
+    Fix {
+        /// Paths to the program's files or directories of them, or `-` to fix the standard
+        /// input to the standard output.
+        #[arg(required = true)]
+        program_paths: Vec<PathBuf>,
+
+        /// Don't rewrite anything, but print the fixes that would be made and exit with an
+        /// error if there are any.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print a program's file with its keywords, names, literals and comments highlighted. Names
+    /// are told apart by what they refer to, so they're only highlighted in files that type
+    /// check on their own.
+    Highlight {
+        /// Path to the file, or `-` to read it from the standard input.
+        program_path: PathBuf,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: HighlightFormat,
+    },
+
+    /// Compile a program to a bytecode file, which `run` can run without compiling it again,
+    /// or to a native executable.
+    Build {
+        /// Paths to the program's files or directories of them, or `-` to read it from the
+        /// standard input. Without any, the project whose `matrix.toml` is in the current
+        /// directory or the closest of its parents is built as its manifest says.
+        program_paths: Vec<PathBuf>,
+
+        /// Where to write the bytecode file or executable. Defaults to the program's path with
+        /// an `.mxc` extension, or without an extension for an executable, or for a project to
+        /// its output directory.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Build an executable with the x86-64 backend, optimized at the `-O` level. `cc`
+        /// assembles it and links it with the backend's runtime. Overrides a project's
+        /// `backend`.
+        #[arg(long)]
+        native: bool,
+
+        /// Make building the same program give the same bytes wherever and whenever it's
+        /// built: bytecode files name the program's files relative to the current directory,
+        /// or to a project's root, and the toolchain is asked to leave out timestamps.
+        #[arg(long)]
+        deterministic: bool,
+    },
+
+    /// Make a project in a new directory of the current one: a `matrix.toml` manifest, a
+    /// `src/main.mtx` printing a greeting, and a `.gitignore` for what `build` writes.
+    New {
+        /// The project's name, which its directory is named too.
+        name: String,
+    },
+
+    /// Make the current directory a project, like `new` does, leaving the files it already
+    /// has as they are.
+    Init {
+        /// The project's name. Defaults to the directory's.
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Search programs' files for nodes matching a structural query, like
+    /// `call(name="print*", in=proc(name="main"))`, printing where each one is with the line it
+    /// starts on. Exits with an error if nothing matches.
+    ///
+    /// A query is a kind of node, or `*` for any, with constraints on its fields in parentheses.
+    /// Values are compared as text, with `*` matching any run of characters, and `!=` matching
+    /// nodes without the field or with other values. `in=` and `has=` take a query that a node
+    /// containing it, or one it contains, matches.
+    Query {
+        /// The query.
+        query: String,
+
+        /// Paths to the files to search or directories of them, or `-` to search the standard
+        /// input. Without any, the source directory of the project whose `matrix.toml` is in
+        /// the current directory or the closest of its parents is searched.
+        program_paths: Vec<PathBuf>,
+    },
+
+    /// Print the extended explanation of a diagnostic's code, with examples of what causes it
+    /// and how to fix it. Without a code, list every code with its short code.
+    Explain {
+        /// The code, like `lexer::unterminated_string_literal`, or its short code, like
+        /// `E0003`.
+        code: Option<String>,
+    },
+}
+
+/// The program path that reads the program from the standard input instead.
+const STDIN_PATH: &str = "-";
+
+/// Read a program's file, or the standard input if its path is `-`.
+fn read_program(path: &Path) -> miette::Result<Vec<u8>> {
+    if path != Path::new(STDIN_PATH) {
+        return fs::read(path).into_diagnostic();
+    }
+
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes).into_diagnostic()?;
+    Ok(bytes)
+}
+
+/// How large a program's file is before it's mapped into memory rather than read.
+const MAP_LEN: u64 = 1 << 20;
+
+/// The bytes of a program's file, mapped into memory if it's large.
+enum ProgramBytes {
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+impl std::ops::Deref for ProgramBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Read(bytes) => bytes,
+        }
+    }
+}
+
+/// Read a program's file like [`read_program`], but map a large one into memory, so its text
+/// is copied once, into the program's sources, rather than read into memory and then copied.
+/// A file that can't be mapped, like a pipe, is read.
+fn load_program(path: &Path) -> miette::Result<ProgramBytes> {
+    if path != Path::new(STDIN_PATH) {
+        let file = fs::File::open(path).into_diagnostic()?;
+        if file.metadata().into_diagnostic()?.len() >= MAP_LEN {
+            // SAFETY: the mapping is only read, and only while the file is copied into the
+            // program's sources. Something else changing the file meanwhile would change the
+            // bytes under it, which the compiler can't guard against; like other compilers, it
+            // expects its sources to be left alone while it reads them.
+            if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                return Ok(ProgramBytes::Mapped(map));
+            }
+        }
+    }
+    read_program(path).map(ProgramBytes::Read)
+}
+
+/// The name diagnostics show for a program's source.
+fn source_name(path: &Path) -> String {
+    if path == Path::new(STDIN_PATH) {
+        "<stdin>".to_owned()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// The source files a program's paths stand for: each file, and every `.mtx` file in each
+/// directory, in name order.
+fn source_files(paths: &[PathBuf]) -> miette::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut entries = fs::read_dir(path)
+            .into_diagnostic()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()
+            .into_diagnostic()?;
+        entries.retain(|entry| {
+            entry.is_file()
+                && entry
+                    .extension()
+                    .is_some_and(|extension| extension == "mtx")
+        });
+        if entries.is_empty() {
+            return Err(miette::miette!(
+                "`{}` doesn't have any `.mtx` files",
+                path.display()
+            ));
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// What a program's paths hold.
+enum Input {
+    /// A bytecode file made by `build`.
+    Bytecode(Vec<u8>),
+    Sources(Sources),
+}
+
+/// Read the files of a program. A bytecode file has to be the only one.
+///
+/// Each file is copied straight into the program's sources, which have room for every file
+/// from the start, so the largest a program's text takes in memory is about its size.
+fn read_input(paths: &[PathBuf]) -> miette::Result<Input> {
+    let files = source_files(paths)?;
+    // Each file is followed by a newline in the sources.
+    let len = files
+        .iter()
+        .map(|path| fs::metadata(path).map_or(0, |metadata| metadata.len() as usize + 1))
+        .sum();
+    let mut map = SourceMap::with_capacity(len);
+    for path in &files {
+        let bytes = load_program(path)?;
+        if vm::file::is_bytecode(&bytes) {
+            if files.len() > 1 {
+                return Err(miette::miette!(
+                    help = "run or disassemble it on its own",
+                    "`{}` is a bytecode file, which can't be compiled with other files",
+                    source_name(path)
+                ));
+            }
+            return Ok(Input::Bytecode(bytes.to_vec()));
+        }
+        map.add_bytes(source_name(path), &bytes).map_err(|error| {
+            let line = bytes[..error.offset]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count()
+                + 1;
+            miette::miette!(
+                help = "save it as UTF-8 text",
+                "`{}` isn't UTF-8: its bytes on line {line} aren't",
+                source_name(path)
+            )
+        })?;
+    }
+    Ok(Input::Sources(map.into()))
+}
+
+/// Where to write what's compiled from a program when no output is given: beside the program,
+/// with another extension.
+fn default_output(path: &Path, extension: &str) -> miette::Result<PathBuf> {
+    if path == Path::new(STDIN_PATH) {
+        return Err(miette::miette!(
+            help = "choose where to write it with `--output`",
+            "A program read from the standard input has no path to write its output beside"
+        ));
+    }
+    Ok(path.with_extension(extension))
+}
+
+fn parse_pass(name: &str) -> Result<mir::opt::Pass, String> {
+    mir::opt::Pass::from_name(name).ok_or_else(|| {
+        let names = mir::opt::Pass::ALL.map(|pass| pass.name());
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_overflow(name: &str) -> Result<hir::Overflow, String> {
+    hir::Overflow::from_name(name).ok_or_else(|| {
+        let names = hir::Overflow::ALL.map(|mode| mode.name());
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+/// What `--deny` and `--allow` take every warning with.
+const WARNINGS: &str = "warnings";
+
+/// Read a code for `--deny` or `--allow` as the full code it stands for, or `warnings`.
+fn parse_lint(name: &str) -> Result<String, String> {
+    if name == WARNINGS {
+        return Ok(name.to_owned());
+    }
+    if let Some(entry) = explain::lookup(name) {
+        return Ok(entry.code.to_owned());
+    }
+    let suffix = format!("::{name}");
+    let mut codes = explain::entries().filter(|entry| entry.code.ends_with(&suffix));
+    match (codes.next(), codes.next()) {
+        (Some(entry), None) => Ok(entry.code.to_owned()),
+        (Some(first), Some(second)) => Err(format!(
+            "`{name}` could be `{}` or `{}`",
+            first.code, second.code
+        )),
+        (None, _) => Err(format!(
+            "expected `{WARNINGS}` or a diagnostic code; `mtxc explain` lists them"
+        )),
+    }
+}
+
+/// What to do with warnings, from `--warnings`, then `--allow` and `--deny`.
+fn levels(args: &Cli) -> Levels {
+    let mut levels = Levels::new();
+    levels.set_warnings(args.warnings.into());
+    for (codes, level) in [(&args.allow, Level::Allow), (&args.deny, Level::Deny)] {
+        for code in codes {
+            match code.as_str() {
+                WARNINGS => levels.set_warnings(level),
+                code => levels.set(code, level),
+            }
+        }
+    }
+    levels
+}
+
+/// An error in the program being compiled or run, rather than in how mtxc was used, so it can
+/// exit with [`PROGRAM_ERROR`]. It's reported just like the error it holds.
+#[derive(Debug)]
+struct ProgramError(Report);
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for ProgramError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl Diagnostic for ProgramError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.0.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        self.0.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.0.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.0.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.0.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.0.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        self.0.related()
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.0.diagnostic_source()
+    }
+}
+
+/// An error shown with only the first of its related diagnostics, once `--error-limit` leaves no
+/// room for the rest.
+#[derive(Debug)]
+struct Truncated {
+    report: Report,
+    related: usize,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.report, f)
+    }
+}
+
+impl std::error::Error for Truncated {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.report.source()
+    }
+}
+
+impl Diagnostic for Truncated {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.report.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        self.report.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.report.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.report.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.report.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.report.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        let related = self.report.related()?;
+        Some(Box::new(related.take(self.related)))
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.report.diagnostic_source()
+    }
+}
+
+/// The exit code of an error a command stopped with.
+fn failure_code(error: &Report) -> u8 {
+    if error.downcast_ref::<ProgramError>().is_some() {
+        PROGRAM_ERROR
+    } else {
+        USAGE_ERROR
+    }
 }
 
+/// Report the errors of a program with the source they're in.
 fn map_err_to_report<T, E: Diagnostic + Send + Sync + 'static>(
     r: Result<T, E>,
-    (source_name, source_code): (impl AsRef<str>, impl SourceCode + 'static),
+    source_code: impl SourceCode + 'static,
 ) -> miette::Result<T> {
     r.map_err(|diagnostics| {
-        Report::from(diagnostics).with_source_code(NamedSource::new(source_name, source_code))
+        Report::new(ProgramError(
+            Report::from(diagnostics).with_source_code(source_code),
+        ))
     })
 }
 
-fn main() -> miette::Result<()> {
-    let args = Cli::parse();
+/// Report the diagnostics a stage of compiling failed with, with the source they're in and the
+/// fixes they suggest.
+fn map_sink_to_report<T, D: Stage>(
+    r: Result<T, DiagnosticSink<D>>,
+    source_code: impl SourceCode + 'static,
+) -> miette::Result<T> {
+    r.map_err(|sink| Report::new(ProgramError(sink.into_report(source_code))))
+}
+
+/// The width of the terminal stderr is, if it's one.
+fn terminal_width() -> Option<usize> {
+    #[cfg(unix)]
+    let size = terminal_size::terminal_size_using_fd(io::stderr().as_raw_fd());
+    #[cfg(not(unix))]
+    let size = terminal_size::terminal_size();
+    size.map(|(terminal_size::Width(width), _)| width as usize)
+}
+
+/// How reports are drawn, as the flags and the config say.
+struct ReportStyle {
+    ariadne: bool,
+    color: bool,
+    width: usize,
+    catalog: Option<Arc<locale::Catalog>>,
+}
+
+/// The style reports are drawn in, set once the flags and the config are read.
+static REPORT_STYLE: OnceLock<ReportStyle> = OnceLock::new();
+
+/// Draws reports in [`REPORT_STYLE`] when they're shown rather than when they're made, since
+/// miette gives a report its handler when it's made. Reports made before the style is known,
+/// like an invalid config's, are then drawn like any other.
+struct StyledHandler;
+
+impl miette::ReportHandler for StyledHandler {
+    fn debug(&self, diagnostic: &dyn Diagnostic, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(style) = REPORT_STYLE.get() else {
+            return render::handler(false, false, 80).debug(diagnostic, f);
+        };
+        let handler = render::handler(style.ariadne, style.color, style.width);
+        match &style.catalog {
+            Some(catalog) => locale::Localized {
+                handler,
+                catalog: catalog.clone(),
+            }
+            .debug(diagnostic, f),
+            None => handler.debug(diagnostic, f),
+        }
+    }
+}
+
+/// Set up how miette renders reports: in a style, colored or not, and wrapped to the width of
+/// where they're shown.
+fn init_reports(style: DiagnosticStyle, color: bool, catalog: Option<Arc<locale::Catalog>>) {
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(terminal_width)
+        .unwrap_or(80);
+    let style = ReportStyle {
+        ariadne: style == DiagnosticStyle::Ariadne,
+        color,
+        width,
+        catalog,
+    };
+    assert!(
+        REPORT_STYLE.set(style).is_ok(),
+        "the report style is only set once"
+    );
+}
+
+/// Log the phases of compilation to stderr at the level `-v` asks for, with how long each
+/// took once it's done, or as `MTXC_LOG` filters them.
+fn init_logging(verbose: u8, color: bool) {
+    use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+    let level = match verbose {
+        0 => "off",
+        1 => "info",
+        _ => "debug",
+    };
+    let filter = EnvFilter::try_from_env("MTXC_LOG").unwrap_or_else(|_| EnvFilter::new(level));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .with_ansi(color)
+        .init();
+}
+
+/// Where warnings and errors go, as `--message-format` says.
+struct Reporter {
+    format: MessageFormat,
+    style: DiagnosticStyle,
+
+    /// How many diagnostics are shown to humans before the rest are only counted, or 0 for
+    /// no limit. SARIF logs and JSON always have every diagnostic.
+    error_limit: usize,
+
+    /// How many diagnostics were shown, and how many were left out past the limit.
+    shown: usize,
+    hidden: usize,
+
+    /// The warnings reported so far, and the error the command stopped with, when they're
+    /// kept for a SARIF log or JSON.
+    reports: Vec<Report>,
+}
+
+impl Reporter {
+    fn new(args: &Cli) -> Self {
+        Self {
+            format: args.message_format,
+            style: args.diagnostic_style,
+            error_limit: args.error_limit,
+            shown: 0,
+            hidden: 0,
+            reports: Vec::new(),
+        }
+    }
+
+    /// What the error a command stopped with is shown after. ariadne's reports start by saying
+    /// they're errors already.
+    fn error_prefix(&self) -> &'static str {
+        match self.style {
+            DiagnosticStyle::Miette => "Error: ",
+            DiagnosticStyle::Ariadne => "",
+        }
+    }
+
+    /// How many more diagnostics can be shown.
+    fn room(&self) -> usize {
+        match self.error_limit {
+            0 => usize::MAX,
+            limit => limit.saturating_sub(self.shown),
+        }
+    }
+
+    /// Report a diagnostic that doesn't stop the command, like a warning or a failed test.
+    fn report(&mut self, diagnostic: Report) {
+        match self.format {
+            MessageFormat::Human if self.room() == 0 => self.hidden += 1,
+            MessageFormat::Human => {
+                self.shown += 1;
+                eprintln!("{diagnostic:?}");
+            }
+            MessageFormat::Sarif | MessageFormat::Json => self.reports.push(diagnostic),
+        }
+    }
+
+    /// Show the error a command stopped with. Each of its related diagnostics counts towards
+    /// the limit, but the error itself is always shown.
+    fn show_error(&mut self, error: Report) {
+        let related = error.related().map_or(0, Iterator::count);
+        let room = self.room();
+        if related <= room {
+            self.shown += related;
+            eprintln!("{}{error:?}", self.error_prefix());
+            return;
+        }
+
+        self.shown += room;
+        self.hidden += related - room;
+        let error = Report::new(Truncated {
+            report: error,
+            related: room,
+        });
+        eprintln!("{}{error:?}", self.error_prefix());
+    }
+
+    /// Finish reporting once a command has run, reporting the error it stopped with, if any,
+    /// and returning the process's exit code.
+    fn finish(mut self, result: miette::Result<ExitCode>) -> ExitCode {
+        let code = result.unwrap_or_else(|error| {
+            let code = ExitCode::from(failure_code(&error));
+            match self.format {
+                MessageFormat::Human => self.show_error(error),
+                MessageFormat::Sarif | MessageFormat::Json => self.reports.push(error),
+            }
+            code
+        });
+        if self.hidden > 0 {
+            let plural = if self.hidden == 1 { "" } else { "s" };
+            eprintln!(
+                "error: too many errors; not showing {} more diagnostic{plural}. Raise \
+                 `--error-limit`, or set it to 0 to show them all",
+                self.hidden
+            );
+        }
+        match self.format {
+            MessageFormat::Human => {}
+            MessageFormat::Sarif => print!("{}", sarif::log(&self.reports)),
+            MessageFormat::Json => print!("{}", json::diagnostics(&self.reports)),
+        }
+        code
+    }
+}
+
+/// Print every token of a program on its own line.
+fn print_tokens(code: &str, tokens: &[lexer::token::Token]) {
+    let lines = vm::debug::Lines::new(code);
+    for token in tokens {
+        let (line, column) = lines.locate(token.span.start);
+        let text = &code[token.span.start..token.span.end];
+        println!(
+            "{:<8}{:<24}{text}",
+            format!("{line}:{column}"),
+            token.kind.to_string()
+        );
+    }
+}
+
+/// Report a program that didn't compile, with the source its errors are in.
+fn compile_error(error: matrix_compiler::Error) -> Report {
+    Report::new(ProgramError(error.into_report()))
+}
+
+/// Stop compiling at the first node of a syntax tree that breaks an invariant, which is a bug
+/// in the parser rather than in the program.
+fn verify_ast(program: &parser::ast::Program) -> Result<(), Veto> {
+    let Err(violations) = parser::ast::validate(program) else {
+        return Ok(());
+    };
+    let first = &violations[0];
+    let help = match violations.len() - 1 {
+        0 => "this is a bug in the parser".to_owned(),
+        more => format!("this is a bug in the parser, which broke {more} more invariants too"),
+    };
+    Err(Veto::new(format!("Invalid syntax tree: {}", first.message))
+        .at(first.span)
+        .with_help(help))
+}
+
+/// Lex, parse and check a program's files with some options, printing any warnings, and the
+/// tokens, syntax tree or HIR if `emit` asks for them. With `verify_ast`, the syntax tree is
+/// checked to keep its invariants.
+fn compile(
+    sources: &Sources,
+    options: &hir::LowerOptions<'_>,
+    emit: &[Emit],
+    verify_ast: bool,
+    reporter: &mut Reporter,
+) -> miette::Result<hir::Program> {
+    let mut session = Session::new(sources.clone()).options(options.clone());
+    if verify_ast {
+        session = session.on_ast(self::verify_ast);
+    }
+    let lexed = session.lex().map_err(compile_error)?;
+    if emit.contains(&Emit::Tokens) {
+        let several = lexed.tokens().len() > 1;
+        for (file, tokens) in sources.files().zip(lexed.tokens()) {
+            if several {
+                println!("{}:", file.name);
+            }
+            print_tokens(file.text, tokens);
+        }
+    }
+    if emit.contains(&Emit::TokensJson) {
+        print!("{}", tokens::json(sources, lexed.tokens()));
+    }
+    if emit.contains(&Emit::TokensCsv) {
+        print!("{}", tokens::csv(sources, lexed.tokens()));
+    }
 
-    let code = fs::read_to_string(&args.program_path).into_diagnostic()?;
-    let source_name = args.program_path.display().to_string();
+    let parsed = lexed.parse().map_err(compile_error)?;
+    if emit.contains(&Emit::Ast) {
+        println!("{:#?}", parsed.ast());
+    }
+    let checked = parsed.check().map_err(compile_error)?;
+    let warnings = std::mem::take(&mut session.diagnostics().check);
+    for warning in warnings.into_diagnostics() {
+        reporter.report(diagnostics::report(warning, sources.clone()));
+    }
+    if emit.contains(&Emit::Hir) {
+        println!("{:#?}", checked.program);
+    }
 
-    let tokens = map_err_to_report(lexer::lex(&code), (&source_name, code.clone()))?;
-    dbg!(&tokens);
-    let ast = map_err_to_report(parser::parse(tokens), (&source_name, code))?;
-    dbg!(ast);
+    Ok(checked.program)
+}
+
+/// Compile a program with the LLVM backend, printing its IR or writing an object file.
+#[cfg(feature = "llvm")]
+fn emit_llvm(
+    program: &mir::Program,
+    emit: Emit,
+    opt_level: u8,
+    output: &Path,
+) -> miette::Result<()> {
+    let _span = tracing::info_span!("codegen", backend = "llvm", opt_level).entered();
+    let opt_level = codegen_llvm::OptLevel::from_level(opt_level).expect("clap checks the range");
+    if let Emit::Obj = emit {
+        codegen_llvm::emit_object(program, opt_level, output)?;
+    } else {
+        print!("{}", codegen_llvm::emit_llvm_ir(program, opt_level)?);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn emit_llvm(_: &mir::Program, _: Emit, _: u8, _: &Path) -> miette::Result<()> {
+    Err(miette::miette!(
+        help = "rebuild mtxc with `--features llvm`, which needs LLVM 16 installed",
+        "This build of mtxc doesn't include the LLVM backend"
+    ))
+}
+
+/// Compile a program to VM bytecode.
+fn compile_bytecode(program: &hir::Program) -> vm::Bytecode {
+    let _span =
+        tracing::info_span!("codegen", backend = "bytecode", procs = program.procs.len()).entered();
+    vm::compile(program)
+}
 
+/// Lower a program to MIR and optimize it at a level, leaving out the passes `args` disable.
+fn optimized_mir(program: &hir::Program, opt_level: u8, args: &Cli) -> mir::Program {
+    let _span = tracing::info_span!("mir", opt_level).entered();
+    let mut program = mir::lower(program);
+    let passes = args
+        .disable_pass
+        .iter()
+        .fold(mir::opt::PassManager::preset(opt_level), |passes, &pass| {
+            passes.without(pass)
+        });
+    let stats = passes.run(&mut program, |pass, program| {
+        tracing::debug!(pass = pass.name(), "ran pass");
+        if args.print_ir_after.contains(&pass) {
+            eprint!("*** IR after {} ***\n{program}", pass.name());
+        }
+    });
+    if args.verbose > 0 {
+        eprintln!("{stats}");
+    }
+    program
+}
+
+/// Check a program for errors, which for a bytecode file means checking that it's valid.
+fn check(program_paths: &[PathBuf], args: &Cli, reporter: &mut Reporter) -> miette::Result<()> {
+    match read_input(program_paths)? {
+        Input::Bytecode(bytes) => {
+            vm::BytecodeFile::from_bytes(&bytes)?;
+        }
+        Input::Sources(sources) => {
+            let options = args.lower_options();
+            compile(&sources, &options, &[], args.verify_ast, reporter)?;
+        }
+    }
     Ok(())
 }
+
+/// The I/O of a test, which reads nothing and is seeded with 0 when `deterministic`.
+fn test_io<'a>(
+    input: &'a mut dyn io::BufRead,
+    output: &'a mut dyn io::Write,
+    deterministic: bool,
+) -> interp::Io<'a> {
+    let io = interp::Io::new(input, output);
+    if deterministic {
+        io.seeded(0)
+    } else {
+        io
+    }
+}
+
+/// Run a test with the interpreter, on a thread with enough stack for the call depth, returning
+/// what it printed.
+fn run_test(
+    program: &hir::Program,
+    test: hir::ProcId,
+    options: interp::Options,
+    deterministic: bool,
+) -> miette::Result<(Vec<u8>, Option<interp::RunError>)> {
+    std::thread::scope(|scope| {
+        let interpreter = std::thread::Builder::new()
+            .stack_size(options.interpreter_stack_size())
+            .spawn_scoped(scope, || {
+                let (mut input, mut output) = (io::empty(), Vec::new());
+                let io = test_io(&mut input, &mut output, deterministic);
+                let error = interp::run_proc(program, test, io, options).err();
+                (output, error)
+            })
+            .into_diagnostic()?;
+        Ok(interpreter
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })
+}
+
+/// Run a test on the VM, counting what it runs in a coverage, returning what it printed.
+fn run_test_covered(
+    bytecode: &vm::Bytecode,
+    test: hir::ProcId,
+    coverage: &mut vm::Coverage<'_>,
+    options: interp::Options,
+    deterministic: bool,
+) -> (Vec<u8>, Option<interp::RunError>) {
+    let (mut input, mut output) = (io::empty(), Vec::new());
+    let io = test_io(&mut input, &mut output, deterministic);
+    let machine = vm::Machine::start_at(bytecode, test.0, io, options);
+    let error = machine.run_covered(coverage).err();
+    (output, error)
+}
+
+/// Write the lcov tracefile of a program's coverage to a path, and print how much of each
+/// file ran to stderr.
+fn write_coverage(
+    path: &Path,
+    sources: &Sources,
+    coverage: &vm::Coverage<'_>,
+) -> miette::Result<()> {
+    let report = coverage::Report::new(sources, coverage);
+    fs::write(path, report.to_lcov()).into_diagnostic()?;
+    eprint!("{report}");
+    Ok(())
+}
+
+/// Run a program's tests, printing whether each passed and then why each that failed did. With
+/// a coverage path, they run on the VM and the coverage of all of them is written there.
+fn run_tests(
+    program_paths: &[PathBuf],
+    coverage_path: Option<&Path>,
+    deterministic: bool,
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<ExitCode> {
+    let Input::Sources(sources) = read_input(program_paths)? else {
+        miette::bail!(
+            help = "test the program's source files instead",
+            "Bytecode files can't be tested"
+        );
+    };
+    let options = args.lower_options();
+    let program = compile(&sources, &options, &[], args.verify_ast, reporter)?;
+    let bytecode = coverage_path.map(|_| compile_bytecode(&program));
+    let mut coverage = bytecode.as_ref().map(vm::Coverage::new);
+
+    let tests: Vec<_> = program.procs.iter().filter(|proc| proc.test).collect();
+    let count = tests.len();
+    let plural = if count == 1 { "" } else { "s" };
+    println!("running {count} test{plural}");
+    let mut failures = Vec::new();
+    for test in tests {
+        let name = program.qualified_name(test.id);
+        let (output, error) = match (&bytecode, &mut coverage) {
+            (Some(bytecode), Some(coverage)) => run_test_covered(
+                bytecode,
+                test.id,
+                coverage,
+                args.run_options(),
+                deterministic,
+            ),
+            _ => run_test(&program, test.id, args.run_options(), deterministic)?,
+        };
+        match error {
+            None => println!("test {name} ... ok"),
+            Some(error) => {
+                println!("test {name} ... FAILED");
+                failures.push((name, output, error));
+            }
+        }
+    }
+
+    for (name, output, error) in &failures {
+        eprintln!("\n---- {name} ----");
+        eprint!("{}", String::from_utf8_lossy(output));
+        reporter.report(Report::new(error.clone()).with_source_code(sources.clone()));
+    }
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {result}. {} passed; {} failed",
+        count - failures.len(),
+        failures.len()
+    );
+    if let (Some(path), Some(coverage)) = (coverage_path, &coverage) {
+        write_coverage(path, &sources, coverage)?;
+    }
+
+    Ok(if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(PROGRAM_ERROR)
+    })
+}
+
+/// Debug a program on the VM, stopping first at its first line.
+fn debug_program(
+    program_paths: &[PathBuf],
+    breakpoints: &[String],
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<ExitCode> {
+    let Input::Sources(sources) = read_input(program_paths)? else {
+        miette::bail!(
+            help = "debug the program's source files instead",
+            "Bytecode files can't be debugged"
+        );
+    };
+    let options = args.lower_options();
+    let program = compile(&sources, &options, &[], args.verify_ast, reporter)?;
+    let bytecode = compile_bytecode(&program);
+
+    match debugger::run(
+        &sources,
+        &program,
+        &bytecode,
+        breakpoints,
+        args.run_options(),
+    )? {
+        Some(result) => map_err_to_report(result.map(|value| exit_code(&value)), sources),
+        None => Ok(ExitCode::SUCCESS),
+    }
+}
+
+/// Time a program's benchmarks on the VM, printing a table of their statistics. What they print
+/// is discarded.
+fn run_benches(
+    program_paths: &[PathBuf],
+    warmup: u32,
+    samples: u32,
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<()> {
+    let Input::Sources(sources) = read_input(program_paths)? else {
+        miette::bail!(
+            help = "benchmark the program's source files instead",
+            "Bytecode files can't be benchmarked"
+        );
+    };
+    let options = args.lower_options();
+    let program = compile(&sources, &options, &[], args.verify_ast, reporter)?;
+    let bytecode = compile_bytecode(&program);
+
+    let mut results = Vec::new();
+    for proc in program.procs.iter().filter(|proc| proc.bench) {
+        let name = program.qualified_name(proc.id);
+        eprintln!("benchmarking {name}");
+        let mut times = Vec::with_capacity(samples as usize);
+        for run in 0..warmup + samples {
+            let (mut input, mut output) = (io::empty(), io::sink());
+            let io = interp::Io::new(&mut input, &mut output);
+            let machine = vm::Machine::start_at(&bytecode, proc.id.0, io, args.run_options());
+            let start = Instant::now();
+            let result = machine.run();
+            let time = start.elapsed();
+            map_err_to_report(result, sources.clone())?;
+            if run >= warmup {
+                times.push(time);
+            }
+        }
+        results.push((name, bench::Stats::new(&times)));
+    }
+
+    if results.is_empty() {
+        println!("no benchmarks");
+    } else {
+        print!("{}", bench::table(&results));
+    }
+    Ok(())
+}
+
+/// Format a file's source, reporting its errors if it doesn't parse.
+fn format_source(name: &str, text: &str) -> miette::Result<String> {
+    let sources = Sources::new([(name.to_owned(), text.to_owned())]);
+    let tokens = map_sink_to_report(lexer::lex_with_comments(text), sources.clone())?;
+    let code = tokens
+        .iter()
+        .copied()
+        .filter(|token| token.kind != lexer::token::TokenKind::Comment)
+        .collect();
+    let program = map_sink_to_report(parser::parse(text, code), sources)?;
+    Ok(formatter::format(text, &tokens, &program))
+}
+
+/// Format programs' files, or with `check`, list the ones that aren't formatted.
+fn format_files(program_paths: &[PathBuf], check: bool) -> miette::Result<ExitCode> {
+    let mut unformatted = false;
+    for path in source_files(program_paths)? {
+        let text = String::from_utf8(read_program(&path)?).into_diagnostic()?;
+        let name = source_name(&path);
+        let formatted = format_source(&name, &text)?;
+        if check {
+            if formatted != text {
+                println!("{name}");
+                unformatted = true;
+            }
+        } else if path == Path::new(STDIN_PATH) {
+            print!("{formatted}");
+        } else if formatted != text {
+            fs::write(&path, formatted).into_diagnostic()?;
+        }
+    }
+
+    Ok(if unformatted {
+        ExitCode::from(PROGRAM_ERROR)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Fix a program's files until no more fixes are suggested, then rewrite the ones that
+/// changed, or with `dry_run`, only print the fixes.
+fn fix_files(program_paths: &[PathBuf], dry_run: bool) -> miette::Result<ExitCode> {
+    let paths = source_files(program_paths)?;
+    let mut files = paths
+        .iter()
+        .map(|path| {
+            let text = String::from_utf8(read_program(path)?).into_diagnostic()?;
+            Ok((source_name(path), text))
+        })
+        .collect::<miette::Result<Vec<_>>>()?;
+
+    let mut fixed = false;
+    let mut settled = false;
+    for _ in 0..fix::MAX_PASSES {
+        let sources = Sources::new(files.clone());
+        let suggestions = fix::suggestions(&sources);
+        if suggestions.is_empty() {
+            settled = true;
+            break;
+        }
+
+        for (file, (name, text)) in sources.files().zip(&mut files) {
+            let applied = fix::apply(&file, &suggestions);
+            for fix in &applied.fixes {
+                if dry_run {
+                    println!("{name}:{fix}");
+                } else {
+                    eprintln!("fixed {name}:{fix}");
+                }
+            }
+            fixed |= !applied.fixes.is_empty();
+            *text = applied.text;
+        }
+    }
+    if !settled {
+        eprintln!(
+            "warning: stopped after {} rounds of fixes, with more still suggested",
+            fix::MAX_PASSES
+        );
+    }
+
+    if dry_run {
+        return Ok(if fixed {
+            ExitCode::from(PROGRAM_ERROR)
+        } else {
+            ExitCode::SUCCESS
+        });
+    }
+    for (path, (_, text)) in paths.iter().zip(files) {
+        if path == Path::new(STDIN_PATH) {
+            print!("{text}");
+        } else if fs::read_to_string(path).ok().as_deref() != Some(text.as_str()) {
+            fs::write(path, text).into_diagnostic()?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Search files for nodes matching a query, printing each match as `name:line:column: line`
+/// with the match highlighted if `color` is set. Files that don't parse are reported and
+/// skipped.
+fn query_files(
+    query: &str,
+    program_paths: &[PathBuf],
+    color: bool,
+    reporter: &mut Reporter,
+) -> miette::Result<ExitCode> {
+    let query = query::Query::parse(query)?;
+    let paths = if program_paths.is_empty() {
+        let manifest = manifest::Manifest::find()?;
+        source_files(&[manifest.source_dir()])?
+    } else {
+        source_files(program_paths)?
+    };
+
+    let mut matched = false;
+    for path in paths {
+        let text = String::from_utf8(read_program(&path)?).into_diagnostic()?;
+        let sources = Sources::new([(source_name(&path), text)]);
+        let file = sources.files().next().expect("there is one file");
+        let (name, text) = (file.name, file.text);
+        let ast = map_sink_to_report(lexer::lex(text), sources.clone())
+            .and_then(|tokens| map_sink_to_report(parser::parse(text, tokens), sources.clone()));
+        let ast = match ast {
+            Ok(ast) => ast,
+            Err(error) => {
+                reporter.report(error);
+                continue;
+            }
+        };
+
+        for span in query.find(&ast) {
+            matched = true;
+            let (line, column) = file.line_column(span.start);
+            let line_start = text[..span.start]
+                .rfind('\n')
+                .map_or(0, |newline| newline + 1);
+            let line_end = text[span.start..]
+                .find('\n')
+                .map_or(text.len(), |newline| span.start + newline);
+            let end = span.end.min(line_end);
+            let (before, found, after) = (
+                &text[line_start..span.start],
+                &text[span.start..end],
+                &text[end..line_end],
+            );
+            if color {
+                println!("{name}:{line}:{column}: {before}\x1b[1;31m{found}\x1b[0m{after}");
+            } else {
+                println!("{name}:{line}:{column}: {before}{found}{after}");
+            }
+        }
+    }
+
+    Ok(if matched {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(PROGRAM_ERROR)
+    })
+}
+
+/// Print a file highlighted. Its tokens are classified without what its names refer to if it
+/// doesn't parse or type check, rather than reporting its errors.
+fn highlight_file(path: &Path, format: HighlightFormat) -> miette::Result<()> {
+    let text = String::from_utf8(read_program(path)?).into_diagnostic()?;
+    let sources = Sources::new([(source_name(path), text)]);
+    let text = sources.files().next().expect("there is one file").text;
+    let tokens = map_sink_to_report(lexer::lex_with_comments(text), sources.clone())?;
+    let code = tokens
+        .iter()
+        .copied()
+        .filter(|token| token.kind != lexer::token::TokenKind::Comment)
+        .collect();
+    let program = parser::parse(text, code)
+        .ok()
+        .and_then(|ast| hir::lower(&ast).ok())
+        .map(|lowered| lowered.program)
+        .unwrap_or_default();
+
+    let classified = hir::classify(&tokens, &program);
+    match format {
+        HighlightFormat::Ansi => print!("{}", highlight::ansi(text, &classified)),
+        HighlightFormat::Html => print!("{}", highlight::html(text, &classified)),
+    }
+    Ok(())
+}
+
+/// Compile a program to a bytecode file, or to an executable when `native` is set. For
+/// `--deterministic`, `relative_to` is the directory the bytecode file names the program's files
+/// relative to.
+fn build(
+    program_paths: &[PathBuf],
+    output: &Path,
+    native: bool,
+    opt_level: u8,
+    relative_to: Option<&Path>,
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<()> {
+    let Input::Sources(sources) = read_input(program_paths)? else {
+        return Err(miette::miette!(
+            "`{}` is already a bytecode file",
+            source_name(&program_paths[0])
+        ));
+    };
+    let options = args.lower_options();
+    let program = compile(&sources, &options, &[], args.verify_ast, reporter)?;
+
+    if native {
+        let program = optimized_mir(&program, opt_level, args);
+        let _span = tracing::info_span!("codegen", backend = "x86").entered();
+        let options = codegen_x86::BuildOptions {
+            deterministic: relative_to.is_some(),
+        };
+        codegen_x86::build_executable_with(&program, output, options)?;
+        return Ok(());
+    }
+
+    // Bytecode files hold a single source, so a program of several files keeps their text
+    // under all of their names.
+    let source_name = match relative_to {
+        Some(dir) => {
+            let names: Vec<_> = sources
+                .files()
+                .map(|file| {
+                    let path = Path::new(file.name);
+                    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+                })
+                .collect();
+            names.join(", ")
+        }
+        None => sources.name(),
+    };
+    let file = vm::BytecodeFile {
+        bytecode: compile_bytecode(&program),
+        source_name,
+        source: sources.text().to_owned(),
+    };
+    fs::write(output, file.to_bytes()).into_diagnostic()
+}
+
+/// Build the project whose manifest is in the current directory or the closest of its parents.
+/// The entry file comes first among the program's files, followed by the rest of the source
+/// directory's.
+fn build_project(
+    output: Option<&Path>,
+    native: bool,
+    deterministic: bool,
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<()> {
+    let manifest = manifest::Manifest::find()?;
+    let entry = manifest.entry();
+    let mut files = source_files(&[manifest.source_dir()])?;
+    let Some(index) = files.iter().position(|file| *file == entry) else {
+        return Err(miette::miette!(
+            help = format!(
+                "set `entry` in `{}` to the file with `main`",
+                manifest::FILE_NAME
+            ),
+            "The entry file `{}` doesn't exist",
+            entry.display()
+        ));
+    };
+    files.remove(index);
+    files.insert(0, entry);
+
+    let backend = if native {
+        manifest::Backend::Native
+    } else {
+        manifest.build.backend
+    };
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => {
+            let output = manifest.output(backend);
+            if let Some(dir) = output.parent() {
+                fs::create_dir_all(dir).into_diagnostic()?;
+            }
+            output
+        }
+    };
+    let opt_level = args.opt_level.or(manifest.opt_level()).unwrap_or_default();
+    build(
+        &files,
+        &output,
+        backend == manifest::Backend::Native,
+        opt_level,
+        deterministic.then_some(manifest.root.as_path()),
+        args,
+        reporter,
+    )
+}
+
+/// How to run a program, from the `run` command's options.
+struct RunSettings<'a> {
+    vm: bool,
+    trace: bool,
+    profile: Option<ProfileFormat>,
+    coverage: Option<&'a Path>,
+    options: interp::Options,
+    args: &'a [String],
+    sandbox: bool,
+    deterministic: bool,
+}
+
+impl<'a> RunSettings<'a> {
+    /// The program's I/O, with the standard input and output.
+    fn io(
+        &self,
+        input: &'a mut dyn std::io::BufRead,
+        output: &'a mut dyn std::io::Write,
+    ) -> interp::Io<'a> {
+        let io = interp::Io::new(input, output).with_args(self.args);
+        let io = if self.sandbox { io.sandboxed() } else { io };
+        if self.deterministic {
+            io.seeded(0)
+        } else {
+            io
+        }
+    }
+}
+
+/// Run bytecode on the VM with the standard input and output, printing every instruction it
+/// runs to stderr, with where in the source it came from, when tracing, or its profile when
+/// profiling, or counting what it runs in a coverage.
+fn run_vm(
+    bytecode: &vm::Bytecode,
+    source: &str,
+    settings: &RunSettings<'_>,
+    coverage: Option<&mut vm::Coverage<'_>>,
+) -> Result<interp::Value, interp::RunError> {
+    let (mut input, mut output) = (std::io::stdin().lock(), std::io::stdout().lock());
+    let io = settings.io(&mut input, &mut output);
+    let machine = vm::Machine::new(bytecode, io, settings.options)?.with_source(source);
+    if settings.trace {
+        return machine.run_traced(&mut std::io::stderr().lock());
+    }
+    if let Some(coverage) = coverage {
+        return machine.run_covered(coverage);
+    }
+    let Some(format) = settings.profile else {
+        return machine.run();
+    };
+
+    let (result, profile) = machine.run_profiled();
+    match format {
+        ProfileFormat::Table => eprint!("{profile}"),
+        ProfileFormat::Json => eprint!("{}", profile.to_json()),
+    }
+    result
+}
+
+/// Run bytecode compiled from some sources on the VM, writing its coverage once it stops if
+/// `--coverage` asks for it, and reporting errors against the sources.
+fn run_on_vm(
+    bytecode: &vm::Bytecode,
+    sources: &Sources,
+    settings: &RunSettings<'_>,
+) -> miette::Result<ExitCode> {
+    let mut coverage = settings.coverage.map(|_| vm::Coverage::new(bytecode));
+    let result = run_vm(bytecode, sources.text(), settings, coverage.as_mut());
+    if let (Some(path), Some(coverage)) = (settings.coverage, &coverage) {
+        write_coverage(path, sources, coverage)?;
+    }
+    map_err_to_report(result.map(|value| exit_code(&value)), sources.clone())
+}
+
+/// Run a bytecode file on the VM, reporting errors against the source it was built from. The
+/// coverage of a file built from several sources is reported as one file, named after all of
+/// them.
+fn run_bytecode(bytes: &[u8], settings: &RunSettings<'_>) -> miette::Result<ExitCode> {
+    let file = vm::BytecodeFile::from_bytes(bytes)?;
+    let _span = tracing::info_span!("run", vm = true).entered();
+    let sources = Sources::new([(file.source_name, file.source)]);
+    run_on_vm(&file.bytecode, &sources, settings)
+}
+
+/// Print a code's explanation, or list every code without one.
+fn explain(code: Option<&str>) -> miette::Result<()> {
+    let Some(code) = code else {
+        print!("{}", explain::list());
+        return Ok(());
+    };
+    let entry = explain::lookup(code).ok_or_else(|| {
+        miette::miette!(
+            help = "run `mtxc explain` without a code to list them all",
+            "There's no diagnostic with the code `{code}`"
+        )
+    })?;
+    print!("{}", explain::explanation(&entry));
+    Ok(())
+}
+
+/// The process's exit code for the value `main` returned. Like a native executable's, it's the
+/// low byte of an int, and success for anything else.
+fn exit_code(value: &interp::Value) -> ExitCode {
+    match *value {
+        interp::Value::Int(code, _) => ExitCode::from(code as u8),
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn main() -> ExitCode {
+    miette::set_hook(Box::new(|_| Box::new(StyledHandler))).expect("the hook is only set once");
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    // The flags are set from the config before anything is reported, but an invalid config is
+    // reported as the flags on the command line say.
+    let config = config::Config::find(args.first_path())
+        .and_then(|config| config.apply(&mut args, &matches));
+    let (catalog, config) = match locale::Catalog::load(&args.locale) {
+        Ok(catalog) => (catalog, config),
+        Err(error) => (None, config.and(Err(error))),
+    };
+
+    let color = args.color.enabled();
+    init_reports(args.diagnostic_style, color, catalog);
+    init_logging(args.verbose, color);
+    let mut reporter = Reporter::new(&args);
+    let result = config.and_then(|()| run_command(&args, &mut reporter));
+    reporter.finish(result)
+}
+
+/// Run the command the arguments ask for.
+fn run_command(args: &Cli, reporter: &mut Reporter) -> miette::Result<ExitCode> {
+    let (program_paths, run) = match &args.command {
+        Some(Command::Build {
+            program_paths,
+            output,
+            native,
+            deterministic,
+        }) => {
+            if program_paths.is_empty() {
+                build_project(output.as_deref(), *native, *deterministic, args, reporter)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let output = match output {
+                Some(output) => output.clone(),
+                None => default_output(&program_paths[0], if *native { "" } else { "mxc" })?,
+            };
+            let opt_level = args.opt_level.unwrap_or_default();
+            let current = env::current_dir().into_diagnostic()?;
+            let relative_to = deterministic.then_some(current.as_path());
+            build(
+                program_paths,
+                &output,
+                *native,
+                opt_level,
+                relative_to,
+                args,
+                reporter,
+            )?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Test {
+            program_paths,
+            coverage,
+            deterministic,
+        }) => {
+            return run_tests(
+                program_paths,
+                coverage.as_deref(),
+                *deterministic,
+                args,
+                reporter,
+            )
+        }
+        Some(Command::Debug {
+            program_paths,
+            breakpoints,
+        }) => return debug_program(program_paths, breakpoints, args, reporter),
+        Some(Command::Bench {
+            program_paths,
+            warmup,
+            samples,
+        }) => {
+            run_benches(program_paths, *warmup, *samples, args, reporter)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Fmt {
+            program_paths,
+            check,
+        }) => return format_files(program_paths, *check),
+        Some(Command::Fix {
+            program_paths,
+            dry_run,
+        }) => return fix_files(program_paths, *dry_run),
+        Some(Command::Highlight {
+            program_path,
+            format,
+        }) => {
+            highlight_file(program_path, *format)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Query {
+            query,
+            program_paths,
+        }) => {
+            return query_files(
+                query,
+                program_paths,
+                args.color.enabled_for(&io::stdout()),
+                reporter,
+            )
+        }
+        Some(Command::New { name }) => {
+            scaffold::new(name)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Init { name }) => {
+            scaffold::init(Path::new("."), name.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Explain { code }) => {
+            explain(code.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Check { program_paths }) => {
+            check(program_paths, args, reporter)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Repl { load }) => {
+            repl::run(
+                load,
+                args.overflow,
+                levels(args),
+                args.run_options(),
+                args.sandbox,
+            )?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Run {
+            program_paths,
+            vm,
+            trace,
+            profile,
+            coverage,
+            deterministic,
+            args: program_args,
+        }) => {
+            let settings = RunSettings {
+                vm: *vm || *trace || profile.is_some() || coverage.is_some(),
+                trace: *trace,
+                profile: *profile,
+                coverage: coverage.as_deref(),
+                options: args.run_options(),
+                args: program_args,
+                sandbox: args.sandbox,
+                deterministic: *deterministic,
+            };
+            (program_paths, Some(settings))
+        }
+        None => (&args.program_paths, None),
+    };
+
+    let bytes = match read_input(program_paths)? {
+        Input::Bytecode(bytes) => bytes,
+        Input::Sources(sources) => {
+            return compile_and_run(&sources, program_paths, run, args, reporter)
+        }
+    };
+    if let Some(settings) = &run {
+        return run_bytecode(&bytes, settings);
+    }
+    if let Some(&emit) = args.emit.iter().find(|&&emit| emit != Emit::Bytecode) {
+        let name = emit.to_possible_value().expect("no stage is skipped");
+        return Err(miette::miette!(
+            "`--emit {}` needs the program's source, but it's a bytecode file",
+            name.get_name()
+        ));
+    }
+    let file = vm::BytecodeFile::from_bytes(&bytes)?;
+    if !args.emit.is_empty() {
+        print!("{}", vm::debug::disassemble(&file.bytecode, &file.source));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Compile a program's source files, printing what `--emit` asks for, and run it if `run` is
+/// given.
+fn compile_and_run(
+    sources: &Sources,
+    program_paths: &[PathBuf],
+    run: Option<RunSettings<'_>>,
+    args: &Cli,
+    reporter: &mut Reporter,
+) -> miette::Result<ExitCode> {
+    let options = args.lower_options();
+    let mut stages = args.emit.clone();
+    stages.sort();
+    stages.dedup();
+    let program = compile(sources, &options, &stages, args.verify_ast, reporter)?;
+    let opt_level = args.opt_level.unwrap_or_default();
+
+    for &emit in &stages {
+        match emit {
+            // Printed while compiling.
+            Emit::Tokens | Emit::TokensJson | Emit::TokensCsv | Emit::Ast | Emit::Hir => {}
+            Emit::Mir => print!("{}", optimized_mir(&program, opt_level, args)),
+            Emit::Asm => {
+                let program = optimized_mir(&program, opt_level, args);
+                let _span = tracing::info_span!("codegen", backend = "x86").entered();
+                print!("{}", codegen_x86::emit(&program)?);
+            }
+            Emit::Bytecode => print!(
+                "{}",
+                vm::debug::disassemble(&compile_bytecode(&program), sources.text())
+            ),
+            Emit::LlvmIr | Emit::Obj => {
+                let output = match &args.output {
+                    Some(output) => output.clone(),
+                    None => default_output(&program_paths[0], "o")?,
+                };
+                emit_llvm(
+                    &optimized_mir(&program, opt_level, args),
+                    emit,
+                    opt_level,
+                    &output,
+                )?;
+            }
+        }
+    }
+
+    let Some(settings) = run else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    let _span = tracing::info_span!("run", vm = settings.vm).entered();
+    if settings.vm {
+        return run_on_vm(&compile_bytecode(&program), sources, &settings);
+    }
+    // The interpreter recurses on the host's stack, so it runs on a thread with enough
+    // stack for the call depth.
+    let options = settings.options;
+    let result = std::thread::scope(|scope| -> miette::Result<_> {
+        let interpreter = std::thread::Builder::new()
+            .stack_size(options.interpreter_stack_size())
+            .spawn_scoped(scope, || {
+                let (mut input, mut output) = (std::io::stdin().lock(), std::io::stdout().lock());
+                let io = settings.io(&mut input, &mut output);
+                interp::run_with_io(&program, io, options).map(|value| exit_code(&value))
+            })
+            .map_err(|error| {
+                miette::miette!(
+                    help = "lower `--max-call-depth`, or run the program with `--vm`",
+                    "Couldn't make a stack for {} nested calls: {error}",
+                    options.max_call_depth
+                )
+            })?;
+        Ok(interpreter
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })?;
+    map_err_to_report(result, sources.clone())
+}
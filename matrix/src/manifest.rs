@@ -0,0 +1,283 @@
+//! `matrix.toml`, the manifest of a project, which `build` reads when it isn't given a
+//! program's paths:
+//!
+//! ```toml
+//! [package]
+//! name = "hello"
+//! entry = "main.mtx"    # The file with `main`, in the source directory.
+//! source-dir = "src"    # Every `.mtx` file in it is part of the program.
+//! output-dir = "build"  # Where the bytecode file or executable is written.
+//!
+//! [build]
+//! backend = "bytecode"  # Or "native" for an executable.
+//! opt-level = 0
+//!
+//! [tool.matrix]         # Defaults for flags, like a `.matrix.toml` has.
+//! warnings = "deny"
+//! ```
+//!
+//! Only `package.name` is required, and the paths are relative to the manifest's directory.
+//! Other tools can keep their settings in tables of `tool` too.
+
+use crate::config::Config;
+use miette::{IntoDiagnostic, LabeledSpan, NamedSource};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+
+/// The name of a project's manifest, in the directory at its root.
+pub const FILE_NAME: &str = "matrix.toml";
+
+/// What a project's program is built to.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// A bytecode file, which `run` runs with the VM.
+    #[default]
+    Bytecode,
+
+    /// An executable built with the x86-64 backend.
+    Native,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Package {
+    /// The project's name, which its bytecode file or executable is named after.
+    pub name: String,
+
+    #[serde(default = "Package::default_entry")]
+    pub entry: PathBuf,
+
+    #[serde(default = "Package::default_source_dir")]
+    pub source_dir: PathBuf,
+
+    #[serde(default = "Package::default_output_dir")]
+    pub output_dir: PathBuf,
+}
+
+impl Package {
+    fn default_entry() -> PathBuf {
+        PathBuf::from("main.mtx")
+    }
+
+    fn default_source_dir() -> PathBuf {
+        PathBuf::from("src")
+    }
+
+    fn default_output_dir() -> PathBuf {
+        PathBuf::from("build")
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BuildSettings {
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// How hard to optimize, like `-O`, which overrides it.
+    pub opt_level: Option<Spanned<u8>>,
+}
+
+/// The settings of tools kept in the manifest, of which only mtxc's are read.
+#[derive(Default, Deserialize)]
+struct Tools {
+    matrix: Option<Config>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    package: Package,
+
+    #[serde(default)]
+    build: BuildSettings,
+
+    #[serde(default)]
+    tool: Tools,
+}
+
+/// A project's manifest.
+pub struct Manifest {
+    /// The directory the manifest is in, which its paths are relative to.
+    pub root: PathBuf,
+    pub package: Package,
+    pub build: BuildSettings,
+
+    /// The config in the manifest's `[tool.matrix]` table, if it has one.
+    pub config: Option<Config>,
+}
+
+impl Manifest {
+    /// Read the manifest in the current directory, or in the closest of its parents with one.
+    pub fn find() -> miette::Result<Self> {
+        let current = env::current_dir().into_diagnostic()?;
+        let Some(root) = current
+            .ancestors()
+            .find(|dir| dir.join(FILE_NAME).is_file())
+        else {
+            return Err(miette::miette!(
+                help = "give the program's paths, or run this in a project",
+                "Neither `{}` nor any of its parents has a `{FILE_NAME}`",
+                current.display()
+            ));
+        };
+        Self::read(root)
+    }
+
+    /// Read the manifest in a project's root directory.
+    pub fn read(root: &Path) -> miette::Result<Self> {
+        let path = root.join(FILE_NAME);
+        let text = fs::read_to_string(&path).into_diagnostic()?;
+        let source = || NamedSource::new(path.display().to_string(), text.clone());
+
+        let file: ManifestFile = match toml::from_str(&text) {
+            Ok(file) => file,
+            Err(error) => {
+                let labels = error.span().map(|span| LabeledSpan::at(span, "here"));
+                return Err(miette::miette!(
+                    labels = labels.into_iter().collect::<Vec<_>>(),
+                    "Invalid manifest: {}",
+                    error.message()
+                )
+                .with_source_code(source()));
+            }
+        };
+
+        if let Some(level) = file
+            .build
+            .opt_level
+            .as_ref()
+            .filter(|level| *level.get_ref() > 3)
+        {
+            return Err(miette::miette!(
+                labels = vec![LabeledSpan::at(level.span(), "this level")],
+                help = "use a level from 0 to 3",
+                "Invalid manifest: `opt-level` is out of range"
+            )
+            .with_source_code(source()));
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            package: file.package,
+            build: file.build,
+            config: file
+                .tool
+                .matrix
+                .map(|config| config.with_source(&path, &text)),
+        })
+    }
+
+    pub fn opt_level(&self) -> Option<u8> {
+        self.build.opt_level.as_ref().map(|level| *level.get_ref())
+    }
+
+    /// The file with `main`.
+    pub fn entry(&self) -> PathBuf {
+        self.source_dir().join(&self.package.entry)
+    }
+
+    pub fn source_dir(&self) -> PathBuf {
+        self.root.join(&self.package.source_dir)
+    }
+
+    /// Where the bytecode file or executable is written, named after the project.
+    pub fn output(&self, backend: Backend) -> PathBuf {
+        let output = self
+            .root
+            .join(&self.package.output_dir)
+            .join(&self.package.name);
+        match backend {
+            Backend::Bytecode => output.with_extension("mxc"),
+            Backend::Native => output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_defaults() -> miette::Result<()> {
+        let root = tempfile::tempdir().into_diagnostic()?;
+        fs::write(root.path().join(FILE_NAME), "[package]\nname = \"hello\"\n")
+            .into_diagnostic()?;
+
+        let manifest = Manifest::read(root.path())?;
+        assert_eq!(manifest.entry(), root.path().join("src/main.mtx"));
+        assert_eq!(
+            manifest.output(Backend::Bytecode),
+            root.path().join("build/hello.mxc")
+        );
+        assert_eq!(
+            manifest.output(Backend::Native),
+            root.path().join("build/hello")
+        );
+        assert!(manifest.build.backend == Backend::Bytecode);
+        assert_eq!(manifest.opt_level(), None);
+        assert!(manifest.config.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_settings() -> miette::Result<()> {
+        let root = tempfile::tempdir().into_diagnostic()?;
+        let text = r#"[package]
+name = "hello"
+entry = "app.mtx"
+source-dir = "lib"
+output-dir = "out"
+
+[build]
+backend = "native"
+opt-level = 2
+
+[tool.matrix]
+overflow = "wrapping"
+
+[tool.other]
+anything = true
+"#;
+        fs::write(root.path().join(FILE_NAME), text).into_diagnostic()?;
+
+        let manifest = Manifest::read(root.path())?;
+        assert_eq!(manifest.entry(), root.path().join("lib/app.mtx"));
+        assert!(manifest.build.backend == Backend::Native);
+        assert_eq!(
+            manifest.output(manifest.build.backend),
+            root.path().join("out/hello")
+        );
+        assert_eq!(manifest.opt_level(), Some(2));
+        assert!(manifest.config.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_manifests() -> miette::Result<()> {
+        let root = tempfile::tempdir().into_diagnostic()?;
+        for (text, error) in [
+            ("[build]\nbackend = \"native\"\n", "missing field `package`"),
+            (
+                "[package]\nname = \"a\"\nnmae = \"b\"\n",
+                "unknown field `nmae`",
+            ),
+            (
+                "[package]\nname = \"a\"\n[build]\nopt-level = 4\n",
+                "`opt-level` is out of range",
+            ),
+        ] {
+            fs::write(root.path().join(FILE_NAME), text).into_diagnostic()?;
+            let Err(report) = Manifest::read(root.path()) else {
+                panic!("`{text}` was read");
+            };
+            assert!(report.to_string().contains(error), "{report}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,697 @@
+//! Structural search over parsed programs, for `query`.
+//!
+//! A query names a kind of node and constraints on it, like `call(name="print*", args=1)`.
+//! Fields are compared as text, with `*` matching any run of characters, and `in=` and `has=`
+//! take a query for an enclosing or nested node, like `ret(in=proc(name="main"))`.
+
+use miette::{LabeledSpan, NamedSource};
+use parser::ast::{
+    Block, Expression, ExpressionKind, Item, ItemKind, LiteralKind, Program, Statement,
+    StatementKind, Visibility,
+};
+use span::Span;
+
+/// Every kind of node a query can look for, with the fields it has.
+const KINDS: &[(&str, &[&str])] = &[
+    ("proc", &["name", "vis", "attr", "ret", "params"]),
+    ("mod", &["name", "vis", "attr"]),
+    ("const", &["name", "vis", "attr", "type"]),
+    ("import", &["name", "vis", "attr"]),
+    ("param", &["name", "type"]),
+    ("let", &["name", "type"]),
+    ("ret", &[]),
+    ("if", &["elifs"]),
+    ("while", &[]),
+    ("do_while", &[]),
+    ("for", &[]),
+    ("for_in", &["name"]),
+    ("block", &[]),
+    ("literal", &["type", "text"]),
+    ("ident", &["name"]),
+    ("path", &["name"]),
+    ("call", &["name", "args"]),
+    ("unary", &["op"]),
+    ("binary", &["op"]),
+    ("group", &[]),
+    ("match", &["arms"]),
+    ("array", &["len"]),
+    ("map", &["len"]),
+    ("index", &[]),
+    ("cast", &["type"]),
+    ("range", &["inclusive"]),
+];
+
+/// Matches nodes of any kind.
+const ANY_KIND: &str = "*";
+
+/// What a query asks of a node.
+#[derive(Debug)]
+enum Constraint {
+    /// One of the node's fields of a name matches a pattern, or with `negated`, none do.
+    Field {
+        name: String,
+        pattern: String,
+        negated: bool,
+    },
+
+    /// One of the nodes the node is in matches.
+    Inside(Query),
+
+    /// One of the nodes in the node matches.
+    Has(Query),
+}
+
+/// A parsed query.
+#[derive(Debug)]
+pub struct Query {
+    kind: String,
+    constraints: Vec<Constraint>,
+}
+
+impl Query {
+    /// Parse a query, reporting where it's malformed.
+    pub fn parse(text: &str) -> miette::Result<Self> {
+        let mut parser = QueryParser { text, position: 0 };
+        let query = parser.query().and_then(|query| match parser.peek() {
+            None => Ok(query),
+            Some(_) => Err(parser.error("expected the end of the query", None)),
+        });
+        query.map_err(|error| error.with_source_code(NamedSource::new("query", text.to_owned())))
+    }
+
+    /// Whether the node at an index matches.
+    fn matches(&self, nodes: &[Node], index: usize) -> bool {
+        let node = &nodes[index];
+        if self.kind != ANY_KIND && self.kind != node.kind {
+            return false;
+        }
+
+        self.constraints.iter().all(|constraint| match constraint {
+            Constraint::Field {
+                name,
+                pattern,
+                negated,
+            } => {
+                let found = node
+                    .fields
+                    .iter()
+                    .any(|(field, value)| field == name && glob(pattern, value));
+                found != *negated
+            }
+            Constraint::Inside(query) => {
+                std::iter::successors(node.parent, |&parent| nodes[parent].parent)
+                    .any(|parent| query.matches(nodes, parent))
+            }
+            Constraint::Has(query) => {
+                (index + 1..node.end).any(|child| query.matches(nodes, child))
+            }
+        })
+    }
+
+    /// The spans of the nodes of a program that match, in the order they're in the source.
+    pub fn find(&self, program: &Program) -> Vec<Span> {
+        let nodes = nodes(program);
+        (0..nodes.len())
+            .filter(|&index| self.matches(&nodes, index))
+            .map(|index| nodes[index].span)
+            .collect()
+    }
+}
+
+/// Whether text matches a pattern, where `*` matches any run of characters.
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Reads a query's text.
+struct QueryParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> QueryParser<'a> {
+    /// An error at the current position, spanning a character, or the end of the query.
+    fn error(&self, message: &str, help: Option<String>) -> miette::Report {
+        let len = self.rest().chars().next().map_or(0, char::len_utf8);
+        let label = LabeledSpan::at(self.position..self.position + len, "here");
+        match help {
+            Some(help) => miette::miette!(
+                labels = vec![label],
+                help = help,
+                "Invalid query: {message}"
+            ),
+            None => miette::miette!(labels = vec![label], "Invalid query: {message}"),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    /// The next character that isn't whitespace, skipping any before it.
+    fn peek(&mut self) -> Option<char> {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+        self.rest().chars().next()
+    }
+
+    /// Skip a character if it's next.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A name made of letters, digits and underscores.
+    fn name(&mut self) -> Option<&'a str> {
+        self.peek();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let start = self.position;
+        self.position += len;
+        (len > 0).then(|| &self.text[start..start + len])
+    }
+
+    /// `kind` or `kind(constraint, ...)`.
+    fn query(&mut self) -> miette::Result<Query> {
+        let start = self.position;
+        let kind = if self.eat('*') {
+            ANY_KIND
+        } else {
+            self.name()
+                .ok_or_else(|| self.error("expected a kind of node", None))?
+        }
+        .to_owned();
+        let fields = match KINDS.iter().find(|(name, _)| *name == kind) {
+            Some((_, fields)) => fields,
+            None if kind == ANY_KIND => &[] as &[&str],
+            None => {
+                self.position = start;
+                let kinds: Vec<_> = KINDS.iter().map(|(name, _)| *name).collect();
+                return Err(self.error(
+                    &format!("unknown kind of node `{kind}`"),
+                    Some(format!("kinds are `*` for any, and {}", kinds.join(", "))),
+                ));
+            }
+        };
+
+        let mut constraints = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                constraints.push(self.constraint(&kind, fields)?);
+                if !self.eat(',') && self.peek() != Some(')') {
+                    return Err(self.error("expected `,` or `)`", None));
+                }
+            }
+        }
+        Ok(Query { kind, constraints })
+    }
+
+    /// `field=value`, `field!=value`, `in=query` or `has=query`.
+    fn constraint(&mut self, kind: &str, fields: &[&str]) -> miette::Result<Constraint> {
+        let start = self.position;
+        let name = self
+            .name()
+            .ok_or_else(|| self.error("expected a field", None))?
+            .to_owned();
+        if name == "in" || name == "has" {
+            if !self.eat('=') {
+                return Err(self.error("expected `=`", None));
+            }
+            let query = self.query()?;
+            return Ok(if name == "in" {
+                Constraint::Inside(query)
+            } else {
+                Constraint::Has(query)
+            });
+        }
+
+        let known = if kind == ANY_KIND {
+            KINDS
+                .iter()
+                .any(|(_, fields)| fields.contains(&name.as_str()))
+        } else {
+            fields.contains(&name.as_str())
+        };
+        if !known {
+            self.position = start;
+            let help = if fields.is_empty() && kind != ANY_KIND {
+                format!("`{kind}` only has `in` and `has`")
+            } else if kind == ANY_KIND {
+                "use a field of any kind of node, or `in` and `has`".to_owned()
+            } else {
+                format!("`{kind}` has {}, `in` and `has`", fields.join(", "))
+            };
+            return Err(self.error(&format!("unknown field `{name}`"), Some(help)));
+        }
+
+        let negated = self.eat('!');
+        if !self.eat('=') {
+            return Err(self.error("expected `=` or `!=`", None));
+        }
+        Ok(Constraint::Field {
+            name,
+            pattern: self.value()?,
+            negated,
+        })
+    }
+
+    /// A string in double quotes, with `\"` and `\\` escapes, or text up to whitespace, `,` or
+    /// `)`, like `2` or `+=`.
+    fn value(&mut self) -> miette::Result<String> {
+        if !self.eat('"') {
+            let rest = self.rest();
+            let len = rest
+                .find(|c: char| c.is_whitespace() || c == ',' || c == ')')
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(self.error("expected a value", None));
+            }
+            let value = rest[..len].to_owned();
+            self.position += len;
+            return Ok(value);
+        }
+
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((at, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += at + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                    _ => {
+                        self.position += at;
+                        return Err(self.error(
+                            "unknown escape",
+                            Some("only `\\\"` and `\\\\` are escapes".to_owned()),
+                        ));
+                    }
+                },
+                c => value.push(c),
+            }
+        }
+        self.position = self.text.len();
+        Err(self.error("unterminated string", None))
+    }
+}
+
+/// A node of a program, in the order they're visited, with the nodes in it after it.
+struct Node {
+    kind: &'static str,
+    fields: Vec<(&'static str, String)>,
+    span: Span,
+
+    /// The index of the node it's in, if any.
+    parent: Option<usize>,
+
+    /// The index after the last node in it.
+    end: usize,
+}
+
+/// Every node of a program, each followed by the nodes in it.
+fn nodes(program: &Program) -> Vec<Node> {
+    let mut collector = Collector::default();
+    for item in &program.items {
+        collector.item(item);
+    }
+    collector.nodes
+}
+
+/// Visits a program's nodes, keeping the ones being visited the insides of.
+#[derive(Default)]
+struct Collector {
+    nodes: Vec<Node>,
+    open: Vec<usize>,
+}
+
+impl Collector {
+    /// Visit a node, with the nodes `inside` visits in it.
+    fn node(
+        &mut self,
+        kind: &'static str,
+        fields: Vec<(&'static str, String)>,
+        span: Span,
+        inside: impl FnOnce(&mut Self),
+    ) {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            kind,
+            fields,
+            span,
+            parent: self.open.last().copied(),
+            end: index + 1,
+        });
+        self.open.push(index);
+        inside(self);
+        self.open.pop();
+        self.nodes[index].end = self.nodes.len();
+    }
+
+    fn item(&mut self, item: &Item) {
+        let mut fields = vec![(
+            "vis",
+            match item.visibility {
+                Visibility::Private => "private",
+                Visibility::Public => "pub",
+            }
+            .to_owned(),
+        )];
+        fields.extend(
+            item.attributes
+                .iter()
+                .map(|attribute| ("attr", attribute.name.name.clone())),
+        );
+
+        match &item.kind {
+            ItemKind::Proc(proc) => {
+                fields.push(("name", proc.name.name.clone()));
+                fields.push(("ret", proc.return_type.kind.to_string()));
+                fields.push(("params", proc.parameters.len().to_string()));
+                self.node("proc", fields, item.span, |this| {
+                    for parameter in &proc.parameters {
+                        let fields = vec![
+                            ("name", parameter.name.name.clone()),
+                            ("type", parameter.ty.kind.to_string()),
+                        ];
+                        this.node("param", fields, parameter.span, |_| {});
+                    }
+                    this.block(&proc.body);
+                });
+            }
+            ItemKind::Mod(module) => {
+                fields.push(("name", module.name.name.clone()));
+                self.node("mod", fields, item.span, |this| {
+                    for item in &module.items {
+                        this.item(item);
+                    }
+                });
+            }
+            ItemKind::Const(constant) => {
+                fields.push(("name", constant.name.name.clone()));
+                fields.push(("type", constant.ty.kind.to_string()));
+                self.node("const", fields, item.span, |this| {
+                    this.expression(&constant.value);
+                });
+            }
+            ItemKind::Import(name) => {
+                fields.push(("name", name.name.clone()));
+                self.node("import", fields, item.span, |_| {});
+            }
+        }
+    }
+
+    /// Visit the statements of a block that's part of another node, like a loop's body.
+    fn block(&mut self, block: &Block) {
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::Let {
+                name,
+                ty,
+                initializer,
+            } => {
+                let mut fields = vec![("name", name.name.clone())];
+                fields.extend(ty.iter().map(|ty| ("type", ty.kind.to_string())));
+                self.node("let", fields, span, |this| this.expression(initializer));
+            }
+            StatementKind::Ret(value) => self.node("ret", Vec::new(), span, |this| {
+                if let Some(value) = value {
+                    this.expression(value);
+                }
+            }),
+            StatementKind::If {
+                condition,
+                then_block,
+                elif_branches,
+                else_block,
+            } => {
+                let fields = vec![("elifs", elif_branches.len().to_string())];
+                self.node("if", fields, span, |this| {
+                    this.expression(condition);
+                    this.block(then_block);
+                    for (condition, block) in elif_branches {
+                        this.expression(condition);
+                        this.block(block);
+                    }
+                    if let Some(block) = else_block {
+                        this.block(block);
+                    }
+                });
+            }
+            StatementKind::While { condition, body } => {
+                self.node("while", Vec::new(), span, |this| {
+                    this.expression(condition);
+                    this.block(body);
+                });
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.node("do_while", Vec::new(), span, |this| {
+                    this.block(body);
+                    this.expression(condition);
+                });
+            }
+            StatementKind::For {
+                initializer,
+                condition,
+                step,
+                body,
+            } => self.node("for", Vec::new(), span, |this| {
+                this.statement(initializer);
+                this.expression(condition);
+                this.expression(step);
+                this.block(body);
+            }),
+            StatementKind::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                let fields = vec![("name", binding.name.clone())];
+                self.node("for_in", fields, span, |this| {
+                    this.expression(iterable);
+                    this.block(body);
+                });
+            }
+            StatementKind::Block(block) => {
+                self.node("block", Vec::new(), span, |this| this.block(block));
+            }
+            StatementKind::Expression(expression) => self.expression(expression),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        let span = expression.span;
+        match &expression.kind {
+            ExpressionKind::Literal { kind, text } => {
+                let ty = match kind {
+                    LiteralKind::Character => "char",
+                    LiteralKind::String => "str",
+                    LiteralKind::Integer => "int",
+                    LiteralKind::Float => "float",
+                    LiteralKind::Boolean => "bool",
+                };
+                let fields = vec![("type", ty.to_owned()), ("text", text.clone())];
+                self.node("literal", fields, span, |_| {});
+            }
+            ExpressionKind::Ident(ident) => {
+                self.node("ident", vec![("name", ident.name.clone())], span, |_| {});
+            }
+            ExpressionKind::Path(path) => {
+                self.node("path", vec![("name", path.to_string())], span, |_| {});
+            }
+            ExpressionKind::Call { callee, arguments } => {
+                let mut fields = vec![("args", arguments.len().to_string())];
+                match &callee.kind {
+                    ExpressionKind::Ident(ident) => fields.push(("name", ident.name.clone())),
+                    ExpressionKind::Path(path) => fields.push(("name", path.to_string())),
+                    _ => {}
+                }
+                self.node("call", fields, span, |this| {
+                    this.expression(callee);
+                    for argument in arguments {
+                        this.expression(argument);
+                    }
+                });
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                let fields = vec![("op", operator.to_string())];
+                self.node("unary", fields, span, |this| this.expression(operand));
+            }
+            ExpressionKind::Binary {
+                lhs, operator, rhs, ..
+            } => {
+                let fields = vec![("op", operator.to_string())];
+                self.node("binary", fields, span, |this| {
+                    this.expression(lhs);
+                    this.expression(rhs);
+                });
+            }
+            ExpressionKind::Grouping(inner) => {
+                self.node("group", Vec::new(), span, |this| this.expression(inner));
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                let fields = vec![("arms", arms.len().to_string())];
+                self.node("match", fields, span, |this| {
+                    this.expression(scrutinee);
+                    for arm in arms {
+                        this.expression(&arm.body);
+                    }
+                });
+            }
+            ExpressionKind::Array(elements) => {
+                let fields = vec![("len", elements.len().to_string())];
+                self.node("array", fields, span, |this| {
+                    for element in elements {
+                        this.expression(element);
+                    }
+                });
+            }
+            ExpressionKind::Map(entries) => {
+                let fields = vec![("len", entries.len().to_string())];
+                self.node("map", fields, span, |this| {
+                    for (key, value) in entries {
+                        this.expression(key);
+                        this.expression(value);
+                    }
+                });
+            }
+            ExpressionKind::Index { array, index } => {
+                self.node("index", Vec::new(), span, |this| {
+                    this.expression(array);
+                    this.expression(index);
+                });
+            }
+            ExpressionKind::Cast { value, ty } => {
+                let fields = vec![("type", ty.kind.to_string())];
+                self.node("cast", fields, span, |this| this.expression(value));
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let fields = vec![("inclusive", inclusive.to_string())];
+                self.node("range", fields, span, |this| {
+                    this.expression(start);
+                    this.expression(end);
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = r#"proc helper(x: int) int {
+    println("helper");
+    ret x + 1;
+}
+
+proc main() int {
+    print("a");
+    println("b", 2);
+    let y: int = helper(2);
+    ret y;
+}
+"#;
+
+    /// The text of the nodes of [`PROGRAM`] a query finds.
+    fn found(query: &str) -> miette::Result<Vec<&'static str>> {
+        let tokens = lexer::lex(PROGRAM).expect("the program lexes");
+        let program = parser::parse(PROGRAM, tokens).expect("the program parses");
+        let spans = Query::parse(query)?.find(&program);
+        Ok(spans
+            .into_iter()
+            .map(|span| &PROGRAM[span.start..span.end])
+            .collect())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("print*", "println"));
+        assert!(glob("print*", "print"));
+        assert!(glob("*ln", "println"));
+        assert!(glob("p*n*n", "println"));
+        assert!(glob("*", ""));
+        assert!(!glob("print", "println"));
+        assert!(!glob("*x*", "println"));
+    }
+
+    #[test]
+    fn test_find_fields() -> miette::Result<()> {
+        assert_eq!(
+            found(r#"call(name="print*")"#)?,
+            [
+                r#"println("helper")"#,
+                r#"print("a")"#,
+                r#"println("b", 2)"#
+            ]
+        );
+        assert_eq!(found("call(name=println, args=2)")?, [r#"println("b", 2)"#]);
+        assert_eq!(found("call(name!=print*)")?, ["helper(2)"]);
+        assert_eq!(found("let(type=int)")?, ["let y: int = helper(2);"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_in_and_has() -> miette::Result<()> {
+        assert_eq!(found("ret(in=proc(name=main))")?, ["ret y;"]);
+        assert_eq!(found("literal(in=call(name=helper))")?, ["2"]);
+        assert_eq!(
+            found("proc(has=call(name=helper))")?.len(),
+            1,
+            "only `main` calls `helper`"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (query, error) in [
+            ("procedure", "unknown kind of node `procedure`"),
+            ("call(nmae=x)", "unknown field `nmae`"),
+            ("ret(name=x)", "unknown field `name`"),
+            ("call(name=x", "expected `,` or `)`"),
+            (r#"call(name="x)"#, "unterminated string"),
+            ("call(name=x) extra", "expected the end of the query"),
+        ] {
+            let report = Query::parse(query).unwrap_err();
+            assert_eq!(
+                report.to_string(),
+                format!("Invalid query: {error}"),
+                "{query}"
+            );
+        }
+    }
+}
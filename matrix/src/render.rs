@@ -0,0 +1,123 @@
+//! How diagnostics are drawn for humans. Both styles are miette report handlers, so everything
+//! that prints a [`miette::Report`] draws it in the style `--diagnostic-style` picks, from the
+//! same code, labels, help and related diagnostics.
+
+use ariadne::{ColorGenerator, Config, Label, ReportKind};
+use miette::{Diagnostic, ReportHandler, Severity, SourceCode};
+use std::fmt;
+
+/// The handler drawing reports in a style.
+pub fn handler(ariadne: bool, color: bool, width: usize) -> Box<dyn ReportHandler> {
+    if ariadne {
+        Box::new(Ariadne { color })
+    } else {
+        Box::new(
+            miette::MietteHandlerOpts::new()
+                .color(color)
+                .width(width)
+                .build(),
+        )
+    }
+}
+
+/// Draws reports with ariadne, which draws labels as arrows pointing into the source.
+struct Ariadne {
+    color: bool,
+}
+
+impl Ariadne {
+    /// Draw a diagnostic, then each diagnostic related to it. Related diagnostics without a
+    /// source of their own are in the source of the one they're related to.
+    fn render(
+        &self,
+        diagnostic: &dyn Diagnostic,
+        source: Option<&dyn SourceCode>,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let source = diagnostic.source_code().or(source);
+        let kind = match diagnostic.severity() {
+            Some(Severity::Warning) => ReportKind::Warning,
+            Some(Severity::Advice) => ReportKind::Advice,
+            Some(Severity::Error) | None => ReportKind::Error,
+        };
+
+        // ariadne reads lines from whole files, and counts offsets in chars, so each label's
+        // file is read in full, with the label moved into it.
+        let mut files: Vec<(String, String)> = Vec::new();
+        let mut labels = Vec::new();
+        let mut start = None;
+        let mut colors = ColorGenerator::new();
+        for label in diagnostic.labels().into_iter().flatten() {
+            let Some(contents) = source
+                .and_then(|source| source.read_span(label.inner(), usize::MAX, usize::MAX).ok())
+            else {
+                continue;
+            };
+            let name = contents.name().unwrap_or("<unknown>").to_owned();
+            let text = String::from_utf8_lossy(contents.data()).into_owned();
+            let offset = label.offset() - contents.span().offset();
+            let chars = |offset: usize| {
+                text.get(..offset)
+                    .map_or(0, |before| before.chars().count())
+            };
+            let span = (name.clone(), chars(offset)..chars(offset + label.len()));
+            start.get_or_insert_with(|| (name.clone(), span.1.start));
+
+            let mut drawn = Label::new(span);
+            if self.color {
+                drawn = drawn.with_color(colors.next());
+            }
+            if let Some(message) = label.label() {
+                drawn = drawn.with_message(message);
+            }
+            labels.push(drawn);
+            if !files.iter().any(|(file, _)| *file == name) {
+                files.push((name, text));
+            }
+        }
+
+        // A report is placed in a file even without labels, so one without them gets an empty
+        // file to be in.
+        let (file, offset) = start.unwrap_or_default();
+        if labels.is_empty() {
+            files.push((file.clone(), String::new()));
+        }
+        let mut report = ariadne::Report::build(kind, file, offset)
+            .with_message(diagnostic.to_string())
+            .with_labels(labels)
+            .with_config(Config::default().with_color(self.color));
+        if let Some(code) = diagnostic.code() {
+            report = report.with_code(code);
+        }
+        if let Some(help) = diagnostic.help() {
+            report = report.with_help(help);
+        }
+        let causes: Vec<_> = std::iter::successors(diagnostic.source(), |cause| cause.source())
+            .map(ToString::to_string)
+            .collect();
+        if !causes.is_empty() {
+            report = report.with_note(format!("caused by: {}", causes.join(": ")));
+        }
+
+        let mut drawn = Vec::new();
+        report
+            .finish()
+            .write(ariadne::sources(files), &mut drawn)
+            .map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&drawn))?;
+
+        for related in diagnostic.related().into_iter().flatten() {
+            self.render(related, source, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl ReportHandler for Ariadne {
+    fn debug(&self, diagnostic: &dyn Diagnostic, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return fmt::Debug::fmt(diagnostic, f);
+        }
+        self.render(diagnostic, None, f)
+    }
+}
@@ -0,0 +1,538 @@
+//! The `repl` command, which runs statements, expressions and items as they're entered.
+//!
+//! Programs are lowered whole, so the REPL keeps the source of every item entered so far and
+//! lowers it again with each input. Statements and expressions become the body of a procedure
+//! taking every variable defined so far as a parameter. It's run with the interpreter, and the
+//! values its locals are left with are kept for the next input.
+//!
+//! On a terminal, lines are edited with [`Editor`], which completes keywords and the names in
+//! scope after the last input.
+//!
+//! `:save` writes a session to a file as source: its items, then a `let` for each of its
+//! variables. `:load` and `--load` run a file as if its lines were entered one at a time, so a
+//! saved session is resumed by loading it. Arrays and maps shared between variables are saved as
+//! copies.
+
+use crate::{
+    editor::{Editor, Line},
+    map_err_to_report, map_sink_to_report,
+};
+use diagnostics::{DiagnosticSink, Level, Levels};
+use hir::{LowerDiagnostic, ModuleId, StmtKind, Ty};
+use interp::Value;
+use lexer::token::{IdentKind, Keyword, TokenKind};
+use miette::NamedSource;
+use std::{
+    fmt::Write,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+const SOURCE_NAME: &str = "<repl>";
+
+/// The procedure inputs of statements and expressions are run as.
+const ENTRY: &str = "__repl_input";
+
+/// A variable defined by an earlier input.
+struct Binding {
+    name: String,
+    ty: Ty,
+    value: Value,
+}
+
+struct Repl {
+    /// The source of every item entered so far.
+    items: String,
+    bindings: Vec<Binding>,
+    overflow: hir::Overflow,
+    levels: Levels,
+    options: interp::Options,
+    sandbox: bool,
+
+    /// The `rand` module's generator, kept between inputs so seeding it lasts.
+    rng: interp::Rng,
+
+    /// The names in scope at the top level after the last input that lowered.
+    names: Vec<String>,
+}
+
+/// Whether an input starting with a token declares items rather than running statements.
+fn starts_item(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::At
+            | TokenKind::Ident(IdentKind::Keyword(
+                Keyword::Proc | Keyword::Pub | Keyword::Mod | Keyword::Const | Keyword::Import
+            ))
+    )
+}
+
+/// Whether a type can be written in the source, so a variable of it can be a parameter.
+fn is_writable(ty: Ty) -> bool {
+    match ty {
+        Ty::Char => false,
+        Ty::Array(element) => is_writable(*element),
+        Ty::Map(key, value) => is_writable(*key) && is_writable(*value),
+        _ => true,
+    }
+}
+
+/// Whether some source opens more brackets than it closes, so more lines are needed to finish
+/// it. Source that doesn't lex is complete, so its errors are reported straight away.
+fn is_unfinished(source: &str) -> bool {
+    let Ok(tokens) = lexer::lex(source) else {
+        return false;
+    };
+
+    lexer::delimiter_depth(&tokens) > 0
+}
+
+/// The source of a value that gives it back, or `None` if it has none, like an infinite float.
+fn literal(value: &Value) -> Option<String> {
+    Some(match value {
+        // Saved variables are declared with their types, which their literals take.
+        Value::Int(n, ty) => ty.value(*n).to_string(),
+        Value::Float(x) if x.is_finite() => format!("{x:?}"),
+        Value::Bool(b) => b.to_string(),
+        Value::Str(s) => {
+            let mut literal = String::from('"');
+            for c in s.chars() {
+                match c {
+                    '\n' => literal.push_str("\\n"),
+                    '\t' => literal.push_str("\\t"),
+                    '\r' => literal.push_str("\\r"),
+                    '\0' => literal.push_str("\\0"),
+                    '\\' | '"' => {
+                        literal.push('\\');
+                        literal.push(c);
+                    }
+                    c => literal.push(c),
+                }
+            }
+            literal.push('"');
+            literal
+        }
+        Value::Array(elements) => {
+            let elements = elements
+                .borrow()
+                .iter()
+                .map(literal)
+                .collect::<Option<Vec<_>>>()?;
+            format!("[{}]", elements.join(", "))
+        }
+        Value::Map(entries) if entries.borrow().is_empty() => "[:]".to_owned(),
+        Value::Map(entries) => {
+            let entries = entries.borrow();
+            let mut keys: Vec<_> = entries.keys().collect();
+            keys.sort();
+            let entries = keys
+                .into_iter()
+                .map(|key| {
+                    Some(format!(
+                        "{}: {}",
+                        literal(&key.value())?,
+                        literal(&entries[key])?
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            format!("[{}]", entries.join(", "))
+        }
+        Value::Float(_) | Value::Char(_) | Value::Proc(_) | Value::Void => return None,
+    })
+}
+
+/// Read an input, prompting for more lines while its brackets are unbalanced. Returns `None`
+/// once the standard input ends. Interrupting drops the input, leaving it empty.
+fn read_input(editor: &mut Editor, repl: &Repl) -> io::Result<Option<String>> {
+    let mut input = String::new();
+    let mut prompt = "> ";
+    loop {
+        let line = match editor.read_line(prompt, |word| repl.completions(word))? {
+            Line::Entered(line) => line,
+            Line::Interrupted => return Ok(Some(String::new())),
+            Line::Eof => return Ok((!input.is_empty()).then_some(input)),
+        };
+        editor.add_history(&line);
+        input.push_str(&line);
+        input.push('\n');
+        if !is_unfinished(&input) {
+            return Ok(Some(input));
+        }
+        prompt = "... ";
+    }
+}
+
+/// The source an input is lowered as: the items entered so far followed by the input, which
+/// may be wrapped in the entry procedure.
+struct Source {
+    text: String,
+
+    /// Where the items end.
+    items_end: usize,
+    input: Range<usize>,
+}
+
+impl Source {
+    /// The source to report diagnostics against, with the code wrapping the input blanked out,
+    /// so only code that was entered shows around their labels.
+    fn shown(&self) -> NamedSource {
+        let text = self
+            .text
+            .char_indices()
+            .flat_map(|(i, c)| {
+                let generated =
+                    (self.items_end..self.input.start).contains(&i) || i >= self.input.end;
+                let blank = c != '\n' && generated;
+                let shown = if blank {
+                    " ".repeat(c.len_utf8())
+                } else {
+                    c.into()
+                };
+                shown.into_bytes()
+            })
+            .collect();
+        let text = String::from_utf8(text).expect("blanking keeps the text UTF-8");
+        NamedSource::new(SOURCE_NAME, text)
+    }
+
+    /// Print warnings about the input. Earlier items were warned about when they were entered.
+    fn warn(&self, warnings: Vec<LowerDiagnostic>) {
+        use miette::Diagnostic;
+
+        let about_input = warnings.into_iter().filter(|warning| {
+            warning
+                .labels()
+                .into_iter()
+                .flatten()
+                .any(|label| label.offset() >= self.input.start)
+        });
+        for warning in about_input {
+            let report = diagnostics::report(warning, self.shown());
+            eprintln!("{report:?}");
+        }
+    }
+}
+
+impl Repl {
+    /// The keywords and names a word could be completed to.
+    fn completions(&self, word: &str) -> Vec<String> {
+        lexer::keywords()
+            .map(str::to_owned)
+            .chain(self.names.iter().filter(|name| *name != ENTRY).cloned())
+            .chain(self.bindings.iter().map(|binding| binding.name.clone()))
+            .filter(|name| name.starts_with(word))
+            .collect()
+    }
+
+    /// Lex, parse and lower the source of an input, remembering the names in scope.
+    fn lower(&mut self, source: &Source, levels: Levels) -> miette::Result<hir::Lowered> {
+        let text = &source.text;
+        let tokens = map_sink_to_report(lexer::lex(text), source.shown())?;
+        let ast = map_sink_to_report(parser::parse(text, tokens), source.shown())?;
+        let options = hir::LowerOptions {
+            overflow: self.overflow,
+            sandbox: self.sandbox,
+            levels,
+            ..Default::default()
+        };
+        let lowered = map_sink_to_report(hir::lower_with(&ast, &options), source.shown())?;
+        self.names.clone_from(&lowered.names);
+        Ok(lowered)
+    }
+
+    /// Keep a variable for later inputs, replacing any earlier one with its name.
+    fn define(&mut self, name: &str, ty: Ty, value: Value) {
+        self.bindings.retain(|binding| binding.name != name);
+        if !is_writable(ty) {
+            eprintln!("note: `{name}` isn't kept for later inputs, since it's a `{ty}`");
+            return;
+        }
+
+        self.bindings.push(Binding {
+            name: name.to_owned(),
+            ty,
+            value,
+        });
+    }
+
+    /// Run a REPL command, an input starting with `:`.
+    fn command(&mut self, command: &str) -> miette::Result<()> {
+        let (name, path) = command.split_once(' ').unwrap_or((command, ""));
+        let path = Path::new(path.trim());
+        match name {
+            ":save" | ":load" if path.as_os_str().is_empty() => Err(miette::miette!(
+                help = format!("write the file after it, like `{name} session.mtx`"),
+                "`{name}` needs a file"
+            )),
+            ":save" => self.save(path),
+            ":load" => self.load(path),
+            _ => Err(miette::miette!(
+                help = "use `:save FILE` or `:load FILE`",
+                "Unknown command `{name}`"
+            )),
+        }
+    }
+
+    /// Write the session's items and variables to a file, as source that defines them again.
+    fn save(&self, path: &Path) -> miette::Result<()> {
+        let mut text = String::from("// A REPL session, which `:load` and `--load` resume.\n");
+        text.push_str(&self.items);
+        for binding in &self.bindings {
+            let Some(value) = literal(&binding.value) else {
+                eprintln!(
+                    "note: `{}` isn't saved, since its value can't be written",
+                    binding.name
+                );
+                continue;
+            };
+            writeln!(text, "let {}: {} = {value};", binding.name, binding.ty).unwrap();
+        }
+        fs::write(path, text)
+            .map_err(|error| miette::miette!("Cannot write `{}`: {error}", path.display()))
+    }
+
+    /// Run the inputs in a file, stopping at the first that fails.
+    fn load(&mut self, path: &Path) -> miette::Result<()> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| miette::miette!("Cannot read `{}`: {error}", path.display()))?;
+        let mut input = String::new();
+        for line in text.lines() {
+            input.push_str(line);
+            input.push('\n');
+            if is_unfinished(&input) {
+                continue;
+            }
+            // Lines with nothing but comments aren't inputs.
+            if !lexer::lex(&input).is_ok_and(|tokens| tokens.is_empty()) {
+                self.eval(&input)?;
+            }
+            input.clear();
+        }
+        if !input.trim().is_empty() {
+            self.eval(&input)?;
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, input: &str) -> miette::Result<()> {
+        let input = input.trim_end();
+        let tokens = map_sink_to_report(
+            lexer::lex(input),
+            NamedSource::new(SOURCE_NAME, input.to_owned()),
+        )?;
+        if tokens.first().is_some_and(|token| starts_item(token.kind)) {
+            let source = Source {
+                text: format!("{}{input}\n", self.items),
+                items_end: self.items.len(),
+                input: self.items.len()..self.items.len() + input.len(),
+            };
+            source.warn(self.lower(&source, self.levels.clone())?.warnings);
+            self.items = source.text;
+            return Ok(());
+        }
+
+        // An input ending in an expression without a semicolon prints the expression's value.
+        let trailing_expr = !input.ends_with([';', '}']);
+        let params = self
+            .bindings
+            .iter()
+            .map(|binding| format!("{}: {}", binding.name, binding.ty))
+            .collect::<Vec<_>>();
+        let prefix = format!(
+            "{}proc {ENTRY}({}) void {{\n",
+            self.items,
+            params.join(", ")
+        );
+        let end = if trailing_expr { ";" } else { "" };
+        let source = Source {
+            text: format!("{prefix}{input}{end}\n}}\n"),
+            items_end: self.items.len(),
+            input: prefix.len()..prefix.len() + input.len(),
+        };
+        // The trailing expression is lowered as a statement, which is warned about as discarding
+        // its value, so warnings are only made errors once it's returned.
+        let mut levels = self.levels.clone();
+        if trailing_expr {
+            for code in ["hir::no_effect", "hir::unused_must_use"] {
+                levels.set(code, Level::Warn);
+            }
+        }
+        let hir::Lowered {
+            mut program,
+            mut warnings,
+            ..
+        } = self.lower(&source, levels)?;
+
+        let entry = program
+            .procs
+            .iter_mut()
+            .find(|proc| proc.module == ModuleId::ROOT && proc.name == ENTRY)
+            .expect("the entry procedure was lowered");
+        let entry_id = entry.id;
+
+        // Return the trailing expression's value rather than discarding it, which also
+        // means it has an effect.
+        let returned = entry.body.stmts.last_mut().filter(|stmt| {
+            trailing_expr && matches!(&stmt.kind, StmtKind::Expr(expr) if expr.ty != Ty::Void)
+        });
+        if let Some(stmt) = returned {
+            let StmtKind::Expr(expr) = std::mem::replace(&mut stmt.kind, StmtKind::Ret(None))
+            else {
+                unreachable!("only expression statements are returned");
+            };
+            let span = expr.span;
+            warnings.retain(|warning| {
+                !matches!(
+                    warning,
+                    LowerDiagnostic::NoEffect(warned)
+                        | LowerDiagnostic::UnusedMustUse(_, warned) if *warned == span
+                )
+            });
+            stmt.kind = StmtKind::Ret(Some(expr));
+        }
+        let mut sink = DiagnosticSink::with_levels(self.levels.clone());
+        for warning in warnings {
+            sink.push_diagnostic(warning);
+        }
+        if sink.has_errors() {
+            return map_sink_to_report(Err(sink), source.shown());
+        }
+        source.warn(sink.into_diagnostics());
+
+        let args = self
+            .bindings
+            .iter()
+            .map(|binding| binding.value.clone())
+            .collect();
+        let result = {
+            let (mut input, mut output) = (io::stdin().lock(), io::stdout().lock());
+            let io = interp::Io::new(&mut input, &mut output).with_rng(&mut self.rng);
+            let io = if self.sandbox { io.sandboxed() } else { io };
+            interp::call_keeping_locals(&program, entry_id, args, io, self.options)
+        };
+        let (value, locals) = map_err_to_report(result, source.shown())?;
+
+        // Keep assignments to earlier variables, and the variables the input defined.
+        let entry = program.proc(entry_id);
+        for (binding, param) in self.bindings.iter_mut().zip(&entry.params) {
+            binding.value = locals[param.0 as usize].clone();
+        }
+        for stmt in &entry.body.stmts {
+            if let StmtKind::Let { local, .. } = &stmt.kind {
+                let declared = entry.local(*local);
+                self.define(
+                    &declared.name,
+                    declared.ty,
+                    locals[local.0 as usize].clone(),
+                );
+            }
+        }
+
+        match value {
+            Value::Void => {}
+            // Quote strings and chars, so they can be told apart from other values.
+            Value::Str(s) => println!("{s:?}"),
+            Value::Char(c) => println!("{c:?}"),
+            value => println!("{value}"),
+        }
+        Ok(())
+    }
+}
+
+/// Run the files to load, then read inputs from the standard input until it ends, running each
+/// one and reporting any errors without stopping.
+pub fn run(
+    load: &[PathBuf],
+    overflow: hir::Overflow,
+    levels: Levels,
+    options: interp::Options,
+    sandbox: bool,
+) -> miette::Result<()> {
+    // The interpreter recurses on the host's stack, so the REPL runs on a thread with enough
+    // stack for the call depth.
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(options.interpreter_stack_size())
+            .spawn_scoped(scope, || {
+                let mut repl = Repl {
+                    items: String::new(),
+                    bindings: Vec::new(),
+                    overflow,
+                    levels,
+                    options,
+                    sandbox,
+                    rng: interp::Rng::random(),
+                    names: Vec::new(),
+                };
+                for path in load {
+                    if let Err(report) = repl.load(path) {
+                        eprintln!("{report:?}");
+                    }
+                }
+                let mut editor = Editor::new();
+                while let Some(input) = read_input(&mut editor, &repl)? {
+                    let input = input.trim();
+                    if input.is_empty() {
+                        continue;
+                    }
+                    let result = if input.starts_with(':') {
+                        repl.command(input)
+                    } else {
+                        repl.eval(input)
+                    };
+                    if let Err(report) = result {
+                        eprintln!("{report:?}");
+                    }
+                }
+                println!();
+                io::Result::Ok(())
+            })
+            .map_err(|error| {
+                miette::miette!(
+                    help = "lower `--max-call-depth`",
+                    "Couldn't make a stack for {} nested calls: {error}",
+                    options.max_call_depth
+                )
+            })?
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            .map_err(|error| miette::miette!("Cannot read input: {error}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_unfinished_inputs() {
+        assert!(is_unfinished("proc f() void {\n"));
+        assert!(is_unfinished("let xs = [1,\n"));
+        assert!(!is_unfinished("proc f() void {}\n"));
+        assert!(!is_unfinished("let x = 1;\n"));
+        // Errors are reported as soon as the input is entered.
+        assert!(!is_unfinished("let s = \"{\n"));
+    }
+
+    #[test]
+    fn test_literals() {
+        fn array(values: Vec<Value>) -> Value {
+            Value::Array(Rc::new(RefCell::new(values)))
+        }
+
+        assert_eq!(literal(&Value::int(-3)).as_deref(), Some("-3"));
+        assert_eq!(literal(&Value::Float(1.0)).as_deref(), Some("1.0"));
+        assert_eq!(
+            literal(&Value::Str("a \"b\"\\\n".into())).as_deref(),
+            Some(r#""a \"b\"\\\n""#)
+        );
+        assert_eq!(
+            literal(&array(vec![array(vec![Value::Bool(true)]), array(vec![])])).as_deref(),
+            Some("[[true], []]")
+        );
+        assert_eq!(literal(&Value::Float(f64::INFINITY)), None);
+        assert_eq!(literal(&array(vec![Value::Char('c')])), None);
+    }
+}
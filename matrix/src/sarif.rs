@@ -0,0 +1,256 @@
+//! Diagnostics as a SARIF 2.1.0 log, which code scanning tools can show, for
+//! `--message-format sarif`.
+//!
+//! A diagnostic's notes are related locations of its result if they point into the source, and
+//! otherwise lines after its message.
+
+use miette::{Diagnostic, LabeledSpan, Report, Severity, SourceCode};
+use std::fmt::Write;
+
+/// Quote a string for JSON.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Where a label is: the file it's in, and the lines and columns it covers, counting from 1.
+pub struct Region<'a> {
+    pub file: String,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub message: Option<&'a str>,
+}
+
+/// The region of a label, or `None` if it isn't in the source.
+pub fn region<'a>(source: &dyn SourceCode, label: &'a LabeledSpan) -> Option<Region<'a>> {
+    let contents = source.read_span(label.inner(), 0, 0).ok()?;
+    let name = contents.name().unwrap_or("<unknown>");
+
+    // Columns count chars, but miette counts bytes, so read the start of the label's line too.
+    let before = match contents.column() {
+        0 => String::new(),
+        column => {
+            let start = (label.offset() - column, column).into();
+            let line = source.read_span(&start, 0, 0).ok()?;
+            String::from_utf8_lossy(line.data()).into_owned()
+        }
+    };
+    let covered = String::from_utf8_lossy(contents.data().get(..label.len())?);
+
+    let start_line = contents.line() + 1;
+    let start_column = before.chars().count() + 1;
+    let (end_line, end_column) = match covered.rsplit_once('\n') {
+        Some((_, last)) => (
+            start_line + covered.matches('\n').count(),
+            last.chars().count() + 1,
+        ),
+        None => (start_line, start_column + covered.chars().count()),
+    };
+    Some(Region {
+        file: name.to_owned(),
+        start_line,
+        start_column,
+        end_line,
+        end_column,
+        message: label.label(),
+    })
+}
+
+/// The location of a label as SARIF, or `None` if it isn't in the source.
+fn location(source: &dyn SourceCode, label: &LabeledSpan) -> Option<String> {
+    let region = region(source, label)?;
+    let message = region.message.map_or_else(String::new, |text| {
+        format!(", \"message\": {{ \"text\": {} }}", json_string(text))
+    });
+    Some(format!(
+        "{{ \"physicalLocation\": {{ \"artifactLocation\": {{ \"uri\": {} }}, \"region\": {{ \"startLine\": {}, \"startColumn\": {}, \"endLine\": {}, \"endColumn\": {} }} }}{message} }}",
+        json_string(&region.file),
+        region.start_line,
+        region.start_column,
+        region.end_line,
+        region.end_column
+    ))
+}
+
+/// A diagnostic to export, with the source its labels point into and its notes.
+pub struct Exported<'a> {
+    pub diagnostic: &'a dyn Diagnostic,
+    pub source: Option<&'a dyn SourceCode>,
+    pub notes: Vec<&'a dyn Diagnostic>,
+}
+
+impl Exported<'_> {
+    /// The text of each note, and its first label with the text as the label's.
+    pub fn note_labels(&self) -> impl Iterator<Item = (String, Option<LabeledSpan>)> + '_ {
+        self.notes.iter().map(|note| {
+            let text = note.to_string();
+            let label = note
+                .labels()
+                .into_iter()
+                .flatten()
+                .next()
+                .map(|label| LabeledSpan::new_with_span(Some(text.clone()), *label.inner()));
+            (text, label)
+        })
+    }
+}
+
+/// Add a diagnostic to export. Diagnostics that only gather others, like the parser's and the
+/// HIR's failures, are replaced by the diagnostics they gather, while notes stay with theirs.
+fn collect<'a>(
+    diagnostic: &'a dyn Diagnostic,
+    source: Option<&'a dyn SourceCode>,
+    exported: &mut Vec<Exported<'a>>,
+) {
+    let source = diagnostic.source_code().or(source);
+    let (notes, gathered): (Vec<_>, Vec<_>) = diagnostic
+        .related()
+        .into_iter()
+        .flatten()
+        .partition(|related| diagnostics::is_note(*related));
+    if gathered.is_empty() {
+        exported.push(Exported {
+            diagnostic,
+            source,
+            notes,
+        });
+    }
+    for diagnostic in gathered {
+        collect(diagnostic, source, exported);
+    }
+}
+
+/// Every diagnostic some reports hold, in their order.
+pub fn exported(reports: &[Report]) -> Vec<Exported<'_>> {
+    let mut exported = Vec::new();
+    for report in reports {
+        collect(report.as_ref(), None, &mut exported);
+    }
+    exported
+}
+
+/// A diagnostic as a SARIF result. Its first label is where it is, and any others, and its
+/// notes, are related locations.
+fn result(exported: &Exported<'_>) -> String {
+    let Exported {
+        diagnostic, source, ..
+    } = *exported;
+    let level = match diagnostic.severity() {
+        Some(Severity::Warning) => "warning",
+        Some(Severity::Advice) => "note",
+        Some(Severity::Error) | None => "error",
+    };
+    let mut fields = Vec::new();
+    if let Some(code) = diagnostic.code() {
+        fields.push(format!("\"ruleId\": {}", json_string(&code.to_string())));
+    }
+    fields.push(format!("\"level\": \"{level}\""));
+
+    let mut message = diagnostic.to_string();
+    let mut notes = Vec::new();
+    for (text, label) in exported.note_labels() {
+        match label.and_then(|label| location(source?, &label)) {
+            Some(location) => notes.push(location),
+            None => write!(message, "\nnote: {text}").unwrap(),
+        }
+    }
+    fields.push(format!(
+        "\"message\": {{ \"text\": {} }}",
+        json_string(&message)
+    ));
+
+    let mut locations = source
+        .map_or_else(Vec::new, |source| {
+            diagnostic
+                .labels()
+                .into_iter()
+                .flatten()
+                .filter_map(|label| location(source, &label))
+                .collect()
+        })
+        .into_iter();
+    if let Some(location) = locations.next() {
+        fields.push(format!("\"locations\": [{location}]"));
+    }
+    let related: Vec<_> = locations.chain(notes).collect();
+    if !related.is_empty() {
+        fields.push(format!("\"relatedLocations\": [{}]", related.join(", ")));
+    }
+
+    format!("    {{ {} }}", fields.join(", "))
+}
+
+/// A SARIF log of every diagnostic some reports hold.
+pub fn log(reports: &[Report]) -> String {
+    let results: Vec<_> = exported(reports).iter().map(result).collect();
+
+    format!(
+        "{{\n  \"$schema\": \"https://json.schemastore.org/sarif-2.1.0.json\",\n  \"version\": \"2.1.0\",\n  \"runs\": [\n    {{\n      \"tool\": {{ \"driver\": {{ \"name\": \"mtxc\", \"version\": \"{}\" }} }},\n      \"columnKind\": \"unicodeCodePoints\",\n      \"results\": [{}\n      ]\n    }}\n  ]\n}}\n",
+        env!("CARGO_PKG_VERSION"),
+        results
+            .iter()
+            .map(|result| format!("\n    {result}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miette::NamedSource;
+
+    #[test]
+    fn test_json_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\" \\ c"), r#""a \"b\" \\ c""#);
+        assert_eq!(json_string("tab\tline\n"), r#""tab\u0009line\u000a""#);
+    }
+
+    #[test]
+    fn test_regions_count_chars() {
+        let source = NamedSource::new("a.mtx", "let é = 1;\nlet x = bad;\n".to_owned());
+        let one = LabeledSpan::at(9..10, "one");
+        let within = region(&source, &one).unwrap();
+        assert_eq!(within.file, "a.mtx");
+        assert_eq!(
+            (within.start_line, within.start_column),
+            (1, 9),
+            "columns count the two-byte `é` once"
+        );
+        assert_eq!((within.end_line, within.end_column), (1, 10));
+        assert_eq!(within.message, Some("one"));
+
+        let two = LabeledSpan::at(9..23, "two");
+        let across = region(&source, &two).unwrap();
+        assert_eq!((across.start_line, across.start_column), (1, 9));
+        assert_eq!((across.end_line, across.end_column), (2, 12));
+    }
+
+    #[test]
+    fn test_log_results() {
+        let report = miette::miette!(
+            code = "matrix::test",
+            severity = Severity::Warning,
+            labels = vec![LabeledSpan::at(4..5, "this")],
+            "Something's off"
+        )
+        .with_source_code(NamedSource::new("a.mtx", "let x = 1;\n".to_owned()));
+        let log = log(&[report]);
+        assert!(log.contains("\"version\": \"2.1.0\""));
+        assert!(log.contains(
+            "{ \"ruleId\": \"matrix::test\", \"level\": \"warning\", \"message\": { \"text\": \"Something's off\" }, \"locations\": [{ \"physicalLocation\": { \"artifactLocation\": { \"uri\": \"a.mtx\" }, \"region\": { \"startLine\": 1, \"startColumn\": 5, \"endLine\": 1, \"endColumn\": 6 } }, \"message\": { \"text\": \"this\" } }] }"
+        ));
+    }
+}
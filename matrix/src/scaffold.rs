@@ -0,0 +1,94 @@
+//! The `new` and `init` commands, which set up a project that `build` can build: a manifest, a
+//! source directory with a program printing a greeting, and a `.gitignore` for the output
+//! directory.
+
+use crate::manifest;
+use miette::IntoDiagnostic;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The program a project starts with.
+const MAIN: &str = "\
+proc main() void {
+    println(\"Hello, world!\");
+}
+";
+
+/// Whether a project's name is one its executable can be named, and that the manifest can
+/// hold without quoting.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The files of a project, by their paths in its directory.
+fn files(name: &str) -> [(PathBuf, String); 3] {
+    [
+        (
+            PathBuf::from(manifest::FILE_NAME),
+            format!("[package]\nname = \"{name}\"\n"),
+        ),
+        (PathBuf::from("src").join("main.mtx"), MAIN.to_owned()),
+        (PathBuf::from(".gitignore"), "/build/\n".to_owned()),
+    ]
+}
+
+/// Set up a project in a directory, named after it unless given a name. Files the directory
+/// already has are left as they are, but it can't already be a project.
+pub fn init(dir: &Path, name: Option<&str>) -> miette::Result<()> {
+    let dir = dir.canonicalize().into_diagnostic()?;
+    if dir.join(manifest::FILE_NAME).exists() {
+        return Err(miette::miette!(
+            "`{}` already has a `{}`",
+            dir.display(),
+            manifest::FILE_NAME
+        ));
+    }
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_owned(),
+    };
+    if !valid_name(&name) {
+        return Err(miette::miette!(
+            help = "choose another with `--name`, using letters, digits, `-` and `_`",
+            "`{name}` can't be a project's name"
+        ));
+    }
+
+    for (path, text) in files(&name) {
+        let path = dir.join(path);
+        if path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).into_diagnostic()?;
+        }
+        fs::write(&path, text).into_diagnostic()?;
+    }
+    println!("created project `{name}` in `{}`", dir.display());
+    Ok(())
+}
+
+/// Set up a project in a new directory of the current one, with the project's name.
+pub fn new(name: &str) -> miette::Result<()> {
+    if !valid_name(name) {
+        return Err(miette::miette!(
+            help = "use letters, digits, `-` and `_`",
+            "`{name}` can't be a project's name"
+        ));
+    }
+    let dir = Path::new(name);
+    if dir.exists() {
+        return Err(miette::miette!("`{name}` already exists"));
+    }
+    fs::create_dir(dir).into_diagnostic()?;
+    init(dir, Some(name))
+}
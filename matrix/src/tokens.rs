@@ -0,0 +1,130 @@
+//! Tokens as JSON or CSV, for `--emit tokens=json` and `--emit tokens=csv`, so tools like
+//! grammars and editor tokenizers can be checked against what the lexer makes.
+//!
+//! Each token has the file it's in, its kind as the lexer names it, like `Ident(NonReserved)`,
+//! its text, and the byte range of the text in the file, from `start` up to `end`.
+
+use crate::sarif::json_string;
+use lexer::token::Token;
+use matrix_compiler::Sources;
+use std::fmt::Write;
+
+/// The tokens of each of a program's files as a JSON array of objects.
+pub fn json(sources: &Sources, tokens: &[Vec<Token>]) -> String {
+    let mut json = String::from("[");
+    let mut first = true;
+    for (file, tokens) in sources.files().zip(tokens) {
+        for token in tokens {
+            let text = &file.text[token.span.start..token.span.end];
+            if !first {
+                json.push(',');
+            }
+            first = false;
+            write!(
+                json,
+                "\n  {{ \"file\": {}, \"kind\": {}, \"lexeme\": {}, \"start\": {}, \"end\": {} }}",
+                json_string(file.name),
+                json_string(&format!("{:?}", token.kind)),
+                json_string(text),
+                token.span.start,
+                token.span.end
+            )
+            .unwrap();
+        }
+    }
+    json.push_str("\n]\n");
+    json
+}
+
+/// Quote a field for CSV if it has to be: if it has a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The tokens of each of a program's files as CSV, with a header row.
+pub fn csv(sources: &Sources, tokens: &[Vec<Token>]) -> String {
+    let mut csv = String::from("file,kind,lexeme,start,end\n");
+    for (file, tokens) in sources.files().zip(tokens) {
+        for token in tokens {
+            let text = &file.text[token.span.start..token.span.end];
+            writeln!(
+                csv,
+                "{},{},{},{},{}",
+                csv_field(file.name),
+                csv_field(&format!("{:?}", token.kind)),
+                csv_field(text),
+                token.span.start,
+                token.span.end
+            )
+            .unwrap();
+        }
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two files, the second with a name and text CSV has to quote, and their tokens.
+    fn lexed() -> (Sources, Vec<Vec<Token>>) {
+        let sources = Sources::new([
+            ("a.mtx".to_owned(), "let x = 1;".to_owned()),
+            ("b,c.mtx".to_owned(), r#""say \"hi\", \n""#.to_owned()),
+        ]);
+        let tokens = sources
+            .files()
+            .map(|file| lexer::lex(file.text).expect("the file lexes"))
+            .collect();
+        (sources, tokens)
+    }
+
+    #[test]
+    fn test_json() {
+        let (sources, tokens) = lexed();
+        assert_eq!(
+            json(&sources, &tokens),
+            r#"[
+  { "file": "a.mtx", "kind": "Ident(Keyword(Let))", "lexeme": "let", "start": 0, "end": 3 },
+  { "file": "a.mtx", "kind": "Ident(NonReserved)", "lexeme": "x", "start": 4, "end": 5 },
+  { "file": "a.mtx", "kind": "Equal", "lexeme": "=", "start": 6, "end": 7 },
+  { "file": "a.mtx", "kind": "Literal(Integer { base: Decimal })", "lexeme": "1", "start": 8, "end": 9 },
+  { "file": "a.mtx", "kind": "Semicolon", "lexeme": ";", "start": 9, "end": 10 },
+  { "file": "a.mtx", "kind": "EoF", "lexeme": "", "start": 10, "end": 10 },
+  { "file": "b,c.mtx", "kind": "Literal(String)", "lexeme": "\"say \\\"hi\\\", \\n\"", "start": 0, "end": 16 },
+  { "file": "b,c.mtx", "kind": "EoF", "lexeme": "", "start": 16, "end": 16 }
+]
+"#
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let (sources, tokens) = lexed();
+        assert_eq!(
+            csv(&sources, &tokens),
+            r#"file,kind,lexeme,start,end
+a.mtx,Ident(Keyword(Let)),let,0,3
+a.mtx,Ident(NonReserved),x,4,5
+a.mtx,Equal,=,6,7
+a.mtx,Literal(Integer { base: Decimal }),1,8,9
+a.mtx,Semicolon,;,9,10
+a.mtx,EoF,,10,10
+"b,c.mtx",Literal(String),"""say \""hi\"", \n""",0,16
+"b,c.mtx",EoF,,16,16
+"#
+        );
+    }
+
+    #[test]
+    fn test_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,717 @@
+//! Tests of how mtxc reads its command line, running it on programs given on the standard input.
+
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+/// Run mtxc with some arguments, writing a program to its standard input.
+fn mtxc(args: &[&str], program: &str) -> Output {
+    mtxc_in(Path::new("."), args, program)
+}
+
+/// Run mtxc like [`mtxc`], but in a directory, like a project's.
+fn mtxc_in(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mtxc"))
+        .args(["--color", "never"])
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("mtxc runs");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .expect("the input is written");
+    child.wait_with_output().expect("mtxc runs")
+}
+
+const PRINTS: &str = r#"proc main() int { print("hi"); ret 3; }"#;
+
+const READS_A_FILE: &str = r#"proc main() void { print(read_file("a.txt")); }"#;
+
+#[test]
+fn test_global_flags_before_the_command() {
+    let output = mtxc(&["-v", "--overflow", "wrapping", "run", "-"], PRINTS);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi");
+}
+
+#[test]
+fn test_sandbox_before_or_after_the_command() {
+    for args in [["--sandbox", "run", "-"], ["run", "--sandbox", "-"]] {
+        let output = mtxc(&args, READS_A_FILE);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("read_file"),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn test_sandbox_applies_to_check() {
+    assert!(mtxc(&["check", "-"], READS_A_FILE).status.success());
+    assert_eq!(
+        mtxc(&["--sandbox", "check", "-"], READS_A_FILE)
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_program_paths_without_a_command() {
+    let output = mtxc(&["--emit", "ast", "-"], PRINTS);
+    assert!(output.status.success());
+    assert!(!output.stdout.is_empty());
+}
+
+#[test]
+fn test_repl_keeps_items_and_variables() {
+    let inputs = "proc double(x: int) int {\n    ret x * 2;\n}\nlet x = 3;\nx += 1;\ndouble(x)\n";
+    let output = mtxc(&["repl"], inputs);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().filter(|line| line.ends_with("8")).count(), 1);
+}
+
+#[test]
+fn test_repl_reports_errors_without_stopping() {
+    let output = mtxc(&["repl"], "let x: int = true;\n\"still \" + \"running\"\n");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("hir::type_mismatch"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"still running\""));
+}
+
+#[test]
+fn test_sarif_reports_gathered_diagnostics() {
+    let program = "proc main() void { let x: int = true; }";
+    let output = mtxc(&["--message-format", "sarif", "check", "-"], program);
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("\"ruleId\": \"hir::type_mismatch\""));
+    assert!(!log.contains("hir::failure"));
+    assert!(log.contains("\"startLine\": 1, \"startColumn\": 33"));
+}
+
+#[test]
+fn test_build_a_project() -> std::io::Result<()> {
+    let root = tempfile::tempdir()?;
+    fs::write(
+        root.path().join("matrix.toml"),
+        "[package]\nname = \"hello\"\n",
+    )?;
+    fs::create_dir(root.path().join("src"))?;
+    fs::write(root.path().join("src/main.mtx"), PRINTS)?;
+
+    let nested = root.path().join("src");
+    assert!(mtxc_in(&nested, &["build"], "").status.success());
+    let output = mtxc_in(root.path(), &["run", "build/hello.mxc"], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi");
+    Ok(())
+}
+
+const THREE_ERRORS: &str =
+    r#"proc main() void { let a: int = true; let b: int = "b"; let c: bool = 1; }"#;
+
+#[test]
+fn test_error_limit_counts_the_rest() {
+    let output = mtxc(&["--error-limit", "2", "check", "-"], THREE_ERRORS);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("Mismatched types").count(), 2);
+    assert!(stderr.contains("not showing 1 more diagnostic."));
+
+    let output = mtxc(&["--error-limit", "0", "check", "-"], THREE_ERRORS);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("Mismatched types").count(), 3);
+    assert!(!stderr.contains("too many errors"));
+}
+
+#[test]
+fn test_error_limit_leaves_sarif_whole() {
+    let args = ["--error-limit=1", "--message-format=sarif", "check", "-"];
+    let output = mtxc(&args, THREE_ERRORS);
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(log.matches("\"ruleId\"").count(), 3);
+    assert!(output.stderr.is_empty());
+}
+
+/// A program in a file of its own, since the debugger reads its commands from the standard
+/// input.
+const ADDS: &str = "proc add(a: int, b: int) int {
+    let sum: int = a + b;
+    ret sum;
+}
+
+proc main() int {
+    let x: int = 2;
+    let y: int = add(x, 3);
+    ret y;
+}
+";
+
+#[test]
+fn test_debug_stops_at_breakpoints() -> std::io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("adds.mtx"), ADDS)?;
+
+    let commands = "continue\nprint a\nbacktrace\nfinish\nlocals\ncontinue\n";
+    let output = mtxc_in(dir.path(), &["debug", "--break", "2", "adds.mtx"], commands);
+    assert_eq!(output.status.code(), Some(5));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("stopped in add at adds.mtx:2:20"));
+    assert!(stdout.contains("a = 2\n"));
+    assert!(stdout.contains("*  0  add at adds.mtx:2:20\n   1  main at adds.mtx:8:18\n"));
+    assert!(stdout.contains("x = 2\ny = 5\n"));
+    assert!(stdout.contains("finished, returning 5"));
+    Ok(())
+}
+
+#[test]
+fn test_debug_steps_into_calls() -> std::io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("adds.mtx"), ADDS)?;
+
+    let commands = "step\nstep\nnext\nquit\n";
+    let output = mtxc_in(dir.path(), &["debug", "adds.mtx"], commands);
+    assert!(output.status.success());
+    let stops: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| Some(line.split_once("stopped in ")?.1.to_owned()))
+        .collect();
+    assert_eq!(
+        stops,
+        [
+            "main at adds.mtx:7:18",
+            "main at adds.mtx:8:22",
+            "add at adds.mtx:2:20",
+            "add at adds.mtx:3:5"
+        ]
+    );
+
+    let output = mtxc_in(dir.path(), &["debug", "--break", "20", "adds.mtx"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("there's no code on line 20"));
+    Ok(())
+}
+
+#[test]
+fn test_config_sets_defaults_for_flags() -> std::io::Result<()> {
+    let root = tempfile::tempdir()?;
+    let config = "message-format = \"sarif\"\n";
+    fs::write(root.path().join(".matrix.toml"), config)?;
+    fs::create_dir(root.path().join("src"))?;
+    let program = "proc main() void { let x: int = true; }";
+    fs::write(root.path().join("src/main.mtx"), program)?;
+
+    let output = mtxc_in(root.path(), &["check", "src/main.mtx"], "");
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("\"ruleId\": \"hir::type_mismatch\""));
+
+    let args = ["--message-format", "human", "check", "src/main.mtx"];
+    let output = mtxc_in(root.path(), &args, "");
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("hir::type_mismatch"));
+    Ok(())
+}
+
+#[test]
+fn test_invalid_config_is_reported() -> std::io::Result<()> {
+    let root = tempfile::tempdir()?;
+    fs::write(root.path().join(".matrix.toml"), "overflw = \"wrapping\"\n")?;
+    fs::write(root.path().join("main.mtx"), PRINTS)?;
+
+    let output = mtxc_in(root.path(), &["check", "main.mtx"], "");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown field `overflw`"), "{stderr}");
+    Ok(())
+}
+
+#[test]
+fn test_locale_translates_diagnostics() {
+    let program = "proc main() void { let x: int = true; }";
+    let output = mtxc(&["--locale", "fr", "check", "-"], program);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Types incompatibles. `int` attendu, `bool` trouvé"));
+    assert!(stderr.contains("╰── `int` attendu, `bool` trouvé"));
+
+    let output = mtxc(&["--locale", "xx", "check", "-"], program);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no diagnostics in the language"));
+}
+
+#[test]
+fn test_repl_save_and_load() -> std::io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let inputs = r#"proc double(x: int) int {
+    ret x * 2;
+}
+let xs: [int] = [1, 2];
+let name = "a \"b\"\n";
+let m: [str: int] = ["k": 1, "j": 2];
+let small: i8 = -3;
+:save session.mtx
+"#;
+    assert!(mtxc_in(dir.path(), &["repl"], inputs).status.success());
+    let saved = fs::read_to_string(dir.path().join("session.mtx"))?;
+    assert!(saved.ends_with(
+        r#"let xs: [int] = [1, 2];
+let name: str = "a \"b\"\n";
+let m: [str: int] = ["j": 2, "k": 1];
+let small: i8 = -3;
+"#
+    ));
+
+    let inputs = "double(xs[1])\nname\nm[\"j\"]\nsmall\n";
+    let output = mtxc_in(dir.path(), &["repl", "--load", "session.mtx"], inputs);
+    let values: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| Some(line.strip_prefix("> ")?.to_owned()))
+        .filter(|value| !value.is_empty())
+        .collect();
+    assert_eq!(values, ["4", r#""a \"b\"\n""#, "2", "-3"]);
+
+    let output = mtxc_in(dir.path(), &["repl"], ":load session.mtx\nsmall * 2\n");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("> -6\n"));
+
+    let inputs = ":load session.mtx\nsmall + 1;\nsmall + 2\n";
+    let args = ["--deny", "hir::no_effect", "repl"];
+    let output = mtxc_in(dir.path(), &args, inputs);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("× Expression statement has no effect"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("`hir::no_effect` is denied"), "{stderr}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("> -1\n"));
+    Ok(())
+}
+
+#[test]
+fn test_examples_compile() -> std::io::Result<()> {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+    for entry in fs::read_dir(examples)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "mtx") {
+            let output = mtxc(&["check", path.to_str().unwrap()], "");
+            assert!(
+                output.status.success(),
+                "{}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+    Ok(())
+}
+
+const BENCHES: &str = "@bench proc sum() void {
+    let total = 0;
+    for let i = 0; i < 100; i += 1 { total += i; }
+}
+@bench proc nothing() void {}
+proc main() void {}";
+
+#[test]
+fn test_bench_prints_a_table() {
+    let output = mtxc(&["bench", "--warmup", "1", "--samples", "3", "-"], BENCHES);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("benchmarking sum") && stderr.contains("benchmarking nothing"));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{stdout}");
+    let header: Vec<_> = lines[0].split_whitespace().collect();
+    assert_eq!(
+        header,
+        [
+            "benchmark",
+            "mean",
+            "median",
+            "stddev",
+            "relative",
+            "samples"
+        ]
+    );
+    for (line, name) in lines[1..].iter().zip(["sum", "nothing"]) {
+        assert!(line.starts_with(name), "{line}");
+        assert!(line.ends_with(" 3"), "{line}");
+    }
+
+    let output = mtxc(&["bench", "-"], "proc main() void {}");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "no benchmarks\n");
+}
+
+#[test]
+fn test_bench_runs_out_of_fuel() {
+    let output = mtxc(&["--fuel", "50", "bench", "--samples", "1", "-"], BENCHES);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Execution budget exhausted"), "{stderr}");
+    assert!(stderr.contains("at most 50 steps"), "{stderr}");
+}
+
+#[test]
+fn test_failed_assertions_and_panics_are_reported() {
+    let asserts =
+        "proc main() void {\n    let x = 2;\n    assert(x == 3, \"x should be three\");\n}";
+    let panics = "proc main() void {\n    panic(\"gave up\");\n}";
+    for backend in [&["run", "-"][..], &["run", "--vm", "-"]] {
+        let output = mtxc(backend, asserts);
+        assert_eq!(output.status.code(), Some(1), "{backend:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("interp::assertion_failed"), "{stderr}");
+        assert!(
+            stderr.contains("Assertion failed: x should be three"),
+            "{stderr}"
+        );
+        assert!(
+            stderr.contains(r#"3 │     assert(x == 3, "x should be three");"#),
+            "{stderr}"
+        );
+        assert!(stderr.contains("this assertion is false"), "{stderr}");
+
+        let output = mtxc(backend, panics);
+        assert_eq!(output.status.code(), Some(1), "{backend:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("interp::panicked"), "{stderr}");
+        assert!(stderr.contains("Panicked: gave up"), "{stderr}");
+        assert!(stderr.contains(r#"2 │     panic("gave up");"#), "{stderr}");
+        assert!(stderr.contains("panicked here"), "{stderr}");
+    }
+
+    let tests = "@test proc fails() void {\n    assert(1 > 2, \"one is not more\");\n}
+        @test proc gives_up() void { panic(\"nope\"); }
+        proc main() void {}";
+    let output = mtxc(&["test", "-"], tests);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("test fails ... FAILED"), "{stdout}");
+    assert!(stdout.contains("test gives_up ... FAILED"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Assertion failed: one is not more"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Panicked: nope"), "{stderr}");
+}
+
+#[test]
+fn test_programs_of_several_files() -> std::io::Result<()> {
+    let root = tempfile::tempdir()?;
+    fs::create_dir(root.path().join("src"))?;
+    fs::write(
+        root.path().join("src/helper.mtx"),
+        "proc helper() int { ret 4; }\n",
+    )?;
+    fs::write(
+        root.path().join("src/main.mtx"),
+        "proc main() int { ret helper() + 1; }\n",
+    )?;
+    fs::write(
+        root.path().join("again.mtx"),
+        "proc helper() int { ret 5; }\n",
+    )?;
+
+    let files = ["src/main.mtx", "src/helper.mtx"];
+    assert_eq!(
+        mtxc_in(root.path(), &["run", files[0], files[1]], "")
+            .status
+            .code(),
+        Some(5)
+    );
+    assert_eq!(
+        mtxc_in(root.path(), &["run", "src"], "").status.code(),
+        Some(5)
+    );
+
+    // Both definitions are labeled, each in its own file.
+    let output = mtxc_in(root.path(), &["check", "src", "again.mtx"], "");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Procedure `helper` is defined multiple times"),
+        "{stderr}"
+    );
+    let previous = stderr
+        .find("src/helper.mtx:1:1")
+        .expect("the first file is shown");
+    let redefined = stderr
+        .find("again.mtx:1:1")
+        .expect("the second file is shown");
+    assert!(previous < stderr.find("previous definition here").unwrap());
+    assert!(redefined > previous && redefined < stderr.find("redefined here").unwrap());
+    Ok(())
+}
+
+#[test]
+fn test_emit_prints_each_stage_in_order() {
+    let program = "proc main() int { ret 1 + 2; }";
+    let output = mtxc(&["--emit", "mir,tokens", "-"], program);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let tokens = stdout
+        .find("keyword `proc`")
+        .expect("the tokens are printed");
+    let mir = stdout
+        .find("%0: int = add 1, 2")
+        .expect("the MIR is printed");
+    assert!(tokens < mir, "{stdout}");
+    assert!(stdout.ends_with("ret %0\n}\n"), "{stdout}");
+
+    // `--emit` can be given more than once, and the program isn't run.
+    let output = mtxc(&["--emit", "bytecode", "--emit", "ast", "-"], program);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Program {"), "{stdout}");
+    assert!(stdout.contains("BinaryConst { op: Add"), "{stdout}");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = mtxc(&["check", "-"], program);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_stdin_is_named_in_diagnostics() {
+    let output = mtxc(&["run", "-"], "proc main() int { ret x; }");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("╭─[<stdin>:1:1]"), "{stderr}");
+    assert!(stderr.contains("Cannot find `x` in this scope"), "{stderr}");
+
+    let output = mtxc(&["fmt", "-"], "proc main() int {ret   3;}");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "proc main() int {\n    ret 3;\n}\n"
+    );
+}
+
+#[test]
+fn test_check_fails_only_on_errors() {
+    // The program isn't run, and a warning doesn't fail the check.
+    let warns = r#"proc main() void { 1 + 2; println("ran"); }"#;
+    let output = mtxc(&["check", "-"], warns);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no effect"));
+
+    let output = mtxc(&["--deny", "no_effect", "check", "-"], warns);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = mtxc(&["check", "-"], "proc main() int { ret true; }");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_exit_codes() {
+    let cases: [(&[&str], &str, i32); 6] = [
+        (&["run", "-"], "proc main() void {}", 0),
+        (&["run", "-"], "proc main() int { ret 42; }", 42),
+        // Errors compiling or running the program.
+        (&["run", "-"], "proc main() int { ret x; }", 1),
+        (
+            &["run", "-"],
+            "proc main() int { let zero = 0; ret 1 / zero; }",
+            1,
+        ),
+        // Mistakes using mtxc.
+        (&["run", "missing.mtx"], "", 2),
+        (&["run", "--no-such-flag", "-"], "", 2),
+    ];
+    for (args, program, code) in cases {
+        assert_eq!(
+            mtxc(args, program).status.code(),
+            Some(code),
+            "{args:?} {program}"
+        );
+    }
+
+    let help = String::from_utf8_lossy(&mtxc(&["--help"], "").stdout).into_owned();
+    assert!(help.contains("Exit codes:"), "{help}");
+    assert!(help.contains("101  An internal compiler error"), "{help}");
+}
+
+#[test]
+fn test_highlight_as_ansi_and_html() {
+    let output = mtxc(&["highlight", "-"], "proc main() int { ret 1; } // hi");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("\x1b[35mproc\x1b[0m \x1b[34mmain\x1b[0m()"),
+        "{stdout:?}"
+    );
+    assert!(stdout.contains("\x1b[90m// hi\x1b[0m"), "{stdout:?}");
+
+    let output = mtxc(
+        &["highlight", "--format", "html", "-"],
+        "proc main() int { ret 1; }",
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "<pre class=\"matrix\"><code><span class=\"keyword\">proc</span> \
+        <span class=\"function\">main</span>() <span class=\"type\">int</span> { \
+        <span class=\"keyword\">ret</span> <span class=\"literal\">1</span>; }</code></pre>\n"
+    );
+
+    // Names aren't classified in a file that doesn't type check, and text is escaped.
+    let program = "proc main() int { ret 1 < 2; } // <hi>";
+    let output = mtxc(&["highlight", "--format", "html", "-"], program);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("</span> main()"), "{stdout}");
+    assert!(
+        stdout.contains("&lt; <span") && stdout.contains("// &lt;hi&gt;"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_color_and_width() -> std::io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("undefined.mtx");
+    fs::write(&path, "proc main() int { ret undefined_name_here; }")?;
+    let stderr = |color: &str, columns: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_mtxc"))
+            .args(["--color", color, "check"])
+            .arg(&path)
+            .env("COLUMNS", columns)
+            .output()
+            .expect("mtxc runs");
+        assert_eq!(output.status.code(), Some(1));
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    assert!(stderr("always", "80").contains("\x1b[31mhir::unresolved_name\x1b[0m"));
+    // Stderr is piped rather than a terminal.
+    assert!(!stderr("auto", "80").contains('\x1b'));
+    assert!(!stderr("never", "80").contains('\x1b'));
+
+    let wide = stderr("never", "80");
+    assert!(
+        wide.contains("× Cannot find `undefined_name_here` in this scope\n"),
+        "{wide}"
+    );
+    let narrow = stderr("never", "30");
+    assert!(narrow.contains("× Cannot find\n"), "{narrow}");
+    Ok(())
+}
+
+#[test]
+fn test_diagnostic_styles_show_the_same_diagnostic() {
+    let program = "proc main() void { 1 + 2; }";
+    let miette = mtxc(&["check", "-"], program);
+    let ariadne = mtxc(&["--diagnostic-style", "ariadne", "check", "-"], program);
+    assert_eq!(miette.status.code(), ariadne.status.code());
+
+    let miette = String::from_utf8_lossy(&miette.stderr);
+    let ariadne = String::from_utf8_lossy(&ariadne.stderr);
+    assert!(
+        miette.contains("⚠ Expression statement has no effect"),
+        "{miette}"
+    );
+    assert!(
+        ariadne.contains("[hir::no_effect] Warning: Expression statement has no effect"),
+        "{ariadne}"
+    );
+    assert!(ariadne.contains("╭─[<stdin>:1:20]"), "{ariadne}");
+    for part in [
+        "this value is computed and then discarded",
+        "use the value, or remove the statement",
+    ] {
+        assert!(miette.contains(part) && ariadne.contains(part), "{part}");
+    }
+}
+
+#[test]
+fn test_new_and_init_scaffold_projects() -> std::io::Result<()> {
+    let root = tempfile::tempdir()?;
+    assert!(mtxc_in(root.path(), &["new", "hello"], "").status.success());
+    let project = root.path().join("hello");
+    assert_eq!(
+        fs::read_to_string(project.join("matrix.toml"))?,
+        "[package]\nname = \"hello\"\n"
+    );
+    assert_eq!(fs::read_to_string(project.join(".gitignore"))?, "/build/\n");
+
+    // The project builds and runs as it's made.
+    assert!(mtxc_in(&project, &["build"], "").status.success());
+    let output = mtxc_in(&project, &["run", "build/hello.mxc"], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello, world!\n");
+
+    // `init` names the project after its directory, and won't overwrite one.
+    let other = root.path().join("other");
+    fs::create_dir(&other)?;
+    assert!(mtxc_in(&other, &["init"], "").status.success());
+    assert!(fs::read_to_string(other.join("matrix.toml"))?.contains("name = \"other\""));
+    assert!(other.join("src/main.mtx").is_file());
+    let output = mtxc_in(&other, &["init"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already has a `matrix.toml`"));
+    Ok(())
+}
+
+#[test]
+fn test_runtime_errors_show_their_calls() {
+    let divides = "proc inner(n: int) int {
+    ret 10 / n;
+}
+proc outer(n: int) int {
+    ret inner(n - 1) + 1;
+}
+proc main() int {
+    ret outer(1);
+}";
+    let recurses =
+        "proc deep(n: int) int {\n    ret deep(n + 1) + 1;\n}\nproc main() int { ret deep(0); }";
+    for backend in [&["run", "-"][..], &["run", "--vm", "-"]] {
+        let output = mtxc(backend, divides);
+        assert_eq!(output.status.code(), Some(1), "{backend:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("× Division by zero"), "{stderr}");
+        let labels = [
+            "the divisor is zero",
+            "`inner` called here",
+            "`outer` called here",
+        ];
+        let positions: Vec<_> = labels.iter().map(|label| stderr.find(label)).collect();
+        assert!(positions.iter().all(Option::is_some), "{stderr}");
+        assert!(positions.is_sorted(), "{stderr}");
+        let trace = "call trace, innermost first:\n            inner\n            outer\n";
+        assert!(
+            stderr.contains(&format!("{trace}            main")),
+            "{stderr}"
+        );
+
+        let args = [&["--max-call-depth", "5"], backend].concat();
+        let output = mtxc(&args, recurses);
+        assert_eq!(output.status.code(), Some(1), "{backend:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("× Stack overflow in matrix program"),
+            "{stderr}"
+        );
+        assert!(
+            stderr.contains("this call is too deeply nested"),
+            "{stderr}"
+        );
+        assert!(
+            stderr.contains("deep (4 calls)\n            main"),
+            "{stderr}"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! UI tests of how diagnostics are reported. Each program in `tests/ui` is checked with mtxc,
+//! and what it reports is compared with the `.stderr` file beside the program, so a change to
+//! a diagnostic's wording, labels or spans shows up as a diff.
+//!
+//! Run them with `cargo test -p matrix --test ui`, giving names to run only the programs whose
+//! names contain them. `cargo test -p matrix --test ui -- --bless` writes what's reported to
+//! the `.stderr` files instead, for a change that's meant.
+//!
+//! A program's lines starting with `//@ flags:` give mtxc more flags, like
+//! `//@ flags: --deny no_effect`.
+
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::Path,
+    process::{Command, ExitCode},
+};
+
+const FLAGS: &str = "//@ flags:";
+
+/// The flags a program gives mtxc.
+fn flags(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .filter_map(|line| line.strip_prefix(FLAGS))
+        .flat_map(str::split_whitespace)
+        .collect()
+}
+
+/// What mtxc reports checking a program, run from its directory so reports name it as it's
+/// named there. Reports aren't colored, and are drawn the same wherever the tests run.
+fn check(dir: &Path, name: &str, flags: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_mtxc"))
+        .current_dir(dir)
+        .args(["check", "--color", "never"])
+        .args(flags)
+        .arg(name)
+        .env("COLUMNS", "100")
+        .env("TERM", "xterm")
+        .output()
+        .expect("mtxc runs");
+    String::from_utf8(output.stderr).expect("reports are UTF-8")
+}
+
+/// The lines of one text that another lacks, after `-`, and that it adds, after `+`, between
+/// the lines they share.
+fn diff(expected: &str, actual: &str) -> String {
+    let (old, new): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+
+    // The length of the longest common subsequence of the lines from each pair of positions.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            writeln!(diff, " {}", old[i]).unwrap();
+            (i, j) = (i + 1, j + 1);
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            writeln!(diff, "+{}", new[j]).unwrap();
+            j += 1;
+        } else {
+            writeln!(diff, "-{}", old[i]).unwrap();
+            i += 1;
+        }
+    }
+    diff
+}
+
+fn main() -> ExitCode {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let filters: Vec<_> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ui");
+    let mut names: Vec<_> = fs::read_dir(&dir)
+        .expect("the UI tests are in `tests/ui`")
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".mtx"))
+        .filter(|name| filters.is_empty() || filters.iter().any(|filter| name.contains(*filter)))
+        .collect();
+    names.sort();
+
+    println!("\nrunning {} UI tests", names.len());
+    let mut failures = Vec::new();
+    for name in &names {
+        let source = fs::read_to_string(dir.join(name)).unwrap();
+        let actual = check(&dir, name, &flags(&source));
+        let expected_path = dir.join(name).with_extension("stderr");
+        if bless {
+            if actual.is_empty() {
+                let _ = fs::remove_file(&expected_path);
+            } else {
+                fs::write(&expected_path, &actual).unwrap();
+            }
+            println!("test ui/{name} ... blessed");
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual == expected {
+            println!("test ui/{name} ... ok");
+        } else {
+            println!("test ui/{name} ... FAILED");
+            failures.push((name, diff(&expected, &actual)));
+        }
+    }
+
+    for (name, diff) in &failures {
+        println!("\n---- ui/{name} reported differently (-expected +actual) ----\n{diff}");
+    }
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {result}. {} passed; {} failed\n",
+        names.len() - failures.len(),
+        failures.len()
+    );
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        println!("run `cargo test -p matrix --test ui -- --bless` if the changes are meant\n");
+        ExitCode::FAILURE
+    }
+}
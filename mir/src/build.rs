@@ -0,0 +1,689 @@
+//! Lowering from HIR to MIR.
+//!
+//! SSA form is constructed directly while lowering, following Braun et al., "Simple and
+//! Efficient Construction of Static Single Assignment Form". Each block remembers the value
+//! last assigned to each local. Reading a local in a block without one looks through the
+//! block's predecessors, placing a phi where they can disagree. Blocks whose predecessors
+//! aren't all known yet get placeholder phis, completed once the block is sealed. Phis that
+//! turn out to always select the same value are removed at the end.
+
+use crate::{
+    nodes::{BasicBlock, BlockId, Body, Inst, InstKind, Operand, Phi, Temp, Terminator},
+    transform::{remove_trivial_phis, remove_unreachable_blocks, renumber_temps},
+};
+use hir::{
+    BinOp, Block, Expr, ExprKind, JumpTable, LocalId, LogicalOp, MatchArm, Pat, PatKind, Proc,
+    Program, Stmt, StmtKind, Ty,
+};
+use span::Span;
+use std::collections::HashMap;
+
+/// A block under construction.
+#[derive(Debug)]
+struct BlockData {
+    block: BasicBlock,
+    preds: Vec<BlockId>,
+
+    /// Whether every predecessor of the block is known.
+    sealed: bool,
+
+    /// The value of each local at the end of the block, so far.
+    defs: HashMap<LocalId, Operand>,
+
+    /// Phis placed before the block was sealed, whose incoming values are filled in when it
+    /// is.
+    incomplete: Vec<(LocalId, Temp)>,
+}
+
+struct Builder<'a> {
+    program: &'a Program,
+    proc: &'a Proc,
+    blocks: Vec<BlockData>,
+    temps: Vec<Ty>,
+
+    /// The block being appended to, or `None` after a `ret` or `break`, when the following
+    /// statements of the HIR block are unreachable.
+    current: Option<BlockId>,
+
+    /// The exit block of each loop being lowered, innermost last.
+    loop_exits: Vec<BlockId>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(program: &'a Program, proc: &'a Proc) -> Self {
+        let mut builder = Self {
+            program,
+            proc,
+            blocks: Vec::new(),
+            temps: Vec::new(),
+            current: None,
+            loop_exits: Vec::new(),
+        };
+
+        let entry = builder.new_block();
+        builder.seal(entry);
+        builder.current = Some(entry);
+        builder
+    }
+
+    fn data(&mut self, block: BlockId) -> &mut BlockData {
+        &mut self.blocks[block.0 as usize]
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BlockData {
+            block: BasicBlock {
+                phis: Vec::new(),
+                insts: Vec::new(),
+                terminator: Terminator::Unreachable,
+            },
+            preds: Vec::new(),
+            sealed: false,
+            defs: HashMap::new(),
+            incomplete: Vec::new(),
+        });
+        BlockId(self.blocks.len() as u32 - 1)
+    }
+
+    fn new_temp(&mut self, ty: Ty) -> Temp {
+        self.temps.push(ty);
+        Temp(self.temps.len() as u32 - 1)
+    }
+
+    fn current(&self) -> BlockId {
+        self.current
+            .expect("unreachable statements are never lowered")
+    }
+
+    /// Append an instruction to the current block, returning its destination.
+    fn push_inst(&mut self, kind: InstKind, ty: Ty, span: Span) -> Option<Operand> {
+        let dest = (ty != Ty::Void).then(|| self.new_temp(ty));
+        let current = self.current();
+        self.data(current)
+            .block
+            .insts
+            .push(Inst { dest, kind, span });
+        dest.map(Operand::Temp)
+    }
+
+    /// End the current block, recording it as a predecessor of its successors.
+    fn terminate(&mut self, terminator: Terminator) {
+        let current = self.current();
+        for successor in terminator.successors() {
+            self.data(successor).preds.push(current);
+        }
+
+        self.data(current).block.terminator = terminator;
+        self.current = None;
+    }
+
+    /// Jump from the current block, if it's reachable, to another.
+    fn jump_to(&mut self, target: BlockId) {
+        if self.current.is_some() {
+            self.terminate(Terminator::Jump(target));
+        }
+    }
+
+    /// Continue lowering in a block. If it has no predecessors, everything lowered into it
+    /// would be unreachable, so nothing is.
+    fn switch_to(&mut self, block: BlockId) {
+        let reachable = block == BlockId::ENTRY || !self.blocks[block.0 as usize].preds.is_empty();
+        self.current = reachable.then_some(block);
+    }
+
+    fn write_local(&mut self, local: LocalId, block: BlockId, value: Operand) {
+        self.data(block).defs.insert(local, value);
+    }
+
+    fn read_local(&mut self, local: LocalId, block: BlockId) -> Operand {
+        if let Some(value) = self.blocks[block.0 as usize].defs.get(&local) {
+            return value.clone();
+        }
+
+        let ty = self.proc.local(local).ty;
+        let data = &self.blocks[block.0 as usize];
+        let (sealed, preds) = (data.sealed, data.preds.clone());
+        let value = if !sealed {
+            let phi = self.new_phi(block, ty);
+            self.data(block).incomplete.push((local, phi));
+            Operand::Temp(phi)
+        } else if let [pred] = preds[..] {
+            self.read_local(local, pred)
+        } else {
+            assert!(
+                !preds.is_empty(),
+                "locals are initialized before they're read"
+            );
+
+            // Record the phi before reading the predecessors, which may loop back here.
+            let phi = self.new_phi(block, ty);
+            self.write_local(local, block, Operand::Temp(phi));
+            self.fill_phi(local, block, phi);
+            Operand::Temp(phi)
+        };
+
+        self.write_local(local, block, value.clone());
+        value
+    }
+
+    fn new_phi(&mut self, block: BlockId, ty: Ty) -> Temp {
+        let dest = self.new_temp(ty);
+        self.data(block).block.phis.push(Phi {
+            dest,
+            incoming: Vec::new(),
+        });
+        dest
+    }
+
+    /// Set the incoming values of a phi for a local from the block's predecessors.
+    fn fill_phi(&mut self, local: LocalId, block: BlockId, phi: Temp) {
+        let preds = self.blocks[block.0 as usize].preds.clone();
+        let incoming = preds
+            .into_iter()
+            .map(|pred| (pred, self.read_local(local, pred)))
+            .collect();
+
+        let phis = &mut self.data(block).block.phis;
+        phis.iter_mut()
+            .find(|p| p.dest == phi)
+            .expect("the phi was placed in this block")
+            .incoming = incoming;
+    }
+
+    /// Mark every predecessor of a block as known, completing its placeholder phis.
+    fn seal(&mut self, block: BlockId) {
+        for (local, phi) in std::mem::take(&mut self.data(block).incomplete) {
+            self.fill_phi(local, block, phi);
+        }
+
+        self.data(block).sealed = true;
+    }
+
+    fn lower_block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            if self.current.is_none() {
+                break;
+            }
+
+            self.lower_stmt(stmt);
+        }
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { local, init } => {
+                let value = self.lower_expr(init).expect("locals aren't void");
+                let current = self.current();
+                self.write_local(*local, current, value);
+            }
+            StmtKind::Expr(expr) => {
+                self.lower_expr(expr);
+                // Nothing after a builtin like `panic` runs.
+                if matches!(&expr.kind, ExprKind::Builtin { builtin, .. } if builtin.diverges()) {
+                    self.terminate(Terminator::Unreachable);
+                }
+            }
+            StmtKind::Ret(value) => {
+                let value = value.as_ref().and_then(|value| self.lower_expr(value));
+                self.terminate(Terminator::Ret(value));
+            }
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.lower_operand(cond);
+                let then = self.new_block();
+                let otherwise = self.new_block();
+                let join = if else_block.is_some() {
+                    self.new_block()
+                } else {
+                    otherwise
+                };
+
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                });
+                self.seal(then);
+
+                self.switch_to(then);
+                self.lower_block(then_block);
+                self.jump_to(join);
+
+                // Without an else block, `otherwise` is the join, and isn't sealed until the
+                // then block has jumped to it.
+                if let Some(else_block) = else_block {
+                    self.seal(otherwise);
+                    self.switch_to(otherwise);
+                    self.lower_block(else_block);
+                    self.jump_to(join);
+                }
+
+                self.seal(join);
+                self.switch_to(join);
+            }
+            StmtKind::While { cond, body } => {
+                let header = self.new_block();
+                self.jump_to(header);
+                self.switch_to(header);
+
+                let cond = self.lower_operand(cond);
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then: body_block,
+                    otherwise: exit,
+                });
+                self.seal(body_block);
+
+                self.lower_loop_body(body, body_block, header, exit);
+            }
+            StmtKind::Loop(body) => {
+                let header = self.new_block();
+                let exit = self.new_block();
+                self.jump_to(header);
+                self.lower_loop_body(body, header, header, exit);
+            }
+            StmtKind::Break => {
+                let exit = *self.loop_exits.last().expect("breaks only appear in loops");
+                self.terminate(Terminator::Jump(exit));
+            }
+            StmtKind::Block(block) => self.lower_block(block),
+        }
+    }
+
+    /// Lower the body of a loop starting at `body_block`, which jumps back to `header` and
+    /// exits to `exit`. The header is sealed once the back edge is known.
+    fn lower_loop_body(
+        &mut self,
+        body: &Block,
+        body_block: BlockId,
+        header: BlockId,
+        exit: BlockId,
+    ) {
+        self.loop_exits.push(exit);
+        self.switch_to(body_block);
+        self.lower_block(body);
+        self.jump_to(header);
+        self.loop_exits.pop();
+
+        self.seal(header);
+        self.seal(exit);
+        self.switch_to(exit);
+    }
+
+    /// Lower an expression that isn't void.
+    fn lower_operand(&mut self, expr: &Expr) -> Operand {
+        self.lower_expr(expr)
+            .expect("only non-void expressions are used as operands")
+    }
+
+    /// Lower an expression, returning its value, or `None` if it's void.
+    fn lower_expr(&mut self, expr: &Expr) -> Option<Operand> {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => Some(Operand::Const(literal.clone())),
+            ExprKind::Local(local) => {
+                let current = self.current();
+                Some(self.read_local(*local, current))
+            }
+            ExprKind::Const(id) => Some(Operand::Const(self.program.constant(*id).value.clone())),
+            ExprKind::Call { callee, args } => {
+                let args = args.iter().map(|arg| self.lower_operand(arg)).collect();
+                let kind = InstKind::Call {
+                    callee: *callee,
+                    args,
+                };
+                self.push_inst(kind, expr.ty, span)
+            }
+            ExprKind::Builtin { builtin, args } => {
+                let args = args.iter().map(|arg| self.lower_operand(arg)).collect();
+                let kind = InstKind::Builtin {
+                    builtin: *builtin,
+                    args,
+                };
+                self.push_inst(kind, expr.ty, span)
+            }
+            ExprKind::Native { native, args } => {
+                let args = args.iter().map(|arg| self.lower_operand(arg)).collect();
+                let kind = InstKind::Native {
+                    native: *native,
+                    args,
+                };
+                self.push_inst(kind, expr.ty, span)
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand = self.lower_operand(operand);
+                self.push_inst(InstKind::Unary { op: *op, operand }, expr.ty, span)
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let lhs = self.lower_operand(lhs);
+                let rhs = self.lower_operand(rhs);
+                let kind = InstKind::Binary { lhs, op: *op, rhs };
+                self.push_inst(kind, expr.ty, span)
+            }
+            ExprKind::Logical { lhs, op, rhs } => {
+                // `a && b` is `if a { b } else { false }`, and `a || b` is
+                // `if a { true } else { b }`.
+                let lhs = self.lower_operand(lhs);
+                let lhs_block = self.current();
+                let rhs_block = self.new_block();
+                let join = self.new_block();
+
+                let (then, otherwise) = match op {
+                    LogicalOp::And => (rhs_block, join),
+                    LogicalOp::Or => (join, rhs_block),
+                };
+                self.terminate(Terminator::Branch {
+                    cond: lhs,
+                    then,
+                    otherwise,
+                });
+                self.seal(rhs_block);
+
+                self.switch_to(rhs_block);
+                let rhs = self.lower_operand(rhs);
+                let rhs_end = self.current();
+                self.jump_to(join);
+                self.seal(join);
+                self.switch_to(join);
+
+                let short_circuit = Operand::Const(hir::Literal::Bool(*op == LogicalOp::Or));
+                Some(self.join_values(join, vec![(lhs_block, short_circuit), (rhs_end, rhs)]))
+            }
+            ExprKind::Assign { local, value } => {
+                let value = self.lower_operand(value);
+                let current = self.current();
+                self.write_local(*local, current, value);
+                None
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let table = hir::jump_table(scrutinee.ty, arms);
+                let scrutinee = self.lower_operand(scrutinee);
+                let join = self.new_block();
+                let values = match table {
+                    Some(table) => self.lower_switch(&scrutinee, &table, arms, join),
+                    None => self.lower_arms(&scrutinee, arms, join),
+                };
+
+                self.current = None;
+                self.seal(join);
+                self.switch_to(join);
+
+                (expr.ty != Ty::Void).then(|| self.join_values(join, values))
+            }
+            ExprKind::Array(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.lower_operand(element))
+                    .collect();
+                self.push_inst(InstKind::Array { elements }, expr.ty, span)
+            }
+            ExprKind::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .map(|operand| self.lower_operand(operand))
+                    .collect();
+                self.push_inst(InstKind::Map { entries }, expr.ty, span)
+            }
+            ExprKind::Index { array, index } => {
+                let array = self.lower_operand(array);
+                let index = self.lower_operand(index);
+                self.push_inst(InstKind::Index { array, index }, expr.ty, span)
+            }
+            ExprKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                let (_, element_ty) = array
+                    .ty
+                    .indexed()
+                    .expect("only arrays and maps are indexed");
+                let array = self.lower_operand(array);
+                let index = self.lower_operand(index);
+                let mut value = self.lower_operand(value);
+
+                // `xs[i] op= value` stores `xs[i] op value`.
+                if let Some(op) = *op {
+                    let element = InstKind::Index {
+                        array: array.clone(),
+                        index: index.clone(),
+                    };
+                    let element = self.push_inst(element, element_ty, span).unwrap();
+                    let kind = InstKind::Binary {
+                        lhs: element,
+                        op,
+                        rhs: value,
+                    };
+                    value = self.push_inst(kind, element_ty, span).unwrap();
+                }
+
+                self.push_inst(
+                    InstKind::Store {
+                        array,
+                        index,
+                        value,
+                    },
+                    Ty::Void,
+                    span,
+                )
+            }
+            ExprKind::Error => unreachable!("erroneous programs are never lowered"),
+        }
+    }
+
+    /// Merge the values flowing into a block from each of its predecessors.
+    fn join_values(&mut self, block: BlockId, incoming: Vec<(BlockId, Operand)>) -> Operand {
+        if let [(_, value)] = &incoming[..] {
+            return value.clone();
+        }
+
+        let ty = match &incoming[0].1 {
+            Operand::Temp(temp) => self.temps[temp.0 as usize],
+            Operand::Const(literal) => literal.ty(),
+        };
+        let dest = self.new_phi(block, ty);
+        let phi = self
+            .data(block)
+            .block
+            .phis
+            .last_mut()
+            .expect("the phi was just placed");
+        phi.incoming = incoming;
+        Operand::Temp(dest)
+    }
+
+    /// Branch to `matched` if a value matches a pattern, and to `failed` if it doesn't.
+    /// Lower the arms of a match, testing each one's pattern in turn. Returns the value of each
+    /// arm that reaches `join`, with the block it comes from.
+    fn lower_arms(
+        &mut self,
+        scrutinee: &Operand,
+        arms: &[MatchArm],
+        join: BlockId,
+    ) -> Vec<(BlockId, Operand)> {
+        let mut values = Vec::with_capacity(arms.len());
+
+        for arm in arms {
+            // Arms after one that always matches are unreachable.
+            if self.current.is_none() {
+                break;
+            }
+
+            let body = self.new_block();
+            let next = self.new_block();
+            self.lower_pattern(&arm.pat, scrutinee, body, next);
+            self.seal(body);
+            self.seal(next);
+
+            self.switch_to(body);
+            let value = self.lower_expr(&arm.body);
+            if let Some(value) = value {
+                values.push((self.current(), value));
+            }
+            self.jump_to(join);
+
+            // The last arm's `next` block is only reached if no arm matches, which
+            // exhaustiveness checking rules out. It's left as `unreachable`.
+            self.switch_to(next);
+        }
+
+        values
+    }
+
+    /// Lower the arms of a match that jumps straight to its arm through a table, like
+    /// [`Self::lower_arms`].
+    fn lower_switch(
+        &mut self,
+        scrutinee: &Operand,
+        table: &JumpTable,
+        arms: &[MatchArm],
+        join: BlockId,
+    ) -> Vec<(BlockId, Operand)> {
+        let targets = table.targets();
+        let blocks: HashMap<_, _> = targets.iter().map(|&arm| (arm, self.new_block())).collect();
+        self.terminate(Terminator::Switch {
+            value: scrutinee.clone(),
+            low: table.low,
+            targets: table.arms.iter().map(|arm| blocks[arm]).collect(),
+            otherwise: blocks[&table.default],
+        });
+
+        let mut values = Vec::with_capacity(targets.len());
+        for arm in targets {
+            self.seal(blocks[&arm]);
+            self.switch_to(blocks[&arm]);
+            let value = self.lower_expr(&arms[arm].body);
+            if let Some(value) = value {
+                values.push((self.current(), value));
+            }
+            self.jump_to(join);
+        }
+
+        values
+    }
+
+    fn lower_pattern(&mut self, pat: &Pat, value: &Operand, matched: BlockId, failed: BlockId) {
+        let span = pat.span;
+
+        let cond = match &pat.kind {
+            PatKind::Wildcard => {
+                self.terminate(Terminator::Jump(matched));
+                return;
+            }
+            PatKind::Literal(literal) => self.compare(value, BinOp::Eq, literal, span),
+            PatKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                // Check the start of the range, then the end.
+                let above_start = self.compare(value, BinOp::Ge, start, span);
+                let check_end = self.new_block();
+                self.terminate(Terminator::Branch {
+                    cond: above_start,
+                    then: check_end,
+                    otherwise: failed,
+                });
+                self.seal(check_end);
+                self.switch_to(check_end);
+
+                let op = if *inclusive { BinOp::Le } else { BinOp::Lt };
+                self.compare(value, op, end, span)
+            }
+            PatKind::Or(alternatives) => {
+                let (last, rest) = alternatives
+                    .split_last()
+                    .expect("or patterns have at least two alternatives");
+
+                for alternative in rest {
+                    let next = self.new_block();
+                    self.lower_pattern(alternative, value, matched, next);
+                    self.seal(next);
+                    self.switch_to(next);
+
+                    // Alternatives after a wildcard are unreachable.
+                    if self.current.is_none() {
+                        return;
+                    }
+                }
+
+                self.lower_pattern(last, value, matched, failed);
+                return;
+            }
+            PatKind::Error => unreachable!("erroneous programs are never lowered"),
+        };
+
+        self.terminate(Terminator::Branch {
+            cond,
+            then: matched,
+            otherwise: failed,
+        });
+    }
+
+    fn compare(
+        &mut self,
+        value: &Operand,
+        op: BinOp,
+        literal: &hir::Literal,
+        span: Span,
+    ) -> Operand {
+        let kind = InstKind::Binary {
+            lhs: value.clone(),
+            op,
+            rhs: Operand::Const(literal.clone()),
+        };
+        self.push_inst(kind, Ty::Bool, span)
+            .expect("comparisons produce bools")
+    }
+
+    fn finish(self, params: Vec<Temp>) -> Body {
+        let mut body = Body {
+            proc: self.proc.id,
+            name: self.program.qualified_name(self.proc.id),
+            symbol: self.program.symbol_name(self.proc.id),
+            params,
+            ret_ty: self.proc.ret_ty,
+            temps: self.temps,
+            blocks: self.blocks.into_iter().map(|data| data.block).collect(),
+            overflow: self.program.overflow,
+        };
+
+        remove_trivial_phis(&mut body);
+        remove_unreachable_blocks(&mut body);
+        renumber_temps(&mut body);
+        body
+    }
+}
+
+/// Lower a procedure to SSA form.
+pub fn lower_proc(program: &Program, proc: &Proc) -> Body {
+    let mut builder = Builder::new(program, proc);
+
+    let params = proc
+        .params
+        .iter()
+        .map(|&param| {
+            let temp = builder.new_temp(proc.local(param).ty);
+            builder.write_local(param, BlockId::ENTRY, Operand::Temp(temp));
+            temp
+        })
+        .collect();
+
+    builder.lower_block(&proc.body);
+
+    // Lowering checks that non-void procedures always return, so only void procedures can
+    // reach the end of their body.
+    if builder.current.is_some() {
+        builder.terminate(Terminator::Ret(None));
+    }
+
+    builder.finish(params)
+}
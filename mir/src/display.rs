@@ -0,0 +1,198 @@
+//! The text format of MIR, as printed by `--emit mir`.
+//!
+//! ```text
+//! proc count(%0: int) int {
+//! bb0:
+//!     jump bb1
+//! bb1:
+//!     %1: int = phi [bb0: 0], [bb2: %3]
+//!     %2: bool = lt %1, %0
+//!     branch %2, bb2, bb3
+//! ...
+//! }
+//! ```
+
+use crate::nodes::{Body, Inst, InstKind, Operand, Program, Terminator};
+use hir::{float, BinOp, Literal, UnOp};
+use std::fmt;
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Temp(temp) => write!(f, "%{}", temp.0),
+            Self::Const(Literal::Int(value, ty)) => write!(f, "{}", ty.value(*value)),
+            Self::Const(Literal::Float(value)) => write!(f, "{}", float::format(*value)),
+            Self::Const(Literal::Bool(value)) => write!(f, "{value}"),
+            Self::Const(Literal::Char(value)) => write!(f, "{value:?}"),
+            Self::Const(Literal::Str(value)) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+fn unary_mnemonic(op: UnOp) -> &'static str {
+    match op {
+        UnOp::Neg => "neg",
+        UnOp::Not => "not",
+        UnOp::BitNot => "bitnot",
+        // The type converted to is the type of the result.
+        UnOp::Cast(_) => "cast",
+    }
+}
+
+fn binary_mnemonic(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Rem => "rem",
+        BinOp::BitAnd => "bitand",
+        BinOp::BitOr => "bitor",
+        BinOp::Shl => "shl",
+        BinOp::Shr => "shr",
+        BinOp::Eq => "eq",
+        BinOp::Ne => "ne",
+        BinOp::Lt => "lt",
+        BinOp::Le => "le",
+        BinOp::Gt => "gt",
+        BinOp::Ge => "ge",
+    }
+}
+
+/// Write a comma separated list.
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+
+    Ok(())
+}
+
+impl Body {
+    fn fmt_inst(&self, f: &mut fmt::Formatter<'_>, program: &Program, inst: &Inst) -> fmt::Result {
+        write!(f, "    ")?;
+        if let Some(dest) = inst.dest {
+            write!(f, "%{}: {} = ", dest.0, self.temp_ty(dest))?;
+        }
+
+        match &inst.kind {
+            InstKind::Unary { op, operand } => write!(f, "{} {operand}", unary_mnemonic(*op))?,
+            InstKind::Binary { lhs, op, rhs } => {
+                write!(f, "{} {lhs}, {rhs}", binary_mnemonic(*op))?;
+            }
+            InstKind::Call { callee, args } => {
+                write!(f, "call {}(", program.body(*callee).name)?;
+                write_list(f, args)?;
+                write!(f, ")")?;
+            }
+            InstKind::Builtin { builtin, args } => {
+                write!(f, "builtin {}(", builtin.name())?;
+                write_list(f, args)?;
+                write!(f, ")")?;
+            }
+            InstKind::Native { native, args } => {
+                write!(f, "native {}(", program.native(*native).name)?;
+                write_list(f, args)?;
+                write!(f, ")")?;
+            }
+            InstKind::Array { elements } => {
+                write!(f, "array [")?;
+                write_list(f, elements)?;
+                write!(f, "]")?;
+            }
+            InstKind::Map { entries } => {
+                write!(f, "map [")?;
+                for (i, entry) in entries.chunks(2).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", entry[0], entry[1])?;
+                }
+                write!(f, "]")?;
+            }
+            InstKind::Index { array, index } => write!(f, "index {array}[{index}]")?,
+            InstKind::Store {
+                array,
+                index,
+                value,
+            } => write!(f, "store {array}[{index}], {value}")?,
+        }
+
+        writeln!(f)
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, program: &Program) -> fmt::Result {
+        write!(f, "proc {}(", self.name)?;
+        for (i, &param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "%{}: {}", param.0, self.temp_ty(param))?;
+        }
+        writeln!(f, ") {} {{", self.ret_ty)?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "bb{i}:")?;
+
+            for phi in &block.phis {
+                write!(f, "    %{}: {} = phi ", phi.dest.0, self.temp_ty(phi.dest))?;
+                for (j, (pred, value)) in phi.incoming.iter().enumerate() {
+                    if j > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[bb{}: {value}]", pred.0)?;
+                }
+                writeln!(f)?;
+            }
+
+            for inst in &block.insts {
+                self.fmt_inst(f, program, inst)?;
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump bb{}", target.0)?,
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => writeln!(f, "    branch {cond}, bb{}, bb{}", then.0, otherwise.0)?,
+                Terminator::Switch {
+                    value,
+                    low,
+                    targets,
+                    otherwise,
+                } => {
+                    write!(f, "    switch {value} from {low}, [")?;
+                    for (i, target) in targets.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "bb{}", target.0)?;
+                    }
+                    writeln!(f, "], bb{}", otherwise.0)?;
+                }
+                Terminator::Ret(Some(value)) => writeln!(f, "    ret {value}")?,
+                Terminator::Ret(None) => writeln!(f, "    ret")?,
+                Terminator::Unreachable => writeln!(f, "    unreachable")?,
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, body) in self.bodies.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            body.fmt_with(f, self)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod build;
+mod display;
+mod nodes;
+pub mod opt;
+mod transform;
+
+pub use nodes::*;
+
+/// Lower every procedure of a program to SSA form.
+pub fn lower(program: &hir::Program) -> Program {
+    Program {
+        bodies: program
+            .procs
+            .iter()
+            .map(|proc| build::lower_proc(program, proc))
+            .collect(),
+        natives: program.natives.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashSet;
+
+    fn lower_source(source: &str) -> anyhow::Result<Program> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        Ok(lower(&hir::lower(&ast)?.program))
+    }
+
+    /// Check that every temporary is defined exactly once.
+    fn assert_single_assignment(body: &Body) {
+        let mut defined = body.params.iter().copied().collect::<HashSet<_>>();
+        let defs = body.blocks.iter().flat_map(|block| {
+            let phis = block.phis.iter().map(|phi| phi.dest);
+            phis.chain(block.insts.iter().filter_map(|inst| inst.dest))
+        });
+
+        for temp in defs {
+            assert!(defined.insert(temp), "%{} is defined twice", temp.0);
+        }
+    }
+
+    #[test]
+    fn test_lower_loop_to_ssa() -> anyhow::Result<()> {
+        let source = "proc sum(n: int) int {
+            let total = 0;
+            for let i = 0; i < n; i += 1 { total += i; }
+            ret total;
+        }";
+        let program = lower_source(source)?;
+        assert_single_assignment(&program.bodies[0]);
+
+        assert_eq!(
+            program.to_string(),
+            "proc sum(%0: int) int {
+bb0:
+    jump bb1
+bb1:
+    %1: int = phi [bb0: 0], [bb2: %5]
+    %2: int = phi [bb0: 0], [bb2: %4]
+    %3: bool = lt %1, %0
+    branch %3, bb2, bb3
+bb2:
+    %4: int = add %2, %1
+    %5: int = add %1, 1
+    jump bb1
+bb3:
+    ret %2
+}
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_branches_and_matches_to_ssa() -> anyhow::Result<()> {
+        let source = "proc f(x: int, b: bool) int {
+            let y = 1;
+            if b { y = 2; }
+            let unchanged = x;
+            ret match y { 0..=1 => unchanged, _ => y };
+        }";
+        let program = lower_source(source)?;
+        assert_single_assignment(&program.bodies[0]);
+
+        // `unchanged` and `x` never differ, so no phi is needed for them.
+        assert_eq!(
+            program.to_string(),
+            "proc f(%0: int, %1: bool) int {
+bb0:
+    branch %1, bb1, bb2
+bb1:
+    jump bb2
+bb2:
+    %2: int = phi [bb0: 1], [bb1: 2]
+    %3: bool = ge %2, 0
+    branch %3, bb6, bb5
+bb3:
+    %4: int = phi [bb4: %0], [bb7: %2]
+    ret %4
+bb4:
+    jump bb3
+bb5:
+    jump bb7
+bb6:
+    %5: bool = le %2, 1
+    branch %5, bb4, bb5
+bb7:
+    jump bb3
+}
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_dense_matches_to_switches() -> anyhow::Result<()> {
+        let source = "proc f(x: int) int {
+            ret match x { 1 => 10, 2 | 4 => 20, 3 => x, 6 => 60, _ => 0 };
+        }";
+        let program = lower_source(source)?;
+        assert_single_assignment(&program.bodies[0]);
+
+        // 5 isn't matched, so it selects the wildcard arm like ints outside the table.
+        assert_eq!(
+            program.to_string(),
+            "proc f(%0: int) int {
+bb0:
+    switch %0 from 1, [bb2, bb3, bb4, bb3, bb6, bb5], bb6
+bb1:
+    %1: int = phi [bb2: 10], [bb3: 20], [bb4: %0], [bb5: 60], [bb6: 0]
+    ret %1
+bb2:
+    jump bb1
+bb3:
+    jump bb1
+bb4:
+    jump bb1
+bb5:
+    jump bb1
+bb6:
+    jump bb1
+}
+"
+        );
+
+        Ok(())
+    }
+}
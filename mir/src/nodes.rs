@@ -0,0 +1,226 @@
+use hir::{BinOp, Builtin, Literal, NativeId, NativeSignature, Overflow, ProcId, Ty, UnOp};
+use span::Span;
+
+/// Identifies an SSA value within a body. Indexes into [`Body::temps`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Temp(pub u32);
+
+/// Identifies a basic block within a body. Indexes into [`Body::blocks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl BlockId {
+    /// The block a body starts at.
+    pub const ENTRY: Self = Self(0);
+}
+
+/// A value used by an instruction: either a temporary or a constant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Const(Literal),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstKind {
+    Unary {
+        op: UnOp,
+        operand: Operand,
+    },
+
+    /// A binary operation. Unlike in the HIR, comparisons also apply to chars and bools, which
+    /// compare as their integer values. This is how range patterns are lowered.
+    Binary {
+        lhs: Operand,
+        op: BinOp,
+        rhs: Operand,
+    },
+
+    Call {
+        callee: ProcId,
+        args: Vec<Operand>,
+    },
+
+    /// A call to a procedure built into the language, implemented by the runtime.
+    Builtin {
+        builtin: Builtin,
+        args: Vec<Operand>,
+    },
+
+    /// A call to a native procedure of the program's host, which only the interpreter and the
+    /// VM can make.
+    Native {
+        native: NativeId,
+        args: Vec<Operand>,
+    },
+
+    /// Allocate a new array holding the elements.
+    Array {
+        elements: Vec<Operand>,
+    },
+
+    /// Allocate a new map holding the entries, which are the key and then the value of each.
+    Map {
+        entries: Vec<Operand>,
+    },
+
+    /// Read an element of an array, which fails if the index is out of bounds, or the value of
+    /// a key in a map, which fails if the map doesn't have the key.
+    Index {
+        array: Operand,
+        index: Operand,
+    },
+
+    /// Store into an element of an array, which fails if the index is out of bounds, or set the
+    /// value of a key in a map.
+    Store {
+        array: Operand,
+        index: Operand,
+        value: Operand,
+    },
+}
+
+/// An instruction, which defines its destination temporary, if it produces a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub dest: Option<Temp>,
+    pub kind: InstKind,
+    pub span: Span,
+}
+
+/// Selects a value depending on the predecessor control came from. Phis are evaluated
+/// simultaneously on entry to their block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phi {
+    pub dest: Temp,
+
+    /// The value for each predecessor of the block.
+    pub incoming: Vec<(BlockId, Operand)>,
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+
+    /// Jump to `then` if the bool `cond` is `true`, and to `otherwise` if it's `false`.
+    Branch {
+        cond: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+
+    /// Jump to the target for the int `value` in a table of targets for the ints from `low` up,
+    /// and to `otherwise` if it's outside the table.
+    Switch {
+        value: Operand,
+        low: i64,
+        targets: Vec<BlockId>,
+        otherwise: BlockId,
+    },
+
+    Ret(Option<Operand>),
+
+    /// Control never reaches the end of this block, such as after the last arm of an
+    /// exhaustive match fails to match.
+    Unreachable,
+}
+
+impl Terminator {
+    /// The blocks control can continue at. A switch's are each listed once, in the order of
+    /// its table.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(target) => vec![*target],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Self::Switch {
+                targets, otherwise, ..
+            } => {
+                let mut successors = Vec::new();
+                for &target in targets.iter().chain([otherwise]) {
+                    if !successors.contains(&target) {
+                        successors.push(target);
+                    }
+                }
+                successors
+            }
+            Self::Ret(_) | Self::Unreachable => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+/// The SSA form of a procedure. Every temporary is defined exactly once, by a parameter, phi or
+/// instruction, and that definition dominates its uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub proc: ProcId,
+
+    /// The procedure's name, qualified with the path of modules containing it.
+    pub name: String,
+
+    /// The mangled name of the procedure's symbol.
+    pub symbol: String,
+
+    /// The temporaries holding the arguments.
+    pub params: Vec<Temp>,
+    pub ret_ty: Ty,
+
+    /// The type of every temporary.
+    pub temps: Vec<Ty>,
+
+    /// The body's basic blocks, starting with [`BlockId::ENTRY`].
+    pub blocks: Vec<BasicBlock>,
+
+    /// What the body's integer arithmetic does on overflow, from the program it was built from.
+    pub overflow: Overflow,
+}
+
+impl Body {
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn temp_ty(&self, temp: Temp) -> Ty {
+        self.temps[temp.0 as usize]
+    }
+
+    /// The native procedures the body calls, in the order of its calls.
+    pub fn natives(&self) -> impl Iterator<Item = NativeId> + '_ {
+        self.blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|inst| match inst.kind {
+                InstKind::Native { native, .. } => Some(native),
+                _ => None,
+            })
+    }
+}
+
+/// A program in MIR.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    /// Indexed by [`ProcId`].
+    pub bodies: Vec<Body>,
+
+    /// The native procedures the program was lowered with, indexed by [`NativeId`].
+    pub natives: Vec<NativeSignature>,
+}
+
+impl Program {
+    pub fn body(&self, id: ProcId) -> &Body {
+        &self.bodies[id.0 as usize]
+    }
+
+    pub fn native(&self, id: NativeId) -> &NativeSignature {
+        &self.natives[id.0 as usize]
+    }
+}
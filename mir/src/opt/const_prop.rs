@@ -0,0 +1,86 @@
+//! Constant propagation: instructions whose operands are all constants are evaluated at compile
+//! time, and their uses replaced with the result.
+
+use crate::{
+    nodes::{Body, Inst, InstKind, Operand, Temp},
+    transform::{replace_uses, resolve},
+};
+use hir::{consteval, BinOp, Literal, Overflow};
+use std::collections::HashMap;
+
+fn constant(replacements: &HashMap<Temp, Operand>, operand: &Operand) -> Option<Literal> {
+    match resolve(replacements, operand) {
+        Operand::Const(literal) => Some(literal),
+        Operand::Temp(_) => None,
+    }
+}
+
+/// Compare chars or bools, which MIR allows but the constant evaluator doesn't.
+fn compare<T: Ord>(op: BinOp, lhs: T, rhs: T) -> Option<Literal> {
+    let result = match op {
+        BinOp::Eq => lhs == rhs,
+        BinOp::Ne => lhs != rhs,
+        BinOp::Lt => lhs < rhs,
+        BinOp::Le => lhs <= rhs,
+        BinOp::Gt => lhs > rhs,
+        BinOp::Ge => lhs >= rhs,
+        _ => return None,
+    };
+
+    Some(Literal::Bool(result))
+}
+
+/// Evaluate an instruction, if its operands are constants. Instructions that would fail, such
+/// as a division by zero, are left to fail at runtime.
+fn fold(replacements: &HashMap<Temp, Operand>, inst: &Inst, overflow: Overflow) -> Option<Literal> {
+    match &inst.kind {
+        InstKind::Unary { op, operand } => {
+            consteval::eval_unary(*op, constant(replacements, operand)?, overflow, inst.span).ok()
+        }
+        InstKind::Binary { lhs, op, rhs } => {
+            match (constant(replacements, lhs)?, constant(replacements, rhs)?) {
+                (Literal::Char(lhs), Literal::Char(rhs)) => compare(*op, lhs, rhs),
+                (Literal::Bool(lhs), Literal::Bool(rhs)) => compare(*op, lhs, rhs),
+                (lhs, rhs) => consteval::eval_binary(*op, lhs, rhs, overflow, inst.span).ok(),
+            }
+        }
+        InstKind::Call { .. }
+        | InstKind::Builtin { .. }
+        | InstKind::Native { .. }
+        | InstKind::Array { .. }
+        | InstKind::Map { .. }
+        | InstKind::Index { .. }
+        | InstKind::Store { .. } => None,
+    }
+}
+
+pub fn run(body: &mut Body) -> bool {
+    let mut replacements = HashMap::new();
+    let overflow = body.overflow;
+
+    // Blocks aren't ordered by dominance, so folding an instruction can make an earlier one's
+    // operands constant. This repeats until nothing more folds.
+    loop {
+        let mut changed = false;
+
+        for block in &mut body.blocks {
+            block.insts.retain(|inst| {
+                let (Some(dest), Some(value)) = (inst.dest, fold(&replacements, inst, overflow))
+                else {
+                    return true;
+                };
+
+                replacements.insert(dest, Operand::Const(value));
+                changed = true;
+                false
+            });
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    replace_uses(body, &replacements);
+    !replacements.is_empty()
+}
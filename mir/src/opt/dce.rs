@@ -0,0 +1,75 @@
+//! Dead code elimination: blocks control never reaches, and phis and instructions whose values
+//! are never used, are removed. Branches on constants are folded first, so the blocks constant
+//! propagation proved are skipped count as unreachable.
+//!
+//! Instructions that can fail at runtime, such as checked integer arithmetic, calls and calls to
+//! natives, which can do anything, array accesses and builtins that do I/O or can fail are kept
+//! even when unused, so removing them doesn't change what a program does.
+
+use super::{simplify_cfg::fold_branches, Stats};
+use crate::{
+    nodes::{Body, InstKind, Operand},
+    transform::{remove_unreachable_blocks, used_temps},
+};
+use hir::{BinOp, Ty, UnOp};
+
+fn can_fail_or_has_effects(kind: &InstKind, temps: &[Ty]) -> bool {
+    match kind {
+        InstKind::Unary { op, operand } => {
+            matches!(op, UnOp::Neg | UnOp::Cast(_))
+                && matches!(operand_ty(operand, temps), Ty::Int(_))
+        }
+        InstKind::Binary { lhs, op, .. } => {
+            use BinOp::*;
+
+            matches!(op, Add | Sub | Mul | Div | Rem | Shl | Shr)
+                && matches!(operand_ty(lhs, temps), Ty::Int(_))
+        }
+        InstKind::Call { .. }
+        | InstKind::Native { .. }
+        | InstKind::Index { .. }
+        | InstKind::Store { .. } => true,
+        InstKind::Array { .. } | InstKind::Map { .. } => false,
+        InstKind::Builtin { builtin, .. } => builtin.has_side_effects() || builtin.can_fail(),
+    }
+}
+
+fn operand_ty(operand: &Operand, temps: &[Ty]) -> Ty {
+    match operand {
+        Operand::Temp(temp) => temps[temp.0 as usize],
+        Operand::Const(literal) => literal.ty(),
+    }
+}
+
+pub fn run(body: &mut Body, stats: &mut Stats) -> bool {
+    // Unreachable blocks can use values nothing else does, so they go first.
+    let blocks = body.blocks.len();
+    let folded = fold_branches(body);
+    let mut changed = remove_unreachable_blocks(body) || folded;
+    stats.dead_blocks += blocks - body.blocks.len();
+
+    // Removing a value can leave the values it used unused too.
+    loop {
+        let used = used_temps(body);
+        let mut removed = false;
+
+        for block in &mut body.blocks {
+            let (phis, insts) = (block.phis.len(), block.insts.len());
+            block.phis.retain(|phi| used.contains(&phi.dest));
+            block.insts.retain(|inst| match inst.dest {
+                Some(dest) if !used.contains(&dest) => {
+                    can_fail_or_has_effects(&inst.kind, &body.temps)
+                }
+                _ => true,
+            });
+            stats.dead_phis += phis - block.phis.len();
+            stats.dead_insts += insts - block.insts.len();
+            removed |= block.phis.len() + block.insts.len() < phis + insts;
+        }
+
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
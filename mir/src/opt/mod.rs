@@ -0,0 +1,303 @@
+//! Optimization passes over MIR, run in order by a [`PassManager`].
+
+mod const_prop;
+mod dce;
+mod simplify_cfg;
+
+use crate::{
+    nodes::{Body, Program},
+    transform::{remove_trivial_phis, renumber_temps},
+};
+use std::fmt;
+
+/// How many times the `-O2` pipeline repeats at most, in case passes keep undoing each other.
+const MAX_ITERATIONS: usize = 8;
+
+/// An optimization pass over a body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// Evaluate instructions whose operands are constants, replacing their uses with the result.
+    ConstProp,
+
+    /// Replace phis that always select the same value with that value.
+    CopyProp,
+
+    /// Remove unreachable blocks, and phis and instructions whose values are never used.
+    Dce,
+
+    /// Turn branches on constants into jumps, skip empty blocks and merge blocks into their
+    /// only predecessor.
+    SimplifyCfg,
+}
+
+impl Pass {
+    pub const ALL: [Self; 4] = [
+        Self::ConstProp,
+        Self::CopyProp,
+        Self::Dce,
+        Self::SimplifyCfg,
+    ];
+
+    /// The name of the pass on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ConstProp => "const-prop",
+            Self::CopyProp => "copy-prop",
+            Self::Dce => "dce",
+            Self::SimplifyCfg => "simplify-cfg",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|pass| pass.name() == name)
+    }
+
+    /// Run the pass over a body, counting what it removes, and returning whether it changed
+    /// anything.
+    pub fn run(self, body: &mut Body, stats: &mut Stats) -> bool {
+        let changed = match self {
+            Self::ConstProp => const_prop::run(body),
+            Self::CopyProp => remove_trivial_phis(body),
+            Self::Dce => dce::run(body, stats),
+            Self::SimplifyCfg => simplify_cfg::run(body),
+        };
+
+        if changed {
+            renumber_temps(body);
+        }
+        changed
+    }
+}
+
+/// What the passes of a pipeline removed from a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Blocks dead code elimination removed because control never reaches them.
+    pub dead_blocks: usize,
+
+    /// Phis dead code elimination removed because their values are never used.
+    pub dead_phis: usize,
+
+    /// Instructions dead code elimination removed because their values are never used.
+    pub dead_insts: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dce removed {} unreachable block(s), {} phi(s) and {} instruction(s)",
+            self.dead_blocks, self.dead_phis, self.dead_insts
+        )
+    }
+}
+
+/// Runs an ordered pipeline of passes over every body of a program.
+#[derive(Debug, Clone, Default)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+
+    /// Whether to repeat the pipeline until it no longer changes anything.
+    repeat: bool,
+}
+
+impl PassManager {
+    /// A pipeline that runs each pass once, in order.
+    pub fn new(passes: Vec<Pass>) -> Self {
+        Self {
+            passes,
+            repeat: false,
+        }
+    }
+
+    /// The pipeline for an optimization level. `-O0` doesn't optimize, `-O1` runs every pass
+    /// once, and `-O2` repeats them until the program stops changing. Higher levels are the
+    /// same as `-O2`.
+    pub fn preset(level: u8) -> Self {
+        let passes = vec![
+            Pass::ConstProp,
+            Pass::CopyProp,
+            Pass::SimplifyCfg,
+            Pass::Dce,
+        ];
+        match level {
+            0 => Self::default(),
+            1 => Self::new(passes),
+            _ => Self {
+                passes,
+                repeat: true,
+            },
+        }
+    }
+
+    /// Leave a pass out of the pipeline, such as to check whether it causes a miscompile.
+    pub fn without(mut self, pass: Pass) -> Self {
+        self.passes.retain(|&kept| kept != pass);
+        self
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    /// Run the pipeline over a program, calling `after` with the program after each pass, and
+    /// return what the passes removed.
+    pub fn run(&self, program: &mut Program, mut after: impl FnMut(Pass, &Program)) -> Stats {
+        let mut stats = Stats::default();
+        for _ in 0..MAX_ITERATIONS {
+            let mut changed = false;
+            for &pass in &self.passes {
+                for body in &mut program.bodies {
+                    changed |= pass.run(body, &mut stats);
+                }
+                after(pass, program);
+            }
+
+            if !self.repeat || !changed {
+                break;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn optimize(source: &str, level: u8) -> anyhow::Result<String> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let mut program = crate::lower(&hir::lower(&ast)?.program);
+        PassManager::preset(level).run(&mut program, |_, _| {});
+        Ok(program.to_string())
+    }
+
+    #[test]
+    fn test_optimize_folds_constants_and_branches() -> anyhow::Result<()> {
+        let source = "proc f(x: int) int {
+            let scale = 2 * 3;
+            let unused = x == 4;
+            if scale > 5 { x = x + scale; } else { x = 0; }
+            ret match 'm' { 'a'..='z' => x, _ => 0 };
+        }";
+
+        assert_eq!(
+            optimize(source, 2)?,
+            "proc f(%0: int) int {
+bb0:
+    %1: int = add %0, 6
+    ret %1
+}
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimize_keeps_runtime_failures_and_loops() -> anyhow::Result<()> {
+        let source = "proc f(n: int) int {
+            let zero = 0;
+            let unused = n / zero;
+            let i = 0;
+            while i < n { i += 1; }
+            ret i;
+        }";
+
+        assert_eq!(
+            optimize(source, 2)?,
+            "proc f(%0: int) int {
+bb0:
+    %1: int = div %0, 0
+    jump bb1
+bb1:
+    %2: int = phi [bb0: 0], [bb2: %4]
+    %3: bool = lt %2, %0
+    branch %3, bb2, bb3
+bb2:
+    %4: int = add %2, 1
+    jump bb1
+bb3:
+    ret %2
+}
+"
+        );
+
+        // Nothing changes without optimization.
+        let unoptimized = optimize(source, 0)?;
+        assert!(unoptimized.contains("%1: int = div %0, 0"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dce_counts_what_it_removes() -> anyhow::Result<()> {
+        let source = "proc f(x: int) int {
+            let debug = false;
+            let unused = x == 3;
+            if debug { x = 0; }
+            ret x;
+        }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let lowered = crate::lower(&hir::lower(&ast)?.program);
+
+        let mut program = lowered.clone();
+        let passes = vec![Pass::ConstProp, Pass::Dce];
+        let stats = PassManager::new(passes).run(&mut program, |_, _| {});
+        assert_eq!(
+            stats,
+            Stats {
+                dead_blocks: 1,
+                dead_phis: 0,
+                dead_insts: 1,
+            }
+        );
+        assert!(!program.to_string().contains("eq"));
+
+        // Without DCE, the unused comparison is kept.
+        let mut program = lowered;
+        let stats = PassManager::preset(2)
+            .without(Pass::Dce)
+            .run(&mut program, |_, _| {});
+        assert_eq!(stats, Stats::default());
+        assert!(program.to_string().contains("eq %0, 3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimize_folds_switches() -> anyhow::Result<()> {
+        let source = "proc f(x: int) int {
+            let known = match 3 { 1 => 10, 2 => 20, 3 => x, 4 => 40, _ => 0 };
+            ret known + match x { 1 => 10, 2 => 20, 3 => 30, 4 => 40, _ => 0 };
+        }";
+
+        // Only one empty arm can be skipped, since the others would give the join's phi a
+        // second value from the switch.
+        assert_eq!(
+            optimize(source, 2)?,
+            "proc f(%0: int) int {
+bb0:
+    switch %0 from 1, [bb1, bb2, bb3, bb4], bb5
+bb1:
+    %1: int = phi [bb2: 20], [bb3: 30], [bb4: 40], [bb5: 0], [bb0: 10]
+    %2: int = add %0, %1
+    ret %2
+bb2:
+    jump bb1
+bb3:
+    jump bb1
+bb4:
+    jump bb1
+bb5:
+    jump bb1
+}
+"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Control flow simplification: branches and switches on constants become jumps, empty blocks
+//! that only jump elsewhere are skipped, and blocks are merged into their only predecessor.
+
+use crate::{
+    nodes::{BasicBlock, BlockId, Body, Operand, Terminator},
+    transform::{predecessors, remove_unreachable_blocks, replace_uses},
+};
+use hir::Literal;
+use std::collections::HashMap;
+
+/// Make a terminator continue at `to` instead of `from`.
+fn retarget(terminator: &mut Terminator, from: BlockId, to: BlockId) {
+    match terminator {
+        Terminator::Jump(target) => {
+            if *target == from {
+                *target = to;
+            }
+        }
+        Terminator::Branch {
+            then, otherwise, ..
+        } => {
+            for target in [then, otherwise] {
+                if *target == from {
+                    *target = to;
+                }
+            }
+        }
+        Terminator::Switch {
+            targets, otherwise, ..
+        } => {
+            for target in targets.iter_mut().chain([otherwise]) {
+                if *target == from {
+                    *target = to;
+                }
+            }
+        }
+        Terminator::Ret(_) | Terminator::Unreachable => {}
+    }
+}
+
+/// The block a branch or switch always continues at, if there's only one.
+fn only_target(terminator: &Terminator) -> Option<BlockId> {
+    match terminator {
+        Terminator::Branch {
+            then, otherwise, ..
+        } if then == otherwise => Some(*then),
+        Terminator::Branch {
+            cond: Operand::Const(Literal::Bool(cond)),
+            then,
+            otherwise,
+        } => Some(if *cond { *then } else { *otherwise }),
+        Terminator::Switch {
+            value: Operand::Const(Literal::Int(value, ty)),
+            low,
+            targets,
+            otherwise,
+        } => Some(
+            usize::try_from(ty.value(*value) - i128::from(*low))
+                .ok()
+                .and_then(|index| targets.get(index))
+                .copied()
+                .unwrap_or(*otherwise),
+        ),
+        Terminator::Switch { .. } => match terminator.successors().as_slice() {
+            &[target] => Some(target),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Replace branches and switches that always go the same way with jumps.
+pub(super) fn fold_branches(body: &mut Body) -> bool {
+    let mut changed = false;
+
+    for i in 0..body.blocks.len() {
+        let block = BlockId(i as u32);
+        let terminator = &body.blocks[i].terminator;
+        let Some(taken) = only_target(terminator) else {
+            continue;
+        };
+
+        for skipped in terminator.successors() {
+            if skipped != taken {
+                for phi in &mut body.blocks[skipped.0 as usize].phis {
+                    phi.incoming.retain(|(pred, _)| *pred != block);
+                }
+            }
+        }
+        body.blocks[i].terminator = Terminator::Jump(taken);
+        changed = true;
+    }
+
+    changed
+}
+
+/// Make the predecessors of a block that only jumps elsewhere jump there directly. Blocks are
+/// skipped one at a time, returning whether one was.
+fn skip_empty_block(body: &mut Body) -> bool {
+    let preds = predecessors(body);
+    let skippable = body
+        .blocks
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(i, block)| {
+            let Terminator::Jump(target) = block.terminator else {
+                return None;
+            };
+            // A predecessor that's already a predecessor of the target would need two different
+            // values in the target's phis.
+            let skippable = block.phis.is_empty()
+                && block.insts.is_empty()
+                && target.0 as usize != i
+                && !preds[i].is_empty()
+                && preds[i]
+                    .iter()
+                    .all(|pred| !preds[target.0 as usize].contains(pred));
+
+            skippable.then_some((BlockId(i as u32), target))
+        });
+    let Some((empty, target)) = skippable else {
+        return false;
+    };
+
+    let empty_preds = &preds[empty.0 as usize];
+    for pred in empty_preds {
+        retarget(&mut body.blocks[pred.0 as usize].terminator, empty, target);
+    }
+    for phi in &mut body.blocks[target.0 as usize].phis {
+        let index = phi
+            .incoming
+            .iter()
+            .position(|(pred, _)| *pred == empty)
+            .expect("phis have a value for every predecessor");
+        let (_, value) = phi.incoming.remove(index);
+        phi.incoming
+            .extend(empty_preds.iter().map(|&pred| (pred, value.clone())));
+    }
+
+    true
+}
+
+/// Merge a block into its predecessor, if that's its only predecessor and it only jumps there.
+/// Blocks are merged one at a time, returning whether one was.
+fn merge_block(body: &mut Body) -> bool {
+    let preds = predecessors(body);
+
+    for (i, merged_preds) in preds.iter().enumerate().skip(1) {
+        let merged = BlockId(i as u32);
+        let &[pred] = merged_preds.as_slice() else {
+            continue;
+        };
+        if pred == merged || body.block(pred).terminator != Terminator::Jump(merged) {
+            continue;
+        }
+
+        let block = std::mem::replace(
+            &mut body.blocks[i],
+            BasicBlock {
+                phis: Vec::new(),
+                insts: Vec::new(),
+                terminator: Terminator::Unreachable,
+            },
+        );
+
+        // With a single predecessor, every phi selects the same value.
+        let replacements = block
+            .phis
+            .into_iter()
+            .map(|mut phi| (phi.dest, phi.incoming.remove(0).1))
+            .collect::<HashMap<_, _>>();
+
+        for succ in block.terminator.successors() {
+            for phi in &mut body.blocks[succ.0 as usize].phis {
+                for (incoming_pred, _) in &mut phi.incoming {
+                    if *incoming_pred == merged {
+                        *incoming_pred = pred;
+                    }
+                }
+            }
+        }
+
+        let pred_block = &mut body.blocks[pred.0 as usize];
+        pred_block.insts.extend(block.insts);
+        pred_block.terminator = block.terminator;
+        replace_uses(body, &replacements);
+        return true;
+    }
+
+    false
+}
+
+pub fn run(body: &mut Body) -> bool {
+    let mut changed = false;
+
+    // Unreachable blocks are removed after every step, so the steps only see the predecessors
+    // control can actually come from.
+    loop {
+        let simplified = fold_branches(body) || skip_empty_block(body) || merge_block(body);
+        let removed = remove_unreachable_blocks(body);
+        if !simplified && !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
@@ -0,0 +1,275 @@
+//! Transformations of bodies shared by SSA construction and the optimization passes.
+
+use crate::nodes::{BlockId, Body, InstKind, Operand, Temp, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// Follow the replacements of removed values to the operand that's left.
+pub fn resolve(replacements: &HashMap<Temp, Operand>, operand: &Operand) -> Operand {
+    let mut operand = operand;
+    while let Operand::Temp(temp) = operand {
+        match replacements.get(temp) {
+            Some(replacement) => operand = replacement,
+            None => break,
+        }
+    }
+
+    operand.clone()
+}
+
+/// Call a function on every operand used in a body.
+pub fn for_each_operand(body: &mut Body, mut f: impl FnMut(&mut Operand)) {
+    for block in &mut body.blocks {
+        for phi in &mut block.phis {
+            phi.incoming.iter_mut().for_each(|(_, operand)| f(operand));
+        }
+
+        for inst in &mut block.insts {
+            match &mut inst.kind {
+                InstKind::Unary { operand, .. } => f(operand),
+                InstKind::Binary { lhs, rhs, .. } => {
+                    f(lhs);
+                    f(rhs);
+                }
+                InstKind::Call { args, .. }
+                | InstKind::Builtin { args, .. }
+                | InstKind::Native { args, .. }
+                | InstKind::Array { elements: args }
+                | InstKind::Map { entries: args } => {
+                    args.iter_mut().for_each(&mut f);
+                }
+                InstKind::Index { array, index } => {
+                    f(array);
+                    f(index);
+                }
+                InstKind::Store {
+                    array,
+                    index,
+                    value,
+                } => {
+                    f(array);
+                    f(index);
+                    f(value);
+                }
+            }
+        }
+
+        match &mut block.terminator {
+            Terminator::Branch { cond, .. } | Terminator::Switch { value: cond, .. } => f(cond),
+            Terminator::Ret(Some(value)) => f(value),
+            Terminator::Jump(_) | Terminator::Ret(None) | Terminator::Unreachable => {}
+        }
+    }
+}
+
+/// Replace every use of the temporaries in `replacements`.
+pub fn replace_uses(body: &mut Body, replacements: &HashMap<Temp, Operand>) {
+    if !replacements.is_empty() {
+        for_each_operand(body, |operand| *operand = resolve(replacements, operand));
+    }
+}
+
+/// The temporaries a body uses. A phi using itself doesn't count, since that alone doesn't
+/// make its value needed.
+pub fn used_temps(body: &Body) -> HashSet<Temp> {
+    let mut used = HashSet::new();
+    let mut use_operand = |operand: &Operand| {
+        if let Operand::Temp(temp) = operand {
+            used.insert(*temp);
+        }
+    };
+
+    for block in &body.blocks {
+        for phi in &block.phis {
+            phi.incoming
+                .iter()
+                .map(|(_, operand)| operand)
+                .filter(|operand| **operand != Operand::Temp(phi.dest))
+                .for_each(&mut use_operand);
+        }
+
+        for inst in &block.insts {
+            match &inst.kind {
+                InstKind::Unary { operand, .. } => use_operand(operand),
+                InstKind::Binary { lhs, rhs, .. } => {
+                    use_operand(lhs);
+                    use_operand(rhs);
+                }
+                InstKind::Call { args, .. }
+                | InstKind::Builtin { args, .. }
+                | InstKind::Native { args, .. }
+                | InstKind::Array { elements: args }
+                | InstKind::Map { entries: args } => {
+                    args.iter().for_each(&mut use_operand);
+                }
+                InstKind::Index { array, index } => {
+                    use_operand(array);
+                    use_operand(index);
+                }
+                InstKind::Store {
+                    array,
+                    index,
+                    value,
+                } => {
+                    use_operand(array);
+                    use_operand(index);
+                    use_operand(value);
+                }
+            }
+        }
+
+        match &block.terminator {
+            Terminator::Branch { cond, .. } | Terminator::Switch { value: cond, .. } => {
+                use_operand(cond);
+            }
+            Terminator::Ret(Some(value)) => use_operand(value),
+            Terminator::Jump(_) | Terminator::Ret(None) | Terminator::Unreachable => {}
+        }
+    }
+
+    used
+}
+
+/// The distinct predecessors of every block, indexed by [`BlockId`].
+pub fn predecessors(body: &Body) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); body.blocks.len()];
+    for (i, block) in body.blocks.iter().enumerate() {
+        for succ in block.terminator.successors() {
+            let succ_preds: &mut Vec<_> = &mut preds[succ.0 as usize];
+            if !succ_preds.contains(&BlockId(i as u32)) {
+                succ_preds.push(BlockId(i as u32));
+            }
+        }
+    }
+
+    preds
+}
+
+/// Remove phis that only ever select one value besides themselves, replacing their uses with
+/// that value. Removing one phi can make others trivial, so this repeats until none are left.
+/// Returns whether any were removed.
+pub fn remove_trivial_phis(body: &mut Body) -> bool {
+    let mut replacements = HashMap::new();
+
+    loop {
+        let mut changed = false;
+
+        for block in &mut body.blocks {
+            block.phis.retain(|phi| {
+                let mut values = phi
+                    .incoming
+                    .iter()
+                    .map(|(_, operand)| resolve(&replacements, operand))
+                    .filter(|operand| *operand != Operand::Temp(phi.dest));
+
+                let Some(first) = values.next() else {
+                    return true;
+                };
+                if values.all(|value| value == first) {
+                    replacements.insert(phi.dest, first);
+                    changed = true;
+                    return false;
+                }
+
+                true
+            });
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    replace_uses(body, &replacements);
+    !replacements.is_empty()
+}
+
+/// Remove blocks control can never reach, such as the exit of a loop that's only left with
+/// `ret`, and renumber the rest. Returns whether any were removed.
+pub fn remove_unreachable_blocks(body: &mut Body) -> bool {
+    let mut reachable = vec![false; body.blocks.len()];
+    let mut stack = vec![BlockId::ENTRY];
+    while let Some(block) = stack.pop() {
+        if !std::mem::replace(&mut reachable[block.0 as usize], true) {
+            stack.extend(body.block(block).terminator.successors());
+        }
+    }
+
+    if reachable.iter().all(|&reachable| reachable) {
+        return false;
+    }
+
+    let mut renumbered = Vec::with_capacity(body.blocks.len());
+    let mut next = 0;
+    for &reachable in &reachable {
+        renumbered.push(BlockId(next));
+        next += u32::from(reachable);
+    }
+
+    let blocks = std::mem::take(&mut body.blocks);
+    body.blocks = blocks
+        .into_iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| **reachable)
+        .map(|(mut block, _)| {
+            block.phis.iter_mut().for_each(|phi| {
+                phi.incoming.retain(|(pred, _)| reachable[pred.0 as usize]);
+                phi.incoming
+                    .iter_mut()
+                    .for_each(|(pred, _)| *pred = renumbered[pred.0 as usize]);
+            });
+
+            match &mut block.terminator {
+                Terminator::Jump(target) => *target = renumbered[target.0 as usize],
+                Terminator::Branch {
+                    then, otherwise, ..
+                } => {
+                    *then = renumbered[then.0 as usize];
+                    *otherwise = renumbered[otherwise.0 as usize];
+                }
+                Terminator::Switch {
+                    targets, otherwise, ..
+                } => {
+                    for target in targets.iter_mut().chain([otherwise]) {
+                        *target = renumbered[target.0 as usize];
+                    }
+                }
+                Terminator::Ret(_) | Terminator::Unreachable => {}
+            }
+
+            block
+        })
+        .collect();
+    true
+}
+
+/// Number temporaries in the order they're defined, closing the gaps left by removed phis.
+pub fn renumber_temps(body: &mut Body) {
+    let mut renumbered = HashMap::new();
+    let mut temps = Vec::with_capacity(body.temps.len());
+    let mut renumber = |temp: &mut Temp| {
+        temps.push(body.temps[temp.0 as usize]);
+        let new = Temp(renumbered.len() as u32);
+        renumbered.insert(*temp, new);
+        *temp = new;
+    };
+
+    body.params.iter_mut().for_each(&mut renumber);
+    for block in &mut body.blocks {
+        block
+            .phis
+            .iter_mut()
+            .for_each(|phi| renumber(&mut phi.dest));
+        block
+            .insts
+            .iter_mut()
+            .filter_map(|inst| inst.dest.as_mut())
+            .for_each(&mut renumber);
+    }
+
+    body.temps = temps;
+    for_each_operand(body, |operand| {
+        if let Operand::Temp(temp) = operand {
+            *temp = renumbered[temp];
+        }
+    });
+}
@@ -1,3 +1,7 @@
+mod validate;
+
+pub use validate::{validate, Violation};
+
 use lexer::token::{self, Token};
 use span::Span;
 
@@ -177,30 +181,335 @@ impl Into<BinaryOpKind> for token::TokenKind {
     }
 }
 
+impl BinaryOpKind {
+    /// Returns if this operator is an assignment (`=`) or compound assignment (`+=`, `<<=`, ...).
+    pub fn is_assignment(self) -> bool {
+        use BinaryOpKind::*;
+
+        matches!(
+            self,
+            Equal
+                | PlusEqual
+                | MinusEqual
+                | MulEqual
+                | DivEqual
+                | ModEqual
+                | BwAndEqual
+                | BwOrEqual
+                | ShlEqual
+                | ShrEqual
+        )
+    }
+}
+
+/// An identifier along with where it appears in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A path through modules to an item, with at least two segments (math::add, a::b::c).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub segments: Vec<Ident>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub enum ExpressionKind {
-    /// A literal ("hello", 123, 20.4).
-    Literal(LiteralKind),
+    /// A literal ("hello", 123, 20.4), along with its text as written in the source.
+    Literal { kind: LiteralKind, text: String },
+
+    /// A reference to a named value (x, foo).
+    Ident(Ident),
+
+    /// A reference to an item in another module (math::add).
+    Path(Path),
+
+    /// A procedure call (foo(), add(1, 2)).
+    Call {
+        callee: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
 
     /// A unary expression (!false, -10).
     Unary {
         operator: UnaryOpKind,
-        operand: Box<ExpressionKind>,
+        operand: Box<Expression>,
     },
 
-    /// A binary expression (1 + 2, 5 > 3, 2 / 3).
+    /// A binary expression (1 + 2, 5 > 3, 2 / 3), including assignments (x = 1, x += 2).
     Binary {
-        lhs: Box<ExpressionKind>,
+        lhs: Box<Expression>,
         operator: BinaryOpKind,
-        rhs: Box<ExpressionKind>,
+        rhs: Box<Expression>,
+
+        /// The span of the operator's token.
+        operator_span: Span,
     },
 
     /// A grouping ( (1 + 2), ((1 + 2) + (3 + 4)) ).
-    Grouping(Box<ExpressionKind>),
+    Grouping(Box<Expression>),
+
+    /// A match (match x { 0 => a, 1..=9 => b, _ => c }).
+    Match {
+        scrutinee: Box<Expression>,
+        arms: Vec<MatchArm>,
+    },
+
+    /// An array literal ([1, 2, 3], []).
+    Array(Vec<Expression>),
+
+    /// A map literal, with the key and value of each entry (["a": 1, "b": 2], [:]).
+    Map(Vec<(Expression, Expression)>),
+
+    /// An element of an array or the value of a key in a map (xs[0], grid[i][j], ages["bo"]).
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>,
+    },
+
+    /// A conversion of a value to another type (x as u8).
+    Cast { value: Box<Expression>, ty: Type },
+
+    /// A range of ints, which is only written as what a for loop iterates over (0..10, 1..=n).
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    /// Matches anything (_).
+    Wildcard,
+
+    /// Matches a single value (true, 'a', -1).
+    Literal {
+        kind: LiteralKind,
+        text: String,
+        negated: bool,
+    },
+
+    /// Matches a range of values (1..5, 'a'..='z').
+    Range {
+        start: Box<Pattern>,
+        end: Box<Pattern>,
+        inclusive: bool,
+    },
+
+    /// Matches if any of the alternatives match (1 | 2 | 3).
+    Or(Vec<Pattern>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+/// A single arm of a match (1..=9 => x * 2).
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expression,
+    pub span: Span,
+}
+
+/// Types that can be written in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeKind {
+    /// int
+    Int,
+
+    /// Ints of a width, signed or unsigned (i8, u64).
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+
+    /// float
+    Float,
+
+    /// bool
+    Bool,
+
+    /// char
+    Char,
+
+    /// str
+    Str,
+
+    /// void
+    Void,
+
+    /// An array of elements of a type ([int], [[str]]).
+    Array(Box<Type>),
+
+    /// A map from keys of a type to values of another ([str: int], [char: [str]]).
+    Map(Box<Type>, Box<Type>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Type {
+    pub kind: TypeKind,
+    pub span: Span,
 }
 
-// #[derive(Debug, Clone)]
-// pub struct Expression {
-//     pub kind: ExpressionKind,
-//     pub span: Span,
-// }
+/// A block of statements surrounded by curly braces.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
+    /// A variable declaration (let x = 10;, let y: float = 2.5;).
+    Let {
+        name: Ident,
+        ty: Option<Type>,
+        initializer: Expression,
+    },
+
+    /// A return statement (ret;, ret x + y;).
+    Ret(Option<Expression>),
+
+    /// An if statement with any number of elif branches and an optional else branch.
+    If {
+        condition: Expression,
+        then_block: Block,
+        elif_branches: Vec<(Expression, Block)>,
+        else_block: Option<Block>,
+    },
+
+    /// A while loop (while x < 10 { ... }).
+    While { condition: Expression, body: Block },
+
+    /// A do-while loop (do { ... } while x < 10;).
+    DoWhile { body: Block, condition: Expression },
+
+    /// A for loop (for let i = 0; i < 10; i += 1 { ... }).
+    For {
+        initializer: Box<Statement>,
+        condition: Expression,
+        step: Expression,
+        body: Block,
+    },
+
+    /// A for loop over the elements of an array, the chars of a string, the keys of a map or
+    /// the ints in a range (for x in xs { ... }, for i in 0..10 { ... }).
+    ForIn {
+        binding: Ident,
+        iterable: Expression,
+        body: Block,
+    },
+
+    /// A nested block ({ ... }).
+    Block(Block),
+
+    /// An expression followed by a semicolon (foo();, x += 1;).
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+/// A procedure parameter (x: int).
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: Ident,
+    pub ty: Type,
+    pub span: Span,
+}
+
+/// A procedure declaration.
+#[derive(Debug, Clone)]
+pub struct Proc {
+    pub name: Ident,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Type,
+    pub body: Block,
+}
+
+/// A module declaration.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: Ident,
+    pub items: Vec<Item>,
+}
+
+/// A constant declaration.
+#[derive(Debug, Clone)]
+pub struct Const {
+    pub name: Ident,
+    pub ty: Type,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone)]
+pub enum ItemKind {
+    /// A procedure (proc add(x: int, y: int) int { ... }).
+    Proc(Proc),
+
+    /// A module (mod math { ... }).
+    Mod(Module),
+
+    /// A constant, evaluated at compile time (const MAX: int = 1 << 16;).
+    Const(Const),
+
+    /// An import of a standard library module into the enclosing module (import math;).
+    Import(Ident),
+}
+
+/// Whether an item can be used from outside the module it's defined in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    /// Only visible in the defining module and the modules nested in it.
+    #[default]
+    Private,
+
+    /// Visible everywhere (pub).
+    Public,
+}
+
+/// An attribute on an item (@must_use).
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Ident,
+    pub span: Span,
+}
+
+/// An item in a program or module.
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub span: Span,
+    pub visibility: Visibility,
+
+    /// The text of the doc comments directly before the item, one line per comment, without
+    /// the leading `///`.
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+}
+
+/// A whole parsed program.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub items: Vec<Item>,
+}
@@ -0,0 +1,434 @@
+//! Checks of the invariants the parser keeps in the syntax trees it builds, which catch a bug
+//! in the parser where it is rather than in a later stage. Parsing checks them in debug builds,
+//! and so in every test, and `mtxc --verify-ast` checks them in any build.
+
+use super::{
+    Block, Expression, ExpressionKind, Ident, Item, ItemKind, Pattern, PatternKind, Program,
+    Statement, StatementKind, Type, TypeKind,
+};
+use span::Span;
+use std::fmt;
+
+/// An invariant a syntax tree breaks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub message: String,
+
+    /// The span of the node that breaks it.
+    pub span: Span,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:?}", self.message, self.span)
+    }
+}
+
+/// Check the invariants of a program's syntax tree, returning every violation.
+///
+/// Every node's span isn't empty and covers its children's, which are in the order they're
+/// written without overlapping. Identifiers have names, and paths and or-patterns have more
+/// than one part.
+pub fn validate(program: &Program) -> Result<(), Vec<Violation>> {
+    let mut validator = Validator::default();
+    validator.items(&program.items);
+
+    if validator.violations.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.violations)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    fn violation(&mut self, span: Span, message: String) {
+        self.violations.push(Violation { message, span });
+    }
+
+    /// Check a node's span against its children's, given in the order they're written.
+    fn node(&mut self, node: &str, span: Span, children: &[Span]) {
+        if span.is_empty() {
+            self.violation(span, format!("{node} has an empty span"));
+        }
+
+        let mut previous_end = span.start;
+        for &child in children {
+            if !span.contains(child) {
+                self.violation(span, format!("{node} doesn't cover its child at {child:?}"));
+            } else if child.start < previous_end {
+                self.violation(
+                    span,
+                    format!("{node} has a child at {child:?} overlapping or before the last"),
+                );
+            }
+            previous_end = previous_end.max(child.end);
+        }
+    }
+
+    /// Check that items are in order without overlapping.
+    fn items(&mut self, items: &[Item]) {
+        let mut previous_end = 0;
+        for item in items {
+            if item.span.start < previous_end {
+                self.violation(item.span, "item overlaps or is before the last".to_owned());
+            }
+            previous_end = item.span.end;
+            self.item(item);
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        let mut children: Vec<_> = item.attributes.iter().map(|a| a.span).collect();
+        for attribute in &item.attributes {
+            self.node("attribute", attribute.span, &[attribute.name.span]);
+            self.ident(&attribute.name);
+        }
+
+        match &item.kind {
+            ItemKind::Proc(proc) => {
+                children.push(proc.name.span);
+                children.extend(proc.parameters.iter().map(|p| p.span));
+                children.extend([proc.return_type.span, proc.body.span]);
+                self.node("procedure", item.span, &children);
+
+                self.ident(&proc.name);
+                for parameter in &proc.parameters {
+                    let parts = [parameter.name.span, parameter.ty.span];
+                    self.node("parameter", parameter.span, &parts);
+                    self.ident(&parameter.name);
+                    self.ty(&parameter.ty);
+                }
+                self.ty(&proc.return_type);
+                self.block(&proc.body);
+            }
+            ItemKind::Const(constant) => {
+                children.extend([constant.name.span, constant.ty.span, constant.value.span]);
+                self.node("constant", item.span, &children);
+
+                self.ident(&constant.name);
+                self.ty(&constant.ty);
+                self.expr(&constant.value);
+            }
+            ItemKind::Mod(module) => {
+                children.push(module.name.span);
+                children.extend(module.items.iter().map(|item| item.span));
+                self.node("module", item.span, &children);
+
+                self.ident(&module.name);
+                self.items(&module.items);
+            }
+            ItemKind::Import(name) => {
+                children.push(name.span);
+                self.node("import", item.span, &children);
+                self.ident(name);
+            }
+        }
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        if ident.name.is_empty() {
+            self.violation(ident.span, "identifier has no name".to_owned());
+        }
+        self.node("identifier", ident.span, &[]);
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match &ty.kind {
+            TypeKind::Array(element) => {
+                self.node("array type", ty.span, &[element.span]);
+                self.ty(element);
+            }
+            TypeKind::Map(key, value) => {
+                self.node("map type", ty.span, &[key.span, value.span]);
+                self.ty(key);
+                self.ty(value);
+            }
+            _ => self.node("type", ty.span, &[]),
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let children: Vec<_> = block.statements.iter().map(|s| s.span).collect();
+        self.node("block", block.span, &children);
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::Let {
+                name,
+                ty,
+                initializer,
+            } => {
+                let mut children = vec![name.span];
+                children.extend(ty.as_ref().map(|ty| ty.span));
+                children.push(initializer.span);
+                self.node("let statement", span, &children);
+
+                self.ident(name);
+                if let Some(ty) = ty {
+                    self.ty(ty);
+                }
+                self.expr(initializer);
+            }
+            StatementKind::Ret(value) => {
+                let children: Vec<_> = value.iter().map(|value| value.span).collect();
+                self.node("ret statement", span, &children);
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StatementKind::If {
+                condition,
+                then_block,
+                elif_branches,
+                else_block,
+            } => {
+                let mut children = vec![condition.span, then_block.span];
+                for (condition, block) in elif_branches {
+                    children.extend([condition.span, block.span]);
+                }
+                children.extend(else_block.as_ref().map(|block| block.span));
+                self.node("if statement", span, &children);
+
+                self.expr(condition);
+                self.block(then_block);
+                for (condition, block) in elif_branches {
+                    self.expr(condition);
+                    self.block(block);
+                }
+                if let Some(block) = else_block {
+                    self.block(block);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.node("while loop", span, &[condition.span, body.span]);
+                self.expr(condition);
+                self.block(body);
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.node("do-while loop", span, &[body.span, condition.span]);
+                self.block(body);
+                self.expr(condition);
+            }
+            StatementKind::For {
+                initializer,
+                condition,
+                step,
+                body,
+            } => {
+                let children = [initializer.span, condition.span, step.span, body.span];
+                self.node("for loop", span, &children);
+
+                self.statement(initializer);
+                self.expr(condition);
+                self.expr(step);
+                self.block(body);
+            }
+            StatementKind::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.node("for loop", span, &[binding.span, iterable.span, body.span]);
+                self.ident(binding);
+                self.expr(iterable);
+                self.block(body);
+            }
+            StatementKind::Block(block) => {
+                self.node("block statement", span, &[block.span]);
+                self.block(block);
+            }
+            StatementKind::Expression(expr) => {
+                self.node("expression statement", span, &[expr.span]);
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        let span = expr.span;
+        match &expr.kind {
+            ExpressionKind::Literal { .. } => self.node("literal", span, &[]),
+            ExpressionKind::Ident(ident) => {
+                self.node("name", span, &[ident.span]);
+                self.ident(ident);
+            }
+            ExpressionKind::Path(path) => {
+                if path.segments.len() < 2 {
+                    self.violation(span, "path has fewer than two segments".to_owned());
+                }
+                let segments: Vec<_> = path.segments.iter().map(|s| s.span).collect();
+                self.node("path expression", span, &[path.span]);
+                self.node("path", path.span, &segments);
+                for segment in &path.segments {
+                    self.ident(segment);
+                }
+            }
+            ExpressionKind::Call { callee, arguments } => {
+                let mut children = vec![callee.span];
+                children.extend(arguments.iter().map(|argument| argument.span));
+                self.node("call", span, &children);
+
+                self.expr(callee);
+                for argument in arguments {
+                    self.expr(argument);
+                }
+            }
+            ExpressionKind::Unary { operand, .. } => {
+                self.node("unary expression", span, &[operand.span]);
+                self.expr(operand);
+            }
+            ExpressionKind::Binary {
+                lhs,
+                rhs,
+                operator_span,
+                ..
+            } => {
+                let children = [lhs.span, *operator_span, rhs.span];
+                self.node("binary expression", span, &children);
+                self.node("operator", *operator_span, &[]);
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExpressionKind::Grouping(inner) => {
+                self.node("grouping", span, &[inner.span]);
+                self.expr(inner);
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                let mut children = vec![scrutinee.span];
+                children.extend(arms.iter().map(|arm| arm.span));
+                self.node("match", span, &children);
+
+                self.expr(scrutinee);
+                for arm in arms {
+                    self.node("match arm", arm.span, &[arm.pattern.span, arm.body.span]);
+                    self.pattern(&arm.pattern);
+                    self.expr(&arm.body);
+                }
+            }
+            ExpressionKind::Array(elements) => {
+                let children: Vec<_> = elements.iter().map(|element| element.span).collect();
+                self.node("array", span, &children);
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+            ExpressionKind::Map(entries) => {
+                let children: Vec<_> = entries
+                    .iter()
+                    .flat_map(|(key, value)| [key.span, value.span])
+                    .collect();
+                self.node("map", span, &children);
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExpressionKind::Index { array, index } => {
+                self.node("index", span, &[array.span, index.span]);
+                self.expr(array);
+                self.expr(index);
+            }
+            ExpressionKind::Cast { value, ty } => {
+                self.node("cast", span, &[value.span, ty.span]);
+                self.expr(value);
+                self.ty(ty);
+            }
+            ExpressionKind::Range { start, end, .. } => {
+                self.node("range", span, &[start.span, end.span]);
+                self.expr(start);
+                self.expr(end);
+            }
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        let span = pattern.span;
+        match &pattern.kind {
+            PatternKind::Wildcard => self.node("wildcard pattern", span, &[]),
+            PatternKind::Literal { .. } => self.node("literal pattern", span, &[]),
+            PatternKind::Range { start, end, .. } => {
+                self.node("range pattern", span, &[start.span, end.span]);
+                self.pattern(start);
+                self.pattern(end);
+            }
+            PatternKind::Or(alternatives) => {
+                if alternatives.len() < 2 {
+                    self.violation(
+                        span,
+                        "or-pattern has fewer than two alternatives".to_owned(),
+                    );
+                }
+                let children: Vec<_> = alternatives.iter().map(|a| a.span).collect();
+                self.node("or-pattern", span, &children);
+                for alternative in alternatives {
+                    self.pattern(alternative);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Program {
+        crate::parse(source, lexer::lex(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parsed_programs_are_valid() {
+        let program = parse(
+            "@must_use pub proc f(x: int, xs: [int]) int {
+                 let y: int = -x * (2 + xs[0]);
+                 if y > 1 { ret y; } elif y < 0 { ret 0; } else { y += 1; }
+                 for i in xs { while i > 0 { i -= 1; } }
+                 for j in 0..=y { m::g(j); }
+                 for let i = 0; i < 3; i += 1 { do { m::g(i); } while false; }
+                 ret match y { 0 | 1 => 1, 2..=9 => [y][0], _ => y };
+             }
+             mod m { pub proc g(x: int) void {} }
+             const C: int = 1;
+             import math;",
+        );
+        assert_eq!(validate(&program), Ok(()));
+    }
+
+    #[test]
+    fn test_reports_broken_invariants() {
+        let mut program = parse("proc f() int { ret 1 + 2; }");
+        let ItemKind::Proc(proc) = &mut program.items[0].kind else {
+            unreachable!()
+        };
+        let StatementKind::Ret(Some(value)) = &mut proc.body.statements[0].kind else {
+            unreachable!()
+        };
+        let ExpressionKind::Binary { lhs, rhs, .. } = &mut value.kind else {
+            unreachable!()
+        };
+        std::mem::swap(lhs, rhs);
+        rhs.span = Span::from(0..0);
+
+        let violations = validate(&program).unwrap_err();
+        let messages: Vec<_> = violations.iter().map(|v| v.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "binary expression has a child at 21..22 overlapping or before the last",
+                "binary expression doesn't cover its child at 0..0",
+                "literal has an empty span",
+            ]
+        );
+        assert!(violations[0].to_string().ends_with("at 19..24"));
+    }
+}
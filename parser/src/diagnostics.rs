@@ -1,3 +1,5 @@
+use diagnostics::{Stage, Suggestion};
+use lexer::token::TokenKind;
 use miette::Diagnostic;
 use span::Span;
 use thiserror::Error;
@@ -5,28 +7,76 @@ use thiserror::Error;
 /// Diagnostics that can happen within the parser.
 #[derive(Debug, Clone, Error, Diagnostic)]
 pub enum ParseDiagnostic {
-    #[error("O")]
-    O,
-}
+    #[diagnostic(code(parser::unexpected_token))]
+    #[error("Expected {0}, found {1}")]
+    UnexpectedToken(
+        &'static str,
+        TokenKind,
+        #[label("unexpected token here")] Span,
+    ),
 
-#[derive(Debug, Default, Error, Diagnostic)]
-#[diagnostic(code(parser::failure))]
-#[error("parsing failed with {} diagnostic{}", diagnostics.len(), if diagnostics.len() != 1 { "s" } else { "" })]
-pub struct DiagnosticSink {
-    #[related]
-    diagnostics: Vec<ParseDiagnostic>,
-}
+    #[diagnostic(
+        code(parser::missing_semicolon),
+        help("add a `;` at the end of the statement")
+    )]
+    #[error("Expected `;`, found {found}")]
+    MissingSemicolon {
+        found: TokenKind,
+        #[label("expected `;` after this")]
+        previous: Span,
+    },
 
-impl DiagnosticSink {
-    pub fn new() -> Self {
-        Self::default()
-    }
+    #[diagnostic(code(parser::expected_expression))]
+    #[error("Expected an expression, found {0}")]
+    ExpectedExpression(TokenKind, #[label("expected an expression here")] Span),
 
-    pub fn push_diagnostic(&mut self, diagnostic: ParseDiagnostic) {
-        self.diagnostics.push(diagnostic);
-    }
+    #[diagnostic(
+        code(parser::expected_pattern),
+        help(
+            "patterns are `_`, literals, ranges such as `1..=5`, or alternatives such as `1 | 2`"
+        )
+    )]
+    #[error("Expected a pattern, found {0}")]
+    ExpectedPattern(TokenKind, #[label("expected a pattern here")] Span),
+
+    #[diagnostic(
+        code(parser::expected_type),
+        help(
+            "the available types are int, float, bool, char, str, void, the sized ints i8, i16, \
+             i32, i64, u8, u16, u32 and u64, arrays such as `[int]` and maps such as `[str: int]`"
+        )
+    )]
+    #[error("Expected a type, found {0}")]
+    ExpectedType(TokenKind, #[label("expected a type here")] Span),
 
-    pub fn has_diagnostics(&self) -> bool {
-        !self.diagnostics.is_empty()
+    #[diagnostic(
+        code(parser::expected_item),
+        help(
+            "items are procedures (`proc`), constants (`const`), modules (`mod`) and imports \
+             (`import`)"
+        )
+    )]
+    #[error("Expected an item, found {0}")]
+    ExpectedItem(TokenKind, #[label("expected an item here")] Span),
+
+    #[diagnostic(
+        code(parser::nested_too_deeply),
+        help("move some of the nesting into a `let` or a procedure of its own")
+    )]
+    #[error("Nesting is deeper than the limit of {0} levels")]
+    NestedTooDeeply(usize, #[label("nested too deeply here")] Span),
+}
+
+impl Stage for ParseDiagnostic {
+    const NAME: &'static str = "parsing";
+    const FAILURE: &'static str = "parser::failure";
+
+    fn suggestion(&self) -> Option<Suggestion> {
+        match self {
+            Self::MissingSemicolon { previous, .. } => Some(Suggestion::insert(previous.end, ";")),
+            _ => None,
+        }
     }
 }
+
+pub type DiagnosticSink = diagnostics::DiagnosticSink<ParseDiagnostic>;
@@ -0,0 +1,105 @@
+//! Extended explanations of the parser's diagnostics, which `mtxc explain` prints.
+
+/// Every diagnostic's code and explanation. Short codes number them in this order, so new ones
+/// go at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "parser::unexpected_token",
+        r#"The parser found a token where the grammar needs a different one.
+
+The error names the token that was expected, which is often punctuation left out just before
+the one that was found. For example, a call's arguments have to be closed with a parenthesis:
+
+    proc main() void {
+        println("hello";
+    }
+
+Add the missing token, like `println("hello");`."#,
+    ),
+    (
+        "parser::expected_expression",
+        r#"The parser needed an expression, but found a token that can't start one.
+
+Expressions are literals, names, calls, operators applied to other expressions, arrays,
+indexing and `match`es. For example, an operator needs an operand on each side:
+
+    let x = 1 + ;
+
+Write the missing expression, like `let x = 1 + 2;`."#,
+    ),
+    (
+        "parser::expected_pattern",
+        r#"A `match` arm starts with a token that can't start a pattern.
+
+Patterns are `_`, literals, ranges such as `1..=5`, and alternatives of them such as `1 | 2`.
+Names and expressions can't be matched against:
+
+    match n {
+        limit => 0,
+        _ => 1,
+    }
+
+Match a literal or a range instead, or compare with `if n == limit` outside the `match`."#,
+    ),
+    (
+        "parser::expected_type",
+        r#"A type was expected, like after a parameter's colon or a procedure's parameters.
+
+The types are `int`, `float`, `bool`, `str`, `void`, and arrays of them such as `[int]`:
+
+    proc twice(x: integer) int {
+        ret x * 2;
+    }
+
+Use one of the types, like `x: int`."#,
+    ),
+    (
+        "parser::expected_item",
+        r#"The top level of a program, or of a module, holds something that isn't an item.
+
+Items are procedures (`proc`), constants (`const`), modules (`mod`) and imports (`import`).
+Statements like `let` have to be inside a procedure:
+
+    let limit = 10;
+
+    proc main() void {}
+
+Make it a constant, like `const LIMIT: int = 10;`, or move it into a procedure."#,
+    ),
+    (
+        "parser::failure",
+        r#"The program couldn't be parsed.
+
+This error holds every diagnostic the parser found, which are shown after it. Each has its own
+code to explain it. The parser recovers after an error to find more of them, so later ones can
+be caused by earlier ones, and disappear once those are fixed."#,
+    ),
+    (
+        "parser::missing_semicolon",
+        r#"A statement, constant or import isn't followed by a semicolon.
+
+Every statement except blocks, `if`s, loops and `match`es ends with `;`:
+
+    proc main() void {
+        let x = 1
+        println(to_str(x));
+    }
+
+Add the semicolon after the statement, like `let x = 1;`. `mtxc fix` adds missing semicolons
+on its own."#,
+    ),
+    (
+        "parser::nested_too_deeply",
+        r#"Expressions, statements, types or modules are nested in each other too deeply.
+
+The parser allows 64 levels of nesting, such as parentheses in parentheses or blocks in
+blocks, so that a program nesting them further is reported rather than crashing the compiler.
+Code that deep is almost always generated:
+
+    proc main() int {
+        ret ((((((((((((((((((((((((((((((((((((((((...))))))))))))))))))))))))))))))))))))));
+    }
+
+Name some of the nested parts with `let`, or move them into a procedure of their own."#,
+    ),
+];
@@ -3,25 +3,58 @@
 #![allow(clippy::missing_const_for_fn)]
 #![allow(unused)]
 
-mod ast;
+pub mod ast;
 mod diagnostics;
+mod explanations;
 mod print_ast;
 
-use ast::{Expression, ExpressionKind, ExpressionKind::*, UnaryOpKind};
-use diagnostics::{DiagnosticSink, ParseDiagnostic};
-use lexer::token::{LiteralKind, Token, TokenKind};
+pub use diagnostics::{DiagnosticSink, ParseDiagnostic};
+pub use explanations::EXPLANATIONS;
+
+use ast::{
+    Attribute, BinaryOpKind, Block, Const, Expression, ExpressionKind, Ident, Item, ItemKind,
+    MatchArm, Module, Parameter, Path, Pattern, PatternKind, Proc, Program, Statement,
+    StatementKind, Type, TypeKind, UnaryOpKind, Visibility,
+};
+use lexer::token::{IdentKind, Keyword, LiteralKind, Token, TokenKind};
+use span::{Cancel, Cancelled, Span};
 use std::{iter::Peekable, vec::IntoIter};
 
+/// How deeply expressions, statements, types and modules can be nested in each other. Deeper
+/// nesting is an error rather than overflowing the stack, which parsing it recursively would.
+pub const MAX_NESTING: usize = 64;
+
 #[derive(Debug)]
-struct Parser {
-    /// An iterator over the tokens outputted by the lexer.
+struct Parser<'src> {
+    /// The source code the tokens were lexed from, which doc comments are read from. Identifiers
+    /// and literals keep their own text.
+    source: &'src str,
+
+    /// An iterator over the tokens outputted by the lexer, except for doc comments.
     tokens: Peekable<IntoIter<Token>>,
+
+    /// The doc comments outputted by the lexer, which are attached to items separately.
+    doc_comments: Peekable<IntoIter<Token>>,
+
+    /// The span of the most recently consumed token.
+    previous_span: Span,
+
+    /// How many nested expressions, statements, types and modules are being parsed.
+    depth: usize,
 }
 
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
+impl<'src> Parser<'src> {
+    fn new(source: &'src str, tokens: Vec<Token>) -> Self {
+        let (doc_comments, tokens): (Vec<_>, Vec<_>) = tokens
+            .into_iter()
+            .partition(|t| t.kind == TokenKind::DocComment);
+
         Self {
+            source,
             tokens: tokens.into_iter().peekable(),
+            doc_comments: doc_comments.into_iter().peekable(),
+            previous_span: Span::from(0..0),
+            depth: 0,
         }
     }
 
@@ -30,142 +63,989 @@ impl Parser {
         self.tokens.peek()
     }
 
+    /// Peek the kind of the next token, treating a missing token as the end of file.
+    fn peek_kind(&mut self) -> TokenKind {
+        self.peek().map_or(TokenKind::EoF, |t| t.kind)
+    }
+
+    /// Peek the span of the next token, falling back to the end of the last consumed token.
+    fn peek_span(&mut self) -> Span {
+        let fallback = self.previous_span.shrink_to_end();
+        self.peek().map_or(fallback, |t| t.span)
+    }
+
     /// Advance to the next token.
     fn advance(&mut self) -> Option<Token> {
-        self.tokens.next()
+        let token = self.tokens.next()?;
+        self.previous_span = token.span;
+        Some(token)
     }
 
     /// Check if the parser has reached an end of file.
     fn at_end(&mut self) -> bool {
-        self.peek().is_some_and(|t| t.kind == TokenKind::EoF)
+        self.peek_kind() == TokenKind::EoF
     }
 
-    fn parse_primary(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        if let Some(&peek) = self.peek() {
-            if let TokenKind::Literal(lit) = peek.kind {
-                self.advance();
-                let lit_kind = lit.into();
-                return Ok(ExpressionKind::Literal(lit_kind));
-            } else if peek.kind == TokenKind::OpenParen {
-                self.advance();
+    /// Check if the next token is of a specified kind, returning whether it was consumed or not.
+    fn next_is(&mut self, kind: TokenKind) -> bool {
+        if self.peek_kind() == kind {
+            self.advance();
+            return true;
+        }
+
+        false
+    }
+
+    /// Consume the next token if it is of the specified kind, otherwise error.
+    fn expect(
+        &mut self,
+        kind: TokenKind,
+        expected: &'static str,
+    ) -> Result<Token, ParseDiagnostic> {
+        if self.peek_kind() == kind {
+            return Ok(self.advance().unwrap());
+        }
+
+        Err(ParseDiagnostic::UnexpectedToken(
+            expected,
+            self.peek_kind(),
+            self.peek_span(),
+        ))
+    }
+
+    /// Consume a semicolon ending a statement or item, otherwise error with a fix inserting it
+    /// after the previous token.
+    fn expect_semicolon(&mut self) -> Result<Token, ParseDiagnostic> {
+        if self.peek_kind() == TokenKind::Semicolon {
+            return Ok(self.advance().unwrap());
+        }
+
+        Err(ParseDiagnostic::MissingSemicolon {
+            found: self.peek_kind(),
+            previous: self.previous_span,
+        })
+    }
+
+    /// Parse something nested in what's being parsed, erroring if it's nested too deeply.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseDiagnostic>,
+    ) -> Result<T, ParseDiagnostic> {
+        if self.depth == MAX_NESTING {
+            return Err(ParseDiagnostic::NestedTooDeeply(
+                MAX_NESTING,
+                self.peek_span(),
+            ));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Get the text a span covers in the source code, for tokens that don't keep their text.
+    fn lexeme(&self, span: Span) -> &'src str {
+        &self.source[span.start..span.end]
+    }
+
+    /// Take the doc comments between the previous token and an item starting at a span, joining
+    /// them into the item's documentation. Doc comments anywhere else document nothing and are
+    /// treated as ordinary comments.
+    fn take_doc(&mut self, item_start: Span) -> Option<String> {
+        let mut lines = Vec::new();
+
+        while let Some(comment) = self
+            .doc_comments
+            .next_if(|comment| comment.span.start < item_start.start)
+        {
+            if Span::between(self.previous_span, item_start).contains(comment.span) {
+                let text = &self.lexeme(comment.span)["///".len()..];
+                let text = text.strip_suffix('\r').unwrap_or(text);
+                lines.push(text.strip_prefix(' ').unwrap_or(text));
+            }
+        }
+
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Skip tokens until the start of the next item so parsing can resume after an error.
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.at_end()
+            && !matches!(
+                self.peek_kind(),
+                TokenKind::Ident(IdentKind::Keyword(
+                    Keyword::Proc | Keyword::Mod | Keyword::Pub | Keyword::Const | Keyword::Import
+                )) | TokenKind::At
+            )
+        {
+            self.advance();
+        }
+    }
+
+    /// Parse an identifier.
+    fn parse_ident(&mut self) -> Result<Ident, ParseDiagnostic> {
+        let token = self.expect(TokenKind::Ident(IdentKind::NonReserved), "an identifier")?;
+
+        Ok(Ident {
+            name: token
+                .text()
+                .unwrap_or_else(|| self.lexeme(token.span))
+                .to_owned(),
+            span: token.span,
+        })
+    }
+
+    /// Parse a type.
+    fn parse_type(&mut self) -> Result<Type, ParseDiagnostic> {
+        let kind = match self.peek_kind() {
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Int)) => TypeKind::Int,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Float)) => TypeKind::Float,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Bool)) => TypeKind::Bool,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Char)) => TypeKind::Char,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::I8)) => TypeKind::I8,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::I16)) => TypeKind::I16,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::I32)) => TypeKind::I32,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::I64)) => TypeKind::I64,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::U8)) => TypeKind::U8,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::U16)) => TypeKind::U16,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::U32)) => TypeKind::U32,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::U64)) => TypeKind::U64,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Str)) => TypeKind::Str,
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Void)) => TypeKind::Void,
+            TokenKind::OpenSquare => {
+                let start = self.advance().unwrap().span;
+                let element = self.nested(Self::parse_type)?;
+                // `[K: V]` is a map type, and `[T]` an array type.
+                let kind = if self.next_is(TokenKind::Colon) {
+                    let value = self.nested(Self::parse_type)?;
+                    TypeKind::Map(Box::new(element), Box::new(value))
+                } else {
+                    TypeKind::Array(Box::new(element))
+                };
+                let end = self.expect(TokenKind::ClosingSquare, "`]`")?.span;
+
+                return Ok(Type {
+                    kind,
+                    span: start.coalesce_adjacent(end),
+                });
+            }
+            found => return Err(ParseDiagnostic::ExpectedType(found, self.peek_span())),
+        };
+
+        let span = self.advance().unwrap().span;
+        Ok(Type { kind, span })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseDiagnostic> {
+        match self.peek_kind() {
+            TokenKind::Literal(lit) => {
+                let token = self.advance().unwrap();
+
+                Ok(Expression {
+                    kind: ExpressionKind::Literal {
+                        kind: lit.into(),
+                        text: self.lexeme(token.span).to_owned(),
+                    },
+                    span: token.span,
+                })
+            }
+            TokenKind::Ident(IdentKind::NonReserved) => {
+                let ident = self.parse_ident()?;
+                if self.peek_kind() != TokenKind::ColonColon {
+                    let span = ident.span;
+                    return Ok(Expression {
+                        kind: ExpressionKind::Ident(ident),
+                        span,
+                    });
+                }
+
+                let mut segments = vec![ident];
+                while self.next_is(TokenKind::ColonColon) {
+                    segments.push(self.parse_ident()?);
+                }
+
+                let span = segments[0]
+                    .span
+                    .coalesce_adjacent(segments.last().unwrap().span);
+                Ok(Expression {
+                    kind: ExpressionKind::Path(Path { segments, span }),
+                    span,
+                })
+            }
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Match)) => self.parse_match(),
+            TokenKind::OpenSquare => {
+                let start = self.advance().unwrap().span;
+
+                // `[:]` is an empty map, and a colon after the first element makes a map, whose
+                // every entry then has one.
+                let empty_map = self.next_is(TokenKind::Colon);
+                let mut is_map = empty_map;
+                let mut elements = Vec::new();
+                let mut entries = Vec::new();
+                while !empty_map && !self.at_end() && self.peek_kind() != TokenKind::ClosingSquare
+                {
+                    let element = self.parse_expr()?;
+                    if elements.is_empty() && entries.is_empty() {
+                        is_map = self.peek_kind() == TokenKind::Colon;
+                    }
+                    if is_map {
+                        self.expect(TokenKind::Colon, "`:`")?;
+                        entries.push((element, self.parse_expr()?));
+                    } else {
+                        elements.push(element);
+                    }
+
+                    if !self.next_is(TokenKind::Comma) {
+                        break;
+                    }
+                }
+
+                let end = self.expect(TokenKind::ClosingSquare, "`]`")?.span;
+                let kind = if is_map {
+                    ExpressionKind::Map(entries)
+                } else {
+                    ExpressionKind::Array(elements)
+                };
+                Ok(Expression {
+                    kind,
+                    span: start.coalesce_adjacent(end),
+                })
+            }
+            TokenKind::OpenParen => {
+                let start = self.advance().unwrap().span;
                 let expr = self.parse_expr()?;
-                self.advance();
-                return Ok(ExpressionKind::Grouping(Box::new(expr)));
-            } else {
-                return Err(ParseDiagnostic::O);
+                let end = self.expect(TokenKind::ClosingParen, "`)`")?.span;
+
+                Ok(Expression {
+                    kind: ExpressionKind::Grouping(Box::new(expr)),
+                    span: start.coalesce_adjacent(end),
+                })
             }
+            found => Err(ParseDiagnostic::ExpectedExpression(found, self.peek_span())),
         }
-        return Err(ParseDiagnostic::O);
     }
 
-    fn parse_unary(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        if let Some(&peek) = self.peek()
-            && peek.kind.is_unary_op()
+    /// Parse a possibly negated literal pattern.
+    fn parse_literal_pattern(&mut self) -> Result<Pattern, ParseDiagnostic> {
+        let start = self.peek_span();
+        let negated = self.next_is(TokenKind::Minus);
+
+        let TokenKind::Literal(lit) = self.peek_kind() else {
+            return Err(ParseDiagnostic::ExpectedPattern(
+                self.peek_kind(),
+                self.peek_span(),
+            ));
+        };
+
+        let token = self.advance().unwrap();
+        Ok(Pattern {
+            kind: PatternKind::Literal {
+                kind: lit.into(),
+                text: self.lexeme(token.span).to_owned(),
+                negated,
+            },
+            span: start.coalesce_adjacent(token.span),
+        })
+    }
+
+    /// Parse a single pattern without alternatives.
+    fn parse_single_pattern(&mut self) -> Result<Pattern, ParseDiagnostic> {
+        let start = self.peek_span();
+
+        if let Some(&token) = self.peek()
+            && token.kind == TokenKind::Ident(IdentKind::NonReserved)
+            && token.text() == Some("_")
         {
-            let operator = self.advance().unwrap().kind.into();
-            let operand = self.parse_unary()?;
-            return Ok(ExpressionKind::Unary {
-                operator,
-                operand: Box::new(operand),
+            self.advance();
+            return Ok(Pattern {
+                kind: PatternKind::Wildcard,
+                span: start,
             });
         }
 
-        self.parse_primary()
+        let literal = self.parse_literal_pattern()?;
+        let inclusive = match self.peek_kind() {
+            TokenKind::PeriodPeriod => false,
+            TokenKind::PeriodPeriodEqual => true,
+            _ => return Ok(literal),
+        };
+
+        self.advance();
+        let end = self.parse_literal_pattern()?;
+
+        Ok(Pattern {
+            span: literal.span.coalesce_adjacent(end.span),
+            kind: PatternKind::Range {
+                start: Box::new(literal),
+                end: Box::new(end),
+                inclusive,
+            },
+        })
     }
 
-    fn parse_factor(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        let mut expr = self.parse_unary()?;
+    /// Parse a pattern, including alternatives separated by `|`.
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseDiagnostic> {
+        let first = self.parse_single_pattern()?;
+        if self.peek_kind() != TokenKind::Bar {
+            return Ok(first);
+        }
 
-        while let Some(&peek) = self.peek()
-            && (peek.kind == TokenKind::Star || peek.kind == TokenKind::Slash)
-        {
-            let operator = self.advance().unwrap().kind.into();
-            let rhs = self.parse_unary()?;
-            expr = ExpressionKind::Binary {
-                lhs: Box::new(expr),
-                operator,
-                rhs: Box::new(rhs),
-            };
+        let mut alternatives = vec![first];
+        while self.next_is(TokenKind::Bar) {
+            alternatives.push(self.parse_single_pattern()?);
         }
 
-        Ok(expr)
+        Ok(Pattern {
+            span: alternatives[0]
+                .span
+                .coalesce_adjacent(alternatives.last().unwrap().span),
+            kind: PatternKind::Or(alternatives),
+        })
     }
 
-    fn parse_term(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        let mut expr = self.parse_factor()?;
+    /// Parse a match expression.
+    fn parse_match(&mut self) -> Result<Expression, ParseDiagnostic> {
+        let start = self.advance().unwrap().span;
+        let scrutinee = self.parse_expr()?;
+        self.expect(TokenKind::OpenCurly, "`{`")?;
 
-        while let Some(&peek) = self.peek()
-            && (peek.kind == TokenKind::Minus || peek.kind == TokenKind::Plus)
-        {
-            let operator = self.advance().unwrap().kind.into();
-            let rhs = self.parse_factor()?;
-            expr = ExpressionKind::Binary {
-                lhs: Box::new(expr),
-                operator,
-                rhs: Box::new(rhs),
+        let mut arms = Vec::new();
+        while !self.at_end() && self.peek_kind() != TokenKind::ClosingCurly {
+            let pattern = self.parse_pattern()?;
+            self.expect(TokenKind::FatArrow, "`=>`")?;
+            let body = self.parse_expr()?;
+            let span = pattern.span.coalesce_adjacent(body.span);
+            arms.push(MatchArm {
+                pattern,
+                body,
+                span,
+            });
+
+            if !self.next_is(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        let end = self.expect(TokenKind::ClosingCurly, "`}`")?.span;
+        Ok(Expression {
+            kind: ExpressionKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+            span: start.coalesce_adjacent(end),
+        })
+    }
+
+    /// Parse calls and indexing, which can follow each other (f(x)[0], fs[0](x)).
+    fn parse_call(&mut self) -> Result<Expression, ParseDiagnostic> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            if self.next_is(TokenKind::OpenSquare) {
+                let index = self.parse_expr()?;
+                let end = self.expect(TokenKind::ClosingSquare, "`]`")?.span;
+                let span = expr.span.coalesce_adjacent(end);
+                expr = Expression {
+                    kind: ExpressionKind::Index {
+                        array: Box::new(expr),
+                        index: Box::new(index),
+                    },
+                    span,
+                };
+                continue;
+            }
+
+            if !self.next_is(TokenKind::OpenParen) {
+                break;
+            }
+
+            let mut arguments = Vec::new();
+
+            if self.peek_kind() != TokenKind::ClosingParen {
+                loop {
+                    arguments.push(self.parse_expr()?);
+
+                    if !self.next_is(TokenKind::Comma) {
+                        break;
+                    }
+                }
+            }
+
+            let end = self.expect(TokenKind::ClosingParen, "`)`")?.span;
+            let span = expr.span.coalesce_adjacent(end);
+            expr = Expression {
+                kind: ExpressionKind::Call {
+                    callee: Box::new(expr),
+                    arguments,
+                },
+                span,
             };
         }
 
         Ok(expr)
     }
 
-    fn parse_comparison(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        let mut expr = self.parse_term()?;
+    fn parse_unary(&mut self) -> Result<Expression, ParseDiagnostic> {
+        if self.peek_kind().is_unary_op() {
+            let operator_token = self.advance().unwrap();
+            let operand = self.nested(Self::parse_unary)?;
+            let span = operator_token.span.coalesce_adjacent(operand.span);
 
-        while let Some(&peek) = self.peek()
-            && peek.kind.is_comparison_op()
-        {
-            let operator = self.advance().unwrap().kind.into();
-            let rhs = self.parse_term()?;
-            expr = ExpressionKind::Binary {
-                lhs: Box::new(expr),
-                operator,
-                rhs: Box::new(rhs),
+            return Ok(Expression {
+                kind: ExpressionKind::Unary {
+                    operator: operator_token.kind.into(),
+                    operand: Box::new(operand),
+                },
+                span,
+            });
+        }
+
+        self.parse_call()
+    }
+
+    /// Parse conversions, which bind tighter than binary operators (-x as u8 * y).
+    fn parse_cast(&mut self) -> Result<Expression, ParseDiagnostic> {
+        let mut expr = self.parse_unary()?;
+
+        while self.next_is(TokenKind::Ident(IdentKind::Keyword(Keyword::As))) {
+            let ty = self.parse_type()?;
+            let span = expr.span.coalesce_adjacent(ty.span);
+            expr = Expression {
+                kind: ExpressionKind::Cast {
+                    value: Box::new(expr),
+                    ty,
+                },
+                span,
             };
         }
 
         Ok(expr)
     }
 
-    fn parse_equality(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        let mut expr = self.parse_comparison()?;
+    /// Parse a left-associative chain of binary operators sharing a precedence level.
+    fn parse_binary(
+        &mut self,
+        parse_operand: fn(&mut Self) -> Result<Expression, ParseDiagnostic>,
+        is_operator: fn(TokenKind) -> bool,
+    ) -> Result<Expression, ParseDiagnostic> {
+        let mut expr = parse_operand(self)?;
 
-        while let Some(&peek) = self.peek()
-            && peek.kind.is_equality_op()
-        {
-            let operator = self.advance().unwrap().kind.into();
-            let rhs = self.parse_comparison()?;
-            expr = ExpressionKind::Binary {
-                lhs: Box::new(expr),
-                operator,
-                rhs: Box::new(rhs),
+        while is_operator(self.peek_kind()) {
+            let token = self.advance().unwrap();
+            let rhs = parse_operand(self)?;
+            let span = expr.span.coalesce_adjacent(rhs.span);
+            expr = Expression {
+                kind: ExpressionKind::Binary {
+                    lhs: Box::new(expr),
+                    operator: token.kind.into(),
+                    rhs: Box::new(rhs),
+                    operator_span: token.span,
+                },
+                span,
             };
         }
 
         Ok(expr)
     }
 
+    fn parse_factor(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_cast, |kind| {
+            matches!(
+                kind,
+                TokenKind::Star | TokenKind::Slash | TokenKind::Percent
+            )
+        })
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_factor, |kind| {
+            matches!(kind, TokenKind::Minus | TokenKind::Plus)
+        })
+    }
+
+    fn parse_shift(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_term, |kind| {
+            matches!(kind, TokenKind::Shl | TokenKind::Shr)
+        })
+    }
+
+    fn parse_bitwise_and(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_shift, |kind| kind == TokenKind::Ampersand)
+    }
+
+    fn parse_bitwise_or(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_bitwise_and, |kind| kind == TokenKind::Bar)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_bitwise_or, TokenKind::is_comparison_op)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_comparison, TokenKind::is_equality_op)
+    }
+
+    fn parse_logical_and(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_equality, |kind| kind == TokenKind::AmpAmp)
+    }
+
+    fn parse_logical_or(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.parse_binary(Self::parse_logical_and, |kind| kind == TokenKind::BarBar)
+    }
+
+    /// Parse an assignment, which is right-associative and has the lowest precedence.
+    fn parse_assignment(&mut self) -> Result<Expression, ParseDiagnostic> {
+        let lhs = self.parse_logical_or()?;
+
+        if self.peek_kind().is_assignment_op() {
+            let token = self.advance().unwrap();
+            let rhs = self.parse_expr()?;
+            let span = lhs.span.coalesce_adjacent(rhs.span);
+
+            return Ok(Expression {
+                kind: ExpressionKind::Binary {
+                    lhs: Box::new(lhs),
+                    operator: token.kind.into(),
+                    rhs: Box::new(rhs),
+                    operator_span: token.span,
+                },
+                span,
+            });
+        }
+
+        Ok(lhs)
+    }
+
     /// Parse an expression.
-    fn parse_expr(&mut self) -> Result<ExpressionKind, ParseDiagnostic> {
-        self.parse_equality()
+    fn parse_expr(&mut self) -> Result<Expression, ParseDiagnostic> {
+        self.nested(Self::parse_assignment)
+    }
+
+    /// Parse a block of statements.
+    fn parse_block(&mut self) -> Result<Block, ParseDiagnostic> {
+        let start = self.expect(TokenKind::OpenCurly, "`{`")?.span;
+        let mut statements = Vec::new();
+
+        while !self.at_end() && self.peek_kind() != TokenKind::ClosingCurly {
+            statements.push(self.nested(Self::parse_statement)?);
+        }
+
+        let end = self.expect(TokenKind::ClosingCurly, "`}`")?.span;
+        Ok(Block {
+            statements,
+            span: start.coalesce_adjacent(end),
+        })
+    }
+
+    /// Parse a let statement.
+    fn parse_let(&mut self) -> Result<StatementKind, ParseDiagnostic> {
+        let name = self.parse_ident()?;
+        let ty = if self.next_is(TokenKind::Colon) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.expect(TokenKind::Equal, "`=`")?;
+        let initializer = self.parse_expr()?;
+        self.expect_semicolon()?;
+
+        Ok(StatementKind::Let {
+            name,
+            ty,
+            initializer,
+        })
+    }
+
+    /// Parse an if statement along with its elif and else branches.
+    fn parse_if(&mut self) -> Result<StatementKind, ParseDiagnostic> {
+        let condition = self.parse_expr()?;
+        let then_block = self.parse_block()?;
+
+        let mut elif_branches = Vec::new();
+        while self.next_is(TokenKind::Ident(IdentKind::Keyword(Keyword::Elif))) {
+            let condition = self.parse_expr()?;
+            let block = self.parse_block()?;
+            elif_branches.push((condition, block));
+        }
+
+        let else_block = if self.next_is(TokenKind::Ident(IdentKind::Keyword(Keyword::Else))) {
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+
+        Ok(StatementKind::If {
+            condition,
+            then_block,
+            elif_branches,
+            else_block,
+        })
+    }
+
+    /// Parse a do-while loop.
+    fn parse_do_while(&mut self) -> Result<StatementKind, ParseDiagnostic> {
+        let body = self.parse_block()?;
+        self.expect(
+            TokenKind::Ident(IdentKind::Keyword(Keyword::While)),
+            "`while`",
+        )?;
+        let condition = self.parse_expr()?;
+        self.expect_semicolon()?;
+
+        Ok(StatementKind::DoWhile { body, condition })
+    }
+
+    /// Parse a for loop, either over an array, string, map or range (for x in xs { ... }, for i in
+    /// 0..n { ... }) or with an initializer, condition and step.
+    fn parse_for(&mut self) -> Result<StatementKind, ParseDiagnostic> {
+        // Both kinds can start with an identifier, which is followed by `in` when iterating over
+        // an array, and is the start of the initializer otherwise.
+        let start = self.peek_span();
+        let initializer = if self.peek_kind() == TokenKind::Ident(IdentKind::NonReserved) {
+            let expr = self.parse_expr()?;
+            if let ExpressionKind::Ident(binding) = &expr.kind
+                && self.next_is(TokenKind::Ident(IdentKind::Keyword(Keyword::In)))
+            {
+                let binding = binding.clone();
+                let iterable = self.parse_iterable()?;
+                let body = self.parse_block()?;
+                return Ok(StatementKind::ForIn {
+                    binding,
+                    iterable,
+                    body,
+                });
+            }
+
+            self.expect_semicolon()?;
+            Statement {
+                kind: StatementKind::Expression(expr),
+                span: start.coalesce_adjacent(self.previous_span),
+            }
+        } else {
+            self.nested(Self::parse_statement)?
+        };
+
+        let initializer = Box::new(initializer);
+        let condition = self.parse_expr()?;
+        self.expect_semicolon()?;
+        let step = self.parse_expr()?;
+        let body = self.parse_block()?;
+
+        Ok(StatementKind::For {
+            initializer,
+            condition,
+            step,
+            body,
+        })
+    }
+
+    /// Parse what a for loop iterates over, which is the only place a range can be written.
+    fn parse_iterable(&mut self) -> Result<Expression, ParseDiagnostic> {
+        let start = self.parse_expr()?;
+        let inclusive = match self.peek_kind() {
+            TokenKind::PeriodPeriod => false,
+            TokenKind::PeriodPeriodEqual => true,
+            _ => return Ok(start),
+        };
+
+        self.advance();
+        let end = self.parse_expr()?;
+        Ok(Expression {
+            span: start.span.coalesce_adjacent(end.span),
+            kind: ExpressionKind::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            },
+        })
+    }
+
+    /// Parse a statement.
+    fn parse_statement(&mut self) -> Result<Statement, ParseDiagnostic> {
+        let start = self.peek_span();
+
+        let kind = match self.peek_kind() {
+            TokenKind::OpenCurly => StatementKind::Block(self.parse_block()?),
+            TokenKind::Ident(IdentKind::Keyword(keyword))
+                if matches!(
+                    keyword,
+                    Keyword::Let
+                        | Keyword::Ret
+                        | Keyword::If
+                        | Keyword::While
+                        | Keyword::Do
+                        | Keyword::For
+                ) =>
+            {
+                self.advance();
+
+                match keyword {
+                    Keyword::Let => self.parse_let()?,
+                    Keyword::Ret => {
+                        let value = if self.peek_kind() == TokenKind::Semicolon {
+                            None
+                        } else {
+                            Some(self.parse_expr()?)
+                        };
+                        self.expect_semicolon()?;
+                        StatementKind::Ret(value)
+                    }
+                    Keyword::If => self.parse_if()?,
+                    Keyword::While => {
+                        let condition = self.parse_expr()?;
+                        let body = self.parse_block()?;
+                        StatementKind::While { condition, body }
+                    }
+                    Keyword::Do => self.parse_do_while()?,
+                    Keyword::For => self.parse_for()?,
+                    _ => unreachable!(),
+                }
+            }
+            _ => {
+                let expr = self.parse_expr()?;
+
+                // A match used as a statement doesn't need to be followed by a semicolon.
+                if !matches!(expr.kind, ExpressionKind::Match { .. }) {
+                    self.expect_semicolon()?;
+                } else {
+                    self.next_is(TokenKind::Semicolon);
+                }
+
+                StatementKind::Expression(expr)
+            }
+        };
+
+        Ok(Statement {
+            kind,
+            span: start.coalesce_adjacent(self.previous_span),
+        })
+    }
+
+    /// Parse the parameter list of a procedure.
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, ParseDiagnostic> {
+        self.expect(TokenKind::OpenParen, "`(`")?;
+        let mut parameters = Vec::new();
+
+        if self.peek_kind() != TokenKind::ClosingParen {
+            loop {
+                let name = self.parse_ident()?;
+                self.expect(TokenKind::Colon, "`:`")?;
+                let ty = self.parse_type()?;
+                let span = name.span.coalesce_adjacent(ty.span);
+                parameters.push(Parameter { name, ty, span });
+
+                if !self.next_is(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(TokenKind::ClosingParen, "`)`")?;
+        Ok(parameters)
+    }
+
+    /// Parse a top-level item.
+    /// Parse the attributes before an item.
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, ParseDiagnostic> {
+        let mut attributes = Vec::new();
+
+        while self.peek_kind() == TokenKind::At {
+            let start = self.advance().unwrap().span;
+            let name = self.parse_ident()?;
+            attributes.push(Attribute {
+                span: start.coalesce_adjacent(name.span),
+                name,
+            });
+        }
+
+        Ok(attributes)
+    }
+
+    fn parse_item(&mut self) -> Result<Item, ParseDiagnostic> {
+        let start = self.peek_span();
+        let doc = self.take_doc(start);
+        let attributes = self.parse_attributes()?;
+        let visibility = if self.next_is(TokenKind::Ident(IdentKind::Keyword(Keyword::Pub))) {
+            Visibility::Public
+        } else {
+            Visibility::Private
+        };
+
+        match self.peek_kind() {
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Proc)) => {
+                self.advance();
+                let name = self.parse_ident()?;
+                let parameters = self.parse_parameters()?;
+                let return_type = self.parse_type()?;
+                let body = self.parse_block()?;
+
+                Ok(Item {
+                    span: start.coalesce_adjacent(body.span),
+                    kind: ItemKind::Proc(Proc {
+                        name,
+                        parameters,
+                        return_type,
+                        body,
+                    }),
+                    visibility,
+                    doc,
+                    attributes,
+                })
+            }
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Const)) => {
+                self.advance();
+                let name = self.parse_ident()?;
+                self.expect(TokenKind::Colon, "`:`")?;
+                let ty = self.parse_type()?;
+                self.expect(TokenKind::Equal, "`=`")?;
+                let value = self.parse_expr()?;
+                let end = self.expect_semicolon()?.span;
+
+                Ok(Item {
+                    span: start.coalesce_adjacent(end),
+                    kind: ItemKind::Const(Const { name, ty, value }),
+                    visibility,
+                    doc,
+                    attributes,
+                })
+            }
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Mod)) => {
+                self.advance();
+                let name = self.parse_ident()?;
+                self.expect(TokenKind::OpenCurly, "`{`")?;
+
+                let mut items = Vec::new();
+                while !self.at_end() && self.peek_kind() != TokenKind::ClosingCurly {
+                    items.push(self.nested(Self::parse_item)?);
+                }
+
+                let end = self.expect(TokenKind::ClosingCurly, "`}`")?.span;
+                Ok(Item {
+                    span: start.coalesce_adjacent(end),
+                    kind: ItemKind::Mod(Module { name, items }),
+                    visibility,
+                    doc,
+                    attributes,
+                })
+            }
+            TokenKind::Ident(IdentKind::Keyword(Keyword::Import)) => {
+                self.advance();
+                let name = self.parse_ident()?;
+                let end = self.expect_semicolon()?.span;
+
+                Ok(Item {
+                    span: start.coalesce_adjacent(end),
+                    kind: ItemKind::Import(name),
+                    visibility,
+                    doc,
+                    attributes,
+                })
+            }
+            found => Err(ParseDiagnostic::ExpectedItem(found, self.peek_span())),
+        }
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Vec<ExpressionKind>, DiagnosticSink> {
-    let mut parser = Parser::new(tokens);
-    let mut nodes = Vec::new();
+pub fn parse(source: &str, tokens: Vec<Token>) -> Result<Program, DiagnosticSink> {
+    parse_cancellable(source, tokens, &Cancel::new())
+        .unwrap_or_else(|Cancelled| unreachable!("nothing else holds the token"))
+}
+
+/// Parse a program like [`parse`], stopping between items if the token is cancelled.
+pub fn parse_cancellable(
+    source: &str,
+    tokens: Vec<Token>,
+    cancel: &Cancel,
+) -> Result<Result<Program, DiagnosticSink>, Cancelled> {
+    let mut parser = Parser::new(source, tokens);
+    let mut program = Program::default();
     let mut diagnostics = DiagnosticSink::new();
 
     while !parser.at_end() {
-        match parser.parse_expr() {
-            Ok(expr) => nodes.push(expr),
-            Err(e) => diagnostics.push_diagnostic(e),
+        cancel.check()?;
+        match parser.parse_item() {
+            Ok(item) => program.items.push(item),
+            Err(e) => {
+                diagnostics.push_diagnostic(e);
+                parser.synchronize();
+            }
         }
     }
 
     if diagnostics.has_diagnostics() {
-        return Err(diagnostics);
+        return Ok(Err(diagnostics));
+    }
+
+    // A tree that breaks its invariants is a bug in the parser, found here in debug builds,
+    // and so by every test, rather than by whichever later stage trips over it.
+    #[cfg(debug_assertions)]
+    if let Err(violations) = ast::validate(&program) {
+        let violations: Vec<_> = violations.iter().map(ToString::to_string).collect();
+        panic!("the parser built an invalid tree:\n{}", violations.join("\n"));
+    }
+
+    Ok(Ok(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path, sync::mpsc, thread, time::Duration};
+
+    /// Lex and parse a program on a thread of its own, failing if either panics, overflows the
+    /// thread's stack, or doesn't finish in time.
+    fn lex_and_parse(name: &str, source: String) -> Option<Result<Program, DiagnosticSink>> {
+        let (sender, receiver) = mpsc::channel();
+        let parsing = thread::spawn(move || {
+            let parsed = lexer::lex(&source)
+                .ok()
+                .map(|tokens| parse(&source, tokens));
+            sender.send(parsed).unwrap();
+        });
+
+        match receiver.recv_timeout(Duration::from_secs(10)) {
+            Ok(parsed) => parsed,
+            Err(mpsc::RecvTimeoutError::Timeout) => panic!("`{name}` doesn't finish parsing"),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let panic = parsing.join().unwrap_err();
+                std::panic::resume_unwind(panic)
+            }
+        }
     }
 
-    Ok(nodes)
+    #[test]
+    fn test_regressions_parse_without_crashing() {
+        let dir = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/regressions");
+        let mut count = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let source = String::from_utf8_lossy(&fs::read(&path).unwrap()).into_owned();
+            lex_and_parse(&path.display().to_string(), source);
+            count += 1;
+        }
+        assert!(count > 0, "no regressions in `{}`", dir.display());
+    }
+
+    #[test]
+    fn test_nesting_too_deeply_is_an_error() {
+        // The statement and the expression it returns are nested in the procedure too.
+        let nested = |depth| {
+            let (open, close) = ("(".repeat(depth), ")".repeat(depth));
+            format!("proc main() int {{ ret {open}1{close}; }}")
+        };
+
+        assert!(lex_and_parse("limit", nested(MAX_NESTING - 2)).is_some_and(|ast| ast.is_ok()));
+
+        let too_deep = lex_and_parse("past the limit", nested(MAX_NESTING - 1))
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(
+            too_deep.diagnostics(),
+            [ParseDiagnostic::NestedTooDeeply(MAX_NESTING, _)]
+        ));
+    }
 }
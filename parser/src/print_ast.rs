@@ -19,6 +19,31 @@ impl fmt::Display for LiteralKind {
     }
 }
 
+impl fmt::Display for TypeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TypeKind::*;
+
+        match self {
+            Int => write!(f, "int"),
+            I8 => write!(f, "i8"),
+            I16 => write!(f, "i16"),
+            I32 => write!(f, "i32"),
+            I64 => write!(f, "i64"),
+            U8 => write!(f, "u8"),
+            U16 => write!(f, "u16"),
+            U32 => write!(f, "u32"),
+            U64 => write!(f, "u64"),
+            Float => write!(f, "float"),
+            Bool => write!(f, "bool"),
+            Char => write!(f, "char"),
+            Str => write!(f, "str"),
+            Void => write!(f, "void"),
+            Array(element) => write!(f, "[{}]", element.kind),
+            Map(key, value) => write!(f, "[{}: {}]", key.kind, value.kind),
+        }
+    }
+}
+
 impl fmt::Display for UnaryOpKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use UnaryOpKind::*;
@@ -74,3 +99,16 @@ impl fmt::Display for BinaryOpKind {
         )
     }
 }
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                write!(f, "::")?;
+            }
+            write!(f, "{}", segment.name)?;
+        }
+
+        Ok(())
+    }
+}
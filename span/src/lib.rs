@@ -1,5 +1,16 @@
+pub mod source_map;
+
+pub use source_map::{FileId, FullSpan, InvalidUtf8, SourceMap};
+
 use miette::SourceSpan;
-use std::{fmt, ops::Range};
+use std::{
+    fmt,
+    ops::{Deref, DerefMut, Range},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 /// An exclusive range representing a part of source code.
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -24,6 +35,54 @@ impl Span {
 
         Self { start, end }
     }
+
+    pub fn len(self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether another span is inside this one, counting its ends.
+    pub fn contains(self, other: Self) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// Whether two spans share any of the source. An empty span is the position it's at, so it
+    /// intersects a span it's inside or at either end of.
+    pub fn intersects(self, other: Self) -> bool {
+        if self.is_empty() || other.is_empty() {
+            self.start <= other.end && other.start <= self.end
+        } else {
+            self.start < other.end && other.start < self.end
+        }
+    }
+
+    /// The gap between the end of one span and the start of a later one, which is empty if
+    /// they touch or overlap.
+    pub fn between(before: Self, after: Self) -> Self {
+        Self {
+            start: before.end,
+            end: after.start.max(before.end),
+        }
+    }
+
+    /// The empty span at the start of this one.
+    pub fn shrink_to_start(self) -> Self {
+        Self {
+            start: self.start,
+            end: self.start,
+        }
+    }
+
+    /// The empty span at the end of this one.
+    pub fn shrink_to_end(self) -> Self {
+        Self {
+            start: self.end,
+            end: self.end,
+        }
+    }
 }
 
 impl fmt::Debug for Span {
@@ -45,9 +104,93 @@ impl Into<SourceSpan> for Span {
     }
 }
 
+/// A value with the span of the source it came from. It derefs to the value, so its methods can
+/// be called on it directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+
+    /// Change the value, keeping its span.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned {
+            node: f(self.node),
+            span: self.span,
+        }
+    }
+
+    pub fn as_ref(&self) -> Spanned<&T> {
+        Spanned {
+            node: &self.node,
+            span: self.span,
+        }
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+/// A token for stopping work another thread is doing, like compiling a file that's changed
+/// since. Clones share the token, so cancelling any of them cancels them all. The work stops
+/// the next time it checks, rather than straight away.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns [`Cancelled`] if the token was cancelled, for stopping with `?`.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Work was stopped by a [`Cancel`] before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
 #[cfg(test)]
 mod tests {
-    use super::Span;
+    use super::{Cancel, Cancelled, Span, Spanned};
 
     #[test]
     fn test_coalesce_adjacent_spans() {
@@ -67,4 +210,51 @@ mod tests {
         let eigth = Span::from(2..4);
         assert_eq!(seventh.coalesce_adjacent(eigth), Span::from(1..5));
     }
+
+    #[test]
+    fn test_span_utilities() {
+        let span = Span::from(2..6);
+        assert_eq!((span.len(), span.is_empty()), (4, false));
+        assert!(Span::from(3..3).is_empty());
+
+        assert!(span.contains(Span::from(2..6)));
+        assert!(span.contains(Span::from(3..4)));
+        assert!(!span.contains(Span::from(5..7)));
+
+        assert!(span.intersects(Span::from(5..7)));
+        assert!(!span.intersects(Span::from(6..8)));
+        assert!(span.intersects(Span::from(6..6)));
+        assert!(Span::from(2..2).intersects(span));
+        assert!(!span.intersects(Span::from(7..7)));
+
+        assert_eq!(Span::between(span, Span::from(9..10)), Span::from(6..9));
+        assert_eq!(Span::between(span, Span::from(4..8)), Span::from(6..6));
+
+        assert_eq!(span.shrink_to_start(), Span::from(2..2));
+        assert_eq!(span.shrink_to_end(), Span::from(6..6));
+    }
+
+    #[test]
+    fn test_spanned_keeps_its_span() {
+        let name = Spanned::new("main".to_owned(), Span::from(5..9));
+        assert_eq!(name.len(), 4);
+        assert_eq!(
+            name.as_ref().map(String::as_str),
+            Spanned::new("main", name.span)
+        );
+
+        let len = name.map(|name| name.len());
+        assert_eq!((len.node, len.span), (4, Span::from(5..9)));
+    }
+
+    #[test]
+    fn test_cancelling_a_clone_cancels_the_token() {
+        let cancel = Cancel::new();
+        let clone = cancel.clone();
+        assert_eq!(cancel.check(), Ok(()));
+
+        clone.cancel();
+        assert!(cancel.is_cancelled());
+        assert_eq!(cancel.check(), Err(Cancelled));
+    }
 }
@@ -0,0 +1,346 @@
+//! The files of a program, each with an id of its own.
+//!
+//! The files are laid out one after another in a single text, so a [`Span`] into the text is
+//! in exactly one of them. A [`FullSpan`] names that file, with the span from the file's start,
+//! for reporting where something is when a program has several files.
+
+use crate::Span;
+use miette::{MietteError, MietteSpanContents, SourceCode, SourceSpan, SpanContents};
+use std::{fmt, ops::Range};
+
+/// A file of a [`SourceMap`], numbered from 0 in the order they're added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(u32);
+
+impl FileId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A span in a file, from the start of the file rather than of the program's text.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FullSpan {
+    pub file: FileId,
+    pub span: Span,
+}
+
+impl fmt::Debug for FullSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:?}", self.file.0, self.span)
+    }
+}
+
+/// How many bytes of a file [`SourceMap::add_bytes`] checks are UTF-8 at a time.
+const UTF8_CHUNK: usize = 1 << 16;
+
+/// Bytes added as a file that aren't UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidUtf8 {
+    /// Where the first bytes that aren't are, from the start of the file.
+    pub offset: usize,
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the bytes at offset {} aren't UTF-8", self.offset)
+    }
+}
+
+impl std::error::Error for InvalidUtf8 {}
+
+/// A file's name, where it is in the text, and where its lines start.
+#[derive(Debug)]
+struct File {
+    name: String,
+    range: Range<usize>,
+
+    /// Where each line starts, from the start of the file.
+    lines: Vec<usize>,
+}
+
+/// The files of a program, laid out in one text.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    /// The text of every file, each followed by a newline so no span can reach from one file
+    /// into the next.
+    text: String,
+    files: Vec<File>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A map with room for `len` bytes of text, counting the newline after each file, so adding
+    /// large files doesn't grow the text, copying it, as they're added.
+    pub fn with_capacity(len: usize) -> Self {
+        Self {
+            text: String::with_capacity(len),
+            files: Vec::new(),
+        }
+    }
+
+    /// Add a file after the others.
+    pub fn add(&mut self, name: impl Into<String>, text: &str) -> FileId {
+        let start = self.text.len();
+        let mut lines = vec![0];
+        self.push(text, &mut lines, start);
+        self.finish_file(name.into(), start, lines)
+    }
+
+    /// Add a file after the others from its bytes, which are checked to be UTF-8 a chunk at a
+    /// time as they're copied in, so a large file is read through once rather than checked,
+    /// then copied. Bytes that aren't UTF-8 leave the map as it was.
+    pub fn add_bytes(
+        &mut self,
+        name: impl Into<String>,
+        mut bytes: &[u8],
+    ) -> Result<FileId, InvalidUtf8> {
+        let start = self.text.len();
+        let mut lines = vec![0];
+        while !bytes.is_empty() {
+            let chunk = &bytes[..bytes.len().min(UTF8_CHUNK)];
+            let valid = match std::str::from_utf8(chunk) {
+                Ok(valid) => valid,
+                // A character cut off by the end of the chunk is checked with the next one.
+                Err(error) if error.error_len().is_none() && chunk.len() < bytes.len() => {
+                    std::str::from_utf8(&chunk[..error.valid_up_to()]).unwrap()
+                }
+                Err(error) => {
+                    let offset = self.text.len() - start + error.valid_up_to();
+                    self.text.truncate(start);
+                    return Err(InvalidUtf8 { offset });
+                }
+            };
+            self.push(valid, &mut lines, start);
+            bytes = &bytes[valid.len()..];
+        }
+        Ok(self.finish_file(name.into(), start, lines))
+    }
+
+    /// Append some of a file's text, noting where its lines start.
+    fn push(&mut self, text: &str, lines: &mut Vec<usize>, file_start: usize) {
+        let offset = self.text.len() - file_start;
+        lines.extend(text.match_indices('\n').map(|(i, _)| offset + i + 1));
+        self.text.push_str(text);
+    }
+
+    fn finish_file(&mut self, name: String, start: usize, lines: Vec<usize>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        self.files.push(File {
+            name,
+            range: start..self.text.len(),
+            lines,
+        });
+        self.text.push('\n');
+        id
+    }
+
+    /// The text of every file, in order.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Every file, in order.
+    pub fn files(&self) -> impl ExactSizeIterator<Item = FileId> {
+        (0..self.files.len() as u32).map(FileId)
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.index()].name
+    }
+
+    /// The text of a file.
+    pub fn source(&self, file: FileId) -> &str {
+        &self.text[self.files[file.index()].range.clone()]
+    }
+
+    /// Where a file starts in the text.
+    pub fn start(&self, file: FileId) -> usize {
+        self.files[file.index()].range.start
+    }
+
+    /// The file an offset of the text is in, counting the newline after each file as its own.
+    pub fn file_at(&self, offset: usize) -> FileId {
+        let index = self
+            .files
+            .partition_point(|file| file.range.start <= offset)
+            .saturating_sub(1);
+        FileId(index as u32)
+    }
+
+    /// The file a span of the text is in, with the span from the file's start.
+    pub fn locate(&self, span: Span) -> FullSpan {
+        let file = self.file_at(span.start);
+        let start = self.start(file);
+        FullSpan {
+            file,
+            span: Span::from(span.start - start..span.end - start),
+        }
+    }
+
+    /// The span of the text a span in a file is at.
+    pub fn span(&self, span: FullSpan) -> Span {
+        let start = self.start(span.file);
+        Span::from(span.span.start + start..span.span.end + start)
+    }
+
+    /// The line an offset from the start of a file is on, from 0.
+    fn line(&self, file: FileId, offset: usize) -> usize {
+        let lines = &self.files[file.index()].lines;
+        lines.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The line and column of an offset from the start of a file, from 1, with columns counted
+    /// in characters.
+    pub fn line_column(&self, file: FileId, offset: usize) -> (usize, usize) {
+        let line = self.line(file, offset);
+        let start = self.files[file.index()].lines[line];
+        let text = self.source(file);
+        (line + 1, text[start..offset].chars().count() + 1)
+    }
+
+    /// Read a span from the start of a file, naming the file, with lines counted from its
+    /// start. Only the lines around the span are read.
+    pub fn read_file_span(
+        &self,
+        file: FileId,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<MietteSpanContents<'_>, MietteError> {
+        let text = self.source(file);
+        let first = self
+            .line(file, span.offset().min(text.len()))
+            .saturating_sub(context_lines_before);
+        let from = self.files[file.index()].lines[first];
+        let local = SourceSpan::new((span.offset() - from).into(), span.len().into());
+        let contents = text[from..].read_span(&local, context_lines_before, context_lines_after)?;
+        let read = SourceSpan::new(
+            (contents.span().offset() + from).into(),
+            contents.span().len().into(),
+        );
+        Ok(MietteSpanContents::new_named(
+            self.name(file).to_owned(),
+            contents.data(),
+            read,
+            contents.line() + first,
+            contents.column(),
+            contents.line_count(),
+        ))
+    }
+}
+
+/// Reads spans from the file they're in, named after it, with lines counted from its start.
+impl SourceCode for SourceMap {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let file = self.file_at(span.offset());
+        let start = self.start(file);
+        let local = SourceSpan::new((span.offset() - start).into(), span.len().into());
+        let contents =
+            self.read_file_span(file, &local, context_lines_before, context_lines_after)?;
+        let read = SourceSpan::new(
+            (contents.span().offset() + start).into(),
+            contents.span().len().into(),
+        );
+        Ok(Box::new(MietteSpanContents::new_named(
+            self.name(file).to_owned(),
+            contents.data(),
+            read,
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locates_spans_in_their_files() {
+        let mut map = SourceMap::new();
+        let a = map.add("a.mtx", "one\ntwo\nthree");
+        let b = map.add("b.mtx", "four\nfive ünf\n");
+        assert_eq!(map.files().collect::<Vec<_>>(), [a, b]);
+        assert_eq!((map.name(b), map.source(b)), ("b.mtx", "four\nfive ünf\n"));
+
+        let five = map.text().find("five").unwrap();
+        let span = Span::from(five..five + 4);
+        let full = map.locate(span);
+        assert_eq!(
+            full,
+            FullSpan {
+                file: b,
+                span: Span::from(5..9)
+            }
+        );
+        assert_eq!(map.span(full), span);
+        assert_eq!(map.locate(Span::from(2..3)).file, a);
+
+        assert_eq!(map.line_column(b, 0), (1, 1));
+        assert_eq!(map.line_column(b, 5), (2, 1));
+        assert_eq!(
+            map.line_column(b, map.source(b).rfind('n').unwrap()),
+            (2, 7)
+        );
+    }
+
+    #[test]
+    fn test_adds_files_from_bytes_a_chunk_at_a_time() {
+        // Long enough to span several chunks, with a character cut by the end of each.
+        let text = "ü\n".repeat(UTF8_CHUNK / 2 + 1) + "x";
+        assert!(!text.is_char_boundary(UTF8_CHUNK));
+        let mut map = SourceMap::with_capacity(text.len() + 1);
+        let capacity = map.text.capacity();
+        let file = map.add_bytes("a.mtx", text.as_bytes()).unwrap();
+        assert_eq!(map.source(file), text);
+        assert_eq!(map.text.capacity(), capacity);
+
+        let mut lines = SourceMap::new();
+        lines.add("a.mtx", &text);
+        assert_eq!(map.files[0].lines, lines.files[0].lines);
+
+        let mut bytes = text.into_bytes();
+        bytes[UTF8_CHUNK + 5] = 0xff;
+        assert_eq!(
+            map.add_bytes("b.mtx", &bytes),
+            Err(InvalidUtf8 {
+                offset: UTF8_CHUNK + 5
+            })
+        );
+        assert_eq!(map.files().len(), 1);
+        assert_eq!(map.text().len(), map.source(file).len() + 1);
+    }
+
+    #[test]
+    fn test_reads_spans_around_their_lines() {
+        let mut map = SourceMap::new();
+        map.add("a.mtx", "one\ntwo\nthree");
+        let b = map.add("b.mtx", "four\nfive ünf\n");
+
+        let two = map.text().find("two").unwrap();
+        let contents = map.read_span(&(two, 3).into(), 1, 1).unwrap();
+        assert_eq!(contents.name(), Some("a.mtx"));
+        assert_eq!(contents.line(), 0);
+        assert_eq!(contents.data(), b"one\ntwo\nthree");
+
+        let five = map.text().find("five").unwrap();
+        let contents = map.read_span(&(five, 4).into(), 0, 0).unwrap();
+        assert_eq!(contents.name(), Some("b.mtx"));
+        assert_eq!((contents.line(), contents.column()), (1, 0));
+        assert_eq!(contents.data(), b"five");
+
+        let contents = map.read_file_span(b, &(5, 4).into(), 0, 0).unwrap();
+        assert_eq!(contents.data(), b"five");
+        assert_eq!((contents.line(), contents.span().offset()), (1, 5));
+    }
+}
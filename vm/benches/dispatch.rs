@@ -0,0 +1,128 @@
+//! Benchmarks of the VM's dispatch loop on loop-heavy programs. Run them with
+//! `cargo bench -p vm`. `switch-dense` and `switch-sparse` match on the same number of arms,
+//! with ints close enough together to jump through a table and too far apart for one.
+//!
+//! The VM was a stack machine before it moved to registers. The same programs on the last
+//! stack design, by criterion's mean on one machine:
+//!
+//! | program | instructions (stack → registers) | time (stack → registers) |
+//! |---------|----------------------------------|--------------------------|
+//! | `fib`   | 218,909 → 120,401 (-45%)         | 6.58 ms → 3.23 ms (-51%) |
+//! | `loops` | 2,450,025 → 1,130,014 (-54%)     | 84.6 ms → 27.3 ms (-68%) |
+//! | `match` | 1,545,010 → 775,005 (-50%)       | 42.1 ms → 16.2 ms (-62%) |
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use interp::{Io, Options, Value};
+
+/// How many instructions the stack design ran for each program it could, which the register
+/// design must stay well under.
+const STACK_DESIGN: [(&str, u64); 3] =
+    [("fib", 218_909), ("loops", 2_450_025), ("match", 1_545_010)];
+
+const PROGRAMS: [(&str, &str, i64); 5] = [
+    (
+        "fib",
+        "proc fib(n: int) int { if n < 2 { ret n; } ret fib(n - 1) + fib(n - 2); }
+        proc main() int { ret fib(20); }",
+        6765,
+    ),
+    (
+        "loops",
+        "proc main() int {
+            let total = 0;
+            for let i = 0; i < 100000; i += 1 {
+                if i % 3 == 0 { total += i; } elif i % 5 == 0 { total -= 1; }
+            }
+            let xs: [int] = [];
+            for let i = 0; i < 10000; i += 1 { push(xs, i * 2); }
+            for x in xs { total += x; }
+            ret total;
+        }",
+        1766660000,
+    ),
+    (
+        "match",
+        "proc classify(n: int) int { ret match n % 40 { 0..10 => 1, 10 | 20..=29 => 2, _ => 3 }; }
+        proc main() int {
+            let total = 0;
+            for let i = 0; i < 50000; i += 1 { total += classify(i); }
+            ret total;
+        }",
+        111250,
+    ),
+    (
+        "switch-dense",
+        "proc digit(n: int) int {
+            ret match n {
+                0 => 3, 1 => 1, 2 => 4, 3 => 1, 4 => 5, 5 => 9, 6 => 2, 7 => 6,
+                8 => 5, 9 => 3, 10 => 5, 11 => 8, 12 => 9, 13 => 7, 14 => 9, _ => 3,
+            };
+        }
+        proc main() int {
+            let total = 0;
+            for i in 0..50000 { total += digit(i % 16); }
+            ret total;
+        }",
+        250000,
+    ),
+    (
+        "switch-sparse",
+        "proc digit(n: int) int {
+            ret match n {
+                0 => 3, 10 => 1, 20 => 4, 30 => 1, 40 => 5, 50 => 9, 60 => 2, 70 => 6,
+                80 => 5, 90 => 3, 100 => 5, 110 => 8, 120 => 9, 130 => 7, 140 => 9, _ => 3,
+            };
+        }
+        proc main() int {
+            let total = 0;
+            for i in 0..50000 { total += digit(i % 16 * 10); }
+            ret total;
+        }",
+        250000,
+    ),
+];
+
+fn compile(source: &str) -> vm::Bytecode {
+    let tokens = lexer::lex(source).unwrap();
+    let ast = parser::parse(source, tokens).unwrap();
+    vm::compile(&hir::lower(&ast).unwrap().program)
+}
+
+/// Count the instructions a program runs.
+fn instructions(bytecode: &vm::Bytecode) -> u64 {
+    let (mut input, mut output) = (&b""[..], Vec::new());
+    let io = Io::new(&mut input, &mut output);
+    let machine = vm::Machine::new(bytecode, io, Options::default()).unwrap();
+    machine.run_profiled().1.total_instrs()
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    for (name, source, expected) in PROGRAMS {
+        let bytecode = compile(source);
+        if let Some(&(_, stack)) = STACK_DESIGN.iter().find(|(program, _)| *program == name) {
+            let registers = instructions(&bytecode);
+            let change = 100.0 * (registers as f64 / stack as f64 - 1.0);
+            eprintln!(
+                "{name}: {stack} instructions on the stack design, {registers} on registers \
+                ({change:+.0}%)"
+            );
+            assert!(
+                registers * 3 < stack * 2,
+                "{name} runs {registers} instructions"
+            );
+        }
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let (mut input, mut output) = (&b""[..], Vec::new());
+                let io = Io::new(&mut input, &mut output);
+                let result = vm::run_with_io(&bytecode, io, Options::default()).unwrap();
+                assert_eq!(result, Value::int(expected));
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
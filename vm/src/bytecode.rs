@@ -0,0 +1,166 @@
+use crate::debug::LineTable;
+use hir::{BinOp, Builtin, NativeSignature, Overflow, UnOp};
+use interp::Value;
+use span::Span;
+
+/// A single VM instruction.
+///
+/// Operands name registers of the current frame, or index into the constant pool, the current
+/// function's code or the program's functions. Instructions read every operand before writing
+/// their destination, so it can be one of their operands.
+///
+/// Instructions are numbered densely from 0 in a byte, so the VM's dispatch `match` compiles
+/// to a jump table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Instr {
+    /// Copy a value from the constant pool into a register.
+    LoadConst { dst: u32, index: u32 },
+
+    /// Copy a value from one register to another.
+    Move { dst: u32, src: u32 },
+
+    /// Apply an operator to a register.
+    Unary { op: UnOp, dst: u32, src: u32 },
+
+    /// Apply an operator to two registers.
+    Binary {
+        op: BinOp,
+        dst: u32,
+        lhs: u32,
+        rhs: u32,
+    },
+
+    /// Apply an operator to a register and a value from the constant pool.
+    BinaryConst {
+        op: BinOp,
+        dst: u32,
+        lhs: u32,
+        rhs: u32,
+    },
+
+    /// Check whether a register is within a range of two values from the constant pool.
+    InRange {
+        dst: u32,
+        src: u32,
+        start: u32,
+        end: u32,
+        inclusive: bool,
+    },
+
+    /// Continue at an instruction in the current function.
+    Jump(u32),
+
+    /// Jump if a register holds `false`.
+    JumpIfFalse { cond: u32, target: u32 },
+
+    /// Jump if a register holds `true`.
+    JumpIfTrue { cond: u32, target: u32 },
+
+    /// Call a function with its arguments in consecutive registers starting at `args`, which
+    /// become the first registers of the callee's frame.
+    Call { function: u32, args: u32, dst: u32 },
+
+    /// Call a function like `Call`, but replace the current frame with the callee's, so the
+    /// callee returns straight to the caller's caller. The outermost frame is kept, so it
+    /// calls like `Call` there, putting the result in `args`, and is always followed by a
+    /// `Ret(args)`.
+    TailCall { function: u32, args: u32 },
+
+    /// Call a builtin with its arguments in consecutive registers starting at `args`.
+    Builtin {
+        builtin: Builtin,
+        args: u32,
+        dst: u32,
+    },
+
+    /// Call a native procedure of the program's host with its arguments in consecutive
+    /// registers starting at `args`. `native` indexes into [`Bytecode::natives`].
+    Native { native: u32, args: u32, dst: u32 },
+
+    /// Discard the current frame and give the value of a register to the caller.
+    Ret(u32),
+
+    /// Make a new array holding the values of consecutive registers.
+    Array { dst: u32, start: u32, len: u32 },
+
+    /// Read the element of an array at an index, or the value of a key in a map.
+    Index { dst: u32, array: u32, index: u32 },
+
+    /// Store a value into the element of an array at an index, or as the value of a key in a
+    /// map.
+    StoreIndex { array: u32, index: u32, value: u32 },
+
+    /// Make a new map from `len` entries in consecutive registers, each a key followed by its
+    /// value.
+    Map { dst: u32, start: u32, len: u32 },
+
+    /// Jump to the target a table of the current function gives an int register.
+    Switch { src: u32, table: u32 },
+}
+
+/// Where a `Switch` jumps for each int in a range, so a dense match reaches its arm in one step
+/// rather than testing each pattern in turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwitchTable {
+    /// The int the first target is for.
+    pub low: i64,
+    pub targets: Vec<u32>,
+
+    /// Where ints outside the table jump.
+    pub default: u32,
+}
+
+impl SwitchTable {
+    /// Where an int jumps.
+    pub fn target(&self, value: i128) -> u32 {
+        usize::try_from(value - i128::from(self.low))
+            .ok()
+            .and_then(|index| self.targets.get(index))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// The compiled code of a procedure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    /// The procedure's name, qualified with the path of modules containing it.
+    pub name: String,
+
+    /// The number of parameters, which are the first registers.
+    pub arity: u32,
+
+    /// The number of registers, which hold the parameters, then the other locals, then
+    /// temporaries introduced by compilation.
+    pub registers: u32,
+    pub code: Vec<Instr>,
+
+    /// The tables of the function's `Switch`es.
+    pub tables: Vec<SwitchTable>,
+
+    /// The span of the source each instruction was compiled from, for runtime errors.
+    pub lines: LineTable,
+
+    /// The span of the procedure's definition.
+    pub span: Span,
+}
+
+/// A compiled program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bytecode {
+    /// Indexed by the procedure ids of the program the bytecode was compiled from.
+    pub functions: Vec<Function>,
+    pub constants: Vec<Value>,
+
+    /// The signatures of the native procedures the program was compiled with, which it has to
+    /// be run with.
+    pub natives: Vec<NativeSignature>,
+
+    /// The function to start running at, if the program has a `main` procedure.
+    pub main: Option<u32>,
+
+    /// What integer arithmetic does when it overflows, as in the program the bytecode was
+    /// compiled from.
+    pub overflow: Overflow,
+}
@@ -0,0 +1,793 @@
+use crate::{
+    bytecode::{Bytecode, Function, Instr, SwitchTable},
+    debug::LineTable,
+};
+use hir::{
+    BinOp, Block, Expr, ExprKind, IntTy, JumpTable, LogicalOp, MatchArm, ModuleId, Pat, PatKind,
+    Proc, Program, Stmt, StmtKind,
+};
+use interp::Value;
+use span::Span;
+use std::{collections::HashMap, rc::Rc};
+
+/// Compiles the procedures of a program to bytecode, sharing one constant pool.
+struct Compiler<'a> {
+    program: &'a Program,
+    constants: Vec<Value>,
+
+    /// Where each constant is in the pool, so equal constants are only stored once. Strings
+    /// are interned this way too, so every use of a string literal shares one allocation.
+    indices: HashMap<ConstantKey, u32>,
+}
+
+/// A constant's identity in the pool. Floats are compared by their bits, so `0.0` and `-0.0`
+/// stay apart and `nan` is found again.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Int(i64, IntTy),
+    Float(u64),
+    Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+    Void,
+}
+
+impl ConstantKey {
+    fn new(value: &Value) -> Self {
+        match value {
+            Value::Int(value, ty) => Self::Int(*value, *ty),
+            Value::Float(value) => Self::Float(value.to_bits()),
+            Value::Bool(value) => Self::Bool(*value),
+            Value::Char(value) => Self::Char(*value),
+            Value::Str(value) => Self::Str(Rc::clone(value)),
+            Value::Void => Self::Void,
+            Value::Array(_) | Value::Map(_) | Value::Proc(_) => {
+                unreachable!("constants are literals, found a {}", value.type_name())
+            }
+        }
+    }
+}
+
+/// Compiles the body of a single procedure.
+///
+/// Each local lives in the register numbered by its id, so reading one needs no instruction.
+/// Temporaries are allocated above the locals like a stack, and freed at the end of the
+/// statement or expression that needed them.
+struct FunctionCompiler<'a, 'b> {
+    compiler: &'b mut Compiler<'a>,
+    code: Vec<Instr>,
+    lines: LineTable,
+    tables: Vec<SwitchTable>,
+
+    /// The number of locals, whose registers come before every temporary.
+    locals: u32,
+
+    /// The first register not holding a live temporary.
+    free: u32,
+
+    /// The number of registers needed, the most ever in use at once.
+    registers: u32,
+
+    /// For every loop being compiled, innermost last, the `Jump`s out of it that need to be
+    /// patched with the loop's end.
+    breaks: Vec<Vec<usize>>,
+}
+
+impl Compiler<'_> {
+    fn constant(&mut self, value: Value) -> u32 {
+        let constants = &mut self.constants;
+        *self
+            .indices
+            .entry(ConstantKey::new(&value))
+            .or_insert_with(|| {
+                constants.push(value);
+                (constants.len() - 1) as u32
+            })
+    }
+
+    fn compile_proc(&mut self, proc: &Proc) -> Function {
+        debug_assert!(
+            proc.params
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.0 as usize == i),
+            "parameters are the first locals"
+        );
+
+        let name = self.program.qualified_name(proc.id);
+        let locals = proc.locals.len() as u32;
+        let mut function = FunctionCompiler {
+            compiler: self,
+            code: Vec::new(),
+            lines: LineTable::default(),
+            tables: Vec::new(),
+            locals,
+            free: locals,
+            registers: locals,
+            breaks: Vec::new(),
+        };
+
+        function.block(&proc.body);
+
+        // Lowering checks that non-void procedures always return, so only void procedures can
+        // reach the end of their body.
+        let end = proc.body.span.shrink_to_end();
+        function.ret_void(end);
+
+        Function {
+            name,
+            arity: proc.params.len() as u32,
+            registers: function.registers,
+            code: function.code,
+            tables: function.tables,
+            lines: function.lines,
+            span: proc.span,
+        }
+    }
+}
+
+impl FunctionCompiler<'_, '_> {
+    /// Append an instruction, returning its index.
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.lines.push(self.here(), span);
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    fn load_constant(&mut self, value: Value, dst: u32, span: Span) {
+        let index = self.compiler.constant(value);
+        self.emit(Instr::LoadConst { dst, index }, span);
+    }
+
+    fn ret_void(&mut self, span: Span) {
+        let src = self.temporary();
+        self.load_constant(Value::Void, src, span);
+        self.emit(Instr::Ret(src), span);
+    }
+
+    /// The index of the next instruction to be emitted.
+    fn here(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    /// Point a previously emitted jump at the next instruction to be emitted.
+    fn patch(&mut self, jump: usize) {
+        let here = self.here();
+        match &mut self.code[jump] {
+            Instr::Jump(target)
+            | Instr::JumpIfFalse { target, .. }
+            | Instr::JumpIfTrue { target, .. } => *target = here,
+            instr => unreachable!("only jumps are patched, found {instr:?}"),
+        }
+    }
+
+    fn temporary(&mut self) -> u32 {
+        self.free += 1;
+        self.registers = self.registers.max(self.free);
+        self.free - 1
+    }
+
+    fn block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let span = stmt.span;
+        let free = self.free;
+
+        match &stmt.kind {
+            StmtKind::Let { local, init } => self.expr_to(init, local.0),
+            // Assignments are common statements, so avoid making their value only to ignore it.
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Assign { local, value },
+                ..
+            }) => self.expr_to(value, local.0),
+            StmtKind::Expr(Expr {
+                kind:
+                    ExprKind::IndexAssign {
+                        array,
+                        index,
+                        op,
+                        value,
+                    },
+                span,
+                ..
+            }) => self.index_assign(array, index, *op, value, *span),
+            StmtKind::Expr(expr) => {
+                self.expr_reg(expr);
+            }
+            StmtKind::Ret(value) => match value {
+                Some(Expr {
+                    kind: ExprKind::Call { callee, args },
+                    span: call_span,
+                    ..
+                }) => {
+                    let args = self.args(args);
+                    // The result lands in the first argument's register, which has to exist
+                    // even without arguments.
+                    self.registers = self.registers.max(args + 1);
+                    self.emit(
+                        Instr::TailCall {
+                            function: callee.0,
+                            args,
+                        },
+                        *call_span,
+                    );
+                    self.emit(Instr::Ret(args), span);
+                }
+                Some(value) => {
+                    let src = self.expr_reg(value);
+                    self.emit(Instr::Ret(src), span);
+                }
+                None => self.ret_void(span),
+            },
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let to_else = self.jump_if_false(cond);
+                self.block(then_block);
+
+                match else_block {
+                    Some(else_block) => {
+                        let to_end = self.emit(Instr::Jump(0), span);
+                        self.patch(to_else);
+                        self.block(else_block);
+                        self.patch(to_end);
+                    }
+                    None => self.patch(to_else),
+                }
+            }
+            StmtKind::While { cond, body } => {
+                let start = self.here();
+                let exit = self.jump_if_false(cond);
+                self.loop_body(body, start, span);
+                self.patch(exit);
+            }
+            StmtKind::Loop(body) => {
+                let start = self.here();
+                self.loop_body(body, start, span);
+            }
+            StmtKind::Break => {
+                let jump = self.emit(Instr::Jump(0), span);
+                self.breaks
+                    .last_mut()
+                    .expect("breaks only appear in loops")
+                    .push(jump);
+            }
+            StmtKind::Block(block) => self.block(block),
+        }
+
+        // Temporaries only live until the end of the statement that needed them.
+        self.free = free;
+    }
+
+    /// Compile a condition followed by a jump, to be patched, taken when it's `false`.
+    fn jump_if_false(&mut self, cond: &Expr) -> usize {
+        let free = self.free;
+        let reg = self.expr_reg(cond);
+        self.free = free;
+        self.emit(
+            Instr::JumpIfFalse {
+                cond: reg,
+                target: 0,
+            },
+            cond.span,
+        )
+    }
+
+    /// Compile the body of a loop that jumps back to `start`, patching its breaks to after it.
+    fn loop_body(&mut self, body: &Block, start: u32, span: Span) {
+        self.breaks.push(Vec::new());
+        self.block(body);
+        self.emit(Instr::Jump(start), span);
+
+        for jump in self.breaks.pop().unwrap() {
+            self.patch(jump);
+        }
+    }
+
+    /// Compile an expression into a register, returning it. A local is read in its own
+    /// register, and anything else is computed into a new temporary.
+    ///
+    /// Reading a local in place is safe even if it's assigned later in the same expression,
+    /// since assignments are `void` and so never the sibling of an operand.
+    fn expr_reg(&mut self, expr: &Expr) -> u32 {
+        if let ExprKind::Local(local) = expr.kind {
+            return local.0;
+        }
+
+        let dst = self.temporary();
+        self.expr_to(expr, dst);
+        dst
+    }
+
+    /// Compile an expression, putting its value in a register.
+    fn expr_to(&mut self, expr: &Expr, dst: u32) {
+        let span = expr.span;
+        let free = self.free;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.load_constant(literal.into(), dst, span),
+            ExprKind::Local(local) => {
+                if local.0 != dst {
+                    self.emit(Instr::Move { dst, src: local.0 }, span);
+                }
+            }
+            ExprKind::Const(id) => {
+                let program = self.compiler.program;
+                self.load_constant((&program.constant(*id).value).into(), dst, span);
+            }
+            ExprKind::Call { callee, args } => {
+                let args = self.args(args);
+                self.emit(
+                    Instr::Call {
+                        function: callee.0,
+                        args,
+                        dst,
+                    },
+                    span,
+                );
+            }
+            ExprKind::Builtin { builtin, args } => {
+                let args = self.args(args);
+                let builtin = *builtin;
+                self.emit(Instr::Builtin { builtin, args, dst }, span);
+            }
+            ExprKind::Native { native, args } => {
+                let args = self.args(args);
+                let native = native.0;
+                self.emit(Instr::Native { native, args, dst }, span);
+            }
+            ExprKind::Unary { op, operand } => {
+                let src = self.expr_reg(operand);
+                self.emit(Instr::Unary { op: *op, dst, src }, span);
+            }
+            ExprKind::Binary { lhs, op, rhs } => {
+                let lhs = self.expr_reg(lhs);
+                // Constants are common right operands, like in `i + 1` and `n < 2`, so they're
+                // used straight from the constant pool.
+                let instr = match self.constant(rhs) {
+                    Some(value) => Instr::BinaryConst {
+                        op: *op,
+                        dst,
+                        lhs,
+                        rhs: self.compiler.constant(value),
+                    },
+                    None => Instr::Binary {
+                        op: *op,
+                        dst,
+                        lhs,
+                        rhs: self.expr_reg(rhs),
+                    },
+                };
+                self.emit(instr, span);
+            }
+            // The right operand could read a local that's also the destination, so it can't be
+            // written before the right operand is evaluated.
+            ExprKind::Logical { .. } if dst < self.locals => {
+                let result = self.temporary();
+                self.expr_to(expr, result);
+                self.emit(Instr::Move { dst, src: result }, span);
+            }
+            ExprKind::Logical { lhs, op, rhs } => {
+                // `a && b` is `if a { b } else { false }`, and `a || b` is
+                // `if a { true } else { b }`, so the left operand is the result when it short
+                // circuits.
+                self.expr_to(lhs, dst);
+                let short_circuit = match op {
+                    LogicalOp::And => Instr::JumpIfFalse {
+                        cond: dst,
+                        target: 0,
+                    },
+                    LogicalOp::Or => Instr::JumpIfTrue {
+                        cond: dst,
+                        target: 0,
+                    },
+                };
+                let short_circuit = self.emit(short_circuit, span);
+                self.expr_to(rhs, dst);
+                self.patch(short_circuit);
+            }
+            ExprKind::Assign { local, value } => {
+                self.expr_to(value, local.0);
+                self.load_constant(Value::Void, dst, span);
+            }
+            ExprKind::Array(elements) => {
+                let start = self.args(elements);
+                let len = elements.len() as u32;
+                self.emit(Instr::Array { dst, start, len }, span);
+            }
+            ExprKind::Map(entries) => {
+                let start = self.free;
+                for _ in 0..2 * entries.len() {
+                    self.temporary();
+                }
+                for (reg, (key, value)) in (start..).step_by(2).zip(entries) {
+                    self.expr_to(key, reg);
+                    self.expr_to(value, reg + 1);
+                }
+                let len = entries.len() as u32;
+                self.emit(Instr::Map { dst, start, len }, span);
+            }
+            ExprKind::Index { array, index } => {
+                let array = self.expr_reg(array);
+                let index = self.expr_reg(index);
+                self.emit(Instr::Index { dst, array, index }, span);
+            }
+            ExprKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                self.index_assign(array, index, *op, value, span);
+                self.load_constant(Value::Void, dst, span);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let slot = self.expr_reg(scrutinee);
+                match hir::jump_table(scrutinee.ty, arms) {
+                    Some(table) => self.switch(slot, &table, arms, dst, span),
+                    None => self.test_arms(slot, arms, dst),
+                }
+            }
+            ExprKind::Error => unreachable!("erroneous programs are never compiled"),
+        }
+
+        self.free = free;
+    }
+
+    /// The value of an expression that's a literal or a constant.
+    fn constant(&self, expr: &Expr) -> Option<Value> {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Some(literal.into()),
+            ExprKind::Const(id) => Some((&self.compiler.program.constant(*id).value).into()),
+            _ => None,
+        }
+    }
+
+    /// Compile the arguments of a call into consecutive new temporaries, returning the first.
+    fn args(&mut self, args: &[Expr]) -> u32 {
+        let start = self.free;
+        for _ in args {
+            self.temporary();
+        }
+        for (reg, arg) in (start..).zip(args) {
+            self.expr_to(arg, reg);
+        }
+        start
+    }
+
+    /// Compile an assignment to an element of an array.
+    fn index_assign(
+        &mut self,
+        array: &Expr,
+        index: &Expr,
+        op: Option<BinOp>,
+        value: &Expr,
+        span: Span,
+    ) {
+        let array = self.expr_reg(array);
+        let index = self.expr_reg(index);
+        let value = match op {
+            Some(op) => {
+                // `xs[i] op= value` reads the element before storing into it.
+                let element = self.temporary();
+                self.emit(
+                    Instr::Index {
+                        dst: element,
+                        array,
+                        index,
+                    },
+                    span,
+                );
+                let rhs = self.expr_reg(value);
+                self.emit(
+                    Instr::Binary {
+                        op,
+                        dst: element,
+                        lhs: element,
+                        rhs,
+                    },
+                    span,
+                );
+                element
+            }
+            None => self.expr_reg(value),
+        };
+        self.emit(
+            Instr::StoreIndex {
+                array,
+                index,
+                value,
+            },
+            span,
+        );
+    }
+
+    /// Compile a match that tests each arm's pattern in turn. Matches are checked to be
+    /// exhaustive, so the last arm is only reached when its pattern matches, and isn't tested.
+    fn test_arms(&mut self, slot: u32, arms: &[MatchArm], dst: u32) {
+        let (last, rest) = arms.split_last().expect("matches have at least one arm");
+        let mut to_end = Vec::with_capacity(rest.len());
+        for arm in rest {
+            let matched = self.temporary();
+            self.pattern(&arm.pat, slot, matched);
+            let next_arm = self.emit(
+                Instr::JumpIfFalse {
+                    cond: matched,
+                    target: 0,
+                },
+                arm.pat.span,
+            );
+            self.free = matched;
+
+            self.expr_to(&arm.body, dst);
+            to_end.push(self.emit(Instr::Jump(0), arm.span));
+            self.patch(next_arm);
+        }
+        self.expr_to(&last.body, dst);
+
+        for jump in to_end {
+            self.patch(jump);
+        }
+    }
+
+    /// Compile a match that jumps straight to its arm through a table, with the arms the table
+    /// can select one after another.
+    fn switch(&mut self, slot: u32, table: &JumpTable, arms: &[MatchArm], dst: u32, span: Span) {
+        // The table is filled in once the arms are compiled, which can add tables of their own.
+        let index = self.tables.len();
+        self.tables.push(SwitchTable {
+            low: table.low,
+            targets: Vec::new(),
+            default: 0,
+        });
+        self.emit(
+            Instr::Switch {
+                src: slot,
+                table: index as u32,
+            },
+            span,
+        );
+
+        let targets = table.targets();
+        let (&last, rest) = targets
+            .split_last()
+            .expect("tables select at least one arm");
+        let mut starts = vec![0; arms.len()];
+        let mut to_end = Vec::with_capacity(rest.len());
+        for &arm in rest {
+            starts[arm] = self.here();
+            self.expr_to(&arms[arm].body, dst);
+            to_end.push(self.emit(Instr::Jump(0), arms[arm].span));
+        }
+        starts[last] = self.here();
+        self.expr_to(&arms[last].body, dst);
+
+        for jump in to_end {
+            self.patch(jump);
+        }
+        let switch = &mut self.tables[index];
+        switch.targets = table.arms.iter().map(|&arm| starts[arm]).collect();
+        switch.default = starts[table.default];
+    }
+
+    /// Compile a test of whether the value in a register matches a pattern, putting a bool in
+    /// `dst`.
+    fn pattern(&mut self, pat: &Pat, slot: u32, dst: u32) {
+        let span = pat.span;
+
+        match &pat.kind {
+            PatKind::Wildcard => self.load_constant(Value::Bool(true), dst, span),
+            PatKind::Literal(literal) => {
+                let rhs = self.compiler.constant(literal.into());
+                self.emit(
+                    Instr::BinaryConst {
+                        op: BinOp::Eq,
+                        dst,
+                        lhs: slot,
+                        rhs,
+                    },
+                    span,
+                );
+            }
+            PatKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = self.compiler.constant(start.into());
+                let end = self.compiler.constant(end.into());
+                self.emit(
+                    Instr::InRange {
+                        dst,
+                        src: slot,
+                        start,
+                        end,
+                        inclusive: *inclusive,
+                    },
+                    span,
+                );
+            }
+            PatKind::Or(alternatives) => {
+                // Any matching alternative jumps straight to the end with `true` in `dst`.
+                let (last, rest) = alternatives
+                    .split_last()
+                    .expect("or patterns have at least two alternatives");
+                let mut to_end = Vec::with_capacity(rest.len());
+                for alternative in rest {
+                    self.pattern(alternative, slot, dst);
+                    to_end.push(self.emit(
+                        Instr::JumpIfTrue {
+                            cond: dst,
+                            target: 0,
+                        },
+                        alternative.span,
+                    ));
+                }
+
+                self.pattern(last, slot, dst);
+                for jump in to_end {
+                    self.patch(jump);
+                }
+            }
+            PatKind::Error => unreachable!("erroneous programs are never compiled"),
+        }
+    }
+}
+
+/// Compile every procedure of a program to bytecode. A program calling native procedures has to
+/// be run with the natives it was lowered with.
+pub fn compile(program: &Program) -> Bytecode {
+    let mut compiler = Compiler {
+        program,
+        constants: Vec::new(),
+        indices: HashMap::new(),
+    };
+
+    let functions = program
+        .procs
+        .iter()
+        .map(|proc| compiler.compile_proc(proc))
+        .collect();
+
+    let main = program
+        .procs
+        .iter()
+        .find(|proc| proc.module == ModuleId::ROOT && proc.name == "main")
+        .map(|proc| proc.id.0);
+
+    Bytecode {
+        functions,
+        constants: compiler.constants,
+        natives: program.natives.clone(),
+        main,
+        overflow: program.overflow,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_source(source: &str) -> anyhow::Result<Bytecode> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        Ok(compile(&hir::lower(&ast)?.program))
+    }
+
+    fn function<'b>(bytecode: &'b Bytecode, name: &str) -> &'b Function {
+        bytecode
+            .functions
+            .iter()
+            .find(|function| function.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_locals_are_read_in_place() -> anyhow::Result<()> {
+        let bytecode = compile_source("proc add(a: int, b: int) int { ret a + b; }")?;
+        let add = function(&bytecode, "add");
+
+        // The parameters are registers 0 and 1, and the sum a temporary above them.
+        assert_eq!(
+            add.code[..2],
+            [
+                Instr::Binary {
+                    op: BinOp::Add,
+                    dst: 2,
+                    lhs: 0,
+                    rhs: 1,
+                },
+                Instr::Ret(2),
+            ]
+        );
+        assert_eq!((add.arity, add.registers), (2, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_temporaries_are_freed_after_each_statement() -> anyhow::Result<()> {
+        let source = "proc f(a: int) int {
+                let x = (a + 1) * (a + 2);
+                let y = (a + 3) * (a + 4);
+                ret x + y;
+            }";
+        let bytecode = compile_source(source)?;
+        let f = function(&bytecode, "f");
+
+        // `a`, `x` and `y` are registers 0 to 2, and each `let` computes its operands in the
+        // same two temporaries, straight into the local.
+        let products: Vec<_> = f
+            .code
+            .iter()
+            .filter_map(|instr| match *instr {
+                Instr::Binary {
+                    op: BinOp::Mul,
+                    dst,
+                    lhs,
+                    rhs,
+                } => Some((dst, lhs, rhs)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(products, [(1, 3, 4), (2, 3, 4)]);
+        assert_eq!(f.registers, 5);
+        assert!(!f
+            .code
+            .iter()
+            .any(|instr| matches!(instr, Instr::Move { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arguments_are_consecutive_registers() -> anyhow::Result<()> {
+        let source = "proc g(a: int, b: int) int { ret a - b; }
+            proc f(n: int) int {
+                let m = g(n, n + 1) + 1;
+                ret m;
+            }";
+        let bytecode = compile_source(source)?;
+        let f = function(&bytecode, "f");
+        let one = bytecode
+            .constants
+            .iter()
+            .position(|constant| *constant == Value::int(1))
+            .unwrap() as u32;
+
+        // `n` and `m` are registers 0 and 1. The call's result is a temporary, and its
+        // arguments the two registers above it.
+        assert_eq!(
+            f.code[..4],
+            [
+                Instr::Move { dst: 3, src: 0 },
+                Instr::BinaryConst {
+                    op: BinOp::Add,
+                    dst: 4,
+                    lhs: 0,
+                    rhs: one,
+                },
+                Instr::Call {
+                    function: 0,
+                    args: 3,
+                    dst: 2,
+                },
+                Instr::BinaryConst {
+                    op: BinOp::Add,
+                    dst: 1,
+                    lhs: 2,
+                    rhs: one,
+                },
+            ]
+        );
+        assert_eq!(f.registers, 5);
+
+        Ok(())
+    }
+}
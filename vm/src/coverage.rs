@@ -0,0 +1,93 @@
+//! Counters collected while running bytecode with [`Machine::run_covered`], for finding the
+//! code a program's runs or tests never reach.
+//!
+//! [`Machine::run_covered`]: crate::Machine::run_covered
+
+use crate::bytecode::{Bytecode, Instr};
+use span::Span;
+
+/// How often each instruction of some bytecode ran, and which way each conditional jump went.
+/// One coverage can be kept over several runs, such as every test of a program.
+#[derive(Debug, Clone)]
+pub struct Coverage<'a> {
+    bytecode: &'a Bytecode,
+
+    /// How many times each instruction ran, by function then offset.
+    counts: Vec<Vec<u64>>,
+
+    /// How many times each instruction jumped, by function then offset. Only conditional jumps
+    /// are counted.
+    jumps: Vec<Vec<u64>>,
+}
+
+/// Which way a conditional jump went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    /// The span of the condition the jump was compiled from.
+    pub span: Span,
+
+    /// How many times it jumped.
+    pub taken: u64,
+
+    /// How many times it fell through to the next instruction.
+    pub not_taken: u64,
+}
+
+impl<'a> Coverage<'a> {
+    pub fn new(bytecode: &'a Bytecode) -> Self {
+        let zeros = || {
+            bytecode
+                .functions
+                .iter()
+                .map(|function| vec![0; function.code.len()])
+                .collect()
+        };
+        Self {
+            bytecode,
+            counts: zeros(),
+            jumps: zeros(),
+        }
+    }
+
+    /// Record that the instruction at an offset of a function ran.
+    pub(crate) fn record(&mut self, function: u32, offset: usize) {
+        self.counts[function as usize][offset] += 1;
+    }
+
+    /// Record that the conditional jump at an offset of a function jumped.
+    pub(crate) fn record_jump(&mut self, function: u32, offset: usize) {
+        self.jumps[function as usize][offset] += 1;
+    }
+
+    /// The span every instruction was compiled from, with how many times it ran.
+    pub fn instrs(&self) -> impl Iterator<Item = (Span, u64)> + '_ {
+        self.bytecode
+            .functions
+            .iter()
+            .zip(&self.counts)
+            .flat_map(|(function, counts)| {
+                counts
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, &count)| (function.lines.span_at(offset), count))
+            })
+    }
+
+    /// Every conditional jump, in the order they're in the bytecode.
+    pub fn branches(&self) -> Vec<Branch> {
+        let mut branches = Vec::new();
+        for (i, function) in self.bytecode.functions.iter().enumerate() {
+            for (offset, instr) in function.code.iter().enumerate() {
+                if let Instr::JumpIfFalse { .. } | Instr::JumpIfTrue { .. } = instr {
+                    let (count, taken) = (self.counts[i][offset], self.jumps[i][offset]);
+                    branches.push(Branch {
+                        span: function.lines.span_at(offset),
+                        taken,
+                        not_taken: count - taken,
+                    });
+                }
+            }
+        }
+        branches
+    }
+}
@@ -0,0 +1,218 @@
+//! Debug info mapping bytecode back to the source it was compiled from, for runtime errors, the
+//! disassembler and tracing.
+
+use crate::bytecode::{Bytecode, Instr};
+use span::Span;
+use std::fmt::Write;
+
+/// The span of the source each instruction of a function was compiled from.
+///
+/// Most expressions compile to several instructions from the same span, so the table is stored
+/// as runs: each entry holds the offset of the first instruction of a run and their span. The
+/// runs are expanded to the span of every instruction too, so the VM can find an
+/// instruction's span without searching.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    runs: Vec<(u32, Span)>,
+    spans: Vec<Span>,
+}
+
+impl LineTable {
+    /// Make the table of a function with `len` instructions from its runs, or return `None` if
+    /// their offsets aren't increasing from 0 within the function.
+    pub fn from_runs(runs: Vec<(u32, Span)>, len: usize) -> Option<Self> {
+        let mut offsets = runs.iter().map(|&(offset, _)| offset as usize);
+        let valid = offsets.next().map_or(len == 0, |first| {
+            first == 0
+                && len > 0
+                && offsets
+                    .try_fold(first, |previous, offset| {
+                        (previous < offset && offset < len).then_some(offset)
+                    })
+                    .is_some()
+        });
+        if !valid {
+            return None;
+        }
+
+        let ends = runs
+            .iter()
+            .skip(1)
+            .map(|&(offset, _)| offset as usize)
+            .chain([len]);
+        let spans = runs
+            .iter()
+            .zip(ends)
+            .flat_map(|(&(start, span), end)| std::iter::repeat_n(span, end - start as usize))
+            .collect();
+        Some(Self { runs, spans })
+    }
+
+    /// The runs of instructions, in order.
+    pub fn runs(&self) -> &[(u32, Span)] {
+        &self.runs
+    }
+
+    /// Record the span of the instruction at `offset`, which follows every instruction
+    /// recorded so far.
+    pub fn push(&mut self, offset: u32, span: Span) {
+        debug_assert_eq!(
+            offset as usize,
+            self.spans.len(),
+            "instructions are pushed in order"
+        );
+        if self.runs.last().map(|&(_, last)| last) != Some(span) {
+            self.runs.push((offset, span));
+        }
+        self.spans.push(span);
+    }
+
+    /// The span of the instruction at an offset.
+    #[inline]
+    pub fn span_at(&self, offset: usize) -> Span {
+        self.spans[offset]
+    }
+}
+
+/// Finds the line and column of offsets in a source.
+pub struct Lines<'a> {
+    source: &'a str,
+
+    /// The offset every line starts at.
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { source, starts }
+    }
+
+    /// The line and column, both counted from 1, of an offset. Columns count chars.
+    pub fn locate(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+        let column = self
+            .source
+            .get(start..offset)
+            .map_or(0, |before| before.chars().count());
+        (line + 1, column + 1)
+    }
+}
+
+/// List every instruction of some bytecode, with the line and column each run of instructions
+/// was compiled from, after the size of the constant pool.
+pub fn disassemble(bytecode: &Bytecode, source: &str) -> String {
+    let lines = Lines::new(source);
+    let mut out = String::new();
+
+    let strings = bytecode
+        .constants
+        .iter()
+        .filter_map(|constant| match constant {
+            interp::Value::Str(s) => Some(s.len()),
+            _ => None,
+        });
+    let (count, bytes) = strings.fold((0, 0), |(count, bytes), len| (count + 1, bytes + len));
+    writeln!(
+        out,
+        "constant pool: {} constants, {count} strings of {bytes} bytes\n",
+        bytecode.constants.len()
+    )
+    .unwrap();
+
+    for (i, function) in bytecode.functions.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let (line, column) = lines.locate(function.span.start);
+        let main = if bytecode.main == Some(i as u32) {
+            ", main"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "fn {i} `{}` at {line}:{column}: {} params, {} registers{main}",
+            function.name, function.arity, function.registers
+        )
+        .unwrap();
+
+        let mut runs = function.lines.runs().iter().peekable();
+        for (offset, instr) in function.code.iter().enumerate() {
+            let location = match runs.next_if(|&&(start, _)| start as usize == offset) {
+                Some((_, span)) => {
+                    let (line, column) = lines.locate(span.start);
+                    format!("{line}:{column}")
+                }
+                None => String::new(),
+            };
+            write!(out, "{offset:>6}  {location:<8}{instr:?}").unwrap();
+
+            // Show what operands refer to.
+            match *instr {
+                Instr::LoadConst { index, .. } | Instr::BinaryConst { rhs: index, .. } => {
+                    match &bytecode.constants[index as usize] {
+                        interp::Value::Str(s) => write!(out, "  ; {s:?}").unwrap(),
+                        value => write!(out, "  ; {value}").unwrap(),
+                    }
+                }
+                Instr::Call { function, .. } | Instr::TailCall { function, .. } => {
+                    write!(out, "  ; {}", bytecode.functions[function as usize].name).unwrap();
+                }
+                Instr::Builtin { builtin, .. } => write!(out, "  ; {}", builtin.name()).unwrap(),
+                Instr::Native { native, .. } => {
+                    let name = &bytecode.natives[native as usize].name;
+                    write!(out, "  ; {name}").unwrap();
+                }
+                Instr::Switch { table, .. } => {
+                    let table = &function.tables[table as usize];
+                    let (low, targets) = (table.low, &table.targets);
+                    write!(out, "  ; from {low} to {targets:?}, else {}", table.default).unwrap();
+                }
+                _ => {}
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_table_runs() {
+        let (a, b) = (Span::from(0..4), Span::from(5..9));
+        let mut table = LineTable::default();
+        for (offset, span) in [a, a, b, b, b, a].into_iter().enumerate() {
+            table.push(offset as u32, span);
+        }
+
+        assert_eq!(table.runs(), [(0, a), (2, b), (5, a)]);
+        assert_eq!(
+            (0..6)
+                .map(|offset| table.span_at(offset))
+                .collect::<Vec<_>>(),
+            [a, a, b, b, b, a]
+        );
+        assert_eq!(LineTable::from_runs(table.runs().to_vec(), 6), Some(table));
+        assert_eq!(LineTable::from_runs(vec![(1, a)], 2), None);
+        assert_eq!(LineTable::from_runs(vec![(0, a), (0, b)], 2), None);
+        assert_eq!(LineTable::from_runs(vec![(0, a), (2, b)], 2), None);
+        assert_eq!(LineTable::from_runs(Vec::new(), 1), None);
+    }
+
+    #[test]
+    fn test_locate_lines() {
+        let lines = Lines::new("let x;\nlet ü = 'y';\n");
+        assert_eq!(lines.locate(0), (1, 1));
+        assert_eq!(lines.locate(7), (2, 1));
+        assert_eq!(lines.locate(14), (2, 7));
+        assert_eq!(lines.locate(21), (3, 1));
+    }
+}
@@ -0,0 +1,26 @@
+use crate::file::VERSION;
+use miette::Diagnostic;
+use thiserror::Error;
+
+/// Errors reading a bytecode file.
+#[derive(Debug, Clone, Error, Diagnostic)]
+pub enum FileError {
+    #[diagnostic(code(vm::not_bytecode))]
+    #[error("Not a matrix bytecode file")]
+    NotBytecode,
+
+    #[diagnostic(
+        code(vm::unsupported_version),
+        help("build the program again with this version of matrix")
+    )]
+    #[error("Unsupported bytecode version {0}, expected version {VERSION}")]
+    UnsupportedVersion(u16),
+
+    #[diagnostic(code(vm::truncated))]
+    #[error("The bytecode file is truncated")]
+    Truncated,
+
+    #[diagnostic(code(vm::invalid_bytecode))]
+    #[error("Invalid bytecode: {0}")]
+    Invalid(String),
+}
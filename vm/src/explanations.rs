@@ -0,0 +1,56 @@
+//! Extended explanations of the errors reading bytecode files, which `mtxc explain` prints.
+//! Running bytecode stops with the interpreter's errors.
+
+/// Every error's code and explanation. Short codes number them in this order, so new ones go
+/// at the end.
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "vm::not_bytecode",
+        r#"A file that was read as bytecode doesn't start like a matrix bytecode file.
+
+Bytecode files are made by `mtxc build`, and start with a header that identifies them. This
+error means the file is something else, or was damaged. Build the program again with
+`mtxc build`."#,
+    ),
+    (
+        "vm::unsupported_version",
+        r#"A bytecode file was built by a version of matrix with a different bytecode format.
+
+The format changes between versions of matrix, and files are only read by the version that
+matches theirs. Build the program again with this version of `mtxc`."#,
+    ),
+    (
+        "vm::truncated",
+        r#"A bytecode file ends before all of its contents.
+
+This usually means the file was only partly copied or written. Build the program again with
+`mtxc build`."#,
+    ),
+    (
+        "vm::invalid_bytecode",
+        r#"A bytecode file has the right header, but its contents aren't valid.
+
+Bytecode files are checked before they're run, such as for jumps and calls to places that
+don't exist, so a damaged or hand-edited file can't crash the VM. Build the program again with
+`mtxc build`."#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_error_is_explained() {
+        let mut codes: Vec<_> = include_str!("diagnostics.rs")
+            .split("code(")
+            .skip(1)
+            .map(|rest| &rest[..rest.find(')').unwrap()])
+            .filter(|code| !code.is_empty())
+            .collect();
+        let mut explained: Vec<_> = EXPLANATIONS.iter().map(|(code, _)| *code).collect();
+        codes.sort_unstable();
+        explained.sort_unstable();
+        assert_eq!(codes, explained);
+    }
+}
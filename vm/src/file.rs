@@ -0,0 +1,1039 @@
+//! The `.mxc` file format, which stores compiled bytecode so a program can be run many times
+//! without compiling it again.
+//!
+//! All integers are little-endian, and strings are a `u32` length followed by UTF-8. A file is:
+//!
+//! - the magic bytes `MXC\0` and a `u16` format version,
+//! - the constant pool: a `u32` count, then each constant as a tag byte and its value, with an
+//!   int's type as a byte before it,
+//! - the code: a `u32` count of functions, then each function's arity, number of registers,
+//!   instructions and switch tables, the index of `main` as a flag byte and a `u32`, and the
+//!   overflow mode as a byte. A switch table is its first int as a `u64`, then a `u32` count of
+//!   targets, each target and the default target,
+//! - the natives the program calls: a `u32` count, then each native's name, a `u32` count of
+//!   parameters, and the type of each parameter and of its result. A type is a tag byte, with
+//!   an int's type as a byte after it, and an array's element type or a map's key and value
+//!   types after theirs,
+//! - the debug tables: the name and text of the source, then each function's name, the span of
+//!   its definition and its line table, as a `u32` count of runs, then the offset of each run's
+//!   first instruction and the span they were compiled from.
+//!
+//! Reading a file checks that every index in it is in bounds, so a corrupt file is reported
+//! rather than crashing the VM.
+
+use crate::{
+    bytecode::{Bytecode, Function, Instr, SwitchTable},
+    debug::LineTable,
+    diagnostics::FileError,
+};
+use hir::{BinOp, Builtin, IntTy, NativeSignature, Overflow, Ty, UnOp};
+use interp::Value;
+use span::Span;
+
+pub const MAGIC: [u8; 4] = *b"MXC\0";
+
+/// The version of the format, which changes whenever the layout or the instruction set does.
+pub const VERSION: u16 = 13;
+
+/// How deeply types in a file can nest, so a corrupt one can't overflow the stack reading them.
+const MAX_TY_DEPTH: usize = 64;
+
+/// Operators are stored as their index in these tables.
+const UNARY_OPS: [UnOp; 11] = {
+    use IntTy::*;
+
+    [
+        UnOp::Neg,
+        UnOp::Not,
+        UnOp::BitNot,
+        UnOp::Cast(I8),
+        UnOp::Cast(I16),
+        UnOp::Cast(I32),
+        UnOp::Cast(I64),
+        UnOp::Cast(U8),
+        UnOp::Cast(U16),
+        UnOp::Cast(U32),
+        UnOp::Cast(U64),
+    ]
+};
+const BINARY_OPS: [BinOp; 15] = {
+    use BinOp::*;
+
+    [
+        Add, Sub, Mul, Div, Rem, BitAnd, BitOr, Shl, Shr, Eq, Ne, Lt, Le, Gt, Ge,
+    ]
+};
+
+/// Compiled bytecode, along with the source it was compiled from, for reporting errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytecodeFile {
+    pub bytecode: Bytecode,
+    pub source_name: String,
+    pub source: String,
+}
+
+/// Returns whether some bytes start like a bytecode file, rather than source code.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// The code of an operator, builtin or mode: its index in a table of them.
+fn code<T: PartialEq>(table: &[T], item: T) -> u8 {
+    table.iter().position(|found| *found == item).unwrap() as u8
+}
+
+fn lookup<T: Copy>(table: &[T], code: u8, kind: &str) -> Result<T, FileError> {
+    table
+        .get(code as usize)
+        .copied()
+        .ok_or_else(|| invalid(format!("unknown {kind} {code}")))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(u32::try_from(len).expect("bytecode tables fit in a `u32`"));
+    }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.bytes.extend(s.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.len(span.start);
+        self.len(span.end);
+    }
+
+    fn constant(&mut self, value: &Value) {
+        match value {
+            Value::Int(value, ty) => {
+                self.u8(0);
+                self.u8(code(&IntTy::ALL, *ty));
+                self.u64(*value as u64);
+            }
+            Value::Float(value) => {
+                self.u8(1);
+                self.u64(value.to_bits());
+            }
+            Value::Bool(value) => {
+                self.u8(2);
+                self.u8(u8::from(*value));
+            }
+            Value::Char(value) => {
+                self.u8(3);
+                self.u32(u32::from(*value));
+            }
+            Value::Str(value) => {
+                self.u8(4);
+                self.str(value);
+            }
+            Value::Void => self.u8(5),
+            Value::Array(_) | Value::Map(_) | Value::Proc(_) => {
+                unreachable!("constants are literals, found a {}", value.type_name())
+            }
+        }
+    }
+
+    fn instr(&mut self, instr: Instr) {
+        match instr {
+            Instr::LoadConst { dst, index } => {
+                self.u8(0);
+                self.u32(dst);
+                self.u32(index);
+            }
+            Instr::Move { dst, src } => {
+                self.u8(1);
+                self.u32(dst);
+                self.u32(src);
+            }
+            Instr::Unary { op, dst, src } => {
+                self.u8(2);
+                self.u8(code(&UNARY_OPS, op));
+                self.u32(dst);
+                self.u32(src);
+            }
+            Instr::Binary { op, dst, lhs, rhs } => {
+                self.u8(3);
+                self.u8(code(&BINARY_OPS, op));
+                self.u32(dst);
+                self.u32(lhs);
+                self.u32(rhs);
+            }
+            Instr::InRange {
+                dst,
+                src,
+                start,
+                end,
+                inclusive,
+            } => {
+                self.u8(4);
+                self.u32(dst);
+                self.u32(src);
+                self.u32(start);
+                self.u32(end);
+                self.u8(u8::from(inclusive));
+            }
+            Instr::Jump(target) => {
+                self.u8(5);
+                self.u32(target);
+            }
+            Instr::JumpIfFalse { cond, target } => {
+                self.u8(6);
+                self.u32(cond);
+                self.u32(target);
+            }
+            Instr::JumpIfTrue { cond, target } => {
+                self.u8(7);
+                self.u32(cond);
+                self.u32(target);
+            }
+            Instr::Call {
+                function,
+                args,
+                dst,
+            } => {
+                self.u8(8);
+                self.u32(function);
+                self.u32(args);
+                self.u32(dst);
+            }
+            Instr::TailCall { function, args } => {
+                self.u8(9);
+                self.u32(function);
+                self.u32(args);
+            }
+            Instr::Builtin { builtin, args, dst } => {
+                self.u8(10);
+                self.u8(code(&Builtin::ALL, builtin));
+                self.u32(args);
+                self.u32(dst);
+            }
+            Instr::Ret(src) => {
+                self.u8(11);
+                self.u32(src);
+            }
+            Instr::Array { dst, start, len } => {
+                self.u8(12);
+                self.u32(dst);
+                self.u32(start);
+                self.u32(len);
+            }
+            Instr::Index { dst, array, index } => {
+                self.u8(13);
+                self.u32(dst);
+                self.u32(array);
+                self.u32(index);
+            }
+            Instr::StoreIndex {
+                array,
+                index,
+                value,
+            } => {
+                self.u8(14);
+                self.u32(array);
+                self.u32(index);
+                self.u32(value);
+            }
+            Instr::BinaryConst { op, dst, lhs, rhs } => {
+                self.u8(15);
+                self.u8(code(&BINARY_OPS, op));
+                self.u32(dst);
+                self.u32(lhs);
+                self.u32(rhs);
+            }
+            Instr::Map { dst, start, len } => {
+                self.u8(16);
+                self.u32(dst);
+                self.u32(start);
+                self.u32(len);
+            }
+            Instr::Switch { src, table } => {
+                self.u8(17);
+                self.u32(src);
+                self.u32(table);
+            }
+            Instr::Native { native, args, dst } => {
+                self.u8(18);
+                self.u32(native);
+                self.u32(args);
+                self.u32(dst);
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: Ty) {
+        match ty {
+            Ty::Int(ty) => {
+                self.u8(0);
+                self.u8(code(&IntTy::ALL, ty));
+            }
+            Ty::Float => self.u8(1),
+            Ty::Bool => self.u8(2),
+            Ty::Char => self.u8(3),
+            Ty::Str => self.u8(4),
+            Ty::Void => self.u8(5),
+            Ty::Array(element) => {
+                self.u8(6);
+                self.ty(*element);
+            }
+            Ty::Map(key, value) => {
+                self.u8(7);
+                self.ty(*key);
+                self.ty(*value);
+            }
+            Ty::Error => unreachable!("erroneous programs are never compiled"),
+        }
+    }
+
+    fn native(&mut self, native: &NativeSignature) {
+        self.str(&native.name);
+        self.len(native.params.len());
+        for &param in &native.params {
+            self.ty(param);
+        }
+        self.ty(native.ret_ty);
+    }
+
+    fn switch_table(&mut self, table: &SwitchTable) {
+        self.u64(table.low as u64);
+        self.len(table.targets.len());
+        for &target in &table.targets {
+            self.u32(target);
+        }
+        self.u32(table.default);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], FileError> {
+        let (taken, rest) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or(FileError::Truncated)?;
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, FileError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FileError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, FileError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, FileError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn len(&mut self) -> Result<usize, FileError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bool(&mut self) -> Result<bool, FileError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(invalid(format!("{byte} isn't a bool"))),
+        }
+    }
+
+    fn str(&mut self) -> Result<&'a str, FileError> {
+        let len = self.len()?;
+        if self.bytes.len() < len {
+            return Err(FileError::Truncated);
+        }
+
+        let (s, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        std::str::from_utf8(s).map_err(|error| invalid(format!("string isn't UTF-8: {error}")))
+    }
+
+    fn span(&mut self) -> Result<Span, FileError> {
+        let (start, end) = (self.len()?, self.len()?);
+        if start > end {
+            return Err(invalid(format!("span {start}..{end} is backwards")));
+        }
+        Ok(Span::from(start..end))
+    }
+
+    /// Read a table of `len` entries. Lengths aren't trusted for preallocating, since the file
+    /// could be corrupt.
+    fn table<T>(
+        &mut self,
+        len: usize,
+        mut entry: impl FnMut(&mut Self) -> Result<T, FileError>,
+    ) -> Result<Vec<T>, FileError> {
+        (0..len).map(|_| entry(self)).collect()
+    }
+
+    fn constant(&mut self) -> Result<Value, FileError> {
+        Ok(match self.u8()? {
+            0 => {
+                let ty = lookup(&IntTy::ALL, self.u8()?, "int type")?;
+                let value = self.u64()? as i64;
+                if ty.wrap(value.into()) != value {
+                    return Err(invalid(format!("{value} isn't a `{ty}`")));
+                }
+                Value::Int(value, ty)
+            }
+            1 => Value::Float(f64::from_bits(self.u64()?)),
+            2 => Value::Bool(self.bool()?),
+            3 => {
+                let value = self.u32()?;
+                Value::Char(
+                    char::from_u32(value)
+                        .ok_or_else(|| invalid(format!("{value:#x} isn't a char")))?,
+                )
+            }
+            4 => Value::Str(self.str()?.into()),
+            5 => Value::Void,
+            tag => return Err(invalid(format!("unknown constant tag {tag}"))),
+        })
+    }
+
+    fn instr(&mut self) -> Result<Instr, FileError> {
+        Ok(match self.u8()? {
+            0 => Instr::LoadConst {
+                dst: self.u32()?,
+                index: self.u32()?,
+            },
+            1 => Instr::Move {
+                dst: self.u32()?,
+                src: self.u32()?,
+            },
+            2 => Instr::Unary {
+                op: lookup(&UNARY_OPS, self.u8()?, "unary operator")?,
+                dst: self.u32()?,
+                src: self.u32()?,
+            },
+            3 => Instr::Binary {
+                op: lookup(&BINARY_OPS, self.u8()?, "binary operator")?,
+                dst: self.u32()?,
+                lhs: self.u32()?,
+                rhs: self.u32()?,
+            },
+            4 => Instr::InRange {
+                dst: self.u32()?,
+                src: self.u32()?,
+                start: self.u32()?,
+                end: self.u32()?,
+                inclusive: self.bool()?,
+            },
+            5 => Instr::Jump(self.u32()?),
+            6 => Instr::JumpIfFalse {
+                cond: self.u32()?,
+                target: self.u32()?,
+            },
+            7 => Instr::JumpIfTrue {
+                cond: self.u32()?,
+                target: self.u32()?,
+            },
+            8 => Instr::Call {
+                function: self.u32()?,
+                args: self.u32()?,
+                dst: self.u32()?,
+            },
+            9 => Instr::TailCall {
+                function: self.u32()?,
+                args: self.u32()?,
+            },
+            10 => Instr::Builtin {
+                builtin: lookup(&Builtin::ALL, self.u8()?, "builtin")?,
+                args: self.u32()?,
+                dst: self.u32()?,
+            },
+            11 => Instr::Ret(self.u32()?),
+            12 => Instr::Array {
+                dst: self.u32()?,
+                start: self.u32()?,
+                len: self.u32()?,
+            },
+            13 => Instr::Index {
+                dst: self.u32()?,
+                array: self.u32()?,
+                index: self.u32()?,
+            },
+            14 => Instr::StoreIndex {
+                array: self.u32()?,
+                index: self.u32()?,
+                value: self.u32()?,
+            },
+            15 => Instr::BinaryConst {
+                op: lookup(&BINARY_OPS, self.u8()?, "binary operator")?,
+                dst: self.u32()?,
+                lhs: self.u32()?,
+                rhs: self.u32()?,
+            },
+            16 => Instr::Map {
+                dst: self.u32()?,
+                start: self.u32()?,
+                len: self.u32()?,
+            },
+            17 => Instr::Switch {
+                src: self.u32()?,
+                table: self.u32()?,
+            },
+            18 => Instr::Native {
+                native: self.u32()?,
+                args: self.u32()?,
+                dst: self.u32()?,
+            },
+            opcode => return Err(invalid(format!("unknown opcode {opcode}"))),
+        })
+    }
+
+    fn ty(&mut self, depth: usize) -> Result<Ty, FileError> {
+        if depth == MAX_TY_DEPTH {
+            return Err(invalid(format!("types nest more than {MAX_TY_DEPTH} deep")));
+        }
+        Ok(match self.u8()? {
+            0 => Ty::Int(lookup(&IntTy::ALL, self.u8()?, "int type")?),
+            1 => Ty::Float,
+            2 => Ty::Bool,
+            3 => Ty::Char,
+            4 => Ty::Str,
+            5 => Ty::Void,
+            6 => Ty::array_of(self.ty(depth + 1)?),
+            7 => {
+                let key = self.ty(depth + 1)?;
+                Ty::map_of(key, self.ty(depth + 1)?)
+            }
+            tag => return Err(invalid(format!("unknown type tag {tag}"))),
+        })
+    }
+
+    fn native(&mut self) -> Result<NativeSignature, FileError> {
+        let name = self.str()?.to_owned();
+        let len = self.len()?;
+        Ok(NativeSignature {
+            name,
+            params: self.table(len, |reader| reader.ty(0))?,
+            ret_ty: self.ty(0)?,
+        })
+    }
+
+    fn switch_table(&mut self) -> Result<SwitchTable, FileError> {
+        let low = self.u64()? as i64;
+        let len = self.len()?;
+        Ok(SwitchTable {
+            low,
+            targets: self.table(len, Reader::u32)?,
+            default: self.u32()?,
+        })
+    }
+}
+
+fn invalid(message: String) -> FileError {
+    FileError::Invalid(message)
+}
+
+/// Check that every index in the bytecode is in bounds.
+fn validate(bytecode: &Bytecode) -> Result<(), FileError> {
+    let constants = bytecode.constants.len();
+    let functions = &bytecode.functions;
+    if bytecode
+        .main
+        .is_some_and(|main| main as usize >= functions.len())
+    {
+        return Err(invalid("`main` isn't a function".to_owned()));
+    }
+
+    for function in functions {
+        let name = &function.name;
+        if function.arity > function.registers {
+            return Err(invalid(format!(
+                "`{name}` has fewer registers than parameters"
+            )));
+        }
+        // Functions can't run past their end.
+        if !matches!(function.code.last(), Some(Instr::Ret(_))) {
+            return Err(invalid(format!("`{name}` doesn't end by returning")));
+        }
+
+        let registers = function.registers as usize;
+        let reg = |reg: u32| (reg as usize) < registers;
+        // A run of `len` registers from `start`, which can be empty at the end of the frame.
+        let run = |start: u32, len: usize| start as usize + len <= registers;
+        let constant = |index: u32| (index as usize) < constants;
+        let target = |target: u32| (target as usize) < function.code.len();
+        let arity = |callee: u32| {
+            functions
+                .get(callee as usize)
+                .map(|callee| callee.arity as usize)
+        };
+        let native_arity = |native: u32| {
+            bytecode
+                .natives
+                .get(native as usize)
+                .map(|native| native.params.len())
+        };
+
+        for (i, &instr) in function.code.iter().enumerate() {
+            let in_bounds = match instr {
+                Instr::LoadConst { dst, index } => reg(dst) && constant(index),
+                Instr::Move { dst, src } | Instr::Unary { dst, src, .. } => reg(dst) && reg(src),
+                Instr::Binary { dst, lhs, rhs, .. } => reg(dst) && reg(lhs) && reg(rhs),
+                Instr::BinaryConst { dst, lhs, rhs, .. } => reg(dst) && reg(lhs) && constant(rhs),
+                Instr::InRange {
+                    dst,
+                    src,
+                    start,
+                    end,
+                    ..
+                } => reg(dst) && reg(src) && constant(start) && constant(end),
+                Instr::Jump(to) => target(to),
+                Instr::Switch { src, table } => {
+                    reg(src)
+                        && function.tables.get(table as usize).is_some_and(|table| {
+                            target(table.default) && table.targets.iter().all(|&to| target(to))
+                        })
+                }
+                Instr::JumpIfFalse { cond, target: to }
+                | Instr::JumpIfTrue { cond, target: to } => reg(cond) && target(to),
+                Instr::Call {
+                    function: callee,
+                    args,
+                    dst,
+                } => reg(dst) && arity(callee).is_some_and(|arity| run(args, arity)),
+                // Tail calls from the outermost frame return like calls.
+                Instr::TailCall {
+                    function: callee,
+                    args,
+                } => {
+                    reg(args)
+                        && arity(callee).is_some_and(|arity| run(args, arity))
+                        && function.code.get(i + 1) == Some(&Instr::Ret(args))
+                }
+                Instr::Builtin { builtin, args, dst } => {
+                    reg(dst) && run(args, builtin.params().len())
+                }
+                Instr::Native { native, args, dst } => {
+                    reg(dst) && native_arity(native).is_some_and(|arity| run(args, arity))
+                }
+                Instr::Ret(src) => reg(src),
+                Instr::Array { dst, start, len } => reg(dst) && run(start, len as usize),
+                Instr::Map { dst, start, len } => reg(dst) && run(start, 2 * len as usize),
+                Instr::Index { dst, array, index } => reg(dst) && reg(array) && reg(index),
+                Instr::StoreIndex {
+                    array,
+                    index,
+                    value,
+                } => reg(array) && reg(index) && reg(value),
+            };
+            if !in_bounds {
+                return Err(invalid(format!("`{instr:?}` in `{name}` is out of bounds")));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl BytecodeFile {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.bytes.extend(MAGIC);
+        writer.u16(VERSION);
+
+        let bytecode = &self.bytecode;
+        writer.len(bytecode.constants.len());
+        for constant in &bytecode.constants {
+            writer.constant(constant);
+        }
+
+        writer.len(bytecode.functions.len());
+        for function in &bytecode.functions {
+            writer.u32(function.arity);
+            writer.u32(function.registers);
+            writer.len(function.code.len());
+            for &instr in &function.code {
+                writer.instr(instr);
+            }
+            writer.len(function.tables.len());
+            for table in &function.tables {
+                writer.switch_table(table);
+            }
+        }
+        writer.u8(u8::from(bytecode.main.is_some()));
+        writer.u32(bytecode.main.unwrap_or(0));
+        writer.u8(code(&Overflow::ALL, bytecode.overflow));
+        writer.len(bytecode.natives.len());
+        for native in &bytecode.natives {
+            writer.native(native);
+        }
+
+        writer.str(&self.source_name);
+        writer.str(&self.source);
+        for function in &bytecode.functions {
+            writer.str(&function.name);
+            writer.span(function.span);
+            let runs = function.lines.runs();
+            writer.len(runs.len());
+            for &(offset, span) in runs {
+                writer.u32(offset);
+                writer.span(span);
+            }
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        if !is_bytecode(bytes) {
+            return Err(FileError::NotBytecode);
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes[MAGIC.len()..],
+        };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(FileError::UnsupportedVersion(version));
+        }
+
+        let len = reader.len()?;
+        let constants = reader.table(len, Reader::constant)?;
+
+        let len = reader.len()?;
+        let mut functions = reader.table(len, |reader| {
+            let (arity, registers) = (reader.u32()?, reader.u32()?);
+            let len = reader.len()?;
+            let code = reader.table(len, Reader::instr)?;
+            let len = reader.len()?;
+            Ok(Function {
+                name: String::new(),
+                arity,
+                registers,
+                code,
+                tables: reader.table(len, Reader::switch_table)?,
+                lines: LineTable::default(),
+                span: Span::from(0..0),
+            })
+        })?;
+        let has_main = reader.bool()?;
+        let main = reader.u32()?;
+        let overflow = lookup(&Overflow::ALL, reader.u8()?, "overflow mode")?;
+        let len = reader.len()?;
+        let natives = reader.table(len, Reader::native)?;
+
+        let source_name = reader.str()?.to_owned();
+        let source = reader.str()?.to_owned();
+        for function in &mut functions {
+            function.name = reader.str()?.to_owned();
+            function.span = reader.span()?;
+            let len = reader.len()?;
+            let runs = reader.table(len, |reader| Ok((reader.u32()?, reader.span()?)))?;
+            function.lines = LineTable::from_runs(runs, function.code.len()).ok_or_else(|| {
+                invalid(format!(
+                    "the line table of `{}` is malformed",
+                    function.name
+                ))
+            })?;
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(invalid("unexpected data after the debug tables".to_owned()));
+        }
+
+        let bytecode = Bytecode {
+            functions,
+            constants,
+            natives,
+            main: has_main.then_some(main),
+            overflow,
+        };
+        validate(&bytecode)?;
+
+        Ok(Self {
+            bytecode,
+            source_name,
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, run};
+    use interp::Options;
+
+    fn build(source: &str) -> anyhow::Result<BytecodeFile> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        Ok(BytecodeFile {
+            bytecode: compile(&hir::lower(&ast)?.program),
+            source_name: "test.mtx".to_owned(),
+            source: source.to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_bytecode_file_round_trips() -> anyhow::Result<()> {
+        let source = r#"proc classify(n: int) int {
+                ret match n { 0..10 => 1, 10..=99 => 2, _ => 3 };
+            }
+            proc weekday(n: int) int {
+                ret match n { 0 | 6 => 0, 1 => 1, 2 => 2, 3 => 3, 4 | 5 => 4, _ => -1 };
+            }
+            proc main() int {
+                let total = 0;
+                let scale = -2.5;
+                while total < 100 {
+                    if !(scale > 0.0) {
+                        let letter = match 'q' { 'a'..='z' => 1, _ => 0 };
+                        total += (classify(5) * letter + classify(50) * 10 + len("ünï")) * weekday(1);
+                    }
+                }
+                ret total;
+            }"#;
+        let file = build(source)?;
+        assert!(file.bytecode.functions.iter().any(|f| !f.tables.is_empty()));
+
+        let bytes = file.to_bytes();
+        let read = BytecodeFile::from_bytes(&bytes)?;
+        assert_eq!(read, file);
+        assert_eq!(run(&read.bytecode, Options::default())?, Value::int(104));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_instruction_round_trips() -> anyhow::Result<()> {
+        // Register operands take all 32 bits, so large frames encode like small ones.
+        let (dst, lhs, rhs) = (0, 7, u32::MAX);
+        let mut instrs = vec![
+            Instr::LoadConst { dst, index: rhs },
+            Instr::Move { dst, src: lhs },
+            Instr::InRange {
+                dst,
+                src: lhs,
+                start: 1,
+                end: rhs,
+                inclusive: true,
+            },
+            Instr::InRange {
+                dst,
+                src: lhs,
+                start: 1,
+                end: 2,
+                inclusive: false,
+            },
+            Instr::Jump(rhs),
+            Instr::JumpIfFalse {
+                cond: lhs,
+                target: 3,
+            },
+            Instr::JumpIfTrue {
+                cond: lhs,
+                target: 3,
+            },
+            Instr::Call {
+                function: 2,
+                args: lhs,
+                dst,
+            },
+            Instr::TailCall {
+                function: 2,
+                args: rhs,
+            },
+            Instr::Native {
+                native: 1,
+                args: lhs,
+                dst,
+            },
+            Instr::Ret(rhs),
+            Instr::Array {
+                dst,
+                start: lhs,
+                len: 4,
+            },
+            Instr::Index {
+                dst,
+                array: lhs,
+                index: rhs,
+            },
+            Instr::StoreIndex {
+                array: dst,
+                index: lhs,
+                value: rhs,
+            },
+            Instr::Map {
+                dst,
+                start: lhs,
+                len: 2,
+            },
+            Instr::Switch { src: lhs, table: 1 },
+        ];
+        instrs.extend(UNARY_OPS.map(|op| Instr::Unary { op, dst, src: rhs }));
+        for op in BINARY_OPS {
+            instrs.push(Instr::Binary { op, dst, lhs, rhs });
+            instrs.push(Instr::BinaryConst { op, dst, lhs, rhs });
+        }
+        instrs.extend(Builtin::ALL.map(|builtin| Instr::Builtin {
+            builtin,
+            args: lhs,
+            dst,
+        }));
+
+        let mut writer = Writer { bytes: Vec::new() };
+        for &instr in &instrs {
+            writer.instr(instr);
+        }
+        let mut reader = Reader {
+            bytes: &writer.bytes,
+        };
+        for &instr in &instrs {
+            assert_eq!(reader.instr()?, instr);
+        }
+        assert!(reader.bytes.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytecode_file_keeps_natives() -> anyhow::Result<()> {
+        let source = "proc main() void { log(lookup([1, 2]), 'x'); }";
+        let natives = [
+            NativeSignature {
+                name: "lookup".to_owned(),
+                params: vec![Ty::array_of(Ty::INT)],
+                ret_ty: Ty::map_of(Ty::Str, Ty::Int(IntTy::U8)),
+            },
+            NativeSignature {
+                name: "log".to_owned(),
+                params: vec![Ty::map_of(Ty::Str, Ty::Int(IntTy::U8)), Ty::Char],
+                ret_ty: Ty::Void,
+            },
+        ];
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let options = hir::LowerOptions {
+            natives: &natives,
+            ..Default::default()
+        };
+        let file = BytecodeFile {
+            bytecode: compile(&hir::lower_with(&ast, &options)?.program),
+            source_name: "test.mtx".to_owned(),
+            source: source.to_owned(),
+        };
+        assert_eq!(
+            file.bytecode.natives[hir::SystemNative::ALL.len()..],
+            natives
+        );
+
+        let read = BytecodeFile::from_bytes(&file.to_bytes())?;
+        assert_eq!(read, file);
+
+        let mut file = file;
+        file.bytecode.natives.pop();
+        assert!(matches!(
+            BytecodeFile::from_bytes(&file.to_bytes()),
+            Err(FileError::Invalid(message)) if message.contains("out of bounds")
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_building_twice_writes_the_same_bytes() -> anyhow::Result<()> {
+        // Many constants and names, so an order leaking from a hash map would likely show.
+        let source = r#"mod shapes {
+                pub const SIDES: int = 4;
+                pub proc area(w: float, h: float) float { ret w * h; }
+            }
+            proc name(n: int) str {
+                ret match n { 0 => "zero", 1 => "one", 2 => "two", 3 => "three", _ => "many" };
+            }
+            proc main() void {
+                println(name(shapes::SIDES));
+                let total = shapes::area(1.5, 2.5) + shapes::area(0.25, 8.0) + 3.75;
+                println("done");
+            }"#;
+        let first = build(source)?.to_bytes();
+        for _ in 0..4 {
+            assert!(build(source)?.to_bytes() == first, "the files differ");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytecode_file_rejects_bad_files() -> anyhow::Result<()> {
+        let bytes = build("proc main() void { }")?.to_bytes();
+
+        assert!(matches!(
+            BytecodeFile::from_bytes(b"proc main() void { }"),
+            Err(FileError::NotBytecode)
+        ));
+        assert!(matches!(
+            BytecodeFile::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(FileError::Truncated)
+        ));
+
+        let mut newer = bytes;
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            BytecodeFile::from_bytes(&newer),
+            Err(FileError::UnsupportedVersion(version)) if version == VERSION + 1
+        ));
+
+        let mut file = build("proc main() void { }")?;
+        file.bytecode.functions[0].code[0] = Instr::LoadConst { dst: 0, index: 7 };
+        assert!(matches!(
+            BytecodeFile::from_bytes(&file.to_bytes()),
+            Err(FileError::Invalid(_))
+        ));
+
+        let mut file = build("proc main() void { }")?;
+        file.bytecode.functions[0].code[0] = Instr::Move { dst: 0, src: 9 };
+        assert!(matches!(
+            BytecodeFile::from_bytes(&file.to_bytes()),
+            Err(FileError::Invalid(message)) if message.contains("out of bounds")
+        ));
+
+        let mut file = build("proc main() void { }")?;
+        file.bytecode.functions[0].code[0] = Instr::Switch { src: 0, table: 0 };
+        assert!(matches!(
+            BytecodeFile::from_bytes(&file.to_bytes()),
+            Err(FileError::Invalid(message)) if message.contains("out of bounds")
+        ));
+
+        let mut file = build("proc main() void { }")?;
+        file.bytecode.functions[0].lines = LineTable::default();
+        assert!(matches!(
+            BytecodeFile::from_bytes(&file.to_bytes()),
+            Err(FileError::Invalid(message)) if message.contains("line table")
+        ));
+
+        Ok(())
+    }
+}
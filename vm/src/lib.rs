@@ -0,0 +1,749 @@
+#![warn(rust_2018_idioms, clippy::nursery)]
+#![allow(clippy::missing_const_for_fn)]
+
+mod bytecode;
+mod compiler;
+mod coverage;
+pub mod debug;
+mod diagnostics;
+mod explanations;
+pub mod file;
+mod machine;
+mod profile;
+
+pub use bytecode::{Bytecode, Function, Instr, SwitchTable};
+pub use compiler::compile;
+pub use coverage::{Branch, Coverage};
+pub use diagnostics::FileError;
+pub use explanations::EXPLANATIONS;
+pub use file::BytecodeFile;
+pub use machine::{run, run_with_io, run_with_natives, CallFrame, Machine, Step};
+pub use profile::{FunctionProfile, Profile};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hir::Overflow;
+    use interp::{Options, RunError, RuntimeError, Value};
+
+    /// Run a program with both the tree-walking interpreter and the VM, checking that they
+    /// agree.
+    fn run_both(source: &str) -> anyhow::Result<Result<Value, RunError>> {
+        run_both_with(source, Overflow::Checked)
+    }
+
+    fn run_both_with(source: &str, overflow: Overflow) -> anyhow::Result<Result<Value, RunError>> {
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let options = hir::LowerOptions {
+            overflow,
+            ..Default::default()
+        };
+        let program = hir::lower_with(&ast, &options)?.program;
+
+        // The interpreter recurses on the host's stack, so it runs with enough for its calls.
+        let options = Options::default();
+        let interpreted = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(options.interpreter_stack_size())
+                .spawn_scoped(scope, || format!("{:?}", interp::run(&program, options)))
+                .map(|thread| thread.join().unwrap())
+        })?;
+        let executed = run(&compile(&program), options);
+        assert_eq!(
+            interpreted,
+            format!("{executed:?}"),
+            "the interpreter and the VM disagree"
+        );
+
+        Ok(executed)
+    }
+
+    #[test]
+    fn test_vm_matches_interpreter() -> anyhow::Result<()> {
+        let source = "const LIMIT: int = 10;
+            proc fib(n: int) int {
+                if n < 2 { ret n; }
+                ret fib(n - 1) + fib(n - 2);
+            }
+            proc classify(n: int) int {
+                ret match n { 0..10 => 1, 10 | 20..=29 => 2, _ => 3 };
+            }
+            proc main() int {
+                let total = 0;
+                for let i = 0; i < LIMIT; i += 1 {
+                    if i % 2 == 0 { total += fib(i); } elif i == 9 { total += 1000; }
+                }
+                let n = 0;
+                do { n += 1; } while n < 5;
+                let letter = 'q';
+                let scale = 1.5 * 2.0;
+                if scale == 3.0 {
+                    n += classify(4) + classify(29) * 10 + match letter { 'a'..='z' => 100, _ => 0 };
+                }
+                ret total * 1000 + n;
+            }";
+
+        // fib(0) + fib(2) + fib(4) + fib(6) + fib(8) + 1000 = 0 + 1 + 3 + 8 + 21 + 1000
+        assert_eq!(run_both(source)?.unwrap(), Value::int(1033126));
+
+        // A seeded generator gives the same numbers on both.
+        let source = "import rand;
+            proc main() float {
+                rand::seed(3);
+                ret to_float(to_str(rand::rand_int(-50, 50))) + rand::rand_float();
+            }";
+        run_both(source)?.unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_arrays_match_interpreter() -> anyhow::Result<()> {
+        let source = "proc main() str {
+                let grid: [[int]] = [[1], []];
+                for let i = 0; i < 3; i += 1 { push(grid[1], i); }
+                grid[1][2] *= 10;
+                let total = 0;
+                for row in grid {
+                    for x in row { total += x; }
+                }
+                grid[0] = grid[1];
+                push(grid[0], total);
+                ret to_str(grid) + to_str(pop(grid[1]));
+            }";
+        assert_eq!(
+            run_both(source)?.unwrap(),
+            Value::Str("[[0, 1, 20, 22], [0, 1, 20]]20".into())
+        );
+
+        let source = "proc main() void { let xs = [1, 2]; xs[0 - 1] += 1; }";
+        assert!(matches!(
+            run_both(source)?.unwrap_err().error,
+            RuntimeError::IndexOutOfBounds(-1, 2, _)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_for_loops_match_interpreter() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let out = "";
+                for i in -2..2 { out += to_str(i); }
+                for b in 253 as u8..=255 {
+                    out += to_str(b);
+                    b = 0;
+                }
+                for c in "é!" { out += to_str(c); }
+                ret out;
+            }"#;
+        assert_eq!(
+            run_both(source)?.unwrap(),
+            Value::Str("-2-101253254255é!".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_switches_match_interpreter() -> anyhow::Result<()> {
+        let source = r#"proc name(n: int) str {
+                ret match n {
+                    -1 => "minus one",
+                    0 | 2 | 4 => "even",
+                    1 | 3 => match n { 1 => "one", 3 => "three", 5 => "five", 7 => "seven", _ => "" },
+                    5..=8 => "big",
+                    _ => "other",
+                };
+            }
+            proc quarter(b: u8) int {
+                ret match b { 0..64 => 0, 64..128 => 1, 128..192 => 2, 192..=255 => 3 };
+            }
+            proc sparse(n: int) int {
+                ret match n { 0 => 1, 100 => 2, 200 => 3, 300 => 4, _ => 5 };
+            }
+            proc main() str {
+                let out = "";
+                for i in -3..10 { out += name(i) + ","; }
+                let quarters = quarter(0 as u8) + quarter(100 as u8) * 10 + quarter(255 as u8) * 100;
+                ret out + to_str(quarters + sparse(200) * 1000);
+            }"#;
+        assert_eq!(
+            run_both(source)?.unwrap(),
+            Value::Str(
+                "other,other,minus one,even,one,even,three,even,big,big,big,big,other,3310".into()
+            )
+        );
+
+        // Only the dense matches jump through a table.
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+        let switches = |name: &str| {
+            let function = bytecode.functions.iter().find(|f| f.name == name).unwrap();
+            let code = &function.code;
+            code.iter()
+                .filter(|instr| matches!(instr, Instr::Switch { .. }))
+                .count()
+        };
+        assert_eq!(switches("name"), 2);
+        assert_eq!(switches("quarter"), 1);
+        assert_eq!(switches("sparse"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_maps_match_interpreter() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let counts: [char: int] = [:];
+                for let i = 0; i < len("banana"); i += 1 {
+                    let c = "banana"[i];
+                    insert(counts, c, get(counts, c, 0));
+                    counts[c] += 1;
+                }
+                let words = ['a': ["ant"], 'b': ["bee"]];
+                words['a'] = words['b'];
+                push(words['a'], "bat");
+                ret to_str(counts) + to_str(words) + to_str(keys(counts));
+            }"#;
+        assert_eq!(
+            run_both(source)?.unwrap().to_string(),
+            "[a: 3, b: 1, n: 2][a: [bee, bat], b: [bee]][a, b, n]"
+        );
+
+        let source = "proc main() void { let squares = [1: 1, 2: 4]; squares[3] += 1; }";
+        assert!(matches!(
+            run_both(source)?.unwrap_err().error,
+            RuntimeError::MissingKey(key, _) if key == "3"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_runtime_errors_match_interpreter() -> anyhow::Result<()> {
+        let source = "proc add(a: int, b: int) int { ret a + b; }
+            proc main() int { let big = 9223372036854775807; ret add(big, 1); }";
+        let error = run_both(source)?.unwrap_err();
+        assert!(matches!(error.error, RuntimeError::Overflow(_)));
+        assert_eq!(error.trace.0.len(), 2);
+
+        let source = "mod m { pub proc f(n: int) int { let m = f(n + 1); ret m; } }
+            proc main() int { ret m::f(0); }";
+        let error = run_both(source)?.unwrap_err();
+        assert!(matches!(error.error, RuntimeError::StackOverflow(..)));
+        assert_eq!(error.trace.0.last().unwrap().proc, "main");
+        assert_eq!(error.trace.0[0].proc, "m::f");
+
+        let source = "proc main(x: int) void {}";
+        assert!(matches!(
+            run_both(source)?.unwrap_err().error,
+            RuntimeError::MainHasParameters(_)
+        ));
+
+        let source = r#"proc half(n: int) int {
+                assert(n % 2 == 0, to_str(n) + " is odd");
+                if n != 0 { ret n / 2; }
+                panic("zero");
+            }
+            proc main() int { ret half(4) + half(3); }"#;
+        let error = run_both(source)?.unwrap_err();
+        assert_eq!(error.error.to_string(), "Assertion failed: 3 is odd");
+        let source = r#"proc half(n: int) int {
+                if n != 0 { ret n / 2; }
+                panic("zero");
+            }
+            proc main() int { ret half(0); }"#;
+        let error = run_both(source)?.unwrap_err();
+        assert!(matches!(error.error, RuntimeError::Panicked(ref message, _) if message == "zero"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail_calls_reuse_frames() -> anyhow::Result<()> {
+        // Both recursions are far deeper than the default call depth.
+        let source = "proc sum(n: int, acc: int) int {
+                if n == 0 { ret acc; }
+                ret sum(n - 1, acc + n);
+            }
+            proc is_even(n: int) bool { if n == 0 { ret true; } ret is_odd(n - 1); }
+            proc is_odd(n: int) bool { if n == 0 { ret false; } ret is_even(n - 1); }
+            proc main() int {
+                if is_even(10001) { ret 0; }
+                ret sum(100000, 0);
+            }";
+        assert_eq!(run_both(source)?.unwrap(), Value::int(5000050000));
+
+        // A tail call's frame reports where the tail call was made.
+        let source = "proc fail(n: int) int { ret n / 0; }
+            proc relay(n: int) int { ret fail(n); }
+            proc main() int { ret 1 + relay(2); }";
+        let error = run_both(source)?.unwrap_err();
+        let procs = error.trace.0.iter().map(|frame| frame.proc.as_str());
+        assert_eq!(procs.collect::<Vec<_>>(), ["fail", "main"]);
+        assert_eq!(
+            error.trace.0[0].call_span.start,
+            source.find("fail(n);").unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_modes_match_interpreter() -> anyhow::Result<()> {
+        // `LOW` is evaluated at compile time, the rest at runtime.
+        let source = "const LOW: int = -9223372036854775807 - 2;
+            proc add(a: int, b: int) int { ret a + b; }
+            proc main() int {
+                let max = 9223372036854775807;
+                ret add(max, 1) / 2 - LOW / 2;
+            }";
+
+        let tokens = lexer::lex(source)?;
+        assert!(hir::lower(&parser::parse(source, tokens)?).is_err());
+        assert_eq!(
+            run_both_with(source, Overflow::Wrapping)?.unwrap(),
+            Value::int(i64::MIN + 1)
+        );
+        assert_eq!(
+            run_both_with(source, Overflow::Saturating)?.unwrap(),
+            Value::int(i64::MAX)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sized_ints_match_interpreter() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let byte: u8 = 250;
+                let small: i8 = -128;
+                let big: u64 = 18446744073709551615;
+                let wide = byte as i32 * 1000;
+                ret to_str(byte / 3) + " " + to_str(small / 3) + " " + to_str(big) + " "
+                    + to_str(wide) + " " + to_str(~byte) + " " + to_str(byte > 200);
+            }"#;
+        assert_eq!(
+            run_both(source)?.unwrap(),
+            Value::Str("83 -42 18446744073709551615 250000 5 true".into())
+        );
+
+        // Arithmetic and conversions overflow at the width of their type.
+        let source = r#"proc main() str {
+                let byte: u8 = 250;
+                let x = 300;
+                ret to_str(byte + 10) + " " + to_str(x as u8) + " " + to_str(-1 as u64);
+            }"#;
+        assert!(matches!(
+            run_both(source)?.unwrap_err().error,
+            RuntimeError::Overflow(_)
+        ));
+        assert_eq!(
+            run_both_with(source, Overflow::Wrapping)?.unwrap(),
+            Value::Str("4 44 18446744073709551615".into())
+        );
+        assert_eq!(
+            run_both_with(source, Overflow::Saturating)?.unwrap(),
+            Value::Str("255 255 0".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_builtins_match_interpreter() -> anyhow::Result<()> {
+        let source = r#"proc greet(name: str) void {
+                print("hi ");
+                println(name);
+            }
+            proc main() int {
+                let first = read_line();
+                greet(first);
+                println(to_str(1.5 * 3.0));
+                to_str(12);
+                ret match read_line() { "two" => 2, _ => 0 };
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+
+        let (mut interp_output, mut vm_output) = (Vec::new(), Vec::new());
+        let interpreted = interp::run_with_io(
+            &program,
+            interp::Io::new(&mut &b"one\ntwo"[..], &mut interp_output),
+            Options::default(),
+        )?;
+        let executed = run_with_io(
+            &compile(&program),
+            interp::Io::new(&mut &b"one\ntwo"[..], &mut vm_output),
+            Options::default(),
+        )?;
+
+        assert_eq!(interpreted, Value::int(2));
+        assert_eq!(executed, Value::int(2));
+        assert_eq!(String::from_utf8(vm_output)?, "hi one\n4.5\n");
+        assert_eq!(interp_output, b"hi one\n4.5\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_natives_match_interpreter() -> anyhow::Result<()> {
+        let mut natives = interp::Natives::new();
+        natives
+            .register_fn("shout", |s: String| s.to_uppercase() + "!")
+            .register_fn("checked_div", |a: i64, b: i64| {
+                a.checked_div(b).ok_or("division by zero")
+            });
+        let lower = |source: &str| -> anyhow::Result<_> {
+            let ast = parser::parse(source, lexer::lex(source)?)?;
+            let signatures = natives.signatures();
+            let options = hir::LowerOptions {
+                natives: &signatures,
+                ..Default::default()
+            };
+            Ok(hir::lower_with(&ast, &options)?.program)
+        };
+        let run_both = |program: &hir::Program| {
+            let (mut input, mut output) = (&b""[..], Vec::new());
+            let io = interp::Io::new(&mut input, &mut output);
+            let interpreted = interp::run_with_natives(program, io, &natives, Options::default());
+            let bytecode = compile(program);
+            let io = interp::Io::new(&mut input, &mut output);
+            let executed = run_with_natives(&bytecode, io, &natives, Options::default());
+            assert_eq!(
+                format!("{interpreted:?}"),
+                format!("{executed:?}"),
+                "the interpreter and the VM disagree"
+            );
+            executed
+        };
+
+        let program =
+            lower(r#"proc main() str { ret shout("hey") + to_str(checked_div(7, 2)); }"#)?;
+        assert_eq!(run_both(&program)?, Value::Str("HEY!3".into()));
+
+        let program = lower("proc main() int { ret checked_div(1, 0); }")?;
+        assert!(matches!(
+            run_both(&program).unwrap_err().error,
+            RuntimeError::Native(name, message, _)
+                if name == "checked_div" && message == "division by zero"
+        ));
+
+        // Bytecode calling natives can only run with the natives it was compiled with.
+        assert!(matches!(
+            run(&compile(&program), Options::default())
+                .unwrap_err()
+                .error,
+            RuntimeError::MismatchedNatives
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_args_as_an_array() -> anyhow::Result<()> {
+        let source = r#"proc main() str {
+                let all = args();
+                ret all[len(all) - 1] + to_str(len(all));
+            }"#;
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        let args = ["one".to_owned(), "two".to_owned()];
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = interp::Io::new(&mut input, &mut output).with_args(&args);
+        assert_eq!(
+            run_with_io(&bytecode, io, Options::default())?,
+            Value::Str("two2".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_runs_system_natives() -> anyhow::Result<()> {
+        // Cargo sets the name of the package whose tests it runs.
+        let source = r#"proc main() str {
+                let start = clock_ms();
+                sleep_ms(5);
+                ret env("CARGO_PKG_NAME") + to_str(clock_ms() - start > 4);
+            }"#;
+        let ast = parser::parse(source, lexer::lex(source)?)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+        assert_eq!(
+            run(&bytecode, Options::default())?,
+            Value::Str("vmtrue".into())
+        );
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let io = interp::Io::new(&mut input, &mut output).sandboxed();
+        assert!(matches!(
+            run_with_io(&bytecode, io, Options::default())
+                .unwrap_err()
+                .error,
+            RuntimeError::Sandboxed("clock_ms", _)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_call_depth_is_configurable() -> anyhow::Result<()> {
+        let source = "proc count(n: int) int { if n == 0 { ret 0; } ret 1 + count(n - 1); }
+            proc main() int { ret count(5000); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        assert!(matches!(
+            run(&bytecode, Options::default()).unwrap_err().error,
+            RuntimeError::StackOverflow(256, _)
+        ));
+        let options = Options {
+            max_call_depth: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(run(&bytecode, options)?, Value::int(5000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_steps_one_instruction_at_a_time() -> anyhow::Result<()> {
+        let source = "proc sq(n: int) int { ret n * n; }
+            proc main() int { let x = 3; ret sq(x) + 1; }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let mut machine = Machine::new(
+            &bytecode,
+            interp::Io::new(&mut input, &mut output),
+            Options::default(),
+        )?
+        .with_source(source);
+        let mut log = Vec::new();
+        let mut steps = 0;
+        let result = loop {
+            machine.write_state(&mut log)?;
+            if machine
+                .next_instr()
+                .is_some_and(|(instr, _)| matches!(instr, Instr::Ret(_)))
+                && machine.function().unwrap().name == "sq"
+            {
+                assert_eq!(machine.registers(), [Value::int(3), Value::int(9)]);
+                assert_eq!(machine.trace().0.len(), 2);
+
+                // The caller is at its call, with `x` in its first register.
+                let frames = machine.frames();
+                assert_eq!(frames.len(), machine.depth());
+                assert_eq!(frames[0].registers, machine.registers());
+                assert_eq!(bytecode.functions[frames[1].function as usize].name, "main");
+                assert_eq!(&source[frames[1].span.start..frames[1].span.end], "sq(x)");
+                assert_eq!(frames[1].registers[0], Value::int(3));
+            }
+
+            steps += 1;
+            if let Step::Finished(value) = machine.step()? {
+                break value;
+            }
+        };
+
+        assert_eq!(result, Value::int(10));
+        assert_eq!(machine.next_instr(), None);
+        let log = String::from_utf8(log)?;
+        assert_eq!(log.lines().count(), steps);
+        assert!(log.lines().next().unwrap().contains("main@0 (2:"), "{log}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuel_stops_runaway_programs() -> anyhow::Result<()> {
+        let source = "proc spin(n: int) int { while true {} ret n; }
+            proc main() int { let x = 1; ret spin(x); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        let options = Options {
+            fuel: Some(1000),
+            ..Default::default()
+        };
+
+        let interpreted = interp::run(&program, options).unwrap_err();
+        let executed = run(&compile(&program), options).unwrap_err();
+        for error in [interpreted, executed] {
+            assert!(matches!(error.error, RuntimeError::OutOfFuel(1000, _)));
+            assert_eq!(error.trace.0[0].proc, "spin");
+        }
+
+        // A program that finishes within its budget runs as usual.
+        let source = "proc main() int { let total = 0; for let i = 0; i < 10; i += 1 { total += i; } ret total; }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let program = hir::lower(&ast)?.program;
+        assert_eq!(interp::run(&program, options)?, Value::int(45));
+        assert_eq!(run(&compile(&program), options)?, Value::int(45));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_profiles_calls_and_instructions() -> anyhow::Result<()> {
+        let source = "proc fib(n: int) int { if n < 2 { ret n; } ret fib(n - 1) + fib(n - 2); }
+            proc main() int { ret fib(5) + fib(1); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let machine = Machine::new(
+            &bytecode,
+            interp::Io::new(&mut input, &mut output),
+            Options::default(),
+        )?;
+        let (result, profile) = machine.run_profiled();
+        assert_eq!(result?, Value::int(6));
+
+        let calls = profile
+            .functions()
+            .into_iter()
+            .map(|function| (function.name, function.calls))
+            .collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(calls, [("fib", 16), ("main", 1)].into());
+        let instrs = profile.instrs();
+        assert_eq!(
+            instrs.iter().map(|(_, count)| count).sum::<u64>(),
+            profile.total_instrs()
+        );
+        assert!(instrs.contains(&("Call".to_owned(), 16)), "{instrs:?}");
+        assert!(profile
+            .to_json()
+            .contains(r#"{ "name": "fib", "calls": 16,"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_switches_dispatch_in_fewer_instructions() -> anyhow::Result<()> {
+        // The same arms, on ints close enough together for a table and too far apart for one.
+        let digits = [3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5, 8, 9, 7, 9];
+        let instrs = |step: usize| -> anyhow::Result<u64> {
+            let arms = digits
+                .iter()
+                .enumerate()
+                .map(|(i, digit)| format!("{} => {digit}, ", i * step))
+                .collect::<String>();
+            let source = format!(
+                "proc digit(n: int) int {{ ret match n {{ {arms}_ => 3 }}; }}
+                proc main() int {{
+                    let total = 0;
+                    for i in 0..160 {{ total += digit(i % 16 * {step}); }}
+                    ret total;
+                }}"
+            );
+            let tokens = lexer::lex(&source)?;
+            let ast = parser::parse(&source, tokens)?;
+            let bytecode = compile(&hir::lower(&ast)?.program);
+
+            let (mut input, mut output) = (&b""[..], Vec::new());
+            let machine = Machine::new(
+                &bytecode,
+                interp::Io::new(&mut input, &mut output),
+                Options::default(),
+            )?;
+            let (result, profile) = machine.run_profiled();
+            assert_eq!(result?, Value::int(800));
+            let digit = profile.functions().into_iter().find(|f| f.name == "digit");
+            Ok(digit.unwrap().instrs)
+        };
+
+        let (dense, sparse) = (instrs(1)?, instrs(10)?);
+        assert!(
+            dense * 3 < sparse,
+            "{dense} instructions with a table, {sparse} without"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_covers_instructions_and_branches() -> anyhow::Result<()> {
+        let source = "proc sign(n: int) int {
+                if n < 0 { ret 0 - 1; }
+                ret 1;
+            }
+            proc unused() int { ret 2; }
+            proc main() int { ret sign(5) + sign(7); }";
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        let mut coverage = Coverage::new(&bytecode);
+        let (mut input, mut output) = (&b""[..], Vec::new());
+        let machine = Machine::new(
+            &bytecode,
+            interp::Io::new(&mut input, &mut output),
+            Options::default(),
+        )?;
+        assert_eq!(machine.run_covered(&mut coverage)?, Value::int(2));
+
+        // The most times any instruction compiled from within a piece of the source ran.
+        let count = |text: &str| {
+            let start = source.find(text).unwrap();
+            coverage
+                .instrs()
+                .filter(|(span, _)| (start..start + text.len()).contains(&span.start))
+                .map(|(_, count)| count)
+                .max()
+        };
+        assert_eq!(count("ret 1;"), Some(2));
+        assert_eq!(count("ret 0 - 1;"), Some(0));
+        assert_eq!(count("ret 2;"), Some(0));
+
+        let branches = coverage.branches();
+        assert_eq!(branches.len(), 1, "{branches:?}");
+        assert_eq!(branches[0].taken + branches[0].not_taken, 2);
+        assert_eq!(
+            &source[branches[0].span.start..branches[0].span.end],
+            "n < 0"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_pool_is_deduplicated() -> anyhow::Result<()> {
+        let source = r#"const NEG_ZERO: float = -0.0;
+            proc greet(n: int) str { ret "hi " + to_str(n + 1); }
+            proc main() float {
+                println("hi " + greet(1) + "hi ");
+                let zero = 0.0;
+                ret zero * NEG_ZERO + zero + to_float(to_str(len("hi ")));
+            }"#;
+        let tokens = lexer::lex(source)?;
+        let ast = parser::parse(source, tokens)?;
+        let bytecode = compile(&hir::lower(&ast)?.program);
+
+        let count = |value: Value| {
+            bytecode
+                .constants
+                .iter()
+                .filter(|constant| format!("{constant:?}") == format!("{value:?}"))
+                .count()
+        };
+        assert_eq!(count(Value::Str("hi ".into())), 1);
+        assert_eq!(count(Value::int(1)), 1);
+        assert_eq!(count(Value::Float(0.0)), 1);
+        assert_eq!(count(Value::Float(-0.0)), 1);
+        assert!(debug::disassemble(&bytecode, source)
+            .starts_with("constant pool: 5 constants, 1 strings of 3 bytes\n"));
+
+        Ok(())
+    }
+}
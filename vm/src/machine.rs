@@ -0,0 +1,621 @@
+use crate::{
+    bytecode::{Bytecode, Function, Instr},
+    coverage::Coverage,
+    debug::Lines,
+    profile::Profile,
+};
+use hir::Builtin;
+use interp::{
+    builtins, ops, CallTrace, Heap, Io, Natives, Options, RunError, RunResult, RuntimeError,
+    TraceFrame, Value,
+};
+use span::Span;
+use std::{
+    io::{self, Write},
+    time::Instant,
+};
+
+/// An active function call.
+struct Frame {
+    function: u32,
+
+    /// The index of the next instruction to run.
+    ip: usize,
+
+    /// Where the frame's registers start on the stack.
+    base: usize,
+
+    /// Where on the stack the caller wants the result.
+    ret: usize,
+
+    /// Where the function was called.
+    call_span: Span,
+}
+
+/// Where the running function is up to. It's kept out of the frame while running, so the
+/// next instruction needn't be found through the frame stack, and saved into the frame when
+/// the machine stops or makes a call.
+struct Cursor<'a> {
+    function: &'a Function,
+    ip: usize,
+
+    /// Where the frame's registers start on the stack.
+    base: usize,
+}
+
+/// What happened when the machine ran an instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// The program is still running.
+    Running,
+
+    /// The function the machine started at, usually `main`, returned this value.
+    Finished(Value),
+}
+
+/// An active call, as it is between steps.
+#[derive(Debug, Clone, Copy)]
+pub struct CallFrame<'m> {
+    /// The index of the function being run.
+    pub function: u32,
+
+    /// The span the call is at: the next instruction's for the innermost call, and for the
+    /// others, the call they're waiting on.
+    pub span: Span,
+
+    pub registers: &'m [Value],
+}
+
+/// A register machine running bytecode.
+///
+/// Each frame's registers are a window of the value stack, and a call's arguments, the last
+/// registers of the caller's window, become the first registers of the callee's.
+///
+/// The machine can be run an instruction at a time with [`Machine::step`], and inspected
+/// between steps.
+pub struct Machine<'a> {
+    bytecode: &'a Bytecode,
+    io: Io<'a>,
+
+    /// The natives the program calls, which match the ones it was compiled with, or `None` if
+    /// it only calls the system natives.
+    natives: Option<&'a Natives>,
+    heap: Heap,
+    options: Options,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+
+    /// The number of instructions run so far.
+    steps: u64,
+
+    /// The source the bytecode was compiled from, for showing where traced instructions came
+    /// from.
+    lines: Option<Lines<'a>>,
+}
+
+impl<'a> Machine<'a> {
+    /// Make a machine about to run the first instruction of `main`.
+    pub fn new(bytecode: &'a Bytecode, io: Io<'a>, options: Options) -> Result<Self, RunError> {
+        Self::start(bytecode, io, None, options)
+    }
+
+    /// Make a machine like [`Machine::new`], letting the program call the natives it was
+    /// compiled with.
+    pub fn with_natives(
+        bytecode: &'a Bytecode,
+        io: Io<'a>,
+        natives: &'a Natives,
+        options: Options,
+    ) -> Result<Self, RunError> {
+        Self::start(bytecode, io, Some(natives), options)
+    }
+
+    fn start(
+        bytecode: &'a Bytecode,
+        io: Io<'a>,
+        natives: Option<&'a Natives>,
+        options: Options,
+    ) -> Result<Self, RunError> {
+        let system = Natives::new();
+        if !natives.unwrap_or(&system).matches(&bytecode.natives) {
+            return Err(RuntimeError::MismatchedNatives.into());
+        }
+        let main = bytecode.main.ok_or(RuntimeError::NoMain)?;
+        let function = &bytecode.functions[main as usize];
+        if function.arity != 0 {
+            return Err(RuntimeError::MainHasParameters(function.span).into());
+        }
+
+        let mut machine = Self::start_at(bytecode, main, io, options);
+        machine.natives = natives;
+        Ok(machine)
+    }
+
+    /// Make a machine about to run the first instruction of a function that takes no
+    /// parameters, like a benchmark, instead of `main`.
+    pub fn start_at(bytecode: &'a Bytecode, function: u32, io: Io<'a>, options: Options) -> Self {
+        let span = bytecode.functions[function as usize].span;
+        assert_eq!(
+            bytecode.functions[function as usize].arity, 0,
+            "the function takes parameters"
+        );
+
+        let mut machine = Self {
+            bytecode,
+            io,
+            natives: None,
+            heap: Heap::new(),
+            options,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            steps: 0,
+            lines: None,
+        };
+        machine.enter(function, 0, 0, span);
+        machine
+    }
+
+    /// Show where in a source each instruction came from when writing the machine's state.
+    pub fn with_source(mut self, source: &'a str) -> Self {
+        self.lines = Some(Lines::new(source));
+        self
+    }
+
+    /// The function being run, or `None` once the program has finished.
+    pub fn function(&self) -> Option<&'a Function> {
+        let frame = self.frames.last()?;
+        Some(&self.bytecode.functions[frame.function as usize])
+    }
+
+    /// The instruction that will run next and the span it was compiled from, or `None` once
+    /// the program has finished.
+    pub fn next_instr(&self) -> Option<(Instr, Span)> {
+        let (frame, function) = (self.frames.last()?, self.function()?);
+        Some((function.code[frame.ip], function.lines.span_at(frame.ip)))
+    }
+
+    /// The registers of the function being run.
+    pub fn registers(&self) -> &[Value] {
+        self.frames.last().map_or(&[], |frame| {
+            let registers = self.function().map_or(0, |function| function.registers);
+            &self.stack[frame.base..frame.base + registers as usize]
+        })
+    }
+
+    /// The active calls, innermost first, with their registers.
+    pub fn frames(&self) -> Vec<CallFrame<'_>> {
+        let innermost = self.frames.len().saturating_sub(1);
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, frame)| {
+                let function = &self.bytecode.functions[frame.function as usize];
+                // Callers have moved past their call.
+                let ip = if i == innermost {
+                    frame.ip
+                } else {
+                    frame.ip - 1
+                };
+                CallFrame {
+                    function: frame.function,
+                    span: function.lines.span_at(ip),
+                    registers: &self.stack[frame.base..frame.base + function.registers as usize],
+                }
+            })
+            .collect()
+    }
+
+    /// The function being run and the index of the instruction that will run next, or `None`
+    /// once the program has finished.
+    pub fn position(&self) -> Option<(u32, usize)> {
+        let frame = self.frames.last()?;
+        Some((frame.function, frame.ip))
+    }
+
+    /// How many calls are active.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The active calls, innermost first.
+    pub fn trace(&self) -> CallTrace {
+        let frames = self.frames.iter().rev().map(|frame| TraceFrame {
+            proc: self.bytecode.functions[frame.function as usize]
+                .name
+                .clone(),
+            call_span: frame.call_span,
+        });
+        CallTrace(frames.collect())
+    }
+
+    /// Write a line showing the instruction that will run next and the state it will run in.
+    pub fn write_state(&self, log: &mut dyn Write) -> io::Result<()> {
+        let (Some(function), Some((instr, span))) = (self.function(), self.next_instr()) else {
+            return writeln!(log, "finished");
+        };
+        let ip = self.frames.last().map_or(0, |frame| frame.ip);
+        let indent = "  ".repeat(self.frames.len() - 1);
+
+        write!(log, "{indent}{}@{ip}", function.name)?;
+        if let Some(lines) = &self.lines {
+            let (line, column) = lines.locate(span.start);
+            write!(log, " ({line}:{column})")?;
+        }
+        write!(log, ": {instr:?}")?;
+        write_values(log, "registers", self.registers())?;
+        writeln!(log)
+    }
+
+    /// The position of the innermost frame.
+    fn cursor(&self) -> Cursor<'a> {
+        let frame = self
+            .frames
+            .last()
+            .expect("a finished machine can't be stepped");
+        Cursor {
+            function: &self.bytecode.functions[frame.function as usize],
+            ip: frame.ip,
+            base: frame.base,
+        }
+    }
+
+    /// Start a call to a function whose arguments are on the stack from `base`, which will
+    /// return its result to `ret`.
+    fn enter(&mut self, function: u32, base: usize, ret: usize, call_span: Span) {
+        let registers = self.bytecode.functions[function as usize].registers;
+        self.stack.resize(base + registers as usize, Value::Void);
+        self.frames.push(Frame {
+            function,
+            ip: 0,
+            base,
+            ret,
+            call_span,
+        });
+    }
+
+    /// Call a function from the cursor's frame, moving the cursor to the start of the callee.
+    #[inline]
+    fn call(
+        &mut self,
+        cursor: &mut Cursor<'a>,
+        function: u32,
+        base: usize,
+        ret: usize,
+        span: Span,
+    ) -> RunResult<()> {
+        if self.frames.len() == self.options.max_call_depth {
+            let depth = self.options.max_call_depth;
+            return Err(RuntimeError::StackOverflow(depth, span));
+        }
+
+        self.frames.last_mut().unwrap().ip = cursor.ip;
+        self.enter(function, base, ret, span);
+        *cursor = self.cursor();
+        Ok(())
+    }
+
+    /// Replace the cursor's frame with a call to a function whose arguments are on the stack
+    /// from `args`.
+    #[inline]
+    fn tail_call(&mut self, cursor: &mut Cursor<'a>, function: u32, args: usize, span: Span) {
+        // Move the arguments down to the frame's base, and the callee reuses its registers.
+        let arity = self.bytecode.functions[function as usize].arity as usize;
+        self.stack.truncate(args + arity);
+        self.stack.drain(cursor.base..args);
+        let frame = self.frames.pop().unwrap();
+        self.enter(function, cursor.base, frame.ret, span);
+        *cursor = self.cursor();
+    }
+
+    /// Return a value from the cursor's frame, moving the cursor back to the caller. Returns
+    /// the value instead if the outermost frame returned it, finishing the program.
+    #[inline]
+    fn ret(&mut self, cursor: &mut Cursor<'a>, value: Value) -> Option<Value> {
+        let frame = self.frames.pop().unwrap();
+        let Some(caller) = self.frames.last() else {
+            return Some(value);
+        };
+
+        let registers = self.bytecode.functions[caller.function as usize].registers;
+        self.stack
+            .resize(caller.base + registers as usize, Value::Void);
+        self.stack[frame.ret] = value;
+        *cursor = self.cursor();
+        None
+    }
+
+    #[inline]
+    fn builtin(&mut self, builtin: Builtin, args: usize, span: Span) -> RunResult<Value> {
+        let args = self.stack[args..args + builtin.params().len()].to_vec();
+        builtins::call(builtin, args, &mut self.io, &mut self.heap, span)
+    }
+
+    fn native(&mut self, native: u32, args: usize, span: Span) -> RunResult<Value> {
+        let arity = self.bytecode.natives[native as usize].params.len();
+        let args = &self.stack[args..args + arity];
+        let (id, io, heap) = (hir::NativeId(native), &mut self.io, &mut self.heap);
+        match self.natives {
+            Some(natives) => natives.call(id, args, io, heap, span),
+            None => Natives::new().call(id, args, io, heap, span),
+        }
+    }
+
+    /// Run the next instruction. An error stops the program, so the machine shouldn't be
+    /// stepped again after one, or after the program has finished.
+    pub fn step(&mut self) -> Result<Step, RunError> {
+        self.run_for(1)
+    }
+
+    /// Run instructions until `limit` of them have run or the program finishes.
+    fn run_for(&mut self, limit: u64) -> Result<Step, RunError> {
+        let mut cursor = self.cursor();
+        let step = self.execute(&mut cursor, limit);
+        // Calls save the cursor into the caller's frame, so only the innermost frame is behind.
+        if let Some(frame) = self.frames.last_mut() {
+            frame.ip = cursor.ip;
+        }
+
+        // An error returns straight away, leaving the frames as they were, for the trace.
+        step.map_err(|error| RunError {
+            error,
+            trace: self.trace(),
+        })
+    }
+
+    /// Run the program to the end, returning the value `main` returned.
+    pub fn run(mut self) -> Result<Value, RunError> {
+        loop {
+            if let Step::Finished(value) = self.run_for(u64::MAX)? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Run the program to the end like [`Machine::run`], writing the state before every
+    /// instruction to a log.
+    pub fn run_traced(mut self, log: &mut dyn Write) -> Result<Value, RunError> {
+        while let Some((_, span)) = self.next_instr() {
+            self.write_state(log)
+                .map_err(|error| RuntimeError::Io(error.to_string(), span))?;
+            if let Step::Finished(value) = self.step()? {
+                return Ok(value);
+            }
+        }
+        unreachable!("the machine stops stepping once the program finishes")
+    }
+
+    /// Run the program to the end like [`Machine::run`], counting the instructions and calls
+    /// that ran and timing each function. The profile covers everything that ran before an
+    /// error too.
+    pub fn run_profiled(mut self) -> (Result<Value, RunError>, Profile<'a>) {
+        let mut profile = Profile::new(self.bytecode);
+        if let Some(main) = self.frames.last() {
+            profile.record_call(main.function);
+        }
+
+        loop {
+            let frame = self
+                .frames
+                .last()
+                .expect("the machine stops stepping once the program finishes");
+            let (function, ip) = (frame.function, frame.ip);
+            let instr = self.bytecode.functions[function as usize].code[ip];
+
+            let start = Instant::now();
+            let step = self.step();
+            profile.record(function, ip, start.elapsed());
+            match step {
+                Ok(Step::Running) => {
+                    if let Instr::Call { function, .. } | Instr::TailCall { function, .. } = instr {
+                        profile.record_call(function);
+                    }
+                }
+                Ok(Step::Finished(value)) => return (Ok(value), profile),
+                Err(error) => return (Err(error), profile),
+            }
+        }
+    }
+
+    /// Run the program to the end like [`Machine::run`], counting in a coverage the
+    /// instructions that ran and the conditional jumps that jumped. The coverage counts
+    /// everything that ran before an error too.
+    pub fn run_covered(mut self, coverage: &mut Coverage<'_>) -> Result<Value, RunError> {
+        loop {
+            let frame = self
+                .frames
+                .last()
+                .expect("the machine stops stepping once the program finishes");
+            let (function, ip, depth) = (frame.function, frame.ip, self.frames.len());
+            let instr = self.bytecode.functions[function as usize].code[ip];
+
+            coverage.record(function, ip);
+            if let Step::Finished(value) = self.step()? {
+                return Ok(value);
+            }
+            if let Instr::JumpIfFalse { target, .. } | Instr::JumpIfTrue { target, .. } = instr {
+                // A jump to the next instruction goes the same way either way, so it's counted
+                // as falling through.
+                let jumped = self.frames.last().map(|frame| frame.ip);
+                if self.frames.len() == depth
+                    && target as usize != ip + 1
+                    && jumped == Some(target as usize)
+                {
+                    coverage.record_jump(function, ip);
+                }
+            }
+        }
+    }
+
+    /// The dispatch loop, running instructions from the cursor until `limit` of them have run
+    /// or the program finishes.
+    fn execute(&mut self, cursor: &mut Cursor<'a>, limit: u64) -> RunResult<Step> {
+        let end = self.steps.saturating_add(limit);
+        loop {
+            if self.steps == end {
+                return Ok(Step::Running);
+            }
+            let span = cursor.function.lines.span_at(cursor.ip);
+            if self.options.fuel == Some(self.steps) {
+                return Err(RuntimeError::OutOfFuel(self.steps, span));
+            }
+            self.steps += 1;
+
+            let instr = cursor.function.code[cursor.ip];
+            cursor.ip += 1;
+            let base = cursor.base;
+            let reg = |reg: u32| base + reg as usize;
+
+            match instr {
+                Instr::LoadConst { dst, index } => {
+                    self.stack[reg(dst)] = self.bytecode.constants[index as usize].clone();
+                }
+                Instr::Move { dst, src } => self.stack[reg(dst)] = self.stack[reg(src)].clone(),
+                Instr::Unary { op, dst, src } => {
+                    let operand = self.stack[reg(src)].clone();
+                    let overflow = self.bytecode.overflow;
+                    self.stack[reg(dst)] = ops::unary(op, operand, overflow, span)?;
+                }
+                Instr::Binary { op, dst, lhs, rhs } => {
+                    let (lhs, rhs) = (self.stack[reg(lhs)].clone(), self.stack[reg(rhs)].clone());
+                    let overflow = self.bytecode.overflow;
+                    self.stack[reg(dst)] =
+                        ops::binary(&mut self.heap, op, lhs, rhs, overflow, span)?;
+                }
+                Instr::BinaryConst { op, dst, lhs, rhs } => {
+                    let lhs = self.stack[reg(lhs)].clone();
+                    let rhs = self.bytecode.constants[rhs as usize].clone();
+                    let overflow = self.bytecode.overflow;
+                    self.stack[reg(dst)] =
+                        ops::binary(&mut self.heap, op, lhs, rhs, overflow, span)?;
+                }
+                Instr::InRange {
+                    dst,
+                    src,
+                    start,
+                    end,
+                    inclusive,
+                } => {
+                    let constants = &self.bytecode.constants;
+                    let in_range = self.stack[reg(src)].in_range(
+                        &constants[start as usize],
+                        &constants[end as usize],
+                        inclusive,
+                    );
+                    self.stack[reg(dst)] = Value::Bool(in_range);
+                }
+                Instr::Jump(target) => cursor.ip = target as usize,
+                Instr::JumpIfFalse { cond, target } => {
+                    if !self.stack[reg(cond)].is_truthy() {
+                        cursor.ip = target as usize;
+                    }
+                }
+                Instr::JumpIfTrue { cond, target } => {
+                    if self.stack[reg(cond)].is_truthy() {
+                        cursor.ip = target as usize;
+                    }
+                }
+                Instr::Switch { src, table } => {
+                    let table = &cursor.function.tables[table as usize];
+                    let target = match self.stack[reg(src)] {
+                        Value::Int(value, ty) => table.target(ty.value(value)),
+                        _ => table.default,
+                    };
+                    cursor.ip = target as usize;
+                }
+                Instr::TailCall { function, args } if self.frames.len() > 1 => {
+                    self.tail_call(cursor, function, reg(args), span);
+                }
+                Instr::TailCall { function, args } => {
+                    self.call(cursor, function, reg(args), reg(args), span)?;
+                }
+                Instr::Call {
+                    function,
+                    args,
+                    dst,
+                } => self.call(cursor, function, reg(args), reg(dst), span)?,
+                Instr::Builtin { builtin, args, dst } => {
+                    self.stack[reg(dst)] = self.builtin(builtin, reg(args), span)?;
+                }
+                Instr::Native { native, args, dst } => {
+                    self.stack[reg(dst)] = self.native(native, reg(args), span)?;
+                }
+                Instr::Array { dst, start, len } => {
+                    let elements = self.stack[reg(start)..reg(start) + len as usize].to_vec();
+                    self.stack[reg(dst)] = self.heap.alloc_array(elements);
+                }
+                Instr::Map { dst, start, len } => {
+                    let entries = self.stack[reg(start)..reg(start) + 2 * len as usize]
+                        .chunks(2)
+                        .map(|entry| (entry[0].clone(), entry[1].clone()))
+                        .collect();
+                    self.stack[reg(dst)] = self.heap.alloc_map(entries);
+                }
+                Instr::Index { dst, array, index } => {
+                    let (array, index) = (&self.stack[reg(array)], &self.stack[reg(index)]);
+                    let value = self.heap.load_indexed(array, index, span)?;
+                    self.stack[reg(dst)] = value;
+                }
+                Instr::StoreIndex {
+                    array,
+                    index,
+                    value,
+                } => {
+                    let value = self.stack[reg(value)].clone();
+                    let (array, index) = (&self.stack[reg(array)], &self.stack[reg(index)]);
+                    self.heap.store_indexed(array, index, value, span)?;
+                }
+                Instr::Ret(src) => {
+                    let value = std::mem::replace(&mut self.stack[reg(src)], Value::Void);
+                    if let Some(value) = self.ret(cursor, value) {
+                        return Ok(Step::Finished(value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run compiled bytecode from its `main` function with the standard input and output,
+/// returning its result.
+pub fn run(bytecode: &Bytecode, options: Options) -> Result<Value, RunError> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    run_with_io(bytecode, Io::new(&mut input, &mut output), options)
+}
+
+/// Run compiled bytecode from its `main` function, returning its result. Calls are tracked on
+/// the heap, so any call depth can be reached without overflowing the host's stack.
+pub fn run_with_io<'a>(
+    bytecode: &'a Bytecode,
+    io: Io<'a>,
+    options: Options,
+) -> Result<Value, RunError> {
+    Machine::new(bytecode, io, options)?.run()
+}
+
+/// Run compiled bytecode like [`run_with_io`], letting it call the natives it was compiled
+/// with.
+pub fn run_with_natives<'a>(
+    bytecode: &'a Bytecode,
+    io: Io<'a>,
+    natives: &'a Natives,
+    options: Options,
+) -> Result<Value, RunError> {
+    Machine::with_natives(bytecode, io, natives, options)?.run()
+}
+
+fn write_values(log: &mut dyn Write, name: &str, values: &[Value]) -> io::Result<()> {
+    write!(log, "  {name} [")?;
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(log, ", ")?;
+        }
+        // Quote strings so their contents can't be mistaken for other values.
+        match value {
+            Value::Str(s) => write!(log, "{s:?}")?,
+            value => write!(log, "{value}")?,
+        }
+    }
+    write!(log, "]")
+}
@@ -0,0 +1,223 @@
+//! Counters collected while running bytecode with [`Machine::run_profiled`], for finding where
+//! a program spends its time.
+//!
+//! [`Machine::run_profiled`]: crate::Machine::run_profiled
+
+use crate::bytecode::{Bytecode, Instr};
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    time::Duration,
+};
+
+/// How often each instruction of some bytecode ran, and how often and how long each function
+/// did.
+#[derive(Debug, Clone)]
+pub struct Profile<'a> {
+    bytecode: &'a Bytecode,
+
+    /// How many times each instruction ran, by function then offset.
+    counts: Vec<Vec<u64>>,
+
+    /// How many times each function was called, including tail calls.
+    calls: Vec<u64>,
+
+    /// The time spent running each function's own instructions, not counting its callees.
+    time: Vec<Duration>,
+}
+
+/// What a function did while it was profiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile<'a> {
+    pub name: &'a str,
+    pub calls: u64,
+
+    /// The number of the function's own instructions that ran.
+    pub instrs: u64,
+
+    /// The time spent running the function's own instructions.
+    pub time: Duration,
+}
+
+/// The name instructions are counted under. Operators and builtins are told apart, but other
+/// operands aren't.
+fn kind(instr: Instr) -> String {
+    match instr {
+        Instr::Unary { op, .. } => format!("Unary({op:?})"),
+        Instr::Binary { op, .. } => format!("Binary({op:?})"),
+        Instr::BinaryConst { op, .. } => format!("BinaryConst({op:?})"),
+        Instr::Builtin { builtin, .. } => format!("Builtin({})", builtin.name()),
+        _ => {
+            let debug = format!("{instr:?}");
+            let end = debug.find(['(', ' ']).unwrap_or(debug.len());
+            debug[..end].to_owned()
+        }
+    }
+}
+
+/// Quote a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl<'a> Profile<'a> {
+    pub(crate) fn new(bytecode: &'a Bytecode) -> Self {
+        let functions = &bytecode.functions;
+        Self {
+            bytecode,
+            counts: functions
+                .iter()
+                .map(|function| vec![0; function.code.len()])
+                .collect(),
+            calls: vec![0; functions.len()],
+            time: vec![Duration::ZERO; functions.len()],
+        }
+    }
+
+    /// Record that the instruction at an offset of a function ran, taking some time.
+    pub(crate) fn record(&mut self, function: u32, offset: usize, time: Duration) {
+        self.counts[function as usize][offset] += 1;
+        self.time[function as usize] += time;
+    }
+
+    pub(crate) fn record_call(&mut self, function: u32) {
+        self.calls[function as usize] += 1;
+    }
+
+    /// The number of instructions that ran.
+    pub fn total_instrs(&self) -> u64 {
+        self.counts.iter().flatten().sum()
+    }
+
+    /// The time spent running instructions.
+    pub fn total_time(&self) -> Duration {
+        self.time.iter().sum()
+    }
+
+    /// How many times each kind of instruction ran, most frequent first.
+    pub fn instrs(&self) -> Vec<(String, u64)> {
+        let mut counts = HashMap::<_, u64>::new();
+        for (function, function_counts) in self.bytecode.functions.iter().zip(&self.counts) {
+            for (&instr, &count) in function.code.iter().zip(function_counts) {
+                if count > 0 {
+                    *counts.entry(kind(instr)).or_default() += count;
+                }
+            }
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        counts
+    }
+
+    /// Every function that was called, the one that took the longest first.
+    pub fn functions(&self) -> Vec<FunctionProfile<'a>> {
+        let mut functions: Vec<_> = self
+            .bytecode
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| self.calls[i] > 0)
+            .map(|(i, function)| FunctionProfile {
+                name: &function.name,
+                calls: self.calls[i],
+                instrs: self.counts[i].iter().sum(),
+                time: self.time[i],
+            })
+            .collect();
+        functions.sort_by(|a, b| b.time.cmp(&a.time).then(b.instrs.cmp(&a.instrs)));
+        functions
+    }
+
+    /// The profile as a JSON object, with times in nanoseconds.
+    pub fn to_json(&self) -> String {
+        let instrs = self
+            .instrs()
+            .into_iter()
+            .map(|(kind, count)| {
+                format!(
+                    "    {{ \"instr\": {}, \"count\": {count} }}",
+                    json_string(&kind)
+                )
+            })
+            .collect::<Vec<_>>();
+        let functions = self
+            .functions()
+            .into_iter()
+            .map(|function| {
+                format!(
+                    "    {{ \"name\": {}, \"calls\": {}, \"instrs\": {}, \"time_ns\": {} }}",
+                    json_string(function.name),
+                    function.calls,
+                    function.instrs,
+                    function.time.as_nanos()
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\n  \"instrs\": {},\n  \"time_ns\": {},\n  \"instr_counts\": [\n{}\n  ],\n  \"procs\": [\n{}\n  ]\n}}\n",
+            self.total_instrs(),
+            self.total_time().as_nanos(),
+            instrs.join(",\n"),
+            functions.join(",\n")
+        )
+    }
+}
+
+/// Shows the profile as a table of procedures followed by a table of instructions.
+impl fmt::Display for Profile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let functions = self.functions();
+        let width = functions
+            .iter()
+            .map(|function| function.name.len())
+            .chain(["proc".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{} instructions in {:?}\n",
+            self.total_instrs(),
+            self.total_time()
+        )?;
+        writeln!(
+            f,
+            "{:<width$}  {:>10}  {:>14}  {:>12}",
+            "proc", "calls", "instructions", "self time"
+        )?;
+        for function in functions {
+            writeln!(
+                f,
+                "{:<width$}  {:>10}  {:>14}  {:>12}",
+                function.name,
+                function.calls,
+                function.instrs,
+                format!("{:?}", function.time)
+            )?;
+        }
+
+        let instrs = self.instrs();
+        let width = instrs
+            .iter()
+            .map(|(kind, _)| kind.len())
+            .chain(["instruction".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(f, "\n{:<width$}  {:>14}", "instruction", "count")?;
+        for (kind, count) in instrs {
+            writeln!(f, "{kind:<width$}  {count:>14}")?;
+        }
+        Ok(())
+    }
+}